@@ -0,0 +1,15 @@
+// Runs a test binary built for wasm32-unknown-unknown, which has no imports and no output.
+// A failing test panics, which aborts with a trap, so only the overall result is reported.
+const fs = require("fs");
+
+const path = process.argv[2];
+const wasmModule = new WebAssembly.Module(fs.readFileSync(path));
+const instance = new WebAssembly.Instance(wasmModule, {});
+try {
+  const code = instance.exports.main(0, 0);
+  console.log(`${path}: exited with ${code}`);
+  process.exit(code);
+} catch (e) {
+  console.error(`${path}: failed: ${e.message}`);
+  process.exit(1);
+}
@@ -1,6 +1,6 @@
 use crate::backend::Backend;
 use clap::{builder::PossibleValue, Args, Parser, Subcommand};
-use std::fmt::Display;
+use std::{fmt::Display, path::PathBuf};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -20,6 +20,11 @@ pub enum Commands {
     ///
     /// This will not reset the database unless `--reset` is provided.
     Run(RunParams),
+    /// Import the state stored in an sp-trie database into a fresh NOMT database.
+    ///
+    /// The sp-trie database is expected in the layout written by the sp-trie backend: trie nodes
+    /// in the first column of a RocksDB database and the root in the second one.
+    Import(ImportParams),
 }
 
 impl Display for Backend {
@@ -70,6 +75,68 @@ pub struct RunParams {
     pub reset: bool,
 }
 
+/// Parameters to the import command.
+#[derive(Debug, Args)]
+pub struct ImportParams {
+    /// The path to the sp-trie RocksDB database.
+    #[arg(long = "sp-trie-db")]
+    #[clap(default_value = "sp_trie_db")]
+    pub sp_trie_db: PathBuf,
+
+    /// The hex-encoded sp-trie root to import the state at.
+    ///
+    /// Defaults to the root stored in the sp-trie database.
+    #[arg(long = "sp-trie-root")]
+    pub sp_trie_root: Option<String>,
+
+    /// The path to the NOMT database to create. It must be empty or not exist.
+    #[arg(long = "nomt-db")]
+    #[clap(default_value = "nomt_db")]
+    pub nomt_db: PathBuf,
+
+    /// How to map sp-trie keys to NOMT key paths.
+    #[arg(long = "key-scheme")]
+    #[clap(default_value = "sha256")]
+    pub key_scheme: KeyScheme,
+
+    /// The hex-encoded NOMT root the import is expected to produce.
+    ///
+    /// The import fails if the resulting root differs.
+    #[arg(long = "expected-root")]
+    pub expected_root: Option<String>,
+
+    /// The number of items to commit at once.
+    #[arg(long = "batch-size")]
+    #[clap(default_value = "100000", value_parser=clap::value_parser!(u64).range(1..))]
+    pub batch_size: u64,
+
+    /// The number of threads to use in NOMT Merkle commit.
+    #[arg(long = "commit-concurrency")]
+    #[clap(default_value = "1")]
+    pub commit_concurrency: usize,
+
+    /// Number of io_uring instances (or I/O threads on non-Linux).
+    #[arg(long = "io-workers", short)]
+    #[clap(default_value = "3")]
+    pub io_workers: usize,
+
+    /// The number of hash-table buckets to create the NOMT database with.
+    #[arg(long = "buckets")]
+    pub hashtable_buckets: Option<u32>,
+}
+
+/// The mapping of sp-trie keys to NOMT key paths.
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum KeyScheme {
+    /// Use the keys as they are. Every key must be exactly 32 bytes.
+    Raw,
+    /// Hash the keys with SHA-256, like the workloads of this tool do.
+    Sha256,
+    /// Hash the keys with BLAKE2b-256.
+    #[value(name = "blake2-256")]
+    Blake2,
+}
+
 #[derive(Clone, Debug, Args)]
 pub struct WorkloadParams {
     /// Workload used by benchmarks.
@@ -0,0 +1,152 @@
+//! Import the state of an sp-trie database into NOMT.
+//!
+//! This walks all key-value pairs stored in an sp-trie at a given root, maps every key to a NOMT
+//! key path and bulk-loads the values into a fresh NOMT database in batches.
+
+use crate::{
+    cli::{ImportParams, KeyScheme},
+    sp_trie::{COL_ROOT, COL_TRIE, ROOT_KEY},
+};
+use anyhow::{anyhow, bail, Context as _, Result};
+use hash_db::{HashDBRef, Prefix};
+use kvdb::KeyValueDB;
+use kvdb_rocksdb::{Database, DatabaseConfig};
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+use sha2::Digest;
+use sp_trie::{DBValue, LayoutV1};
+use trie_db::{Trie, TrieDBBuilder};
+
+type Hasher = sp_core::Blake2Hasher;
+type Hash = sp_core::H256;
+
+/// A read-only view of the trie nodes stored in the RocksDB database.
+struct SnapshotDB {
+    kvdb: Database,
+}
+
+impl HashDBRef<Hasher, DBValue> for SnapshotDB {
+    fn get(&self, key: &Hash, prefix: Prefix) -> Option<DBValue> {
+        let key = sp_trie::prefixed_key::<Hasher>(key, prefix);
+        self.kvdb
+            .get(COL_TRIE, &key)
+            .expect("Database backend error")
+    }
+
+    fn contains(&self, key: &Hash, prefix: Prefix) -> bool {
+        self.get(key, prefix).is_some()
+    }
+}
+
+impl KeyScheme {
+    fn key_path(&self, key: &[u8]) -> Result<KeyPath> {
+        Ok(match self {
+            KeyScheme::Raw => key
+                .try_into()
+                .map_err(|_| anyhow!("key of {} bytes is not a key path", key.len()))?,
+            KeyScheme::Sha256 => sha2::Sha256::digest(key).into(),
+            KeyScheme::Blake2 => sp_core::hashing::blake2_256(key),
+        })
+    }
+}
+
+fn parse_hash(hex: &str) -> Result<[u8; 32]> {
+    array_bytes::hex2array(hex).map_err(|e| anyhow!("invalid hash {hex}: {e:?}"))
+}
+
+pub fn import(params: ImportParams) -> Result<()> {
+    let db_cfg = DatabaseConfig::with_columns(crate::sp_trie::NUM_COLUMNS);
+    let kvdb = Database::open(&db_cfg, &params.sp_trie_db)
+        .with_context(|| format!("failed to open sp-trie database at {:?}", params.sp_trie_db))?;
+
+    let root = match params.sp_trie_root {
+        Some(ref root) => Hash::from(parse_hash(root)?),
+        None => match kvdb.get(COL_ROOT, ROOT_KEY)? {
+            Some(root) => Hash::from_slice(&root[..32]),
+            None => bail!("no root stored in the sp-trie database, specify one explicitly"),
+        },
+    };
+    let expected_root = params
+        .expected_root
+        .as_deref()
+        .map(parse_hash)
+        .transpose()?;
+
+    let mut opts = Options::new();
+    opts.path(&params.nomt_db);
+    opts.commit_concurrency(params.commit_concurrency);
+    opts.io_workers(params.io_workers);
+    if let Some(buckets) = params.hashtable_buckets {
+        opts.hashtable_buckets(buckets);
+    }
+    let nomt = Nomt::<Blake3Hasher>::open(opts)?;
+    if !nomt.is_empty() {
+        bail!("the NOMT database at {:?} is not empty", params.nomt_db);
+    }
+
+    let snapshot = SnapshotDB { kvdb };
+    let trie = TrieDBBuilder::<LayoutV1<Hasher>>::new(&snapshot, &root).build();
+    let iter = trie
+        .iter()
+        .map_err(|e| anyhow!("failed to iterate the sp-trie: {e:?}"))?;
+
+    let start = std::time::Instant::now();
+    let mut imported = 0u64;
+    let batch_size = params.batch_size as usize;
+    let mut batch = Vec::with_capacity(batch_size);
+    for item in iter {
+        let (key, value) = item.map_err(|e| anyhow!("failed to read the sp-trie: {e:?}"))?;
+        let key_path = params.key_scheme.key_path(&key)?;
+        batch.push((key_path, KeyReadWrite::Write(Some(value.into()))));
+
+        if batch.len() == batch_size {
+            imported += commit_batch(&nomt, &mut batch)?;
+            println!("imported {imported} items");
+        }
+    }
+    imported += commit_batch(&nomt, &mut batch)?;
+
+    let nomt_root = nomt.root();
+    println!(
+        "imported {imported} items in {}s, sp-trie root 0x{}, NOMT root 0x{}",
+        start.elapsed().as_secs(),
+        array_bytes::bytes2hex("", root.as_bytes()),
+        array_bytes::bytes2hex("", nomt_root),
+    );
+
+    if let Some(expected_root) = expected_root {
+        if nomt_root != expected_root {
+            bail!(
+                "root mismatch: expected 0x{}, got 0x{}",
+                array_bytes::bytes2hex("", expected_root),
+                array_bytes::bytes2hex("", nomt_root),
+            );
+        }
+        println!("NOMT root matches the expected root");
+    }
+
+    nomt.close()?;
+    Ok(())
+}
+
+/// Commit the batch to NOMT, leaving it empty. Returns the number of items committed.
+fn commit_batch(
+    nomt: &Nomt<Blake3Hasher>,
+    batch: &mut Vec<(KeyPath, KeyReadWrite)>,
+) -> Result<u64> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+
+    batch.sort_by_key(|(k, _)| *k);
+    if let Some(w) = batch.windows(2).find(|w| w[0].0 == w[1].0) {
+        bail!(
+            "two keys map to the same key path 0x{}",
+            array_bytes::bytes2hex("", w[0].0)
+        );
+    }
+
+    let len = batch.len() as u64;
+    let session = nomt.begin_session();
+    nomt.commit(session, std::mem::take(batch))?;
+    Ok(len)
+}
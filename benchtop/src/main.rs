@@ -1,6 +1,7 @@
 mod backend;
 mod cli;
 mod custom_workload;
+mod import;
 mod nomt;
 mod sov_db;
 mod sp_trie;
@@ -19,6 +20,7 @@ pub fn main() -> Result<()> {
     match cli.command {
         Commands::Init(params) => init(params),
         Commands::Run(params) => run(params),
+        Commands::Import(params) => import::import(params),
     }
 }
 
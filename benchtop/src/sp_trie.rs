@@ -13,11 +13,11 @@ type Hash = sp_core::H256;
 
 const SP_TRIE_DB_FOLDER: &str = "sp_trie_db";
 
-const NUM_COLUMNS: u32 = 2;
-const COL_TRIE: u32 = 0;
-const COL_ROOT: u32 = 1;
+pub const NUM_COLUMNS: u32 = 2;
+pub const COL_TRIE: u32 = 0;
+pub const COL_ROOT: u32 = 1;
 
-const ROOT_KEY: &[u8] = b"root";
+pub const ROOT_KEY: &[u8] = b"root";
 
 pub struct SpTrieDB {
     pub kvdb: Arc<dyn KeyValueDB>,
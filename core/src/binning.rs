@@ -0,0 +1,178 @@
+//! Remapping of application keys into bins.
+//!
+//! Key paths are usually hashes of the application keys, which scatters related keys, such as the
+//! storage slots of a single contract, all over the trie. Binning keeps them together: the key
+//! path of a key within a bin begins with the first bits of the hash of the bin, followed by the
+//! hash of the key. Keys in the same bin then share the pages of the trie below the bin prefix, so
+//! reading and updating them together touches far fewer pages.
+//!
+//! The binning of a database is fixed when it is created. Verifiers must apply the same binning to
+//! map application keys to the key paths covered by a proof.
+
+use crate::trie::KeyPath;
+use bitvec::prelude::*;
+
+/// The maximum number of bits of a key path taken from the hash of the bin.
+///
+/// The rest of the key path is taken from the hash of the key, so this bounds the chance of two
+/// keys within the same bin colliding.
+pub const MAX_BIN_BITS: u8 = 64;
+
+/// Determines how key paths are derived from bins and keys. See the [module docs](self).
+///
+/// With the `serde`, `scale` or `borsh` features, a binning is encoded as its number of bin
+/// bits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "u8", try_from = "u8")
+)]
+pub struct KeyBinning {
+    bin_bits: u8,
+}
+
+#[cfg(feature = "serde")]
+impl From<KeyBinning> for u8 {
+    fn from(binning: KeyBinning) -> u8 {
+        binning.bin_bits
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<u8> for KeyBinning {
+    type Error = &'static str;
+
+    fn try_from(bin_bits: u8) -> Result<Self, Self::Error> {
+        KeyBinning::from_bin_bits(bin_bits).ok_or("bin bits exceed MAX_BIN_BITS")
+    }
+}
+
+#[cfg(feature = "scale")]
+impl codec::Encode for KeyBinning {
+    fn size_hint(&self) -> usize {
+        1
+    }
+
+    fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+        self.bin_bits.encode_to(dest);
+    }
+}
+
+#[cfg(feature = "scale")]
+impl codec::EncodeLike for KeyBinning {}
+
+#[cfg(feature = "scale")]
+impl codec::Decode for KeyBinning {
+    fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+        KeyBinning::from_bin_bits(u8::decode(input)?)
+            .ok_or_else(|| "bin bits exceed MAX_BIN_BITS".into())
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshSerialize for KeyBinning {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        self.bin_bits.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshDeserialize for KeyBinning {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        KeyBinning::from_bin_bits(u8::deserialize_reader(reader)?).ok_or_else(|| {
+            borsh::io::Error::new(
+                borsh::io::ErrorKind::InvalidData,
+                "bin bits exceed MAX_BIN_BITS",
+            )
+        })
+    }
+}
+
+impl KeyBinning {
+    /// No binning: the key path is the hash of the key.
+    pub const DISABLED: Self = Self { bin_bits: 0 };
+
+    /// Binning where the first `bin_bits` bits of the key path are taken from the hash of the bin.
+    ///
+    /// Panics if `bin_bits` exceeds [`MAX_BIN_BITS`].
+    pub fn new(bin_bits: u8) -> Self {
+        Self::from_bin_bits(bin_bits).expect("bin bits exceed MAX_BIN_BITS")
+    }
+
+    /// Like [`Self::new`], but returns `None` if `bin_bits` exceeds [`MAX_BIN_BITS`].
+    pub fn from_bin_bits(bin_bits: u8) -> Option<Self> {
+        (bin_bits <= MAX_BIN_BITS).then_some(Self { bin_bits })
+    }
+
+    /// The number of bits of a key path taken from the hash of the bin. 0 means disabled.
+    pub fn bin_bits(&self) -> u8 {
+        self.bin_bits
+    }
+
+    /// Whether keys are binned.
+    pub fn is_enabled(&self) -> bool {
+        self.bin_bits != 0
+    }
+
+    /// Returns the key path of a key within a bin, given the hashes of both.
+    ///
+    /// Without binning, this is the hash of the key.
+    pub fn key_path(&self, bin_hash: &[u8; 32], key_hash: &[u8; 32]) -> KeyPath {
+        let bin_bits = self.bin_bits as usize;
+        let mut key_path = [0; 32];
+        let bits = key_path.view_bits_mut::<Msb0>();
+        bits[..bin_bits].copy_from_bitslice(&bin_hash.view_bits::<Msb0>()[..bin_bits]);
+        bits[bin_bits..].copy_from_bitslice(&key_hash.view_bits::<Msb0>()[..256 - bin_bits]);
+        key_path
+    }
+
+    /// Returns the prefix shared by the key paths of all keys within the bin, given the hash of
+    /// the bin. Empty without binning.
+    pub fn bin_prefix<'a>(&self, bin_hash: &'a [u8; 32]) -> &'a BitSlice<u8, Msb0> {
+        &bin_hash.view_bits::<Msb0>()[..self.bin_bits as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_is_key_hash() {
+        let key_hash = [0x5A; 32];
+        assert_eq!(
+            KeyBinning::DISABLED.key_path(&[0xFF; 32], &key_hash),
+            key_hash
+        );
+        assert!(KeyBinning::DISABLED.bin_prefix(&[0xFF; 32]).is_empty());
+    }
+
+    #[test]
+    fn keys_in_bin_share_prefix() {
+        let binning = KeyBinning::new(12);
+        let bin_hash = [0xAB; 32];
+        let a = binning.key_path(&bin_hash, &[0x00; 32]);
+        let b = binning.key_path(&bin_hash, &[0xFF; 32]);
+
+        let prefix = binning.bin_prefix(&bin_hash);
+        assert_eq!(prefix.len(), 12);
+        assert!(a.view_bits::<Msb0>().starts_with(prefix));
+        assert!(b.view_bits::<Msb0>().starts_with(prefix));
+
+        // The rest of the key path is the beginning of the key hash.
+        assert_eq!(&a[..2], &[0xAB, 0xA0]);
+        assert_eq!(&a[2..], &[0x00; 30]);
+        assert_eq!(&b[..2], &[0xAB, 0xAF]);
+        assert_eq!(&b[2..], &[0xFF; 30]);
+    }
+
+    #[test]
+    fn bin_bits_are_bounded() {
+        assert_eq!(
+            KeyBinning::from_bin_bits(MAX_BIN_BITS).map(|b| b.bin_bits()),
+            Some(MAX_BIN_BITS)
+        );
+        assert_eq!(KeyBinning::from_bin_bits(MAX_BIN_BITS + 1), None);
+    }
+}
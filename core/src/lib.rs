@@ -5,16 +5,27 @@
 //!
 //! The core types and proof verification routines of this crate do not require the
 //! standard library, but do require Rust's alloc crate.
+//!
+//! Disable the default `std` feature to build for `no_std` targets, e.g. zkVM guests verifying
+//! witnesses. The `serde`, `scale` and `borsh` features are available without `std` as well.
+//!
+//! Witnesses produced by NOMT can be decoded and verified with the [`witness`] and
+//! [`witness_format`] modules alone, e.g. by light clients compiled to WebAssembly.
 
 #![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
 
 extern crate alloc;
 
+pub mod binning;
 pub mod multi_proof;
 pub mod multi_proof_verification;
 pub mod page;
 pub mod page_id;
 pub mod proof;
+pub mod range_proof;
 pub mod trie;
 pub mod trie_pos;
 pub mod update;
+pub mod witness;
+pub mod witness_compression;
+pub mod witness_format;
@@ -1,13 +1,13 @@
 use crate::{
     multi_proof::{MultiPathProof, MultiProof},
-    proof::{hash_path, KeyOutOfScope, PathProofTerminal},
+    proof::{hash_path, KeyOutOfScope, PathProof, PathProofTerminal},
     trie::{InternalData, KeyPath, LeafData, Node, NodeHasher, NodeHasherExt, TERMINATOR},
 };
 use bitvec::prelude::*;
 use core::cmp::Ordering;
 
 #[cfg(not(feature = "std"))]
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 
 /// Errors in multi-proof verification.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -146,8 +146,53 @@ pub fn verify<H: NodeHasher>(
     multi_proof: &MultiProof,
     root: Node,
 ) -> Result<VerifiedMultiProof, MultiProofVerificationError> {
+    verify_inner::<H>(multi_proof, root, &mut [])?;
+    let paths = multi_proof
+        .paths
+        .iter()
+        .map(|path| VerifiedMultiPath {
+            terminal: path.terminal.clone(),
+            depth: path.depth,
+        })
+        .collect::<Vec<_>>();
+    Ok(VerifiedMultiProof { inner: paths })
+}
+
+/// Verify a multi-proof against an expected root, and recover the path proofs it was made of,
+/// including the siblings which the multi-proof omits because they can be computed from the
+/// other paths.
+///
+/// The path proofs are in the same order as the paths of the multi-proof.
+pub fn verify_path_proofs<H: NodeHasher>(
+    multi_proof: &MultiProof,
+    root: Node,
+) -> Result<Vec<PathProof>, MultiProofVerificationError> {
+    let mut path_siblings = multi_proof
+        .paths
+        .iter()
+        .map(|path| vec![TERMINATOR; path.depth])
+        .collect::<Vec<_>>();
+    verify_inner::<H>(multi_proof, root, &mut path_siblings)?;
+    Ok(multi_proof
+        .paths
+        .iter()
+        .zip(path_siblings)
+        .map(|(path, siblings)| PathProof {
+            terminal: path.terminal.clone(),
+            siblings,
+        })
+        .collect())
+}
+
+// verifies the multi-proof. if `path_siblings` is non-empty, every sibling of every path is
+// recorded in it, by depth.
+fn verify_inner<H: NodeHasher>(
+    multi_proof: &MultiProof,
+    root: Node,
+    path_siblings: &mut [Vec<Node>],
+) -> Result<(), MultiProofVerificationError> {
     let (new_root, siblings_used) =
-        verify_range::<H>(0, &multi_proof.paths, &multi_proof.siblings)?;
+        verify_range::<H>(0, &multi_proof.paths, &multi_proof.siblings, path_siblings)?;
 
     if root != new_root {
         return Err(MultiProofVerificationError::RootMismatch);
@@ -157,15 +202,7 @@ pub fn verify<H: NodeHasher>(
         return Err(MultiProofVerificationError::TooManySiblings);
     }
 
-    let paths = multi_proof
-        .paths
-        .iter()
-        .map(|path| VerifiedMultiPath {
-            terminal: path.terminal.clone(),
-            depth: path.depth,
-        })
-        .collect::<Vec<_>>();
-    Ok(VerifiedMultiProof { inner: paths })
+    Ok(())
 }
 
 // returns the the node made by verifying this range along with the number of siblings used.
@@ -173,6 +210,7 @@ fn verify_range<H: NodeHasher>(
     start_depth: usize,
     paths: &[MultiPathProof],
     siblings: &[Node],
+    path_siblings: &mut [Vec<Node>],
 ) -> Result<(Node, usize), MultiProofVerificationError> {
     // the range should never be empty except in the first call, if the entire multi-proof is
     // empty.
@@ -184,6 +222,9 @@ fn verify_range<H: NodeHasher>(
         // nodes, hash them up, and return that
         let terminal_path = &paths[0];
         let unique_len = terminal_path.depth - start_depth;
+        if let Some(path_siblings) = path_siblings.first_mut() {
+            path_siblings[start_depth..].copy_from_slice(&siblings[..unique_len]);
+        }
 
         let node = hash_path::<H>(
             terminal_path.terminal.node::<H>(),
@@ -222,11 +263,18 @@ fn verify_range<H: NodeHasher>(
     // bisection is based off of them.
     let bisect_idx = search_result.unwrap_err();
 
+    let (left_path_siblings, right_path_siblings) = if path_siblings.is_empty() {
+        (&mut [][..], &mut [][..])
+    } else {
+        path_siblings.split_at_mut(bisect_idx)
+    };
+
     // recurse into the left bisection.
     let (left_node, left_siblings_used) = verify_range::<H>(
         uncommon_start_len,
         &paths[..bisect_idx],
         &siblings[common_bits..],
+        left_path_siblings,
     )?;
 
     // now that we know how many siblings were used on the left, we can recurse into the right.
@@ -234,8 +282,20 @@ fn verify_range<H: NodeHasher>(
         uncommon_start_len,
         &paths[bisect_idx..],
         &siblings[common_bits + left_siblings_used..],
+        right_path_siblings,
     )?;
 
+    // the common siblings are shared by all paths, and each side of the bisection is the sibling
+    // of the paths on the other side.
+    for siblings_of_path in left_path_siblings.iter_mut() {
+        siblings_of_path[start_depth..common_len].copy_from_slice(&siblings[..common_bits]);
+        siblings_of_path[common_len] = right_node;
+    }
+    for siblings_of_path in right_path_siblings.iter_mut() {
+        siblings_of_path[start_depth..common_len].copy_from_slice(&siblings[..common_bits]);
+        siblings_of_path[common_len] = left_node;
+    }
+
     let total_siblings_used = common_bits + left_siblings_used + right_siblings_used;
     // hash up the internal node composed of left/right, then repeatedly apply common siblings.
     let node = hash_path::<H>(
@@ -252,8 +312,8 @@ fn verify_range<H: NodeHasher>(
 #[cfg(test)]
 mod tests {
     use super::{
-        verify, InternalData, LeafData, MultiProof, NodeHasher, NodeHasherExt, PathProofTerminal,
-        TERMINATOR,
+        verify, verify_path_proofs, InternalData, LeafData, MultiProof, NodeHasher, NodeHasherExt,
+        PathProofTerminal, TERMINATOR,
     };
     use crate::{proof::PathProof, trie};
 
@@ -327,6 +387,9 @@ mod tests {
 
         assert!(verified.confirm_value(&leaf_0).unwrap());
         assert!(verified.confirm_value(&leaf_1).unwrap());
+
+        let path_proofs = verify_path_proofs::<Blake3Hasher>(&multi_proof, root).unwrap();
+        assert_eq!(path_proofs, vec![path_proof_0, path_proof_1]);
     }
 
     #[test]
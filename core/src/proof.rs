@@ -4,6 +4,10 @@ use crate::trie::{
     self, InternalData, KeyPath, LeafData, Node, NodeHasher, NodeHasherExt, NodeKind, TERMINATOR,
 };
 use crate::trie_pos::TriePosition;
+use crate::{
+    multi_proof::MultiProof,
+    multi_proof_verification::{self, MultiProofVerificationError},
+};
 
 use bitvec::prelude::*;
 
@@ -13,6 +17,12 @@ use alloc::vec::Vec;
 /// Wrapper for a terminal node, it will store the LeafData if it is a leaf node,
 /// and just the KeyPath to that terminal if it is a terminator node
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub enum PathProofTerminal {
     Leaf(LeafData),
     Terminator(TriePosition),
@@ -36,7 +46,13 @@ impl PathProofTerminal {
 }
 
 /// A proof of some particular path through the trie.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
 pub struct PathProof {
     /// The terminal node encountered when looking up a key. This is always either a terminator or
     /// leaf.
@@ -80,6 +96,22 @@ impl PathProof {
     }
 }
 
+/// Verify a proof that a key has no value in the trie with the given root.
+///
+/// A return value of `Ok(true)` confirms that the key has no value. `Ok(false)` means that the
+/// proof is valid but shows the key with a value.
+///
+/// Fails if the proof doesn't verify against the root along the path of the key.
+pub fn verify_absence<H: NodeHasher>(
+    root: Node,
+    key_path: &KeyPath,
+    proof: &PathProof,
+) -> Result<bool, PathProofVerificationError> {
+    let verified = proof.verify::<H>(key_path.view_bits::<Msb0>(), root)?;
+    // UNWRAP: the proof was verified along the key path, so the key is in scope.
+    Ok(verified.confirm_nonexistence(key_path).unwrap())
+}
+
 /// Given a node, a path, and a set of siblings, hash up to the root and return it.
 /// This only consumes the last `siblings.len()` bits of the path, or the whole path.
 /// Siblings are in ascending order from the last bit of `path`.
@@ -314,6 +346,79 @@ pub fn verify_update<H: NodeHasher>(
     Ok(pending_siblings.pop().map(|n| n.0).unwrap_or(TERMINATOR))
 }
 
+/// Errors in [`verify_multi_proof_update`].
+#[derive(Debug, Clone, Copy)]
+pub enum MultiProofUpdateError {
+    /// The multi-proof doesn't verify against the previous root.
+    InvalidProof(MultiProofVerificationError),
+    /// The ops are not in strictly ascending order by key.
+    OpsOutOfOrder,
+    /// An op's key isn't covered by any path of the multi-proof.
+    OpOutOfScope,
+    /// The ops can't be applied to the paths.
+    InvalidUpdate(VerifyUpdateError),
+}
+
+/// Verify a multi-proof against the root node and compute the root after applying the given
+/// writes, without any backing storage. This is the same root as computed by a full node
+/// applying the writes to the trie.
+///
+/// Ops must be in strictly ascending order by key, and every key must start with the path of one
+/// of the paths of the multi-proof. Paths without ops are ignored.
+pub fn verify_multi_proof_update<H: NodeHasher>(
+    prev_root: Node,
+    multi_proof: &MultiProof,
+    ops: &[(KeyPath, Option<trie::ValueHash>)],
+) -> Result<Node, MultiProofUpdateError> {
+    if ops.windows(2).any(|w| w[0].0 >= w[1].0) {
+        return Err(MultiProofUpdateError::OpsOutOfOrder);
+    }
+
+    let path_proofs = multi_proof_verification::verify_path_proofs::<H>(multi_proof, prev_root)
+        .map_err(MultiProofUpdateError::InvalidProof)?;
+
+    let mut updates: Vec<PathUpdate> = Vec::new();
+    let mut ops = ops.iter().peekable();
+    for path_proof in path_proofs {
+        let path = &path_proof.terminal.path()[..path_proof.siblings.len()];
+        let mut path_ops = Vec::new();
+        if ops
+            .peek()
+            .is_some_and(|(key, _)| key.view_bits::<Msb0>() < path)
+        {
+            return Err(MultiProofUpdateError::OpOutOfScope);
+        }
+        while let Some((key, value)) =
+            ops.next_if(|(key, _)| key.view_bits::<Msb0>().starts_with(path))
+        {
+            path_ops.push((*key, *value));
+        }
+        if path_ops.is_empty() {
+            continue;
+        }
+        updates.push(PathUpdate {
+            inner: VerifiedPathProof {
+                key_path: path.into(),
+                terminal: match path_proof.terminal {
+                    PathProofTerminal::Leaf(leaf_data) => Some(leaf_data),
+                    PathProofTerminal::Terminator(_) => None,
+                },
+                siblings: path_proof.siblings,
+                root: prev_root,
+            },
+            ops: path_ops,
+        });
+    }
+    if ops.next().is_some() {
+        return Err(MultiProofUpdateError::OpOutOfScope);
+    }
+
+    if updates.is_empty() {
+        return Ok(prev_root);
+    }
+    verify_update::<H>(prev_root, &updates).map_err(MultiProofUpdateError::InvalidUpdate)
+}
+
 // TODO: dedup, this appears in `update` as well.
 pub fn shared_bits(a: &BitSlice<u8, Msb0>, b: &BitSlice<u8, Msb0>) -> usize {
     a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
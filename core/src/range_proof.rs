@@ -0,0 +1,110 @@
+//! Proving that a set of leaves is exactly the contents of a range of keys.
+//!
+//! A range proof is a set of path proofs against the same root: one for each key within the
+//! range, and one for each bound of the range. Every node of the trie is either on one of the
+//! paths, below one of the terminals, or below one of the siblings. A range proof is complete if
+//! every sibling whose subtrie intersects the range and which isn't on another path is a
+//! [`TERMINATOR`], so no key within the range can be left out.
+
+use crate::{
+    proof::{PathProof, PathProofTerminal, PathProofVerificationError},
+    trie::{KeyPath, LeafData, Node, NodeHasher, TERMINATOR},
+};
+use bitvec::prelude::*;
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+/// A proof of all the leaves within a range of keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeProof {
+    /// Path proofs of the keys within the range and of the bounds of the range, in ascending
+    /// order by path.
+    pub path_proofs: Vec<PathProof>,
+}
+
+/// Errors in range proof verification.
+#[derive(Debug, Clone, Copy)]
+pub enum RangeProofVerificationError {
+    /// The start of the range is after its end.
+    EmptyRange,
+    /// The path proof at the given index doesn't verify against the root.
+    InvalidPath(usize, PathProofVerificationError),
+    /// The path proofs are not in strictly ascending order by path.
+    PathsOutOfOrder,
+    /// The proof contains no path proofs. At least one is needed to prove the root.
+    NoPaths,
+    /// A non-empty subtrie within the range isn't covered by any path proof.
+    Incomplete,
+}
+
+impl RangeProof {
+    /// Verify the proof against the root for the inclusive range of keys from `start` to `end`.
+    ///
+    /// Returns every leaf whose key is within the range, in ascending key order. The caller
+    /// checks these against the values it was given, e.g. by hashing them.
+    pub fn verify<H: NodeHasher>(
+        &self,
+        root: Node,
+        start: &KeyPath,
+        end: &KeyPath,
+    ) -> Result<Vec<LeafData>, RangeProofVerificationError> {
+        if start > end {
+            return Err(RangeProofVerificationError::EmptyRange);
+        }
+
+        let mut paths: Vec<&BitSlice<u8, Msb0>> = Vec::with_capacity(self.path_proofs.len());
+        for (i, proof) in self.path_proofs.iter().enumerate() {
+            let terminal_path = proof.terminal.path();
+            proof
+                .verify::<H>(terminal_path, root)
+                .map_err(|e| RangeProofVerificationError::InvalidPath(i, e))?;
+            let path = &terminal_path[..proof.siblings.len()];
+            if paths.last().is_some_and(|prev| *prev >= path) {
+                return Err(RangeProofVerificationError::PathsOutOfOrder);
+            }
+            paths.push(path);
+        }
+
+        if paths.is_empty() {
+            return Err(RangeProofVerificationError::NoPaths);
+        }
+
+        // Whether any path goes through the given prefix. Paths starting with the prefix are
+        // contiguous in the ascending order, starting at the first path not below the prefix.
+        let covered = |prefix: &BitSlice<u8, Msb0>| {
+            let i = paths.partition_point(|path| *path < prefix);
+            paths.get(i).is_some_and(|path| path.starts_with(prefix))
+        };
+
+        let mut leaves = Vec::new();
+        for (proof, path) in self.path_proofs.iter().zip(&paths) {
+            for (depth, sibling) in proof.siblings.iter().enumerate() {
+                if *sibling == TERMINATOR {
+                    continue;
+                }
+                let mut prefix: BitVec<u8, Msb0> = path[..depth].to_bitvec();
+                prefix.push(!path[depth]);
+                if intersects(&prefix, start, end) && !covered(&prefix) {
+                    return Err(RangeProofVerificationError::Incomplete);
+                }
+            }
+
+            if let PathProofTerminal::Leaf(leaf) = &proof.terminal {
+                if &leaf.key_path >= start && &leaf.key_path <= end {
+                    leaves.push(leaf.clone());
+                }
+            }
+        }
+        Ok(leaves)
+    }
+}
+
+/// Whether any key starting with the prefix is within the inclusive range.
+fn intersects(prefix: &BitSlice<u8, Msb0>, start: &KeyPath, end: &KeyPath) -> bool {
+    let mut first = [0u8; 32];
+    first.view_bits_mut::<Msb0>()[..prefix.len()].copy_from_bitslice(prefix);
+    let mut last = [0xFFu8; 32];
+    last.view_bits_mut::<Msb0>()[..prefix.len()].copy_from_bitslice(prefix);
+    &first <= end && &last >= start
+}
@@ -7,7 +7,15 @@ use alloc::fmt;
 use bitvec::prelude::*;
 
 /// Encapsulates logic for moving around in paged storage for a binary trie.
+///
+/// With the `serde`, `scale` or `borsh` features, a position is encoded as its path and depth.
+/// The node index is recomputed when decoding.
 #[derive(Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "SerdeTriePosition", try_from = "SerdeTriePosition")
+)]
 pub struct TriePosition {
     // The bits after depth are irrelevant.
     path: [u8; 32],
@@ -23,6 +31,88 @@ impl PartialEq for TriePosition {
 
 impl Eq for TriePosition {}
 
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeTriePosition {
+    path: [u8; 32],
+    depth: u16,
+}
+
+#[cfg(feature = "serde")]
+impl From<TriePosition> for SerdeTriePosition {
+    fn from(pos: TriePosition) -> Self {
+        SerdeTriePosition {
+            path: pos.path,
+            depth: pos.depth,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SerdeTriePosition> for TriePosition {
+    type Error = &'static str;
+
+    fn try_from(pos: SerdeTriePosition) -> Result<Self, Self::Error> {
+        match pos.depth {
+            0 => Ok(TriePosition::new()),
+            1..=256 => Ok(TriePosition::from_path_and_depth(pos.path, pos.depth)),
+            _ => Err("trie position deeper than 256 bits"),
+        }
+    }
+}
+
+#[cfg(feature = "scale")]
+impl codec::Encode for TriePosition {
+    fn size_hint(&self) -> usize {
+        34
+    }
+
+    fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+        self.path.encode_to(dest);
+        self.depth.encode_to(dest);
+    }
+}
+
+#[cfg(feature = "scale")]
+impl codec::EncodeLike for TriePosition {}
+
+#[cfg(feature = "scale")]
+impl codec::Decode for TriePosition {
+    fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let path = <[u8; 32]>::decode(input)?;
+        let depth = u16::decode(input)?;
+        match depth {
+            0 => Ok(TriePosition::new()),
+            1..=256 => Ok(TriePosition::from_path_and_depth(path, depth)),
+            _ => Err("trie position deeper than 256 bits".into()),
+        }
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshSerialize for TriePosition {
+    fn serialize<W: borsh::io::Write>(&self, writer: &mut W) -> borsh::io::Result<()> {
+        self.path.serialize(writer)?;
+        self.depth.serialize(writer)
+    }
+}
+
+#[cfg(feature = "borsh")]
+impl borsh::BorshDeserialize for TriePosition {
+    fn deserialize_reader<R: borsh::io::Read>(reader: &mut R) -> borsh::io::Result<Self> {
+        let path = <[u8; 32]>::deserialize_reader(reader)?;
+        let depth = u16::deserialize_reader(reader)?;
+        match depth {
+            0 => Ok(TriePosition::new()),
+            1..=256 => Ok(TriePosition::from_path_and_depth(path, depth)),
+            _ => Err(borsh::io::Error::new(
+                borsh::io::ErrorKind::InvalidData,
+                "trie position deeper than 256 bits",
+            )),
+        }
+    }
+}
+
 impl TriePosition {
     /// Create a new `TriePosition` at the root.
     pub fn new() -> Self {
@@ -360,4 +450,65 @@ mod tests {
         assert_eq!(p.depth as usize, 255);
         p.down(false);
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        for bits in ["", "1", "0110101", &"10".repeat(128)] {
+            let mut p = TriePosition::new();
+            for bit in bits.chars() {
+                p.down(bit == '1');
+            }
+            let json = serde_json::to_string(&p).unwrap();
+            let q: TriePosition = serde_json::from_str(&json).unwrap();
+            assert_eq!(q, p);
+            assert_eq!(q.depth(), p.depth());
+            assert_eq!(q.node_index(), p.node_index());
+        }
+
+        let too_deep = format!("{{\"path\":{:?},\"depth\":257}}", [0u8; 32]);
+        assert!(serde_json::from_str::<TriePosition>(&too_deep).is_err());
+    }
+
+    #[cfg(feature = "scale")]
+    #[test]
+    fn scale_round_trip() {
+        use codec::{Decode, Encode};
+
+        for bits in ["", "1", "0110101", &"10".repeat(128)] {
+            let mut p = TriePosition::new();
+            for bit in bits.chars() {
+                p.down(bit == '1');
+            }
+            let encoded = p.encode();
+            assert_eq!(encoded.len(), 34);
+            let q = TriePosition::decode(&mut &encoded[..]).unwrap();
+            assert_eq!(q, p);
+            assert_eq!(q.depth(), p.depth());
+            assert_eq!(q.node_index(), p.node_index());
+        }
+
+        let too_deep = ([0u8; 32], 257u16).encode();
+        assert!(TriePosition::decode(&mut &too_deep[..]).is_err());
+    }
+
+    #[cfg(feature = "borsh")]
+    #[test]
+    fn borsh_round_trip() {
+        for bits in ["", "1", "0110101", &"10".repeat(128)] {
+            let mut p = TriePosition::new();
+            for bit in bits.chars() {
+                p.down(bit == '1');
+            }
+            let encoded = borsh::to_vec(&p).unwrap();
+            assert_eq!(encoded.len(), 34);
+            let q: TriePosition = borsh::from_slice(&encoded).unwrap();
+            assert_eq!(q, p);
+            assert_eq!(q.depth(), p.depth());
+            assert_eq!(q.node_index(), p.node_index());
+        }
+
+        let too_deep = borsh::to_vec(&([0u8; 32], 257u16)).unwrap();
+        assert!(borsh::from_slice::<TriePosition>(&too_deep).is_err());
+    }
 }
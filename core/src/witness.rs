@@ -0,0 +1,494 @@
+//! Witnesses of the operations of a commit, and their stateless verification.
+//!
+//! A witness consists of path proofs against the root of the trie before the commit. Together
+//! with the witnessed operations, it allows recomputing the root after the commit without access
+//! to the rest of the trie.
+
+use bitvec::prelude::*;
+use core::fmt;
+
+use crate::{
+    binning::KeyBinning,
+    multi_proof::MultiProof,
+    proof::{
+        self, PathProof, PathProofTerminal, PathProofVerificationError, PathUpdate,
+        VerifyUpdateError,
+    },
+    trie::{KeyPath, Node, NodeHasher, ValueHash},
+    trie_pos::TriePosition,
+};
+
+#[cfg(not(feature = "std"))]
+use alloc::{collections::BTreeMap, vec::Vec};
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+/// A witness that can be used to prove the correctness of state trie retrievals and updates.
+///
+/// Verified together with the witnessed operations by [`verify_witness`].
+///
+/// Witnesses produced by NOMT are canonical: the paths are ordered by their position in the trie,
+/// as checked by [`Witness::is_canonical`]. Together with the canonical order of the witnessed
+/// operations, this makes the witness of a commit, and its encoding, independent of the number of
+/// commit workers and of their scheduling, so that a witness can be committed to by its hash.
+///
+/// Serializable with the `serde` feature, and encodable with SCALE or borsh with the `scale` or
+/// `borsh` features.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct Witness {
+    /// Various paths down the trie used as part of this witness.
+    pub path_proofs: Vec<WitnessedPath>,
+    /// The binning the key paths were derived with. Verifiers must derive the key paths of the
+    /// application keys the same way.
+    pub key_binning: KeyBinning,
+}
+
+impl Witness {
+    /// Whether the paths are in the canonical order, which is strictly ascending by path.
+    pub fn is_canonical(&self) -> bool {
+        self.path_proofs
+            .windows(2)
+            .all(|w| w[0].path.path() < w[1].path.path())
+    }
+
+    /// Combine the path proofs of the witness into a [`MultiProof`], which contains every sibling
+    /// node needed to verify all paths only once, and none of the nodes which can be recomputed
+    /// from the other paths.
+    ///
+    /// The paths of the multi-proof are ordered by path and deduplicated, so they don't
+    /// necessarily correspond to the path indices of the witnessed operations. Use
+    /// [`VerifiedMultiProof::find_index_for`](crate::multi_proof_verification::VerifiedMultiProof::find_index_for)
+    /// to find the path proving a key.
+    pub fn to_multi_proof(&self) -> MultiProof {
+        let mut paths = self.path_proofs.iter().collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.path.path().cmp(b.path.path()));
+        paths.dedup_by(|a, b| a.path.path() == b.path.path());
+        MultiProof::from_path_proofs(paths.into_iter().map(|p| p.inner.clone()).collect())
+    }
+}
+
+/// Operations provable by a corresponding witness.
+///
+/// Every operation refers to the path of the witness covering its key by index. Created with
+/// [`WitnessedOperations::new`], which checks that the operations are consistent with the paths
+/// they refer to. Decoded operations are not checked until they are verified with
+/// [`verify_witness`] or checked with [`WitnessedOperations::validate`].
+///
+/// Operations produced by NOMT are canonical: the reads and the writes are each ordered by key,
+/// as checked by [`WitnessedOperations::is_canonical`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct WitnessedOperations {
+    reads: Vec<WitnessedRead>,
+    writes: Vec<WitnessedWrite>,
+}
+
+impl WitnessedOperations {
+    /// Create the operations witnessed by the given witness.
+    ///
+    /// Fails if an operation refers to a path the witness doesn't contain, if the key of an
+    /// operation doesn't start with the path it refers to, or if a read contradicts the terminal
+    /// node of its path. The path proofs themselves are only checked by [`verify_witness`].
+    pub fn new(
+        witness: &Witness,
+        reads: Vec<WitnessedRead>,
+        writes: Vec<WitnessedWrite>,
+    ) -> Result<Self, WitnessVerificationError> {
+        let witnessed = WitnessedOperations { reads, writes };
+        witnessed.validate(witness)?;
+        Ok(witnessed)
+    }
+
+    /// Check that the operations are consistent with the paths of the witness they refer to, as
+    /// [`WitnessedOperations::new`] does.
+    pub fn validate(&self, witness: &Witness) -> Result<(), WitnessVerificationError> {
+        for read in &self.reads {
+            let path = path_of(witness, read.path_index, &read.key)?;
+            let confirmed = match (&path.inner.terminal, read.value) {
+                (PathProofTerminal::Leaf(leaf), Some(value_hash)) => {
+                    leaf.key_path == read.key && leaf.value_hash == value_hash
+                }
+                (PathProofTerminal::Leaf(leaf), None) => leaf.key_path != read.key,
+                (PathProofTerminal::Terminator(_), value) => value.is_none(),
+            };
+            if !confirmed {
+                return Err(WitnessVerificationError::ReadMismatch(read.key));
+            }
+        }
+        for write in &self.writes {
+            path_of(witness, write.path_index, &write.key)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the reads and the writes are each in the canonical order, which is strictly
+    /// ascending by key.
+    ///
+    /// Along with a canonical witness, this implies that the operations are also ordered by the
+    /// index of the path they refer to.
+    pub fn is_canonical(&self) -> bool {
+        self.reads.windows(2).all(|w| w[0].key < w[1].key)
+            && self.writes.windows(2).all(|w| w[0].key < w[1].key)
+    }
+
+    /// Read operations.
+    pub fn reads(&self) -> &[WitnessedRead] {
+        &self.reads
+    }
+
+    /// Write operations.
+    pub fn writes(&self) -> &[WitnessedWrite] {
+        &self.writes
+    }
+
+    /// Split into the read and write operations.
+    pub fn into_parts(self) -> (Vec<WitnessedRead>, Vec<WitnessedWrite>) {
+        (self.reads, self.writes)
+    }
+}
+
+// Returns the path an operation refers to, checking that it covers the key.
+fn path_of<'a>(
+    witness: &'a Witness,
+    path_index: usize,
+    key: &KeyPath,
+) -> Result<&'a WitnessedPath, WitnessVerificationError> {
+    let path = witness
+        .path_proofs
+        .get(path_index)
+        .ok_or(WitnessVerificationError::PathIndexOutOfBounds(path_index))?;
+    let in_scope = path
+        .path
+        .path()
+        .get(..path.inner.siblings.len())
+        .is_some_and(|prefix| key.view_bits::<Msb0>().starts_with(prefix));
+    if in_scope {
+        Ok(path)
+    } else {
+        Err(WitnessVerificationError::KeyOutOfScope(*key))
+    }
+}
+
+/// A path observed in the witness.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct WitnessedPath {
+    /// Proof of a query path along the trie.
+    pub inner: PathProof,
+    /// The query path itself.
+    pub path: TriePosition,
+}
+
+/// A witness of a read value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct WitnessedRead {
+    /// The key of the read value.
+    pub key: KeyPath,
+    /// The hash of the value witnessed. None means no value.
+    pub value: Option<ValueHash>,
+    /// The index of the path in the corresponding witness.
+    pub path_index: usize,
+}
+
+/// A witness of a write operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct WitnessedWrite {
+    /// The key of the written value.
+    pub key: KeyPath,
+    /// The hash of the written value. `None` means "delete".
+    pub value: Option<ValueHash>,
+    /// The index of the path in the corresponding witness.
+    pub path_index: usize,
+}
+
+// SCALE has no encoding for `usize`, so path indices are encoded as compact `u64`s.
+#[cfg(feature = "scale")]
+mod scale {
+    use codec::{Compact, Decode, Encode, EncodeLike, Error, Input, Output};
+
+    use super::{WitnessedRead, WitnessedWrite};
+
+    fn decode_path_index<I: Input>(input: &mut I) -> Result<usize, Error> {
+        let Compact(path_index) = Compact::<u64>::decode(input)?;
+        usize::try_from(path_index).map_err(|_| "path index out of range".into())
+    }
+
+    macro_rules! impl_witnessed_op {
+        ($ty:ident) => {
+            impl Encode for $ty {
+                fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+                    self.key.encode_to(dest);
+                    self.value.encode_to(dest);
+                    Compact(self.path_index as u64).encode_to(dest);
+                }
+            }
+
+            impl EncodeLike for $ty {}
+
+            impl Decode for $ty {
+                fn decode<I: Input>(input: &mut I) -> Result<Self, Error> {
+                    Ok($ty {
+                        key: Decode::decode(input)?,
+                        value: Decode::decode(input)?,
+                        path_index: decode_path_index(input)?,
+                    })
+                }
+            }
+        };
+    }
+
+    impl_witnessed_op!(WitnessedRead);
+    impl_witnessed_op!(WitnessedWrite);
+}
+
+/// An error verifying a witness with [`verify_witness`].
+#[derive(Debug, Clone, Copy)]
+pub enum WitnessVerificationError {
+    /// The path proof at the given index doesn't verify against the previous root.
+    InvalidPath(usize, PathProofVerificationError),
+    /// An operation refers to a path proof the witness doesn't contain.
+    PathIndexOutOfBounds(usize),
+    /// The key of an operation isn't covered by the path proof it refers to.
+    KeyOutOfScope(KeyPath),
+    /// The path proof of a read contradicts the value read.
+    ReadMismatch(KeyPath),
+    /// The writes can't be applied to the path proofs.
+    InvalidUpdate(VerifyUpdateError),
+}
+
+impl fmt::Display for WitnessVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitnessVerificationError::InvalidPath(index, e) => {
+                write!(f, "path proof {index} is invalid: {e:?}")
+            }
+            WitnessVerificationError::PathIndexOutOfBounds(index) => {
+                write!(f, "no path proof with index {index}")
+            }
+            WitnessVerificationError::KeyOutOfScope(_) => {
+                write!(f, "key out of scope of its path proof")
+            }
+            WitnessVerificationError::ReadMismatch(_) => {
+                write!(f, "read contradicts its path proof")
+            }
+            WitnessVerificationError::InvalidUpdate(e) => write!(f, "invalid update: {e:?}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WitnessVerificationError {}
+
+/// Verify a witness and the operations it witnesses against the root of the trie before them,
+/// returning the root after applying the witnessed writes.
+///
+/// This checks every path proof against `prev_root`, checks that every operation is consistent
+/// with its path as [`WitnessedOperations::validate`] does, and recomputes the new root from the writes. The returned root must be compared to the
+/// expected root of the trie after the operations.
+///
+/// Writes must be ordered by key path within each path, as produced by NOMT when committing.
+pub fn verify_witness<H: NodeHasher>(
+    prev_root: Node,
+    witness: &Witness,
+    witnessed: &WitnessedOperations,
+) -> Result<Node, WitnessVerificationError> {
+    let verified = witness
+        .path_proofs
+        .iter()
+        .enumerate()
+        .map(|(i, witnessed_path)| {
+            witnessed_path
+                .inner
+                .verify::<H>(witnessed_path.path.path(), prev_root)
+                .map_err(|e| WitnessVerificationError::InvalidPath(i, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    witnessed.validate(witness)?;
+
+    if witnessed.writes.is_empty() {
+        return Ok(prev_root);
+    }
+
+    let mut ops = BTreeMap::<usize, Vec<_>>::new();
+    for write in &witnessed.writes {
+        ops.entry(write.path_index)
+            .or_default()
+            .push((write.key, write.value));
+    }
+    let updates = ops
+        .into_iter()
+        .map(|(path_index, ops)| PathUpdate {
+            inner: verified[path_index].clone(),
+            ops,
+        })
+        .collect::<Vec<_>>();
+    proof::verify_update::<H>(prev_root, &updates).map_err(WitnessVerificationError::InvalidUpdate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        verify_witness, Witness, WitnessVerificationError, WitnessedOperations, WitnessedPath,
+        WitnessedRead,
+    };
+    use crate::{
+        proof::{PathProof, PathProofTerminal},
+        trie::{self, InternalData, LeafData, NodeHasher, NodeHasherExt},
+        trie_pos::TriePosition,
+    };
+
+    /// Hash nodes with blake3.
+    struct Blake3Hasher;
+
+    impl NodeHasher for Blake3Hasher {
+        fn hash_node(data: &trie::NodePreimage) -> [u8; 32] {
+            blake3::hash(data).into()
+        }
+    }
+
+    fn leaf(first_byte: u8, value: u8) -> LeafData {
+        let mut key_path = [0; 32];
+        key_path[0] = first_byte;
+        LeafData {
+            key_path,
+            value_hash: [value; 32],
+        }
+    }
+
+    fn position(key_path: [u8; 32], depth: u16) -> TriePosition {
+        TriePosition::from_path_and_depth(key_path, depth)
+    }
+
+    //     root
+    //     /  \
+    //    s3   v1
+    //   / \
+    //  v0  v2
+    fn witness() -> ([u8; 32], Witness, WitnessedOperations) {
+        let (leaf_0, leaf_1, leaf_2) = (
+            leaf(0b00000000, 0),
+            leaf(0b10000000, 1),
+            leaf(0b01000000, 2),
+        );
+        let v0 = Blake3Hasher::hash_leaf(&leaf_0);
+        let v1 = Blake3Hasher::hash_leaf(&leaf_1);
+        let v2 = Blake3Hasher::hash_leaf(&leaf_2);
+        let s3 = Blake3Hasher::hash_internal(&InternalData {
+            left: v0,
+            right: v2,
+        });
+        let root = Blake3Hasher::hash_internal(&InternalData {
+            left: s3,
+            right: v1,
+        });
+
+        let witness = Witness {
+            path_proofs: vec![
+                WitnessedPath {
+                    inner: PathProof {
+                        terminal: PathProofTerminal::Leaf(leaf_0.clone()),
+                        siblings: vec![v1, v2],
+                    },
+                    path: position(leaf_0.key_path, 2),
+                },
+                WitnessedPath {
+                    inner: PathProof {
+                        terminal: PathProofTerminal::Leaf(leaf_1.clone()),
+                        siblings: vec![s3],
+                    },
+                    path: position(leaf_1.key_path, 1),
+                },
+            ],
+            key_binning: Default::default(),
+        };
+        let witnessed = WitnessedOperations {
+            reads: vec![
+                WitnessedRead {
+                    key: leaf_0.key_path,
+                    value: Some(leaf_0.value_hash),
+                    path_index: 0,
+                },
+                WitnessedRead {
+                    key: leaf_1.key_path,
+                    value: Some(leaf_1.value_hash),
+                    path_index: 1,
+                },
+            ],
+            writes: Vec::new(),
+        };
+        (root, witness, witnessed)
+    }
+
+    #[test]
+    fn verify_reads() {
+        let (root, witness, mut witnessed) = witness();
+        assert_eq!(
+            verify_witness::<Blake3Hasher>(root, &witness, &witnessed).unwrap(),
+            root
+        );
+
+        witnessed.reads[1].value = Some([3; 32]);
+        assert!(matches!(
+            verify_witness::<Blake3Hasher>(root, &witness, &witnessed),
+            Err(WitnessVerificationError::ReadMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn canonical_order() {
+        let (_, mut witness, mut witnessed) = witness();
+        assert!(witness.is_canonical());
+        assert!(witnessed.is_canonical());
+
+        witness.path_proofs.swap(0, 1);
+        witnessed.reads.swap(0, 1);
+        assert!(!witness.is_canonical());
+        assert!(!witnessed.is_canonical());
+
+        // Paths may not be repeated either.
+        witness.path_proofs[0] = witness.path_proofs[1].clone();
+        assert!(!witness.is_canonical());
+    }
+
+    #[test]
+    fn encode_and_compress_round_trip() {
+        let (root, witness, witnessed) = witness();
+
+        let decoded = Witness::decode(&witness.encode()).unwrap();
+        assert_eq!(decoded, witness);
+
+        let decompressed = witness.compress().decompress().unwrap();
+        assert_eq!(decompressed, witness);
+        assert_eq!(
+            verify_witness::<Blake3Hasher>(root, &decompressed, &witnessed).unwrap(),
+            root
+        );
+    }
+}
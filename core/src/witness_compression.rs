@@ -0,0 +1,212 @@
+//! Compressed witnesses.
+//!
+//! Path proofs of nearby keys share most of their siblings, and the paths and terminators of a
+//! witness are stored as full 32-byte key paths. [`Witness::compress`] stores every distinct
+//! sibling once and packs every path into as many bytes as its depth needs, while keeping the
+//! witness structured, so that it can still be encoded with serde, SCALE or borsh.
+//!
+//! The canonical format of [`Witness::encode`] applies the same compression while encoding.
+
+use bitvec::prelude::*;
+
+use crate::{
+    binning::KeyBinning,
+    proof::{PathProof, PathProofTerminal},
+    trie::{LeafData, Node},
+    trie_pos::TriePosition,
+    witness::{Witness, WitnessedPath},
+    witness_format::WitnessDecodeError,
+};
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A [`Witness`] with shared siblings and bit-packed paths, created with [`Witness::compress`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct CompressedWitness {
+    /// Every distinct sibling of the witness, in order of first occurrence.
+    pub nodes: Vec<Node>,
+    /// The path proofs of the witness, in order.
+    pub path_proofs: Vec<CompressedPath>,
+    /// The binning the key paths were derived with.
+    pub key_binning: KeyBinning,
+}
+
+/// A path proof of a [`CompressedWitness`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct CompressedPath {
+    /// The query path.
+    pub path: PackedPath,
+    /// The terminal node of the path.
+    pub terminal: CompressedTerminal,
+    /// The siblings along the path, as indices into [`CompressedWitness::nodes`].
+    pub siblings: Vec<u32>,
+}
+
+/// The terminal node of a [`CompressedPath`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub enum CompressedTerminal {
+    /// A leaf.
+    Leaf(LeafData),
+    /// A terminator, at the given position.
+    Terminator(PackedPath),
+}
+
+/// A path of at most 256 bits, packed into `ceil(depth / 8)` bytes, most significant bit first,
+/// with zero padding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "scale", derive(codec::Encode, codec::Decode))]
+#[cfg_attr(
+    feature = "borsh",
+    derive(borsh::BorshSerialize, borsh::BorshDeserialize)
+)]
+pub struct PackedPath {
+    /// The number of bits of the path.
+    pub depth: u16,
+    /// The bits of the path.
+    pub bits: Vec<u8>,
+}
+
+impl PackedPath {
+    fn pack(position: &TriePosition) -> Self {
+        let path = position.path();
+        let mut bits = vec![0u8; path.len().div_ceil(8)];
+        bits.view_bits_mut::<Msb0>()[..path.len()].copy_from_bitslice(path);
+        PackedPath {
+            depth: path.len() as u16,
+            bits,
+        }
+    }
+
+    fn unpack(&self) -> Result<TriePosition, WitnessDecodeError> {
+        let depth = self.depth as usize;
+        if depth > 256 {
+            return Err(WitnessDecodeError::Invalid("path deeper than 256 bits"));
+        }
+        if self.bits.len() != depth.div_ceil(8) {
+            return Err(WitnessDecodeError::Invalid(
+                "path length doesn't match depth",
+            ));
+        }
+        let mut path = [0u8; 32];
+        path[..self.bits.len()].copy_from_slice(&self.bits);
+        if path.view_bits::<Msb0>()[depth..].any() {
+            return Err(WitnessDecodeError::Invalid("non-zero padding bits"));
+        }
+        Ok(match depth {
+            0 => TriePosition::new(),
+            _ => TriePosition::from_path_and_depth(path, self.depth),
+        })
+    }
+}
+
+impl Witness {
+    /// Compress the witness by sharing identical siblings across path proofs and bit-packing
+    /// paths. Undone by [`CompressedWitness::decompress`].
+    pub fn compress(&self) -> CompressedWitness {
+        let mut nodes = Vec::new();
+        let mut indices = BTreeMap::new();
+        let path_proofs = self
+            .path_proofs
+            .iter()
+            .map(|witnessed_path| CompressedPath {
+                path: PackedPath::pack(&witnessed_path.path),
+                terminal: match &witnessed_path.inner.terminal {
+                    PathProofTerminal::Leaf(leaf) => CompressedTerminal::Leaf(leaf.clone()),
+                    PathProofTerminal::Terminator(pos) => {
+                        CompressedTerminal::Terminator(PackedPath::pack(pos))
+                    }
+                },
+                siblings: witnessed_path
+                    .inner
+                    .siblings
+                    .iter()
+                    .map(|sibling| {
+                        *indices.entry(*sibling).or_insert_with(|| {
+                            nodes.push(*sibling);
+                            nodes.len() as u32 - 1
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+        CompressedWitness {
+            nodes,
+            path_proofs,
+            key_binning: self.key_binning,
+        }
+    }
+}
+
+impl CompressedWitness {
+    /// Restore the witness compressed with [`Witness::compress`].
+    ///
+    /// Fails if the compressed witness is malformed, e.g. if it refers to a node it doesn't
+    /// contain or contains the same node twice.
+    pub fn decompress(&self) -> Result<Witness, WitnessDecodeError> {
+        let mut seen = BTreeSet::new();
+        if !self.nodes.iter().all(|node| seen.insert(*node)) {
+            return Err(WitnessDecodeError::Invalid("repeated node not shared"));
+        }
+
+        let path_proofs = self
+            .path_proofs
+            .iter()
+            .map(|compressed| {
+                if compressed.siblings.len() > 256 {
+                    return Err(WitnessDecodeError::Invalid("too many siblings"));
+                }
+                let terminal = match &compressed.terminal {
+                    CompressedTerminal::Leaf(leaf) => PathProofTerminal::Leaf(leaf.clone()),
+                    CompressedTerminal::Terminator(path) => {
+                        PathProofTerminal::Terminator(path.unpack()?)
+                    }
+                };
+                let siblings = compressed
+                    .siblings
+                    .iter()
+                    .map(|&index| {
+                        self.nodes
+                            .get(index as usize)
+                            .copied()
+                            .ok_or(WitnessDecodeError::Invalid("reference to unknown node"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(WitnessedPath {
+                    inner: PathProof { terminal, siblings },
+                    path: compressed.path.unpack()?,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Witness {
+            path_proofs,
+            key_binning: self.key_binning,
+        })
+    }
+}
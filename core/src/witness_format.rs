@@ -0,0 +1,281 @@
+//! The canonical binary format of witnesses.
+//!
+//! Unlike the serde, SCALE and borsh encodings, this format is specified by NOMT itself, so that
+//! independent implementations can exchange witnesses and hash them deterministically. Every
+//! witness has exactly one encoding: decoding rejects any input which isn't the output of
+//! [`Witness::encode`].
+//!
+//! Version 1 of the format consists of:
+//!
+//! 1. The version byte, `1`.
+//! 2. The number of bin bits of the key binning, as a single byte.
+//! 3. The number of path proofs, as a varint, followed by every path proof in order.
+//!
+//! A path proof consists of:
+//!
+//! 1. The query path, as a bit-packed path.
+//! 2. The terminal: either the byte `0` followed by the 32-byte key path and value hash of the
+//!    leaf, or the byte `1` followed by the bit-packed path of the terminator.
+//! 3. The number of siblings, as a varint, followed by every sibling in order.
+//!
+//! Siblings are shared across the whole witness. The first occurrence of a node is encoded as a
+//! `0` varint followed by the 32-byte node. Later occurrences are encoded as the varint `i + 1`,
+//! referring to the `i`-th distinct node of the witness in order of first occurrence.
+//!
+//! A bit-packed path is its depth as a varint, at most 256, followed by its bits, most
+//! significant first, padded with zero bits to a whole number of bytes.
+//!
+//! Varints are unsigned LEB128 without redundant trailing zero bytes.
+
+use core::fmt;
+
+use bitvec::prelude::*;
+
+use crate::{
+    binning::KeyBinning,
+    proof::{PathProof, PathProofTerminal},
+    trie::{LeafData, Node},
+    trie_pos::TriePosition,
+    witness::{Witness, WitnessedPath},
+};
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The version of the witness format written by [`Witness::encode`].
+pub const WITNESS_FORMAT_VERSION: u8 = 1;
+
+const LEAF: u8 = 0;
+const TERMINATOR: u8 = 1;
+
+/// An error decoding a witness with [`Witness::decode`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WitnessDecodeError {
+    /// The witness was encoded with an unknown version of the format.
+    UnsupportedVersion(u8),
+    /// The input ended in the middle of the witness.
+    UnexpectedEnd,
+    /// The input continues after the end of the witness.
+    TrailingBytes,
+    /// The input isn't a canonical encoding of a witness.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for WitnessDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WitnessDecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported witness format version {version}")
+            }
+            WitnessDecodeError::UnexpectedEnd => write!(f, "unexpected end of witness"),
+            WitnessDecodeError::TrailingBytes => write!(f, "trailing bytes after witness"),
+            WitnessDecodeError::Invalid(reason) => write!(f, "invalid witness: {reason}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for WitnessDecodeError {}
+
+impl Witness {
+    /// Encode the witness in the canonical binary format, described in the
+    /// [`witness_format`](crate::witness_format) module.
+    ///
+    /// This takes precedence over the SCALE `Encode::encode` of the `scale` feature, which must be
+    /// called as such.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoder = Encoder {
+            out: vec![WITNESS_FORMAT_VERSION, self.key_binning.bin_bits()],
+            nodes: BTreeMap::new(),
+        };
+        encoder.varint(self.path_proofs.len() as u64);
+        for witnessed_path in &self.path_proofs {
+            encoder.path(witnessed_path.path.path());
+            match &witnessed_path.inner.terminal {
+                PathProofTerminal::Leaf(leaf) => {
+                    encoder.out.push(LEAF);
+                    encoder.out.extend_from_slice(&leaf.key_path);
+                    encoder.out.extend_from_slice(&leaf.value_hash);
+                }
+                PathProofTerminal::Terminator(pos) => {
+                    encoder.out.push(TERMINATOR);
+                    encoder.path(pos.path());
+                }
+            }
+            encoder.varint(witnessed_path.inner.siblings.len() as u64);
+            for sibling in &witnessed_path.inner.siblings {
+                encoder.node(*sibling);
+            }
+        }
+        encoder.out
+    }
+
+    /// Decode a witness from the canonical binary format, as produced by [`Witness::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Witness, WitnessDecodeError> {
+        let mut decoder = Decoder {
+            input: bytes,
+            nodes: Vec::new(),
+            seen: BTreeSet::new(),
+        };
+        let version = decoder.byte()?;
+        if version != WITNESS_FORMAT_VERSION {
+            return Err(WitnessDecodeError::UnsupportedVersion(version));
+        }
+        let key_binning = KeyBinning::from_bin_bits(decoder.byte()?)
+            .ok_or(WitnessDecodeError::Invalid("bin bits out of range"))?;
+
+        let path_count = decoder.varint()?;
+        let mut path_proofs = Vec::new();
+        for _ in 0..path_count {
+            let path = decoder.path()?;
+            let terminal = match decoder.byte()? {
+                LEAF => PathProofTerminal::Leaf(LeafData {
+                    key_path: decoder.array()?,
+                    value_hash: decoder.array()?,
+                }),
+                TERMINATOR => PathProofTerminal::Terminator(decoder.path()?),
+                _ => return Err(WitnessDecodeError::Invalid("unknown terminal kind")),
+            };
+            let sibling_count = decoder.varint()?;
+            if sibling_count > 256 {
+                return Err(WitnessDecodeError::Invalid("too many siblings"));
+            }
+            let siblings = (0..sibling_count)
+                .map(|_| decoder.node())
+                .collect::<Result<Vec<_>, _>>()?;
+            path_proofs.push(WitnessedPath {
+                inner: PathProof { terminal, siblings },
+                path,
+            });
+        }
+
+        if !decoder.input.is_empty() {
+            return Err(WitnessDecodeError::TrailingBytes);
+        }
+        Ok(Witness {
+            path_proofs,
+            key_binning,
+        })
+    }
+}
+
+struct Encoder {
+    out: Vec<u8>,
+    // The index of every distinct node encoded so far, in order of first occurrence.
+    nodes: BTreeMap<Node, u64>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.out.push(value as u8);
+    }
+
+    fn path(&mut self, path: &BitSlice<u8, Msb0>) {
+        self.varint(path.len() as u64);
+        let mut bytes = [0u8; 32];
+        bytes.view_bits_mut::<Msb0>()[..path.len()].copy_from_bitslice(path);
+        self.out.extend_from_slice(&bytes[..path.len().div_ceil(8)]);
+    }
+
+    fn node(&mut self, node: Node) {
+        let next_index = self.nodes.len() as u64;
+        match self.nodes.get(&node) {
+            Some(index) => self.varint(index + 1),
+            None => {
+                self.nodes.insert(node, next_index);
+                self.varint(0);
+                self.out.extend_from_slice(&node);
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    // Every distinct node decoded so far, in order of first occurrence.
+    nodes: Vec<Node>,
+    seen: BTreeSet<Node>,
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], WitnessDecodeError> {
+        if self.input.len() < len {
+            return Err(WitnessDecodeError::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, WitnessDecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn array(&mut self) -> Result<[u8; 32], WitnessDecodeError> {
+        // Unwrap: exactly 32 bytes are taken.
+        Ok(self.bytes(32)?.try_into().unwrap())
+    }
+
+    fn varint(&mut self) -> Result<u64, WitnessDecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7F) as u64;
+            if shift == 63 && bits > 1 {
+                return Err(WitnessDecodeError::Invalid("varint overflow"));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                if byte == 0 && shift != 0 {
+                    return Err(WitnessDecodeError::Invalid("overlong varint"));
+                }
+                return Ok(value);
+            }
+        }
+        Err(WitnessDecodeError::Invalid("varint overflow"))
+    }
+
+    fn path(&mut self) -> Result<TriePosition, WitnessDecodeError> {
+        let depth = self.varint()?;
+        if depth > 256 {
+            return Err(WitnessDecodeError::Invalid("path deeper than 256 bits"));
+        }
+        let depth = depth as usize;
+        let mut path = [0u8; 32];
+        path[..depth.div_ceil(8)].copy_from_slice(self.bytes(depth.div_ceil(8))?);
+        if path.view_bits::<Msb0>()[depth..].any() {
+            return Err(WitnessDecodeError::Invalid("non-zero padding bits"));
+        }
+        Ok(match depth {
+            0 => TriePosition::new(),
+            _ => TriePosition::from_path_and_depth(path, depth as u16),
+        })
+    }
+
+    fn node(&mut self) -> Result<Node, WitnessDecodeError> {
+        match self.varint()? {
+            0 => {
+                let node = self.array()?;
+                if !self.seen.insert(node) {
+                    return Err(WitnessDecodeError::Invalid("repeated node not shared"));
+                }
+                self.nodes.push(node);
+                Ok(node)
+            }
+            reference => usize::try_from(reference - 1)
+                .ok()
+                .and_then(|index| self.nodes.get(index).copied())
+                .ok_or(WitnessDecodeError::Invalid("reference to unknown node")),
+        }
+    }
+}
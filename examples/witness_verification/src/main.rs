@@ -39,7 +39,7 @@ fn main() -> Result<()> {
         // This information could already be known if we committed the batch initially,
         // and thus, the witnessed field could be discarded entirely.
         for read in witnessed
-            .reads
+            .reads()
             .iter()
             .skip_while(|r| r.path_index != i)
             .take_while(|r| r.path_index == i)
@@ -66,7 +66,7 @@ fn main() -> Result<()> {
         // the new trie to the expected state
         let mut write_ops = Vec::new();
         for write in witnessed
-            .writes
+            .writes()
             .iter()
             .skip_while(|r| r.path_index != i)
             .take_while(|r| r.path_index == i)
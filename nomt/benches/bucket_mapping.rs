@@ -0,0 +1,12 @@
+#[cfg(feature = "benchmarks")]
+use criterion::{criterion_group, criterion_main};
+#[cfg(feature = "benchmarks")]
+use nomt::bucket_mapping_benchmark;
+
+#[cfg(feature = "benchmarks")]
+criterion_group!(benches, bucket_mapping_benchmark);
+#[cfg(feature = "benchmarks")]
+criterion_main!(benches);
+
+#[cfg(not(feature = "benchmarks"))]
+fn main() {}
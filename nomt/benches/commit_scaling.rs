@@ -0,0 +1,112 @@
+//! Commit throughput as a function of the number of commit workers.
+//!
+//! The page cache is partitioned between the merkle workers, while the store, the I/O pool and the
+//! root page are shared between all of them. This measures how far commits scale with the
+//! number of workers under that design, `shared`, and under the experimental thread-per-core
+//! mode, `thread_per_core`, which also shards the warm-ups and their I/O handles, see
+//! `Options::thread_per_core`.
+//!
+//! A database is populated once and then reopened for every mode and worker count, from 1 up to
+//! the number of available cores. Every iteration warms up and commits a batch of updates to
+//! random existing keys.
+//!
+//! The number of keys is controlled by the `NOMT_BENCH_SCALING_KEYS` environment variable and
+//! defaults to 1 million.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+use std::path::Path;
+
+const VALUE_SIZE: usize = 32;
+const BATCH_SIZE: u64 = 20_000;
+const MAX_WORKERS: usize = 64;
+
+fn key_path(id: u64) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn options(path: &Path, workers: usize, thread_per_core: bool) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(2_000_000);
+    o.preallocate_ht(false);
+    o.commit_concurrency(workers);
+    o.adaptive_commit_concurrency(false);
+    o.warm_up(true);
+    o.thread_per_core(thread_per_core);
+    o
+}
+
+fn commit_batch(nomt: &Nomt<Blake3Hasher>, ids: impl Iterator<Item = u64>, round: u64) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| {
+            let path = key_path(id);
+            session.warm_up(path);
+            (
+                path,
+                KeyReadWrite::Write(Some(vec![round as u8; VALUE_SIZE].into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(path, _)| *path);
+    actuals.dedup_by_key(|(path, _)| *path);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn populate(path: &Path, keys: u64) {
+    let nomt = Nomt::<Blake3Hasher>::open(options(path, MAX_WORKERS, false)).unwrap();
+    let mut start = 0;
+    while start < keys {
+        let end = std::cmp::min(start + 100_000, keys);
+        commit_batch(&nomt, start..end, 0);
+        start = end;
+    }
+}
+
+fn worker_counts() -> Vec<usize> {
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let max = cores.min(MAX_WORKERS);
+    let mut counts = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|n| *n < max)
+        .collect::<Vec<_>>();
+    counts.push(max);
+    counts
+}
+
+fn commit_scaling_benchmark(c: &mut Criterion) {
+    let keys = std::env::var("NOMT_BENCH_SCALING_KEYS")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(1_000_000);
+    let dir = tempfile::tempdir().unwrap();
+    populate(dir.path(), keys);
+
+    let mut group = c.benchmark_group("commit_scaling");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(BATCH_SIZE));
+
+    let mut round = 0u64;
+    for (mode, thread_per_core) in [("shared", false), ("thread_per_core", true)] {
+        for workers in worker_counts() {
+            let nomt =
+                Nomt::<Blake3Hasher>::open(options(dir.path(), workers, thread_per_core)).unwrap();
+            group.bench_function(BenchmarkId::new(mode, workers), |b| {
+                b.iter(|| {
+                    round += 1;
+                    let ids = (0..BATCH_SIZE).map(|i| {
+                        let hash = blake3::hash(&[round.to_le_bytes(), i.to_le_bytes()].concat());
+                        u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap()) % keys
+                    });
+                    commit_batch(&nomt, ids, round);
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, commit_scaling_benchmark);
+criterion_main!(benches);
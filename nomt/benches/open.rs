@@ -0,0 +1,132 @@
+//! Cold-start benchmark for [`Nomt::open`].
+//!
+//! A database is populated once and then opened repeatedly, both after a clean shutdown and after
+//! a dirty one, where the last sync was interrupted and the WAL has to be replayed.
+//!
+//! The amount of value data written is controlled by the `NOMT_BENCH_OPEN_GIB` environment
+//! variable and defaults to 2 GiB.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const VALUE_SIZE: usize = 1024;
+const COMMIT_SIZE: u64 = 100_000;
+
+fn key_path(id: u64) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn options(path: &Path, recovery_time_hint: Option<Duration>) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(1_000_000);
+    o.preallocate_ht(false);
+    o.commit_concurrency(4);
+    if let Some(hint) = recovery_time_hint {
+        o.max_recovery_time_hint(hint);
+    }
+    o
+}
+
+fn commit_batch(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u64>) {
+    let session = nomt.begin_session();
+    let actuals = {
+        let mut actuals = ids
+            .map(|id| {
+                let path = key_path(id);
+                session.warm_up(path);
+                (
+                    path,
+                    KeyReadWrite::Write(Some(vec![id as u8; VALUE_SIZE].into())),
+                )
+            })
+            .collect::<Vec<_>>();
+        actuals.sort_by_key(|(path, _)| *path);
+        actuals
+    };
+    nomt.commit(session, actuals).unwrap();
+}
+
+/// Populate a clean database and a copy of it whose last sync was interrupted.
+fn populate(root: &Path) -> (PathBuf, PathBuf) {
+    let gib = std::env::var("NOMT_BENCH_OPEN_GIB")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(2);
+    let items = gib * 1024 * 1024 * 1024 / VALUE_SIZE as u64;
+
+    let clean = root.join("clean");
+    let dirty = root.join("dirty");
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(&clean, None)).unwrap();
+    let mut start = 0;
+    while start < items {
+        let end = std::cmp::min(start + COMMIT_SIZE, items);
+        commit_batch(&nomt, start..end);
+        start = end;
+    }
+    drop(nomt);
+
+    copy_dir(&clean, &dirty);
+    let mut o = options(&dirty, None);
+    o.panic_on_sync(true);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        commit_batch(&nomt, items..items + COMMIT_SIZE);
+    }));
+    assert!(r.is_err());
+    drop(nomt);
+
+    (clean, dirty)
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    let _ = std::fs::remove_dir_all(to);
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+fn open_benchmark(c: &mut Criterion) {
+    let root = tempfile::tempdir().unwrap();
+    let (clean, dirty) = populate(root.path());
+    let replay = root.path().join("replay");
+
+    let mut group = c.benchmark_group("open");
+    group.sample_size(10);
+
+    for hint in [None, Some(Duration::from_secs(1))] {
+        let hint_name = match hint {
+            None => "no_hint".to_string(),
+            Some(hint) => format!("hint_{}ms", hint.as_millis()),
+        };
+
+        group.bench_function(BenchmarkId::new("clean", &hint_name), |b| {
+            b.iter(|| Nomt::<Blake3Hasher>::open(options(&clean, hint)).unwrap())
+        });
+
+        group.bench_function(BenchmarkId::new("dirty", &hint_name), |b| {
+            b.iter_batched(
+                || copy_dir(&dirty, &replay),
+                |()| Nomt::<Blake3Hasher>::open(options(&replay, hint)).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, open_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,311 @@
+//! Incremental backups.
+//!
+//! When enabled with [`crate::Options::incremental_backup`], the backup directory receives a copy
+//! of the database files the first time the database is opened, called the base, and then an
+//! increment for every commit holding the pages written by the commit along with the meta page.
+//! [`materialize`] copies the base to a new directory and writes the pages of every increment
+//! over it in order, producing the database as of the last commit.
+//!
+//! The base is the `base` directory, holding the `meta`, `ht`, `wal`, `ln` and `bbn` files and a
+//! `root` file with the root of the trie at the time it was taken. It is moved into place only
+//! once complete. The increments are named after their index, e.g. `0000000000.increment`, and
+//! are written to a temporary file first, so neither is ever found half-written.
+//!
+//! An increment consists of a magic, the roots before and after the commit, the number of pages
+//! as a little-endian `u32` and then the pages, each preceded by a byte naming its file and its
+//! page number as a little-endian `u64`. It ends with an xxh3 checksum of everything before it.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write as _,
+    os::unix::fs::FileExt as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context as _};
+use nomt_core::trie::Node;
+use parking_lot::Mutex;
+
+use crate::{io::PAGE_SIZE, manifest::ChangedPages, store::Store};
+
+const MAGIC: &[u8; 8] = b"NOMTINC1";
+const BASE_DIR: &str = "base";
+const ROOT_FILE: &str = "root";
+const INCREMENT_EXT: &str = "increment";
+
+/// The files of the database copied to the base, the meta file last.
+const FILES: [&str; 5] = ["ht", "wal", "ln", "bbn", "meta"];
+/// The files increments write pages to, in the order of their tags.
+const PAGE_FILES: [&str; 4] = ["ht", "ln", "bbn", "meta"];
+
+/// Appends an increment to the backup directory for every commit.
+pub(crate) struct Backup {
+    dir: PathBuf,
+    /// The index of the next increment.
+    next: Mutex<u64>,
+}
+
+impl Backup {
+    /// Start or continue the backup in the given directory of the database at `db_path`, which has
+    /// just been opened at `root`.
+    ///
+    /// Takes the base if the directory doesn't hold one yet. Otherwise, fails if the last
+    /// increment, or the base if there are none, is not at `root`.
+    pub fn open(dir: PathBuf, db_path: &Path, root: Node) -> anyhow::Result<Self> {
+        let increments = list_increments(&dir)?;
+        if !dir.join(BASE_DIR).exists() {
+            ensure!(
+                increments.is_empty(),
+                "backup at {} has increments but no base",
+                dir.display()
+            );
+            take_base(&dir, db_path, root)?;
+            return Ok(Self {
+                dir,
+                next: Mutex::new(0),
+            });
+        }
+
+        let (backup_root, next) = match increments.last() {
+            Some((index, path)) => (read_header(path)?.1, index + 1),
+            None => (read_root(&dir.join(BASE_DIR))?, 0),
+        };
+        if backup_root != root {
+            bail!(
+                "backup at {} doesn't continue from the root of the database",
+                dir.display()
+            );
+        }
+        Ok(Self {
+            dir,
+            next: Mutex::new(next),
+        })
+    }
+
+    /// Write an increment, as encoded by [`encode_increment`].
+    pub fn append(&self, increment: &[u8]) -> anyhow::Result<()> {
+        let mut next = self.next.lock();
+        let path = self.dir.join(format!("{:010}.{INCREMENT_EXT}", *next));
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(increment)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+        *next += 1;
+        Ok(())
+    }
+}
+
+/// Encode the increment of a commit from `prev_root` to `root` which wrote the given pages.
+pub(crate) fn encode_increment(
+    store: &Store,
+    prev_root: Node,
+    root: Node,
+    pages: &ChangedPages,
+) -> anyhow::Result<Vec<u8>> {
+    let page_count = pages.ht_pages.len() + pages.ln_pages.len() + pages.bbn_pages.len() + 1;
+
+    let mut buf = Vec::with_capacity(80 + page_count * (9 + PAGE_SIZE));
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&prev_root);
+    buf.extend_from_slice(&root);
+    buf.extend_from_slice(&(page_count as u32).to_le_bytes());
+    store.read_pages(pages, |file, pn, page| {
+        // UNWRAP: the store only reads pages of these files.
+        let tag = PAGE_FILES.iter().position(|f| *f == file).unwrap();
+        buf.push(tag as u8);
+        buf.extend_from_slice(&pn.to_le_bytes());
+        buf.extend_from_slice(page);
+    })?;
+    let checksum = xxhash_rust::xxh3::xxh3_64(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    Ok(buf)
+}
+
+/// Copy the files of the database to the base, which is moved into place once complete.
+fn take_base(dir: &Path, db_path: &Path, root: Node) -> anyhow::Result<()> {
+    let tmp_dir = dir.join(format!("{BASE_DIR}.tmp"));
+    if tmp_dir.exists() {
+        std::fs::remove_dir_all(&tmp_dir)?;
+    }
+    std::fs::create_dir_all(&tmp_dir)?;
+    for name in FILES {
+        std::fs::copy(db_path.join(name), tmp_dir.join(name))
+            .with_context(|| format!("failed to copy {name} to the backup"))?;
+        File::open(tmp_dir.join(name))?.sync_all()?;
+    }
+    let mut file = File::create(tmp_dir.join(ROOT_FILE))?;
+    file.write_all(&root)?;
+    file.sync_all()?;
+    drop(file);
+    File::open(&tmp_dir)?.sync_all()?;
+
+    std::fs::rename(&tmp_dir, dir.join(BASE_DIR))?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn read_root(base: &Path) -> anyhow::Result<Node> {
+    let buf = std::fs::read(base.join(ROOT_FILE))?;
+    buf.try_into()
+        .map_err(|_| anyhow::anyhow!("malformed root file in backup base"))
+}
+
+/// Returns the indices and paths of the increments in the directory, in order.
+fn list_increments(dir: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut increments = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != INCREMENT_EXT) {
+            continue;
+        }
+        let index = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u64>().ok())
+            .with_context(|| format!("malformed increment name {}", path.display()))?;
+        increments.push((index, path));
+    }
+    increments.sort();
+    for (i, (index, _)) in increments.iter().enumerate() {
+        ensure!(
+            *index == i as u64,
+            "increment {i} is missing from the backup"
+        );
+    }
+    Ok(increments)
+}
+
+/// Read the roots before and after the commit of an increment.
+fn read_header(path: &Path) -> anyhow::Result<(Node, Node)> {
+    let mut header = [0; 72];
+    File::open(path)?
+        .read_exact_at(&mut header, 0)
+        .with_context(|| format!("truncated increment {}", path.display()))?;
+    ensure!(
+        &header[..8] == MAGIC,
+        "not an increment: {}",
+        path.display()
+    );
+    // UNWRAP: the slices are 32 bytes long.
+    Ok((
+        header[8..40].try_into().unwrap(),
+        header[40..72].try_into().unwrap(),
+    ))
+}
+
+/// Write the database backed up in `backup_dir` to `target`, as of the last increment. Returns the
+/// root of the materialized database, or `None` if there is no base in `backup_dir`.
+///
+/// Fails without touching `target` if it already holds a database, and leaves an uninitialized
+/// database behind if it fails later on.
+pub fn materialize(backup_dir: &Path, target: &Path) -> anyhow::Result<Option<Node>> {
+    let base = backup_dir.join(BASE_DIR);
+    if !base.exists() {
+        return Ok(None);
+    }
+    ensure!(
+        !target.join("meta").exists(),
+        "a database already exists at {}",
+        target.display()
+    );
+    let increments = list_increments(backup_dir)?;
+
+    std::fs::create_dir_all(target)?;
+    // The meta file is written last, see `Store::open`.
+    for name in FILES.iter().filter(|name| **name != "meta") {
+        std::fs::copy(base.join(name), target.join(name))?;
+    }
+    let open = |name: &str| {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(target.join(name))
+    };
+    let files = [open("ht")?, open("ln")?, open("bbn")?];
+
+    let mut root = read_root(&base)?;
+    let mut meta = std::fs::read(base.join("meta"))?;
+    for (index, path) in &increments {
+        let buf = std::fs::read(path)?;
+        let pages = parse_increment(&buf, root)
+            .map_err(|e| crate::Error::Corruption(format!("invalid increment {index}: {e}")))?;
+        for (tag, pn, page) in pages {
+            match files.get(tag as usize) {
+                Some(file) => file.write_all_at(page, pn * PAGE_SIZE as u64)?,
+                // Only the meta page is rewritten by commits, the compression dictionary
+                // following it is not.
+                None => meta[..PAGE_SIZE].copy_from_slice(page),
+            }
+        }
+        // UNWRAP: the header was checked by `parse_increment`.
+        root = buf[40..72].try_into().unwrap();
+    }
+    for file in &files {
+        file.sync_all()?;
+    }
+
+    let wal = open("wal")?;
+    if !increments.is_empty() {
+        // The WAL is cleared at the end of every commit.
+        wal.set_len(0)?;
+    }
+    wal.sync_all()?;
+
+    let mut meta_fd = File::create(target.join("meta.tmp"))?;
+    meta_fd.write_all(&meta)?;
+    meta_fd.sync_all()?;
+    drop(meta_fd);
+    std::fs::rename(target.join("meta.tmp"), target.join("meta"))?;
+    File::open(target)?.sync_all()?;
+    Ok(Some(root))
+}
+
+/// Remove the files of a database written by [`materialize`], the meta file first so that the
+/// database is left uninitialized should this fail midway.
+pub fn discard(target: &Path) -> anyhow::Result<()> {
+    for name in FILES.iter().rev() {
+        match std::fs::remove_file(target.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    File::open(target)?.sync_all()?;
+    Ok(())
+}
+
+/// Check the increment and that it follows `root`, returning its pages along with the tags of
+/// their files and their page numbers.
+fn parse_increment(buf: &[u8], root: Node) -> anyhow::Result<Vec<(u8, u64, &[u8])>> {
+    ensure!(buf.len() >= 84, "truncated");
+    let (body, checksum) = buf.split_at(buf.len() - 8);
+    ensure!(
+        xxhash_rust::xxh3::xxh3_64(body).to_le_bytes() == checksum,
+        "checksum mismatch"
+    );
+    ensure!(&body[..8] == MAGIC, "bad magic");
+    ensure!(body[8..40] == root, "doesn't follow the previous increment");
+
+    // UNWRAP: the body is at least 76 bytes long.
+    let page_count = u32::from_le_bytes(body[72..76].try_into().unwrap()) as usize;
+    let mut rest = &body[76..];
+    let mut pages = Vec::with_capacity(page_count);
+    for _ in 0..page_count {
+        ensure!(rest.len() >= 9 + PAGE_SIZE, "truncated");
+        let tag = rest[0];
+        ensure!((tag as usize) < PAGE_FILES.len(), "unknown file {tag}");
+        // UNWRAP: the slice is 8 bytes long.
+        let pn = u64::from_le_bytes(rest[1..9].try_into().unwrap());
+        pages.push((tag, pn, &rest[9..9 + PAGE_SIZE]));
+        rest = &rest[9 + PAGE_SIZE..];
+    }
+    ensure!(rest.is_empty(), "trailing bytes");
+    Ok(pages)
+}
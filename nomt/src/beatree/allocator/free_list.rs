@@ -1,12 +1,17 @@
 use crate::{
     beatree::FREELIST_EMPTY,
+    checksum::{self, CHECKSUM_SIZE, TRAILER_OFFSET},
     io::{self, page_pool::FatPage, PagePool, PAGE_SIZE},
 };
 use std::{collections::BTreeSet, fs::File};
 
 use super::PageNumber;
 
-const MAX_PNS_PER_PAGE: usize = (PAGE_SIZE - 6) / 4;
+const MAX_PNS_PER_PAGE: usize = (PAGE_SIZE - 6 - CHECKSUM_SIZE) / 4;
+
+/// The pages tracked by the part of a free-list which could be read by [`FreeList::salvage`],
+/// along with the page at which reading stopped early and why, if it did.
+pub type Salvaged = (BTreeSet<PageNumber>, Option<(PageNumber, &'static str)>);
 
 /// In-memory version of FreeList which provides a way to decode from and encode into pages
 /// and provide two primitives, one for extracting free pages from the list and one to append
@@ -31,6 +36,7 @@ impl FreeList {
         page_pool: &PagePool,
         store_file: &File,
         free_list_head: Option<PageNumber>,
+        verify_checksums: Option<&'static str>,
     ) -> anyhow::Result<FreeList> {
         let Some(mut free_list_pn) = free_list_head else {
             return Ok(FreeList {
@@ -50,6 +56,9 @@ impl FreeList {
             }
 
             let page = io::read_page(page_pool, store_file, free_list_pn.0 as u64)?;
+            if let Some(file) = verify_checksums {
+                checksum::check(&page, TRAILER_OFFSET, file, free_list_pn.0 as u64)?;
+            }
 
             let (prev, free_list) = decode_free_list_page(page);
             free_list_portions.push((free_list_pn, free_list));
@@ -69,6 +78,71 @@ impl FreeList {
         })
     }
 
+    /// Read as much of a possibly damaged free-list as can be trusted. Reading stops at the first
+    /// page which is outside of `1..page_count`, is visited twice, doesn't match its checksum or
+    /// tracks a page outside of `1..page_count`.
+    ///
+    /// The pages tracked by the portions read include the pages storing them.
+    pub fn salvage(
+        page_pool: &PagePool,
+        store_file: &File,
+        file: &'static str,
+        free_list_head: PageNumber,
+        page_count: u32,
+    ) -> std::io::Result<Salvaged> {
+        let in_range = |pn: PageNumber| !pn.is_nil() && pn.0 < page_count;
+
+        let mut tracked = BTreeSet::new();
+        let mut free_list_pn = free_list_head;
+        while !free_list_pn.is_nil() {
+            if !in_range(free_list_pn) {
+                return Ok((tracked, Some((free_list_pn, "free-list page out of range"))));
+            }
+            if tracked.contains(&free_list_pn) {
+                return Ok((
+                    tracked,
+                    Some((free_list_pn, "free-list page visited twice")),
+                ));
+            }
+
+            let page = io::read_page(page_pool, store_file, free_list_pn.0 as u64)?;
+            if checksum::check(&page, TRAILER_OFFSET, file, free_list_pn.0 as u64).is_err()
+                || FreeListPageRef(&page[..]).item_count() as usize > MAX_PNS_PER_PAGE
+            {
+                return Ok((tracked, Some((free_list_pn, "free-list page damaged"))));
+            }
+
+            let (prev, free_list) = decode_free_list_page(page);
+            if !free_list.iter().all(|&pn| in_range(pn)) {
+                return Ok((tracked, Some((free_list_pn, "free page out of range"))));
+            }
+            tracked.insert(free_list_pn);
+            tracked.extend(free_list);
+            free_list_pn = prev;
+        }
+        Ok((tracked, None))
+    }
+
+    /// Build a free-list tracking the given pages from scratch. The pages storing the free-list
+    /// are taken from `bump`.
+    ///
+    /// Returns the head of the free-list, if any, along with the pages to write.
+    pub fn build(
+        page_pool: &PagePool,
+        free: Vec<PageNumber>,
+        bump: &mut PageNumber,
+    ) -> (Option<PageNumber>, Vec<(PageNumber, FatPage)>) {
+        let mut free_list = FreeList {
+            portions: vec![],
+            pop: false,
+            released_portions: vec![],
+            fragmented: false,
+            len: 0,
+        };
+        let pages = free_list.commit(page_pool, free, bump);
+        (free_list.head_pn(), pages)
+    }
+
     /// Get a set of all pages tracked in the free-list. This is all free pages plus all the
     /// free-list pages themselves.
     pub fn all_tracked_pages(&self) -> BTreeSet<PageNumber> {
@@ -82,6 +156,21 @@ impl FreeList {
         BTreeSet::from_iter(pns)
     }
 
+    /// Get all free pages plus all the free-list pages themselves, including duplicates.
+    pub fn tracked_pages(&self) -> Vec<PageNumber> {
+        self.portions
+            .iter()
+            .flat_map(|(pn, pns)| std::iter::once(pn).chain(pns))
+            .copied()
+            .collect()
+    }
+
+    /// Get the number of free pages and the number of pages storing the free-list.
+    pub fn page_counts(&self) -> (usize, usize) {
+        let free_pages = self.portions.iter().map(|(_, pns)| pns.len()).sum();
+        (free_pages, self.portions.len())
+    }
+
     pub fn head_pn(&self) -> Option<PageNumber> {
         self.portions.last().map(|(head_pn, _)| head_pn).copied()
     }
@@ -165,7 +254,7 @@ impl FreeList {
     /// fewer items than the maximum and the head page will have exactly one item. This occurs only
     /// when the last item pushed would be the first in a new page and the pop to allocate that
     /// new page dirties a previously untouched page. Needless to say, it is rare, something like
-    /// 1-in-a-million (1022^2) and of near-zero consequence if forced somehow by an adversary.
+    /// 1-in-a-million (1020^2) and of near-zero consequence if forced somehow by an adversary.
     ///
     /// The next call to `commit` will eliminate any previous fragmentation, but may result in
     /// fragmentation itself.
@@ -421,6 +510,7 @@ fn encode_free_list_page(page_pool: &PagePool, prev: PageNumber, pns: &[PageNumb
             e.set_item(i, *pn);
         }
     }
+    checksum::write(&mut page, TRAILER_OFFSET);
 
     page
 }
@@ -1,9 +1,13 @@
-use crate::io::{self, page_pool::FatPage, IoCommand, IoKind, PagePool, PAGE_SIZE};
+use crate::{
+    beatree::compression::Codec,
+    checksum::{self, TRAILER_OFFSET},
+    io::{self, page_pool::FatPage, IoCommand, IoKind, PagePool, PAGE_SIZE},
+};
 
 use crossbeam_channel::{Receiver, Sender};
 use parking_lot::{ArcMutexGuard, Mutex};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fs::File,
     os::{
         fd::{AsRawFd, RawFd},
@@ -15,7 +19,7 @@ use std::{
     },
 };
 
-use free_list::FreeList;
+use free_list::{FreeList, Salvaged};
 
 mod free_list;
 
@@ -38,40 +42,207 @@ impl From<u32> for PageNumber {
 /// 0 is used to indicate that the free-list is empty.
 pub const FREELIST_EMPTY: PageNumber = PageNumber(0);
 
+/// Statistics about the pages of a [`Store`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreStats {
+    /// The next page number to be allocated when the free-list is empty.
+    pub bump: PageNumber,
+    /// The number of free pages tracked by the free-list.
+    pub free_pages: usize,
+    /// The number of pages storing the free-list itself.
+    pub free_list_pages: usize,
+    /// The number of freed pages retained for pins, not yet returned to the free-list.
+    pub retained_pages: usize,
+    /// The number of retained pages returned to the free-list since the store was opened.
+    pub reclaimed_pages: u64,
+}
+
+/// Read as much of a possibly damaged free-list of a store file as can be trusted, for repairing
+/// the store. See [`FreeList::salvage`].
+pub fn salvage_free_list(
+    page_pool: &PagePool,
+    file: &File,
+    file_name: &'static str,
+    free_list_head: PageNumber,
+    page_count: u32,
+) -> std::io::Result<Salvaged> {
+    FreeList::salvage(page_pool, file, file_name, free_list_head, page_count)
+}
+
+/// Build a free-list tracking the given pages from scratch, for repairing a store. See
+/// [`FreeList::build`].
+pub fn build_free_list(
+    page_pool: &PagePool,
+    free: Vec<PageNumber>,
+    bump: &mut PageNumber,
+) -> (Option<PageNumber>, Vec<(PageNumber, FatPage)>) {
+    FreeList::build(page_pool, free, bump)
+}
+
 /// A store is a file keeping beatree data pages.
 ///
-/// The store is shadow-paged and makes use of an embedded free-list to track free pages.
+/// The store is shadow-paged and makes use of an embedded free-list to track free pages. Every
+/// page ends with its checksum, written along with the page.
 #[derive(Clone)]
 pub struct Store {
     file: Arc<File>,
     sync: Arc<Mutex<StoreSync>>,
+    pins: Arc<Mutex<Pins>>,
+    // the name of the file reported on checksum mismatches, if checksums are verified.
+    verify_checksums: Option<&'static str>,
+    codec: Codec,
+}
+
+/// The pins of a [`Store`], by the epoch they were taken at.
+///
+/// The epoch is the number of syncs finished since the store was opened. A pin taken at epoch `e`
+/// needs the pages freed by the syncs finishing epochs after `e`.
+#[derive(Default)]
+struct Pins {
+    epoch: u64,
+    /// The number of live pins taken at each epoch.
+    live: BTreeMap<u64, usize>,
+    /// Pins taken at an epoch below this one have expired: pages they need were reclaimed.
+    expired_below: u64,
+}
+
+impl Pins {
+    fn pin(pins: &Arc<Mutex<Self>>, epoch: u64) -> StorePin {
+        *pins.lock().live.entry(epoch).or_default() += 1;
+        StorePin {
+            pins: pins.clone(),
+            epoch,
+        }
+    }
+}
+
+/// Keeps the pages of a [`Store`] from being reused while alive. See [`Store::pin`].
+pub struct StorePin {
+    pins: Arc<Mutex<Pins>>,
+    epoch: u64,
+}
+
+impl StorePin {
+    /// Whether the pages this pin needs may have been reclaimed because they were retained for
+    /// longer than the horizon of the store. Pages read through an expired pin can't be trusted.
+    pub fn is_expired(&self) -> bool {
+        self.epoch < self.pins.lock().expired_below
+    }
+}
+
+impl Clone for StorePin {
+    fn clone(&self) -> Self {
+        Pins::pin(&self.pins, self.epoch)
+    }
+}
+
+impl Drop for StorePin {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock();
+        // UNWRAP: every pin is counted under its epoch.
+        let count = pins.live.get_mut(&self.epoch).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            pins.live.remove(&self.epoch);
+        }
+    }
 }
 
 impl Store {
     /// Create a new `Store` over an existing file.
+    ///
+    /// If `verify_checksums` is set, the checksums of the pages read from the store are verified
+    /// and mismatches are reported with the given file name. See [`Store::pin`] for the
+    /// `retention_horizon`. The codec is applied by the users of the store to the values they
+    /// store, see [`StoreReader::codec`].
     pub fn open(
         page_pool: &PagePool,
-        file: File,
+        file: Arc<File>,
         bump: PageNumber,
         free_list_head: Option<PageNumber>,
+        verify_checksums: Option<&'static str>,
+        retention_horizon: Option<u32>,
+        codec: Codec,
     ) -> anyhow::Result<Self> {
         let file_size = file.metadata()?.size() as usize;
 
         let sync = StoreSync {
-            free_list: FreeList::read(page_pool, &file, free_list_head)?,
+            free_list: FreeList::read(page_pool, &file, free_list_head, verify_checksums)?,
             bump,
             max_bump: PageNumber((file_size / PAGE_SIZE) as u32),
+            retained: VecDeque::new(),
+            retention_horizon,
+            reclaimed_pages: 0,
+            hole_punch_run: None,
+            holes: Vec::new(),
         };
 
         Ok(Store {
-            file: Arc::new(file),
+            file,
             sync: Arc::new(Mutex::new(sync)),
+            pins: Arc::new(Mutex::new(Pins::default())),
+            verify_checksums,
+            codec,
         })
     }
 
+    /// Pin the pages of the store.
+    ///
+    /// While a pin is alive, the pages freed by syncs finishing after it was taken are retained:
+    /// they are not returned to the free-list, so they are not overwritten and can still be read.
+    /// They are reclaimed by the first sync after all the pins taken before they were freed are
+    /// dropped. If the process stops before that, they are leaked.
+    ///
+    /// With a retention horizon of `n`, pages retained for `n` syncs are reclaimed regardless, and
+    /// the pins which needed them expire, see [`StorePin::is_expired`].
+    pub fn pin(&self) -> StorePin {
+        let epoch = self.pins.lock().epoch;
+        Pins::pin(&self.pins, epoch)
+    }
+
+    /// Deallocate the runs of at least `min_run` consecutive pages returned to the free-list by a
+    /// sync, once the sync is durable, see [`Self::punch_holes`].
+    pub fn enable_hole_punching(&self, min_run: u32) {
+        self.sync.lock().hole_punch_run = Some(min_run.max(1));
+    }
+
+    /// Deallocate the runs of pages returned to the free-list by the last sync from the file, if
+    /// enabled with [`Self::enable_hole_punching`]. This is best-effort: errors, e.g. from
+    /// filesystems which don't support it, are ignored.
+    ///
+    /// The pages may only be deallocated once the sync is durable, since they are still in use as
+    /// of the previous sync.
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn punch_holes(&self) {
+        let holes = std::mem::take(&mut self.sync.lock().holes);
+        #[cfg(target_os = "linux")]
+        for (start, len) in holes {
+            let _ = crate::sys::linux::punch_hole(
+                &self.file,
+                start.0 as u64 * PAGE_SIZE as u64,
+                len as u64 * PAGE_SIZE as u64,
+            );
+        }
+        #[cfg(not(target_os = "linux"))]
+        drop(holes);
+    }
+
     /// Reads the page with the specified page number. Blocks the current thread.
-    pub fn query(&self, page_pool: &PagePool, pn: PageNumber) -> FatPage {
-        io::read_page(page_pool, &self.file, pn.0 as u64).unwrap()
+    ///
+    /// Fails if the read fails or the page doesn't match its checksum.
+    pub fn query(&self, page_pool: &PagePool, pn: PageNumber) -> anyhow::Result<FatPage> {
+        let page = io::read_page(page_pool, &self.file, pn.0 as u64)?;
+        self.check_page(pn, &page)?;
+        Ok(page)
+    }
+
+    /// Check the checksum of a page read from the store, if checksums are verified.
+    pub fn check_page(&self, pn: PageNumber, page: &FatPage) -> anyhow::Result<()> {
+        match self.verify_checksums {
+            Some(file) => checksum::check(page, TRAILER_OFFSET, file, pn.0 as u64),
+            None => Ok(()),
+        }
     }
 
     /// Create an I/O command for querying a page by number.
@@ -90,6 +261,60 @@ impl Store {
         self.sync.lock().free_list.all_tracked_pages()
     }
 
+    /// Get the next page number of the store along with all pages which are not in use: those
+    /// tracked by the free-list, including the pages storing the free-list, and those freed while
+    /// the store was pinned. A page tracked more than once is returned more than once.
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn free_pages(&self) -> (PageNumber, Vec<PageNumber>) {
+        let sync = self.sync.lock();
+        let mut free = sync.free_list.tracked_pages();
+        for (_, pages) in &sync.retained {
+            free.extend_from_slice(pages);
+        }
+        (sync.bump, free)
+    }
+
+    /// Reads the page with the specified page number, without checking its checksum. Blocks the
+    /// current thread.
+    pub fn read_unchecked(&self, page_pool: &PagePool, pn: PageNumber) -> std::io::Result<FatPage> {
+        io::read_page(page_pool, &self.file, pn.0 as u64)
+    }
+
+    /// Get statistics about the pages of the store.
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn stats(&self) -> StoreStats {
+        let sync = self.sync.lock();
+        let (free_pages, free_list_pages) = sync.free_list.page_counts();
+        StoreStats {
+            bump: sync.bump,
+            free_pages,
+            free_list_pages,
+            retained_pages: sync.retained.iter().map(|(_, pages)| pages.len()).sum(),
+            reclaimed_pages: sync.reclaimed_pages,
+        }
+    }
+
+    /// Extend the file up front to fit the pages which the next sync would take from beyond the
+    /// free-list for its first `pages` allocations, so that the sync doesn't block on extending
+    /// it. See [`SyncAllocator::reserve`].
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn reserve(&self, pages: usize) -> anyhow::Result<()> {
+        let mut sync = self.sync.lock();
+        let free_list_len = sync.free_list.as_clean().len();
+        if pages <= free_list_len {
+            return Ok(());
+        }
+        let last_pn = PageNumber(sync.bump.0 + (pages - free_list_len - 1) as u32);
+        if last_pn.0 < sync.max_bump.0 {
+            return Ok(());
+        }
+        sync.max_bump = grow(&self.file, PageNumber(last_pn.0 + 1))?;
+        Ok(())
+    }
+
     /// Start synchronization. This produces two handles,
     /// a [`SyncAllocator`] and a [`SyncFinisher`].
     ///
@@ -103,6 +328,7 @@ impl Store {
 
         let finisher = SyncFinisher {
             file: self.file.clone(),
+            pins: self.pins.clone(),
             sync_finish: sync_rx,
         };
 
@@ -145,11 +371,24 @@ impl StoreReader {
         &self.page_pool
     }
 
+    /// The codec of the values stored in the store.
+    pub fn codec(&self) -> &Codec {
+        &self.store.codec
+    }
+
     /// Reads the page with the specified page number. Blocks the current thread.
-    pub fn query(&self, pn: PageNumber) -> FatPage {
+    ///
+    /// Fails if the read fails or the page doesn't match its checksum.
+    pub fn query(&self, pn: PageNumber) -> anyhow::Result<FatPage> {
         self.store.query(&self.page_pool, pn)
     }
 
+    /// Check the checksum of a page read with [`StoreReader::io_command`], if checksums are
+    /// verified.
+    pub fn check_page(&self, pn: PageNumber, page: &FatPage) -> anyhow::Result<()> {
+        self.store.check_page(pn, page)
+    }
+
     /// Create an I/O command for querying a page by number.
     pub fn io_command(&self, pn: PageNumber, user_data: u64) -> IoCommand {
         self.store.io_command(&self.page_pool, pn, user_data)
@@ -164,6 +403,19 @@ struct StoreSync {
     max_bump: PageNumber,
     /// the free-list of pages.
     free_list: FreeList,
+    /// pages freed while the store was pinned, not yet returned to the free-list, along with the
+    /// epoch of the sync which freed them. Ordered by epoch.
+    retained: VecDeque<(u64, Vec<PageNumber>)>,
+    /// the number of syncs after which retained pages are reclaimed regardless of the pins.
+    retention_horizon: Option<u32>,
+    /// the number of retained pages returned to the free-list so far.
+    reclaimed_pages: u64,
+    /// the minimum length of the runs of pages returned to the free-list which are deallocated
+    /// from the file, if enabled.
+    hole_punch_run: Option<u32>,
+    /// the runs of pages returned to the free-list by the last sync to deallocate, as the first
+    /// page and the number of pages.
+    holes: Vec<(PageNumber, u32)>,
 }
 
 type StoreSyncGuard = ArcMutexGuard<parking_lot::RawMutex, StoreSync>;
@@ -224,6 +476,33 @@ impl SyncAllocator {
         }
     }
 
+    /// Reserve space for the given number of further allocations.
+    ///
+    /// This extends the file up front to fit the pages which the next `pages` calls to
+    /// [`Self::allocate`] would take from beyond the free-list, so that they don't block on
+    /// extending it. Allocations beyond the reservation still extend the file on demand.
+    ///
+    /// This returns an error when setting the length of the file fails.
+    pub fn reserve(&self, pages: usize) -> anyhow::Result<()> {
+        let sync = self.inner.sync();
+        let free_list_len = sync.free_list.as_clean().len();
+        let allocations = self.inner.allocations.load(Ordering::Relaxed) + pages;
+        if allocations <= free_list_len {
+            return Ok(());
+        }
+        let last_pn = PageNumber(sync.bump.0 + (allocations - free_list_len - 1) as u32);
+
+        let mut set_len_guard = self.inner.set_len_lock.lock();
+        if last_pn.0 < set_len_guard.0 {
+            return Ok(());
+        }
+        *set_len_guard = grow(&self.file, PageNumber(last_pn.0 + 1))?;
+        self.inner
+            .max_bump
+            .store(set_len_guard.0, Ordering::Relaxed);
+        Ok(())
+    }
+
     /// Get the raw FD of the store.
     pub fn store_fd(&self) -> RawFd {
         self.file.as_raw_fd()
@@ -259,10 +538,11 @@ impl Drop for SyncAllocatorInner {
 // grow the file to accommodate writes to the page with the given number. returns the new boundary
 // page of the file.
 //
-// returns an error when growing the file fails.
+// returns an error when growing the file fails, `Error::DiskFull` if it ran out of space.
 fn grow(file: &File, page: PageNumber) -> anyhow::Result<PageNumber> {
     let next_bump = page.0.next_multiple_of(GROW_STORE_BY_PAGES);
-    file.set_len(next_bump as u64 * PAGE_SIZE as u64)?;
+    file.set_len(next_bump as u64 * PAGE_SIZE as u64)
+        .map_err(crate::Error::writeout)?;
     Ok(PageNumber(next_bump))
 }
 
@@ -277,6 +557,7 @@ struct Finish {
 /// This does not actually perform any writes, except to alter the length of the store file.
 pub struct SyncFinisher {
     file: Arc<File>,
+    pins: Arc<Mutex<Pins>>,
     sync_finish: Receiver<Finish>,
 }
 
@@ -290,7 +571,7 @@ impl SyncFinisher {
     pub fn finish(
         self,
         page_pool: &PagePool,
-        freed: Vec<PageNumber>,
+        mut freed: Vec<PageNumber>,
     ) -> anyhow::Result<(Vec<(PageNumber, FatPage)>, StoreMeta)> {
         // Block on `sync_finish`.
         // UNWRAP: `SyncAllocator` sends the guard when dropped. We assume it is not leaked.
@@ -300,6 +581,55 @@ impl SyncFinisher {
             mut max_bump,
         } = self.sync_finish.recv().unwrap();
 
+        // Record the allocated pages before the free-list is modified. Allocations are served
+        // from the free-list first and then from the bump.
+        let mut written_pages = {
+            let free_list = sync.free_list.as_clean();
+            let from_free_list = std::cmp::min(allocations, free_list.len());
+            (0..from_free_list)
+                .map(|n| free_list.get_nth_pop(n))
+                .chain(
+                    (0..allocations - from_free_list).map(|n| PageNumber(sync.bump.0 + n as u32)),
+                )
+                .collect::<Vec<_>>()
+        };
+
+        // Pages freed while the store is pinned may still be read, so keep them out of the
+        // free-list until the pins taken before they were freed are gone.
+        {
+            let mut pins = self.pins.lock();
+            pins.epoch += 1;
+            let epoch = pins.epoch;
+            if !freed.is_empty() {
+                sync.retained.push_back((epoch, std::mem::take(&mut freed)));
+            }
+            let oldest_pin = pins.live.keys().next().copied();
+            let horizon = sync
+                .retention_horizon
+                .map(|h| epoch.saturating_sub(h as u64));
+            while let Some((freed_at, _)) = sync.retained.front() {
+                let freed_at = *freed_at;
+                let unpinned = oldest_pin.is_none_or(|pin| pin >= freed_at);
+                if !unpinned && horizon.is_none_or(|horizon| freed_at > horizon) {
+                    break;
+                }
+                if !unpinned {
+                    pins.expired_below = pins.expired_below.max(freed_at);
+                }
+                // UNWRAP: checked to be non-empty above.
+                let (_, mut pages) = sync.retained.pop_front().unwrap();
+                if freed_at != epoch {
+                    sync.reclaimed_pages += pages.len() as u64;
+                }
+                freed.append(&mut pages);
+            }
+        }
+
+        sync.holes = match sync.hole_punch_run {
+            Some(min_run) => free_runs(&freed, min_run),
+            None => Vec::new(),
+        };
+
         let bumps = allocations - sync.free_list.discard(allocations);
 
         // remaining allocations all logically incremented bump.
@@ -315,18 +645,205 @@ impl SyncFinisher {
         sync.bump = next_bump;
         sync.max_bump = max_bump;
 
+        written_pages.extend(freelist_pages.iter().map(|(pn, _)| *pn));
+
         let meta = StoreMeta {
             freelist_pn: sync.free_list.head_pn().unwrap_or(FREELIST_EMPTY).0,
             bump: next_bump.0,
+            written_pages,
         };
         Ok((freelist_pages, meta))
     }
 }
 
+// the runs of at least `min_run` consecutive pages among the given ones, as the first page and the
+// number of pages.
+fn free_runs(pages: &[PageNumber], min_run: u32) -> Vec<(PageNumber, u32)> {
+    let mut pages = pages.to_vec();
+    pages.sort_unstable();
+    pages.dedup();
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < pages.len() {
+        let start = pages[i];
+        let mut len = 1;
+        while i + (len as usize) < pages.len() && pages[i + len as usize].0 == start.0 + len {
+            len += 1;
+        }
+        if len >= min_run {
+            runs.push((start, len));
+        }
+        i += len as usize;
+    }
+    runs
+}
+
 /// New store metadata following a sync.
 pub struct StoreMeta {
     /// The page-number indicating the head of the free-list.
     pub freelist_pn: u32,
     /// The next free page number.
     pub bump: u32,
+    /// The pages written during the sync, including the free-list pages.
+    pub written_pages: Vec<PageNumber>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{free_runs, Codec, PageNumber, Store, GROW_STORE_BY_PAGES};
+    use crate::io::{PagePool, PAGE_SIZE};
+    use std::sync::Arc;
+
+    #[test]
+    fn reserve_extends_file_up_front() {
+        let page_pool = PagePool::new();
+        let file = Arc::new(tempfile::tempfile().unwrap());
+        let store = Store::open(
+            &page_pool,
+            file.clone(),
+            PageNumber(1),
+            None,
+            None,
+            None,
+            Codec::default(),
+        )
+        .unwrap();
+        let file_len = || file.metadata().unwrap().len() as usize / PAGE_SIZE;
+
+        let (allocator, _finisher) = store.start_sync();
+        allocator.reserve(0).unwrap();
+        assert_eq!(file_len(), 0);
+
+        // The reservation covers the bump allocations which follow it.
+        let pages = GROW_STORE_BY_PAGES as usize + 10;
+        allocator.reserve(pages).unwrap();
+        assert_eq!(file_len(), 2 * GROW_STORE_BY_PAGES as usize);
+        for _ in 0..pages {
+            assert!((allocator.allocate().unwrap().0 as usize) < file_len());
+        }
+        assert_eq!(file_len(), 2 * GROW_STORE_BY_PAGES as usize);
+
+        // Allocations beyond the reservation extend the file on demand.
+        for _ in 0..GROW_STORE_BY_PAGES {
+            allocator.allocate().unwrap();
+        }
+        assert_eq!(file_len(), 3 * GROW_STORE_BY_PAGES as usize);
+    }
+
+    #[test]
+    fn reserve_ahead_of_sync() {
+        let page_pool = PagePool::new();
+        let file = Arc::new(tempfile::tempfile().unwrap());
+        let store = Store::open(
+            &page_pool,
+            file.clone(),
+            PageNumber(1),
+            None,
+            None,
+            None,
+            Codec::default(),
+        )
+        .unwrap();
+        let file_len = || file.metadata().unwrap().len() as usize / PAGE_SIZE;
+
+        let pages = GROW_STORE_BY_PAGES as usize + 10;
+        store.reserve(pages).unwrap();
+        assert_eq!(file_len(), 2 * GROW_STORE_BY_PAGES as usize);
+        store.reserve(pages - 20).unwrap();
+        assert_eq!(file_len(), 2 * GROW_STORE_BY_PAGES as usize);
+
+        // The next sync allocates within the reservation without extending the file.
+        let (allocator, _finisher) = store.start_sync();
+        for _ in 0..pages {
+            assert!((allocator.allocate().unwrap().0 as usize) < file_len());
+        }
+        assert_eq!(file_len(), 2 * GROW_STORE_BY_PAGES as usize);
+    }
+
+    fn sync(store: &Store, page_pool: &PagePool, freed: &[u32]) {
+        let (allocator, finisher) = store.start_sync();
+        for _ in 0..10 {
+            allocator.allocate().unwrap();
+        }
+        drop(allocator);
+        let freed = freed.iter().copied().map(PageNumber).collect();
+        finisher.finish(page_pool, freed).unwrap();
+    }
+
+    #[test]
+    fn freed_pages_are_retained_for_older_pins() {
+        let page_pool = PagePool::new();
+        let file = Arc::new(tempfile::tempfile().unwrap());
+        let store = Store::open(
+            &page_pool,
+            file,
+            PageNumber(1),
+            None,
+            None,
+            None,
+            Codec::default(),
+        )
+        .unwrap();
+        sync(&store, &page_pool, &[]);
+
+        let older = store.pin();
+        sync(&store, &page_pool, &[1, 2]);
+        assert_eq!(store.stats().retained_pages, 2);
+        drop(older);
+
+        // The newer pin only needs the pages freed after it was taken.
+        let newer = store.pin();
+        sync(&store, &page_pool, &[3]);
+        let stats = store.stats();
+        assert_eq!((stats.retained_pages, stats.reclaimed_pages), (1, 2));
+        assert!(!newer.is_expired());
+
+        drop(newer);
+        sync(&store, &page_pool, &[]);
+        let stats = store.stats();
+        assert_eq!((stats.retained_pages, stats.reclaimed_pages), (0, 3));
+    }
+
+    #[test]
+    fn retention_horizon_expires_pins() {
+        let page_pool = PagePool::new();
+        let file = Arc::new(tempfile::tempfile().unwrap());
+        let store = Store::open(
+            &page_pool,
+            file,
+            PageNumber(1),
+            None,
+            None,
+            Some(2),
+            Codec::default(),
+        )
+        .unwrap();
+        sync(&store, &page_pool, &[]);
+
+        let pin = store.pin();
+        sync(&store, &page_pool, &[1, 2]);
+        sync(&store, &page_pool, &[3]);
+        assert_eq!(store.stats().retained_pages, 3);
+        assert!(!pin.is_expired());
+
+        sync(&store, &page_pool, &[]);
+        let stats = store.stats();
+        assert_eq!((stats.retained_pages, stats.reclaimed_pages), (1, 2));
+        assert!(pin.is_expired());
+        assert!(!store.pin().is_expired());
+    }
+
+    #[test]
+    fn free_runs_of_min_length() {
+        let pages = [9, 3, 4, 5, 12, 10, 11, 1, 4]
+            .into_iter()
+            .map(PageNumber)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            free_runs(&pages, 3),
+            vec![(PageNumber(3), 3), (PageNumber(9), 4)]
+        );
+        assert_eq!(free_runs(&pages, 4), vec![(PageNumber(9), 4)]);
+        assert_eq!(free_runs(&pages, 1).len(), 3);
+    }
 }
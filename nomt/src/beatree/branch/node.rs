@@ -2,6 +2,7 @@ use bitvec::prelude::*;
 
 use super::BRANCH_NODE_SIZE;
 use crate::beatree::Key;
+use crate::checksum::{CHECKSUM_SIZE, TRAILER_OFFSET};
 use crate::io::{FatPage, PagePool};
 
 // Here is the layout of a branch node:
@@ -23,13 +24,14 @@ use crate::io::{FatPage, PagePool};
 // separators: bitvec
 //
 // # Node pointers follow. The list is aligned to the end of the node, with the last item in the
-// # list occupying the last 4 bytes of the node.
+// # list occupying the last 4 bytes before the checksum.
 //
 // node_pointers: LNPN or BNID[n]
+// checksum: u64          // see `crate::checksum`, written along with the node.
 // ```
 
 const BRANCH_NODE_HEADER_SIZE: usize = 4 + 2 + 2 + 2;
-pub const BRANCH_NODE_BODY_SIZE: usize = BRANCH_NODE_SIZE - BRANCH_NODE_HEADER_SIZE;
+pub const BRANCH_NODE_BODY_SIZE: usize = BRANCH_NODE_SIZE - BRANCH_NODE_HEADER_SIZE - CHECKSUM_SIZE;
 
 /// A branch node, regardless of its level.
 pub struct BranchNode {
@@ -135,7 +137,7 @@ impl BranchNode {
     }
 
     fn set_node_pointer(&mut self, i: usize, node_pointer: u32) {
-        let offset = BRANCH_NODE_SIZE - (self.n() as usize - i) * 4;
+        let offset = TRAILER_OFFSET - (self.n() as usize - i) * 4;
         self.as_mut_slice()[offset..offset + 4].copy_from_slice(&node_pointer.to_le_bytes());
     }
 }
@@ -214,7 +216,7 @@ impl<'a> BranchNodeView<'a> {
     }
 
     pub fn node_pointer(&self, i: usize) -> u32 {
-        let offset = BRANCH_NODE_SIZE - (self.n() as usize - i) * 4;
+        let offset = TRAILER_OFFSET - (self.n() as usize - i) * 4;
         u32::from_le_bytes(self.inner[offset..offset + 4].try_into().unwrap())
     }
 }
@@ -307,10 +309,10 @@ pub mod benches {
             Key,
             {
                 benches::get_keys,
-                branch::{BranchNode, BranchNodeBuilder},
+                branch::{BranchNode, BranchNodeBuilder, BRANCH_NODE_BODY_SIZE},
             },
         },
-        io::{PagePool, PAGE_SIZE},
+        io::PagePool,
     };
     use criterion::{BenchmarkId, Criterion};
 
@@ -325,7 +327,7 @@ pub mod benches {
         for prefix_len_bytes in [1, 4, 8, 12, 16] {
             // body_size = (2 * n) + (prefix_len_bits + (separator_len_bits * n) + 7)/8 + (4 * n)
             // n = (8 * body_size - prefix_len_bits) / (separator_len_bits + 8*6)
-            let body_size_target = PAGE_SIZE - 8;
+            let body_size_target = BRANCH_NODE_BODY_SIZE;
             let prefix_len_bits = prefix_len_bytes * 8;
             let separator_len_bits = (32 - prefix_len_bytes) * 8;
             let n = (8 * body_size_target - prefix_len_bits) / (separator_len_bits + 8 * 6);
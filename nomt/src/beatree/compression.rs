@@ -0,0 +1,334 @@
+//! Compression of the values stored in the leaf store.
+//!
+//! Values are compressed when a sync writes them out and decompressed when they are read back
+//! from a leaf or from overflow pages. Values are compressed before deciding how to store them,
+//! so values which compress well take fewer overflow pages or fit in the leaf altogether, and more
+//! of them fit in a leaf.
+//!
+//! The values of a compressed store are framed with a tag byte: `0` is followed by the value as
+//! is, which is used when compressing doesn't save space, and `1` by the length of the value as
+//! a little-endian `u32` and the compressed value. The values of an uncompressed store are not
+//! framed.
+//!
+//! With zstd, the values may be compressed with a dictionary trained on sample values, which
+//! makes small values compress much better. See [`train_dictionary`].
+
+use std::{borrow::Cow, ops::Range, sync::Arc};
+
+use anyhow::Result;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+use super::ValueRef;
+use crate::io::page_pool::FatPage;
+
+const TAG_RAW: u8 = 0;
+const TAG_COMPRESSED: u8 = 1;
+
+/// The size of the frame of a compressed value: the tag and the length of the value.
+pub(crate) const COMPRESSED_HEADER_SIZE: usize = 5;
+
+/// The compression applied to the values stored in the leaf store.
+///
+/// The compression is chosen when the database is created and cannot be changed afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Values are stored as is.
+    #[default]
+    None,
+    /// Values are compressed with LZ4, which is fast but saves less space than zstd.
+    Lz4,
+    /// Values are compressed with zstd at the given level. Levels range from -128, the fastest,
+    /// to 22, the smallest, and are clamped to that range. Level 0 stands for zstd's default.
+    Zstd {
+        /// The compression level.
+        level: i32,
+    },
+}
+
+impl Compression {
+    pub(crate) fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Compression::None => [0, 0],
+            Compression::Lz4 => [1, 0],
+            Compression::Zstd { level } => [2, clamp_level(level) as i8 as u8],
+        }
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; 2]) -> Option<Self> {
+        match bytes {
+            [0, 0] => Some(Compression::None),
+            [1, 0] => Some(Compression::Lz4),
+            [2, level] => Some(Compression::Zstd {
+                level: level as i8 as i32,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Train a zstd dictionary on sample values, for use with
+/// [`Options::compression_dictionary`](crate::Options::compression_dictionary).
+///
+/// The samples should be representative of the values stored, and there should be many of them:
+/// training fails with [`Error::InvalidOperation`](crate::Error::InvalidOperation) if there are
+/// too few. `max_size` bounds the size of the dictionary, a few dozen KiB is typical.
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> crate::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size).map_err(|e| {
+        crate::Error::InvalidOperation(format!("failed to train a compression dictionary: {e}"))
+    })
+}
+
+fn clamp_level(level: i32) -> i32 {
+    level.clamp(i8::MIN as i32, 22)
+}
+
+/// A zstd dictionary, prepared for compressing and decompressing values.
+struct Dictionary {
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+/// Encodes the values to store with the compression of the store, and decodes them back.
+///
+/// This is cheap to clone.
+#[derive(Clone, Default)]
+pub(crate) struct Codec {
+    compression: Compression,
+    dictionary: Option<Arc<Dictionary>>,
+}
+
+impl Codec {
+    /// Create a codec. The dictionary is only used with zstd.
+    pub(crate) fn new(compression: Compression, dictionary: Option<&[u8]>) -> Self {
+        let dictionary = match (compression, dictionary) {
+            (Compression::Zstd { level }, Some(dictionary)) => Some(Arc::new(Dictionary {
+                encoder: EncoderDictionary::copy(dictionary, clamp_level(level)),
+                decoder: DecoderDictionary::copy(dictionary),
+            })),
+            _ => None,
+        };
+        Codec {
+            compression,
+            dictionary,
+        }
+    }
+
+    /// Whether values are stored as is.
+    pub(crate) fn is_none(&self) -> bool {
+        self.compression == Compression::None
+    }
+
+    /// Encode a value to be stored. Borrows the value if the store is not compressed.
+    pub(crate) fn encode<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        let compressed = match (self.compression, &self.dictionary) {
+            (Compression::None, _) => return Cow::Borrowed(value),
+            (Compression::Lz4, _) => lz4_flex::block::compress(value),
+            // UNWRAP: compressing into a vector only fails on invalid parameters, and the level is
+            // clamped and the dictionary was accepted when it was prepared.
+            (Compression::Zstd { level }, None) => {
+                zstd::bulk::compress(value, clamp_level(level)).unwrap()
+            }
+            (Compression::Zstd { .. }, Some(dictionary)) => {
+                zstd::bulk::Compressor::with_prepared_dictionary(&dictionary.encoder)
+                    .and_then(|mut compressor| compressor.compress(value))
+                    .unwrap()
+            }
+        };
+
+        let mut encoded;
+        if COMPRESSED_HEADER_SIZE + compressed.len() < 1 + value.len() {
+            encoded = Vec::with_capacity(COMPRESSED_HEADER_SIZE + compressed.len());
+            encoded.push(TAG_COMPRESSED);
+            encoded.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            encoded.extend_from_slice(&compressed);
+        } else {
+            encoded = Vec::with_capacity(1 + value.len());
+            encoded.push(TAG_RAW);
+            encoded.extend_from_slice(value);
+        }
+        Cow::Owned(encoded)
+    }
+
+    /// Decode a stored value. Borrows the value unless it was compressed.
+    pub(crate) fn decode<'a>(&self, stored: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if self.is_none() {
+            return Ok(Cow::Borrowed(stored));
+        }
+        match stored.first() {
+            Some(&TAG_RAW) => Ok(Cow::Borrowed(&stored[1..])),
+            Some(&TAG_COMPRESSED) if stored.len() >= COMPRESSED_HEADER_SIZE => {
+                // UNWRAP: the slice is 4 bytes long.
+                let len = u32::from_le_bytes(stored[1..5].try_into().unwrap()) as usize;
+                let compressed = &stored[COMPRESSED_HEADER_SIZE..];
+                let value = match (self.compression, &self.dictionary) {
+                    (Compression::None, _) => unreachable!(),
+                    (Compression::Lz4, _) => lz4_flex::block::decompress(compressed, len).ok(),
+                    (Compression::Zstd { .. }, None) => {
+                        zstd::bulk::decompress(compressed, len).ok()
+                    }
+                    (Compression::Zstd { .. }, Some(dictionary)) => {
+                        zstd::bulk::Decompressor::with_prepared_dictionary(&dictionary.decoder)
+                            .and_then(|mut decompressor| decompressor.decompress(compressed, len))
+                            .ok()
+                    }
+                };
+                match value {
+                    Some(value) if value.len() == len => Ok(Cow::Owned(value)),
+                    _ => Err(corrupted()),
+                }
+            }
+            _ => Err(corrupted()),
+        }
+    }
+
+    /// Decode a stored value read into an owned buffer, e.g. from overflow pages, reusing the
+    /// buffer unless the value was compressed.
+    pub(crate) fn decode_owned(&self, stored: Vec<u8>) -> Result<ValueRef> {
+        if self.is_none() {
+            return Ok(ValueRef::owned(stored));
+        }
+        match self.decode(&stored)? {
+            Cow::Borrowed(_) => {
+                let len = stored.len();
+                Ok(ValueRef::owned_range(stored, 1..len))
+            }
+            Cow::Owned(value) => Ok(ValueRef::owned(value)),
+        }
+    }
+
+    /// Returns the size of a value given its stored form, without decompressing it.
+    pub(crate) fn value_size(&self, stored: &[u8]) -> usize {
+        self.value_size_from_header(stored, stored.len())
+    }
+
+    /// Returns the size of a value given the size of its stored form and its first bytes, of
+    /// which [`COMPRESSED_HEADER_SIZE`] are enough.
+    pub(crate) fn value_size_from_header(&self, header: &[u8], stored_len: usize) -> usize {
+        match header {
+            _ if self.is_none() => stored_len,
+            [TAG_COMPRESSED, len @ ..] if len.len() >= 4 => {
+                // UNWRAP: the slice is 4 bytes long.
+                u32::from_le_bytes(len[..4].try_into().unwrap()) as usize
+            }
+            _ => stored_len.saturating_sub(1),
+        }
+    }
+
+    /// Decode a value stored inline in a leaf page. Values stored as is are still served from the
+    /// page without copying them.
+    pub(crate) fn decode_in_page(&self, page: FatPage, range: Range<usize>) -> Result<ValueRef> {
+        if self.is_none() {
+            return Ok(ValueRef::in_page(page, range));
+        }
+        match self.decode(&page[range.clone()])? {
+            Cow::Borrowed(_) => Ok(ValueRef::in_page(page, range.start + 1..range.end)),
+            Cow::Owned(value) => Ok(ValueRef::owned(value)),
+        }
+    }
+}
+
+fn corrupted() -> anyhow::Error {
+    crate::Error::Corruption("malformed compressed value in the leaf store".to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{train_dictionary, Codec, Compression};
+
+    #[test]
+    fn roundtrip() {
+        let compressible = vec![7u8; 1000];
+        let mut incompressible = vec![0; 1000];
+        blake3::Hasher::new()
+            .finalize_xof()
+            .fill(&mut incompressible);
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd { level: 3 },
+        ] {
+            let codec = Codec::new(compression, None);
+            for value in [&compressible[..], &incompressible[..], &[]] {
+                let encoded = codec.encode(value);
+                assert_eq!(&*codec.decode(&encoded).unwrap(), value);
+                assert_eq!(&*codec.decode_owned(encoded.to_vec()).unwrap(), value);
+                assert_eq!(codec.value_size(&encoded), value.len());
+                assert_eq!(
+                    codec.value_size_from_header(
+                        &encoded[..encoded.len().min(super::COMPRESSED_HEADER_SIZE)],
+                        encoded.len()
+                    ),
+                    value.len()
+                );
+            }
+        }
+
+        let lz4 = Codec::new(Compression::Lz4, None);
+        assert!(lz4.encode(&compressible).len() < 100);
+        assert_eq!(lz4.encode(&incompressible).len(), 1 + incompressible.len());
+    }
+
+    #[test]
+    fn dictionary_roundtrip() {
+        // Records sharing most of their bytes, which don't compress well on their own.
+        let record = |i: u32| {
+            let mut record = format!("{{\"owner\":\"account-{i}\",\"nonce\":").into_bytes();
+            record.extend_from_slice(blake3::hash(&(i % 7).to_le_bytes()).as_bytes());
+            record.extend_from_slice(&i.to_le_bytes());
+            record
+        };
+        let samples = (0..1000).map(record).collect::<Vec<_>>();
+        let dictionary = train_dictionary(&samples, 4096).unwrap();
+
+        let compression = Compression::Zstd { level: 3 };
+        let plain = Codec::new(compression, None);
+        let with_dictionary = Codec::new(compression, Some(&dictionary));
+        let value = record(5000);
+        let encoded = with_dictionary.encode(&value);
+        assert!(encoded.len() * 3 < plain.encode(&value).len() * 2);
+        assert_eq!(&*with_dictionary.decode(&encoded).unwrap(), &value[..]);
+
+        // Values compressed with a dictionary can't be decompressed without it.
+        assert!(plain.decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn too_few_samples_are_rejected() {
+        assert!(matches!(
+            train_dictionary(&[b"value"], 4096),
+            Err(crate::Error::InvalidOperation(_))
+        ));
+    }
+
+    #[test]
+    fn damaged_values_are_rejected() {
+        let zstd = Codec::new(Compression::Zstd { level: 3 }, None);
+        let mut encoded = zstd.encode(&[7u8; 1000]).into_owned();
+        encoded[1] ^= 1;
+        assert!(zstd.decode(&encoded).is_err());
+
+        let lz4 = Codec::new(Compression::Lz4, None);
+        assert!(lz4.decode(&[2, 0]).is_err());
+        assert!(lz4.decode(&[]).is_err());
+    }
+    #[test]
+    fn bytes_roundtrip() {
+        for compression in [
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd { level: -5 },
+            Compression::Zstd { level: 22 },
+        ] {
+            assert_eq!(
+                Compression::from_bytes(compression.to_bytes()),
+                Some(compression)
+            );
+        }
+        assert_eq!(
+            Compression::from_bytes(Compression::Zstd { level: 100 }.to_bytes()),
+            Some(Compression::Zstd { level: 22 })
+        );
+        assert_eq!(Compression::from_bytes([3, 0]), None);
+    }
+}
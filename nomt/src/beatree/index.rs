@@ -33,6 +33,16 @@ impl Index {
             .map(|(k, _)| *k)
     }
 
+    /// Get the last separator less than the given key.
+    pub fn prev_key(&self, key: Key) -> Option<Key> {
+        self.first_key_map.range(..key).next_back().map(|(k, _)| *k)
+    }
+
+    /// Iterate over all the branches, in order.
+    pub fn branches(&self) -> impl Iterator<Item = &Arc<BranchNode>> {
+        self.first_key_map.values()
+    }
+
     /// Remove the branch with the given separator key.
     pub fn remove(&mut self, separator: &Key) -> Option<Arc<BranchNode>> {
         self.first_key_map.remove(separator)
@@ -21,7 +21,8 @@
 /// When a cell is an overflow cell, the high bit in the offset is set to `1`. Only the low
 /// 15 bits should count when considering the offset.
 ///
-/// Cells are left-aligned and thus the last value is always attached to the end.
+/// Cells are left-aligned and thus the last value is always attached to the end, right before
+/// the checksum of the page (see `crate::checksum`), which takes the last 8 bytes.
 ///
 /// The offset of the first cell also serves to detect potential overlap
 /// between the growth of cell_pointers and cells.
@@ -29,11 +30,12 @@ use std::ops::Range;
 
 use crate::{
     beatree::Key,
+    checksum::{CHECKSUM_SIZE, TRAILER_OFFSET},
     io::{page_pool::FatPage, PagePool, PAGE_SIZE},
 };
 
-/// The size of the leaf node body: everything excluding the mandatory header.
-pub const LEAF_NODE_BODY_SIZE: usize = PAGE_SIZE - 2;
+/// The size of the leaf node body: everything excluding the mandatory header and the checksum.
+pub const LEAF_NODE_BODY_SIZE: usize = PAGE_SIZE - 2 - CHECKSUM_SIZE;
 
 /// The maximum value size before overflow pages are used.
 pub const MAX_LEAF_VALUE_SIZE: usize = (LEAF_NODE_BODY_SIZE / 3) - 32;
@@ -69,12 +71,17 @@ impl LeafNode {
     }
 
     pub fn get(&self, key: &Key) -> Option<(&[u8], bool)> {
+        self.get_range(key)
+            .map(|(range, overflow)| (&self.inner[range], overflow))
+    }
+
+    /// Like [`LeafNode::get`], but returns the range of the page at which the value is stored.
+    pub fn get_range(&self, key: &Key) -> Option<(Range<usize>, bool)> {
         let cell_pointers = self.cell_pointers();
 
         search(cell_pointers, key)
             .ok()
             .map(|index| self.value_range(cell_pointers, index))
-            .map(|(range, overflow)| (&self.inner[range], overflow))
     }
 
     pub fn values_size(&self, from: usize, to: usize) -> usize {
@@ -88,7 +95,7 @@ impl LeafNode {
     fn value_range(&self, cell_pointers: &[[u8; 34]], index: usize) -> (Range<usize>, bool) {
         let (start, overflow) = cell_offset(cell_pointers, index);
         let end = if index == cell_pointers.len() - 1 {
-            PAGE_SIZE
+            TRAILER_OFFSET
         } else {
             cell_offset(cell_pointers, index + 1).0
         };
@@ -134,7 +141,7 @@ impl LeafBuilder {
     pub fn push_cell(&mut self, key: Key, value: &[u8], overflow: bool) {
         assert!(self.index < self.leaf.n());
 
-        let offset = PAGE_SIZE - self.remaining_value_size;
+        let offset = TRAILER_OFFSET - self.remaining_value_size;
         let cell_pointer = &mut self.leaf.cell_pointers_mut()[self.index];
 
         encode_cell_pointer(&mut cell_pointer[..], key, offset, overflow);
@@ -153,7 +160,7 @@ impl LeafBuilder {
         self.leaf.cell_pointers_mut()[self.index..self.index + n_items]
             .copy_from_slice(&base_node_cell_pointers[from..to]);
 
-        let offset = PAGE_SIZE - self.remaining_value_size;
+        let offset = TRAILER_OFFSET - self.remaining_value_size;
 
         let value_range_start = base_node.value_range(base_node_cell_pointers, from).0.start;
         let value_range_end = base_node.value_range(base_node_cell_pointers, to - 1).0.end;
@@ -8,20 +8,22 @@
 /// n_bytes: u16
 /// pointers: [PageNumber; n_pointers]
 /// bytes: [u8; n_bytes]
+/// checksum: u64 // in the last 8 bytes of the page, see `crate::checksum`.
 /// ```
 use crate::{
     beatree::{
         allocator::{StoreReader, SyncAllocator},
         PageNumber,
     },
+    checksum::{self, CHECKSUM_SIZE, TRAILER_OFFSET},
     io::{page_pool::FatPage, IoCommand, IoHandle, IoKind, PagePool, PAGE_SIZE},
 };
 
 use super::node::MAX_OVERFLOW_CELL_NODE_POINTERS;
 
-const BODY_SIZE: usize = PAGE_SIZE - 4;
-const MAX_PNS: usize = BODY_SIZE / 4;
 const HEADER_SIZE: usize = 4;
+const BODY_SIZE: usize = PAGE_SIZE - HEADER_SIZE - CHECKSUM_SIZE;
+const MAX_PNS: usize = BODY_SIZE / 4;
 
 /// Encode a large value into freshly allocated overflow pages. Returns a vector of page pointers
 /// and the total number of page writes submitted.
@@ -76,6 +78,8 @@ pub fn chunk(
         page[start..end].copy_from_slice(&value[..bytes]);
         value = &value[bytes..];
 
+        checksum::write(&mut page, TRAILER_OFFSET);
+
         // write the page.
         let command = IoCommand {
             kind: IoKind::Write(leaf_writer.store_fd(), pn.0 as u64, page),
@@ -113,7 +117,8 @@ pub fn encode_cell(value_size: usize, pages: &[PageNumber]) -> Vec<u8> {
     v
 }
 
-fn total_needed_pages(value_size: usize) -> usize {
+/// The number of overflow pages needed to store a value of the given size.
+pub fn total_needed_pages(value_size: usize) -> usize {
     // the encoded size is equal to the size of the value plus the number of node pointers that
     // will appear in pages.
     let needed_pages_raw_value = needed_pages(value_size);
@@ -169,7 +174,7 @@ fn needed_pages(size: usize) -> usize {
 }
 
 /// Read a large value from pages referenced by an overflow cell.
-pub fn read(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
+pub fn read(cell: &[u8], leaf_reader: &StoreReader) -> anyhow::Result<Vec<u8>> {
     let (value_size, cell_pages) = decode_cell(cell);
     let total_pages = total_needed_pages(value_size);
 
@@ -179,7 +184,7 @@ pub fn read(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
     page_numbers.extend(cell_pages);
 
     for i in 0..total_pages {
-        let page = leaf_reader.query(page_numbers[i]);
+        let page = leaf_reader.query(page_numbers[i])?;
         let (page_pns, bytes) = read_page(&page);
         page_numbers.extend(page_pns);
         value.extend(bytes);
@@ -188,21 +193,62 @@ pub fn read(cell: &[u8], leaf_reader: &StoreReader) -> Vec<u8> {
     assert_eq!(page_numbers.len(), total_pages);
     assert_eq!(value.len(), value_size);
 
-    value
+    Ok(value)
+}
+
+/// Read the first `len` bytes of a large value, or the whole value if it is shorter. Only the pages
+/// up to the one holding the last of those bytes are read.
+pub fn read_prefix(cell: &[u8], len: usize, leaf_reader: &StoreReader) -> anyhow::Result<Vec<u8>> {
+    let (value_size, cell_pages) = decode_cell(cell);
+    let len = len.min(value_size);
+
+    let mut prefix = Vec::with_capacity(len);
+    let mut page_numbers = cell_pages.collect::<Vec<_>>();
+    let mut i = 0;
+    while prefix.len() < len {
+        let Some(&pn) = page_numbers.get(i) else {
+            anyhow::bail!("overflow value ends after {} bytes", prefix.len());
+        };
+        let page = leaf_reader.query(pn)?;
+        let (page_pns, bytes) = read_page(&page);
+        page_numbers.extend(page_pns);
+        prefix.extend_from_slice(&bytes[..bytes.len().min(len - prefix.len())]);
+        i += 1;
+    }
+
+    Ok(prefix)
 }
 
 /// Iterate all pages related to an overflow cell and push onto a free-list.
 pub fn delete(cell: &[u8], leaf_reader: &StoreReader, freed: &mut Vec<PageNumber>) {
+    // UNWRAP: the pages being deleted were checked when the value was read or the leaf
+    // holding the cell was loaded, and a failure here cannot be recovered from.
+    pages(cell, |pn| leaf_reader.query(pn), freed).unwrap();
+}
+
+/// Collect the numbers of all pages related to an overflow cell, reading the pages which hold
+/// page numbers with `read`.
+///
+/// Fails if `read` fails or the pages don't hold as many page numbers as the size of the value
+/// requires.
+pub fn pages(
+    cell: &[u8],
+    mut read: impl FnMut(PageNumber) -> anyhow::Result<FatPage>,
+    pages: &mut Vec<PageNumber>,
+) -> anyhow::Result<()> {
     let (value_size, cell_pages) = decode_cell(cell);
     let total_pages = total_needed_pages(value_size);
 
-    let start = freed.len();
-    freed.extend(cell_pages);
+    let start = pages.len();
+    pages.extend(cell_pages);
 
     for i in 0..total_pages {
-        let page = leaf_reader.query(freed[start + i]);
+        let Some(&pn) = pages.get(start + i) else {
+            break;
+        };
+        let page = read(pn)?;
         let (page_pns, bytes) = read_page(&page);
-        freed.extend(page_pns);
+        pages.extend(page_pns);
 
         // stop at the first page containing value data. no more pages will have more
         // page numbers to release.
@@ -211,7 +257,12 @@ pub fn delete(cell: &[u8], leaf_reader: &StoreReader, freed: &mut Vec<PageNumber
         }
     }
 
-    assert_eq!(freed.len() - start, total_pages);
+    anyhow::ensure!(
+        pages.len() - start == total_pages,
+        "expected {total_pages} overflow pages, found {}",
+        pages.len() - start
+    );
+    Ok(())
 }
 
 fn read_page<'a>(page: &'a FatPage) -> (impl Iterator<Item = PageNumber> + 'a, &'a [u8]) {
@@ -281,7 +332,7 @@ mod tests {
         let size = 1 << 30;
 
         // this many pages for the value
-        let pages0 = 262401;
+        let pages0 = 262915;
         assert_eq!(needed_pages(size), pages0);
 
         let pages_in_pages0 = pages0 - MAX_OVERFLOW_CELL_NODE_POINTERS;
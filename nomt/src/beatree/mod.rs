@@ -1,25 +1,42 @@
-use allocator::{PageNumber, Store, StoreReader, FREELIST_EMPTY};
+use allocator::{PageNumber, Store, StorePin, StoreReader, StoreStats, FREELIST_EMPTY};
 use anyhow::{Context, Result};
 use branch::BRANCH_NODE_SIZE;
 use parking_lot::{Mutex, RwLock};
-use std::{collections::BTreeMap, fs::File, mem, ops::DerefMut, path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    mem,
+    ops::DerefMut,
+    path::Path,
+    sync::Arc,
+};
 use threadpool::ThreadPool;
 
 use crate::io::{IoHandle, IoPool, PagePool};
 
 pub(crate) mod allocator;
 pub(crate) mod branch;
+pub(crate) mod compression;
 mod index;
 mod leaf;
 pub(crate) mod ops;
+mod value_ref;
 pub(crate) mod writeout;
+#[cfg(feature = "unstable")]
+pub use compression::train_dictionary;
+use compression::Codec;
+pub use compression::Compression;
 pub(crate) use index::Index;
+pub use ops::{CommitWorkers, SizeEstimate};
+pub use value_ref::ValueRef;
 
 #[cfg(feature = "benchmarks")]
 pub mod benches;
 
 pub type Key = [u8; 32];
 
+pub use crate::Value;
+
 #[derive(Clone)]
 pub struct Tree {
     shared: Arc<RwLock<Shared>>,
@@ -35,19 +52,35 @@ struct Shared {
     leaf_store_rd: StoreReader,
     /// Primary staging collects changes that are committed but not synced yet. Upon sync, changes
     /// from here are moved to secondary staging.
-    primary_staging: BTreeMap<Key, Option<Vec<u8>>>,
+    primary_staging: BTreeMap<Key, Option<Value>>,
     /// Secondary staging collects committed changes that are currently being synced. This is None
     /// if there is no sync in progress.
-    secondary_staging: Option<Arc<BTreeMap<Key, Option<Vec<u8>>>>>,
+    secondary_staging: Option<Arc<BTreeMap<Key, Option<Value>>>>,
 }
 
 struct Sync {
     tp: ThreadPool,
-    commit_concurrency: usize,
+    commit_workers: CommitWorkers,
+}
+
+/// The settings of a [`Tree`] which are not stored in the meta.
+pub struct TreeOptions {
+    /// The workers used by the updates of the tree.
+    pub commit_workers: CommitWorkers,
+    /// The number of threads used to reconstruct the branch index.
+    pub recovery_concurrency: usize,
+    /// Whether to verify the checksums of the pages read from disk.
+    pub verify_checksums: bool,
+    /// The number of syncs after which leaf pages retained for snapshots are reclaimed.
+    pub snapshot_horizon: Option<u32>,
+    /// The codec used to store the values of the leaves.
+    pub codec: Codec,
+    /// The minimum length of the runs of freed pages deallocated from the files, if enabled.
+    pub hole_punch_run: Option<u32>,
 }
 
 impl Shared {
-    fn take_staged_changeset(&mut self) -> Arc<BTreeMap<Key, Option<Vec<u8>>>> {
+    fn take_staged_changeset(&mut self) -> Arc<BTreeMap<Key, Option<Value>>> {
         assert!(self.secondary_staging.is_none());
         let staged = Arc::new(mem::take(&mut self.primary_staging));
         self.secondary_staging = Some(staged.clone());
@@ -63,10 +96,18 @@ impl Tree {
         bbn_freelist_pn: u32,
         ln_bump: u32,
         bbn_bump: u32,
-        bbn_file: &File,
-        ln_file: &File,
-        commit_concurrency: usize,
+        bbn_file: &Arc<File>,
+        ln_file: &Arc<File>,
+        options: TreeOptions,
     ) -> Result<Tree> {
+        let TreeOptions {
+            commit_workers,
+            recovery_concurrency,
+            verify_checksums,
+            snapshot_horizon,
+            codec,
+            hole_punch_run,
+        } = options;
         let ln_freelist_pn = Some(ln_freelist_pn)
             .map(PageNumber)
             .filter(|&x| x != FREELIST_EMPTY);
@@ -79,24 +120,37 @@ impl Tree {
 
         let leaf_store = Store::open(
             &page_pool,
-            ln_file.try_clone().unwrap(),
+            ln_file.clone(),
             ln_bump,
             ln_freelist_pn,
+            verify_checksums.then_some("ln"),
+            snapshot_horizon,
+            codec,
         )?;
 
         let bbn_store = Store::open(
             &page_pool,
-            bbn_file.try_clone().unwrap(),
+            bbn_file.clone(),
             bbn_bump,
             bbn_freelist_pn,
+            verify_checksums.then_some("bbn"),
+            None,
+            Codec::default(),
         )?;
 
+        if let Some(min_run) = hole_punch_run {
+            leaf_store.enable_hole_punching(min_run);
+            bbn_store.enable_hole_punching(min_run);
+        }
+
         let bbn_freelist_tracked = bbn_store.all_tracked_freelist_pages();
         let index = ops::reconstruct(
-            bbn_file.try_clone().unwrap(),
+            bbn_file,
             &page_pool,
             &bbn_freelist_tracked,
             bbn_bump,
+            recovery_concurrency,
+            verify_checksums,
         )
         .with_context(|| format!("failed to reconstruct btree from bbn store file"))?;
         let shared = Shared {
@@ -111,8 +165,8 @@ impl Tree {
         };
 
         let sync = Sync {
-            tp: ThreadPool::with_name("beatree-sync".into(), commit_concurrency),
-            commit_concurrency,
+            tp: ThreadPool::with_name("beatree-sync".into(), commit_workers.max),
+            commit_workers,
         };
 
         Ok(Tree {
@@ -122,21 +176,81 @@ impl Tree {
     }
 
     /// Lookup a key in the btree.
-    pub fn lookup(&self, key: Key) -> Option<Vec<u8>> {
+    pub fn lookup(&self, key: Key) -> Result<Option<ValueRef>> {
         let shared = self.shared.read();
 
         // First look up in the primary staging which contains the most recent changes.
         if let Some(val) = shared.primary_staging.get(&key) {
-            return val.clone();
+            return Ok(val.clone().map(ValueRef::shared));
         }
 
         // Then check the secondary staging which is a bit older, but fresher still than the btree.
         if let Some(val) = shared.secondary_staging.as_ref().and_then(|x| x.get(&key)) {
-            return val.clone();
+            return Ok(val.clone().map(ValueRef::shared));
         }
 
         // Finally, look up in the btree.
-        ops::lookup(key, &shared.bbn_index, &shared.leaf_store_rd).unwrap()
+        ops::lookup(key, &shared.bbn_index, &shared.leaf_store_rd)
+    }
+
+    /// Check whether a key is present in the btree, without loading its value.
+    pub fn contains(&self, key: Key) -> Result<bool> {
+        let shared = self.shared.read();
+
+        if let Some(val) = shared.primary_staging.get(&key) {
+            return Ok(val.is_some());
+        }
+
+        if let Some(val) = shared.secondary_staging.as_ref().and_then(|x| x.get(&key)) {
+            return Ok(val.is_some());
+        }
+
+        ops::contains(key, &shared.bbn_index, &shared.leaf_store_rd)
+    }
+
+    /// Get the size of the value stored under a key in the btree, without loading the value.
+    pub fn value_size(&self, key: Key) -> Result<Option<usize>> {
+        let shared = self.shared.read();
+
+        if let Some(val) = shared.primary_staging.get(&key) {
+            return Ok(val.as_ref().map(|v| v.len()));
+        }
+
+        if let Some(val) = shared.secondary_staging.as_ref().and_then(|x| x.get(&key)) {
+            return Ok(val.as_ref().map(|v| v.len()));
+        }
+
+        ops::value_size(key, &shared.bbn_index, &shared.leaf_store_rd)
+    }
+
+    /// Collect the keys within the inclusive range `start..=end`, in order.
+    pub fn keys_in_range(&self, start: Key, end: Key) -> Vec<Key> {
+        let shared = self.shared.read();
+
+        let mut btree = ops::RangeIter::new(
+            start,
+            end,
+            shared.bbn_index.clone(),
+            shared.leaf_store_rd.clone(),
+        );
+        let mut keys: BTreeSet<Key> = std::iter::from_fn(|| btree.next_key()).collect();
+
+        // Apply the staged changes, from the oldest to the most recent.
+        let secondary_staging = shared.secondary_staging.as_deref();
+        for staging in secondary_staging
+            .into_iter()
+            .chain([&shared.primary_staging])
+        {
+            for (key, value) in staging.range(start..=end) {
+                if value.is_some() {
+                    keys.insert(*key);
+                } else {
+                    keys.remove(key);
+                }
+            }
+        }
+
+        keys.into_iter().collect()
     }
 
     /// Commit a set of changes to the btree.
@@ -145,7 +259,7 @@ impl Tree {
     /// The changeset is applied atomically. If the changeset is empty, the btree is not modified.
     // There might be some temptation to unify this with prepare_sync, but this should not be done
     // because in the future sync and commit will be called on different threads at different times.
-    pub fn commit(&self, changeset: Vec<(Key, Option<Vec<u8>>)>) {
+    pub fn commit(&self, changeset: Vec<(Key, Option<Value>)>) {
         if changeset.is_empty() {
             return;
         }
@@ -156,10 +270,68 @@ impl Tree {
         }
     }
 
+    /// Take a snapshot of the btree, which keeps seeing the current state regardless of further
+    /// commits and syncs.
+    ///
+    /// This is cheap: the index is copied-on-write and the staged changes, which are usually empty
+    /// between syncs, are shared. The leaf store is pinned for as long as the snapshot is alive,
+    /// see [`Store::pin`]. Once the pin expired, lookups fail and iterators panic.
+    ///
+    /// Must not be called while a sync is in progress: the pages freed by it would not be pinned.
+    pub fn snapshot(&self) -> Snapshot {
+        let shared = self.shared.read();
+        Snapshot {
+            bbn_index: shared.bbn_index.clone(),
+            leaf_store_rd: shared.leaf_store_rd.clone(),
+            primary_staging: Arc::new(shared.primary_staging.clone()),
+            secondary_staging: shared.secondary_staging.clone(),
+            pin: shared.leaf_store.pin(),
+        }
+    }
+
+    /// Extend the leaf store up front to fit `pages` allocations by the next sync.
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn reserve_leaf_pages(&self, pages: usize) -> Result<()> {
+        let leaf_store = self.shared.read().leaf_store.clone();
+        leaf_store.reserve(pages)
+    }
+
+    /// Get statistics about the pages of the leaf and bbn stores, in that order.
+    ///
+    /// Blocks if sync is ongoing.
+    pub fn store_stats(&self) -> (StoreStats, StoreStats) {
+        let (leaf_store, bbn_store) = {
+            let shared = self.shared.read();
+            (shared.leaf_store.clone(), shared.bbn_store.clone())
+        };
+        (leaf_store.stats(), bbn_store.stats())
+    }
+
+    /// Check the b-tree as of the last sync, recording any problems in the report. See
+    /// [`ops::check_integrity`].
+    ///
+    /// Must not be called while a sync is in progress.
+    pub fn check_integrity(&self, report: &mut crate::store::IntegrityReport) -> Result<()> {
+        let (page_pool, bbn_index, leaf_store, bbn_store) = {
+            let shared = self.shared.read();
+            (
+                shared.page_pool.clone(),
+                shared.bbn_index.clone(),
+                shared.leaf_store.clone(),
+                shared.bbn_store.clone(),
+            )
+        };
+        ops::check_integrity(&page_pool, &bbn_index, &leaf_store, &bbn_store, report)
+    }
+
     /// Dump all changes performed by commits to the underlying storage medium.
     ///
-    /// Either blocks or panics if another sync is inflight.
-    pub fn prepare_sync(&self) -> SyncData {
+    /// Either blocks or panics if another sync is inflight. Fails if allocating or writing the
+    /// pages fails, with [`crate::Error::DiskFull`] if the disk ran out of space. The files are
+    /// left as referenced by the last meta then, but the staged changes are not restored, so no
+    /// further syncs may be attempted.
+    pub fn prepare_sync(&self) -> Result<SyncData> {
         // Take the sync lock.
         //
         // That will exclude any other syncs from happening. This is a long running operation.
@@ -212,20 +384,252 @@ impl Tree {
                 page_pool,
                 io_handle,
                 sync.tp.clone(),
-                sync.commit_concurrency,
+                sync.commit_workers,
             )
-            .unwrap()
         }
     }
 
+    /// Complete a sync once it is durable.
     pub fn finish_sync(&self, bbn_index: Index) {
         // Take the shared lock again to complete the update to the new shared state
-        let mut inner = self.shared.write();
-        inner.secondary_staging = None;
-        inner.bbn_index = bbn_index;
+        let (leaf_store, bbn_store) = {
+            let mut inner = self.shared.write();
+            inner.secondary_staging = None;
+            inner.bbn_index = bbn_index;
+            (inner.leaf_store.clone(), inner.bbn_store.clone())
+        };
+        // The pages freed by the sync are no longer referenced by the meta.
+        leaf_store.punch_holes();
+        bbn_store.punch_holes();
+    }
+}
+
+/// A read-only view of the btree as of the time it was taken. See [`Tree::snapshot`].
+#[derive(Clone)]
+pub struct Snapshot {
+    bbn_index: index::Index,
+    leaf_store_rd: StoreReader,
+    primary_staging: Arc<BTreeMap<Key, Option<Value>>>,
+    secondary_staging: Option<Arc<BTreeMap<Key, Option<Value>>>>,
+    pin: StorePin,
+}
+
+impl Snapshot {
+    /// Lookup a key in the snapshot.
+    pub fn lookup(&self, key: Key) -> Result<Option<ValueRef>> {
+        if let Some(val) = self.primary_staging.get(&key) {
+            return Ok(val.clone().map(ValueRef::shared));
+        }
+
+        if let Some(val) = self.secondary_staging.as_ref().and_then(|x| x.get(&key)) {
+            return Ok(val.clone().map(ValueRef::shared));
+        }
+
+        let value = ops::lookup(key, &self.bbn_index, &self.leaf_store_rd)?;
+        // The pages may have been reused while they were read.
+        if self.pin.is_expired() {
+            return Err(expired().into());
+        }
+        Ok(value)
+    }
+
+    /// Iterate over the entries within the inclusive range `start..=end` as of the snapshot, in
+    /// order. The iterator may also be consumed from the back, in reverse order. It keeps the
+    /// pages it reads pinned on its own.
+    pub fn iter(&self, start: Key, end: Key) -> Iter {
+        // Apply the staged changes from the oldest to the most recent.
+        let mut staged = BTreeMap::new();
+        for staging in self
+            .secondary_staging
+            .as_deref()
+            .into_iter()
+            .chain([&*self.primary_staging])
+        {
+            staged.extend(staging.range(start..=end).map(|(k, v)| (*k, v.clone())));
+        }
+        Iter {
+            btree: ops::RangeIter::new(
+                start,
+                end,
+                self.bbn_index.clone(),
+                self.leaf_store_rd.clone(),
+            ),
+            next_btree: None,
+            next_btree_back: None,
+            staged,
+            _pin: self.pin.clone(),
+        }
+    }
+
+    /// Estimate the amount of data stored as of the snapshot. See [`ops::estimate_size`].
+    ///
+    /// Staged changes are not taken into account.
+    pub fn estimate_size(&self) -> SizeEstimate {
+        ops::estimate_size(&self.bbn_index, &self.leaf_store_rd)
+    }
+
+    /// Returns the lowest key stored as of the snapshot, without loading any value.
+    pub fn first_key(&self) -> Option<Key> {
+        self.edge_key(false)
+    }
+
+    /// Returns the highest key stored as of the snapshot, without loading any value.
+    pub fn last_key(&self) -> Option<Key> {
+        self.edge_key(true)
+    }
+
+    fn edge_key(&self, rev: bool) -> Option<Key> {
+        // The first key in the given direction which is stored in a staging, taking the more
+        // recent staging into account.
+        let secondary_staging = self.secondary_staging.as_deref();
+        let staged = |staging: &BTreeMap<Key, Option<Value>>, newer: Option<&BTreeMap<_, _>>| {
+            let mut keys = staging
+                .iter()
+                .filter(|(key, value)| {
+                    value.is_some() && newer.is_none_or(|newer| !newer.contains_key(*key))
+                })
+                .map(|(key, _)| *key);
+            if rev {
+                keys.next_back()
+            } else {
+                keys.next()
+            }
+        };
+        let staged_key = [
+            staged(&self.primary_staging, None),
+            secondary_staging.and_then(|staging| staged(staging, Some(&self.primary_staging))),
+        ]
+        .into_iter()
+        .flatten();
+
+        // The first key in the btree which isn't deleted by a staging.
+        let mut btree = ops::RangeIter::new(
+            [0; 32],
+            [0xFF; 32],
+            self.bbn_index.clone(),
+            self.leaf_store_rd.clone(),
+        );
+        let btree_key = std::iter::from_fn(|| {
+            if rev {
+                btree.next_key_back()
+            } else {
+                btree.next_key()
+            }
+        })
+        .find(|key| {
+            let staged = self
+                .primary_staging
+                .get(key)
+                .or_else(|| secondary_staging.and_then(|staging| staging.get(key)));
+            !matches!(staged, Some(None))
+        });
+
+        let keys = staged_key.chain(btree_key);
+        if rev {
+            keys.max()
+        } else {
+            keys.min()
+        }
+    }
+}
+
+/// An iterator over the entries of the btree within a range of keys, in order. Created with
+/// [`Snapshot::iter`].
+pub struct Iter {
+    btree: ops::RangeIter,
+    /// The next entries of the btree from the front and from the back, if already read.
+    next_btree: Option<(Key, Value)>,
+    next_btree_back: Option<(Key, Value)>,
+    /// The staged changes within the range which were not yet returned. They take precedence
+    /// over the btree.
+    staged: BTreeMap<Key, Option<Value>>,
+    _pin: StorePin,
+}
+
+impl Iter {
+    /// Take the next entry from the front or, if `rev`, from the back.
+    fn next_entry(&mut self, rev: bool) -> Option<(Key, Value)> {
+        loop {
+            // Once the btree is exhausted on one side, the entry read ahead on the other side
+            // is the only one left.
+            let next_btree = if rev {
+                if self.next_btree_back.is_none() {
+                    self.next_btree_back =
+                        self.btree.next_back().or_else(|| self.next_btree.take());
+                }
+                &mut self.next_btree_back
+            } else {
+                if self.next_btree.is_none() {
+                    self.next_btree = self.btree.next().or_else(|| self.next_btree_back.take());
+                }
+                &mut self.next_btree
+            };
+            let btree_key = next_btree.as_ref().map(|(key, _)| *key);
+            let staged_key = if rev {
+                self.staged.last_key_value()
+            } else {
+                self.staged.first_key_value()
+            }
+            .map(|(key, _)| *key);
+
+            match (btree_key, staged_key) {
+                (None, None) => return None,
+                (Some(btree_key), Some(staged_key))
+                    if btree_key != staged_key && (btree_key > staged_key) == rev =>
+                {
+                    return next_btree.take()
+                }
+                (Some(_), None) => return next_btree.take(),
+                (btree_key, Some(staged_key)) => {
+                    // The staged change replaces the entry of the btree, or deletes it.
+                    if btree_key == Some(staged_key) {
+                        *next_btree = None;
+                    }
+                    let staged = if rev {
+                        self.staged.pop_last()
+                    } else {
+                        self.staged.pop_first()
+                    };
+                    // UNWRAP: checked to be non-empty above.
+                    if let (key, Some(value)) = staged.unwrap() {
+                        return Some((key, value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Iter {
+    type Item = (Key, Value);
+
+    /// Panics if the snapshot expired, see [`Store::pin`].
+    fn next(&mut self) -> Option<(Key, Value)> {
+        let entry = self.next_entry(false);
+        if self._pin.is_expired() {
+            panic!("{}", expired());
+        }
+        entry
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    /// Panics if the snapshot expired, see [`Store::pin`].
+    fn next_back(&mut self) -> Option<(Key, Value)> {
+        let entry = self.next_entry(true);
+        if self._pin.is_expired() {
+            panic!("{}", expired());
+        }
+        entry
     }
 }
 
+fn expired() -> crate::Error {
+    crate::Error::InvalidOperation(
+        "snapshot expired: its pages were reclaimed past the snapshot horizon".to_string(),
+    )
+}
+
 /// Data generated during update
 pub struct SyncData {
     pub bbn_index: Index,
@@ -233,6 +637,20 @@ pub struct SyncData {
     pub ln_bump: u32,
     pub bbn_freelist_pn: u32,
     pub bbn_bump: u32,
+    /// The leaf pages written during the sync.
+    pub ln_written_pages: Vec<PageNumber>,
+    /// The branch pages written during the sync.
+    pub bbn_written_pages: Vec<PageNumber>,
+}
+
+/// The number of overflow pages needed to store a value of the given size. 0 for values stored
+/// within a leaf.
+pub fn overflow_pages(value_size: usize) -> usize {
+    if value_size > leaf::node::MAX_LEAF_VALUE_SIZE {
+        leaf::overflow::total_needed_pages(value_size)
+    } else {
+        0
+    }
 }
 
 /// Creates the required files for the beatree.
@@ -251,3 +669,124 @@ pub fn create(db_dir: impl AsRef<Path>) -> anyhow::Result<()> {
     bbn_fd.sync_all()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{create, Codec, Key, Tree, TreeOptions, Value};
+    use crate::{
+        beatree::CommitWorkers,
+        io::{start_test_io_pool, PagePool},
+    };
+    use std::{collections::BTreeMap, fs::OpenOptions, sync::Arc};
+
+    fn key(i: u32) -> Key {
+        let mut key = [0; 32];
+        key[..4].copy_from_slice(&i.to_be_bytes());
+        key
+    }
+
+    #[test]
+    fn iter_merges_staged_changes_from_both_ends() {
+        let dir = tempfile::tempdir().unwrap();
+        create(dir.path()).unwrap();
+        let open = |name| {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(dir.path().join(name))
+                .unwrap();
+            Arc::new(file)
+        };
+        let page_pool = PagePool::new();
+        let io_pool = start_test_io_pool(2, page_pool.clone());
+        let workers = CommitWorkers {
+            max: 2,
+            adaptive: false,
+        };
+        let tree = Tree::open(
+            page_pool,
+            &io_pool,
+            0,
+            0,
+            1,
+            1,
+            &open("bbn"),
+            &open("ln"),
+            TreeOptions {
+                commit_workers: workers,
+                recovery_concurrency: 1,
+                verify_checksums: false,
+                snapshot_horizon: None,
+                hole_punch_run: None,
+                codec: Codec::default(),
+            },
+        )
+        .unwrap();
+
+        let mut model = BTreeMap::new();
+        let changes = (0..50_000)
+            .map(|i| {
+                (
+                    key(i),
+                    Some(Value::from(vec![i as u8; 1 + i as usize % 200])),
+                )
+            })
+            .collect::<Vec<_>>();
+        model.extend(changes.iter().map(|(k, v)| (*k, v.clone().unwrap())));
+        tree.commit(changes);
+        tree.finish_sync(tree.prepare_sync().unwrap().bbn_index);
+
+        // Spread over several branches.
+        assert!(tree
+            .shared
+            .read()
+            .bbn_index
+            .prev_key(key(50_000))
+            .is_some_and(|k| k > key(0)));
+
+        // Staged deletions, updates and insertions, including at both ends of the range.
+        let mut changes = Vec::new();
+        for i in (0..50_000).step_by(3).chain([1, 2, 49_997, 49_998, 49_999]) {
+            changes.push((key(i), None));
+            model.remove(&key(i));
+        }
+        for i in (1..50_000).step_by(5).chain(50_000..50_010) {
+            let value = Value::from(vec![0xFF; 7]);
+            changes.push((key(i), Some(value.clone())));
+            model.insert(key(i), value);
+        }
+        tree.commit(changes);
+
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.first_key(), model.keys().next().copied());
+        assert_eq!(snapshot.last_key(), model.keys().next_back().copied());
+        let expected = |start, end| {
+            model
+                .range(key(start)..=key(end))
+                .map(|(k, v)| (*k, v.clone()))
+                .collect::<Vec<_>>()
+        };
+        for (start, end) in [(0, 60_000), (2, 49_999), (100, 30_000), (5, 5)] {
+            let forward = snapshot.iter(key(start), key(end)).collect::<Vec<_>>();
+            assert_eq!(forward, expected(start, end));
+            let mut backward = snapshot
+                .iter(key(start), key(end))
+                .rev()
+                .collect::<Vec<_>>();
+            backward.reverse();
+            assert_eq!(backward, expected(start, end));
+
+            // Taking entries from both ends in turn meets in the middle.
+            let mut iter = snapshot.iter(key(start), key(end));
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+            loop {
+                let Some(entry) = iter.next() else { break };
+                front.push(entry);
+                let Some(entry) = iter.next_back() else { break };
+                back.push(entry);
+            }
+            front.extend(back.into_iter().rev());
+            assert_eq!(front, expected(start, end));
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Integrity checks of the b-tree.
+//!
+//! The branch nodes are walked in the order of the index, and the leaves in the order of the
+//! branch nodes. Every node must match its checksum and keep its keys within the bounds set by
+//! the separators. Every page of the node files must be either in use by exactly one node, free,
+//! or leaked. Leaked pages are counted but not reported as problems.
+
+use anyhow::Result;
+use std::{collections::HashSet, ops::Range};
+
+use super::get_key;
+use crate::{
+    beatree::{
+        allocator::{PageNumber, Store},
+        index::Index,
+        leaf::{
+            self,
+            node::{LeafNode, LEAF_NODE_BODY_SIZE},
+        },
+        Key,
+    },
+    checksum::{self, TRAILER_OFFSET},
+    io::{FatPage, PagePool},
+    store::IntegrityReport,
+};
+
+/// Check the b-tree as of the last sync, recording any problems in the report.
+///
+/// Must not be called while a sync is in progress. Fails only if I/O fails.
+pub fn check_integrity(
+    page_pool: &PagePool,
+    bbn_index: &Index,
+    leaf_store: &Store,
+    bbn_store: &Store,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let mut ln_pages = PageTracker::new("ln", leaf_store, report);
+    let mut bbn_pages = PageTracker::new("bbn", bbn_store, report);
+
+    let branches = bbn_index.branches().collect::<Vec<_>>();
+    for (i, branch) in branches.iter().enumerate() {
+        report.branches += 1;
+        let bbn_pn = branch.bbn_pn() as u64;
+        if !bbn_pages.use_page(bbn_pn, report) {
+            continue;
+        }
+        let Some(page) = read_page(page_pool, bbn_store, "bbn", bbn_pn, report)? else {
+            continue;
+        };
+        if page[..] != *branch.as_slice() {
+            report.page_issue("bbn", bbn_pn, "differs from the branch node in use");
+            continue;
+        }
+
+        let n = branch.n() as usize;
+        if n == 0 {
+            report.page_issue("bbn", bbn_pn, "empty branch node");
+            continue;
+        }
+        let separators = (0..n).map(|j| get_key(branch, j)).collect::<Vec<_>>();
+        if separators.windows(2).any(|w| w[0] >= w[1]) {
+            report.page_issue("bbn", bbn_pn, "separators out of order");
+            continue;
+        }
+        let branch_end = branches.get(i + 1).map(|next| get_key(next, 0));
+        if branch_end.is_some_and(|end| separators[n - 1] >= end) {
+            report.page_issue("bbn", bbn_pn, "separators overlap the next branch node");
+            continue;
+        }
+
+        for (j, separator) in separators.iter().enumerate() {
+            let end = separators.get(j + 1).copied().or(branch_end);
+            check_leaf(
+                page_pool,
+                leaf_store,
+                &mut ln_pages,
+                branch.node_pointer(j) as u64,
+                *separator,
+                end,
+                report,
+            )?;
+        }
+    }
+
+    report.leaked_ln_ranges = ln_pages.leaked();
+    report.leaked_bbn_ranges = bbn_pages.leaked();
+    report.leaked_ln_pages = report
+        .leaked_ln_ranges
+        .iter()
+        .map(|r| r.clone().count())
+        .sum();
+    report.leaked_bbn_pages = report
+        .leaked_bbn_ranges
+        .iter()
+        .map(|r| r.clone().count())
+        .sum();
+    Ok(())
+}
+
+/// Check the leaf stored at page `pn`, whose keys must lie within `start..end`.
+fn check_leaf(
+    page_pool: &PagePool,
+    leaf_store: &Store,
+    ln_pages: &mut PageTracker,
+    pn: u64,
+    start: Key,
+    end: Option<Key>,
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    report.leaves += 1;
+    if !ln_pages.use_page(pn, report) {
+        return Ok(());
+    }
+    let Some(page) = read_page(page_pool, leaf_store, "ln", pn, report)? else {
+        return Ok(());
+    };
+    let leaf = LeafNode { inner: page };
+
+    let n = leaf.n();
+    if leaf::node::body_size(n, 0) > LEAF_NODE_BODY_SIZE {
+        report.page_issue("ln", pn, format!("invalid number of cells: {n}"));
+        return Ok(());
+    }
+
+    let mut prev_key = None;
+    for i in 0..n {
+        let key = leaf.key(i);
+        if prev_key.is_some_and(|prev| prev >= key) {
+            report.page_issue("ln", pn, "keys out of order");
+            return Ok(());
+        }
+        if key < start || end.is_some_and(|end| key >= end) {
+            report.page_issue("ln", pn, "key outside of the range of the separators");
+            return Ok(());
+        }
+        prev_key = Some(key);
+
+        let (cell, is_overflow) = leaf.value(i);
+        if is_overflow {
+            check_overflow(page_pool, leaf_store, ln_pages, pn, cell, report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check the overflow pages of a value stored in the leaf with the given page number.
+fn check_overflow(
+    page_pool: &PagePool,
+    leaf_store: &Store,
+    ln_pages: &mut PageTracker,
+    leaf_pn: u64,
+    cell: &[u8],
+    report: &mut IntegrityReport,
+) -> Result<()> {
+    let bump = ln_pages.bump;
+    let mut pages = Vec::new();
+    let collected = leaf::overflow::pages(
+        cell,
+        |pn| {
+            anyhow::ensure!(
+                !pn.is_nil() && (pn.0 as u64) < bump,
+                "overflow page number {} out of range",
+                pn.0
+            );
+            let page = leaf_store.read_unchecked(page_pool, pn)?;
+            checksum::check(&page, TRAILER_OFFSET, "ln", pn.0 as u64)?;
+            Ok(page)
+        },
+        &mut pages,
+    );
+    if let Err(e) = collected {
+        // I/O errors fail the check, anything else is a problem with the value.
+        if e.downcast_ref::<std::io::Error>().is_some() {
+            return Err(e);
+        }
+        report.page_issue("ln", leaf_pn, format!("overflow value: {e}"));
+        return Ok(());
+    }
+
+    for pn in pages {
+        let pn = pn.0 as u64;
+        if ln_pages.use_page(pn, report) {
+            read_page(page_pool, leaf_store, "ln", pn, report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a page of a node file, recording a problem and returning `None` if it doesn't match its
+/// checksum.
+fn read_page(
+    page_pool: &PagePool,
+    store: &Store,
+    file: &'static str,
+    pn: u64,
+    report: &mut IntegrityReport,
+) -> Result<Option<FatPage>> {
+    let page = store.read_unchecked(page_pool, PageNumber(pn as u32))?;
+    if checksum::check(&page, TRAILER_OFFSET, file, pn).is_err() {
+        report.page_issue(file, pn, "checksum mismatch");
+        return Ok(None);
+    }
+    Ok(Some(page))
+}
+
+/// Tracks the use of the pages of a node file.
+struct PageTracker {
+    file: &'static str,
+    bump: u64,
+    free: HashSet<u64>,
+    used: HashSet<u64>,
+}
+
+impl PageTracker {
+    fn new(file: &'static str, store: &Store, report: &mut IntegrityReport) -> Self {
+        let (bump, free_pages) = store.free_pages();
+        let bump = bump.0 as u64;
+        let mut free = HashSet::with_capacity(free_pages.len());
+        for pn in free_pages {
+            let pn = pn.0 as u64;
+            if pn == 0 || pn >= bump {
+                report.page_issue(file, pn, "free page out of range");
+            } else if !free.insert(pn) {
+                report.page_issue(file, pn, "free page tracked more than once");
+            }
+        }
+        PageTracker {
+            file,
+            bump,
+            free,
+            used: HashSet::new(),
+        }
+    }
+
+    /// Record the use of a page by a node. Returns false and records a problem if the page can't
+    /// be in use.
+    fn use_page(&mut self, pn: u64, report: &mut IntegrityReport) -> bool {
+        if pn == 0 || pn >= self.bump {
+            report.page_issue(self.file, pn, "page number out of range");
+            false
+        } else if self.free.contains(&pn) {
+            report.page_issue(self.file, pn, "page in use is free");
+            false
+        } else if !self.used.insert(pn) {
+            report.page_issue(self.file, pn, "page in use more than once");
+            false
+        } else {
+            true
+        }
+    }
+
+    /// The ranges of consecutive pages which are neither in use nor free, in ascending order.
+    fn leaked(&self) -> Vec<Range<u64>> {
+        let mut leaked: Vec<Range<u64>> = Vec::new();
+        for pn in (1..self.bump).filter(|pn| !self.used.contains(pn) && !self.free.contains(pn)) {
+            match leaked.last_mut() {
+                Some(range) if range.end == pn => range.end += 1,
+                _ => leaked.push(pn..pn + 1),
+            }
+        }
+        leaked
+    }
+}
@@ -3,48 +3,425 @@
 use anyhow::Result;
 use bitvec::prelude::*;
 
-use std::cmp::Ordering;
+use std::{cmp::Ordering, sync::Arc};
 
 use super::{
     allocator::{PageNumber, StoreReader},
     branch::BranchNode,
+    compression::COMPRESSED_HEADER_SIZE,
     index::Index,
     leaf::{self, node::LeafNode},
-    Key,
+    Key, Value, ValueRef,
 };
 
 pub(crate) mod bit_ops;
+mod integrity;
 mod reconstruction;
+mod repair;
 mod update;
 
+pub use integrity::check_integrity;
 pub use reconstruction::reconstruct;
-pub use update::update;
+pub use repair::{repair, TreeMeta};
+pub use update::{update, CommitWorkers};
 
 /// Lookup a key in the btree.
-pub fn lookup(key: Key, bbn_index: &Index, leaf_store: &StoreReader) -> Result<Option<Vec<u8>>> {
-    let branch = match bbn_index.lookup(key) {
+///
+/// Values stored inline in the leaf are returned without copying them out of the leaf page, unless
+/// they are compressed.
+pub fn lookup(key: Key, bbn_index: &Index, leaf_store: &StoreReader) -> Result<Option<ValueRef>> {
+    let leaf = match search_leaf(key, bbn_index, leaf_store)? {
         None => return Ok(None),
-        Some((_, branch)) => branch,
+        Some(leaf) => leaf,
     };
 
-    let leaf_pn = match search_branch(&branch, key.clone()) {
+    let codec = leaf_store.codec();
+    let maybe_value = match leaf.get_range(&key) {
+        None => None,
+        Some((range, true)) => {
+            let stored = leaf::overflow::read(&leaf.inner[range], leaf_store)?;
+            Some(codec.decode_owned(stored)?)
+        }
+        Some((range, false)) => Some(codec.decode_in_page(leaf.inner, range)?),
+    };
+
+    Ok(maybe_value)
+}
+
+/// Check whether a key is present in the btree.
+///
+/// Unlike [`lookup`], this never reads overflow pages.
+pub fn contains(key: Key, bbn_index: &Index, leaf_store: &StoreReader) -> Result<bool> {
+    let leaf = match search_leaf(key, bbn_index, leaf_store)? {
+        None => return Ok(false),
+        Some(leaf) => leaf,
+    };
+
+    Ok(leaf.get(&key).is_some())
+}
+
+/// Get the size of the value stored under a key in the btree.
+///
+/// Unlike [`lookup`], this never reads overflow pages: the size of overflow values is taken from
+/// the overflow cell. The exception are overflow values of a compressed store, whose size is read
+/// from the frame at the start of the first overflow page.
+pub fn value_size(key: Key, bbn_index: &Index, leaf_store: &StoreReader) -> Result<Option<usize>> {
+    let leaf = match search_leaf(key, bbn_index, leaf_store)? {
         None => return Ok(None),
-        Some((_, leaf_pn)) => leaf_pn,
+        Some(leaf) => leaf,
     };
 
-    let leaf = LeafNode {
-        inner: leaf_store.query(leaf_pn),
+    let codec = leaf_store.codec();
+    let maybe_size = match leaf.get(&key) {
+        None => None,
+        Some((v, true)) if codec.is_none() => Some(leaf::overflow::decode_cell(v).0),
+        Some((v, true)) => {
+            let header = leaf::overflow::read_prefix(v, COMPRESSED_HEADER_SIZE, leaf_store)?;
+            let stored_len = leaf::overflow::decode_cell(v).0;
+            Some(codec.value_size_from_header(&header, stored_len))
+        }
+        Some((v, false)) => Some(codec.value_size(v)),
     };
 
-    let maybe_value = leaf.get(&key).map(|(v, is_overflow)| {
-        if is_overflow {
-            leaf::overflow::read(v, leaf_store)
+    Ok(maybe_size)
+}
+
+/// The number of leaves sampled by [`estimate_size`].
+const ESTIMATE_SAMPLED_LEAVES: usize = 64;
+
+/// An estimate of the amount of data stored in the btree. See [`estimate_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeEstimate {
+    /// The number of keys.
+    pub keys: u64,
+    /// The total size of the keys and values, in bytes.
+    pub bytes: u64,
+}
+
+/// Estimate the number of keys and the size of the data stored in the btree.
+///
+/// The number of leaves is known from the index, so only a sample of evenly spread leaves is
+/// read, and the rest are assumed to hold as much on average. With few enough leaves, all of them
+/// are read and the result is exact. The size of the overflow values of a compressed store is taken
+/// to be their compressed size.
+pub fn estimate_size(bbn_index: &Index, leaf_store: &StoreReader) -> SizeEstimate {
+    let total_leaves = bbn_index
+        .branches()
+        .map(|branch| branch.n() as usize)
+        .sum::<usize>();
+    if total_leaves == 0 {
+        return SizeEstimate { keys: 0, bytes: 0 };
+    }
+
+    let samples = std::cmp::min(total_leaves, ESTIMATE_SAMPLED_LEAVES);
+    let mut sampled = SizeEstimate { keys: 0, bytes: 0 };
+    let mut next_sample = 0;
+    let mut first_leaf = 0;
+    for branch in bbn_index.branches() {
+        let n = branch.n() as usize;
+        // The index of the k-th sampled leaf is `k * total_leaves / samples`.
+        loop {
+            let leaf_index = next_sample * total_leaves / samples;
+            if next_sample == samples || leaf_index >= first_leaf + n {
+                break;
+            }
+            let leaf = LeafNode {
+                // UNWRAP: the estimate is only a hint and checksum failures surface on reads.
+                inner: leaf_store
+                    .query(branch.node_pointer(leaf_index - first_leaf).into())
+                    .unwrap(),
+            };
+            sampled.keys += leaf.n() as u64;
+            for i in 0..leaf.n() {
+                let (value, is_overflow) = leaf.value(i);
+                let value_size = if is_overflow {
+                    leaf::overflow::decode_cell(value).0
+                } else {
+                    leaf_store.codec().value_size(value)
+                };
+                sampled.bytes += (32 + value_size) as u64;
+            }
+            next_sample += 1;
+        }
+        first_leaf += n;
+    }
+
+    let scale = |sampled: u64| (sampled as u128 * total_leaves as u128 / samples as u128) as u64;
+    SizeEstimate {
+        keys: scale(sampled.keys),
+        bytes: scale(sampled.bytes),
+    }
+}
+
+/// A cursor over the entries of the btree within the inclusive range `start..=end`, in order.
+///
+/// Entries may be taken from either end of the range: the cursors moving from the start and
+/// from the end stop where they meet.
+///
+/// The cursor works on its own copy of the index, so it keeps seeing the btree as of the time it
+/// was created, as long as the leaf pages it reads are not reused.
+pub struct RangeIter {
+    start: Key,
+    end: Key,
+    bbn_index: Index,
+    leaf_store: StoreReader,
+    /// The cursors moving from the start and from the end of the range. Created on first use.
+    front: Option<Cursor>,
+    back: Option<Cursor>,
+    /// The last keys taken from the front and from the back.
+    front_last: Option<Key>,
+    back_last: Option<Key>,
+}
+
+impl RangeIter {
+    pub fn new(start: Key, end: Key, bbn_index: Index, leaf_store: StoreReader) -> Self {
+        RangeIter {
+            start,
+            end,
+            bbn_index,
+            leaf_store,
+            front: None,
+            back: None,
+            front_last: None,
+            back_last: None,
+        }
+    }
+
+    /// Advance to the next key, without loading its value.
+    pub fn next_key(&mut self) -> Option<Key> {
+        let i = self.advance(false)?;
+        Some(self.current_leaf(false).key(i))
+    }
+
+    /// Advance to the next key from the back, without loading its value.
+    pub fn next_key_back(&mut self) -> Option<Key> {
+        let i = self.advance(true)?;
+        Some(self.current_leaf(true).key(i))
+    }
+
+    /// Advance to the next entry from the front or, if `rev`, from the back. Returns its index
+    /// within the current leaf of that cursor.
+    fn advance(&mut self, rev: bool) -> Option<usize> {
+        let RangeIter {
+            start,
+            end,
+            ref bbn_index,
+            ref leaf_store,
+            ref mut front,
+            ref mut back,
+            ref mut front_last,
+            ref mut back_last,
+        } = *self;
+
+        if rev {
+            let cursor = back.get_or_insert_with(|| Cursor::backward(end, bbn_index));
+            let i = cursor.advance(
+                bbn_index,
+                leaf_store,
+                |key| key > end,
+                |key| key < start || front_last.is_some_and(|last| key <= last),
+            )?;
+            *back_last = Some(cursor.key(i));
+            Some(i)
         } else {
-            v.to_vec()
+            let cursor = front.get_or_insert_with(|| Cursor::forward(start, bbn_index));
+            let i = cursor.advance(
+                bbn_index,
+                leaf_store,
+                |key| key < start,
+                |key| key > end || back_last.is_some_and(|last| key >= last),
+            )?;
+            *front_last = Some(cursor.key(i));
+            Some(i)
         }
-    });
+    }
 
-    Ok(maybe_value)
+    fn current_leaf(&self, rev: bool) -> &LeafNode {
+        let cursor = if rev { &self.back } else { &self.front };
+        // UNWRAP: only called after `advance` returned an entry of the current leaf.
+        &cursor.as_ref().unwrap().leaf.as_ref().unwrap().0
+    }
+
+    fn entry(&self, rev: bool, i: usize) -> (Key, Value) {
+        let leaf = self.current_leaf(rev);
+        let key = leaf.key(i);
+        let (cell, is_overflow) = leaf.value(i);
+        let codec = self.leaf_store.codec();
+        // UNWRAP: iterators have no way to report errors.
+        let value = if is_overflow {
+            let stored = leaf::overflow::read(cell, &self.leaf_store).unwrap();
+            codec.decode_owned(stored).unwrap().into_value()
+        } else {
+            codec.decode(cell).unwrap().into()
+        };
+        (key, value)
+    }
+}
+
+impl Iterator for RangeIter {
+    type Item = (Key, Value);
+
+    fn next(&mut self) -> Option<(Key, Value)> {
+        let i = self.advance(false)?;
+        Some(self.entry(false, i))
+    }
+}
+
+impl DoubleEndedIterator for RangeIter {
+    fn next_back(&mut self) -> Option<(Key, Value)> {
+        let i = self.advance(true)?;
+        Some(self.entry(true, i))
+    }
+}
+
+/// A position within the leaves of the btree, moving either forwards or backwards.
+struct Cursor {
+    /// Whether the cursor moves backwards.
+    rev: bool,
+    /// The current branch along with its separator. `None` once the cursor is exhausted.
+    branch: Option<(Key, Arc<BranchNode>)>,
+    /// The index of the next leaf to load from the current branch. Moving backwards, this is the
+    /// index following it.
+    next_leaf: usize,
+    /// The current leaf along with the index of the next entry within it. Moving backwards, this
+    /// is the index following it.
+    leaf: Option<(LeafNode, usize)>,
+}
+
+impl Cursor {
+    /// A cursor moving forwards from the given key.
+    fn forward(start: Key, bbn_index: &Index) -> Self {
+        let branch = match bbn_index.lookup(start) {
+            Some(branch) => Some(branch),
+            None => bbn_index
+                .next_key(start)
+                .and_then(|separator| bbn_index.lookup(separator)),
+        };
+        let next_leaf = branch
+            .as_ref()
+            .and_then(|(_, branch)| search_branch(branch, start))
+            .map_or(0, |(i, _)| i);
+        Cursor {
+            rev: false,
+            branch,
+            next_leaf,
+            leaf: None,
+        }
+    }
+
+    /// A cursor moving backwards from the given key.
+    fn backward(end: Key, bbn_index: &Index) -> Self {
+        let branch = bbn_index.lookup(end);
+        let next_leaf = branch
+            .as_ref()
+            .and_then(|(_, branch)| search_branch(branch, end))
+            .map_or(0, |(i, _)| i + 1);
+        Cursor {
+            rev: true,
+            branch,
+            next_leaf,
+            leaf: None,
+        }
+    }
+
+    /// Advance to the next entry, returning its index within the current leaf.
+    ///
+    /// Entries for which `skip` holds are passed over. The cursor is exhausted at the first key
+    /// for which `past` holds, which must then hold for all the keys beyond it.
+    fn advance(
+        &mut self,
+        bbn_index: &Index,
+        leaf_store: &StoreReader,
+        skip: impl Fn(Key) -> bool,
+        past: impl Fn(Key) -> bool,
+    ) -> Option<usize> {
+        loop {
+            if let Some((leaf, next)) = &mut self.leaf {
+                let i = if self.rev {
+                    next.checked_sub(1)
+                } else {
+                    Some(*next).filter(|next| *next < leaf.n())
+                };
+                if let Some(i) = i {
+                    *next = if self.rev { i } else { i + 1 };
+                    let key = leaf.key(i);
+                    if past(key) {
+                        self.branch = None;
+                        self.leaf = None;
+                        return None;
+                    }
+                    if !skip(key) {
+                        return Some(i);
+                    }
+                    continue;
+                }
+            }
+
+            let (separator, branch) = self.branch.as_ref()?;
+            let n = branch.n() as usize;
+            // Moving forwards, the keys of the next leaf are past if its separator is. Moving
+            // backwards, the keys of the leaves before that separator are past if it is.
+            if self.next_leaf < n && past(get_key(branch, self.next_leaf)) {
+                self.branch = None;
+                self.leaf = None;
+                return None;
+            }
+
+            let leaf_index = if self.rev {
+                self.next_leaf.checked_sub(1)
+            } else {
+                Some(self.next_leaf).filter(|next| *next < n)
+            };
+            if let Some(leaf_index) = leaf_index {
+                let leaf = LeafNode {
+                    // UNWRAP: iterators have no way to report errors.
+                    inner: leaf_store
+                        .query(branch.node_pointer(leaf_index).into())
+                        .unwrap(),
+                };
+                let next = if self.rev { leaf.n() } else { 0 };
+                self.leaf = Some((leaf, next));
+                self.next_leaf = if self.rev { leaf_index } else { leaf_index + 1 };
+            } else {
+                self.branch = if self.rev {
+                    // The keys of the previous branches are before its separator.
+                    bbn_index.prev_key(*separator).filter(|_| !past(*separator))
+                } else {
+                    bbn_index
+                        .next_key(*separator)
+                        .filter(|separator| !past(*separator))
+                }
+                .and_then(|separator| bbn_index.lookup(separator));
+                self.next_leaf = match &self.branch {
+                    Some((_, branch)) if self.rev => branch.n() as usize,
+                    _ => 0,
+                };
+                self.leaf = None;
+            }
+        }
+    }
+
+    fn key(&self, i: usize) -> Key {
+        // UNWRAP: only called after `advance` returned an entry of the current leaf.
+        self.leaf.as_ref().unwrap().0.key(i)
+    }
+}
+
+/// Find the leaf node which may contain the given key, loading it from the store.
+fn search_leaf(key: Key, bbn_index: &Index, leaf_store: &StoreReader) -> Result<Option<LeafNode>> {
+    let branch = match bbn_index.lookup(key) {
+        None => return Ok(None),
+        Some((_, branch)) => branch,
+    };
+
+    let leaf_pn = match search_branch(&branch, key.clone()) {
+        None => return Ok(None),
+        Some((_, leaf_pn)) => leaf_pn,
+    };
+
+    Ok(Some(LeafNode {
+        inner: leaf_store.query(leaf_pn)?,
+    }))
 }
 
 /// Binary search a branch node for the child node containing the key. This returns the last child
@@ -99,11 +476,11 @@ pub mod benches {
     use crate::{
         beatree::{
             benches::get_keys,
-            branch::{node::BranchNodeBuilder, BranchNode},
+            branch::{node::BranchNodeBuilder, BranchNode, BRANCH_NODE_BODY_SIZE},
             ops::bit_ops::separator_len,
             Key,
         },
-        io::{PagePool, PAGE_SIZE},
+        io::PagePool,
     };
     use criterion::{BenchmarkId, Criterion};
     use rand::Rng;
@@ -118,7 +495,7 @@ pub mod benches {
             //
             // body_size = (prefix_len_bits + (separator_len_bits * n) + 7)/8 + 4 * n
             // n = (8 * body_size - prefix_len_bits) / (separator_len_bits + 8*4)
-            let body_size_target = PAGE_SIZE - 8;
+            let body_size_target = BRANCH_NODE_BODY_SIZE;
             let prefix_len_bits = prefix_len_bytes * 8;
             let separator_len_bits = (32 - prefix_len_bytes) * 8;
             let n = (8 * body_size_target - prefix_len_bits) / (separator_len_bits + 8 * 4);
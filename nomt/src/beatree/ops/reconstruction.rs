@@ -16,19 +16,78 @@ use crate::beatree::{
     branch::{BranchNode, BranchNodeView, BRANCH_NODE_SIZE},
     index::Index,
 };
-use crate::io::PagePool;
+use crate::{
+    checksum::{self, TRAILER_OFFSET},
+    io::PagePool,
+};
 
 /// Reconstruct the upper branch nodes of the btree from the bottom branch nodes and the leaf nodes.
 /// This places all branches into the BNP and returns an index into all BBNs.
+///
+/// The BBN file is split into `concurrency` contiguous ranges which are scanned in parallel. If
+/// `verify_checksums` is set, the checksums of the BBNs are verified.
 pub fn reconstruct(
-    bn_fd: File,
+    bn_fd: &File,
     page_pool: &PagePool,
     bbn_freelist_tracked: &BTreeSet<PageNumber>,
     bump: PageNumber,
+    concurrency: usize,
+    verify_checksums: bool,
 ) -> Result<Index> {
+    let concurrency = concurrency.max(1) as u32;
+    let chunk_len = bump.0.div_ceil(concurrency);
+
+    let chunks = std::thread::scope(|scope| {
+        let handles = (0..concurrency)
+            .map(|i| {
+                let start = std::cmp::min(i * chunk_len, bump.0);
+                let end = std::cmp::min(start + chunk_len, bump.0);
+                scope.spawn(move || {
+                    read_branches(
+                        bn_fd,
+                        page_pool,
+                        bbn_freelist_tracked,
+                        start,
+                        end,
+                        verify_checksums,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
     let mut index = Index::default();
+    for chunk in chunks {
+        for (separator, branch) in chunk? {
+            if let Some(_) = index.insert(separator, Arc::new(branch)) {
+                bail!(
+                    "2 branch nodes with same separator, separator={:?}",
+                    separator
+                );
+            }
+        }
+    }
+    Ok(index)
+}
 
-    let mut chunker = SeqFileReader::new(bn_fd, bump.0)?;
+/// Read all live BBNs with page numbers in `start..end`, returning them along with their
+/// separators.
+fn read_branches(
+    bn_fd: &File,
+    page_pool: &PagePool,
+    bbn_freelist_tracked: &BTreeSet<PageNumber>,
+    start: u32,
+    end: u32,
+    verify_checksums: bool,
+) -> Result<Vec<([u8; 32], BranchNode)>> {
+    let mut branches = Vec::new();
+
+    let mut chunker = SeqFileReader::new(bn_fd, start, end)?;
     while let Some((pn, node)) = chunker.next()? {
         let view = BranchNodeView::from_slice(node);
 
@@ -41,6 +100,10 @@ pub fn reconstruct(
             continue;
         }
 
+        if verify_checksums {
+            checksum::check(node, TRAILER_OFFSET, "bbn", pn as u64)?;
+        }
+
         ensure!(
             view.bbn_pn() == pn,
             "pn mismatch {} != {}",
@@ -60,17 +123,12 @@ pub fn reconstruct(
             separator[prefix.len()..prefix.len() + first.len()].copy_from_bitslice(first);
         }
 
-        if let Some(_) = index.insert(separator, Arc::new(branch)) {
-            bail!(
-                "2 branch nodes with same separator, separator={:?}",
-                separator
-            );
-        }
+        branches.push((separator, branch));
     }
-    Ok(index)
+    Ok(branches)
 }
 
-/// An utility to read sequentially from a file.
+/// An utility to read sequentially from a range of pages of a file.
 ///
 /// This is backed by an mmap of the file. The kernel is instructed that the contents of the file
 /// should be read sequentially. This will make the kernel to read ahead the file sequentially.
@@ -82,7 +140,7 @@ struct SeqFileReader {
 }
 
 impl SeqFileReader {
-    fn new(bbn_fd: File, bump: u32) -> Result<Self> {
+    fn new(bbn_fd: &File, start: u32, bump: u32) -> Result<Self> {
         let len = bbn_fd.metadata()?.len();
         ensure!(
             len % BRANCH_NODE_SIZE as u64 == 0,
@@ -93,7 +151,7 @@ impl SeqFileReader {
             "file is too small for BBN store"
         );
 
-        let pn = 0u32;
+        let pn = start;
         let ptr = unsafe {
             // MAP_PRIVATE
             //
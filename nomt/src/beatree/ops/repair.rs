@@ -0,0 +1,265 @@
+//! Repair of the b-tree metadata from the node files.
+//!
+//! Unlike [`super::reconstruct`], this trusts neither the free-lists nor any single page:
+//!   1. Salvage what can be read of the free-lists. Only the branch node free-list is used, to
+//!      skip stale branch nodes.
+//!   2. Scan the whole branch node file for bottom-level branch nodes matching their checksums and
+//!      order them by their first separator.
+//!   3. Keep every branch node whose leaves and overflow pages are intact and which doesn't
+//!      overlap the branch nodes kept before it. Drop everything else.
+//!   4. Rebuild both free-lists from the pages not in use.
+//!
+//! Stale branch nodes can't be told apart from live ones once the branch node free-list is lost,
+//! so in that case a stale branch node may be kept in place of a live one covering the same keys.
+
+use anyhow::{bail, ensure, Result};
+use std::{collections::BTreeSet, fs::File, os::unix::fs::FileExt as _};
+
+use super::get_key;
+use crate::{
+    beatree::{
+        allocator::{self, PageNumber},
+        branch::BranchNode,
+        leaf::{
+            self,
+            node::{LeafNode, LEAF_NODE_BODY_SIZE},
+        },
+        Key,
+    },
+    checksum::{self, TRAILER_OFFSET},
+    io::{self, FatPage, PagePool, PAGE_SIZE},
+    store::RepairReport,
+};
+
+/// The b-tree metadata recorded in the meta file, which is read and rebuilt by [`repair`].
+pub struct TreeMeta {
+    pub ln_freelist_pn: u32,
+    pub ln_bump: u32,
+    pub bbn_freelist_pn: u32,
+    pub bbn_bump: u32,
+}
+
+/// Rebuild the free-lists of the node files from the branch nodes and leaves which are intact,
+/// recording the dropped pages in the report. The free-list pages are written past the bumps, so
+/// no page which was in use is overwritten.
+///
+/// The node files are synced, but the returned metadata must be written to the meta file for the
+/// repair to take effect. Fails only if I/O fails.
+pub fn repair(
+    page_pool: &PagePool,
+    ln_file: &File,
+    bbn_file: &File,
+    meta: TreeMeta,
+    report: &mut RepairReport,
+) -> Result<TreeMeta> {
+    let TreeMeta {
+        ln_freelist_pn,
+        ln_bump,
+        bbn_freelist_pn,
+        bbn_bump,
+    } = meta;
+    let ln_pages = page_count(ln_file)?;
+    let bbn_pages = page_count(bbn_file)?;
+
+    // The leaf free-list is rebuilt from the pages in use, so damage to it is only reported.
+    let (_, stopped) = allocator::salvage_free_list(
+        page_pool,
+        ln_file,
+        "ln",
+        PageNumber(ln_freelist_pn),
+        ln_pages,
+    )?;
+    if let Some((pn, reason)) = stopped {
+        report.drop_page("ln", pn.0 as u64, reason);
+    }
+    let (bbn_free, stopped) = allocator::salvage_free_list(
+        page_pool,
+        bbn_file,
+        "bbn",
+        PageNumber(bbn_freelist_pn),
+        bbn_pages,
+    )?;
+    if let Some((pn, reason)) = stopped {
+        report.drop_page("bbn", pn.0 as u64, reason);
+    }
+
+    let mut candidates = Vec::new();
+    for pn in 1..bbn_pages {
+        if bbn_free.contains(&PageNumber(pn)) {
+            continue;
+        }
+        let page = io::read_page(page_pool, bbn_file, pn as u64)?;
+        if page.iter().all(|b| *b == 0) {
+            // never written.
+            continue;
+        }
+        if checksum::check(&page, TRAILER_OFFSET, "bbn", pn as u64).is_err() {
+            report.drop_page("bbn", pn as u64, "checksum mismatch");
+            continue;
+        }
+
+        let mut branch = BranchNode::new_in(page_pool);
+        branch.as_mut_slice().copy_from_slice(&page);
+        if branch.bbn_pn() != pn {
+            report.drop_page("bbn", pn as u64, "not a branch node");
+            continue;
+        }
+        let n = branch.n() as usize;
+        if n == 0 {
+            report.drop_page("bbn", pn as u64, "empty branch node");
+            continue;
+        }
+        let separators = (0..n).map(|j| get_key(&branch, j)).collect::<Vec<_>>();
+        if separators.windows(2).any(|w| w[0] >= w[1]) {
+            report.drop_page("bbn", pn as u64, "separators out of order");
+            continue;
+        }
+        candidates.push((separators, branch));
+    }
+    candidates.sort_by(|a, b| a.0[0].cmp(&b.0[0]));
+
+    let mut ln_used = BTreeSet::new();
+    let mut bbn_used = BTreeSet::new();
+    // the greatest key covered by the branch nodes kept so far.
+    let mut covered_to: Option<Key> = None;
+    for (separators, branch) in candidates {
+        let pn = branch.bbn_pn() as u64;
+        if covered_to.is_some_and(|end| separators[0] <= end) {
+            report.drop_page("bbn", pn, "overlaps another branch node");
+            continue;
+        }
+        match check_branch(page_pool, ln_file, ln_pages, &ln_used, &branch, &separators) {
+            Ok((pages, last_key)) => {
+                ln_used.extend(pages);
+                bbn_used.insert(pn as u32);
+                covered_to = Some(last_key);
+                report.branches += 1;
+                report.leaves += separators.len();
+            }
+            // I/O errors fail the repair, anything else is a problem with the branch node.
+            Err(e) if e.downcast_ref::<std::io::Error>().is_some() => return Err(e),
+            Err(e) => report.drop_page("bbn", pn, e.to_string()),
+        }
+    }
+
+    let (ln_freelist_pn, ln_bump, free) =
+        rebuild_free_list(page_pool, ln_file, ln_pages, ln_bump, &ln_used)?;
+    report.free_ln_pages = free;
+    let (bbn_freelist_pn, bbn_bump, free) =
+        rebuild_free_list(page_pool, bbn_file, bbn_pages, bbn_bump, &bbn_used)?;
+    report.free_bbn_pages = free;
+
+    Ok(TreeMeta {
+        ln_freelist_pn,
+        ln_bump,
+        bbn_freelist_pn,
+        bbn_bump,
+    })
+}
+
+/// Check the leaves of a branch node and their overflow pages, none of which may be in `used`.
+///
+/// Returns the pages of the leaf node file used by the branch node along with the greatest key
+/// it covers.
+fn check_branch(
+    page_pool: &PagePool,
+    ln_file: &File,
+    ln_pages: u32,
+    used: &BTreeSet<u32>,
+    branch: &BranchNode,
+    separators: &[Key],
+) -> Result<(Vec<u32>, Key)> {
+    let read = |pn: PageNumber| -> Result<FatPage> {
+        ensure!(
+            !pn.is_nil() && pn.0 < ln_pages,
+            "leaf page {} out of range",
+            pn.0
+        );
+        let page = io::read_page(page_pool, ln_file, pn.0 as u64)?;
+        checksum::check(&page, TRAILER_OFFSET, "ln", pn.0 as u64)?;
+        Ok(page)
+    };
+
+    let mut pages = Vec::new();
+    let mut last_key = separators[separators.len() - 1];
+    for (j, separator) in separators.iter().enumerate() {
+        let leaf_pn = PageNumber(branch.node_pointer(j));
+        let leaf = LeafNode {
+            inner: read(leaf_pn)?,
+        };
+        pages.push(leaf_pn.0);
+
+        let n = leaf.n();
+        ensure!(
+            leaf::node::body_size(n, 0) <= LEAF_NODE_BODY_SIZE,
+            "leaf page {}: invalid number of cells: {n}",
+            leaf_pn.0
+        );
+        let end = separators.get(j + 1);
+        for i in 0..n {
+            let key = leaf.key(i);
+            ensure!(
+                i == 0 || leaf.key(i - 1) < key,
+                "leaf page {}: keys out of order",
+                leaf_pn.0
+            );
+            ensure!(
+                key >= *separator && end.is_none_or(|end| key < *end),
+                "leaf page {}: key outside of the range of the separators",
+                leaf_pn.0
+            );
+            last_key = last_key.max(key);
+
+            let (cell, is_overflow) = leaf.value(i);
+            if is_overflow {
+                let mut overflow_pages = Vec::new();
+                leaf::overflow::pages(cell, read, &mut overflow_pages)?;
+                pages.extend(overflow_pages.into_iter().map(|pn| pn.0));
+            }
+        }
+    }
+
+    let mut seen = BTreeSet::new();
+    for &pn in &pages {
+        if used.contains(&pn) || !seen.insert(pn) {
+            bail!("leaf page {pn} in use more than once");
+        }
+    }
+    Ok((pages, last_key))
+}
+
+/// Write a new free-list tracking the pages below the bump which are not in use.
+///
+/// The bump is the one recorded in the meta file, clamped to the size of the file and raised
+/// past the pages in use. Returns the head of the free-list, the new bump and the number of
+/// free pages.
+fn rebuild_free_list(
+    page_pool: &PagePool,
+    file: &File,
+    page_count: u32,
+    bump: u32,
+    used: &BTreeSet<u32>,
+) -> Result<(u32, u32, usize)> {
+    let bump = bump
+        .min(page_count)
+        .max(used.last().map_or(1, |pn| pn + 1))
+        .max(1);
+    let free = (1..bump)
+        .filter(|pn| !used.contains(pn))
+        .map(PageNumber)
+        .collect::<Vec<_>>();
+    let free_pages = free.len();
+
+    let mut bump = PageNumber(bump);
+    let (head, pages) = allocator::build_free_list(page_pool, free, &mut bump);
+    for (pn, page) in pages {
+        file.write_all_at(&page, pn.0 as u64 * PAGE_SIZE as u64)?;
+    }
+    file.sync_all()?;
+
+    Ok((head.map_or(0, |pn| pn.0), bump.0, free_pages))
+}
+
+fn page_count(file: &File) -> Result<u32> {
+    Ok((file.metadata()?.len() / PAGE_SIZE as u64) as u32)
+}
@@ -9,6 +9,7 @@ use crate::beatree::{
     Key,
 };
 
+use crate::checksum::{self, TRAILER_OFFSET};
 use crate::io::{IoCommand, IoHandle, IoKind, PagePool, PAGE_SIZE};
 
 use super::branch_updater::{BaseBranch, BranchUpdater, DigestResult as BranchDigestResult};
@@ -35,6 +36,9 @@ pub struct BranchStageOutput {
     pub submitted_io: usize,
     /// Data which should be dropped after all submitted I/Os have concluded.
     pub post_io_drop: PostIoDrop,
+    /// The first error the stage ran into, e.g. failing to allocate a page. The output is
+    /// incomplete then, but the submitted I/Os still have to be awaited.
+    pub error: Option<anyhow::Error>,
 }
 
 /// Change the btree's branch nodes in the specified way.
@@ -46,9 +50,9 @@ pub fn run(
     changeset: Vec<(Key, Option<PageNumber>)>,
     thread_pool: ThreadPool,
     num_workers: usize,
-) -> anyhow::Result<BranchStageOutput> {
+) -> BranchStageOutput {
     if changeset.is_empty() {
-        return Ok(BranchStageOutput::default());
+        return BranchStageOutput::default();
     }
 
     assert!(num_workers >= 1);
@@ -88,13 +92,19 @@ pub fn run(
 
     let mut output = BranchStageOutput::default();
 
+    let mut unsent_writes = 0;
     for _ in 0..num_workers {
         // UNWRAP: results are always sent unless worker panics.
-        let worker_output = worker_result_rx.recv().unwrap();
+        let mut worker_output = worker_result_rx.recv().unwrap();
+        unsent_writes += worker_output.unsent_writes;
+        if let Some(e) = worker_output.error.take() {
+            output.error.get_or_insert(e);
+        }
         apply_bbn_changes(bbn_index, &mut output, worker_output);
     }
+    output.submitted_io -= unsent_writes;
 
-    Ok(output)
+    output
 }
 
 fn apply_bbn_changes(
@@ -275,6 +285,8 @@ fn reset_branch_base_fresh(
 
 struct BranchWorkerOutput {
     branches_tracker: BranchesTracker,
+    error: Option<anyhow::Error>,
+    unsent_writes: usize,
 }
 
 fn run_worker(
@@ -294,6 +306,8 @@ fn run_worker(
         bbn_writer,
         branches_tracker: BranchesTracker::new(),
         io_handle,
+        error: None,
+        unsent_writes: 0,
     };
 
     // point branch updater at first branch.
@@ -400,6 +414,8 @@ fn run_worker(
 
     BranchWorkerOutput {
         branches_tracker: new_branch_state.branches_tracker,
+        error: new_branch_state.error,
+        unsent_writes: new_branch_state.unsent_writes,
     }
 }
 
@@ -407,19 +423,33 @@ struct NewBranchHandler {
     bbn_writer: SyncAllocator,
     branches_tracker: BranchesTracker,
     io_handle: IoHandle,
+    /// The first allocation failure, which fails the update once the stage is over.
+    error: Option<anyhow::Error>,
+    /// The number of tracked branches which were not written because their allocation failed.
+    unsent_writes: usize,
 }
 
 impl super::branch_updater::HandleNewBranch for NewBranchHandler {
     fn handle_new_branch(&mut self, key: Key, mut bbn: BranchNode, cutoff: Option<Key>) {
         let fd = self.bbn_writer.store_fd();
 
-        // TODO: handle error
-        let page_number = self.bbn_writer.allocate().unwrap();
+        // The workers have to carry on with their protocol after an allocation failure, so the
+        // branch is tracked as usual, but it is not written.
+        let page_number = match self.bbn_writer.allocate() {
+            Ok(page_number) => page_number,
+            Err(e) => {
+                self.error.get_or_insert(e);
+                self.unsent_writes += 1;
+                self.branches_tracker
+                    .insert(key, Arc::new(bbn), cutoff, PageNumber(0));
+                return;
+            }
+        };
 
         bbn.set_bbn_pn(page_number.0);
+        checksum::write(bbn.as_mut_slice(), TRAILER_OFFSET);
         let bbn = Arc::new(bbn);
 
-        // TODO: handle error
         let ptr = bbn.as_slice().as_ptr();
         self.io_handle
             .send(IoCommand {
@@ -23,8 +23,9 @@ use crate::beatree::{
             leaf_updater::{BaseLeaf, DigestResult as LeafDigestResult, LeafUpdater},
         },
     },
-    Key,
+    Key, Value,
 };
+use crate::checksum::{self, TRAILER_OFFSET};
 use crate::io::{IoCommand, IoHandle, IoKind, PAGE_SIZE};
 
 /// Tracker of all changes that happen to leaves during an update
@@ -66,6 +67,9 @@ pub struct LeafStageOutput {
     pub submitted_io: usize,
     /// Data which should be dropped after all submitted I/Os have concluded.
     pub post_io_drop: PostIoDrop,
+    /// The first error the stage ran into, e.g. failing to allocate a page. The output is
+    /// incomplete then, but the submitted I/Os still have to be awaited.
+    pub error: Option<anyhow::Error>,
 }
 
 /// Change the btree's leaves in the specified way
@@ -75,21 +79,24 @@ pub fn run(
     leaf_reader: StoreReader,
     leaf_writer: SyncAllocator,
     io_handle: IoHandle,
-    changeset: Arc<BTreeMap<Key, Option<Vec<u8>>>>,
+    changeset: Arc<BTreeMap<Key, Option<Value>>>,
     thread_pool: ThreadPool,
     num_workers: usize,
-) -> anyhow::Result<LeafStageOutput> {
+) -> LeafStageOutput {
+    let mut output = LeafStageOutput::default();
     if changeset.is_empty() {
-        return Ok(LeafStageOutput::default());
+        return output;
     }
 
     let mut overflow_io = 0;
     let page_pool = leaf_reader.page_pool().clone();
 
+    // Values are compressed before being placed, so that they take as little space as possible.
+    let codec = leaf_reader.codec();
     let changeset = changeset
         .iter()
-        .map(|(k, v)| match v {
-            Some(v) if v.len() <= MAX_LEAF_VALUE_SIZE => Ok((*k, Some((v.clone(), false)))),
+        .map(|(k, v)| match v.as_ref().map(|v| codec.encode(v)) {
+            Some(v) if v.len() <= MAX_LEAF_VALUE_SIZE => Ok((*k, Some((v.into_owned(), false)))),
             Some(large_value) => {
                 let (pages, num_writes) =
                     overflow::chunk(&large_value, &leaf_writer, &page_pool, &io_handle)?;
@@ -100,7 +107,15 @@ pub fn run(
             }
             None => Ok((*k, None)),
         })
-        .collect::<anyhow::Result<Vec<_>>>()?;
+        .collect::<anyhow::Result<Vec<_>>>();
+    output.submitted_io += overflow_io;
+    let changeset = match changeset {
+        Ok(changeset) => changeset,
+        Err(e) => {
+            output.error = Some(e);
+            return output;
+        }
+    };
 
     assert!(num_workers >= 1);
     let workers = prepare_workers(bbn_index, &changeset, num_workers);
@@ -142,17 +157,20 @@ pub fn run(
     drop(changeset);
     drop(leaf_cache);
 
-    let mut output = LeafStageOutput::default();
-    output.submitted_io += overflow_io;
-
+    let mut unsent_writes = 0;
     for _ in 0..num_workers {
         // UNWRAP: results are always sent unless worker panics.
-        let worker_output = worker_result_rx.recv().unwrap();
+        let mut worker_output = worker_result_rx.recv().unwrap();
+        unsent_writes += worker_output.unsent_writes;
+        if let Some(e) = worker_output.error.take() {
+            output.error.get_or_insert(e);
+        }
         apply_worker_changes(&leaf_reader, &mut output, worker_output);
     }
+    output.submitted_io -= unsent_writes;
 
     output.leaf_changeset.sort_by_key(|(k, _)| *k);
-    Ok(output)
+    output
 }
 
 fn apply_worker_changes(
@@ -348,7 +366,8 @@ fn reset_leaf_base_fresh(
             .map(|(_, l)| l)
             .unwrap_or_else(|| {
                 Arc::new(LeafNode {
-                    inner: leaf_reader.query(leaf_pn),
+                    // UNWRAP: the commit has no way to recover from a corrupted leaf.
+                    inner: leaf_reader.query(leaf_pn).unwrap(),
                 })
             }),
         separator,
@@ -360,6 +379,8 @@ fn reset_leaf_base_fresh(
 struct LeafWorkerOutput {
     leaves_tracker: LeavesTracker,
     overflow_deleted: Vec<Vec<u8>>,
+    error: Option<anyhow::Error>,
+    unsent_writes: usize,
 }
 
 fn run_worker(
@@ -381,6 +402,8 @@ fn run_worker(
         leaf_writer,
         leaves_tracker: LeavesTracker::new(),
         io_handle,
+        error: None,
+        unsent_writes: 0,
     };
 
     // point leaf updater at first leaf.
@@ -502,6 +525,8 @@ fn run_worker(
     LeafWorkerOutput {
         leaves_tracker: new_leaf_state.leaves_tracker,
         overflow_deleted,
+        error: new_leaf_state.error,
+        unsent_writes: new_leaf_state.unsent_writes,
     }
 }
 
@@ -509,17 +534,30 @@ struct NewLeafHandler {
     leaf_writer: SyncAllocator,
     leaves_tracker: LeavesTracker,
     io_handle: IoHandle,
+    /// The first allocation failure, which fails the update once the stage is over.
+    error: Option<anyhow::Error>,
+    /// The number of tracked leaves which were not written because their allocation failed.
+    unsent_writes: usize,
 }
 
 impl super::leaf_updater::HandleNewLeaf for NewLeafHandler {
-    fn handle_new_leaf(&mut self, key: Key, leaf: LeafNode, cutoff: Option<Key>) {
+    fn handle_new_leaf(&mut self, key: Key, mut leaf: LeafNode, cutoff: Option<Key>) {
+        checksum::write(&mut leaf.inner, TRAILER_OFFSET);
         let leaf = Arc::new(leaf);
         let fd = self.leaf_writer.store_fd();
 
-        // TODO: handle error
-        let page_number = self.leaf_writer.allocate().unwrap();
+        // The workers have to carry on with their protocol after an allocation failure, so the
+        // leaf is tracked as usual, but it is not written.
+        let page_number = match self.leaf_writer.allocate() {
+            Ok(page_number) => page_number,
+            Err(e) => {
+                self.error.get_or_insert(e);
+                self.unsent_writes += 1;
+                self.leaves_tracker.insert(key, leaf, cutoff, PageNumber(0));
+                return;
+            }
+        };
 
-        // TODO: handle error
         let ptr = leaf.inner.as_ptr();
         self.io_handle
             .send(IoCommand {
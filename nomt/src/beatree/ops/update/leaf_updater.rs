@@ -221,6 +221,15 @@ impl LeafUpdater {
             },
         };
 
+        // the overflow pages of a replaced or deleted value must be freed even if there is
+        // nothing to keep before it.
+        if found {
+            let (val, overflow) = base.cell(to);
+            if overflow {
+                with_deleted_overflow(val);
+            }
+        }
+
         if from == to {
             // nothing to keep
             return;
@@ -229,13 +238,6 @@ impl LeafUpdater {
         let values_size = base.node.values_size(from, to);
         self.ops.push(LeafOp::KeepChunk(from, to, values_size));
 
-        if found {
-            let (val, overflow) = base.cell(to);
-            if overflow {
-                with_deleted_overflow(val);
-            }
-        }
-
         self.bulk_split_step(self.ops.len() - 1);
     }
 
@@ -924,6 +926,33 @@ mod tests {
         };
     }
 
+    #[test]
+    fn delete_first_calls_with_deleted_overflow() {
+        let leaf = make_leaf(vec![
+            (key(1), vec![1u8; 1200], true),
+            (key(2), vec![1u8; 1200], false),
+            (key(3), vec![1u8; 1200], false),
+        ]);
+
+        let mut updater = LeafUpdater::new(
+            PAGE_POOL.clone(),
+            Some(BaseLeaf {
+                node: leaf,
+                low: 0,
+                separator: key(1),
+            }),
+            None,
+        );
+        let mut new_leaves = TestHandleNewLeaf::default();
+
+        let mut called = false;
+        updater.ingest(key(1), None, false, |_| called = true);
+        assert!(called);
+        let DigestResult::Finished = updater.digest(&mut new_leaves) else {
+            panic!()
+        };
+    }
+
     #[test]
     fn delete_completely() {
         let leaf = make_leaf(vec![
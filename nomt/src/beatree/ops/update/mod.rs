@@ -2,17 +2,23 @@ use anyhow::Result;
 use dashmap::DashMap;
 use threadpool::ThreadPool;
 
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, mem, sync::Arc};
 
 use crate::beatree::{
-    allocator::{PageNumber, Store, StoreReader},
+    allocator::{PageNumber, Store, StoreMeta, StoreReader, SyncFinisher},
     branch::BRANCH_NODE_BODY_SIZE,
     index::Index,
-    leaf::node::{LeafNode, LEAF_NODE_BODY_SIZE},
+    leaf::{
+        node::{
+            LeafNode, LEAF_NODE_BODY_SIZE, MAX_LEAF_VALUE_SIZE, MAX_OVERFLOW_CELL_NODE_POINTERS,
+        },
+        overflow,
+    },
     ops::get_key,
-    Key, SyncData,
+    Key, SyncData, Value,
 };
 use crate::io::{IoHandle, PagePool};
+use branch_stage::BranchStageOutput;
 
 mod branch_stage;
 mod branch_updater;
@@ -38,18 +44,59 @@ const LEAF_MERGE_THRESHOLD: usize = LEAF_NODE_BODY_SIZE / 2;
 const LEAF_BULK_SPLIT_THRESHOLD: usize = (LEAF_NODE_BODY_SIZE * 9) / 5;
 const LEAF_BULK_SPLIT_TARGET: usize = (LEAF_NODE_BODY_SIZE * 3) / 4;
 
+// With adaptive workers, a leaf stage worker is added for every this many existing leaves touched
+// by the changeset, or for every this many changes, whichever gives more workers. The latter
+// covers changesets which mostly create new leaves, e.g. when filling a fresh database.
+const LEAVES_PER_LEAF_WORKER: usize = 32;
+const CHANGES_PER_LEAF_WORKER: usize = 2048;
+// With adaptive workers, a branch stage worker is added for every this many changed leaves.
+const LEAVES_PER_BRANCH_WORKER: usize = 256;
+
+/// The number of workers used by the stages of an update.
+#[derive(Clone, Copy, Debug)]
+pub struct CommitWorkers {
+    /// The maximum number of workers.
+    pub max: usize,
+    /// Whether to choose the number of workers of every stage from the amount of work, up to
+    /// `max`. Otherwise, `max` workers are always used.
+    ///
+    /// Small updates are dominated by the coordination between workers, so they are best
+    /// handled by few workers.
+    pub adaptive: bool,
+}
+
+impl CommitWorkers {
+    fn leaf_stage(&self, changes: usize, touched_leaves: usize) -> usize {
+        let wanted =
+            (touched_leaves / LEAVES_PER_LEAF_WORKER).max(changes / CHANGES_PER_LEAF_WORKER) + 1;
+        self.clamp(wanted)
+    }
+
+    fn branch_stage(&self, changed_leaves: usize) -> usize {
+        self.clamp(changed_leaves / LEAVES_PER_BRANCH_WORKER + 1)
+    }
+
+    fn clamp(&self, wanted: usize) -> usize {
+        if self.adaptive {
+            wanted.clamp(1, self.max)
+        } else {
+            self.max
+        }
+    }
+}
+
 /// Change the btree in the specified way. Updates the branch index in-place.
 ///
 /// The changeset is a list of key value pairs to be added or removed from the btree.
 pub fn update(
-    changeset: Arc<BTreeMap<Key, Option<Vec<u8>>>>,
+    changeset: Arc<BTreeMap<Key, Option<Value>>>,
     mut bbn_index: Index,
     leaf_store: Store,
     bbn_store: Store,
     page_pool: PagePool,
     io_handle: IoHandle,
     thread_pool: ThreadPool,
-    workers: usize,
+    workers: CommitWorkers,
 ) -> Result<SyncData> {
     let leaf_reader = StoreReader::new(leaf_store.clone(), page_pool.clone());
     let (leaf_writer, leaf_finisher) = leaf_store.start_sync();
@@ -62,7 +109,14 @@ pub fn update(
         changeset.keys().cloned(),
     )?;
 
-    let leaf_stage_outputs = leaf_stage::run(
+    // Extend the stores up front, so that the workers don't stall on it while holding up the
+    // rest of the stage.
+    let (leaf_pages, bbn_pages) = estimate_allocations(&changeset, leaf_cache.len());
+    leaf_writer.reserve(leaf_pages)?;
+    bbn_writer.reserve(bbn_pages)?;
+
+    let leaf_workers = workers.leaf_stage(changeset.len(), leaf_cache.len());
+    let mut leaf_stage_outputs = leaf_stage::run(
         &bbn_index,
         leaf_cache,
         leaf_reader,
@@ -70,34 +124,60 @@ pub fn update(
         io_handle.clone(),
         changeset,
         thread_pool.clone(),
-        workers,
-    )?;
-
-    let branch_stage_outputs = branch_stage::run(
-        &mut bbn_index,
-        bbn_writer,
-        page_pool.clone(),
-        io_handle.clone(),
-        leaf_stage_outputs.leaf_changeset,
-        thread_pool,
-        workers,
-    )?;
-
-    let (ln_freelist_pages, ln_meta) =
-        leaf_finisher.finish(&page_pool, leaf_stage_outputs.freed_pages)?;
-
-    let (bbn_freelist_pages, bbn_meta) =
-        bbn_finisher.finish(&page_pool, branch_stage_outputs.freed_pages)?;
+        leaf_workers,
+    );
+
+    let mut branch_stage_outputs = BranchStageOutput::default();
+    if leaf_stage_outputs.error.is_none() {
+        let branch_workers = workers.branch_stage(leaf_stage_outputs.leaf_changeset.len());
+        branch_stage_outputs = branch_stage::run(
+            &mut bbn_index,
+            bbn_writer,
+            page_pool.clone(),
+            io_handle.clone(),
+            mem::take(&mut leaf_stage_outputs.leaf_changeset),
+            thread_pool,
+            branch_workers,
+        );
+    }
 
     let mut total_io = leaf_stage_outputs.submitted_io + branch_stage_outputs.submitted_io;
-    total_io += ln_freelist_pages.len();
-    total_io += bbn_freelist_pages.len();
-
-    crate::beatree::writeout::submit_freelist_write(&io_handle, &leaf_store, ln_freelist_pages)?;
-    crate::beatree::writeout::submit_freelist_write(&io_handle, &bbn_store, bbn_freelist_pages)?;
+    let finished = match leaf_stage_outputs
+        .error
+        .take()
+        .or(branch_stage_outputs.error.take())
+    {
+        Some(e) => Err(e),
+        None => finish_stores(
+            FinishingStore {
+                store: &leaf_store,
+                finisher: leaf_finisher,
+                freed_pages: mem::take(&mut leaf_stage_outputs.freed_pages),
+            },
+            FinishingStore {
+                store: &bbn_store,
+                finisher: bbn_finisher,
+                freed_pages: mem::take(&mut branch_stage_outputs.freed_pages),
+            },
+            &page_pool,
+            &io_handle,
+        ),
+    };
+    if let Ok((_, _, freelist_io)) = &finished {
+        total_io += freelist_io;
+    }
 
+    // The submitted writes are awaited even if the update failed, because they refer to the
+    // pages dropped below. The pages written so far are not referenced by the meta, so a failed
+    // update leaves the previous state intact on disk.
+    let mut write_result = Ok(());
     for _ in 0..total_io {
-        io_handle.recv()?;
+        let completion = io_handle.recv()?;
+        if let Err(e) = completion.result {
+            if write_result.is_ok() {
+                write_result = Err(crate::Error::writeout(e));
+            }
+        }
     }
 
     drop((
@@ -105,15 +185,73 @@ pub fn update(
         branch_stage_outputs.post_io_drop,
     ));
 
+    let (ln_meta, bbn_meta, _) = finished?;
+    write_result?;
+
     Ok(SyncData {
         bbn_index,
         ln_freelist_pn: ln_meta.freelist_pn,
         ln_bump: ln_meta.bump,
         bbn_freelist_pn: bbn_meta.freelist_pn,
         bbn_bump: bbn_meta.bump,
+        ln_written_pages: ln_meta.written_pages,
+        bbn_written_pages: bbn_meta.written_pages,
     })
 }
 
+// A store whose sync is being finished, along with the pages freed by the update.
+struct FinishingStore<'a> {
+    store: &'a Store,
+    finisher: SyncFinisher,
+    freed_pages: Vec<PageNumber>,
+}
+
+// Finish the syncs of both stores and submit the writes of their free-lists. Returns the metas of
+// the stores and the number of writes submitted.
+fn finish_stores(
+    leaf: FinishingStore,
+    bbn: FinishingStore,
+    page_pool: &PagePool,
+    io_handle: &IoHandle,
+) -> Result<(StoreMeta, StoreMeta, usize)> {
+    let (ln_freelist_pages, ln_meta) = leaf.finisher.finish(page_pool, leaf.freed_pages)?;
+    let (bbn_freelist_pages, bbn_meta) = bbn.finisher.finish(page_pool, bbn.freed_pages)?;
+
+    let freelist_io = ln_freelist_pages.len() + bbn_freelist_pages.len();
+    crate::beatree::writeout::submit_freelist_write(io_handle, leaf.store, ln_freelist_pages)?;
+    crate::beatree::writeout::submit_freelist_write(io_handle, bbn.store, bbn_freelist_pages)?;
+    Ok((ln_meta, bbn_meta, freelist_io))
+}
+
+// Estimate the number of leaf and branch pages allocated by an update, given the number of
+// existing leaves it touches.
+//
+// Every touched leaf is rewritten, along with the branch above it, and inserted values fill new
+// leaves and overflow pages. Overwritten and deleted values are counted as insertions, so this
+// errs on the side of reserving too much.
+fn estimate_allocations(
+    changeset: &BTreeMap<Key, Option<Value>>,
+    touched_leaves: usize,
+) -> (usize, usize) {
+    let mut leaf_bytes = 0;
+    let mut overflow_pages = 0;
+    for value in changeset.values().flatten() {
+        if value.len() > MAX_LEAF_VALUE_SIZE {
+            let pages = overflow::total_needed_pages(value.len());
+            leaf_bytes += 34 + 8 + 4 * pages.min(MAX_OVERFLOW_CELL_NODE_POINTERS);
+            overflow_pages += pages;
+        } else {
+            leaf_bytes += 34 + value.len();
+        }
+    }
+    let new_leaves = leaf_bytes.div_ceil(LEAF_BULK_SPLIT_TARGET);
+    let new_branches = (new_leaves * 34).div_ceil(BRANCH_BULK_SPLIT_TARGET);
+    (
+        touched_leaves + new_leaves + overflow_pages,
+        touched_leaves + new_branches,
+    )
+}
+
 // TODO: this should not be necessary with proper warm-ups.
 fn preload_leaves(
     leaf_reader: &StoreReader,
@@ -148,6 +286,7 @@ fn preload_leaves(
         completion.result?;
         let pn = PageNumber(completion.command.user_data as u32);
         let page = completion.command.kind.unwrap_buf();
+        leaf_reader.check_page(pn, &page)?;
         leaf_pages.insert(pn, Arc::new(LeafNode { inner: page }));
     }
 
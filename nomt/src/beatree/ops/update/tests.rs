@@ -2,6 +2,7 @@ use crate::{
     beatree::{
         allocator::{PageNumber, Store, StoreReader},
         branch::{self, node::BranchNode, BRANCH_NODE_BODY_SIZE, BRANCH_NODE_SIZE},
+        compression::Codec,
         leaf::{
             self,
             node::{LeafNode, MAX_LEAF_VALUE_SIZE},
@@ -10,7 +11,7 @@ use crate::{
             bit_ops::separate,
             update::{
                 branch_stage::BranchStageOutput, branch_updater::tests::make_branch_until, get_key,
-                leaf_stage::LeafStageOutput, preload_leaves, LEAF_MERGE_THRESHOLD,
+                leaf_stage::LeafStageOutput, preload_leaves, CommitWorkers, LEAF_MERGE_THRESHOLD,
             },
         },
         Index,
@@ -86,9 +87,12 @@ impl TreeData {
     fn leaf_store(&self) -> Store {
         Store::open(
             &PAGE_POOL,
-            self.ln_fd.try_clone().unwrap(),
+            Arc::new(self.ln_fd.try_clone().unwrap()),
             PageNumber(self.ln_bump),
             Some(PageNumber(self.ln_freelist_pn)),
+            Some("ln"),
+            None,
+            Codec::default(),
         )
         .unwrap()
     }
@@ -116,18 +120,34 @@ fn init_beatree() -> TreeData {
         .map(|key| (key, vec![170u8; rng.gen_range(500..MAX_LEAF_VALUE_SIZE)]))
         .collect();
 
-    let leaf_store =
-        Store::open(&PAGE_POOL, ln_fd.try_clone().unwrap(), PageNumber(1), None).unwrap();
+    let leaf_store = Store::open(
+        &PAGE_POOL,
+        Arc::new(ln_fd.try_clone().unwrap()),
+        PageNumber(1),
+        None,
+        None,
+        None,
+        Codec::default(),
+    )
+    .unwrap();
 
-    let bbn_store =
-        Store::open(&PAGE_POOL, bbn_fd.try_clone().unwrap(), PageNumber(1), None).unwrap();
+    let bbn_store = Store::open(
+        &PAGE_POOL,
+        Arc::new(bbn_fd.try_clone().unwrap()),
+        PageNumber(1),
+        None,
+        None,
+        None,
+        Codec::default(),
+    )
+    .unwrap();
 
     let sync_data = super::update(
         Arc::new(
             initial_items
                 .clone()
                 .into_iter()
-                .map(|(k, v)| (k, Some(v)))
+                .map(|(k, v)| (k, Some(v.into())))
                 .collect(),
         ),
         Index::default(),
@@ -136,7 +156,10 @@ fn init_beatree() -> TreeData {
         PAGE_POOL.clone(),
         IO_POOL.make_handle(),
         THREAD_POOL.clone(),
-        1,
+        CommitWorkers {
+            max: 1,
+            adaptive: false,
+        },
     )
     .unwrap();
 
@@ -194,11 +217,16 @@ fn exec_leaf_stage(
         leaf_reader,
         leaf_writer,
         io_handle.clone(),
-        Arc::new(changeset.into_iter().collect()),
+        Arc::new(
+            changeset
+                .into_iter()
+                .map(|(k, v)| (k, v.map(Into::into)))
+                .collect(),
+        ),
         THREAD_POOL.clone(),
         commit_concurrency,
-    )
-    .unwrap();
+    );
+    assert!(leaf_stage_output.error.is_none());
 
     // we don't actually write the free-list pages so the store is effectively clean.
     let _ = leaf_finisher.finish(&PAGE_POOL, Vec::new()).unwrap();
@@ -236,7 +264,7 @@ fn is_valid_leaf_stage_output(
     let mut found_underfull_leaf = false;
     for (_, new_pn) in output.leaf_changeset.into_iter() {
         let Some(new_pn) = new_pn else { continue };
-        let page = leaf_reader.query(new_pn);
+        let page = leaf_reader.query(new_pn).unwrap();
         let leaf_node = LeafNode { inner: page };
 
         let n = leaf_node.n();
@@ -450,9 +478,12 @@ fn branch_stage_inner(insertions: BTreeMap<Key, u32>, deletions: Vec<u16>) -> Te
 
     let bbn_store = Store::open(
         &PAGE_POOL,
-        bbn_fd.try_clone().unwrap(),
+        Arc::new(bbn_fd.try_clone().unwrap()),
         PageNumber(SEPARATORS.len() as u32),
         None,
+        None,
+        None,
+        Codec::default(),
     )
     .unwrap();
 
@@ -476,8 +507,8 @@ fn branch_stage_inner(insertions: BTreeMap<Key, u32>, deletions: Vec<u16>) -> Te
         changeset.into_iter().collect(),
         THREAD_POOL.clone(),
         64,
-    )
-    .unwrap();
+    );
+    assert!(branch_stage_output.error.is_none());
 
     let old_pages = bbn_index.into_iter().collect::<BTreeMap<_, _>>();
 
@@ -526,3 +557,27 @@ fn branch_stage() {
         std::panic::resume_unwind(cause);
     }
 }
+
+#[test]
+fn commit_workers_adapt_to_update_size() {
+    let adaptive = CommitWorkers {
+        max: 8,
+        adaptive: true,
+    };
+    assert_eq!(adaptive.leaf_stage(1, 1), 1);
+    assert_eq!(adaptive.leaf_stage(500, 20), 1);
+    // Changes spread over many leaves.
+    assert_eq!(adaptive.leaf_stage(500, 100), 4);
+    // Many changes creating new leaves.
+    assert_eq!(adaptive.leaf_stage(10_000, 0), 5);
+    assert_eq!(adaptive.leaf_stage(1_000_000, 100_000), 8);
+    assert_eq!(adaptive.branch_stage(10), 1);
+    assert_eq!(adaptive.branch_stage(1000), 4);
+
+    let fixed = CommitWorkers {
+        max: 8,
+        adaptive: false,
+    };
+    assert_eq!(fixed.leaf_stage(1, 1), 8);
+    assert_eq!(fixed.branch_stage(1), 8);
+}
@@ -0,0 +1,98 @@
+//! A handle to a value read from the btree.
+
+use std::ops::{Deref, Range};
+
+use super::Value;
+use crate::io::page_pool::FatPage;
+
+/// A value read from the database.
+///
+/// Values stored inline within a leaf page are served straight from the page they were read into,
+/// without copying them. Values which are not yet synced to disk share the buffer of the committed
+/// value. Large values, which span several overflow pages, are assembled into an owned buffer.
+///
+/// A `ValueRef` dereferences to the bytes of the value. Use [`ValueRef::to_vec`] or
+/// [`ValueRef::into_vec`] to obtain an owned copy.
+pub struct ValueRef(Repr);
+
+enum Repr {
+    Owned(Vec<u8>),
+    OwnedRange { buf: Vec<u8>, range: Range<usize> },
+    Shared(Value),
+    InPage { page: FatPage, range: Range<usize> },
+}
+
+impl ValueRef {
+    /// Create a value reference backed by an owned buffer.
+    pub(crate) fn owned(value: Vec<u8>) -> Self {
+        ValueRef(Repr::Owned(value))
+    }
+
+    /// Create a value reference backed by the given range of an owned buffer.
+    pub(crate) fn owned_range(buf: Vec<u8>, range: Range<usize>) -> Self {
+        ValueRef(Repr::OwnedRange { buf, range })
+    }
+
+    /// Create a value reference sharing the given value.
+    pub(crate) fn shared(value: Value) -> Self {
+        ValueRef(Repr::Shared(value))
+    }
+
+    /// Create a value reference backed by the given range of a page.
+    pub(crate) fn in_page(page: FatPage, range: Range<usize>) -> Self {
+        ValueRef(Repr::InPage { page, range })
+    }
+
+    /// Copy the value into a fresh vector.
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.deref().to_vec()
+    }
+
+    /// Convert into an owned vector, copying only if the value is not already owned.
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            Repr::Owned(value) => value,
+            Repr::OwnedRange { buf, range } => buf[range].to_vec(),
+            Repr::Shared(value) => value.to_vec(),
+            Repr::InPage { page, range } => page[range].to_vec(),
+        }
+    }
+
+    /// Convert into a [`Value`], copying only if the value is not already shared.
+    pub(crate) fn into_value(self) -> Value {
+        match self.0 {
+            Repr::Owned(value) => value.into(),
+            Repr::OwnedRange { buf, range } => buf[range].into(),
+            Repr::Shared(value) => value,
+            Repr::InPage { page, range } => page[range].into(),
+        }
+    }
+}
+
+impl Deref for ValueRef {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self.0 {
+            Repr::Owned(ref value) => &value[..],
+            Repr::OwnedRange { ref buf, ref range } => &buf[range.clone()],
+            Repr::Shared(ref value) => &value[..],
+            Repr::InPage {
+                ref page,
+                ref range,
+            } => &page[range.clone()],
+        }
+    }
+}
+
+impl AsRef<[u8]> for ValueRef {
+    fn as_ref(&self) -> &[u8] {
+        self.deref()
+    }
+}
+
+impl std::fmt::Debug for ValueRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ValueRef").field(&self.deref()).finish()
+    }
+}
@@ -0,0 +1,172 @@
+#![cfg(feature = "benchmarks")]
+
+//! Compares the read amplification of the bucket mapping strategies.
+//!
+//! The hash-table is populated with the pages of a trie holding uniformly distributed keys and
+//! then all the pages on the path to a sample of keys are looked up. For every strategy and load
+//! factor this reports:
+//!
+//! - the number of buckets read from disk per page lookup. A bucket has to be read whenever the
+//!   meta-map can't rule it out, so this includes reads of buckets holding other pages.
+//! - the number of distinct 64 KiB extents of the hash-table file read per key. Fewer extents
+//!   means more reads can be served by read-ahead or merged by the device.
+
+use super::{
+    hash_page_id,
+    mapping::{BucketMapping, BucketMappingStrategy},
+    meta_map::MetaMap,
+    ProbeResult, ProbeSequence,
+};
+use criterion::{BenchmarkId, Criterion};
+use nomt_core::{
+    page_id::{PageId, PageIdsIterator},
+    trie::KeyPath,
+};
+use std::collections::{HashMap, HashSet};
+
+const NUM_KEYS: u64 = 1_000_000;
+const NUM_SAMPLES: usize = 10_000;
+const BUCKETS_PER_EXTENT: u64 = 16;
+const SEED: [u8; 16] = [0; 16];
+
+/// Reports the read amplification of every strategy and benchmarks the lookups.
+pub fn bucket_mapping_benchmark(c: &mut Criterion) {
+    let mut keys = (0..NUM_KEYS)
+        .map(|id| *blake3::hash(&id.to_le_bytes()).as_bytes())
+        .collect::<Vec<KeyPath>>();
+    keys.sort();
+    let paths = page_paths(&keys);
+    let pages = paths.iter().flatten().cloned().collect::<HashSet<_>>();
+    let samples = paths
+        .iter()
+        .step_by(paths.len() / NUM_SAMPLES)
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("bucket_mapping");
+    for load_factor in [0.5, 0.8] {
+        let num_buckets = (pages.len() as f64 / load_factor) as u64;
+        for strategy in [
+            BucketMappingStrategy::Hashed,
+            BucketMappingStrategy::Clustered,
+        ] {
+            let table = Table::populate(strategy, num_buckets, &pages);
+
+            let mut reads = 0;
+            let mut extents = 0;
+            for path in &samples {
+                let mut key_extents = HashSet::new();
+                for page_id in path.iter() {
+                    for bucket in table.lookup(page_id) {
+                        reads += 1;
+                        key_extents.insert(bucket / BUCKETS_PER_EXTENT);
+                    }
+                }
+                extents += key_extents.len();
+            }
+            let lookups = samples.iter().map(|path| path.len()).sum::<usize>();
+            println!(
+                "{strategy:?} at load {load_factor}: {:.3} reads per page, {:.2} extents per key",
+                reads as f64 / lookups as f64,
+                extents as f64 / samples.len() as f64,
+            );
+
+            group.bench_function(
+                BenchmarkId::new(format!("{strategy:?}"), load_factor),
+                |b| {
+                    b.iter(|| {
+                        for path in &samples {
+                            for page_id in path.iter() {
+                                criterion::black_box(table.lookup(page_id));
+                            }
+                        }
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+/// Returns the IDs of the pages on the path to every key, from the root down to the page holding
+/// the leaf. The keys must be sorted.
+fn page_paths(keys: &[KeyPath]) -> Vec<Vec<PageId>> {
+    let shared_bits = |a: &KeyPath, b: &KeyPath| {
+        let x = u128::from_be_bytes(a[..16].try_into().unwrap())
+            ^ u128::from_be_bytes(b[..16].try_into().unwrap());
+        x.leading_zeros() as usize
+    };
+    (0..keys.len())
+        .map(|i| {
+            // A leaf sits right below the longest prefix shared with its neighbours.
+            let prev = i
+                .checked_sub(1)
+                .map_or(0, |j| shared_bits(&keys[j], &keys[i]));
+            let next = keys.get(i + 1).map_or(0, |key| shared_bits(key, &keys[i]));
+            let leaf_depth = prev.max(next) + 1;
+            PageIdsIterator::new(keys[i])
+                .take((leaf_depth - 1) / 6 + 1)
+                .collect()
+        })
+        .collect()
+}
+
+struct Table {
+    mapping: Box<dyn BucketMapping>,
+    meta_map: MetaMap,
+    occupants: HashMap<u64, PageId>,
+}
+
+impl Table {
+    fn populate(
+        strategy: BucketMappingStrategy,
+        num_buckets: u64,
+        pages: &HashSet<PageId>,
+    ) -> Self {
+        let meta_bytes = vec![0; (num_buckets as usize).next_multiple_of(4096)];
+        let mut table = Table {
+            mapping: strategy.mapping(),
+            meta_map: MetaMap::from_bytes(meta_bytes, num_buckets as usize),
+            occupants: HashMap::new(),
+        };
+        for page_id in pages {
+            let mut probe_seq = table.probe_sequence(page_id);
+            let bucket = loop {
+                match probe_seq.next(&table.meta_map) {
+                    ProbeResult::PossibleHit(_) => continue,
+                    ProbeResult::Empty(bucket) | ProbeResult::Tombstone(bucket) => break bucket,
+                }
+            };
+            table.meta_map.set_full(bucket as usize, probe_seq.hash);
+            table.occupants.insert(bucket, page_id.clone());
+        }
+        table
+    }
+
+    fn probe_sequence(&self, page_id: &PageId) -> ProbeSequence {
+        let hash = hash_page_id(page_id, &SEED);
+        ProbeSequence {
+            hash,
+            bucket: self
+                .mapping
+                .home_bucket(page_id, hash, self.meta_map.len() as u64),
+            step: 0,
+        }
+    }
+
+    /// Returns the buckets that have to be read to find the page.
+    fn lookup(&self, page_id: &PageId) -> Vec<u64> {
+        let mut probe_seq = self.probe_sequence(page_id);
+        let mut reads = Vec::new();
+        loop {
+            match probe_seq.next(&self.meta_map) {
+                ProbeResult::PossibleHit(bucket) => {
+                    reads.push(bucket);
+                    if self.occupants.get(&bucket) == Some(page_id) {
+                        return reads;
+                    }
+                }
+                ProbeResult::Empty(_) | ProbeResult::Tombstone(_) => return reads,
+            }
+        }
+    }
+}
@@ -5,7 +5,7 @@ use super::meta_map::MetaMap;
 use crate::io::{self, PagePool, PAGE_SIZE};
 use std::{
     fs::{File, OpenOptions},
-    path::PathBuf,
+    path::Path,
 };
 
 /// The offsets of the HT file.
@@ -43,7 +43,9 @@ pub fn open(
     ht_fd: &File,
 ) -> anyhow::Result<(HTOffsets, MetaMap)> {
     if ht_fd.metadata()?.len() != expected_file_len(num_pages) {
-        anyhow::bail!("Store corrupted; unexpected file length");
+        return Err(
+            crate::Error::Corruption("unexpected hash-table file length".to_string()).into(),
+        );
     }
 
     let num_meta_byte_pages = num_meta_byte_pages(num_pages);
@@ -60,12 +62,17 @@ pub fn open(
     ))
 }
 
-/// Creates the store file. Fails if store file already exists.
+/// Creates the hash-table file in `ht_dir` and the WAL file in `wal_dir`.
 ///
 /// Lays out the meta page. If `preallocate` is true, preallocates the blocks for the file.
-pub fn create(path: PathBuf, num_pages: u32, preallocate: bool) -> std::io::Result<()> {
+pub fn create(
+    ht_dir: &Path,
+    wal_dir: &Path,
+    num_pages: u32,
+    preallocate: bool,
+) -> std::io::Result<()> {
     let start = std::time::Instant::now();
-    let ht_path = path.join("ht");
+    let ht_path = ht_dir.join("ht");
     let ht_file = OpenOptions::new().write(true).create(true).open(ht_path)?;
 
     // number of pages + pages required for meta bits.
@@ -77,7 +84,7 @@ pub fn create(path: PathBuf, num_pages: u32, preallocate: bool) -> std::io::Resu
     ht_file.sync_all()?;
     drop(ht_file);
 
-    let wal_path = path.join("wal");
+    let wal_path = wal_dir.join("wal");
     let wal_file = OpenOptions::new().write(true).create(true).open(wal_path)?;
     wal_file.sync_all()?;
     drop(wal_file);
@@ -0,0 +1,132 @@
+//! Strategies for mapping page IDs to the buckets of the hash-table.
+//!
+//! A mapping only determines the bucket at which the probe sequence of a page starts. Collisions
+//! are resolved by probing in the same way regardless of the strategy.
+
+use nomt_core::page_id::PageId;
+
+/// The strategy used for mapping pages to buckets.
+///
+/// The strategy is chosen when the database is created and cannot be changed afterwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BucketMappingStrategy {
+    /// Pages are placed at a pseudo-random bucket given by the hash of the page ID.
+    ///
+    /// This spreads the pages evenly across the table regardless of the distribution of the keys.
+    #[default]
+    Hashed,
+    /// Pages are placed in the order of the key ranges they cover, so that pages adjacent in the
+    /// trie, such as a page and its children, are stored close to each other in the file.
+    ///
+    /// This relies on the key paths being uniformly distributed, e.g. being hashes. Otherwise, the
+    /// pages pile up in some regions of the table, leading to long probe sequences.
+    Clustered,
+}
+
+impl BucketMappingStrategy {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            BucketMappingStrategy::Hashed => 0,
+            BucketMappingStrategy::Clustered => 1,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(BucketMappingStrategy::Hashed),
+            1 => Some(BucketMappingStrategy::Clustered),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn mapping(self) -> Box<dyn BucketMapping> {
+        match self {
+            BucketMappingStrategy::Hashed => Box::new(HashedMapping),
+            BucketMappingStrategy::Clustered => Box::new(ClusteredMapping),
+        }
+    }
+}
+
+/// Determines the bucket at which the probe sequence of a page starts.
+pub trait BucketMapping: Send + Sync {
+    /// Returns the home bucket of the page, given the seeded hash of its ID. The result must be
+    /// less than `num_buckets`.
+    fn home_bucket(&self, page_id: &PageId, hash: u64, num_buckets: u64) -> u64;
+}
+
+/// See [`BucketMappingStrategy::Hashed`].
+pub struct HashedMapping;
+
+impl BucketMapping for HashedMapping {
+    fn home_bucket(&self, _page_id: &PageId, hash: u64, num_buckets: u64) -> u64 {
+        hash % num_buckets
+    }
+}
+
+/// See [`BucketMappingStrategy::Clustered`].
+pub struct ClusteredMapping;
+
+impl BucketMapping for ClusteredMapping {
+    fn home_bucket(&self, page_id: &PageId, _hash: u64, num_buckets: u64) -> u64 {
+        let path = page_id.length_dependent_encoding();
+
+        // The start of the key range covered by the page, as a fraction of 2^64. 10 levels give
+        // 60 bits, which is more than enough to tell apart any two buckets.
+        let mut position = 0u64;
+        for (i, child_index) in path.iter().take(10).enumerate() {
+            position |= (*child_index as u64) << (58 - 6 * i);
+        }
+        let scaled = ((position as u128 * num_buckets as u128) >> 64) as u64;
+
+        // A page and its leftmost descendants cover ranges starting at the same key. Offsetting by
+        // the depth places them next to each other instead of having them collide.
+        (scaled + path.len() as u64) % num_buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nomt_core::page_id::{ChildPageIndex, ROOT_PAGE_ID};
+
+    fn page_id(path: &[u8]) -> PageId {
+        path.iter().fold(ROOT_PAGE_ID, |page_id, &index| {
+            page_id
+                .child_page_id(ChildPageIndex::new(index).unwrap())
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn strategy_roundtrip() {
+        for strategy in [
+            BucketMappingStrategy::Hashed,
+            BucketMappingStrategy::Clustered,
+        ] {
+            assert_eq!(
+                BucketMappingStrategy::from_u8(strategy.to_u8()),
+                Some(strategy)
+            );
+        }
+        assert_eq!(BucketMappingStrategy::from_u8(2), None);
+    }
+
+    #[test]
+    fn clustered_follows_key_order() {
+        let num_buckets = 1_000_000;
+        let mapping = ClusteredMapping;
+        let home = |path: &[u8]| mapping.home_bucket(&page_id(path), 0, num_buckets);
+
+        assert_eq!(home(&[]), 0);
+        assert_eq!(home(&[0]), 1);
+        assert_eq!(home(&[0, 0]), 2);
+        assert!(home(&[1]) < home(&[2]));
+        assert!(home(&[1, 63]) < home(&[2]));
+        assert!(home(&[63, 63, 63]) < num_buckets);
+
+        // Siblings deep in the trie are close to each other.
+        let a = home(&[5, 17, 33, 2]);
+        let b = home(&[5, 17, 33, 3]);
+        assert!(b >= a && b - a < 10);
+    }
+}
@@ -6,26 +6,42 @@ use std::{
     fs::File,
     os::{fd::RawFd, unix::fs::FileExt},
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
 use crate::{
+    checksum::{self, CHECKSUM_SIZE},
     io::{self, page_pool::FatPage, IoCommand, IoHandle, IoKind, PagePool, PAGE_SIZE},
     page_diff::PageDiff,
+    store::IntegrityReport,
 };
 
-use self::{ht_file::HTOffsets, meta_map::MetaMap};
+use self::{
+    ht_file::HTOffsets, mapping::BucketMapping, meta_map::MetaMap, presence_filter::PresenceFilter,
+};
 
 pub use self::ht_file::create;
+pub use self::mapping::BucketMappingStrategy;
 pub use wal::WalBlobBuilder;
 
+#[cfg(feature = "benchmarks")]
+pub mod benches;
 mod ht_file;
+mod mapping;
 mod meta_map;
+mod presence_filter;
 mod wal;
 pub(crate) mod writeout;
 
+/// The number of buckets written by WAL replay between reports of its progress.
+const RECOVERY_PROGRESS_INTERVAL: usize = 1024;
+
+/// The offset of the checksum of a page, right before the page ID at the end of the page.
+const CHECKSUM_OFFSET: usize = PAGE_SIZE - 32 - CHECKSUM_SIZE;
+
 /// The index of a bucket within the map.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct BucketIndex(u64);
@@ -38,61 +54,221 @@ pub struct DB {
 pub struct Shared {
     store: HTOffsets,
     seed: [u8; 16],
+    mapping: Box<dyn BucketMapping>,
     meta_map: Arc<RwLock<MetaMap>>,
     wal_blob_builder: Arc<Mutex<WalBlobBuilder>>,
     occupied_buckets: AtomicUsize,
+    placement_probes: PlacementProbes,
+    verify_checksums: bool,
+    recovery_report: Option<RecoveryReport>,
+    presence_filter: Option<PresenceFilter>,
+}
+
+/// The parameters of an opened bitbox database.
+pub struct DbOptions {
+    /// The number of pages of the hash-table, as recorded in the meta file.
+    pub num_pages: u32,
+    /// The seed of the hash-table, as recorded in the meta file.
+    pub seed: [u8; 16],
+    /// How pages are mapped to buckets, as recorded in the meta file.
+    pub mapping: BucketMappingStrategy,
+    /// The sequence number of the last sync recorded in the meta file. The WAL is only replayed
+    /// if it was written by that sync.
+    pub sync_seqn: u32,
+    /// The number of threads used to replay the WAL.
+    pub recovery_concurrency: usize,
+    /// Whether to verify the checksums of the loaded pages.
+    pub verify_checksums: bool,
+    /// Whether to compress the WAL blobs written by syncs. Compressed blobs are read either way.
+    pub compress_wal: bool,
+    /// Invoked with the progress of replaying the WAL, if any.
+    pub on_recovery_progress: Option<RecoveryProgressCallback>,
+    /// Whether to keep a filter over the stored pages, built on open.
+    pub presence_filter: bool,
+}
+
+/// The progress of replaying the WAL when opening the database, see
+/// [`crate::Options::on_recovery_progress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryProgress {
+    /// The number of WAL entries applied so far.
+    pub entries_applied: u64,
+    /// The number of entries in the WAL.
+    pub entries: u64,
+    /// The number of bytes written to the hash-table file so far.
+    pub bytes_written: u64,
+    /// The time spent replaying the WAL so far.
+    pub elapsed: Duration,
+}
+
+/// A callback receiving the progress of replaying the WAL.
+pub type RecoveryProgressCallback = Arc<dyn Fn(RecoveryProgress) + Send + Sync>;
+
+/// The summary of replaying the WAL when opening the database, see
+/// [`crate::Nomt::recovery_report`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The sequence number of the sync whose WAL was replayed.
+    pub sync_seqn: u32,
+    /// The size of the WAL in bytes.
+    pub wal_bytes: u64,
+    /// The number of WAL entries applied.
+    pub entries: u64,
+    /// The number of buckets cleared.
+    pub buckets_cleared: usize,
+    /// The number of buckets whose page was written.
+    pub buckets_updated: usize,
+    /// The number of bytes written to the hash-table file, including the meta pages.
+    pub bytes_written: u64,
+    /// The time spent replaying the WAL.
+    pub elapsed: Duration,
 }
 
 impl DB {
     /// Opens an existing bitbox database.
     pub fn open(
-        num_pages: u32,
-        seed: [u8; 16],
+        options: DbOptions,
         page_pool: &PagePool,
         ht_fd: &File,
         wal_fd: &File,
     ) -> anyhow::Result<Self> {
-        let (store, mut meta_map) = match ht_file::open(num_pages, page_pool, ht_fd) {
+        let (store, mut meta_map) = match ht_file::open(options.num_pages, page_pool, ht_fd) {
             Ok(x) => x,
             Err(e) => {
                 anyhow::bail!("encountered error in opening store: {e:?}");
             }
         };
 
-        if wal_fd.metadata()?.len() > 0 {
-            recover(ht_fd, wal_fd, page_pool, &store, &mut meta_map, seed)?;
-        }
+        let recovery_report = if wal_fd.metadata()?.len() > 0 {
+            recover(ht_fd, wal_fd, page_pool, &store, &mut meta_map, &options)?
+        } else {
+            None
+        };
 
         let occupied_buckets = meta_map.full_count();
+        let presence_filter = if options.presence_filter {
+            Some(build_presence_filter(
+                ht_fd, page_pool, &store, &meta_map, &options,
+            )?)
+        } else {
+            None
+        };
 
-        let wal_blob_builder = WalBlobBuilder::new()?;
+        let wal_blob_builder =
+            WalBlobBuilder::new(options.compress_wal, Some(wal_fd.try_clone()?))?;
         Ok(Self {
             shared: Arc::new(Shared {
                 store,
-                seed,
+                seed: options.seed,
+                mapping: options.mapping.mapping(),
                 meta_map: Arc::new(RwLock::new(meta_map)),
                 wal_blob_builder: Arc::new(Mutex::new(wal_blob_builder)),
                 occupied_buckets: AtomicUsize::new(occupied_buckets),
+                placement_probes: PlacementProbes::default(),
+                verify_checksums: options.verify_checksums,
+                recovery_report,
+                presence_filter,
             }),
         })
     }
 
+    /// Returns the summary of replaying the WAL on open, or `None` if there was nothing to replay.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.shared.recovery_report
+    }
+
+    /// Returns the number of occupied buckets and the total number of buckets.
+    pub fn bucket_counts(&self) -> (usize, usize) {
+        let occupied = self.shared.occupied_buckets.load(Ordering::Relaxed);
+        (occupied, self.shared.meta_map.read().len())
+    }
+
+    /// Returns the number of pages placed into buckets since the database was opened, the total
+    /// number of buckets probed to place them, and the most probed to place a single page.
+    pub fn placement_probes(&self) -> (u64, u64, u64) {
+        let probes = &self.shared.placement_probes;
+        (
+            probes.placements.load(Ordering::Relaxed),
+            probes.probes.load(Ordering::Relaxed),
+            probes.longest.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Check the occupied buckets of the hash-table. Their pages must match their checksums and
+    /// hold a page ID whose hash matches the meta map, and no page ID may be stored in more than
+    /// one bucket.
+    ///
+    /// Must not be called while a sync is in progress. Fails only if I/O fails.
+    pub fn check_integrity(
+        &self,
+        page_pool: &PagePool,
+        ht_fd: &File,
+        report: &mut IntegrityReport,
+    ) -> anyhow::Result<()> {
+        let meta_map = self.shared.meta_map.read();
+        let mut buckets_by_page_id = HashMap::new();
+        for bucket in 0..meta_map.len() {
+            if meta_map.hint_empty(bucket) || meta_map.hint_tombstone(bucket) {
+                continue;
+            }
+            report.buckets += 1;
+
+            let pn = self.shared.store.data_page_index(bucket as u64);
+            let page = io::read_page(page_pool, ht_fd, pn)?;
+            if checksum::check(&page, CHECKSUM_OFFSET, "ht", pn).is_err() {
+                report.page_issue("ht", pn, "checksum mismatch");
+                continue;
+            }
+
+            // UNWRAP: the slice is 32 bytes long.
+            let raw_page_id: [u8; 32] = page[PAGE_SIZE - 32..].try_into().unwrap();
+            if meta_map.hint_not_match(bucket, hash_raw_page_id(raw_page_id, &self.shared.seed)) {
+                report.page_issue("ht", pn, "page ID doesn't match the meta map");
+                continue;
+            }
+            if let Some(other) = buckets_by_page_id.insert(raw_page_id, bucket) {
+                report.page_issue(
+                    "ht",
+                    pn,
+                    format!("page ID is also stored in bucket {other}"),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail to grow the WAL blob past `size` bytes, see [`WalBlobBuilder::limit_size`].
+    #[cfg(test)]
+    pub fn limit_wal_blob(&self, size: usize) {
+        self.shared.wal_blob_builder.lock().limit_size(size);
+    }
+
     /// Return a bucket allocator, used to determine the buckets which any newly inserted pages
     /// will clear.
-    pub fn bucket_allocator(&self) -> BucketAllocator {
+    ///
+    /// The allocator is prepared for the given number of changed pages up front. More may be
+    /// allocated or freed, at the cost of growing it on demand.
+    pub fn bucket_allocator(&self, expected_pages: usize) -> BucketAllocator {
         BucketAllocator {
             shared: self.shared.clone(),
-            changed_buckets: HashMap::new(),
+            changed_buckets: HashMap::with_capacity(expected_pages),
         }
     }
 
+    /// Write the changes to the WAL blob and collect the hash-table pages they update.
+    ///
+    /// The meta map and the presence filter are updated along the way, so if building the blob
+    /// fails, they are ahead of the files and the database can't be used until it is reopened.
     pub fn prepare_sync(
         &self,
         page_pool: &PagePool,
+        sync_seqn: u32,
         changes: Vec<(PageId, BucketIndex, Option<(FatPage, PageDiff)>)>,
     ) -> anyhow::Result<WriteoutData> {
         let mut meta_map = self.shared.meta_map.write();
         let mut wal_blob_builder = self.shared.wal_blob_builder.lock();
+        wal_blob_builder.write_start(sync_seqn)?;
 
         let mut changed_meta_pages = HashSet::new();
         let mut ht_pages = Vec::new();
@@ -103,9 +279,13 @@ impl DB {
             match page_info {
                 Some((mut page, page_diff)) => {
                     page[PAGE_SIZE - 32..].copy_from_slice(&page_id.encode());
+                    checksum::write(&mut page, CHECKSUM_OFFSET);
 
                     // update meta map with new info
                     let hash = hash_page_id(&page_id, &self.shared.seed);
+                    if let Some(ref presence_filter) = self.shared.presence_filter {
+                        presence_filter.insert(hash);
+                    }
                     let meta_map_changed = meta_map.hint_not_match(bucket as usize, hash);
                     if meta_map_changed {
                         occupied_buckets_delta += 1;
@@ -118,7 +298,7 @@ impl DB {
                         &page_diff,
                         page_diff.pack_changed_nodes(&page),
                         bucket,
-                    );
+                    )?;
 
                     let pn = self.shared.store.data_page_index(bucket);
                     ht_pages.push((pn, page));
@@ -127,7 +307,7 @@ impl DB {
                     occupied_buckets_delta -= 1;
                     meta_map.set_tombstone(bucket as usize);
                     changed_meta_pages.insert(meta_map.page_index(bucket as usize));
-                    wal_blob_builder.write_clear(bucket);
+                    wal_blob_builder.write_clear(bucket)?;
                 }
             };
         }
@@ -147,6 +327,9 @@ impl DB {
             assert_eq!(orig_len, ht_pages.len());
         }
 
+        let wal_blob = wal_blob_builder.finalize()?;
+        let wal_offset = wal_blob_builder.flushed();
+
         if occupied_buckets_delta < 0 {
             self.shared
                 .occupied_buckets
@@ -157,35 +340,118 @@ impl DB {
                 .fetch_add(occupied_buckets_delta as usize, Ordering::Relaxed);
         }
 
-        let wal_blob = wal_blob_builder.finalize();
+        Ok(WriteoutData {
+            ht_pages,
+            wal_blob,
+            wal_offset,
+        })
+    }
+}
 
-        Ok(WriteoutData { ht_pages, wal_blob })
+/// The lengths of the probe sequences walked to place pages into buckets, since the database was
+/// opened. Probe sequences much longer than the load of the table explains are a sign of pages
+/// colliding on purpose.
+#[derive(Default)]
+struct PlacementProbes {
+    placements: AtomicU64,
+    probes: AtomicU64,
+    longest: AtomicU64,
+}
+
+impl PlacementProbes {
+    fn record(&self, probes: u64) {
+        self.placements.fetch_add(1, Ordering::Relaxed);
+        self.probes.fetch_add(probes, Ordering::Relaxed);
+        self.longest.fetch_max(probes, Ordering::Relaxed);
     }
 }
 
+/// An update to a bucket page, as read from the WAL.
+struct BucketUpdate {
+    page_id: [u8; 32],
+    /// Whether the page was newly placed into the bucket. The page then starts out empty rather
+    /// than from whatever was previously stored in the bucket.
+    fresh: bool,
+    page_diff: PageDiff,
+    changed_nodes: Vec<[u8; 32]>,
+}
+
+/// Whether opening the database requires replaying the WAL, i.e. the WAL belongs to the sync
+/// recorded in the meta file. See [`recover`].
+pub fn needs_recovery(wal_fd: &File, sync_seqn: u32) -> anyhow::Result<bool> {
+    use std::os::unix::fs::FileExt as _;
+
+    let mut start = [0u8; 5];
+    if wal_fd.metadata()?.len() < start.len() as u64 {
+        return Ok(false);
+    }
+    wal_fd.read_exact_at(&mut start, 0)?;
+    Ok(wal::is_start_of(&start, sync_seqn))
+}
+
 /// Perform recovery by applying the WAL to the HT file.
+///
+/// The WAL is written and synced before the meta file, so a WAL which doesn't belong to the sync
+/// recorded in the meta file (`sync_seqn`) is left over from a sync which was interrupted before
+/// completing. It may be torn and is discarded. The WAL of the recorded sync, on the other hand,
+/// is whole: if any of its entries is invalid, recovery fails with [`crate::Error::Corruption`]
+/// rather than apply a part of it, which would leave the hash-table out of step with the b-tree.
+///
+/// Updates to distinct buckets are independent, so the page updates are applied by `concurrency`
+/// threads, each responsible for a disjoint set of buckets. The progress is reported to
+/// [`DbOptions::on_recovery_progress`] once the WAL is read, every [`RECOVERY_PROGRESS_INTERVAL`]
+/// buckets written, and once done. Returns `None` if the WAL was discarded.
 fn recover(
     ht_fd: &File,
     mut wal_fd: &File,
     page_pool: &PagePool,
     ht_offsets: &HTOffsets,
     meta_map: &mut MetaMap,
-    seed: [u8; 16],
-) -> anyhow::Result<()> {
+    options: &DbOptions,
+) -> anyhow::Result<Option<RecoveryReport>> {
     use crate::bitbox::wal::WalBlobReader;
     use std::io::{Seek, SeekFrom};
 
+    let DbOptions {
+        seed,
+        sync_seqn,
+        recovery_concurrency: concurrency,
+        ..
+    } = *options;
+    let start = Instant::now();
+    let wal_bytes = wal_fd.metadata()?.len();
+
     wal_fd.seek(SeekFrom::Start(0))?;
 
     // The indicies of pages (in the metabits page space) that were changed and require updates.
     // Note those are not ht page numbers yet and still require additional conversion.
     let mut changed_meta_page_ixs = HashSet::new();
     let mut wal_reader = WalBlobReader::new(page_pool, wal_fd)?;
+    match wal_reader.read_entry() {
+        Some(wal::WalEntry::Start {
+            sync_seqn: wal_sync_seqn,
+        }) if wal_sync_seqn == sync_seqn => {}
+        _ => {
+            wal_fd.set_len(0)?;
+            return Ok(None);
+        }
+    }
+
+    // The page updates to apply, grouped by bucket. The updates for every bucket are kept in the
+    // order they appear in the WAL.
+    let mut bucket_updates: HashMap<u64, Vec<BucketUpdate>> = HashMap::new();
+    let mut entries = 0u64;
+    let mut buckets_cleared = 0;
 
-    while let Some(entry) = wal_reader.read_entry()? {
+    while let Some(entry) = wal_reader.read_entry() {
+        entries += 1;
         match entry {
+            wal::WalEntry::Start { .. } => {
+                anyhow::bail!("unexpected start entry in the middle of the WAL");
+            }
             wal::WalEntry::Clear { bucket } => {
                 meta_map.set_tombstone(bucket as usize);
+                buckets_cleared += 1;
 
                 // Note that the meta page requires update.
                 changed_meta_page_ixs.insert(meta_map.page_index(bucket as usize));
@@ -204,16 +470,6 @@ fn recover(
                     changed_meta_page_ixs.insert(meta_map.page_index(bucket as usize));
                 }
 
-                // Apply the diff to the page in the ht file.
-                //
-                // The algorithm is:
-                // - read the bucket page from the ht file.
-                // - for each index of a bit in a diff that equals to 1, copy the changed node into
-                //   the page.
-                // - store the changed page.
-                let pn = ht_offsets.data_page_index(bucket);
-
-                let mut page = io::read_page(page_pool, ht_fd, pn)?;
                 if page_diff.count() != changed_nodes.len() {
                     anyhow::bail!(
                         "mismatched number of changed nodes: {} != {}",
@@ -221,17 +477,100 @@ fn recover(
                         changed_nodes.len()
                     );
                 }
-                page_diff.unpack_changed_nodes(&changed_nodes, &mut page);
-
-                ht_fd.write_all_at(&page, pn * PAGE_SIZE as u64)?;
+                bucket_updates
+                    .entry(bucket)
+                    .or_default()
+                    .push(BucketUpdate {
+                        page_id,
+                        fresh: meta_map_changed,
+                        page_diff,
+                        changed_nodes,
+                    });
             }
         }
     }
+    if !wal_reader.is_complete() {
+        return Err(
+            crate::Error::Corruption(format!("the WAL of sync {sync_seqn} is damaged")).into(),
+        );
+    }
+
+    // Apply the diffs to the pages in the ht file.
+    //
+    // The algorithm is:
+    // - read the bucket page from the ht file.
+    // - for each update of the bucket, start from an empty page if the page was newly placed in
+    //   the bucket or the bucket holds a different page. The diff only covers the nodes changed
+    //   by the commit, so leftovers from the previous occupant must not survive.
+    // - for each index of a bit in a diff that equals to 1, copy the changed node into the page.
+    // - write the page ID into the tail of the page, and the checksum before it.
+    // - store the changed page.
+    let bucket_updates = bucket_updates.into_iter().collect::<Vec<_>>();
+    let chunk_len = std::cmp::max(1, bucket_updates.len().div_ceil(concurrency));
+
+    // Clears only touch the meta map, so they are applied as soon as the WAL is read.
+    let updates = bucket_updates
+        .iter()
+        .map(|(_, updates)| updates.len() as u64)
+        .sum::<u64>();
+    let entries_applied = AtomicU64::new(entries - updates);
+    let buckets_written = AtomicUsize::new(0);
+    let report_progress = |buckets_written: usize| {
+        if let Some(ref f) = options.on_recovery_progress {
+            f(RecoveryProgress {
+                entries_applied: entries_applied.load(Ordering::Relaxed),
+                entries,
+                bytes_written: (buckets_written * PAGE_SIZE) as u64,
+                elapsed: start.elapsed(),
+            });
+        }
+    };
+    report_progress(0);
+
+    std::thread::scope(|scope| {
+        let handles = bucket_updates
+            .chunks(chunk_len)
+            .map(|chunk| {
+                let entries_applied = &entries_applied;
+                let buckets_written = &buckets_written;
+                let report_progress = &report_progress;
+                scope.spawn(move || -> anyhow::Result<()> {
+                    for (bucket, updates) in chunk {
+                        let pn = ht_offsets.data_page_index(*bucket);
+                        let mut page = io::read_page(page_pool, ht_fd, pn)?;
+                        for update in updates {
+                            if update.fresh || page[PAGE_SIZE - 32..] != update.page_id {
+                                page.fill(0);
+                            }
+                            update
+                                .page_diff
+                                .unpack_changed_nodes(&update.changed_nodes, &mut page);
+                            page[PAGE_SIZE - 32..].copy_from_slice(&update.page_id);
+                        }
+                        checksum::write(&mut page, CHECKSUM_OFFSET);
+                        ht_fd.write_all_at(&page, pn * PAGE_SIZE as u64)?;
+
+                        entries_applied.fetch_add(updates.len() as u64, Ordering::Relaxed);
+                        let written = buckets_written.fetch_add(1, Ordering::Relaxed) + 1;
+                        if written.is_multiple_of(RECOVERY_PROGRESS_INTERVAL) {
+                            report_progress(written);
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
 
     // Now that we have applied all the updates, we know precisely which meta pages have been
     // updated.
     //
     // We now write those pages out to the HT file.
+    let meta_pages_written = changed_meta_page_ixs.len();
     for changed_meta_page_ix in changed_meta_page_ixs {
         unsafe {
             let page = page_pool.alloc();
@@ -249,7 +588,56 @@ fn recover(
     // Finally, we collapse the WAL file.
     wal_fd.set_len(0)?;
 
-    Ok(())
+    let buckets_updated = bucket_updates.len();
+    report_progress(buckets_updated);
+    Ok(Some(RecoveryReport {
+        sync_seqn,
+        wal_bytes,
+        entries,
+        buckets_cleared,
+        buckets_updated,
+        bytes_written: ((buckets_updated + meta_pages_written) * PAGE_SIZE) as u64,
+        elapsed: start.elapsed(),
+    }))
+}
+
+/// Build the filter over the pages stored in the hash-table, by reading the page ID of every
+/// occupied bucket. The buckets are split among `recovery_concurrency` threads.
+fn build_presence_filter(
+    ht_fd: &File,
+    page_pool: &PagePool,
+    ht_offsets: &HTOffsets,
+    meta_map: &MetaMap,
+    options: &DbOptions,
+) -> anyhow::Result<PresenceFilter> {
+    let presence_filter = PresenceFilter::new(meta_map.len());
+    let occupied = (0..meta_map.len())
+        .filter(|&bucket| !meta_map.hint_empty(bucket) && !meta_map.hint_tombstone(bucket))
+        .collect::<Vec<_>>();
+    let chunk_len = std::cmp::max(1, occupied.len().div_ceil(options.recovery_concurrency));
+    std::thread::scope(|scope| {
+        let handles = occupied
+            .chunks(chunk_len)
+            .map(|chunk| {
+                let presence_filter = &presence_filter;
+                scope.spawn(move || -> anyhow::Result<()> {
+                    for &bucket in chunk {
+                        let pn = ht_offsets.data_page_index(bucket as u64);
+                        let page = io::read_page(page_pool, ht_fd, pn)?;
+                        // UNWRAP: the slice is 32 bytes long.
+                        let page_id = page[PAGE_SIZE - 32..].try_into().unwrap();
+                        presence_filter.insert(hash_raw_page_id(page_id, &options.seed));
+                    }
+                    Ok(())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .try_for_each(|handle| handle.join().unwrap())
+    })?;
+    Ok(presence_filter)
 }
 
 pub struct WriteoutData {
@@ -257,6 +645,9 @@ pub struct WriteoutData {
     pub ht_pages: Vec<(u64, FatPage)>,
     /// The WAL blob to write out to the WAL file.
     pub wal_blob: (*mut u8, usize),
+    /// The offset in the WAL file to write the blob at. The part of the WAL before it was written
+    /// while building the blob.
+    pub wal_offset: u64,
 }
 
 // TODO: remove this once we split up the writeout logic.
@@ -283,7 +674,7 @@ impl PageLoader {
     /// Create a new page load.
     pub fn start_load(&self, page_id: PageId) -> PageLoad {
         PageLoad {
-            probe_sequence: ProbeSequence::new(&page_id, &self.meta_map, &self.shared.seed),
+            probe_sequence: ProbeSequence::new(&page_id, &self.meta_map, &self.shared),
             page_id,
             state: PageLoadState::Pending,
         }
@@ -302,6 +693,12 @@ impl PageLoader {
         load: &mut PageLoad,
         user_data: u64,
     ) -> anyhow::Result<bool> {
+        if let Some(ref presence_filter) = self.shared.presence_filter {
+            if !presence_filter.may_contain(load.probe_sequence.hash) {
+                return Ok(false);
+            }
+        }
+
         let bucket = loop {
             match load.probe_sequence.next(&self.meta_map) {
                 ProbeResult::Tombstone(_) => continue,
@@ -329,16 +726,20 @@ impl PageLoader {
 
     /// Try to receive the next completion, without blocking the current thread.
     ///
-    /// Fails if the I/O pool is down or a request caused an I/O error.
+    /// Fails if the I/O pool is down, a request caused an I/O error or the page doesn't match its
+    /// checksum.
     pub fn try_complete(&self) -> anyhow::Result<Option<PageLoadCompletion>> {
         match self.io_handle.try_recv() {
             Ok(completion) => {
                 completion.result?;
                 match completion.command.kind {
-                    IoKind::Read(_, _, page) => Ok(Some(PageLoadCompletion {
-                        page,
-                        user_data: completion.command.user_data,
-                    })),
+                    IoKind::Read(_, pn, page) => {
+                        self.check_page(pn, &page)?;
+                        Ok(Some(PageLoadCompletion {
+                            page,
+                            user_data: completion.command.user_data,
+                        }))
+                    }
                     _ => panic!(),
                 }
             }
@@ -349,16 +750,20 @@ impl PageLoader {
 
     /// Receive the next completion, blocking the current thread.
     ///
-    /// Fails if the I/O pool is down or a request caused an I/O error.
+    /// Fails if the I/O pool is down, a request caused an I/O error or the page doesn't match its
+    /// checksum.
     pub fn complete(&self) -> anyhow::Result<PageLoadCompletion> {
         match self.io_handle.recv() {
             Ok(completion) => {
                 completion.result?;
                 match completion.command.kind {
-                    IoKind::Read(_, _, page) => Ok(PageLoadCompletion {
-                        page,
-                        user_data: completion.command.user_data,
-                    }),
+                    IoKind::Read(_, pn, page) => {
+                        self.check_page(pn, &page)?;
+                        Ok(PageLoadCompletion {
+                            page,
+                            user_data: completion.command.user_data,
+                        })
+                    }
                     _ => panic!(),
                 }
             }
@@ -370,6 +775,15 @@ impl PageLoader {
     pub fn io_handle(&self) -> &IoHandle {
         &self.io_handle
     }
+
+    // Only pages written by syncs are loaded, so every loaded page carries a checksum, whether or
+    // not it is the page being probed for.
+    fn check_page(&self, pn: u64, page: &FatPage) -> anyhow::Result<()> {
+        if self.shared.verify_checksums {
+            checksum::check(page, CHECKSUM_OFFSET, "ht", pn)?;
+        }
+        Ok(())
+    }
 }
 
 /// Represents the completion of a page load.
@@ -430,7 +844,7 @@ impl BucketAllocator {
     /// or pages may silently disappear later.
     pub fn allocate(&mut self, page_id: PageId) -> BucketIndex {
         let meta_map = self.shared.meta_map.read();
-        let mut probe_seq = ProbeSequence::new(&page_id, &meta_map, &self.shared.seed);
+        let mut probe_seq = ProbeSequence::new(&page_id, &meta_map, &self.shared);
 
         let mut i = 0;
         loop {
@@ -442,6 +856,7 @@ impl BucketAllocator {
                     // unless some other page has taken the bucket, fill it.
                     if self.changed_buckets.get(&bucket).map_or(true, |full| !full) {
                         self.changed_buckets.insert(bucket, true);
+                        self.shared.placement_probes.record(probe_seq.step);
                         return BucketIndex(bucket);
                     }
                 }
@@ -482,11 +897,13 @@ enum ProbeResult {
 }
 
 impl ProbeSequence {
-    fn new(page_id: &PageId, meta_map: &MetaMap, seed: &[u8; 16]) -> Self {
-        let hash = hash_page_id(page_id, seed);
+    fn new(page_id: &PageId, meta_map: &MetaMap, shared: &Shared) -> Self {
+        let hash = hash_page_id(page_id, &shared.seed);
         Self {
             hash,
-            bucket: hash % meta_map.len() as u64,
+            bucket: shared
+                .mapping
+                .home_bucket(page_id, hash, meta_map.len() as u64),
             step: 0,
         }
     }
@@ -0,0 +1,106 @@
+//! An in-memory filter over the pages stored in the hash-table, see
+//! [`crate::Options::presence_filter`].
+//!
+//! The meta map tells which buckets are occupied, along with a few bits of the hash of the page
+//! stored in each, so looking up a page which isn't stored still walks the probe sequence up to an
+//! empty bucket, and reads every bucket along the way whose hint matches by chance. The filter is
+//! a blocked bloom filter over the full hashes of the stored pages, which rules out most such
+//! lookups before probing at all.
+//!
+//! Pages are added as they are stored but never removed, so the filter gets less selective as
+//! pages are deleted, until it is rebuilt when the database is opened again.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The number of bits of the filter per bucket of the hash-table.
+const BITS_PER_BUCKET: usize = 16;
+
+/// The number of 64-bit words of a block. Every page sets bits within a single block, which spans
+/// one cache line.
+const BLOCK_WORDS: usize = 8;
+
+/// The number of bits set by every page.
+const BITS_PER_PAGE: usize = 6;
+
+pub struct PresenceFilter {
+    blocks: Vec<[AtomicU64; BLOCK_WORDS]>,
+}
+
+impl PresenceFilter {
+    /// Create an empty filter sized for a hash-table with the given number of buckets.
+    pub fn new(buckets: usize) -> Self {
+        let num_blocks = (buckets * BITS_PER_BUCKET)
+            .div_ceil(BLOCK_WORDS * 64)
+            .max(1);
+        PresenceFilter {
+            blocks: (0..num_blocks)
+                .map(|_| std::array::from_fn(|_| AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Add the page with the given hash.
+    pub fn insert(&self, hash: u64) {
+        let (block, mask) = self.locate(hash);
+        for (word, mask) in block.iter().zip(mask) {
+            if mask != 0 {
+                word.fetch_or(mask, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Whether the page with the given hash may have been added. `false` means it wasn't.
+    pub fn may_contain(&self, hash: u64) -> bool {
+        let (block, mask) = self.locate(hash);
+        block
+            .iter()
+            .zip(mask)
+            .all(|(word, mask)| word.load(Ordering::Relaxed) & mask == mask)
+    }
+
+    // The block of the page, chosen by the high bits of the hash, and the bits it sets in the
+    // block, chosen by the bits of a remix of the hash.
+    fn locate(&self, hash: u64) -> (&[AtomicU64; BLOCK_WORDS], [u64; BLOCK_WORDS]) {
+        let block = ((hash as u128 * self.blocks.len() as u128) >> 64) as usize;
+        let mut bits = hash.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let mut mask = [0u64; BLOCK_WORDS];
+        for _ in 0..BITS_PER_PAGE {
+            let bit = (bits >> 55) as usize;
+            mask[bit / 64] |= 1 << (bit % 64);
+            bits <<= 9;
+        }
+        (&self.blocks[block], mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PresenceFilter;
+
+    fn hash(i: u64) -> u64 {
+        let hash = blake3::hash(&i.to_le_bytes());
+        u64::from_le_bytes(hash.as_bytes()[..8].try_into().unwrap())
+    }
+
+    #[test]
+    fn no_false_negatives() {
+        let filter = PresenceFilter::new(10_000);
+        for i in 0..9_000 {
+            filter.insert(hash(i));
+        }
+        assert!((0..9_000).all(|i| filter.may_contain(hash(i))));
+    }
+
+    #[test]
+    fn few_false_positives() {
+        // A table loaded to 90%.
+        let filter = PresenceFilter::new(10_000);
+        for i in 0..9_000 {
+            filter.insert(hash(i));
+        }
+        let false_positives = (9_000..109_000)
+            .filter(|i| filter.may_contain(hash(*i)))
+            .count();
+        assert!(false_positives < 1_000, "{false_positives} false positives");
+    }
+}
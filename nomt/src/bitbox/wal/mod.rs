@@ -1,6 +1,31 @@
+//! The WAL of the hash-table.
+//!
+//! The WAL blob is a sequence of entries, starting with a start entry and terminated by an end
+//! entry. Every entry is laid out as `tag | body | seqn | checksum`, where `seqn` is the index of
+//! the entry within the blob as a little-endian `u32` and `checksum` is the xxh3 hash of the rest
+//! of the entry, seeded with the sequence number of the sync which wrote the blob, as a
+//! little-endian `u64`. This lets the reader stop at the first entry torn by an interrupted write,
+//! even if it is followed by intact entries left over from an older sync.
+//!
+//! With compression, the entries following the start entry are held by a single compressed entry
+//! instead, laid out as `tag | len | compressed_len | compressed | seqn | checksum` where the
+//! lengths are little-endian `u64`s and `compressed` is the lz4 block of the `len` bytes of the
+//! entries. The start entry stays uncompressed so that the sync a blob belongs to can be told
+//! without reading the whole of it.
+
 const WAL_ENTRY_TAG_END: u8 = 0;
 const WAL_ENTRY_TAG_CLEAR: u8 = 1;
 const WAL_ENTRY_TAG_UPDATE: u8 = 2;
+const WAL_ENTRY_TAG_START: u8 = 3;
+const WAL_ENTRY_TAG_COMPRESSED: u8 = 4;
+
+/// The length of the start entry: the tag, the sync sequence number and the trailer.
+const START_ENTRY_LEN: usize = 1 + 4 + 4 + 8;
+
+/// Compute the checksum of an entry, excluding the checksum itself.
+fn entry_checksum(entry: &[u8], sync_seqn: u32) -> u64 {
+    xxhash_rust::xxh3::xxh3_64_with_seed(entry, sync_seqn as u64)
+}
 
 pub use read::{WalBlobReader, WalEntry};
 pub use write::WalBlobBuilder;
@@ -8,5 +33,10 @@ pub use write::WalBlobBuilder;
 mod read;
 mod write;
 
+/// Whether the given bytes are a start entry of the sync with the given sequence number.
+pub fn is_start_of(bytes: &[u8; 5], sync_seqn: u32) -> bool {
+    bytes[0] == WAL_ENTRY_TAG_START && bytes[1..] == sync_seqn.to_le_bytes()
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,6 +1,9 @@
 //! The read-path for the WAL.
 
-use super::{WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_UPDATE};
+use super::{
+    entry_checksum, WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_COMPRESSED, WAL_ENTRY_TAG_END,
+    WAL_ENTRY_TAG_START, WAL_ENTRY_TAG_UPDATE,
+};
 use crate::{
     io::{self, PagePool, PAGE_SIZE},
     page_diff::PageDiff,
@@ -10,6 +13,10 @@ use std::{fs::File, io::Seek};
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum WalEntry {
+    Start {
+        /// The sequence number of the sync which wrote the WAL.
+        sync_seqn: u32,
+    },
     Update {
         /// The unique identifier of the page being updated.
         page_id: [u8; 32],
@@ -31,6 +38,16 @@ pub enum WalEntry {
 pub struct WalBlobReader {
     wal: Vec<u8>,
     offset: usize,
+    /// The sequence number of the next entry within the blob.
+    entry_seqn: u32,
+    /// The sequence number of the sync which wrote the blob, as read from the start entry.
+    sync_seqn: u32,
+    /// Whether the end entry or an invalid entry was reached.
+    done: bool,
+    /// Whether the end entry was reached.
+    complete: bool,
+    /// Whether `wal` holds the entries of a compressed entry rather than the blob as read.
+    decompressed: bool,
 }
 
 impl WalBlobReader {
@@ -61,16 +78,110 @@ impl WalBlobReader {
             wal.extend_from_slice(&*page);
         }
 
-        Ok(Self { wal, offset: 0 })
+        Ok(Self {
+            wal,
+            offset: 0,
+            entry_seqn: 0,
+            sync_seqn: 0,
+            done: false,
+            complete: false,
+            decompressed: false,
+        })
     }
 
     /// Reads the next entry from the WAL file.
     ///
-    /// Returns `None` if the end of the file is reached.
-    pub fn read_entry(&mut self) -> anyhow::Result<Option<WalEntry>> {
+    /// Returns `None` once the end entry is reached, or at the first invalid entry: one which is
+    /// malformed, cut short, out of sequence or doesn't match its checksum, e.g. because the write
+    /// of the WAL was torn. No entries are returned after that.
+    pub fn read_entry(&mut self) -> Option<WalEntry> {
+        if self.done {
+            return None;
+        }
+        let entry = self.try_read_entry();
+        self.complete = matches!(entry, Ok(None));
+        let entry = entry.ok().flatten();
+        self.done = entry.is_none();
+        entry
+    }
+
+    /// Whether reading stopped at the end entry, as opposed to an invalid entry, i.e. whether the
+    /// entries returned are the whole blob.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Reads the next entry, failing if it is invalid. Returns `None` for the end entry.
+    fn try_read_entry(&mut self) -> anyhow::Result<Option<WalEntry>> {
+        if self.wal.get(self.offset) == Some(&WAL_ENTRY_TAG_COMPRESSED) {
+            self.decompress()?;
+        }
+
+        let entry_start = self.offset;
+        let entry = self.read_entry_body()?;
+
+        if let Some(WalEntry::Start { sync_seqn }) = entry {
+            if self.entry_seqn != 0 {
+                bail!("unexpected start entry in the middle of the WAL");
+            }
+            self.sync_seqn = sync_seqn;
+        }
+
+        self.read_trailer(entry_start)?;
+        self.entry_seqn += 1;
+        Ok(entry)
+    }
+
+    /// Reads the sequence number and the checksum of the entry starting at the given offset,
+    /// failing if either doesn't match.
+    fn read_trailer(&mut self, entry_start: usize) -> anyhow::Result<()> {
+        let entry_seqn = u32::from_le_bytes(self.read_buf()?);
+        if entry_seqn != self.entry_seqn {
+            bail!("WAL entry {entry_seqn} is out of sequence");
+        }
+        let checksum_offset = self.offset;
+        let checksum = self.read_u64()?;
+        if checksum != entry_checksum(&self.wal[entry_start..checksum_offset], self.sync_seqn) {
+            bail!("WAL entry {entry_seqn} doesn't match its checksum");
+        }
+        Ok(())
+    }
+
+    /// Reads the compressed entry at the current offset and continues with the entries it holds
+    /// in place of the rest of the blob. It may only follow the start entry.
+    fn decompress(&mut self) -> anyhow::Result<()> {
+        if self.entry_seqn != 1 || self.decompressed {
+            bail!("unexpected compressed entry in the WAL");
+        }
+        let entry_start = self.offset;
+        self.offset += 1;
+        let len = self.read_u64()? as usize;
+        let compressed_len = self.read_u64()? as usize;
+        let compressed_start = self.offset;
+        if compressed_len > self.wal.len() - compressed_start {
+            bail!("Unexpected end of WAL file");
+        }
+        self.offset += compressed_len;
+        // The entries held by the compressed entry are numbered from its own sequence number on.
+        self.read_trailer(entry_start)?;
+
+        let compressed = &self.wal[compressed_start..compressed_start + compressed_len];
+        self.wal = lz4_flex::block::decompress(compressed, len)
+            .map_err(|e| anyhow::anyhow!("failed to decompress the WAL: {e}"))?;
+        self.offset = 0;
+        self.decompressed = true;
+        Ok(())
+    }
+
+    /// Reads the tag and the body of the next entry.
+    fn read_entry_body(&mut self) -> anyhow::Result<Option<WalEntry>> {
         let entry_tag = self.read_byte()?;
         match entry_tag {
             WAL_ENTRY_TAG_END => Ok(None),
+            WAL_ENTRY_TAG_START => {
+                let sync_seqn = u32::from_le_bytes(self.read_buf()?);
+                Ok(Some(WalEntry::Start { sync_seqn }))
+            }
             WAL_ENTRY_TAG_CLEAR => {
                 let bucket = self.read_u64()?;
                 Ok(Some(WalEntry::Clear { bucket }))
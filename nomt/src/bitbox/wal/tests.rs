@@ -1,4 +1,4 @@
-use super::{WalBlobBuilder, WalBlobReader, WalEntry};
+use super::{WalBlobBuilder, WalBlobReader, WalEntry, WAL_ENTRY_TAG_COMPRESSED};
 use crate::{io::page_pool::PagePool, page_diff::PageDiff};
 use std::{fs::OpenOptions, io::Write as _};
 
@@ -13,40 +13,47 @@ fn test_write_read() {
         options.open(&wal_filename).unwrap()
     };
 
-    let mut builder = WalBlobBuilder::new().unwrap();
-    builder.write_clear(0);
-    builder.write_update(
-        [0; 32],
-        &PageDiff::from_bytes(hex_literal::hex!(
-            "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
-        ))
-        .unwrap(),
-        vec![].into_iter(),
-        0,
-    );
-    builder.write_clear(1);
-    builder.write_update(
-        [1; 32],
-        &PageDiff::from_bytes(hex_literal::hex!(
-            "01 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
-        ))
-        .unwrap(),
-        vec![[1; 32]].into_iter(),
-        1,
-    );
-    builder.write_update(
-        [2; 32],
-        &{
-            let mut diff = PageDiff::default();
-            for i in 0..126 {
-                diff.set_changed(i);
-            }
-            diff
-        },
-        (0..126).map(|x| [x; 32]),
-        2,
-    );
-    let (ptr, len) = builder.finalize();
+    let mut builder = WalBlobBuilder::new(false, None).unwrap();
+    builder.write_start(7).unwrap();
+    builder.write_clear(0).unwrap();
+    builder
+        .write_update(
+            [0; 32],
+            &PageDiff::from_bytes(hex_literal::hex!(
+                "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+            ))
+            .unwrap(),
+            vec![].into_iter(),
+            0,
+        )
+        .unwrap();
+    builder.write_clear(1).unwrap();
+    builder
+        .write_update(
+            [1; 32],
+            &PageDiff::from_bytes(hex_literal::hex!(
+                "01 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00"
+            ))
+            .unwrap(),
+            vec![[1; 32]].into_iter(),
+            1,
+        )
+        .unwrap();
+    builder
+        .write_update(
+            [2; 32],
+            &{
+                let mut diff = PageDiff::default();
+                for i in 0..126 {
+                    diff.set_changed(i);
+                }
+                diff
+            },
+            (0..126).map(|x| [x; 32]),
+            2,
+        )
+        .unwrap();
+    let (ptr, len) = builder.finalize().unwrap();
     wal_fd
         .write_all(unsafe { std::slice::from_raw_parts(ptr, len) })
         .unwrap();
@@ -54,12 +61,10 @@ fn test_write_read() {
 
     let page_pool = PagePool::new();
     let mut reader = WalBlobReader::new(&page_pool, &wal_fd).unwrap();
+    assert_eq!(reader.read_entry(), Some(WalEntry::Start { sync_seqn: 7 }));
+    assert_eq!(reader.read_entry(), Some(WalEntry::Clear { bucket: 0 }));
     assert_eq!(
-        reader.read_entry().unwrap(),
-        Some(WalEntry::Clear { bucket: 0 })
-    );
-    assert_eq!(
-        reader.read_entry().unwrap(),
+        reader.read_entry(),
         Some(WalEntry::Update {
             page_id: [0; 32],
             page_diff: PageDiff::default(),
@@ -67,12 +72,9 @@ fn test_write_read() {
             bucket: 0,
         })
     );
+    assert_eq!(reader.read_entry(), Some(WalEntry::Clear { bucket: 1 }));
     assert_eq!(
-        reader.read_entry().unwrap(),
-        Some(WalEntry::Clear { bucket: 1 })
-    );
-    assert_eq!(
-        reader.read_entry().unwrap(),
+        reader.read_entry(),
         Some(WalEntry::Update {
             page_id: [1; 32],
             page_diff: {
@@ -85,7 +87,7 @@ fn test_write_read() {
         })
     );
     assert_eq!(
-        reader.read_entry().unwrap(),
+        reader.read_entry(),
         Some(WalEntry::Update {
             page_id: [2; 32],
             page_diff: {
@@ -99,5 +101,132 @@ fn test_write_read() {
             bucket: 2,
         })
     );
-    assert_eq!(reader.read_entry().unwrap(), None);
+    assert_eq!(reader.read_entry(), None);
+    assert!(reader.is_complete());
+}
+
+// Build a blob of a start entry followed by clear entries for the given buckets.
+fn build_blob(sync_seqn: u32, buckets: impl IntoIterator<Item = u64>) -> Vec<u8> {
+    build_blob_with(sync_seqn, buckets, false)
+}
+
+fn build_blob_with(
+    sync_seqn: u32,
+    buckets: impl IntoIterator<Item = u64>,
+    compress: bool,
+) -> Vec<u8> {
+    let mut builder = WalBlobBuilder::new(compress, None).unwrap();
+    builder.write_start(sync_seqn).unwrap();
+    for bucket in buckets {
+        builder.write_clear(bucket).unwrap();
+    }
+    let (ptr, len) = builder.finalize().unwrap();
+    unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()
+}
+
+// Read the entries of the blob, along with whether reading stopped at the end entry.
+fn read_blob(blob: &[u8]) -> (Vec<WalEntry>, bool) {
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut wal_fd = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(tempdir.path().join("wal"))
+        .unwrap();
+    wal_fd.write_all(blob).unwrap();
+
+    let page_pool = PagePool::new();
+    let mut reader = WalBlobReader::new(&page_pool, &wal_fd).unwrap();
+    let entries = std::iter::from_fn(|| reader.read_entry()).collect();
+    (entries, reader.is_complete())
+}
+
+fn clears(buckets: impl IntoIterator<Item = u64>) -> Vec<WalEntry> {
+    buckets
+        .into_iter()
+        .map(|bucket| WalEntry::Clear { bucket })
+        .collect()
+}
+
+// The size of a clear entry: tag, bucket, entry sequence number and checksum.
+const CLEAR_ENTRY_SIZE: usize = 1 + 8 + 4 + 8;
+// The size of a start entry: tag, sync sequence number, entry sequence number and checksum.
+const START_ENTRY_SIZE: usize = 1 + 4 + 4 + 8;
+
+#[test]
+fn stops_at_corrupted_entry() {
+    let mut blob = build_blob(3, 0..10);
+    // Flip a bit in the bucket of the 5th clear entry.
+    blob[START_ENTRY_SIZE + 4 * CLEAR_ENTRY_SIZE + 1] ^= 1;
+
+    let mut expected = vec![WalEntry::Start { sync_seqn: 3 }];
+    expected.extend(clears(0..4));
+    assert_eq!(read_blob(&blob), (expected, false));
+}
+
+#[test]
+fn reads_whole_blob() {
+    let mut expected = vec![WalEntry::Start { sync_seqn: 3 }];
+    expected.extend(clears(0..10));
+    assert_eq!(read_blob(&build_blob(3, 0..10)), (expected, true));
+}
+
+#[test]
+fn stops_at_torn_write() {
+    // A torn write of the blob of sync 2 over the blob of sync 1: the tail of the old blob
+    // follows the entries which made it to disk.
+    let old = build_blob(1, 0..100);
+    let new = build_blob(2, 100..200);
+    let torn_at = START_ENTRY_SIZE + 10 * CLEAR_ENTRY_SIZE + 5;
+    let mut blob = new[..torn_at].to_vec();
+    blob.extend_from_slice(&old[torn_at..]);
+
+    let mut expected = vec![WalEntry::Start { sync_seqn: 2 }];
+    expected.extend(clears(100..110));
+    let (entries, complete) = read_blob(&blob);
+    assert_eq!(entries, expected);
+    assert!(!complete);
+
+    // Entries of the old blob which line up with the new ones are rejected too.
+    let torn_at = START_ENTRY_SIZE + 10 * CLEAR_ENTRY_SIZE;
+    let mut blob = new[..torn_at].to_vec();
+    blob.extend_from_slice(&old[torn_at..]);
+    assert_eq!(read_blob(&blob), (expected, false));
+}
+
+#[test]
+fn stops_at_truncated_blob() {
+    let blob = build_blob(5, 0..10);
+    let mut truncated = blob[..START_ENTRY_SIZE + 3 * CLEAR_ENTRY_SIZE - 1].to_vec();
+    truncated.resize(blob.len(), 0);
+
+    let mut expected = vec![WalEntry::Start { sync_seqn: 5 }];
+    expected.extend(clears(0..2));
+    assert_eq!(read_blob(&truncated), (expected, false));
+}
+
+#[test]
+fn reads_compressed_blob() {
+    let blob = build_blob_with(3, 0..1000, true);
+    assert_eq!(blob[START_ENTRY_SIZE], WAL_ENTRY_TAG_COMPRESSED);
+    assert!(blob.len() < build_blob(3, 0..1000).len());
+
+    let mut expected = vec![WalEntry::Start { sync_seqn: 3 }];
+    expected.extend(clears(0..1000));
+    assert_eq!(read_blob(&blob), (expected, true));
+}
+
+#[test]
+fn stores_incompressible_blob_as_is() {
+    assert_eq!(build_blob_with(3, 0..1, true), build_blob(3, 0..1));
+}
+
+#[test]
+fn stops_at_corrupted_compressed_blob() {
+    let mut blob = build_blob_with(3, 0..1000, true);
+    // Flip a bit in the compressed entries.
+    blob[START_ENTRY_SIZE + 100] ^= 1;
+
+    let expected = vec![WalEntry::Start { sync_seqn: 3 }];
+    assert_eq!(read_blob(&blob), (expected, false));
 }
@@ -1,9 +1,19 @@
 //! The write-path for the WAL.
 
-use super::{WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_UPDATE};
+use super::{
+    entry_checksum, START_ENTRY_LEN, WAL_ENTRY_TAG_CLEAR, WAL_ENTRY_TAG_COMPRESSED,
+    WAL_ENTRY_TAG_END, WAL_ENTRY_TAG_START, WAL_ENTRY_TAG_UPDATE,
+};
 use crate::{io::PAGE_SIZE, page_diff::PageDiff};
+use std::{fs::File, os::unix::fs::FileExt as _};
 
-const MAX_SIZE: usize = 1 << 37; // 128 GiB
+/// The size of the mapping the blob starts out with, and is shrunk back to after a sync which
+/// grew it.
+const INITIAL_SIZE: usize = 64 << 20; // 64 MiB
+
+/// The size of the blob above which the builder flushes the full pages built so far to the WAL
+/// file, if it has one.
+const FLUSH_THRESHOLD: usize = INITIAL_SIZE / 2;
 
 struct Mmap {
     ptr: *mut u8,
@@ -71,27 +81,106 @@ impl Drop for Mmap {
 }
 
 /// A builder for a WAL blob.
+///
+/// The blob is built in an anonymous mapping, which grows as needed. Failing to grow it fails the
+/// write, and so the sync, rather than the process.
+///
+/// Given the WAL file, the builder bounds the memory it takes: once the blob outgrows
+/// [`FLUSH_THRESHOLD`], the full pages before the entry being written are written to the file and
+/// dropped from the mapping. [`Self::finalize`] then returns the rest of the blob, which goes
+/// after the [`Self::flushed`] bytes. Nothing is synced, so the file is synced once, after the
+/// rest is written. A blob which is compressed is built whole in memory.
 pub struct WalBlobBuilder {
     mmap: Mmap,
     /// The position at which the next byte will be written. Never reaches `mmap.size`.
     cur: usize,
+    /// The position at which the entry being written starts.
+    entry_start: usize,
+    /// The sequence number of the next entry within the blob.
+    entry_seqn: u32,
+    /// The sequence number of the sync the blob belongs to, which seeds the entry checksums.
+    sync_seqn: u32,
+    /// Whether the entries following the start entry are compressed when the blob is finalized.
+    compress: bool,
+    /// The WAL file the blob is flushed to, if any.
+    wal_fd: Option<File>,
+    /// The size of the blob above which it is flushed.
+    flush_threshold: usize,
+    /// The number of bytes of the blob flushed to the WAL file. The mapping holds the rest.
+    flushed: u64,
+    /// Whether flushing the blob failed. The rest of the blob is then kept in memory, and the
+    /// error is left to writing it out after [`Self::finalize`].
+    flush_failed: bool,
+    /// The size past which growing the mapping fails, as if the memory ran out. See
+    /// [`Self::limit_size`].
+    #[cfg(test)]
+    size_limit: usize,
 }
 
 impl WalBlobBuilder {
-    pub fn new() -> anyhow::Result<Self> {
-        Self::with_initial_size(1 << 30)
+    /// Create a builder, which flushes the blobs it builds to the given WAL file, if any, unless
+    /// they are compressed.
+    pub fn new(compress: bool, wal_fd: Option<File>) -> anyhow::Result<Self> {
+        let mut builder = Self::with_initial_size(INITIAL_SIZE)?;
+        builder.compress = compress;
+        builder.wal_fd = wal_fd.filter(|_| !compress);
+        Ok(builder)
     }
 
     fn with_initial_size(size: usize) -> anyhow::Result<Self> {
         let mmap = Mmap::new(size)?;
-        Ok(Self { mmap, cur: 0 })
+        Ok(Self {
+            mmap,
+            cur: 0,
+            entry_start: 0,
+            entry_seqn: 0,
+            sync_seqn: 0,
+            compress: false,
+            wal_fd: None,
+            flush_threshold: FLUSH_THRESHOLD,
+            flushed: 0,
+            flush_failed: false,
+            #[cfg(test)]
+            size_limit: usize::MAX,
+        })
+    }
+
+    /// Shrink the mapping to `size` bytes, and fail to grow it past that from now on.
+    #[cfg(test)]
+    pub fn limit_size(&mut self, size: usize) {
+        self.mmap = Mmap::new(size).unwrap();
+        self.cur = 0;
+        self.size_limit = size;
     }
 
-    pub fn write_clear(&mut self, bucket_index: u64) {
+    /// Writes the entry identifying the sync this blob belongs to. This must be the first entry.
+    ///
+    /// This discards whatever was written since the last [`Self::finalize`], e.g. by a sync which
+    /// failed halfway, and gives back the memory a larger blob of the last sync grew the mapping
+    /// by.
+    pub fn write_start(&mut self, sync_seqn: u32) -> anyhow::Result<()> {
+        self.cur = 0;
+        self.entry_seqn = 0;
+        self.flushed = 0;
+        self.flush_failed = false;
+        if self.mmap.size > INITIAL_SIZE {
+            // Failing to shrink only leaves the memory mapped.
+            let _ = self.mmap.resize(INITIAL_SIZE);
+        }
+        self.sync_seqn = sync_seqn;
+        self.begin_entry(WAL_ENTRY_TAG_START)?;
         unsafe {
-            self.write_byte(WAL_ENTRY_TAG_CLEAR);
-            self.write(&bucket_index.to_le_bytes());
+            self.write(&sync_seqn.to_le_bytes())?;
         }
+        self.finish_entry()
+    }
+
+    pub fn write_clear(&mut self, bucket_index: u64) -> anyhow::Result<()> {
+        self.begin_entry(WAL_ENTRY_TAG_CLEAR)?;
+        unsafe {
+            self.write(&bucket_index.to_le_bytes())?;
+        }
+        self.finish_entry()
     }
 
     pub fn write_update(
@@ -100,79 +189,185 @@ impl WalBlobBuilder {
         page_diff: &PageDiff,
         changed: impl Iterator<Item = [u8; 32]>,
         bucket_index: u64,
-    ) {
+    ) -> anyhow::Result<()> {
+        self.begin_entry(WAL_ENTRY_TAG_UPDATE)?;
         unsafe {
             // SAFETY: Those do not overlap with the mmap.
-            self.write_byte(WAL_ENTRY_TAG_UPDATE);
-            self.write(&page_id);
-            self.write(&page_diff.as_bytes());
+            self.write(&page_id)?;
+            self.write(&page_diff.as_bytes())?;
             for changed in changed {
-                self.write(&changed);
+                self.write(&changed)?;
             }
-            self.write(&bucket_index.to_le_bytes());
+            self.write(&bucket_index.to_le_bytes())?;
+        }
+        self.finish_entry()
+    }
+
+    /// The number of bytes of the blob being built, or last finalized, which were written to the
+    /// WAL file. The blob returned by [`Self::finalize`] follows them.
+    pub fn flushed(&self) -> u64 {
+        self.flushed
+    }
+
+    fn begin_entry(&mut self, tag: u8) -> anyhow::Result<()> {
+        if self.cur >= self.flush_threshold {
+            self.flush();
+        }
+        self.entry_start = self.cur;
+        self.write_byte(tag)
+    }
+
+    /// Write the full pages of the blob to the WAL file and drop them from the mapping. Must not be
+    /// called while an entry is being written.
+    fn flush(&mut self) {
+        let Some(ref wal_fd) = self.wal_fd else {
+            return;
+        };
+        if self.flush_failed {
+            return;
+        }
+        let len = self.cur / PAGE_SIZE * PAGE_SIZE;
+        // SAFETY: The blob was written into the mmap, up to `cur`.
+        let pages = unsafe { std::slice::from_raw_parts(self.mmap.ptr, len) };
+        if wal_fd.write_all_at(pages, self.flushed).is_err() {
+            // The pages may have been written in part, so they stay in memory, to be written
+            // again along with the rest of the blob.
+            self.flush_failed = true;
+            return;
+        }
+        unsafe {
+            // SAFETY: Both ranges lie within the mmap. The start stays aligned to the page size.
+            std::ptr::copy(self.mmap.ptr.add(len), self.mmap.ptr, self.cur - len);
+        }
+        self.cur -= len;
+        self.flushed += len as u64;
+    }
+
+    /// Write the trailer of the entry started by the last call to `begin_entry`.
+    fn finish_entry(&mut self) -> anyhow::Result<()> {
+        let entry_seqn = self.entry_seqn;
+        self.entry_seqn += 1;
+        unsafe {
+            // SAFETY: This slice trivially does not overlap with the mmap.
+            self.write(&entry_seqn.to_le_bytes())?;
+        }
+
+        // SAFETY: The entry was written into the mmap, between `entry_start` and `cur`.
+        let entry = unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.ptr.add(self.entry_start),
+                self.cur - self.entry_start,
+            )
+        };
+        let checksum = entry_checksum(entry, self.sync_seqn);
+        unsafe {
+            // SAFETY: This slice trivially does not overlap with the mmap.
+            self.write(&checksum.to_le_bytes())
         }
     }
 
-    fn write_byte(&mut self, byte: u8) {
+    fn write_byte(&mut self, byte: u8) -> anyhow::Result<()> {
         unsafe {
             // SAFETY: This slice trivially does not overlap with the mmap.
-            self.write(&[byte]);
+            self.write(&[byte])
         }
     }
 
+    /// Fails if the mmap can't be grown to fit the bytes, leaving the blob as it was.
+    ///
     /// # Safety
     ///
     /// The `bytes` mut not overlap with the mmap.
-    unsafe fn write(&mut self, bytes: &[u8]) {
+    unsafe fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
         let pos = self.cur;
-        let new_cur = self.cur.checked_add(bytes.len()).unwrap();
+        let new_cur = self
+            .cur
+            .checked_add(bytes.len())
+            .ok_or_else(|| anyhow::anyhow!("WAL blob too large"))?;
         if new_cur >= self.mmap.size {
-            // Grow once to the required size
-            if let Err(e) = self.grow(new_cur) {
-                panic!("WAL blob too large: {e}");
-            }
+            self.grow(new_cur + 1)?;
         }
         // NB: `self.mmap` could have changed after the grow.
         self.cur = new_cur;
         unsafe {
             std::ptr::copy_nonoverlapping(bytes.as_ptr(), self.mmap.ptr.add(pos), bytes.len());
         }
+        Ok(())
     }
 
     /// Grow the mmap to at least `min_new_size` bytes.
     ///
-    /// Since this grows by at least doubling the current size, the resulting size might be
-    /// larger than `min_new_size`.
-    ///
-    /// Returns `Ok(())` if the mmap was successfully resized.
+    /// This tries to at least double the current size first, so that the resulting size might be
+    /// larger than `min_new_size`, and settles for `min_new_size` rounded up to the page size if
+    /// that fails.
     #[cold]
     fn grow(&mut self, min_new_size: usize) -> anyhow::Result<()> {
-        if self.mmap.size >= MAX_SIZE {
-            anyhow::bail!("WAL blob too large (exceeded 128 GiB limit)");
+        let min_new_size = min_new_size
+            .checked_next_multiple_of(PAGE_SIZE)
+            .ok_or_else(|| anyhow::anyhow!("WAL blob too large"))?;
+        let doubled = self.mmap.size.saturating_mul(2).max(min_new_size);
+        if self.grow_to(doubled).is_ok() {
+            return Ok(());
         }
+        self.grow_to(min_new_size).map_err(|e| {
+            anyhow::anyhow!("WAL blob too large: failed to grow to {min_new_size} bytes: {e}")
+        })
+    }
 
-        // Start with current size and double until we cover min_new_size
-        let mut new_size = self.mmap.size;
-        while new_size < min_new_size && new_size < MAX_SIZE {
-            new_size = new_size.saturating_mul(2);
+    fn grow_to(&mut self, new_size: usize) -> anyhow::Result<()> {
+        #[cfg(test)]
+        if new_size > self.size_limit {
+            anyhow::bail!("mmap failed: past the limit of {} bytes", self.size_limit);
         }
-        new_size = new_size.min(MAX_SIZE);
 
         if self.mmap.resize(new_size) {
             return Ok(());
         }
 
-        // Resizing in place did not succeed. We could try to create a new mapping and copy
-        // the data over.
+        // Resizing in place did not succeed. Create a new mapping and copy the data over.
         let new_mmap = Mmap::new(new_size)?;
         unsafe {
-            std::ptr::copy_nonoverlapping(self.mmap.ptr, new_mmap.ptr, self.mmap.size);
+            std::ptr::copy_nonoverlapping(self.mmap.ptr, new_mmap.ptr, self.cur);
         }
         self.mmap = new_mmap;
         Ok(())
     }
 
-    /// Finalizes the builder and returns the pointer to the start of the blob and its length.
+    /// Replace the entries following the start entry, up to and including the end entry, with a
+    /// single entry holding them compressed. The compressed entry takes the sequence number of
+    /// the first of them, and they keep theirs.
+    fn compress_entries(&mut self) -> anyhow::Result<()> {
+        // SAFETY: The entries were written into the mmap, between the start entry and `cur`.
+        let entries = unsafe {
+            std::slice::from_raw_parts(
+                self.mmap.ptr.add(START_ENTRY_LEN),
+                self.cur - START_ENTRY_LEN,
+            )
+        };
+        let len = entries.len();
+        let compressed = lz4_flex::block::compress(entries);
+        // The tag, the lengths and the trailer.
+        if compressed.len() + 29 >= len {
+            return Ok(());
+        }
+
+        self.cur = START_ENTRY_LEN;
+        self.entry_seqn = 1;
+        self.begin_entry(WAL_ENTRY_TAG_COMPRESSED)?;
+        unsafe {
+            // SAFETY: Those do not overlap with the mmap.
+            self.write(&(len as u64).to_le_bytes())?;
+            self.write(&(compressed.len() as u64).to_le_bytes())?;
+            self.write(&compressed)?;
+        }
+        self.finish_entry()
+    }
+
+    /// Finalizes the builder and returns the pointer to the start of the blob and its length. If
+    /// the builder flushed a part of the blob, this is the rest of it, see [`Self::flushed`].
+    ///
+    /// If the builder compresses, the entries following the start entry are replaced by an entry
+    /// holding them compressed, unless that doesn't make the blob smaller.
     ///
     /// This also resets the builder preparing it for a new batch of writes.
     ///
@@ -183,18 +378,20 @@ impl WalBlobBuilder {
     /// the pointer around for too long.
     ///
     /// The pointer is aligned to the page size.
-    pub fn finalize(&mut self) -> (*mut u8, usize) {
-        self.write_byte(WAL_ENTRY_TAG_END);
+    pub fn finalize(&mut self) -> anyhow::Result<(*mut u8, usize)> {
+        self.begin_entry(WAL_ENTRY_TAG_END)?;
+        self.finish_entry()?;
+        if self.compress {
+            self.compress_entries()?;
+        }
 
         let ptr = self.mmap.ptr;
         // round up to the nearest page size.
         let len = (self.cur + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
 
         // Note we don't madvise(DONTNEED) or any other tricks for now. If we did, then each time
-        // we write a page it would need to be mounted and thus zero filled.
-        //
-        // The hope is that should there be memory  pressure, the memory would be swapped out as
-        // needed.
+        // we write a page it would need to be mounted and thus zero filled. The memory is bounded
+        // by flushing instead, see `flush`.
         let cur = self.cur;
         unsafe {
             // Zero memory from `cur` to the end of the blob (which is `len`).
@@ -212,7 +409,8 @@ impl WalBlobBuilder {
         }
 
         self.cur = 0;
-        (ptr, len)
+        self.entry_seqn = 0;
+        Ok((ptr, len))
     }
 }
 
@@ -233,20 +431,20 @@ mod tests {
 
         // Fill up most of the initial capacity
         let data = vec![42u8; 4000];
-        unsafe { builder.write(&data) };
+        unsafe { builder.write(&data).unwrap() };
         assert_eq!(builder.cur, 4000);
         assert_eq!(builder.mmap.size, 4096);
 
         // Write more data that forces a grow
         let more_data = vec![43u8; 2000];
-        unsafe { builder.write(&more_data) };
+        unsafe { builder.write(&more_data).unwrap() };
 
         // Should have grown to accommodate the additional data
         assert!(builder.mmap.size > 4096);
         assert_eq!(builder.cur, 6000);
 
         // Verify we can still write after growing
-        builder.write_byte(44);
+        builder.write_byte(44).unwrap();
         assert_eq!(builder.cur, 6001);
     }
 
@@ -258,11 +456,57 @@ mod tests {
         for i in 0..5 {
             let size = 1000 * (i + 1);
             let data = vec![i as u8; size];
-            unsafe { builder.write(&data) };
+            unsafe { builder.write(&data).unwrap() };
         }
 
         // Should have grown multiple times to fit all data
         assert!(builder.mmap.size >= 15000);
         assert_eq!(builder.cur, 15000);
     }
+
+    #[test]
+    fn test_blob_builder_flushes() {
+        fn build(builder: &mut WalBlobBuilder) -> Vec<u8> {
+            builder.write_start(7).unwrap();
+            for bucket in 0..2000 {
+                builder.write_clear(bucket).unwrap();
+            }
+            let (ptr, len) = builder.finalize().unwrap();
+            unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec()
+        }
+
+        let whole = build(&mut WalBlobBuilder::with_initial_size(4096).unwrap());
+
+        let wal_fd = tempfile::tempfile().unwrap();
+        let mut builder = WalBlobBuilder::with_initial_size(4096).unwrap();
+        builder.wal_fd = Some(wal_fd.try_clone().unwrap());
+        builder.flush_threshold = 8192;
+        let rest = build(&mut builder);
+
+        // The mapping never grew past the pages flushed at a time.
+        assert!(builder.mmap.size <= 4 * PAGE_SIZE);
+        assert!(builder.flushed() > 0);
+        assert_eq!(builder.flushed() as usize % PAGE_SIZE, 0);
+        wal_fd.write_all_at(&rest, builder.flushed()).unwrap();
+        let mut blob = vec![0; whole.len()];
+        wal_fd.read_exact_at(&mut blob, 0).unwrap();
+        assert_eq!(blob, whole);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_blob_builder_shrinks_on_start() {
+        let mut builder = WalBlobBuilder::with_initial_size(INITIAL_SIZE).unwrap();
+        builder.write_start(1).unwrap();
+        let data = vec![42u8; INITIAL_SIZE];
+        unsafe { builder.write(&data).unwrap() };
+        assert!(builder.mmap.size > INITIAL_SIZE);
+        builder.finalize().unwrap();
+
+        // The next blob starts out from the initial size again.
+        builder.write_start(2).unwrap();
+        assert_eq!(builder.mmap.size, INITIAL_SIZE);
+        let (_, len) = builder.finalize().unwrap();
+        assert_eq!(len, PAGE_SIZE);
+    }
 }
@@ -7,17 +7,35 @@
 
 use std::{
     fs::File,
-    io::{Seek as _, SeekFrom, Write},
-    os::fd::AsRawFd as _,
+    io::{Seek as _, SeekFrom},
+    os::{fd::AsRawFd as _, unix::fs::FileExt as _},
 };
 
-use crate::io::{FatPage, IoCommand, IoHandle, IoKind};
+use crate::io::{self, FatPage, IoCommand, IoHandle, IoKind};
 
-pub fn write_wal(mut wal_fd: &File, wal_blob: &[u8]) -> anyhow::Result<()> {
-    wal_fd.set_len(0)?;
-    wal_fd.seek(SeekFrom::Start(0))?;
-    wal_fd.write_all(wal_blob)?;
-    wal_fd.sync_all()?;
+/// Write the WAL blob at the given offset, after the part of it flushed while it was built, and
+/// fsync it through the I/O pool.
+///
+/// If the write fails, the partially written blob is truncated. Running out of space is reported as
+/// [`crate::Error::DiskFull`].
+pub fn write_wal(
+    io_handle: &IoHandle,
+    wal_fd: &File,
+    wal_blob: &[u8],
+    offset: u64,
+) -> anyhow::Result<()> {
+    let res = wal_fd
+        .write_all_at(wal_blob, offset)
+        // Drop whatever follows the blob.
+        .and_then(|()| wal_fd.set_len(offset + wal_blob.len() as u64))
+        .and_then(|()| io::fsync(io_handle, &[wal_fd.as_raw_fd()]));
+    if let Err(e) = res {
+        // Truncating releases space, so it is expected to succeed even if the disk is full. If it
+        // doesn't, recovery discards the partial blob anyway because its sync sequence number is
+        // ahead of the meta.
+        let _ = truncate_wal(wal_fd);
+        return Err(crate::Error::writeout(e).into());
+    }
     Ok(())
 }
 
@@ -50,7 +68,7 @@ pub fn write_ht(
         sent -= 1;
     }
 
-    ht_fd.sync_all()?;
+    io::fsync(&io_handle, &[ht_fd.as_raw_fd()])?;
 
     Ok(())
 }
@@ -0,0 +1,149 @@
+//! Named checkpoints.
+//!
+//! A checkpoint associates a name with the root of the trie at the time it was taken. Rolling
+//! back to a checkpoint reverts the commits made since then using the rollback log, which is why
+//! a checkpoint only stays usable while the log retains those commits.
+//!
+//! Restoring the meta of the store as of the checkpoint is not an option: pages freed by later
+//! commits are reused right away, so the files no longer hold the state the old meta refers to.
+//!
+//! The checkpoints are persisted in the `checkpoints` file of the database directory, which is
+//! replaced atomically on every change. The file holds a sequence of entries, each consisting of
+//! the length of the name as a little-endian `u16`, the name, and the 32-byte root.
+
+use std::{
+    collections::BTreeMap,
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _};
+use nomt_core::trie::Node;
+use parking_lot::Mutex;
+
+/// The name of the file holding the checkpoints.
+pub(crate) const CHECKPOINTS_FILE: &str = "checkpoints";
+
+pub(crate) struct Checkpoints {
+    dir: PathBuf,
+    map: Mutex<BTreeMap<String, Node>>,
+}
+
+impl Checkpoints {
+    /// Load the checkpoints from the database directory. A missing file means no checkpoints.
+    pub fn load(dir: &Path) -> anyhow::Result<Self> {
+        let map = match std::fs::read(dir.join(CHECKPOINTS_FILE)) {
+            Ok(buf) => decode(&buf).context("malformed checkpoints file")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            map: Mutex::new(map),
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Node> {
+        self.map.lock().get(name).copied()
+    }
+
+    pub fn list(&self) -> Vec<(String, Node)> {
+        self.map
+            .lock()
+            .iter()
+            .map(|(name, root)| (name.clone(), *root))
+            .collect()
+    }
+
+    /// Set the checkpoint, replacing an existing one with the same name.
+    pub fn insert(&self, name: String, root: Node) -> anyhow::Result<()> {
+        if name.len() > u16::MAX as usize {
+            bail!("checkpoint name too long");
+        }
+        let mut map = self.map.lock();
+        let mut updated = map.clone();
+        updated.insert(name, root);
+        self.persist(&updated)?;
+        *map = updated;
+        Ok(())
+    }
+
+    /// Remove the checkpoint. Returns whether it existed.
+    pub fn remove(&self, name: &str) -> anyhow::Result<bool> {
+        let mut map = self.map.lock();
+        if !map.contains_key(name) {
+            return Ok(false);
+        }
+        let mut updated = map.clone();
+        updated.remove(name);
+        self.persist(&updated)?;
+        *map = updated;
+        Ok(true)
+    }
+
+    fn persist(&self, map: &BTreeMap<String, Node>) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        for (name, root) in map {
+            buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(root);
+        }
+
+        let tmp_path = self.dir.join(format!("{CHECKPOINTS_FILE}.tmp"));
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, self.dir.join(CHECKPOINTS_FILE))?;
+        std::fs::File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+}
+
+fn decode(mut buf: &[u8]) -> anyhow::Result<BTreeMap<String, Node>> {
+    let mut map = BTreeMap::new();
+    while !buf.is_empty() {
+        if buf.len() < 2 {
+            bail!("truncated entry");
+        }
+        let name_len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+        buf = &buf[2..];
+        if buf.len() < name_len + 32 {
+            bail!("truncated entry");
+        }
+        let name = String::from_utf8(buf[..name_len].to_vec())?;
+        let root = buf[name_len..name_len + 32].try_into().unwrap();
+        buf = &buf[name_len + 32..];
+        map.insert(name, root);
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoints_persist() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = Checkpoints::load(dir.path()).unwrap();
+        assert!(checkpoints.list().is_empty());
+
+        checkpoints.insert("a".to_string(), [1; 32]).unwrap();
+        checkpoints.insert("b".to_string(), [2; 32]).unwrap();
+        checkpoints.insert("a".to_string(), [3; 32]).unwrap();
+        assert!(checkpoints.remove("b").unwrap());
+        assert!(!checkpoints.remove("c").unwrap());
+
+        let reloaded = Checkpoints::load(dir.path()).unwrap();
+        assert_eq!(reloaded.list(), vec![("a".to_string(), [3; 32])]);
+        assert_eq!(reloaded.get("b"), None);
+    }
+
+    #[test]
+    fn malformed_file_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(CHECKPOINTS_FILE), [5, 0, b'a']).unwrap();
+        assert!(Checkpoints::load(dir.path()).is_err());
+    }
+}
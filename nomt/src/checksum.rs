@@ -0,0 +1,72 @@
+//! Checksums of the pages of the database files.
+//!
+//! Every page of the hash-table file and every page of the leaf and branch node files carries an
+//! xxh3 checksum of the rest of the page. The checksum is written along with the page and checked
+//! when the page is read, if [`Options::verify_checksums`](crate::Options::verify_checksums) is
+//! set, so that corrupted pages are reported as [`Error::ChecksumMismatch`] instead of silently
+//! propagating into wrong values and roots.
+
+use crate::{io::PAGE_SIZE, Error};
+
+/// The size of a checksum, in bytes.
+pub const CHECKSUM_SIZE: usize = 8;
+
+/// The offset of the checksum within the pages of the leaf and branch node files, which is at the
+/// end of every page.
+pub const TRAILER_OFFSET: usize = PAGE_SIZE - CHECKSUM_SIZE;
+
+/// Compute the checksum of a page, excluding the checksum itself, stored at `offset`.
+fn compute(page: &[u8], offset: usize) -> u64 {
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    hasher.update(&page[..offset]);
+    hasher.update(&page[offset + CHECKSUM_SIZE..]);
+    hasher.digest()
+}
+
+/// Store the checksum of a page at `offset`.
+pub fn write(page: &mut [u8], offset: usize) {
+    let checksum = compute(page, offset);
+    page[offset..offset + CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Check the checksum stored at `offset` of a page read from the given page of a file.
+pub fn check(
+    page: &[u8],
+    offset: usize,
+    file: &'static str,
+    page_number: u64,
+) -> anyhow::Result<()> {
+    let stored = &page[offset..offset + CHECKSUM_SIZE];
+    if stored == compute(page, offset).to_le_bytes() {
+        Ok(())
+    } else {
+        Err(Error::ChecksumMismatch { file, page_number }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, write, TRAILER_OFFSET};
+    use crate::{io::PAGE_SIZE, Error};
+
+    #[test]
+    fn detects_flipped_bits() {
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[..5].copy_from_slice(b"hello");
+        write(&mut page, TRAILER_OFFSET);
+        check(&page, TRAILER_OFFSET, "ln", 1).unwrap();
+
+        for byte in [0, 100, TRAILER_OFFSET - 1, TRAILER_OFFSET, PAGE_SIZE - 1] {
+            let mut corrupted = page.clone();
+            corrupted[byte] ^= 0x10;
+            let err = check(&corrupted, TRAILER_OFFSET, "ln", 1).unwrap_err();
+            assert!(matches!(
+                Error::internal(err),
+                Error::ChecksumMismatch {
+                    file: "ln",
+                    page_number: 1
+                }
+            ));
+        }
+    }
+}
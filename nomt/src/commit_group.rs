@@ -0,0 +1,156 @@
+//! Committing several sessions together, with a single sync of the store.
+//!
+//! A sync writes out the WAL and the b-tree and waits for both to be flushed to disk, which is the
+//! dominant cost of a commit. A [`CommitGroup`] applies the commits of the sessions in memory as
+//! they arrive, so every session reads the state left by the previous ones, and then syncs them
+//! all at once.
+
+use std::mem;
+
+use crossbeam::channel::{self, Receiver, Sender};
+use nomt_core::{trie::Node, witness::Witness};
+
+use crate::{
+    merkle::PageDiffs, AppliedCommit, Error, HashAlgorithm, KeyPath, KeyReadWrite, Nomt, Result,
+    Session, WitnessedOperations,
+};
+
+/// Commits sessions one after another and syncs them together, returned by
+/// [`Nomt::commit_group`].
+///
+/// Each commit takes effect in memory right away: the root of the database moves to the root of
+/// the commit, and sessions begun afterwards read the values it wrote. The commits are written to
+/// disk together by [`Self::sync`], or when the group is dropped. Each commit returns a
+/// [`PendingCommit`] for waiting on the sync.
+///
+/// While the group is open, committing to the database other than through the group fails with
+/// [`Error::InvalidOperation`]. If the sync fails, none of the commits of the group take effect on
+/// disk and the database has to be reopened, as with any failed commit.
+///
+/// The commits of a group are seen as a single commit by everything which observes the writes to
+/// the files: they share the [changed pages](crate::ChangedPages) reported to commit feeds, a
+/// single manifest and incremental backup cover them from the root before the first commit to the
+/// root after the last, only the root after the last commit is added to the
+/// [root history](crate::Options::root_history), and the tag of the last commit is the one stored.
+pub struct CommitGroup<'a, T: HashAlgorithm> {
+    pub(crate) nomt: &'a Nomt<T>,
+    pub(crate) commits: Vec<(AppliedCommit, Sender<Result<()>>)>,
+    pub(crate) page_diffs: Vec<PageDiffs>,
+    pub(crate) commit_tag: Option<Vec<u8>>,
+}
+
+impl<T: HashAlgorithm> CommitGroup<'_, T> {
+    /// Commit the session to the group and return the pending commit. The actuals are as for
+    /// [`Nomt::commit`].
+    pub fn commit(
+        &mut self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+    ) -> Result<PendingCommit> {
+        Ok(self.commit_inner(session, actuals, None)?.0)
+    }
+
+    /// Like [`Self::commit`], but also creates a proof for the session, as
+    /// [`Nomt::commit_and_prove`].
+    pub fn commit_and_prove(
+        &mut self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+    ) -> Result<(PendingCommit, Witness, WitnessedOperations)> {
+        match self.commit_inner(session, actuals, Some(&|_| true))? {
+            (pending, Some(witness), Some(witnessed)) => Ok((pending, witness, witnessed)),
+            // UNWRAP: witness specified to true
+            _ => unreachable!(),
+        }
+    }
+
+    fn commit_inner(
+        &mut self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        witness: Option<&dyn Fn(&KeyPath) -> bool>,
+    ) -> Result<(PendingCommit, Option<Witness>, Option<WitnessedOperations>)> {
+        let staged = self.nomt.apply_commit(session, actuals, witness, None)?;
+        self.nomt.store.stage_values(staged.tx, staged.applied.root);
+        self.page_diffs.push(staged.page_diffs);
+        self.commit_tag = staged.commit_tag;
+
+        let (synced_tx, synced_rx) = channel::bounded(1);
+        let pending = PendingCommit {
+            root: staged.applied.root,
+            synced: synced_rx,
+        };
+        self.commits.push((staged.applied, synced_tx));
+        Ok((pending, staged.witness, staged.witnessed_operations))
+    }
+
+    /// The number of commits in the group.
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    /// Whether nothing was committed to the group.
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+
+    /// Write all the commits of the group to disk with a single sync, and notify their
+    /// [`PendingCommit`]s of the outcome.
+    pub fn sync(mut self) -> Result<()> {
+        self.sync_inner()
+    }
+
+    fn sync_inner(&mut self) -> Result<()> {
+        if self.commits.is_empty() {
+            self.nomt.shared.lock().commit_group_open = false;
+            return Ok(());
+        }
+        let (commits, synced): (Vec<_>, Vec<_>) = mem::take(&mut self.commits).into_iter().unzip();
+        let page_diffs = PageDiffs::merge(mem::take(&mut self.page_diffs));
+        let result = self.nomt.sync_commits(
+            commits,
+            self.nomt.store.new_value_tx(),
+            page_diffs,
+            self.commit_tag.take(),
+        );
+        self.nomt.shared.lock().commit_group_open = false;
+        for synced in synced {
+            let _ = synced.send(match &result {
+                Ok(()) => Ok(()),
+                Err(e) => Err(e.duplicate()),
+            });
+        }
+        result
+    }
+}
+
+impl<T: HashAlgorithm> Drop for CommitGroup<'_, T> {
+    fn drop(&mut self) {
+        // The pending commits are notified of errors.
+        let _ = self.sync_inner();
+    }
+}
+
+/// A commit made in a [`CommitGroup`] which may not have been written to disk yet.
+pub struct PendingCommit {
+    root: Node,
+    synced: Receiver<Result<()>>,
+}
+
+impl PendingCommit {
+    /// The root of the trie after the commit.
+    pub fn root(&self) -> Node {
+        self.root
+    }
+
+    /// Wait for the group to be synced. Returns the root after the commit, or the error the sync
+    /// failed with.
+    pub fn wait(self) -> Result<Node> {
+        match self.synced.recv() {
+            Ok(result) => result.map(|()| self.root),
+            Err(_) => Err(Error::InvalidOperation(
+                "the commit group was leaked without syncing".to_string(),
+            )),
+        }
+    }
+}
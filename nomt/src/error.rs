@@ -0,0 +1,213 @@
+//! The error type of the public API.
+
+use std::{fmt, io};
+
+/// A specialized `Result` type for NOMT operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error returned by NOMT.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// An I/O operation failed, e.g. because the disk is full.
+    Io(io::Error),
+    /// The database files are corrupted or inconsistent with each other.
+    Corruption(String),
+    /// A page read from a database file doesn't match its checksum, e.g. because of bit rot.
+    ///
+    /// Only reported if [`Options::verify_checksums`](crate::Options::verify_checksums) is set.
+    ChecksumMismatch {
+        /// The name of the file the page was read from: `ht`, `ln` or `bbn`.
+        file: &'static str,
+        /// The number of the page within the file.
+        page_number: u64,
+    },
+    /// The database files were written in a format this version of NOMT can't read, e.g. by an
+    /// older version. The files are left untouched.
+    IncompatibleFormat {
+        /// The version of the format of the files. Files written before the format was versioned
+        /// are reported as version 0.
+        found: u32,
+        /// The version of the format this version of NOMT reads and writes.
+        supported: u32,
+    },
+    /// The actuals passed to a commit are malformed, e.g. not sorted by key path.
+    InvalidActuals(String),
+    /// The operation is not valid with the current options or state of the database.
+    InvalidOperation(String),
+    /// A chunk of state given to `StateSync::ingest` doesn't verify
+    /// against the root being synced, or doesn't follow the chunks ingested before it.
+    InvalidStateChunk(String),
+    /// The database directory is locked by another instance: one which writes to it, or one
+    /// which only reads it if this instance writes to it.
+    Busy,
+    /// The disk ran out of space while writing out a commit.
+    ///
+    /// The commit did not take effect and the previously committed state is intact on disk, but
+    /// the database must be reopened before it can be read or committed to again.
+    DiskFull,
+    /// A commit prepared with `Nomt::prepare` was neither committed nor aborted before the
+    /// process stopped, so it is unknown whether it should take effect.
+    ///
+    /// The database is left untouched. It can be opened by choosing the outcome with
+    /// `Options::in_doubt_commit`, e.g. after checking the external database the commit was
+    /// coordinated with.
+    InDoubtCommit {
+        /// The root of the trie after the commit.
+        root: nomt_core::trie::Node,
+        /// The tag of the commit, empty if it had none.
+        commit_tag: Vec<u8>,
+    },
+    /// Any other error.
+    Other(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl Error {
+    /// Classify an internal error.
+    ///
+    /// Internal code tags well-known conditions by returning an [`Error`] wrapped in
+    /// `anyhow::Error`. Otherwise, an I/O error anywhere in the chain makes this an [`Error::Io`].
+    pub(crate) fn internal(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<io::Error>() {
+            Ok(e) => return Error::Io(e),
+            Err(e) => e,
+        };
+        let io_error = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(|io_error| match io_error.raw_os_error() {
+                Some(code) => io::Error::from_raw_os_error(code),
+                None => io::Error::new(io_error.kind(), format!("{e:#}")),
+            });
+        match io_error {
+            Some(io_error) => Error::Io(io_error),
+            None => Error::Other(e.into()),
+        }
+    }
+
+    /// A copy of the error, for reporting it more than once. Errors which can't be copied are
+    /// reported by their message.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub(crate) fn duplicate(&self) -> Self {
+        match self {
+            Error::Io(e) => Error::Io(match e.raw_os_error() {
+                Some(code) => io::Error::from_raw_os_error(code),
+                None => io::Error::new(e.kind(), e.to_string()),
+            }),
+            Error::Corruption(msg) => Error::Corruption(msg.clone()),
+            Error::ChecksumMismatch { file, page_number } => Error::ChecksumMismatch {
+                file,
+                page_number: *page_number,
+            },
+            Error::IncompatibleFormat { found, supported } => Error::IncompatibleFormat {
+                found: *found,
+                supported: *supported,
+            },
+            Error::InvalidActuals(msg) => Error::InvalidActuals(msg.clone()),
+            Error::InvalidOperation(msg) => Error::InvalidOperation(msg.clone()),
+            Error::InvalidStateChunk(msg) => Error::InvalidStateChunk(msg.clone()),
+            Error::Busy => Error::Busy,
+            Error::DiskFull => Error::DiskFull,
+            Error::InDoubtCommit { root, commit_tag } => Error::InDoubtCommit {
+                root: *root,
+                commit_tag: commit_tag.clone(),
+            },
+            Error::Other(e) => Error::Other(e.to_string().into()),
+        }
+    }
+
+    /// Classify an error of writing out a commit: running out of space is [`Error::DiskFull`].
+    pub(crate) fn writeout(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::StorageFull {
+            Error::DiskFull
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Corruption(msg) => write!(f, "database corrupted: {msg}"),
+            Error::ChecksumMismatch { file, page_number } => {
+                write!(
+                    f,
+                    "checksum mismatch in page {page_number} of the {file} file"
+                )
+            }
+            Error::IncompatibleFormat { found, supported } => write!(
+                f,
+                "incompatible database format: version {found}, expected version {supported}"
+            ),
+            Error::InvalidActuals(msg) => write!(f, "invalid actuals: {msg}"),
+            Error::InvalidOperation(msg) => write!(f, "invalid operation: {msg}"),
+            Error::InvalidStateChunk(msg) => write!(f, "invalid state chunk: {msg}"),
+            Error::Busy => write!(f, "database directory is locked by another instance"),
+            Error::DiskFull => write!(f, "disk full"),
+            Error::InDoubtCommit { .. } => write!(f, "a prepared commit is in doubt"),
+            Error::Other(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Other(e) => Some(&**e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use anyhow::Context as _;
+    use std::io;
+
+    #[test]
+    fn internal_keeps_tagged_errors() {
+        let e = anyhow::Error::from(Error::Corruption("bad meta".into()));
+        assert!(matches!(Error::internal(e), Error::Corruption(msg) if msg == "bad meta"));
+    }
+
+    #[test]
+    fn internal_finds_io_errors_in_chain() {
+        let enospc = io::Error::from_raw_os_error(libc::ENOSPC);
+        let e = Err::<(), _>(enospc).context("writing WAL").unwrap_err();
+        match Error::internal(e) {
+            Error::Io(e) => assert_eq!(e.raw_os_error(), Some(libc::ENOSPC)),
+            e => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn internal_falls_back_to_other() {
+        let e = anyhow::anyhow!("something else");
+        assert!(matches!(Error::internal(e), Error::Other(_)));
+    }
+
+    #[test]
+    fn writeout_reports_disk_full() {
+        let enospc = io::Error::from_raw_os_error(libc::ENOSPC);
+        assert!(matches!(Error::writeout(enospc), Error::DiskFull));
+        let eio = io::Error::from_raw_os_error(libc::EIO);
+        assert!(matches!(Error::writeout(eio), Error::Io(_)));
+    }
+
+    #[test]
+    fn duplicate_keeps_kind() {
+        let eio = Error::Io(io::Error::from_raw_os_error(libc::EIO));
+        assert!(matches!(eio.duplicate(), Error::Io(e) if e.raw_os_error() == Some(libc::EIO)));
+        assert!(matches!(Error::DiskFull.duplicate(), Error::DiskFull));
+        let other = Error::internal(anyhow::anyhow!("something else"));
+        assert_eq!(other.duplicate().to_string(), other.to_string());
+    }
+}
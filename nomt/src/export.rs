@@ -0,0 +1,198 @@
+//! The portable export format, written by [`crate::Nomt::export`] and read by
+//! [`crate::Nomt::import_stream`].
+//!
+//! Unlike the database files, the format only depends on the stored values, so it can be used to
+//! move a state between databases with different options or versions of NOMT.
+//!
+//! An export consists of:
+//!   1. A header: the magic `NOMTEXPT` followed by the version of the format as a little-endian
+//!      `u32`.
+//!   2. Chunks of key-value pairs in ascending order of their keys. A chunk is the length of its
+//!      body as a little-endian `u32`, the body and the xxh3 checksum of the body as a
+//!      little-endian `u64`. The body is a sequence of entries, each being the 32-byte key, the
+//!      length of the value as a little-endian `u32` and the value.
+//!   3. A trailer, starting with a chunk length of zero: the root of the trie, the number of
+//!      key-value pairs in the export as a little-endian `u64` and the xxh3 checksum of both.
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+
+use nomt_core::trie::{KeyPath, Node};
+
+use crate::{Error, Result, Value};
+
+const MAGIC: &[u8; 8] = b"NOMTEXPT";
+
+/// The version of the format written by [`crate::Nomt::export`].
+pub const EXPORT_VERSION: u32 = 1;
+
+/// The size of the body at which a chunk is ended.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// Write the values to the writer in the export format. Returns the number of values written.
+pub fn write(
+    mut writer: impl Write,
+    root: Node,
+    values: impl Iterator<Item = (KeyPath, Value)>,
+) -> std::io::Result<u64> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&EXPORT_VERSION.to_le_bytes())?;
+
+    let mut count = 0u64;
+    let mut body = Vec::with_capacity(CHUNK_SIZE);
+    for (key, value) in values {
+        body.extend_from_slice(&key);
+        body.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        body.extend_from_slice(&value);
+        count += 1;
+        if body.len() >= CHUNK_SIZE {
+            write_chunk(&mut writer, &body)?;
+            body.clear();
+        }
+    }
+    if !body.is_empty() {
+        write_chunk(&mut writer, &body)?;
+    }
+
+    let mut trailer = [0; 40];
+    trailer[..32].copy_from_slice(&root);
+    trailer[32..].copy_from_slice(&count.to_le_bytes());
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&trailer)?;
+    writer.write_all(&xxhash_rust::xxh3::xxh3_64(&trailer).to_le_bytes())?;
+    writer.flush()?;
+    Ok(count)
+}
+
+fn write_chunk(writer: &mut impl Write, body: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&(body.len() as u32).to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&xxhash_rust::xxh3::xxh3_64(body).to_le_bytes())
+}
+
+/// Reads the values of an export, one chunk at a time.
+///
+/// Iterating stops at the trailer or at the first error, which is returned by [`Self::finish`].
+/// The values of a chunk are only yielded once its checksum is verified.
+pub struct Reader<R> {
+    reader: R,
+    values: VecDeque<(KeyPath, Value)>,
+    count: u64,
+    /// The root and the number of values recorded in the trailer, once it was read.
+    trailer: Option<(Node, u64)>,
+    error: Option<Error>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Read the header of the export.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; 12];
+        read_exact(&mut reader, &mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(corruption("not an export"));
+        }
+        // UNWRAP: the slice is 4 bytes long.
+        let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if version != EXPORT_VERSION {
+            return Err(Error::InvalidOperation(format!(
+                "unsupported export version {version}"
+            )));
+        }
+        Ok(Self {
+            reader,
+            values: VecDeque::new(),
+            count: 0,
+            trailer: None,
+            error: None,
+        })
+    }
+
+    /// Returns the root recorded in the trailer, or the error which stopped the iteration.
+    pub fn finish(mut self) -> Result<Node> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match self.trailer {
+            Some((root, count)) if count == self.count => Ok(root),
+            Some((_, count)) => Err(corruption(&format!(
+                "the export holds {} values, but the trailer records {count}",
+                self.count
+            ))),
+            None => Err(corruption("the export was not read to the end")),
+        }
+    }
+
+    /// Read the next chunk into `values`, or the trailer.
+    fn read_chunk(&mut self) -> Result<()> {
+        let mut len = [0; 4];
+        read_exact(&mut self.reader, &mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len == 0 {
+            let mut trailer = [0; 48];
+            read_exact(&mut self.reader, &mut trailer)?;
+            let (body, checksum) = trailer.split_at(40);
+            if xxhash_rust::xxh3::xxh3_64(body).to_le_bytes() != checksum {
+                return Err(corruption("trailer checksum mismatch"));
+            }
+            // UNWRAP: the slices are 32 and 8 bytes long.
+            self.trailer = Some((
+                body[..32].try_into().unwrap(),
+                u64::from_le_bytes(body[32..].try_into().unwrap()),
+            ));
+            return Ok(());
+        }
+
+        let mut body = vec![0; len + 8];
+        read_exact(&mut self.reader, &mut body)?;
+        let (body, checksum) = body.split_at(len);
+        if xxhash_rust::xxh3::xxh3_64(body).to_le_bytes() != checksum {
+            return Err(corruption("chunk checksum mismatch"));
+        }
+
+        let mut rest = body;
+        while !rest.is_empty() {
+            if rest.len() < 36 {
+                return Err(corruption("truncated entry"));
+            }
+            // UNWRAP: the slices are 32 and 4 bytes long.
+            let key = rest[..32].try_into().unwrap();
+            let value_len = u32::from_le_bytes(rest[32..36].try_into().unwrap()) as usize;
+            let Some(value) = rest[36..].get(..value_len) else {
+                return Err(corruption("truncated entry"));
+            };
+            self.values.push_back((key, value.into()));
+            rest = &rest[36 + value_len..];
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = (KeyPath, Value);
+
+    fn next(&mut self) -> Option<(KeyPath, Value)> {
+        while self.values.is_empty() {
+            if self.trailer.is_some() || self.error.is_some() {
+                return None;
+            }
+            if let Err(e) = self.read_chunk() {
+                self.error = Some(e);
+            }
+        }
+        self.count += 1;
+        self.values.pop_front()
+    }
+}
+
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => corruption("the export is truncated"),
+        _ => Error::Io(e),
+    })
+}
+
+fn corruption(msg: &str) -> Error {
+    Error::Corruption(format!("malformed export: {msg}"))
+}
@@ -2,7 +2,7 @@ use super::{CompleteIo, IoCommand, IoKind, IoKindResult, IoPacket, PAGE_SIZE};
 use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use io_uring::{cqueue, opcode, squeue, types, IoUring};
 use slab::Slab;
-use std::collections::VecDeque;
+use std::{collections::VecDeque, os::fd::RawFd};
 
 const RING_CAPACITY: u32 = 128;
 
@@ -107,6 +107,16 @@ fn run_worker(command_rx: Receiver<IoPacket>, iopoll: bool) {
                 }
             };
 
+            // polled rings only support reads and writes, so fsyncs block the worker instead.
+            if let (true, IoKind::Fsync(fd)) = (iopoll, &next_io.command.kind) {
+                let result = fsync_blocking(*fd);
+                let _ = next_io.completion_sender.send(CompleteIo {
+                    command: next_io.command,
+                    result,
+                });
+                continue;
+            }
+
             to_submit = true;
             let pending_index = pending.insert(PendingIo {
                 command: next_io.command,
@@ -131,6 +141,18 @@ fn run_worker(command_rx: Receiver<IoPacket>, iopoll: bool) {
     }
 }
 
+fn fsync_blocking(fd: RawFd) -> std::io::Result<()> {
+    loop {
+        if unsafe { libc::fsync(fd) } == 0 {
+            return Ok(());
+        }
+        let err = std::io::Error::last_os_error();
+        if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
 fn submission_entry(command: &mut IoCommand) -> squeue::Entry {
     match command.kind {
         IoKind::Read(fd, page_index, ref mut page) => {
@@ -148,5 +170,6 @@ fn submission_entry(command: &mut IoCommand) -> squeue::Entry {
                 .offset(page_index * PAGE_SIZE as u64)
                 .build()
         }
+        IoKind::Fsync(fd) => opcode::Fsync::new(types::Fd(fd)).build(),
     }
 }
@@ -22,6 +22,8 @@ pub enum IoKind {
     Read(RawFd, u64, FatPage),
     Write(RawFd, u64, FatPage),
     WriteRaw(RawFd, u64, *const u8, usize),
+    /// Flush the file to disk, like `fsync`. Writes still in flight aren't covered.
+    Fsync(RawFd),
 }
 
 pub enum IoKindResult {
@@ -35,6 +37,7 @@ impl IoKind {
         match self {
             IoKind::Read(_, _, buf) | IoKind::Write(_, _, buf) => buf,
             IoKind::WriteRaw(_, _, _, _) => panic!("attempted to extract buf from write_raw"),
+            IoKind::Fsync(_) => panic!("attempted to extract buf from fsync"),
         }
     }
 
@@ -48,6 +51,7 @@ impl IoKind {
             // there should be no unexpected end-of-file that is not aligned with PAGE_SIZE
             // when all previous writes have succeeded.
             IoKind::Read(_, _, _) if res == 0 => IoKindResult::Ok,
+            IoKind::Fsync(_) if res == 0 => IoKindResult::Ok,
             // pread and pwrite return the number of bytes read or written
             _ if res == PAGE_SIZE as isize => IoKindResult::Ok,
             _ if res == -1 => {
@@ -168,6 +172,31 @@ impl IoHandle {
     }
 }
 
+/// Flush the given files to disk through the I/O pool, and wait until all of them are flushed.
+///
+/// The flushes run concurrently with one another and with the other I/O of the pool, without
+/// blocking a thread per file. The exception is an I/O pool polling for completions, whose rings
+/// can't flush files: each flush then blocks an I/O worker. No other commands may be in flight on
+/// the handle.
+pub fn fsync(io_handle: &IoHandle, fds: &[RawFd]) -> std::io::Result<()> {
+    for &fd in fds {
+        io_handle
+            .send(IoCommand {
+                kind: IoKind::Fsync(fd),
+                user_data: 0,
+            })
+            .map_err(|_| std::io::Error::other("I/O pool hangup"))?;
+    }
+    let mut result = Ok(());
+    for _ in fds {
+        let completion = io_handle
+            .recv()
+            .map_err(|_| std::io::Error::other("I/O pool hangup"))?;
+        result = result.and(completion.result);
+    }
+    result
+}
+
 /// Read a page from the file at the given page number.
 pub fn read_page(page_pool: &PagePool, fd: &File, pn: u64) -> std::io::Result<FatPage> {
     use std::os::unix::fs::FileExt as _;
@@ -175,3 +204,57 @@ pub fn read_page(page_pool: &PagePool, fd: &File, pn: u64) -> std::io::Result<Fa
     fd.read_exact_at(&mut page[..], pn * PAGE_SIZE as u64)?;
     Ok(page)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{fsync, platform, start_test_io_pool, IoCommand, IoKind, IoPool, PagePool};
+    use std::os::fd::AsRawFd as _;
+
+    #[test]
+    fn fsync_after_writes() {
+        let page_pool = PagePool::new();
+        let io_pool = start_test_io_pool(1, page_pool.clone());
+        let io_handle = io_pool.make_handle();
+        let files = [tempfile::tempfile().unwrap(), tempfile::tempfile().unwrap()];
+        for file in &files {
+            io_handle
+                .send(IoCommand {
+                    kind: IoKind::Write(file.as_raw_fd(), 1, page_pool.alloc_fat_page()),
+                    user_data: 0,
+                })
+                .unwrap();
+        }
+        for _ in &files {
+            io_handle.recv().unwrap().result.unwrap();
+        }
+
+        let fds = files
+            .iter()
+            .map(|file| file.as_raw_fd())
+            .collect::<Vec<_>>();
+        fsync(&io_handle, &fds).unwrap();
+        assert!(files
+            .iter()
+            .all(|file| file.metadata().unwrap().len() == 8192));
+    }
+
+    #[test]
+    fn fsync_with_polled_rings() {
+        let io_pool = IoPool {
+            sender: platform::start_io_worker(1, true),
+            page_pool: PagePool::new(),
+        };
+        let io_handle = io_pool.make_handle();
+        let file = tempfile::tempfile().unwrap();
+        fsync(&io_handle, &[file.as_raw_fd()]).unwrap();
+        let err = fsync(&io_handle, &[-1]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    fn fsync_reports_errors() {
+        let io_pool = start_test_io_pool(1, PagePool::new());
+        let err = fsync(&io_pool.make_handle(), &[-1]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+}
@@ -1,10 +1,10 @@
 use super::PAGE_SIZE;
-use parking_lot::{RwLock, RwLockWriteGuard};
+use parking_lot::{Condvar, Mutex, MutexGuard};
 use std::{
     cell::RefCell,
     ops::{Deref, DerefMut},
     sync::{
-        atomic::{AtomicPtr, AtomicU32, Ordering},
+        atomic::{AtomicPtr, AtomicU32, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
 };
@@ -22,6 +22,33 @@ const REGION_COUNT: usize = 4096;
 
 const TLS_FREELIST_CAPACITY: usize = 1024;
 
+/// A snapshot of the usage of the page pool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PagePoolStats {
+    /// The maximum number of pages the pool may hold, or `None` if unbounded.
+    pub capacity: Option<usize>,
+    /// The number of pages allocated from the OS so far.
+    pub allocated: usize,
+    /// The number of allocated pages which are not in use.
+    ///
+    /// Pages cached by individual threads for fast reuse are counted as in use.
+    pub free: usize,
+    /// The number of allocations which had to wait for a page to be freed, because the pool was
+    /// at capacity.
+    pub waits: u64,
+}
+
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+impl PagePoolStats {
+    /// The number of allocated pages which are in use.
+    pub fn used(&self) -> usize {
+        self.allocated - self.free
+    }
+}
+
+/// A callback invoked whenever an allocation has to wait because the page pool is exhausted.
+pub type ExhaustedCallback = Arc<dyn Fn(PagePoolStats) + Send + Sync>;
+
 /// A page reference to the pool.
 #[derive(Clone)]
 pub struct Page(*mut u8);
@@ -114,27 +141,72 @@ struct Inner {
     // Moreover, the pointer stored in `regions[i]` where `i < n_regions` is immutable once set.
     regions: [AtomicPtr<u8>; REGION_COUNT],
     n_regions: AtomicU32,
-    freelist: RwLock<Vec<Page>>,
+    freelist: Mutex<Vec<Page>>,
     // The local freelist for the current thread used to avoid contention on the global freelist.
     tls_freelist: ThreadLocal<RefCell<Vec<Page>>>,
+    // The maximum number of pages, if bounded. The last region may be populated only partially to
+    // respect it.
+    max_pages: Option<usize>,
+    // The number of pages handed out to the freelist so far. Only modified under the freelist lock.
+    n_pages: AtomicUsize,
+    // Allocations waiting for pages are parked here. While `n_waiters` is non-zero, deallocations
+    // bypass the thread-local freelists, so that pages become available to the waiters.
+    freed: Condvar,
+    n_waiters: AtomicUsize,
+    n_waits: AtomicU64,
+    on_exhausted: Option<ExhaustedCallback>,
 }
 
 impl PagePool {
-    /// Creates a new empty page pool.
+    /// Creates a new empty page pool without a capacity limit.
+    #[cfg(any(test, feature = "benchmarks"))]
     pub fn new() -> Self {
+        Self::with_capacity(None, None)
+    }
+
+    /// Creates a new empty page pool holding at most `max_pages` pages.
+    ///
+    /// Once all pages are in use, allocations block until a page is freed, invoking
+    /// `on_exhausted` before they do.
+    pub fn with_capacity(
+        max_pages: Option<usize>,
+        on_exhausted: Option<ExhaustedCallback>,
+    ) -> Self {
         let regions = std::array::from_fn(|_| AtomicPtr::new(std::ptr::null_mut()));
         // The capacity is chosen to be large enough to fit 4 times as much as 50k pages.
-        let freelist = RwLock::new(Vec::with_capacity(200000));
+        let freelist = Mutex::new(Vec::with_capacity(200000));
         Self {
             inner: Arc::new(Inner {
                 regions,
                 n_regions: AtomicU32::new(0),
                 freelist,
                 tls_freelist: ThreadLocal::new(),
+                max_pages,
+                n_pages: AtomicUsize::new(0),
+                freed: Condvar::new(),
+                n_waiters: AtomicUsize::new(0),
+                n_waits: AtomicU64::new(0),
+                on_exhausted,
             }),
         }
     }
 
+    /// Returns a snapshot of the usage of the pool.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn stats(&self) -> PagePoolStats {
+        let freelist = self.inner.freelist.lock();
+        self.stats_locked(&freelist)
+    }
+
+    fn stats_locked(&self, freelist: &[Page]) -> PagePoolStats {
+        PagePoolStats {
+            capacity: self.inner.max_pages,
+            allocated: self.inner.n_pages.load(Ordering::Relaxed),
+            free: freelist.len(),
+            waits: self.inner.n_waits.load(Ordering::Relaxed),
+        }
+    }
+
     /// Allocates a new [`FatPage`].
     pub fn alloc_fat_page(&self) -> FatPage {
         let page = self.alloc();
@@ -155,32 +227,68 @@ impl PagePool {
         }
 
         // if none is available, try to replenish the thread-local freelist from the global one.
-        let mut freelist = self.inner.freelist.write();
+        let mut freelist = self.inner.freelist.lock();
 
         if freelist.len() < TLS_FREELIST_CAPACITY {
-            // ensure that the global freelist has enough pages to refill the thread-local one.
+            // try to ensure that the global freelist has enough pages to refill the thread-local
+            // one. This may fall short if the pool is at capacity.
             self.grow(&mut freelist);
-            assert!(freelist.len() >= TLS_FREELIST_CAPACITY);
+        }
+
+        if freelist.is_empty() {
+            freelist = self.wait_for_pages(freelist);
         }
 
         // transfer at most TLS_FREELIST_CAPACITY pages from the global freelist to the
         // thread-local freelist.
-        tls_freelist.extend(freelist.drain(..TLS_FREELIST_CAPACITY));
+        let n = std::cmp::min(freelist.len(), TLS_FREELIST_CAPACITY);
+        tls_freelist.extend(freelist.drain(..n));
         tls_freelist.pop().unwrap()
     }
 
+    #[cold]
+    fn wait_for_pages<'a>(
+        &'a self,
+        freelist: MutexGuard<'a, Vec<Page>>,
+    ) -> MutexGuard<'a, Vec<Page>> {
+        self.inner.n_waiters.fetch_add(1, Ordering::SeqCst);
+        self.inner.n_waits.fetch_add(1, Ordering::Relaxed);
+
+        // The callback is invoked without holding the lock, so that it is free to query the pool.
+        let stats = self.stats_locked(&freelist);
+        drop(freelist);
+        if let Some(ref on_exhausted) = self.inner.on_exhausted {
+            on_exhausted(stats);
+        }
+
+        let mut freelist = self.inner.freelist.lock();
+        while freelist.is_empty() {
+            self.inner.freed.wait(&mut freelist);
+        }
+        self.inner.n_waiters.fetch_sub(1, Ordering::SeqCst);
+        freelist
+    }
+
     /// Deallocates a [`Page`].
     pub fn dealloc(&self, page: Page) {
         // fast path: try to place page in thread-local freelist.
         let mut tls_freelist = self.tls_freelist();
         tls_freelist.push(page);
 
+        if self.inner.n_waiters.load(Ordering::SeqCst) > 0 {
+            // somebody is waiting for pages: hand over all the pages of this thread.
+            let mut freelist = self.inner.freelist.lock();
+            freelist.extend(tls_freelist.drain(..));
+            self.inner.freed.notify_all();
+            return;
+        }
+
         if tls_freelist.len() < TLS_FREELIST_CAPACITY * 2 {
             return;
         }
 
         // slow path: drain TLS free-list to global free-list.
-        let mut freelist = self.inner.freelist.write();
+        let mut freelist = self.inner.freelist.lock();
         freelist.extend(tls_freelist.drain(TLS_FREELIST_CAPACITY..));
     }
 
@@ -192,7 +300,16 @@ impl PagePool {
     }
 
     #[cold]
-    fn grow(&self, freelist_guard: &mut RwLockWriteGuard<Vec<Page>>) {
+    fn grow(&self, freelist_guard: &mut MutexGuard<Vec<Page>>) {
+        let n_pages = self.inner.n_pages.load(Ordering::Relaxed);
+        let n_slots = match self.inner.max_pages {
+            Some(max_pages) => std::cmp::min(SLOTS_PER_REGION, max_pages.saturating_sub(n_pages)),
+            None => SLOTS_PER_REGION,
+        };
+        if n_slots == 0 {
+            return;
+        }
+
         // First step is to allocate a new region.
         let region_ptr = unsafe {
             libc::mmap(
@@ -222,10 +339,13 @@ impl PagePool {
         self.inner.n_regions.fetch_add(1, Ordering::Release);
 
         // Finally, we need to populate the freelist with the pages in the new region.
-        for slot in 0..SLOTS_PER_REGION {
+        for slot in 0..n_slots {
             let page_ptr = unsafe { region_ptr.add(slot * PAGE_SIZE) } as *mut u8;
             freelist_guard.push(Page(page_ptr));
         }
+        self.inner
+            .n_pages
+            .store(n_pages + n_slots, Ordering::Relaxed);
     }
 }
 
@@ -245,3 +365,53 @@ impl Drop for Inner {
 
 unsafe impl Send for PagePool {}
 unsafe impl Sync for PagePool {}
+
+#[cfg(test)]
+mod tests {
+    use super::{PagePool, TLS_FREELIST_CAPACITY};
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn unbounded_pool_stats() {
+        let pool = PagePool::new();
+        let page = pool.alloc_fat_page();
+        let stats = pool.stats();
+        assert_eq!(stats.capacity, None);
+        assert!(stats.allocated >= TLS_FREELIST_CAPACITY);
+        assert!(stats.used() >= 1);
+        assert_eq!(stats.waits, 0);
+        drop(page);
+    }
+
+    #[test]
+    fn exhausted_pool_blocks_until_freed() {
+        let (tx, rx) = mpsc::channel();
+        let tx = std::sync::Mutex::new(tx);
+        let pool = PagePool::with_capacity(
+            Some(TLS_FREELIST_CAPACITY),
+            Some(Arc::new(move |stats| {
+                tx.lock().unwrap().send(stats).unwrap()
+            })),
+        );
+
+        let pages = (0..TLS_FREELIST_CAPACITY)
+            .map(|_| pool.alloc_fat_page())
+            .collect::<Vec<_>>();
+        assert_eq!(pool.stats().used(), TLS_FREELIST_CAPACITY);
+
+        let waiter = std::thread::spawn({
+            let pool = pool.clone();
+            move || drop(pool.alloc_fat_page())
+        });
+
+        let stats = rx.recv().unwrap();
+        assert_eq!(stats.capacity, Some(TLS_FREELIST_CAPACITY));
+        assert_eq!(stats.allocated, TLS_FREELIST_CAPACITY);
+        assert_eq!(stats.free, 0);
+        assert_eq!(stats.waits, 1);
+
+        drop(pages);
+        waiter.join().unwrap();
+        assert_eq!(pool.stats().allocated, TLS_FREELIST_CAPACITY);
+    }
+}
@@ -53,6 +53,7 @@ fn execute(mut command: IoCommand) -> CompleteIo {
                     (page_index * PAGE_SIZE as u64) as libc::off_t,
                 )
             },
+            IoKind::Fsync(fd) => unsafe { libc::fsync(fd) as isize },
         };
         match command.kind.get_result(res) {
             IoKindResult::Ok => break Ok(()),
@@ -1,31 +1,126 @@
 #![warn(missing_docs)]
 
 //! A Nearly-Optimal Merkle Trie Database.
+//!
+//! # API stability
+//!
+//! The stable API follows semantic versioning. It consists of:
+//!
+//! - Opening, closing, resetting and destroying a database, and the [`Options`] not listed below.
+//! - Reading and writing within a [`Session`], committing with [`Nomt::commit`] and its variants
+//!   which create a witness, and reading the committed values and roots.
+//! - Proving and verifying: [`Nomt::prove`] and its variants, and the witness and proof formats.
+//! - Inspecting, checking and repairing a database, its statistics and metrics.
+//! - Rollback, checkpoints, incremental backups, manifests and [`Nomt::import`].
+//!
+//! The following is only available with the `unstable` feature, which is enabled by default. It
+//! may change in minor releases. Depend on this crate with `default-features = false` to make sure
+//! only the stable API is used.
+//!
+//! - Simulated crashes for testing recovery: `Options::panic_on_sync` and `SyncCrashPoint`.
+//! - Recording and replaying sessions: `Options::record_sessions` and `Nomt::replay`.
+//! - Commit notifications: `Nomt::watch`, `Nomt::commit_feed` and `Nomt::replication_feed`.
+//! - Witness hooks: `Session::set_witness_hook` and `WitnessHook`.
+//! - State sync: `Nomt::state_sync`, `Nomt::state_chunk` and `Nomt::state_chunks`.
+//! - Value compression: `Options::compression`, `Options::compression_dictionary`,
+//!   `train_dictionary` and `DatabaseInfo::compression`.
+//! - Deferred syncs: `Options::sync_mode`, `SyncMode` and `Nomt::flush`.
+//! - Snapshots: `Nomt::snapshot`, `Nomt::snapshot_at`, `Nomt::read_at`, `Nomt::prove_at`,
+//!   `Nomt::iter`, `Nomt::iter_rev`, `Options::snapshot_horizon` and
+//!   `Options::snapshot_retention`.
+//! - Bucket mapping strategies: `Options::bucket_mapping` and `DatabaseInfo::bucket_mapping`.
+//! - Page pool statistics: `Nomt::page_pool_stats` and `Options::on_page_pool_exhausted`.
+//! - The portable export format: `Nomt::export`, `Nomt::import_stream` and `EXPORT_VERSION`.
+//! - Commit groups: `Nomt::commit_group`, `CommitGroup` and `PendingCommit`.
+//! - Two-phase commits: `Nomt::prepare`, `PreparedCommit`, `Options::in_doubt_commit` and
+//!   `InDoubtCommit`.
+//! - Read-only instances: `Options::read_only`.
+//! - Reclaiming the space of freed pages: `Options::punch_holes`.
+//! - WAL compression: `Options::compress_wal`.
+//! - Filtering lookups of pages which aren't stored: `Options::presence_filter`.
+//! - WAL recovery progress: `Options::on_recovery_progress`, `RecoveryProgress`,
+//!   `Nomt::recovery_report` and `RecoveryReport`.
+//! - Placing files outside of the database directory: `Options::ht_dir`, `Options::wal_dir` and
+//!   `Options::beatree_dir`.
+//! - The thread-per-core experiment: `Options::thread_per_core`.
 
 use bitvec::prelude::*;
 use io::PagePool;
+use manifest::Manifests;
 use metrics::{Metric, Metrics};
 use std::{
+    collections::{BTreeSet, VecDeque},
     mem,
+    ops::RangeBounds,
     sync::{atomic::AtomicUsize, Arc},
+    time::Instant,
 };
 
 use merkle::{UpdatePool, Updater};
 use nomt_core::{
     page_id::ROOT_PAGE_ID,
-    proof::PathProof,
+    proof::{PathProof, PathProofTerminal},
+    range_proof::RangeProof,
     trie::{InternalData, NodeHasher, NodeHasherExt, ValueHash, TERMINATOR},
     trie_pos::TriePosition,
 };
 use page_cache::PageCache;
 use parking_lot::Mutex;
+use seek::Seeker;
 use store::Store;
 
 // CARGO HACK: silence lint; this is used in integration tests
 
+pub use beatree::ValueRef;
+#[cfg(feature = "unstable")]
+pub use beatree::{train_dictionary, Compression};
+#[cfg(feature = "unstable")]
+pub use bitbox::{BucketMappingStrategy, RecoveryProgress, RecoveryReport};
+#[cfg(feature = "unstable")]
+pub use commit_group::{CommitGroup, PendingCommit};
+pub use error::{Error, Result};
+#[cfg(feature = "unstable")]
+pub use export::EXPORT_VERSION;
+#[cfg(feature = "unstable")]
+pub use io::page_pool::PagePoolStats;
+pub use manifest::{ChangedPages, CommitManifest};
+#[cfg(feature = "unstable")]
+pub use merkle::WitnessHook;
+pub use nomt_core::binning::KeyBinning;
+pub use nomt_core::multi_proof::{self, MultiProof};
+pub use nomt_core::multi_proof_verification;
 pub use nomt_core::proof;
+pub use nomt_core::range_proof;
 pub use nomt_core::trie::{KeyPath, LeafData, Node, NodePreimage};
+pub use nomt_core::witness::{
+    verify_witness, Witness, WitnessVerificationError, WitnessedOperations, WitnessedPath,
+    WitnessedRead, WitnessedWrite,
+};
+pub use nomt_core::{witness_compression, witness_format};
+#[cfg(feature = "unstable")]
+pub use options::InDoubtCommit;
 pub use options::Options;
+#[cfg(feature = "unstable")]
+pub use options::SyncCrashPoint;
+#[cfg(feature = "unstable")]
+pub use options::SyncMode;
+#[cfg(feature = "unstable")]
+pub use prepared_commit::PreparedCommit;
+#[cfg(feature = "unstable")]
+pub use recorder::{RecordedOp, Recording, ReplayOutcome};
+#[cfg(feature = "unstable")]
+pub use snapshot::{Iter, Snapshot};
+#[cfg(feature = "unstable")]
+pub use state_sync::{StateChunk, StateChunks, StateSync};
+pub use store::{
+    CommitRoot, DatabaseInfo, FileSize, HashTableStats, IntegrityIssue, IntegrityLevel,
+    IntegrityReport, NodeFileStats, RepairReport, StorageStats, MAX_COMMIT_TAG_LEN,
+    MAX_ROOT_HISTORY_LEN,
+};
+#[cfg(feature = "unstable")]
+pub use watch::{
+    CommitDiff, CommitFeed, KeyChange, ReplicationFeed, ReplicationRecord, WatchEvent, Watcher,
+};
 
 // beatree module needs to be exposed to be benchmarked
 #[cfg(feature = "benchmarks")]
@@ -34,75 +129,151 @@ pub mod beatree;
 #[cfg(not(feature = "benchmarks"))]
 mod beatree;
 
+#[cfg(feature = "benchmarks")]
+pub use bitbox::benches::bucket_mapping_benchmark;
+
+mod backup;
 mod bitbox;
+mod checkpoint;
+mod checksum;
+#[cfg(feature = "unstable")]
+mod commit_group;
+mod error;
+#[cfg(feature = "unstable")]
+mod export;
+mod manifest;
 mod merkle;
 mod metrics;
 mod options;
 mod page_cache;
 mod page_diff;
 mod page_region;
+#[cfg(feature = "unstable")]
+mod prepared_commit;
+mod proof_cache;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod recorder;
 mod rollback;
 mod rw_pass_cell;
 mod seek;
 mod seglog;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod snapshot;
+#[cfg(feature = "unstable")]
+mod state_sync;
 mod store;
 mod sys;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod trie_versions;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod watch;
+mod witness_size;
 
 mod io;
 
 const MAX_COMMIT_CONCURRENCY: usize = 64;
 
+/// The number of values committed at once by [`Nomt::import`].
+const IMPORT_BATCH_SIZE: usize = 250_000;
+
 /// A full value stored within the trie.
-pub type Value = Vec<u8>;
+///
+/// Values are reference-counted so that they are cheap to clone and can be shared across threads.
+pub type Value = Arc<[u8]>;
 
 struct Shared {
     /// The current root of the trie.
     root: Node,
+    /// The root written to disk by the last sync.
+    synced_root: Node,
+    /// The sequence number of the last sync, see [`Nomt::commit_seqn`].
+    commit_seqn: u64,
+    /// The manifests of the most recent commits.
+    manifests: Manifests,
+    /// The snapshots of the most recent commits, from the oldest to the newest, along with the
+    /// number of commits synced with each. See [`Options::snapshot_retention`].
+    snapshots: VecDeque<(snapshot::Snapshot, usize)>,
+    /// The trie nodes overwritten by the commits since the oldest retained snapshot.
+    trie_versions: trie_versions::TrieVersions,
+    /// Whether a [`CommitGroup`] is open.
+    commit_group_open: bool,
+    /// Whether a [`PreparedCommit`] is open.
+    prepared_commit_open: bool,
+    /// The number of commits being made other than through a [`CommitGroup`]. A group is opened
+    /// only when there are none.
+    commits_in_progress: usize,
 }
 
-/// A witness that can be used to prove the correctness of state trie retrievals and updates.
+/// The values within a range of keys, proven to be all of them. Returned by
+/// [`Nomt::prove_range`].
 ///
-/// Expected to be serializable.
-pub struct Witness {
-    /// Various paths down the trie used as part of this witness.
-    pub path_proofs: Vec<WitnessedPath>,
+/// The proof can be checked with [`RangeProof::verify`](range_proof::RangeProof::verify), using
+/// the first and last key of the range.
+#[derive(Debug, Clone)]
+pub struct ProvenRange {
+    /// The root the proof is against.
+    pub root: Node,
+    /// The values within the range, in ascending key order.
+    pub values: Vec<(KeyPath, Value)>,
+    /// The proof that these are all the values within the range.
+    pub proof: RangeProof,
 }
 
-/// Operations provable by a corresponding witness.
-// TODO: the format of this structure depends heavily on how it'd be used with the path proofs.
-pub struct WitnessedOperations {
-    /// Read operations.
-    pub reads: Vec<WitnessedRead>,
-    /// Write operations.
-    pub writes: Vec<WitnessedWrite>,
+/// The root of the trie along with how much of it is on disk, as returned by
+/// [`Nomt::sync_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncState {
+    /// The current root of the trie.
+    pub root: Node,
+    /// The sequence number of the last commit written to disk. See [`Nomt::commit_seqn`].
+    pub commit_seqn: u64,
+    /// Whether the root is the one written to disk by the last commit. `false` while commits are
+    /// applied in memory only, e.g. while they are deferred by [`Options::sync_mode`] or while
+    /// they are being synced.
+    pub synced: bool,
 }
 
-/// A path observed in the witness.
-pub struct WitnessedPath {
-    /// Proof of a query path along the trie.
-    pub inner: PathProof,
-    /// The query path itself.
-    pub path: TriePosition,
+/// The node terminating the path of a key in the trie, as found by [`Session::probe`].
+///
+/// The depth is the number of bits of the key path leading to the node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// The key is present: its path ends in its own leaf.
+    Leaf {
+        /// The depth of the leaf.
+        depth: u16,
+        /// The hash of the value stored under the key.
+        value_hash: ValueHash,
+    },
+    /// The key is absent: its path ends in the leaf of another key sharing the first `depth`
+    /// bits.
+    OtherLeaf {
+        /// The depth of the leaf.
+        depth: u16,
+        /// The key stored in the leaf.
+        key_path: KeyPath,
+    },
+    /// The key is absent: its path ends in an empty subtrie.
+    Terminator {
+        /// The depth of the terminator.
+        depth: u16,
+    },
 }
 
-/// A witness of a read value.
-pub struct WitnessedRead {
-    /// The key of the read value.
-    pub key: KeyPath,
-    /// The hash of the value witnessed. None means no value.
-    pub value: Option<ValueHash>,
-    /// The index of the path in the corresponding witness.
-    pub path_index: usize,
-}
+impl Probe {
+    /// The depth of the terminal node.
+    pub fn depth(&self) -> u16 {
+        match *self {
+            Probe::Leaf { depth, .. }
+            | Probe::OtherLeaf { depth, .. }
+            | Probe::Terminator { depth } => depth,
+        }
+    }
 
-/// A witness of a write operation.
-pub struct WitnessedWrite {
-    /// The key of the written value.
-    pub key: KeyPath,
-    /// The hash of the written value. `None` means "delete".
-    pub value: Option<ValueHash>,
-    /// The index of the path in the corresponding witness.
-    pub path_index: usize,
+    /// Whether a value is stored under the key.
+    pub fn is_present(&self) -> bool {
+        matches!(self, Probe::Leaf { .. })
+    }
 }
 
 /// Whether a key was read, written, or both, along with old and new values.
@@ -186,44 +357,370 @@ pub struct Nomt<T: HashAlgorithm> {
     /// The number of active sessions. Expected to be either 0 or 1.
     session_cnt: Arc<AtomicUsize>,
     metrics: Metrics,
+    /// Whether the database has been closed with [`Nomt::close`].
+    closed: bool,
+    /// The options the database was opened with, used to reinitialize it on [`Nomt::reset`].
+    options: Options,
+    checkpoints: checkpoint::Checkpoints,
+    proof_cache: proof_cache::ProofCache,
+    watchers: watch::Watchers,
+    backup: Option<backup::Backup>,
+    /// The commits applied in memory but not written to disk yet, see [`SyncMode`].
+    deferred: Mutex<DeferredCommits>,
     _marker: std::marker::PhantomData<T>,
 }
 
+// The commits applied in memory and left to be synced later, along with their page diffs and the
+// tag of the last one. Their values are staged in the store.
+struct DeferredCommits {
+    commits: Vec<AppliedCommit>,
+    page_diffs: Vec<merkle::PageDiffs>,
+    commit_tag: Option<Vec<u8>>,
+    /// When the deferred commits were last synced.
+    last_sync: Instant,
+}
+
+// Receives the witnessed paths of a commit along with the reads and writes along them.
+type OnWitnessedPath<'a> =
+    &'a mut dyn FnMut(WitnessedPath, Vec<WitnessedRead>, Vec<WitnessedWrite>);
+
+// A commit applied in memory, along with what is left to do once it's synced.
+struct AppliedCommit {
+    prev_root: Node,
+    root: Node,
+    watched_changes: Vec<watch::KeyChange>,
+    key_changes: Option<Vec<(KeyPath, Option<Value>)>>,
+    recording: Option<std::path::PathBuf>,
+}
+
+// A commit applied in memory, along with the writes to sync.
+struct StagedCommit {
+    applied: AppliedCommit,
+    tx: store::ValueTransaction,
+    page_diffs: merkle::PageDiffs,
+    commit_tag: Option<Vec<u8>>,
+    witness: Option<Witness>,
+    witnessed_operations: Option<WitnessedOperations>,
+}
+
 impl<T: HashAlgorithm> Nomt<T> {
     /// Open the database with the given options.
-    pub fn open(mut o: Options) -> anyhow::Result<Self> {
+    ///
+    /// Fails with [`Error::IncompatibleFormat`] if the database was written in a format this
+    /// version of NOMT doesn't read.
+    pub fn open(mut o: Options) -> Result<Self> {
         if o.commit_concurrency == 0 {
-            anyhow::bail!("commit concurrency must be greater than zero".to_string());
+            return Err(Error::InvalidOperation(
+                "commit concurrency must be greater than zero".to_string(),
+            ));
         }
 
         if o.commit_concurrency > MAX_COMMIT_CONCURRENCY {
             o.commit_concurrency = MAX_COMMIT_CONCURRENCY;
         }
 
+        if o.rollback && o.sync_mode != options::SyncMode::Always {
+            return Err(Error::InvalidOperation(
+                "deferring commits is not supported with rollback".to_string(),
+            ));
+        }
+
+        if o.read_only && (o.rollback || o.incremental_backup.is_some()) {
+            return Err(Error::InvalidOperation(
+                "rollback and incremental backups are not supported read-only".to_string(),
+            ));
+        }
+
         let metrics = Metrics::new(o.metrics);
 
-        let page_pool = PagePool::new();
-        let store = Store::open(&o, page_pool.clone())?;
-        let root_page = store.load_page(ROOT_PAGE_ID)?;
+        let page_pool = PagePool::with_capacity(
+            o.page_pool_capacity.map(|bytes| bytes / io::PAGE_SIZE),
+            o.on_page_pool_exhausted.clone(),
+        );
+        let store = Store::open(&o, page_pool.clone()).map_err(Error::internal)?;
+        let root_page = store.load_page(ROOT_PAGE_ID).map_err(Error::internal)?;
         let page_cache = PageCache::new(root_page, &o, metrics.clone());
         let root = compute_root_node::<T>(&page_cache);
+        store.set_root(root);
+        let commit_seqn = store.commit_seqn();
+        let checkpoints = checkpoint::Checkpoints::load(&o.path).map_err(Error::internal)?;
+        let backup = o
+            .incremental_backup
+            .clone()
+            .map(|dir| backup::Backup::open(dir, &o.path, root))
+            .transpose()
+            .map_err(Error::internal)?;
         Ok(Self {
-            merkle_update_pool: UpdatePool::new(o.commit_concurrency, o.warm_up),
+            merkle_update_pool: UpdatePool::new(
+                o.commit_concurrency,
+                o.warm_up,
+                o.thread_per_core,
+                o.snapshot_retention > 0,
+            ),
             page_cache,
             page_pool,
             store,
-            shared: Arc::new(Mutex::new(Shared { root })),
+            shared: Arc::new(Mutex::new(Shared {
+                root,
+                synced_root: root,
+                commit_seqn,
+                manifests: Manifests::new(o.manifest_retention),
+                snapshots: VecDeque::new(),
+                trie_versions: trie_versions::TrieVersions::default(),
+                commit_group_open: false,
+                prepared_commit_open: false,
+                commits_in_progress: 0,
+            })),
             session_cnt: Arc::new(AtomicUsize::new(0)),
             metrics,
+            closed: false,
+            checkpoints,
+            proof_cache: proof_cache::ProofCache::new(o.proof_cache_capacity),
+            watchers: watch::Watchers::new(),
+            backup,
+            deferred: Mutex::new(DeferredCommits {
+                commits: Vec::new(),
+                page_diffs: Vec::new(),
+                commit_tag: None,
+                last_sync: Instant::now(),
+            }),
+            options: o,
             _marker: std::marker::PhantomData,
         })
     }
 
+    /// Reads the configuration and state of the database at the given path without opening it.
+    ///
+    /// This doesn't lock the database, replay the WAL or read any b-tree nodes, so it's cheap even
+    /// for large databases. It can be used to decide whether and how to open a database, e.g. to
+    /// check whether opening it requires recovery. If the database is open at the same time, the
+    /// result may be out of date by the time it is returned.
+    ///
+    /// Returns `None` if there is no initialized database at the path.
+    pub fn inspect(path: impl AsRef<std::path::Path>) -> Result<Option<DatabaseInfo>> {
+        store::inspect(path.as_ref()).map_err(Error::internal)
+    }
+
+    /// Repairs the database at the given path after its b-tree metadata was damaged, e.g. when it
+    /// fails to open because of a corrupted free-list. The database must not be open.
+    ///
+    /// The branch and leaf node files are scanned in full. The branch nodes which match their
+    /// checksums and whose leaves are intact are kept, and the free-lists of both files are
+    /// rebuilt from the remaining pages. Branch nodes which are damaged, point to damaged leaves or
+    /// overlap the branch nodes kept are dropped along with their leaves and listed in the report.
+    /// The hash-table is left as is.
+    ///
+    /// Dropping branch nodes loses the values stored in their leaves, and stale branch nodes can't
+    /// always be told apart from live ones if the free-list of the branch node file is lost. Run
+    /// [`Nomt::check_integrity`] with [`IntegrityLevel::Full`] after opening the repaired database
+    /// to find out whether its root still matches the values.
+    ///
+    /// Returns `None` if there is no initialized database at the path.
+    pub fn repair(path: impl AsRef<std::path::Path>) -> Result<Option<RepairReport>> {
+        store::repair(path.as_ref()).map_err(Error::internal)
+    }
+
+    /// Writes the database backed up to the given directory with
+    /// [`Options::incremental_backup`] to the target directory, as of the last commit backed up.
+    /// Returns the root of the written database.
+    ///
+    /// The base of the backup is copied to the target directory and the pages of every increment
+    /// are written over it in order. Increments which are damaged or don't follow each other fail
+    /// the operation. The target directory must not hold a database, and the backup may be written
+    /// to while it is materialized.
+    ///
+    /// Returns `None` if there is no backup in the directory.
+    pub fn materialize_backup(
+        backup_dir: impl AsRef<std::path::Path>,
+        target_dir: impl AsRef<std::path::Path>,
+    ) -> Result<Option<Node>> {
+        backup::materialize(backup_dir.as_ref(), target_dir.as_ref()).map_err(Error::internal)
+    }
+
+    /// Restores the database backed up to the given directory with
+    /// [`Options::incremental_backup`] to the target directory and verifies it. Returns the root of
+    /// the restored database.
+    ///
+    /// The backup is written to the target directory as with [`Nomt::materialize_backup`]. The
+    /// restored database is then opened, which replays the WAL taken with the base, if any, and
+    /// checked with [`Nomt::check_integrity`] at [`IntegrityLevel::Full`]. The checksums of all its
+    /// pages must match and its root must be the one recorded by the backup and match the values.
+    ///
+    /// If any of this fails, the restored files are removed again and [`Error::Corruption`] or
+    /// [`Error::ChecksumMismatch`] is returned for inconsistent data, so the target directory never
+    /// holds a database which opens but is silently inconsistent.
+    ///
+    /// Returns `None` if there is no backup in the directory.
+    pub fn restore(
+        backup_dir: impl AsRef<std::path::Path>,
+        target_dir: impl AsRef<std::path::Path>,
+    ) -> Result<Option<Node>> {
+        let target_dir = target_dir.as_ref();
+        let Some(root) = Self::materialize_backup(backup_dir, target_dir)? else {
+            return Ok(None);
+        };
+
+        let verify = || {
+            let mut o = Options::new();
+            o.path(target_dir);
+            o.verify_checksums(true);
+            let nomt = Self::open(o)?;
+            if nomt.root() != root {
+                return Err(Error::Corruption(
+                    "restored root doesn't match the backup".to_string(),
+                ));
+            }
+            let report = nomt.check_integrity(IntegrityLevel::Full)?;
+            if let Some(issue) = report.issues.first() {
+                return Err(Error::Corruption(format!(
+                    "restored database has {} issues, the first being: {issue}",
+                    report.issues.len()
+                )));
+            }
+            nomt.close()
+        };
+        if let Err(e) = verify() {
+            backup::discard(target_dir).map_err(Error::internal)?;
+            return Err(e);
+        }
+        Ok(Some(root))
+    }
+
     /// Returns a recent root of the trie.
     pub fn root(&self) -> Node {
         self.shared.lock().root.clone()
     }
 
+    /// Returns the tag attached to the last commit with [`Session::set_commit_tag`], if any.
+    ///
+    /// The tag is persisted atomically with the commit, so after a restart this identifies exactly
+    /// which commit was the last one applied. Returns `None` if the last commit carried no tag,
+    /// which includes commits performed by [`Nomt::rollback`].
+    pub fn last_commit_tag(&self) -> Option<Vec<u8>> {
+        self.store.last_commit_tag()
+    }
+
+    /// Returns the sequence number of the last commit written to disk, or 0 if there were none.
+    ///
+    /// The number is persisted atomically with the commit and increases by one with every sync,
+    /// so a commit group or the commits deferred by [`Options::sync_mode`] count once. Comparing
+    /// it across copies of a database tells how far apart they are. The root before the last
+    /// commit is recorded along with it, see [`DatabaseInfo::parent_root`].
+    pub fn commit_seqn(&self) -> u64 {
+        self.shared.lock().commit_seqn
+    }
+
+    /// Returns the current root along with the sequence number of the last commit written to disk
+    /// and whether the root is the one it wrote, all read at once.
+    ///
+    /// Unlike [`Nomt::root`] and [`Nomt::commit_seqn`] called one after the other, this doesn't
+    /// race with a commit in between, so it tells reliably whether a root has been made durable,
+    /// e.g. before announcing a block. It doesn't wait for an in-flight commit.
+    pub fn sync_state(&self) -> SyncState {
+        let shared = self.shared.lock();
+        SyncState {
+            root: shared.root,
+            commit_seqn: shared.commit_seqn,
+            synced: shared.root == shared.synced_root,
+        }
+    }
+
+    /// Returns the binning the database was created with. See [`Options::key_binning`].
+    pub fn key_binning(&self) -> KeyBinning {
+        self.store.key_binning()
+    }
+
+    /// Returns the key path of a key within a bin, given the hashes of both, according to the
+    /// binning the database was created with. See [`KeyBinning::key_path`].
+    pub fn key_path(&self, bin_hash: &[u8; 32], key_hash: &[u8; 32]) -> KeyPath {
+        self.key_binning().key_path(bin_hash, key_hash)
+    }
+
+    /// Returns the roots of the most recent commits along with their sequence numbers, ordered from
+    /// the oldest to the newest.
+    ///
+    /// The history is persisted in the meta file, so it survives restarts. This allows checking
+    /// whether a root claimed by a peer matches a recent local commit.
+    ///
+    /// See [`Options::root_history`].
+    pub fn recent_roots(&self) -> Vec<CommitRoot> {
+        self.store.recent_roots()
+    }
+
+    /// Returns all pages changed since the given root, or `None` if the root is too old to be
+    /// covered by the retained manifests.
+    ///
+    /// This is intended for read replicas which mirror the database files. A replica at `root` can
+    /// catch up by copying the returned pages of the hash-table, leaf and branch node files, along
+    /// with the meta file, which must always be copied in full. If `None` is returned, the replica
+    /// needs a full snapshot.
+    ///
+    /// See [`Options::manifest_retention`].
+    pub fn changed_pages_since(&self, root: Node) -> Option<ChangedPages> {
+        let shared = self.shared.lock();
+        shared.manifests.changed_since(root, shared.root)
+    }
+
+    /// Returns statistics about the on-disk storage: the sizes of the files, the occupancy of the
+    /// hash-table, and the live and free pages of the b-tree node files.
+    ///
+    /// Waits for an in-flight commit to finish.
+    pub fn stats(&self) -> Result<StorageStats> {
+        self.store.stats().map_err(Error::internal)
+    }
+
+    /// Checks whether the database is healthy.
+    ///
+    /// The occupied buckets of the hash-table must match their checksums and hold pages whose IDs
+    /// match the meta bytes, each in a single bucket. The b-tree is walked from the branch nodes,
+    /// checking the checksums of the nodes and that the keys of the leaves are ordered and within
+    /// the bounds of the separators. Every page of the node files must be used by exactly one node or be
+    /// tracked by the free-list, once. Pages which are neither are counted as leaked, and listed by
+    /// page number in the report. With [`IntegrityLevel::Full`], the root of the trie is also
+    /// re-derived from the stored values, unless the structural checks found problems already.
+    ///
+    /// Problems with the contents of the files are listed in the report rather than failing the
+    /// check, which fails only if I/O fails. This reads the whole database, and commits wait for
+    /// the structural part of the check to finish. Commits not written to disk yet, see
+    /// `SyncMode`, are written out first.
+    pub fn check_integrity(&self, level: IntegrityLevel) -> Result<IntegrityReport> {
+        self.flush_deferred()?;
+        self.store.check_usable().map_err(Error::internal)?;
+        let mut report = self.store.check_integrity().map_err(Error::internal)?;
+        // The values can't be read reliably from a damaged b-tree.
+        if level == IntegrityLevel::Full && report.is_healthy() {
+            let snapshot = self.take_snapshot();
+            let values = snapshot
+                .iter(..)
+                .map(|(key_path, value)| (key_path, T::hash_value(&value)));
+            let derived = nomt_core::update::build_trie::<T>(0, values, |_| {});
+            if derived != snapshot.root() {
+                report.issues.push(IntegrityIssue::RootMismatch {
+                    root: snapshot.root(),
+                    derived,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns a snapshot of the usage of the page pool.
+    ///
+    /// See [`Options::page_pool_capacity`].
+    #[cfg(feature = "unstable")]
+    pub fn page_pool_stats(&self) -> PagePoolStats {
+        self.page_pool.stats()
+    }
+
+    /// Returns the summary of replaying the write-ahead log when the database was opened, or
+    /// `None` if there was no WAL to replay, i.e. the last sync completed.
+    ///
+    /// See [`Options::on_recovery_progress`].
+    #[cfg(feature = "unstable")]
+    pub fn recovery_report(&self) -> Option<RecoveryReport> {
+        self.store.recovery_report()
+    }
+
     /// Returns true if the trie has not been modified after the creation.
     pub fn is_empty(&self) -> bool {
         self.root() == TERMINATOR
@@ -235,8 +732,383 @@ impl<T: HashAlgorithm> Nomt<T> {
     ///
     /// This is used for testing for now.
     #[doc(hidden)]
-    pub fn read(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
-        self.store.load_value(path)
+    pub fn read(&self, path: KeyPath) -> Result<Option<Value>> {
+        let value = self.store.load_value(path).map_err(Error::internal)?;
+        Ok(value.map(|v| v.into_value()))
+    }
+
+    /// Returns the value stored under the given key as of the time the trie had the given root.
+    ///
+    /// Historical reads are served from the rollback log, so they require rollback to be enabled
+    /// and only cover the roots of the last [`Options::max_rollback_log_len`] commits. Fails with
+    /// [`Error::InvalidOperation`] if rollback is disabled or the root is not covered by the log.
+    ///
+    /// Only values are served; see [`Nomt::prove_at`] for proofs against older roots.
+    #[cfg(feature = "unstable")]
+    pub fn read_at(&self, root: Node, path: KeyPath) -> Result<Option<Value>> {
+        if root == self.root() {
+            return self.read(path);
+        }
+        let Some(rollback) = self.store.rollback() else {
+            return Err(Error::InvalidOperation(
+                "read_at: rollback not enabled".to_string(),
+            ));
+        };
+        // The current value must be read before consulting the log, see `Rollback::value_at`.
+        let current_value = self
+            .store
+            .load_value(path)
+            .map_err(Error::internal)?
+            .map(|v| v.into_vec());
+        match rollback.value_at(root, path, current_value) {
+            Some(value) => Ok(value.map(Into::into)),
+            None => Err(Error::InvalidOperation(
+                "read_at: root is not retained".to_string(),
+            )),
+        }
+    }
+
+    /// Generate a proof of the path to the given key at the current root. Returns the root along
+    /// with the proof.
+    ///
+    /// The proof shows either the leaf holding the key or the terminator where the key would be.
+    /// Recently generated proofs are cached, see [`Options::proof_cache_capacity`].
+    pub fn prove(&self, path: KeyPath) -> Result<(Node, PathProof)> {
+        // The number of times a proof may fail to match an unchanged root before giving up.
+        const MAX_STALE_ATTEMPTS: usize = 1000;
+
+        self.store.check_usable().map_err(Error::internal)?;
+        let mut stale_attempts = 0;
+        loop {
+            let root = self.root();
+            if let Some(proof) = self.proof_cache.get(root, path) {
+                self.metrics.count(Metric::ProofCacheHits);
+                return Ok((root, proof));
+            }
+            if self.proof_cache.is_enabled() {
+                self.metrics.count(Metric::ProofCacheMisses);
+            }
+
+            let proof = self.seek_path_proof(root, path).map_err(Error::internal)?;
+
+            // A commit may have updated the pages before updating the root, in which case the
+            // proof doesn't match the root and has to be generated again once the root is updated.
+            if proof.verify::<T>(path.view_bits::<Msb0>(), root).is_ok() {
+                self.proof_cache.insert(root, path, proof.clone());
+                return Ok((root, proof));
+            }
+            if self.root() == root {
+                stale_attempts += 1;
+                if stale_attempts == MAX_STALE_ATTEMPTS {
+                    return Err(Error::Corruption(
+                        "path proof doesn't match the root".to_string(),
+                    ));
+                }
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    /// Generate a proof that the given key has no value at the current root. Returns the root
+    /// along with the proof, or `None` if the key has a value.
+    ///
+    /// The proof ends at the node where the path of the key diverges from the trie: either a
+    /// terminator or the leaf of another key. It can be checked with [`proof::verify_absence`].
+    pub fn prove_absence(&self, path: KeyPath) -> Result<Option<(Node, PathProof)>> {
+        let (root, proof) = self.prove(path)?;
+        match &proof.terminal {
+            PathProofTerminal::Leaf(leaf) if leaf.key_path == path => Ok(None),
+            _ => Ok(Some((root, proof))),
+        }
+    }
+
+    /// Returns the values within the range of keys along with a proof that these are all the
+    /// values within the range.
+    ///
+    /// The proof contains a path proof for each bound of the range and for each key within it.
+    /// Fails with [`Error::InvalidOperation`] if the range is empty.
+    pub fn prove_range(&self, range: impl RangeBounds<KeyPath>) -> Result<ProvenRange> {
+        let Some((start, end)) = snapshot::inclusive_range(range) else {
+            return Err(Error::InvalidOperation(
+                "prove_range: empty range".to_string(),
+            ));
+        };
+        self.store.check_usable().map_err(Error::internal)?;
+        'attempt: loop {
+            let snapshot = self.take_snapshot();
+            let root = snapshot.root();
+            let values = snapshot.iter(start..=end).collect::<Vec<_>>();
+
+            let mut path_proofs = Vec::with_capacity(values.len() + 2);
+            let paths = std::iter::once(start)
+                .chain(values.iter().map(|(path, _)| *path))
+                .chain(std::iter::once(end));
+            for path in paths {
+                let (proof_root, proof) = self.prove(path)?;
+                // A commit happened since the snapshot was taken.
+                if proof_root != root {
+                    continue 'attempt;
+                }
+                path_proofs.push(proof);
+            }
+            // The proofs are ordered by key, so their paths are ordered as well. A bound may
+            // share its path with a key within the range.
+            path_proofs.dedup_by(|a, b| {
+                a.siblings.len() == b.siblings.len()
+                    && a.terminal.path()[..a.siblings.len()]
+                        == b.terminal.path()[..b.siblings.len()]
+            });
+            return Ok(ProvenRange {
+                root,
+                values,
+                proof: RangeProof { path_proofs },
+            });
+        }
+    }
+
+    /// Returns the chunk of the state at the given root which starts at `start` and holds at most
+    /// `max_values` values, for serving state sync to peers.
+    ///
+    /// The chunk ends at its last value if more values follow, and at the highest key otherwise.
+    /// The next chunk starts right after it, at [`StateChunk::next`], so the chunks starting at
+    /// the lowest key cover the whole key space. The values are read from the b-tree and proven
+    /// as with [`Nomt::prove_range`].
+    ///
+    /// Only the current root can be served, as the trie isn't kept at earlier roots. Fails with
+    /// [`Error::InvalidOperation`] if `root` is not the current root, including when a commit lands
+    /// while the chunk is produced, or if `max_values` is zero.
+    #[cfg(feature = "unstable")]
+    pub fn state_chunk(&self, root: Node, start: KeyPath, max_values: usize) -> Result<StateChunk> {
+        if max_values == 0 {
+            return Err(Error::InvalidOperation(
+                "state_chunk: max_values must be greater than zero".to_string(),
+            ));
+        }
+        let not_current =
+            || Error::InvalidOperation("state_chunk: root is not the current root".to_string());
+        self.store.check_usable().map_err(Error::internal)?;
+
+        let snapshot = self.take_snapshot();
+        if snapshot.root() != root {
+            return Err(not_current());
+        }
+        let mut iter = snapshot.iter(start..);
+        let last = iter.by_ref().take(max_values).last().map(|(path, _)| path);
+        let next = iter.next().and(last).and_then(state_sync::successor);
+        let end = match next {
+            // UNWRAP: `next` is only set if there is a last value.
+            Some(_) => last.unwrap(),
+            None => [0xFF; 32],
+        };
+
+        let proven = self.prove_range(start..=end)?;
+        if proven.root != root {
+            return Err(not_current());
+        }
+        Ok(StateChunk {
+            root,
+            start,
+            end,
+            values: proven.values,
+            proof: proven.proof,
+            next,
+        })
+    }
+
+    /// Returns an iterator over all the chunks of the state at the given root, from the lowest key
+    /// on, each holding at most `max_values` values. See [`Nomt::state_chunk`].
+    #[cfg(feature = "unstable")]
+    pub fn state_chunks(&self, root: Node, max_values: usize) -> StateChunks<'_, T> {
+        StateChunks {
+            nomt: self,
+            root,
+            max_values,
+            next: Some([0; 32]),
+        }
+    }
+
+    fn seek_path_proof(&self, root: Node, path: KeyPath) -> anyhow::Result<PathProof> {
+        let read_pass = self.page_cache.new_read_pass();
+        let seeker = Seeker::new(
+            root,
+            self.page_cache.clone(),
+            self.store.page_loader(),
+            true,
+        );
+        let seek = seek::seek_blocking(&read_pass, seeker, path)?;
+        Ok(PathProof {
+            terminal: match seek.terminal {
+                Some(leaf_data) => PathProofTerminal::Leaf(leaf_data),
+                None => PathProofTerminal::Terminator(seek.position),
+            },
+            siblings: seek.siblings,
+        })
+    }
+
+    /// Takes a snapshot of the values, which keeps seeing the state as of now while further commits
+    /// land.
+    ///
+    /// This is cheap, but waits for an in-flight commit to finish. See [`Snapshot`].
+    #[cfg(feature = "unstable")]
+    pub fn snapshot(&self) -> Snapshot {
+        self.take_snapshot()
+    }
+
+    fn take_snapshot(&self) -> snapshot::Snapshot {
+        let (root, values, failed) = self.store.snapshot();
+        snapshot::Snapshot::new(root, values, failed)
+    }
+
+    /// Returns the retained snapshot of the values at a recent root, or `None` if no commit since
+    /// opening the database within the retention window produced that root.
+    ///
+    /// See [`Options::snapshot_retention`].
+    #[cfg(feature = "unstable")]
+    pub fn snapshot_at(&self, root: Node) -> Option<Snapshot> {
+        let shared = self.shared.lock();
+        shared
+            .snapshots
+            .iter()
+            .rev()
+            .find(|(snapshot, _)| snapshot.root() == root)
+            .map(|(snapshot, _)| snapshot.clone())
+    }
+
+    /// Generate a proof of the path of a key in the trie at a recent root: the current one, or the
+    /// root of a retained snapshot or of any commit since the oldest retained snapshot.
+    ///
+    /// The trie pages are rebuilt at the root from the nodes overwritten by the commits since,
+    /// which are kept in memory. Fails with [`Error::InvalidOperation`] if the root is not covered.
+    ///
+    /// See [`Options::snapshot_retention`].
+    #[cfg(feature = "unstable")]
+    pub fn prove_at(&self, root: Node, path: KeyPath) -> Result<PathProof> {
+        // The number of times a proof may fail to match the root while no commit lands before
+        // giving up.
+        const MAX_STALE_ATTEMPTS: usize = 1000;
+
+        self.store.check_usable().map_err(Error::internal)?;
+        let mut stale_attempts = 0;
+        loop {
+            let (current_root, undo) = {
+                let shared = self.shared.lock();
+                (shared.root, shared.trie_versions.undo_to(shared.root, root))
+            };
+            let Some(undo) = undo else {
+                return Err(Error::InvalidOperation(
+                    "prove_at: root not retained".to_string(),
+                ));
+            };
+
+            let proof = self
+                .seek_path_proof_at(root, path, &undo)
+                .map_err(Error::internal)?;
+
+            // A commit may have updated the pages before recording the nodes it overwrote, in
+            // which case the proof is generated again once they are recorded.
+            if proof.verify::<T>(path.view_bits::<Msb0>(), root).is_ok() {
+                return Ok(proof);
+            }
+            if self.root() == current_root {
+                stale_attempts += 1;
+                if stale_attempts == MAX_STALE_ATTEMPTS {
+                    return Err(Error::Corruption(
+                        "path proof doesn't match the root".to_string(),
+                    ));
+                }
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    #[cfg(feature = "unstable")]
+    fn seek_path_proof_at(
+        &self,
+        root: Node,
+        path: KeyPath,
+        undo: &[Arc<trie_versions::PriorNodes>],
+    ) -> anyhow::Result<PathProof> {
+        let read_pass = self.page_cache.new_read_pass();
+        trie_versions::seek_path_proof(root, &path, undo, |page_id| {
+            if let Some(page) = self.page_cache.get(page_id.clone()) {
+                return Ok(Some(
+                    (0..page_cache::NODES_PER_PAGE)
+                        .map(|index| page.node(&read_pass, index))
+                        .collect(),
+                ));
+            }
+            Ok(self
+                .store
+                .load_page(page_id.clone())?
+                .map(|(page, _)| trie_versions::page_nodes(&page)))
+        })
+    }
+
+    /// Returns an iterator over the values within the range of keys, in ascending key order.
+    ///
+    /// The iterator sees the values as of the time it was created, unaffected by later commits.
+    /// Like [`Nomt::snapshot`], this waits for an in-flight commit to finish.
+    #[cfg(feature = "unstable")]
+    pub fn iter(&self, range: impl RangeBounds<KeyPath>) -> Iter {
+        self.take_snapshot().iter(range)
+    }
+
+    /// Returns an iterator over the values within the range of keys, in descending key order.
+    ///
+    /// This is useful to find the last values under a prefix. See [`Nomt::iter`].
+    #[cfg(feature = "unstable")]
+    pub fn iter_rev(&self, range: impl RangeBounds<KeyPath>) -> std::iter::Rev<Iter> {
+        self.take_snapshot().iter_rev(range)
+    }
+
+    /// Returns an estimate of the number of keys currently stored.
+    ///
+    /// The number of leaf pages of the b-tree is known without any I/O, and the estimate
+    /// extrapolates from a small, evenly spread sample of them. It is exact for small databases,
+    /// and otherwise usually within a few percent.
+    pub fn estimate_num_keys(&self) -> u64 {
+        self.take_snapshot().estimate_num_keys()
+    }
+
+    /// Returns an estimate of the total size of the keys and values currently stored, in bytes.
+    ///
+    /// Every key counts for 32 bytes. This is estimated like [`Nomt::estimate_num_keys`].
+    pub fn estimate_data_size(&self) -> u64 {
+        self.take_snapshot().estimate_data_size()
+    }
+
+    /// Returns the lowest key currently stored, if any.
+    ///
+    /// Together with [`Nomt::last_key`], this bounds the key space, e.g. to partition it for
+    /// state sync. No value is loaded.
+    pub fn first_key(&self) -> Option<KeyPath> {
+        self.take_snapshot().first_key()
+    }
+
+    /// Returns the highest key currently stored, if any.
+    pub fn last_key(&self) -> Option<KeyPath> {
+        self.take_snapshot().last_key()
+    }
+
+    /// Returns the first key at or after the given one along with its value, if any.
+    ///
+    /// Together with [`Nomt::seek_at_or_before`], this allows using the database as an ordered
+    /// map, e.g. to find the next entry of a queue.
+    pub fn seek_at_or_after(&self, key: KeyPath) -> Option<(KeyPath, Value)> {
+        self.take_snapshot().seek_at_or_after(key)
+    }
+
+    /// Returns the last key at or before the given one along with its value, if any.
+    pub fn seek_at_or_before(&self, key: KeyPath) -> Option<(KeyPath, Value)> {
+        self.take_snapshot().seek_at_or_before(key)
+    }
+
+    /// Returns whether a value is stored under the given key.
+    ///
+    /// This does not load the value itself, so it is cheap even for very large values.
+    /// Fails only if I/O fails.
+    pub fn contains(&self, path: KeyPath) -> Result<bool> {
+        self.store.contains_value(path).map_err(Error::internal)
     }
 
     /// Creates a new [`Session`] object, that serves a purpose of capturing the reads and writes
@@ -271,19 +1143,29 @@ impl<T: HashAlgorithm> Nomt<T> {
             session_cnt: self.session_cnt.clone(),
             metrics: self.metrics.clone(),
             rollback_delta,
+            commit_tag: None,
+            witness_hook: None,
+            deleted_prefixes: Vec::new(),
+            recorder: self
+                .options
+                .record_sessions
+                .clone()
+                .map(|dir| recorder::SessionRecorder::new(dir, self.root())),
+            hinted_overflow_pages: AtomicUsize::new(0),
         }
     }
 
     /// Commit the transaction and returns the new root.
     ///
     /// The actuals are a list of key paths and the corresponding read/write operations. The list
-    /// must be sorted by the key paths in ascending order. The key paths must be unique.
-    pub fn commit(
-        &self,
-        session: Session,
-        actuals: Vec<(KeyPath, KeyReadWrite)>,
-    ) -> anyhow::Result<Node> {
-        match self.commit_inner(session, actuals, false)? {
+    /// must be sorted by the key paths in ascending order. The key paths must be unique, otherwise
+    /// [`Error::InvalidActuals`] is returned.
+    ///
+    /// If writing out the commit fails, e.g. with [`Error::DiskFull`], the files are left at the
+    /// previous commit while the changes are already applied in memory. All later commits and
+    /// reads then fail with [`Error::InvalidOperation`] until the database is reopened.
+    pub fn commit(&self, session: Session, actuals: Vec<(KeyPath, KeyReadWrite)>) -> Result<Node> {
+        match self.commit_inner(session, actuals, None, None)? {
             (node, None, None) => Ok(node),
             // UNWRAP: witness specified to false
             _ => unreachable!(),
@@ -293,46 +1175,390 @@ impl<T: HashAlgorithm> Nomt<T> {
     /// Commit the transaction and create a proof for the given session. Also, returns the new root.
     ///
     /// The actuals are a list of key paths and the corresponding read/write operations. The list
-    /// must be sorted by the key paths in ascending order. The key paths must be unique.
+    /// must be sorted by the key paths in ascending order. The key paths must be unique, otherwise
+    /// [`Error::InvalidActuals`] is returned.
+    ///
+    /// The witness and the witnessed operations are in the canonical order described by
+    /// [`Witness`], so the same commit produces the same witness regardless of the commit
+    /// concurrency.
     pub fn commit_and_prove(
         &self,
         session: Session,
         actuals: Vec<(KeyPath, KeyReadWrite)>,
-    ) -> anyhow::Result<(Node, Witness, WitnessedOperations)> {
-        match self.commit_inner(session, actuals, true)? {
+    ) -> Result<(Node, Witness, WitnessedOperations)> {
+        self.commit_and_prove_selected(session, actuals, |_| true)
+    }
+
+    /// Like [`Nomt::commit_and_prove`], but only witnesses the operations on the keys selected by
+    /// the predicate.
+    ///
+    /// Every path holding a selected key is witnessed along with all the operations along it, so
+    /// the witness may contain operations on other keys as well. All other paths are left out of
+    /// the witness, which makes it smaller. Note that the new root can be recomputed from the
+    /// witness only if every write is witnessed.
+    ///
+    /// The predicate is called once for every key in the actuals, including the keys deleted by
+    /// [`Session::delete_prefix`].
+    pub fn commit_and_prove_selected(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        select: impl Fn(&KeyPath) -> bool,
+    ) -> Result<(Node, Witness, WitnessedOperations)> {
+        match self.commit_inner(session, actuals, Some(&select), None)? {
             (node, Some(witness), Some(witnessed_ops)) => Ok((node, witness, witnessed_ops)),
-            // UNWRAP: witness specified to true
+            // UNWRAP: witness specified
             _ => unreachable!(),
         }
     }
 
+    /// Like [`Nomt::commit_and_prove`], but passes every path of the witness to `on_path` as soon
+    /// as it is produced, instead of building the whole witness in memory. Returns the new root.
+    ///
+    /// This allows writing the witnesses of large commits out incrementally. `on_path` is called
+    /// on the committing thread with the paths in the order of the witness, along with the reads
+    /// and writes along each path. Operations refer to their path by the number of paths passed
+    /// before it, so the paths and operations add up to the witness and witnessed operations
+    /// returned by [`Nomt::commit_and_prove`]. The commit waits while `on_path` is running.
+    pub fn commit_and_stream_witness(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        mut on_path: impl FnMut(WitnessedPath, Vec<WitnessedRead>, Vec<WitnessedWrite>),
+    ) -> Result<Node> {
+        self.commit_inner(session, actuals, Some(&|_| true), Some(&mut on_path))
+            .map(|(node, _, _)| node)
+    }
+
+    /// Open a group of commits which are written to disk together, see [`CommitGroup`]. Commits
+    /// not written to disk yet, see [`SyncMode`], are written out first.
+    ///
+    /// Fails with [`Error::InvalidOperation`] if a group is open already, if another commit is in
+    /// progress, or if rollback is enabled.
+    #[cfg(feature = "unstable")]
+    pub fn commit_group(&self) -> Result<CommitGroup<'_, T>> {
+        self.store.check_writable().map_err(Error::internal)?;
+        if self.store.rollback().is_some() {
+            return Err(Error::InvalidOperation(
+                "commit groups are not supported with rollback".to_string(),
+            ));
+        }
+        self.flush_deferred()?;
+        let mut shared = self.shared.lock();
+        if shared.commit_group_open {
+            return Err(Error::InvalidOperation(
+                "a commit group is open already".to_string(),
+            ));
+        }
+        if shared.prepared_commit_open {
+            return Err(Error::InvalidOperation(
+                "a prepared commit is open".to_string(),
+            ));
+        }
+        if shared.commits_in_progress > 0 {
+            return Err(Error::InvalidOperation(
+                "a commit is in progress".to_string(),
+            ));
+        }
+        shared.commit_group_open = true;
+        Ok(CommitGroup {
+            nomt: self,
+            commits: Vec::new(),
+            page_diffs: Vec::new(),
+            commit_tag: None,
+        })
+    }
+
+    /// Prepare a commit of the session, to be completed or aborted along with the transaction of
+    /// an external database in a two-phase commit, see [`PreparedCommit`]. The actuals are as for
+    /// [`Nomt::commit`]. Commits not written to disk yet, see [`SyncMode`], are written out first.
+    ///
+    /// Fails with [`Error::InvalidOperation`] if a commit group or another prepared commit is
+    /// open, or if another commit is in progress.
+    #[cfg(feature = "unstable")]
+    pub fn prepare(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+    ) -> Result<PreparedCommit<'_, T>> {
+        self.flush_deferred()?;
+        {
+            let mut shared = self.shared.lock();
+            if shared.commit_group_open {
+                return Err(Error::InvalidOperation(
+                    "a commit group is open".to_string(),
+                ));
+            }
+            if shared.prepared_commit_open {
+                return Err(Error::InvalidOperation(
+                    "a prepared commit is open already".to_string(),
+                ));
+            }
+            if shared.commits_in_progress > 0 {
+                return Err(Error::InvalidOperation(
+                    "a commit is in progress".to_string(),
+                ));
+            }
+            shared.prepared_commit_open = true;
+        }
+        let prepared = self
+            .apply_commit(session, actuals, None, None)
+            .and_then(|staged| {
+                let prev_root = staged.applied.prev_root;
+                let prepared = self
+                    .store
+                    .prepare_commit(
+                        staged.tx,
+                        self.page_cache.clone(),
+                        staged.page_diffs,
+                        staged.commit_tag,
+                        staged.applied.root,
+                    )
+                    .map_err(|e| {
+                        self.shared.lock().root = prev_root;
+                        Error::internal(e)
+                    })?;
+                Ok(PreparedCommit {
+                    nomt: self,
+                    commit: Some((staged.applied, prepared)),
+                })
+            });
+        if prepared.is_err() {
+            self.shared.lock().prepared_commit_open = false;
+        }
+        prepared
+    }
+
+    /// Create a witness of the reads of a session without committing it. Returns the root the
+    /// reads are proven against, along with the witness and the witnessed reads.
+    ///
+    /// This is much cheaper than [`Nomt::commit_and_prove`]: nothing is written and the proofs
+    /// are served from the page cache and the proof cache. The session ends like after a commit.
+    ///
+    /// The actuals must be sorted by key path and contain only reads of the values in the trie,
+    /// and the session must not have deleted any prefix. Otherwise [`Error::InvalidActuals`] is
+    /// returned.
+    pub fn prove_reads(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+    ) -> Result<(Node, Witness, WitnessedOperations)> {
+        if !session.deleted_prefixes.is_empty() {
+            return Err(Error::InvalidActuals(
+                "prove_reads: session deleted a prefix".to_string(),
+            ));
+        }
+        for i in 1..actuals.len() {
+            if actuals[i].0 <= actuals[i - 1].0 {
+                return Err(Error::InvalidActuals(format!(
+                    "actuals are not sorted at index {i}"
+                )));
+            }
+        }
+
+        let root = self.root();
+        let mut path_proofs: Vec<WitnessedPath> = Vec::new();
+        let mut reads = Vec::with_capacity(actuals.len());
+        for (key, read_write) in &actuals {
+            let KeyReadWrite::Read(value) = read_write else {
+                return Err(Error::InvalidActuals(
+                    "prove_reads: actuals contain a write".to_string(),
+                ));
+            };
+            // Keys are sorted, so the keys sharing a path are adjacent.
+            let in_last_path = path_proofs.last().is_some_and(|last| {
+                key.view_bits::<Msb0>()
+                    .starts_with(&last.path.path()[..last.inner.siblings.len()])
+            });
+            if !in_last_path {
+                let (proof_root, proof) = self.prove(*key)?;
+                if proof_root != root {
+                    return Err(Error::Corruption(
+                        "prove_reads: root changed during the session".to_string(),
+                    ));
+                }
+                let path = match proof.siblings.len() {
+                    0 => TriePosition::new(),
+                    depth => TriePosition::from_bitslice(&key.view_bits::<Msb0>()[..depth]),
+                };
+                path_proofs.push(WitnessedPath { path, inner: proof });
+            }
+            reads.push(WitnessedRead {
+                key: *key,
+                value: value.as_deref().map(T::hash_value),
+                path_index: path_proofs.len() - 1,
+            });
+        }
+        drop(session);
+
+        let witness = Witness {
+            path_proofs,
+            key_binning: self.key_binning(),
+        };
+        let witnessed = WitnessedOperations::new(&witness, reads, Vec::new())
+            .map_err(|e| Error::InvalidActuals(format!("prove_reads: {e}")))?;
+        Ok((root, witness, witnessed))
+    }
+
     // Effectively commit the transaction.
-    // If 'witness' is set to true, it collects the witness and
-    // returns `(Node, Some(Witness), Some(WitnessedOperations))`
+    // If 'witness' is set, it collects the witness of the operations on the selected keys and
+    // returns `(Node, Some(Witness), Some(WitnessedOperations))`, unless `on_path` is set, in
+    // which case the witnessed paths are passed to it instead.
     // Otherwise, it solely returns the new root node, returning
     // `(Node, None, None)`
     fn commit_inner(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        witness: Option<&dyn Fn(&KeyPath) -> bool>,
+        on_path: Option<OnWitnessedPath>,
+    ) -> Result<(Node, Option<Witness>, Option<WitnessedOperations>)> {
+        {
+            let mut shared = self.shared.lock();
+            if shared.commit_group_open {
+                return Err(Error::InvalidOperation(
+                    "a commit group is open, commit through it".to_string(),
+                ));
+            }
+            if shared.prepared_commit_open {
+                return Err(Error::InvalidOperation(
+                    "a prepared commit is open".to_string(),
+                ));
+            }
+            shared.commits_in_progress += 1;
+        }
+        let result = match self.options.sync_mode {
+            options::SyncMode::Always => self
+                .apply_commit(session, actuals, witness, on_path)
+                .and_then(|staged| {
+                    let root = staged.applied.root;
+                    self.sync_commits(
+                        vec![staged.applied],
+                        staged.tx,
+                        staged.page_diffs,
+                        staged.commit_tag,
+                    )?;
+                    Ok((root, staged.witness, staged.witnessed_operations))
+                }),
+            sync_mode => self.defer_commit(session, actuals, witness, on_path, sync_mode),
+        };
+        self.shared.lock().commits_in_progress -= 1;
+        result
+    }
+
+    // Apply the commit in memory and stage its values, leaving it to be synced later with the
+    // other deferred commits. They are synced right away if the sync mode says they are due.
+    fn defer_commit(
+        &self,
+        session: Session,
+        actuals: Vec<(KeyPath, KeyReadWrite)>,
+        witness: Option<&dyn Fn(&KeyPath) -> bool>,
+        on_path: Option<OnWitnessedPath>,
+        sync_mode: options::SyncMode,
+    ) -> Result<(Node, Option<Witness>, Option<WitnessedOperations>)> {
+        let mut deferred = self.deferred.lock();
+        let staged = self.apply_commit(session, actuals, witness, on_path)?;
+        let root = staged.applied.root;
+        self.store.stage_values(staged.tx, root);
+        deferred.commits.push(staged.applied);
+        deferred.page_diffs.push(staged.page_diffs);
+        deferred.commit_tag = staged.commit_tag;
+        let due = match sync_mode {
+            options::SyncMode::Periodic(interval) => deferred.last_sync.elapsed() >= interval,
+            _ => false,
+        };
+        if due {
+            self.sync_deferred(&mut deferred)?;
+        }
+        Ok((root, staged.witness, staged.witnessed_operations))
+    }
+
+    // Sync the deferred commits, if any. If the sync fails, the commits are lost.
+    fn sync_deferred(&self, deferred: &mut DeferredCommits) -> Result<()> {
+        deferred.last_sync = Instant::now();
+        if deferred.commits.is_empty() {
+            return Ok(());
+        }
+        let page_diffs = merkle::PageDiffs::merge(mem::take(&mut deferred.page_diffs));
+        self.sync_commits(
+            mem::take(&mut deferred.commits),
+            self.store.new_value_tx(),
+            page_diffs,
+            deferred.commit_tag.take(),
+        )
+    }
+
+    /// Write the commits applied in memory but not written to disk yet to disk, see
+    /// [`SyncMode`]. Does nothing with [`options::SyncMode::Always`].
+    ///
+    /// If writing them out fails, none of them takes effect on disk and the database has to be
+    /// reopened, as with any failed commit.
+    #[cfg(feature = "unstable")]
+    pub fn flush(&self) -> Result<()> {
+        self.flush_deferred()
+    }
+
+    fn flush_deferred(&self) -> Result<()> {
+        self.sync_deferred(&mut self.deferred.lock())
+    }
+
+    // Apply the commit in memory: update the trie and move the root to the new one. The values
+    // and the trie pages are written out by `sync_commits`.
+    fn apply_commit(
         &self,
         mut session: Session,
         actuals: Vec<(KeyPath, KeyReadWrite)>,
-        witness: bool,
-    ) -> anyhow::Result<(Node, Option<Witness>, Option<WitnessedOperations>)> {
-        if cfg!(debug_assertions) {
-            // Check that the actuals are sorted by key path.
-            for i in 1..actuals.len() {
-                assert!(
-                    actuals[i].0 > actuals[i - 1].0,
-                    "actuals are not sorted at index {}",
-                    i
-                );
+        witness: Option<&dyn Fn(&KeyPath) -> bool>,
+        on_path: Option<OnWitnessedPath>,
+    ) -> Result<StagedCommit> {
+        self.store.check_writable().map_err(Error::internal)?;
+        let recording = session
+            .recorder
+            .take()
+            .map(|recorder| {
+                recorder.commit(session.commit_tag.as_deref(), witness.is_some(), &actuals)
+            })
+            .transpose()
+            .map_err(Error::internal)?;
+
+        // Check that the actuals are sorted by key path.
+        for i in 1..actuals.len() {
+            if actuals[i].0 <= actuals[i - 1].0 {
+                return Err(Error::InvalidActuals(format!(
+                    "actuals are not sorted at index {i}"
+                )));
             }
         }
+        let actuals = self.delete_prefixes(mem::take(&mut session.deleted_prefixes), actuals)?;
+        let watched_changes = self.watchers.changes(&actuals, T::hash_value, |path| {
+            let value = self.store.load_value(path).map_err(Error::internal)?;
+            Ok::<_, Error>(value.map(|v| T::hash_value(&v)))
+        })?;
+        let key_changes = self.watchers.has_commit_feeds().then(|| {
+            actuals
+                .iter()
+                .filter_map(|(path, read_write)| match read_write {
+                    KeyReadWrite::Write(value) | KeyReadWrite::ReadThenWrite(_, value) => {
+                        Some((*path, value.clone()))
+                    }
+                    KeyReadWrite::Read(_) => None,
+                })
+                .collect()
+        });
         if let Some(delta_builder) = session.rollback_delta.take() {
             // UNWRAP: if rollback_delta is `Some``, then rollback must be also `Some`.
             let rollback = self.store.rollback().unwrap();
-            rollback.commit(self.store.clone(), &actuals, delta_builder)?;
+            rollback
+                .commit(self.store.clone(), &actuals, delta_builder, self.root())
+                .map_err(Error::internal)?;
         }
 
+        let witnessed = witness.map(|select| {
+            actuals
+                .iter()
+                .map(|(path, _)| select(path))
+                .collect::<Vec<_>>()
+        });
         let mut compact_actuals = Vec::with_capacity(actuals.len());
         for (path, read_write) in &actuals {
             compact_actuals.push((path.clone(), read_write.to_compact::<T>()));
@@ -343,7 +1569,7 @@ impl<T: HashAlgorithm> Nomt<T> {
             .merkle_updater
             .take()
             .unwrap()
-            .update_and_prove::<T>(compact_actuals, witness);
+            .update_and_prove::<T>(compact_actuals, witnessed, session.witness_hook.take());
 
         let mut tx = self.store.new_value_tx();
         for (path, read_write) in actuals {
@@ -352,32 +1578,387 @@ impl<T: HashAlgorithm> Nomt<T> {
             }
         }
 
-        let merkle_update = merkle_update_handle.join();
+        let merkle_update = match on_path {
+            Some(on_path) => merkle_update_handle.join_streaming(on_path),
+            None => merkle_update_handle.join(),
+        };
 
         let new_root = merkle_update.root;
-        self.shared.lock().root = new_root;
-        self.store
-            .commit(tx, self.page_cache.clone(), merkle_update.page_diffs)?;
+        let prev_root = {
+            let mut shared = self.shared.lock();
+            let prev_root = mem::replace(&mut shared.root, new_root);
+            if self.options.snapshot_retention > 0 {
+                shared
+                    .trie_versions
+                    .push(prev_root, merkle_update.prior_nodes);
+            }
+            prev_root
+        };
+        self.proof_cache.clear();
+        Ok(StagedCommit {
+            applied: AppliedCommit {
+                prev_root,
+                root: new_root,
+                watched_changes,
+                key_changes,
+                recording,
+            },
+            tx,
+            page_diffs: merkle_update.page_diffs,
+            commit_tag: session.commit_tag.take(),
+            witness: merkle_update.witness,
+            witnessed_operations: merkle_update.witnessed_operations,
+        })
+    }
+
+    // Sync the commits applied since the last sync, from the oldest to the newest, along with the
+    // values they wrote which weren't staged yet and their page diffs merged. On failure, the root
+    // is moved back to the one before the commits.
+    fn sync_commits(
+        &self,
+        commits: Vec<AppliedCommit>,
+        tx: store::ValueTransaction,
+        page_diffs: merkle::PageDiffs,
+        commit_tag: Option<Vec<u8>>,
+    ) -> Result<()> {
+        // UNWRAP: there is at least one commit.
+        let prev_root = commits[0].prev_root;
+        let new_root = self.root();
+        let changed_pages = self
+            .store
+            .commit(
+                tx,
+                self.page_cache.clone(),
+                page_diffs,
+                commit_tag,
+                new_root,
+            )
+            .map_err(|e| {
+                self.shared.lock().root = prev_root;
+                Error::internal(e)
+            })?;
+        self.commits_synced(commits, new_root, changed_pages)
+    }
+
+    // Record the commits written out by a sync and notify their observers.
+    fn commits_synced(
+        &self,
+        commits: Vec<AppliedCommit>,
+        new_root: Node,
+        changed_pages: ChangedPages,
+    ) -> Result<()> {
+        // UNWRAP: there is at least one commit.
+        let prev_root = commits[0].prev_root;
+        // The commits are on disk from here on, even if recording them below fails.
+        let commit_seqn = {
+            let mut shared = self.shared.lock();
+            shared.synced_root = new_root;
+            shared.commit_seqn += 1;
+            shared.commit_seqn
+        };
+        let increment = if self.backup.is_some() || self.watchers.has_replication_feeds() {
+            let increment =
+                backup::encode_increment(&self.store, prev_root, new_root, &changed_pages)
+                    .map_err(Error::internal)?;
+            Some(Arc::<[u8]>::from(increment))
+        } else {
+            None
+        };
+        if let (Some(backup), Some(increment)) = (&self.backup, &increment) {
+            backup.append(increment).map_err(Error::internal)?;
+        }
+        if let Some(increment) = increment {
+            self.watchers.notify_sync(watch::ReplicationRecord {
+                commit_seqn,
+                prev_root,
+                root: new_root,
+                bytes: increment,
+            });
+        }
+        let snapshot = (self.options.snapshot_retention > 0).then(|| self.take_snapshot());
+        {
+            let mut shared = self.shared.lock();
+            shared.manifests.push(CommitManifest {
+                prev_root,
+                root: new_root,
+                pages: changed_pages.clone(),
+            });
+            if let Some(snapshot) = snapshot {
+                // the overwritten nodes are kept for the commits after the oldest snapshot.
+                let pruned_commits = if shared.snapshots.is_empty() {
+                    commits.len()
+                } else if shared.snapshots.len() == self.options.snapshot_retention {
+                    shared.snapshots.pop_front();
+                    // with a retention of 1, the new snapshot becomes the oldest one.
+                    shared.snapshots.front().map_or(commits.len(), |s| s.1)
+                } else {
+                    0
+                };
+                shared.trie_versions.prune(pruned_commits);
+                shared.snapshots.push_back((snapshot, commits.len()));
+            }
+        }
+        for commit in commits {
+            let diff = commit.key_changes.map(|key_changes| watch::CommitDiff {
+                prev_root: commit.prev_root,
+                root: commit.root,
+                key_changes,
+                pages: changed_pages.clone(),
+            });
+            self.watchers
+                .notify(commit.root, &commit.watched_changes, diff);
+            if let Some(path) = commit.recording {
+                recorder::SessionRecorder::committed(&path, commit.root)
+                    .map_err(Error::internal)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a deletion to the actuals for every stored key starting with one of the prefixes.
+    ///
+    /// Keys written in the actuals keep the written value, and keys only read become deleted after
+    /// the read.
+    fn delete_prefixes(
+        &self,
+        prefixes: Vec<BitVec<u8, Msb0>>,
+        mut actuals: Vec<(KeyPath, KeyReadWrite)>,
+    ) -> Result<Vec<(KeyPath, KeyReadWrite)>> {
+        if prefixes.is_empty() {
+            return Ok(actuals);
+        }
+
+        let mut deleted = BTreeSet::new();
+        for prefix in prefixes {
+            let mut start = [0; 32];
+            let mut end = [0xFF; 32];
+            start.view_bits_mut::<Msb0>()[..prefix.len()].copy_from_bitslice(&prefix);
+            end.view_bits_mut::<Msb0>()[..prefix.len()].copy_from_bitslice(&prefix);
+            deleted.extend(
+                self.store
+                    .keys_in_range(start, end)
+                    .map_err(Error::internal)?,
+            );
+        }
+
+        for (path, read_write) in &mut actuals {
+            if deleted.remove(path) {
+                if let KeyReadWrite::Read(_) = read_write {
+                    read_write.write(None);
+                }
+            }
+        }
+        if !deleted.is_empty() {
+            actuals.extend(
+                deleted
+                    .into_iter()
+                    .map(|path| (path, KeyReadWrite::Write(None))),
+            );
+            actuals.sort_unstable_by_key(|(path, _)| *path);
+        }
+        Ok(actuals)
+    }
+
+    /// Load the given values into an empty database and return the new root.
+    ///
+    /// This is meant for loading large initial states, such as the genesis state of a chain. The
+    /// values must be given in ascending order of their key paths, without duplicates. They are
+    /// committed in large batches without collecting witnesses or logging prior values, so the
+    /// commits performed by the import can't be rolled back.
+    ///
+    /// Fails with [`Error::InvalidOperation`] if the database is not empty and with
+    /// [`Error::InvalidActuals`] if the values are not in order. In that case, the batches before
+    /// the offending value remain committed.
+    ///
+    /// This function assumes no sessions are active and panics otherwise.
+    pub fn import(&self, values: impl IntoIterator<Item = (KeyPath, Value)>) -> Result<Node> {
+        if !self.is_empty() {
+            return Err(Error::InvalidOperation(
+                "import: the database is not empty".to_string(),
+            ));
+        }
+
+        let mut values = values.into_iter().peekable();
+        let mut root = self.root();
+        let mut last_key = None;
+        while values.peek().is_some() {
+            let actuals = values
+                .by_ref()
+                .take(IMPORT_BATCH_SIZE)
+                .map(|(path, value)| (path, KeyReadWrite::Write(Some(value))))
+                .collect::<Vec<_>>();
+            if last_key.is_some_and(|last_key| actuals[0].0 <= last_key) {
+                return Err(Error::InvalidActuals(
+                    "import: values are not sorted".to_string(),
+                ));
+            }
+            last_key = actuals.last().map(|(path, _)| *path);
+
+            root = self.commit_values(actuals)?;
+        }
+        Ok(root)
+    }
+
+    /// Commit the given actuals as part of a bulk load, without recording the session or logging
+    /// prior values.
+    fn commit_values(&self, actuals: Vec<(KeyPath, KeyReadWrite)>) -> Result<Node> {
+        let mut session = self.begin_session_inner(/* allow_rollback */ false);
+        session.recorder = None;
+        Ok(self
+            .commit_inner(session, actuals, /* witness */ None, None)?
+            .0)
+    }
+
+    /// Start syncing the state at the given root into this database from chunks served by
+    /// [`Nomt::state_chunk`], e.g. by peers. See [`StateSync`].
+    ///
+    /// Fails with [`Error::InvalidOperation`] if the database is not empty.
+    #[cfg(feature = "unstable")]
+    pub fn state_sync(&self, root: Node) -> Result<StateSync<'_, T>> {
+        if !self.is_empty() {
+            return Err(Error::InvalidOperation(
+                "state_sync: the database is not empty".to_string(),
+            ));
+        }
+        Ok(StateSync {
+            nomt: self,
+            root,
+            next: Some([0; 32]),
+        })
+    }
+
+    /// Write the values stored in the database to the writer in a portable, versioned format and
+    /// return the root they correspond to.
+    ///
+    /// The export holds the values in ascending order of their key paths, split into chunks with
+    /// checksums, followed by the root. It doesn't depend on the layout of the database files or
+    /// the options the database was opened with, and can be loaded into an empty database with
+    /// [`Nomt::import_stream`]. The values are read from a snapshot, so commits may proceed while
+    /// exporting.
+    #[cfg(feature = "unstable")]
+    pub fn export(&self, writer: impl std::io::Write) -> Result<Node> {
+        let snapshot = self.take_snapshot();
+        export::write(writer, snapshot.root(), snapshot.iter(..)).map_err(Error::Io)?;
+        Ok(snapshot.root())
+    }
+
+    /// Load an export written by [`Nomt::export`] into an empty database and return the new root.
+    ///
+    /// The values are committed as with [`Nomt::import`], one chunk at a time as soon as its
+    /// checksum is verified. Once the whole export is read, the root of the database must match
+    /// the root recorded in the export.
+    ///
+    /// Fails with [`Error::InvalidOperation`] if the database is not empty or the export was
+    /// written with an unsupported version of the format, and with [`Error::Corruption`] if the
+    /// export is malformed, truncated or doesn't match its root. The values committed before the
+    /// failure remain committed, so the database should be reset before trying again.
+    ///
+    /// This function assumes no sessions are active and panics otherwise.
+    #[cfg(feature = "unstable")]
+    pub fn import_stream(&self, reader: impl std::io::Read) -> Result<Node> {
+        let mut values = export::Reader::new(reader)?;
+        let root = self.import(values.by_ref())?;
+        if values.finish()? != root {
+            return Err(Error::Corruption(
+                "the imported values don't match the root of the export".to_string(),
+            ));
+        }
+        Ok(root)
+    }
 
-        Ok((
-            new_root,
-            merkle_update.witness,
-            merkle_update.witnessed_operations,
-        ))
+    /// Re-execute a recorded session: replay its warm-ups and reads, then commit its actuals.
+    ///
+    /// The database must be at the root the session began at, e.g. a copy of the database taken
+    /// at that point. Reads returning different values than recorded are reported rather than
+    /// failing the replay. See [`Options::record_sessions`].
+    #[cfg(feature = "unstable")]
+    pub fn replay(&self, recording: &Recording) -> Result<ReplayOutcome> {
+        if self.root() != recording.prior_root {
+            return Err(Error::InvalidOperation(
+                "replay: the database is not at the prior root of the recording".to_string(),
+            ));
+        }
+
+        let mut session = self.begin_session();
+        let mut mismatched_reads = Vec::new();
+        for op in &recording.ops {
+            match op {
+                RecordedOp::WarmUp(path) => session.warm_up(*path),
+                RecordedOp::Read(path, value) => {
+                    if session.read(*path)? != *value {
+                        mismatched_reads.push(*path);
+                    }
+                }
+                RecordedOp::DeletePrefix(prefix) => session.delete_prefix(prefix)?,
+            }
+        }
+        if let Some(tag) = &recording.commit_tag {
+            session.set_commit_tag(tag.clone());
+        }
+
+        let actuals = recording.actuals.clone();
+        let root = if recording.prove {
+            self.commit_and_prove(session, actuals)?.0
+        } else {
+            self.commit(session, actuals)?
+        };
+        Ok(ReplayOutcome {
+            root,
+            mismatched_reads,
+        })
+    }
+
+    /// Watch the given keys for changes.
+    ///
+    /// The returned watcher receives an event for every commit changing the value of at least one
+    /// of the keys, including commits performed by [`Nomt::rollback`]. Writes which leave a value
+    /// unchanged are not reported. Events carry the hashes of the values rather than the values.
+    ///
+    /// Events are only sent for successful commits. Dropping the watcher stops the watch.
+    #[cfg(feature = "unstable")]
+    pub fn watch(&self, key_paths: impl IntoIterator<Item = KeyPath>) -> Watcher {
+        self.watchers.watch(key_paths)
+    }
+
+    /// Subscribe to the changes made by all commits.
+    ///
+    /// The returned feed receives a [`CommitDiff`] for every successful commit, including commits
+    /// performed by [`Nomt::rollback`], with the values written and the pages changed by the commit.
+    /// This allows indexers and replicas to follow the database without re-reading its state.
+    #[cfg(feature = "unstable")]
+    pub fn commit_feed(&self) -> CommitFeed {
+        self.watchers.commit_feed()
+    }
+
+    /// Subscribe to the pages written by all syncs, to replicate the database in another process.
+    ///
+    /// The returned feed receives a [`ReplicationRecord`] for every sync once it is on disk, with
+    /// its sequence number and the pages it wrote encoded as bytes along with a checksum. Unlike
+    /// the [`CommitDiff`]s of a [`Nomt::commit_feed`], the records carry the contents of the
+    /// pages, and commits synced together, as with [`Options::sync_mode`] or a [`CommitGroup`],
+    /// are received as one. A follower holding a copy of the database files as of the
+    /// subscription can bring it up to date with them, see [`ReplicationRecord::bytes`].
+    ///
+    /// Reading the pages back adds to the cost of every sync while a feed is registered.
+    #[cfg(feature = "unstable")]
+    pub fn replication_feed(&self) -> ReplicationFeed {
+        self.watchers.replication_feed()
     }
 
     /// Perform a rollback of the last `n` commits.
     ///
     /// This function assumes no sessions are active and panics otherwise.
-    pub fn rollback(&self, n: usize) -> anyhow::Result<()> {
+    pub fn rollback(&self, n: usize) -> Result<()> {
         if n == 0 {
             return Ok(());
         }
         let Some(rollback) = self.store.rollback() else {
-            anyhow::bail!("rollback: not enabled");
+            return Err(Error::InvalidOperation("rollback: not enabled".to_string()));
         };
-        let Some(traceback) = rollback.truncate(n)? else {
-            anyhow::bail!("rollback: not enough logged for rolling back");
+        let Some(traceback) = rollback.truncate(n).map_err(Error::internal)? else {
+            return Err(Error::InvalidOperation(
+                "rollback: not enough logged for rolling back".to_string(),
+            ));
         };
 
         // Begin a new session. We do not allow rollback for this operation because that would
@@ -389,20 +1970,140 @@ impl<T: HashAlgorithm> Nomt<T> {
         let mut actuals = Vec::new();
         for (key, value) in traceback {
             sess.warm_up(key);
-            let value = KeyReadWrite::Write(value);
+            let value = KeyReadWrite::Write(value.map(Value::from));
             actuals.push((key, value));
         }
 
-        self.commit_inner(sess, actuals, /* witness */ false)?;
+        self.commit_inner(sess, actuals, /* witness */ None, None)?;
 
         Ok(())
     }
 
+    /// Record the current root under the given name, replacing an existing checkpoint with the same
+    /// name. Returns the root.
+    ///
+    /// The database can be reverted to the checkpoint with [`Nomt::rollback_to`] as long as the
+    /// rollback log retains all the commits made since, see [`Options::max_rollback_log_len`].
+    /// Checkpoints are persisted and survive reopening the database.
+    pub fn checkpoint(&self, name: impl Into<String>) -> Result<Node> {
+        if self.store.rollback().is_none() {
+            return Err(Error::InvalidOperation(
+                "checkpoint: rollback not enabled".to_string(),
+            ));
+        }
+        let root = self.root();
+        self.checkpoints
+            .insert(name.into(), root)
+            .map_err(Error::internal)?;
+        Ok(root)
+    }
+
+    /// Revert the database to the named checkpoint by rolling back the commits made since it was
+    /// taken.
+    ///
+    /// Fails if there is no such checkpoint or if the rollback log no longer retains all the
+    /// commits made since. Checkpoints taken after the one rolled back to are kept, but can't be
+    /// rolled back to unless the database reaches their roots again.
+    ///
+    /// This function assumes no sessions are active and panics otherwise.
+    pub fn rollback_to(&self, name: &str) -> Result<()> {
+        let Some(root) = self.checkpoints.get(name) else {
+            return Err(Error::InvalidOperation(format!(
+                "rollback_to: no checkpoint named {name:?}"
+            )));
+        };
+        if root == self.root() {
+            return Ok(());
+        }
+        let Some(rollback) = self.store.rollback() else {
+            return Err(Error::InvalidOperation(
+                "rollback_to: rollback not enabled".to_string(),
+            ));
+        };
+        let Some(n) = rollback.commits_since(root) else {
+            return Err(Error::InvalidOperation(format!(
+                "rollback_to: checkpoint {name:?} is not retained by the rollback log"
+            )));
+        };
+        self.rollback(n)
+    }
+
+    /// Returns the names and roots of all checkpoints, ordered by name.
+    pub fn checkpoints(&self) -> Vec<(String, Node)> {
+        self.checkpoints.list()
+    }
+
+    /// Remove the named checkpoint. Returns whether it existed.
+    pub fn remove_checkpoint(&self, name: &str) -> Result<bool> {
+        self.store.check_writable().map_err(Error::internal)?;
+        self.checkpoints.remove(name).map_err(Error::internal)
+    }
+
     /// Return Nomt's metrics.
     /// To collect them, they need to be activated at [`Nomt`] creation
     pub fn metrics(&self) -> Metrics {
         self.metrics.clone()
     }
+
+    /// Close the database.
+    ///
+    /// This waits for an in-flight commit, if any, to finish, writes out the commits not written to
+    /// disk yet, see `SyncMode`, and flushes all files to disk, reporting any error. Dropping
+    /// [`Nomt`] does the same on a best-effort basis, ignoring errors.
+    ///
+    /// Sessions which are still alive keep the files open until they are dropped.
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.flush_deferred()?;
+        self.store.flush().map_err(Error::internal)
+    }
+
+    /// Wipe the database and reinitialize it to an empty trie, returning the reopened instance.
+    ///
+    /// All values, the rollback log and the last commit tag are discarded. The wipe is atomic: if
+    /// the process crashes during the reset, the database is found either intact or empty on the
+    /// next open.
+    ///
+    /// Fails if a session is active.
+    pub fn reset(mut self) -> Result<Self> {
+        self.wipe()?;
+        let options = self.options.clone();
+        drop(self);
+        Self::open(options)
+    }
+
+    /// Wipe the database and remove its directory.
+    ///
+    /// Like [`Nomt::reset`], this is atomic with respect to crashes.
+    ///
+    /// Fails if a session is active.
+    pub fn destroy(mut self) -> Result<()> {
+        self.wipe()?;
+        store::remove_store_files(&self.options.path).map_err(Error::Io)?;
+        std::fs::remove_dir_all(&self.options.path).map_err(Error::Io)
+    }
+
+    fn wipe(&mut self) -> Result<()> {
+        if self.session_cnt.load(std::sync::atomic::Ordering::Relaxed) != 0 {
+            return Err(Error::InvalidOperation(
+                "cannot wipe the database while a session is active".to_string(),
+            ));
+        }
+        self.store
+            .wipe(&self.options.path)
+            .map_err(Error::internal)?;
+        self.closed = true;
+        Ok(())
+    }
+}
+
+impl<T: HashAlgorithm> Drop for Nomt<T> {
+    fn drop(&mut self) {
+        if !self.closed {
+            let _ = self.flush_deferred();
+            let _ = self.store.flush();
+        }
+    }
 }
 
 /// A session presents a way of interaction with the trie.
@@ -417,6 +2118,13 @@ pub struct Session {
     session_cnt: Arc<AtomicUsize>,
     metrics: Metrics,
     rollback_delta: Option<rollback::ReverseDeltaBuilder>,
+    commit_tag: Option<Vec<u8>>,
+    witness_hook: Option<Arc<dyn merkle::WitnessHook>>,
+    deleted_prefixes: Vec<BitVec<u8, Msb0>>,
+    recorder: Option<recorder::SessionRecorder>,
+    /// The total number of overflow pages needed by the value sizes hinted at with
+    /// [`Session::warm_up_write`].
+    hinted_overflow_pages: AtomicUsize,
 }
 
 impl Session {
@@ -432,16 +2140,195 @@ impl Session {
     /// session to maximize throughput.
     /// There is no correctness issue with doing too many warm-ups, but there is a cost for I/O.
     pub fn warm_up(&self, path: KeyPath) {
+        if let Some(recorder) = &self.recorder {
+            recorder.warm_up(path);
+        }
         // UNWRAP: merkle_updater always `Some` during lifecycle.
         self.merkle_updater.as_ref().unwrap().warm_up(path);
     }
 
+    /// Like [`Session::warm_up`], for a key which is going to be written, along with an optional
+    /// hint of the size of the value.
+    ///
+    /// Values too large to be stored within a leaf node are split into overflow pages. Given a
+    /// hint, the space for those pages is reserved in the leaf node file right away instead of
+    /// while committing, which shortens the commits of sessions writing many large values. The
+    /// hint doesn't need to be exact, the commit allocates whatever the actual values need.
+    ///
+    /// Fails only if extending the file fails.
+    pub fn warm_up_write(&self, path: KeyPath, value_size_hint: Option<usize>) -> Result<()> {
+        self.warm_up(path);
+        let pages = value_size_hint.map_or(0, beatree::overflow_pages);
+        if pages == 0 {
+            return Ok(());
+        }
+        // The reservation is relative to the state of the file as of the last commit, so it must
+        // cover all the hinted values.
+        let total_pages = self
+            .hinted_overflow_pages
+            .fetch_add(pages, std::sync::atomic::Ordering::Relaxed)
+            + pages;
+        self.store
+            .reserve_overflow_pages(total_pages)
+            .map_err(Error::internal)
+    }
+
     /// Synchronously read the value stored under the given key.
     ///
     /// Returns `None` if the value is not stored under the given key. Fails only if I/O fails.
-    pub fn read(&self, path: KeyPath) -> anyhow::Result<Option<Value>> {
+    pub fn read(&self, path: KeyPath) -> Result<Option<Value>> {
+        Ok(self.read_ref(path)?.map(|v| v.into_value()))
+    }
+
+    /// Synchronously read the value stored under the given key, without copying it.
+    ///
+    /// Values small enough to be stored inline are served directly from the page they were read
+    /// into. Use [`ValueRef::to_vec`] to obtain an owned copy.
+    ///
+    /// Returns `None` if the value is not stored under the given key. Fails only if I/O fails.
+    pub fn read_ref(&self, path: KeyPath) -> Result<Option<ValueRef>> {
         let _maybe_guard = self.metrics.record(Metric::ValueFetchTime);
-        self.store.load_value(path)
+        let value = self.store.load_value(path).map_err(Error::internal)?;
+        if let Some(recorder) = &self.recorder {
+            recorder.read(path, value.as_deref());
+        }
+        Ok(value)
+    }
+
+    /// Find the node terminating the path of the given key in the trie, as of the beginning of
+    /// the session.
+    ///
+    /// This tells whether the key is present and at which depth its path ends, without reading
+    /// the value. Only the merkle pages along the path are loaded, which are usually warm. An empty
+    /// value is present, unlike a deleted one.
+    ///
+    /// Fails only if I/O fails.
+    pub fn probe(&self, path: KeyPath) -> Result<Probe> {
+        self.store.check_usable().map_err(Error::internal)?;
+        // UNWRAP: merkle_updater always `Some` during lifecycle.
+        self.merkle_updater
+            .as_ref()
+            .unwrap()
+            .probe(path)
+            .map_err(Error::internal)
+    }
+
+    /// Estimate the size of the witness of committing the given operations, in the canonical
+    /// format of [`Witness::encode`], before committing them.
+    ///
+    /// The paths of the witness are predicted from the depths at which the paths of the keys end
+    /// in the trie, as found by [`Session::probe`], so the estimate is cheapest after warming up
+    /// the keys. It is an upper bound on the size of the witness of
+    /// [`Nomt::commit_and_prove`], except for keys under prefixes deleted in the same commit: it
+    /// assumes that all siblings at distinct positions are distinct nodes, while the witness
+    /// shares identical ones, such as empty subtries.
+    ///
+    /// Fails only if I/O fails.
+    pub fn estimate_witness_size(&self, actuals: &[(KeyPath, KeyReadWrite)]) -> Result<usize> {
+        let mut paths = std::collections::BTreeMap::new();
+        for (key, _) in actuals {
+            let probe = self.probe(*key)?;
+            let terminal = match probe {
+                Probe::Terminator { .. } => witness_size::TerminalKind::Terminator,
+                Probe::Leaf { .. } | Probe::OtherLeaf { .. } => witness_size::TerminalKind::Leaf,
+            };
+            let path = key.view_bits::<Msb0>()[..probe.depth() as usize].to_bitvec();
+            paths.insert(path, terminal);
+        }
+        Ok(witness_size::estimate_encoded_len(&paths))
+    }
+
+    /// Returns the root of the subtrie holding all the keys starting with the given prefix, as of
+    /// the beginning of the session.
+    ///
+    /// This allows committing to a range of keys, e.g. the storage of an account, and proving it
+    /// independently of the rest of the trie: the returned node can be proven with a path proof
+    /// to the prefix. Since the trie doesn't store subtries with a single leaf, the root of such a
+    /// subtrie is the leaf node itself, and the root of an empty subtrie is the terminator.
+    ///
+    /// Fails if the prefix is longer than a key path, or if I/O fails.
+    pub fn subtree_root(&self, prefix: &BitSlice<u8, Msb0>) -> Result<Node> {
+        if prefix.len() > 256 {
+            return Err(Error::InvalidOperation(
+                "subtree_root: prefix longer than a key path".to_string(),
+            ));
+        }
+        let position = if prefix.is_empty() {
+            TriePosition::new()
+        } else {
+            TriePosition::from_bitslice(prefix)
+        };
+        self.store.check_usable().map_err(Error::internal)?;
+        // UNWRAP: merkle_updater always `Some` during lifecycle.
+        self.merkle_updater
+            .as_ref()
+            .unwrap()
+            .subtrie_root(&position)
+            .map_err(Error::internal)
+    }
+
+    /// Delete every key starting with the given prefix when the session is committed, removing
+    /// the whole subtrie under the prefix.
+    ///
+    /// The deletion applies to the keys stored as of the beginning of the session, without the
+    /// caller having to enumerate them in the actuals. Keys written in the actuals keep the written
+    /// value, and keys only read are deleted after the read. An empty prefix deletes everything.
+    ///
+    /// Fails if the prefix is longer than a key path.
+    pub fn delete_prefix(&mut self, prefix: &BitSlice<u8, Msb0>) -> Result<()> {
+        if prefix.len() > 256 {
+            return Err(Error::InvalidOperation(
+                "delete_prefix: prefix longer than a key path".to_string(),
+            ));
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.delete_prefix(prefix);
+        }
+        self.deleted_prefixes.push(prefix.to_bitvec());
+        Ok(())
+    }
+
+    /// Synchronously check whether a value is stored under the given key.
+    ///
+    /// Unlike [`Session::read`], this does not load the value itself, so it is cheap even for very
+    /// large values. Fails only if I/O fails.
+    pub fn contains(&self, path: KeyPath) -> Result<bool> {
+        self.store.contains_value(path).map_err(Error::internal)
+    }
+
+    /// Synchronously get the size in bytes of the value stored under the given key.
+    ///
+    /// Returns `None` if the value is not stored under the given key. Large values are not loaded,
+    /// making this suitable for rejecting oversized reads or planning buffers ahead of a
+    /// [`Session::read`], unless the database is [compressed](Options::compression). Fails only if
+    /// I/O fails.
+    pub fn value_size(&self, path: KeyPath) -> Result<Option<usize>> {
+        self.store.value_size(path).map_err(Error::internal)
+    }
+
+    /// Attach an opaque tag to the commit of this session, e.g. the hash of the block being applied.
+    ///
+    /// The tag is persisted atomically with the commit and is available through
+    /// [`Nomt::last_commit_tag`], including after a restart.
+    ///
+    /// Panics if the tag is longer than [`MAX_COMMIT_TAG_LEN`] bytes.
+    pub fn set_commit_tag(&mut self, tag: impl Into<Vec<u8>>) {
+        let tag = tag.into();
+        assert!(
+            tag.len() <= MAX_COMMIT_TAG_LEN,
+            "commit tag exceeds {MAX_COMMIT_TAG_LEN} bytes"
+        );
+        self.commit_tag = Some(tag);
+    }
+
+    /// Set a hook to be called by the commit workers for every path witnessed by the commit of
+    /// this session. See [`WitnessHook`].
+    ///
+    /// The hook is called with the paths of all the operations of the commit, even if the commit
+    /// doesn't collect the witness or only witnesses some of the keys.
+    #[cfg(feature = "unstable")]
+    pub fn set_witness_hook(&mut self, hook: Arc<dyn WitnessHook>) {
+        self.witness_hook = Some(hook);
     }
 
     /// Signals that the given key is going to be written to. Relevant only if rollback is enabled.
@@ -544,4 +2431,49 @@ mod tests {
 
         is_sync::<crate::Session>();
     }
+
+    #[test]
+    fn key_read_write_is_send_sync() {
+        fn is_send_sync<T: Send + Sync>() {}
+
+        is_send_sync::<crate::KeyReadWrite>();
+    }
+
+    #[test]
+    fn commit_fails_when_the_wal_blob_cannot_grow() {
+        fn commit(
+            nomt: &crate::Nomt<crate::Blake3Hasher>,
+            ids: std::ops::Range<u32>,
+        ) -> crate::Result<crate::Node> {
+            let session = nomt.begin_session();
+            let actuals = ids
+                .map(|id| {
+                    let mut key = [0; 32];
+                    key[..4].copy_from_slice(&id.to_be_bytes());
+                    (key, crate::KeyReadWrite::Write(Some(vec![1; 8].into())))
+                })
+                .collect();
+            nomt.commit(session, actuals)
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut o = crate::Options::new();
+        o.path(dir.path());
+        o.bitbox_seed([0; 16]);
+        o.hashtable_buckets(4096);
+        let nomt = crate::Nomt::open(o.clone()).unwrap();
+        let root = commit(&nomt, 0..100).unwrap();
+
+        // The WAL of the next commit doesn't fit in a single page.
+        nomt.store.limit_wal_blob(crate::io::PAGE_SIZE);
+        assert!(commit(&nomt, 100..1000).is_err());
+        assert!(matches!(
+            commit(&nomt, 1000..1001),
+            Err(crate::Error::InvalidOperation(_))
+        ));
+        drop(nomt);
+
+        let nomt = crate::Nomt::<crate::Blake3Hasher>::open(o).unwrap();
+        assert_eq!(nomt.root(), root);
+    }
 }
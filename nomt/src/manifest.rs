@@ -0,0 +1,171 @@
+//! Commit manifests, used to bring lagging read replicas up to date.
+//!
+//! A replica which mirrors the files of a NOMT instance at some root only needs the pages which
+//! were written since that root in order to catch up, rather than a full snapshot. Each commit
+//! produces a manifest listing those pages, and the last few manifests are retained in memory.
+
+use std::collections::VecDeque;
+
+use crate::Node;
+
+/// A set of pages changed by one or more commits.
+///
+/// Each list is sorted and free of duplicates.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangedPages {
+    /// The indices of the changed pages within the hash-table file.
+    pub ht_pages: Vec<u64>,
+    /// The numbers of the changed pages within the leaf node file.
+    pub ln_pages: Vec<u32>,
+    /// The numbers of the changed pages within the bottom-level branch node file.
+    pub bbn_pages: Vec<u32>,
+}
+
+impl ChangedPages {
+    pub(crate) fn new(
+        mut ht_pages: Vec<u64>,
+        mut ln_pages: Vec<u32>,
+        mut bbn_pages: Vec<u32>,
+    ) -> Self {
+        sort_dedup(&mut ht_pages);
+        sort_dedup(&mut ln_pages);
+        sort_dedup(&mut bbn_pages);
+        Self {
+            ht_pages,
+            ln_pages,
+            bbn_pages,
+        }
+    }
+
+    /// Whether no pages have been changed.
+    pub fn is_empty(&self) -> bool {
+        self.ht_pages.is_empty() && self.ln_pages.is_empty() && self.bbn_pages.is_empty()
+    }
+
+    fn extend(&mut self, other: &ChangedPages) {
+        self.ht_pages.extend_from_slice(&other.ht_pages);
+        self.ln_pages.extend_from_slice(&other.ln_pages);
+        self.bbn_pages.extend_from_slice(&other.bbn_pages);
+    }
+}
+
+fn sort_dedup<T: Ord>(v: &mut Vec<T>) {
+    v.sort_unstable();
+    v.dedup();
+}
+
+/// Describes the pages changed by a single commit.
+#[derive(Clone, Debug)]
+pub struct CommitManifest {
+    /// The root before the commit.
+    pub prev_root: Node,
+    /// The root after the commit.
+    pub root: Node,
+    /// The pages written by the commit.
+    pub pages: ChangedPages,
+}
+
+/// The manifests of the most recent commits.
+pub(crate) struct Manifests {
+    retention: usize,
+    manifests: VecDeque<CommitManifest>,
+}
+
+impl Manifests {
+    pub fn new(retention: usize) -> Self {
+        Self {
+            retention,
+            manifests: VecDeque::with_capacity(retention),
+        }
+    }
+
+    /// Record the manifest of a commit, discarding the oldest one if the retention limit is
+    /// reached.
+    pub fn push(&mut self, manifest: CommitManifest) {
+        if self.retention == 0 {
+            return;
+        }
+        if self.manifests.len() == self.retention {
+            self.manifests.pop_front();
+        }
+        self.manifests.push_back(manifest);
+    }
+
+    /// Returns all pages changed since the given root, or `None` if the root is not covered by
+    /// the retained manifests.
+    ///
+    /// If the root occurs multiple times in the history, the pages since its oldest occurrence
+    /// are returned. The same root does not imply the same page layout on disk, so this errs on
+    /// the side of transferring more pages.
+    pub fn changed_since(&self, root: Node, current_root: Node) -> Option<ChangedPages> {
+        let start = self.manifests.iter().position(|m| m.prev_root == root);
+        let start = match start {
+            Some(start) => start,
+            None if root == current_root => return Some(ChangedPages::default()),
+            None => return None,
+        };
+
+        let mut pages = ChangedPages::default();
+        for manifest in self.manifests.range(start..) {
+            pages.extend(&manifest.pages);
+        }
+        Some(ChangedPages::new(
+            pages.ht_pages,
+            pages.ln_pages,
+            pages.bbn_pages,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChangedPages, CommitManifest, Manifests};
+
+    fn manifest(prev_root: u8, root: u8, ht_page: u64) -> CommitManifest {
+        CommitManifest {
+            prev_root: [prev_root; 32],
+            root: [root; 32],
+            pages: ChangedPages::new(vec![ht_page], vec![ht_page as u32], vec![]),
+        }
+    }
+
+    #[test]
+    fn changed_since_merges_manifests() {
+        let mut manifests = Manifests::new(3);
+        manifests.push(manifest(0, 1, 10));
+        manifests.push(manifest(1, 2, 5));
+        manifests.push(manifest(2, 3, 10));
+
+        let pages = manifests.changed_since([1; 32], [3; 32]).unwrap();
+        assert_eq!(pages.ht_pages, vec![5, 10]);
+        assert_eq!(pages.ln_pages, vec![5, 10]);
+        assert!(pages.bbn_pages.is_empty());
+
+        assert!(manifests
+            .changed_since([3; 32], [3; 32])
+            .unwrap()
+            .is_empty());
+        assert!(manifests.changed_since([9; 32], [3; 32]).is_none());
+    }
+
+    #[test]
+    fn old_manifests_are_discarded() {
+        let mut manifests = Manifests::new(2);
+        manifests.push(manifest(0, 1, 1));
+        manifests.push(manifest(1, 2, 2));
+        manifests.push(manifest(2, 3, 3));
+
+        assert!(manifests.changed_since([0; 32], [3; 32]).is_none());
+        assert_eq!(
+            manifests.changed_since([1; 32], [3; 32]).unwrap().ht_pages,
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn retention_zero_keeps_nothing() {
+        let mut manifests = Manifests::new(0);
+        manifests.push(manifest(0, 1, 1));
+        assert!(manifests.changed_since([0; 32], [1; 32]).is_none());
+    }
+}
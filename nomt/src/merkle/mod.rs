@@ -2,40 +2,78 @@
 //!
 //! This splits the work of warming-up and performing the trie update across worker threads.
 
+use bitvec::prelude::*;
 use crossbeam::channel::{self, Receiver, Sender};
 use parking_lot::Mutex;
 
 use nomt_core::{
+    binning::KeyBinning,
     page_id::PageId,
     trie::{self, KeyPath, Node, NodeHasher, ValueHash},
     trie_pos::TriePosition,
 };
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use crate::{
     io::PagePool,
     page_cache::{PageCache, ShardIndex},
     page_diff::PageDiff,
     rw_pass_cell::WritePassEnvelope,
-    seek::Seek,
+    seek::{self, Seek, Seeker},
     store::Store,
-    Witness, WitnessedOperations, WitnessedPath, WitnessedRead, WitnessedWrite,
+    trie_versions::PriorNodes,
+    Probe, Witness, WitnessedOperations, WitnessedPath, WitnessedRead, WitnessedWrite,
 };
 use threadpool::ThreadPool;
 
 mod page_walker;
 mod worker;
 
-/// Page diffs produced by update workers.
-pub struct PageDiffs(Vec<Vec<(PageId, PageDiff)>>);
+/// Page diffs produced by update workers, ordered by page ID.
+///
+/// The order doesn't depend on the number of workers or on their scheduling, so neither does the
+/// order in which the pages are written to the WAL.
+pub struct PageDiffs(Vec<(PageId, PageDiff)>);
+
+impl PageDiffs {
+    fn new(worker_diffs: Vec<Vec<(PageId, PageDiff)>>) -> Self {
+        let mut page_diffs = worker_diffs.into_iter().flatten().collect::<Vec<_>>();
+        // A worker may produce several diffs for the same page, which must stay in order.
+        page_diffs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        PageDiffs(page_diffs)
+    }
+
+    /// Merge the diffs of consecutive commits, from the oldest to the newest, into the diffs of a
+    /// single commit.
+    pub fn merge(commits: Vec<PageDiffs>) -> Self {
+        let mut merged = BTreeMap::<PageId, PageDiff>::new();
+        for (page_id, page_diff) in commits.into_iter().flatten() {
+            match merged.entry(page_id) {
+                Entry::Vacant(entry) => {
+                    entry.insert(page_diff);
+                }
+                Entry::Occupied(mut entry) => entry.get_mut().join(&page_diff),
+            }
+        }
+        PageDiffs(merged.into_iter().collect())
+    }
+
+    /// The number of changed pages.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
 
 impl IntoIterator for PageDiffs {
     type Item = (PageId, PageDiff);
-    type IntoIter = std::iter::Flatten<<Vec<Vec<Self::Item>> as IntoIterator>::IntoIter>;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter().flatten()
+        self.0.into_iter()
     }
 }
 
@@ -75,25 +113,53 @@ impl KeyReadWrite {
     }
 }
 
+/// A hook receiving the paths witnessed by a commit directly from the commit workers.
+///
+/// This allows feeding the paths into a proving pipeline, e.g. to build the witness tables of a
+/// zk circuit, while the commit is still running and without going over the witness again
+/// afterwards. Set with [`Session::set_witness_hook`](crate::Session::set_witness_hook).
+pub trait WitnessHook: Send + Sync {
+    /// Called for every path down the trie walked by the commit, with the siblings and the
+    /// terminal node of the path in the trie before the commit. These are the paths of the witness
+    /// of all the operations of the commit.
+    ///
+    /// This is called concurrently by the commit workers, with the paths in no particular order,
+    /// and the commit waits while it's running.
+    fn on_path(&self, path: &WitnessedPath);
+}
+
 /// The update worker pool.
 pub struct UpdatePool {
     worker_tp: ThreadPool,
     do_warm_up: bool,
+    shard_warm_up: bool,
+    record_prior_nodes: bool,
 }
 
 impl UpdatePool {
     /// Create a new `UpdatePool`.
     ///
+    /// If `shard_warm_up` is set, the warm-ups are split between one worker per shard of the page
+    /// cache instead of being handled by a single worker. If `record_prior_nodes` is set, the
+    /// values of the nodes overwritten by every update are returned in its [`Output`].
+    ///
     /// # Panics
     ///
     /// Panics if `num_workers` is zero.
-    pub fn new(num_workers: usize, do_warm_up: bool) -> Self {
+    pub fn new(
+        num_workers: usize,
+        do_warm_up: bool,
+        shard_warm_up: bool,
+        record_prior_nodes: bool,
+    ) -> Self {
         UpdatePool {
             worker_tp: threadpool::Builder::new()
                 .num_threads(num_workers)
                 .thread_name("nomt-commit".to_string())
                 .build(),
             do_warm_up,
+            shard_warm_up,
+            record_prior_nodes,
         }
     }
 
@@ -118,7 +184,12 @@ impl UpdatePool {
         };
 
         let warm_up = if self.do_warm_up {
-            Some(spawn_warm_up(&self.worker_tp, params))
+            let num_workers = if self.shard_warm_up {
+                page_cache.shard_count()
+            } else {
+                1
+            };
+            Some(spawn_warm_up(&self.worker_tp, params, num_workers))
         } else {
             None
         };
@@ -130,6 +201,7 @@ impl UpdatePool {
             root,
             store,
             page_pool,
+            record_prior_nodes: self.record_prior_nodes,
         }
     }
 }
@@ -144,55 +216,157 @@ pub struct Updater {
     root: Node,
     store: Store,
     page_pool: PagePool,
+    record_prior_nodes: bool,
 }
 
 impl Updater {
     /// Warm up the given key-path by pre-fetching the relevant pages.
     pub fn warm_up(&self, key_path: KeyPath) {
         if let Some(ref warm_up) = self.warm_up {
-            let _ = warm_up.warmup_tx.send(WarmUpCommand { key_path });
+            // With a worker per shard, the key is sent to the one owning the pages along its path.
+            let worker = match warm_up.workers.len() {
+                1 => &warm_up.workers[0],
+                _ => &warm_up.workers[self.page_cache.shard_index_for_key(&key_path)],
+            };
+            let _ = worker.warmup_tx.send(WarmUpCommand { key_path });
         }
     }
 
+    /// Find the node terminating the path of the given key, as of the beginning of the session.
+    pub fn probe(&self, key_path: KeyPath) -> anyhow::Result<Probe> {
+        let read_pass = self.page_cache.new_read_pass();
+        let seeker = Seeker::new(
+            self.root,
+            self.page_cache.clone(),
+            self.store.page_loader(),
+            false,
+        );
+        let seek = seek::seek_blocking(&read_pass, seeker, key_path)?;
+        let depth = seek.position.depth();
+        Ok(match seek.terminal {
+            Some(leaf) if leaf.key_path == key_path => Probe::Leaf {
+                depth,
+                value_hash: leaf.value_hash,
+            },
+            Some(leaf) => Probe::OtherLeaf {
+                depth,
+                key_path: leaf.key_path,
+            },
+            None => Probe::Terminator { depth },
+        })
+    }
+
+    /// Returns the root of the subtrie at the given position, as of the beginning of the session.
+    ///
+    /// If the path to the position ends in a terminal node before reaching it, the subtrie holds
+    /// at most one leaf. Its root is then that leaf node if the leaf's key lies under the
+    /// position, and the terminator otherwise.
+    pub fn subtrie_root(&self, position: &TriePosition) -> anyhow::Result<Node> {
+        if position.is_root() {
+            return Ok(self.root);
+        }
+
+        let mut key = KeyPath::default();
+        key.view_bits_mut::<Msb0>()[..position.depth() as usize]
+            .copy_from_bitslice(position.path());
+
+        let read_pass = self.page_cache.new_read_pass();
+        let seeker = Seeker::new(
+            self.root,
+            self.page_cache.clone(),
+            self.store.page_loader(),
+            false,
+        );
+        let seek = seek::seek_blocking(&read_pass, seeker, key)?;
+
+        let node_position = if seek.position.depth() >= position.depth() {
+            position
+        } else {
+            match seek.terminal {
+                Some(ref leaf) if position.subtrie_contains(&leaf.key_path) => &seek.position,
+                _ => return Ok(trie::TERMINATOR),
+            }
+        };
+        if node_position.is_root() {
+            return Ok(self.root);
+        }
+
+        // The seek brings all the pages along the path into the cache.
+        //
+        // UNWRAP: the position is not the root.
+        let page_id = node_position.page_id().unwrap();
+        let page = match self.page_cache.get(page_id.clone()) {
+            Some(page) => page,
+            None => {
+                let page = self.store.load_page(page_id.clone())?;
+                self.page_cache.insert(page_id, page)
+            }
+        };
+        Ok(page.node(&read_pass, node_position.node_index()))
+    }
+
     /// Update the trie with the given key-value read/write operations.
     /// Key-paths should be in sorted order
     /// and should appear at most once within the vector. Witness specifies whether or not
-    /// to collect the witness of the operation.
+    /// to collect the witness of the operation, and if so, which operations to witness: a path is
+    /// witnessed along with all its operations if any of them is selected.
+    ///
+    /// If a hook is given, it is called by the workers for every path they walk, whether or not
+    /// the witness is collected.
     pub fn update_and_prove<H: NodeHasher>(
         self,
         read_write: Vec<(KeyPath, KeyReadWrite)>,
-        witness: bool,
+        witness: Option<Vec<bool>>,
+        witness_hook: Option<Arc<dyn WitnessHook>>,
     ) -> UpdateHandle {
         if let Some(ref warm_up) = self.warm_up {
-            let _ = warm_up.finish_tx.send(());
+            for worker in &warm_up.workers {
+                let _ = worker.finish_tx.send(());
+            }
         }
         let shared = Arc::new(UpdateShared {
-            witness,
+            witness: witness.is_some(),
+            witness_hook,
+            key_binning: self.store.key_binning(),
             read_write,
             root_page_pending: Mutex::new(Vec::with_capacity(64)),
+            record_prior_nodes: self.record_prior_nodes,
         });
 
         let num_workers = self.page_cache.shard_count();
         let shard_regions = (0..num_workers).map(ShardIndex::Shard).collect::<Vec<_>>();
 
-        // receive warm-ups from worker.
+        // receive warm-ups from the workers. With a warm-up worker per shard, every update worker
+        // gets the warm-ups of its own shard only.
         // TODO: handle error better.
-        let warm_ups = if let Some(ref warm_up) = self.warm_up {
-            warm_up.output_rx.recv().unwrap()
-        } else {
-            HashMap::new()
+        let warm_ups = match self.warm_up {
+            Some(ref warm_up) => warm_up
+                .workers
+                .iter()
+                .map(|worker| Arc::new(worker.output_rx.recv().unwrap()))
+                .collect(),
+            None => vec![Arc::new(HashMap::new())],
         };
-        let warm_ups = Arc::new(warm_ups);
 
         let write_pass = self.page_cache.new_write_pass();
         let worker_passes = write_pass.split_n(shard_regions);
 
         let (worker_tx, worker_rx) = crossbeam_channel::bounded(num_workers);
 
-        for write_pass in worker_passes {
+        // workers block when the witnessed paths aren't taken as fast as they're produced, which
+        // bounds the memory used by witnesses of large commits when they are streamed.
+        let (witness_tx, witness_rx) = if shared.witness {
+            let (tx, rx) = crossbeam_channel::bounded(WITNESS_CHANNEL_CAPACITY);
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+
+        for (shard_index, write_pass) in worker_passes.into_iter().enumerate() {
             let command = UpdateCommand {
                 shared: shared.clone(),
                 write_pass: write_pass.into_envelope(),
+                witness_tx: witness_tx.clone(),
             };
 
             let params = worker::UpdateParams {
@@ -200,7 +374,7 @@ impl Updater {
                 page_pool: self.page_pool.clone(),
                 store: self.store.clone(),
                 root: self.root,
-                warm_ups: warm_ups.clone(),
+                warm_ups: warm_ups[shard_index.min(warm_ups.len() - 1)].clone(),
                 command,
             };
             spawn_updater::<H>(&self.worker_tp, params, worker_tx.clone());
@@ -208,68 +382,94 @@ impl Updater {
 
         UpdateHandle {
             shared,
+            witnessed: witness,
             worker_rx,
+            witness_rx,
             num_workers,
         }
     }
 }
 
+// The number of witnessed paths which may be waiting to be taken from the workers.
+const WITNESS_CHANNEL_CAPACITY: usize = 1024;
+
 /// A handle for waiting on the results of a commit operation.
 pub struct UpdateHandle {
     shared: Arc<UpdateShared>,
+    // Which operations to witness, if any.
+    witnessed: Option<Vec<bool>>,
     worker_rx: Receiver<WorkerOutput>,
+    witness_rx: Option<Receiver<WitnessedBatch>>,
     num_workers: usize,
 }
 
 impl UpdateHandle {
     /// Wait on the results of the commit operation.
     pub fn join(self) -> Output {
-        let mut new_root = None;
+        if !self.shared.witness {
+            return self.join_streaming(|_, _, _| {});
+        }
 
-        let mut maybe_witness = self.shared.witness.then_some(Witness {
+        let mut witness = Witness {
             path_proofs: Vec::new(),
+            key_binning: self.shared.key_binning,
+        };
+        let (mut reads, mut writes) = (Vec::new(), Vec::new());
+        let mut output = self.join_streaming(|path, path_reads, path_writes| {
+            witness.path_proofs.push(path);
+            reads.extend(path_reads);
+            writes.extend(path_writes);
         });
 
-        let mut maybe_witnessed_ops = self.shared.witness.then_some(WitnessedOperations {
-            reads: Vec::new(),
-            writes: Vec::new(),
-        });
-
-        let mut page_diffs = Vec::new();
-
-        let mut path_proof_offset = 0;
-        let mut witnessed_start = 0;
-
-        let mut received_outputs = 0;
-        for output in self.worker_rx.into_iter() {
-            received_outputs += 1;
-            if let Some(root) = output.root {
-                assert!(new_root.is_none());
-                new_root = Some(root);
-            }
+        // UNWRAP: every operation refers to the path it was committed along, and the read
+        // values are taken from the terminals of the paths.
+        let witnessed_operations = WitnessedOperations::new(&witness, reads, writes).unwrap();
+        debug_assert!(witness.is_canonical() && witnessed_operations.is_canonical());
+        output.witnessed_operations = Some(witnessed_operations);
+        output.witness = Some(witness);
+        output
+    }
 
-            page_diffs.push(output.page_diffs);
+    /// Wait on the results of the commit operation, passing every witnessed path to `on_path`
+    /// along with the operations along it, as soon as it is produced.
+    ///
+    /// Paths are passed in key order, and the operations refer to them by the index at which
+    /// they were passed, so they add up to the same witness and operations returned by
+    /// [`UpdateHandle::join`]. The returned output contains neither.
+    pub fn join_streaming(
+        self,
+        mut on_path: impl FnMut(WitnessedPath, Vec<WitnessedRead>, Vec<WitnessedWrite>),
+    ) -> Output {
+        if let Some(witness_rx) = self.witness_rx {
+            // UNWRAP: `witnessed` is `Some` if and only if witnesses are collected.
+            let selected = self.witnessed.as_ref().unwrap();
+
+            // workers cover disjoint ranges of keys and finish paths in any order, so paths are
+            // held back until all paths before them are done.
+            let mut pending = BTreeMap::new();
+            let mut next_start = 0;
+            let mut path_index = 0;
+
+            // the channel is closed once all workers are done with their ranges.
+            for batch in witness_rx {
+                pending.insert(batch.start, batch);
+                while let Some(batch) = pending.remove(&next_start) {
+                    let WitnessedBatch {
+                        start,
+                        size,
+                        path,
+                        terminal,
+                    } = batch;
+                    next_start = start + size;
+                    if !selected[start..next_start].contains(&true) {
+                        continue;
+                    }
 
-            // if the Commit worker collected the witnessed paths
-            // then we need to aggregate them
-            if let Some(witnessed_paths) = output.witnessed_paths {
-                // UNWRAP: the same `UpdateShared` object is used to decide whether
-                // to collect witnesses or not. If the commit worker did so,
-                // `maybe_witness` and `maybe_witnessed_ops` must be initialized to contain
-                // all witnesses from all workers.
-                let witness = maybe_witness.as_mut().unwrap();
-                let witnessed_ops = maybe_witnessed_ops.as_mut().unwrap();
-
-                let path_proof_count = witnessed_paths.len();
-                witness.path_proofs.reserve(witnessed_paths.len());
-                for (path_index, (path, leaf_data, batch_size)) in
-                    witnessed_paths.into_iter().enumerate()
-                {
-                    witness.path_proofs.push(path);
-                    let witnessed_end = witnessed_start + batch_size;
-                    for (k, v) in &self.shared.read_write[witnessed_start..witnessed_end] {
+                    let mut reads = Vec::new();
+                    let mut writes = Vec::new();
+                    for (k, v) in &self.shared.read_write[start..next_start] {
                         if v.is_read() {
-                            let value_hash = leaf_data.as_ref().and_then(|leaf_data| {
+                            let value_hash = terminal.as_ref().and_then(|leaf_data| {
                                 if &leaf_data.key_path == k {
                                     Some(leaf_data.value_hash)
                                 } else {
@@ -277,25 +477,40 @@ impl UpdateHandle {
                                 }
                             });
 
-                            witnessed_ops.reads.push(WitnessedRead {
+                            reads.push(WitnessedRead {
                                 key: *k,
                                 value: value_hash,
-                                path_index: path_index + path_proof_offset,
+                                path_index,
                             });
                         }
                         if let Some(written) = v.written_value() {
-                            witnessed_ops.writes.push(WitnessedWrite {
+                            writes.push(WitnessedWrite {
                                 key: *k,
                                 value: written,
-                                path_index: path_index + path_proof_offset,
+                                path_index,
                             });
                         }
                     }
-                    witnessed_start = witnessed_end;
+                    on_path(path, reads, writes);
+                    path_index += 1;
                 }
+            }
+        }
 
-                path_proof_offset += path_proof_count;
+        let mut new_root = None;
+        let mut page_diffs = Vec::new();
+        let mut prior_nodes = PriorNodes::default();
+
+        let mut received_outputs = 0;
+        for output in self.worker_rx.into_iter() {
+            received_outputs += 1;
+            if let Some(root) = output.root {
+                assert!(new_root.is_none());
+                new_root = Some(root);
             }
+
+            page_diffs.push(output.page_diffs);
+            prior_nodes.extend(output.prior_nodes);
         }
 
         // TODO: handle error when a worker dies unexpectedly.
@@ -304,9 +519,10 @@ impl UpdateHandle {
         // UNWRAP: one thread always produces the root.
         Output {
             root: new_root.unwrap(),
-            page_diffs: PageDiffs(page_diffs),
-            witness: maybe_witness,
-            witnessed_operations: maybe_witnessed_ops,
+            page_diffs: PageDiffs::new(page_diffs),
+            prior_nodes,
+            witness: None,
+            witnessed_operations: None,
         }
     }
 }
@@ -315,8 +531,10 @@ impl UpdateHandle {
 pub struct Output {
     /// The new root.
     pub root: Node,
-    /// All page-diffs from all worker threads. The covered sets of pages are disjoint.
+    /// All page-diffs from all worker threads, ordered by page ID.
     pub page_diffs: PageDiffs,
+    /// The values of the nodes overwritten by the update, if recorded.
+    pub prior_nodes: PriorNodes,
     /// Optional witness
     pub witness: Option<Witness>,
     /// Optional list of all witnessed operations.
@@ -326,6 +544,15 @@ pub struct Output {
 struct UpdateCommand {
     shared: Arc<UpdateShared>,
     write_pass: WritePassEnvelope<ShardIndex>,
+    witness_tx: Option<Sender<WitnessedBatch>>,
+}
+
+// A path witnessed by a worker, along with the range of operations committed along it.
+struct WitnessedBatch {
+    start: usize,
+    size: usize,
+    path: WitnessedPath,
+    terminal: Option<trie::LeafData>,
 }
 
 struct WarmUpCommand {
@@ -341,20 +568,11 @@ enum RootPagePending {
     Node(Node),
 }
 
+#[derive(Default)]
 struct WorkerOutput {
     root: Option<Node>,
-    witnessed_paths: Option<Vec<(WitnessedPath, Option<trie::LeafData>, usize)>>,
     page_diffs: Vec<(PageId, PageDiff)>,
-}
-
-impl WorkerOutput {
-    fn new(witness: bool) -> Self {
-        WorkerOutput {
-            root: None,
-            witnessed_paths: if witness { Some(Vec::new()) } else { None },
-            page_diffs: Vec::new(),
-        }
-    }
+    prior_nodes: PriorNodes,
 }
 
 // Shared data used in committing.
@@ -363,9 +581,18 @@ struct UpdateShared {
     // nodes needing to be written to pages above a shard.
     root_page_pending: Mutex<Vec<(TriePosition, RootPagePending)>>,
     witness: bool,
+    witness_hook: Option<Arc<dyn WitnessHook>>,
+    key_binning: KeyBinning,
+    // whether the values of overwritten nodes are recorded.
+    record_prior_nodes: bool,
 }
 
 impl UpdateShared {
+    // whether the siblings along the paths are needed, for the witness or for the hook.
+    fn record_siblings(&self) -> bool {
+        self.witness || self.witness_hook.is_some()
+    }
+
     fn push_pending_root_nodes(&self, nodes: Vec<(TriePosition, Node)>) {
         let mut pending = self.root_page_pending.lock();
         for (trie_pos, node) in nodes {
@@ -399,24 +626,40 @@ impl UpdateShared {
     }
 }
 
+// The warm-up workers: a single one, or one per shard of the page cache.
 struct WarmUpHandle {
+    workers: Vec<WarmUpWorker>,
+}
+
+struct WarmUpWorker {
     finish_tx: Sender<()>,
     warmup_tx: Sender<WarmUpCommand>,
     output_rx: Receiver<HashMap<KeyPath, Seek>>,
 }
 
-fn spawn_warm_up(worker_tp: &ThreadPool, params: worker::WarmUpParams) -> WarmUpHandle {
-    let (warmup_tx, warmup_rx) = channel::unbounded();
-    let (output_tx, output_rx) = channel::bounded(1);
-    let (finish_tx, finish_rx) = channel::bounded(1);
-
-    worker_tp.execute(move || worker::run_warm_up(params, warmup_rx, finish_rx, output_tx));
+fn spawn_warm_up(
+    worker_tp: &ThreadPool,
+    params: worker::WarmUpParams,
+    num_workers: usize,
+) -> WarmUpHandle {
+    let workers = (0..num_workers)
+        .map(|_| {
+            let (warmup_tx, warmup_rx) = channel::unbounded();
+            let (output_tx, output_rx) = channel::bounded(1);
+            let (finish_tx, finish_rx) = channel::bounded(1);
+
+            let params = params.clone();
+            worker_tp.execute(move || worker::run_warm_up(params, warmup_rx, finish_rx, output_tx));
+
+            WarmUpWorker {
+                warmup_tx,
+                finish_tx,
+                output_rx,
+            }
+        })
+        .collect();
 
-    WarmUpHandle {
-        warmup_tx,
-        finish_tx,
-        output_rx,
-    }
+    WarmUpHandle { workers }
 }
 
 fn spawn_updater<H: NodeHasher>(
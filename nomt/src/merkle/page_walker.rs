@@ -56,6 +56,7 @@ use crate::{
     page_cache::{Page, PageCache, PageCacheShard, ShardIndex},
     page_diff::PageDiff,
     rw_pass_cell::{ReadPass, RegionContains, WritePass},
+    trie_versions::PriorNodes,
 };
 
 /// An error type that's returned when a page is needed in order to compact up.
@@ -71,12 +72,16 @@ pub enum Output {
     /// A new root node.
     ///
     /// This is always the output when no parent page is supplied to the walker.
-    Root(Node, Vec<(PageId, PageDiff)>),
+    Root(Node, Vec<(PageId, PageDiff)>, PriorNodes),
     /// Nodes to set in the bottom layer of the parent page, indexed by the position of the node
     /// to set.
     ///
     /// This is always the output when a parent page is supplied to the walker.
-    ChildPageRoots(Vec<(TriePosition, Node)>, Vec<(PageId, PageDiff)>),
+    ChildPageRoots(
+        Vec<(TriePosition, Node)>,
+        Vec<(PageId, PageDiff)>,
+        PriorNodes,
+    ),
 }
 
 struct StackItem {
@@ -130,6 +135,9 @@ pub struct PageWalker<H> {
     sibling_stack: Vec<(Node, usize)>,
     prev_node: Option<Node>, // the node at `self.position` which was replaced in a previous call
 
+    // the values of the nodes overwritten so far, if recorded.
+    prior_nodes: Option<PriorNodes>,
+
     _marker: std::marker::PhantomData<H>,
 }
 
@@ -157,10 +165,17 @@ impl<H: NodeHasher> PageWalker<H> {
             stack: Vec::new(),
             sibling_stack: Vec::new(),
             prev_node: None,
+            prior_nodes: None,
             _marker: std::marker::PhantomData,
         }
     }
 
+    /// Record the values of the nodes before they are overwritten, to be returned in the
+    /// [`Output`].
+    pub fn record_prior_nodes(&mut self) {
+        self.prior_nodes = Some(PriorNodes::default());
+    }
+
     /// Advance to a given trie position and replace the terminal node there with a trie
     /// based on the provided key-value pairs.
     ///
@@ -446,10 +461,15 @@ impl<H: NodeHasher> PageWalker<H> {
         if let Err(p) = self.compact_up(write_pass, None) {
             return Err((p, self));
         }
+        let prior_nodes = self.prior_nodes.unwrap_or_default();
         if self.parent_page.is_none() {
-            Ok(Output::Root(self.root, self.diffs))
+            Ok(Output::Root(self.root, self.diffs, prior_nodes))
         } else {
-            Ok(Output::ChildPageRoots(self.child_page_roots, self.diffs))
+            Ok(Output::ChildPageRoots(
+                self.child_page_roots,
+                self.diffs,
+                prior_nodes,
+            ))
         }
     }
 
@@ -610,6 +630,10 @@ impl<H: NodeHasher> PageWalker<H> {
         }
 
         let stack_top = self.stack.last_mut().unwrap();
+        if let Some(ref mut prior_nodes) = self.prior_nodes {
+            let prior = stack_top.page.node(write_pass.downgrade(), node_index);
+            prior_nodes.record(&stack_top.page_id, node_index, prior);
+        }
         stack_top
             .page
             .set_node(&self.page_pool, write_pass, node_index, node);
@@ -624,6 +648,10 @@ impl<H: NodeHasher> PageWalker<H> {
     ) {
         let node_index = self.position.sibling_index();
         let stack_top = self.stack.last_mut().unwrap();
+        if let Some(ref mut prior_nodes) = self.prior_nodes {
+            let prior = stack_top.page.node(write_pass.downgrade(), node_index);
+            prior_nodes.record(&stack_top.page_id, node_index, prior);
+        }
         stack_top
             .page
             .set_node(&self.page_pool, write_pass, node_index, node);
@@ -682,6 +710,13 @@ impl<H: NodeHasher> PageWalker<H> {
                 })
                 .unwrap();
 
+            if let Some(ref mut prior_nodes) = self.prior_nodes {
+                for index in [children.left(), children.right()] {
+                    let prior = page.node(write_pass.downgrade(), index);
+                    prior_nodes.record(&page_id, index, prior);
+                }
+            }
+
             let cleared = match leaf_data {
                 None => {
                     page.clear_leaf_data(&self.page_pool, write_pass, children);
@@ -930,7 +965,7 @@ mod tests {
             .unwrap();
 
         match walker.conclude(&mut write_pass) {
-            Ok(Output::Root(new_root, diffs)) => {
+            Ok(Output::Root(new_root, diffs, _)) => {
                 assert_eq!(
                     new_root,
                     nomt_core::update::build_trie::<Blake3Hasher>(
@@ -947,7 +982,7 @@ mod tests {
                 assert_eq!(diffs.len(), 1);
                 assert_eq!(&diffs[0].0, &ROOT_PAGE_ID);
             }
-            Ok(Output::ChildPageRoots(_, _)) | Err(_) => unreachable!(),
+            Ok(Output::ChildPageRoots(..)) | Err(_) => unreachable!(),
         }
     }
 
@@ -1001,8 +1036,8 @@ mod tests {
             .unwrap();
 
         match walker.conclude(&mut write_pass) {
-            Ok(Output::Root(_, _)) | Err(_) => unreachable!(),
-            Ok(Output::ChildPageRoots(page_roots, diffs)) => {
+            Ok(Output::Root(..)) | Err(_) => unreachable!(),
+            Ok(Output::ChildPageRoots(page_roots, diffs, _)) => {
                 assert_eq!(page_roots.len(), 2);
                 assert_eq!(diffs.len(), 2);
                 let left_page_id = ROOT_PAGE_ID
@@ -1079,7 +1114,7 @@ mod tests {
                 .unwrap();
 
             match walker.conclude(&mut write_pass) {
-                Ok(Output::Root(new_root, _)) => new_root,
+                Ok(Output::Root(new_root, ..)) => new_root,
                 _ => unreachable!(),
             }
         };
@@ -1234,7 +1269,7 @@ mod tests {
             )
             .unwrap();
 
-        let Ok(Output::Root(_, _)) = walker.conclude(&mut write_pass) else {
+        let Ok(Output::Root(..)) = walker.conclude(&mut write_pass) else {
             panic!()
         };
 
@@ -15,7 +15,7 @@ use crossbeam::channel::{Receiver, Select, Sender, TryRecvError};
 use nomt_core::{
     page_id::{PageId, ROOT_PAGE_ID},
     proof::PathProofTerminal,
-    trie::{KeyPath, Node, NodeHasher, ValueHash},
+    trie::{KeyPath, LeafData, Node, NodeHasher, ValueHash},
 };
 
 use std::{
@@ -25,7 +25,8 @@ use std::{
 
 use super::{
     page_walker::{NeedsPage, Output, PageSource, PageWalker},
-    KeyReadWrite, RootPagePending, UpdateCommand, UpdateShared, WarmUpCommand, WorkerOutput,
+    KeyReadWrite, RootPagePending, UpdateCommand, UpdateShared, WarmUpCommand, WitnessedBatch,
+    WorkerOutput,
 };
 
 use crate::{
@@ -47,6 +48,7 @@ pub(super) struct UpdateParams {
     pub command: UpdateCommand,
 }
 
+#[derive(Clone)]
 pub(super) struct WarmUpParams {
     pub page_cache: PageCache,
     pub store: Store,
@@ -88,7 +90,7 @@ pub(super) fn run_update<H: NodeHasher>(params: UpdateParams, output_tx: Sender<
         root,
         page_cache.clone(),
         store.page_loader(),
-        command.shared.witness,
+        command.shared.record_siblings(),
     );
 
     let output = match update::<H>(root, page_cache, page_pool, seeker, command, warm_ups) {
@@ -185,12 +187,23 @@ fn update<H: NodeHasher>(
     command: UpdateCommand,
     warm_ups: Arc<HashMap<KeyPath, Seek>>,
 ) -> anyhow::Result<WorkerOutput> {
-    let UpdateCommand { shared, write_pass } = command;
+    let UpdateCommand {
+        shared,
+        write_pass,
+        witness_tx,
+    } = command;
     let write_pass = write_pass.into_inner();
 
-    let mut output = WorkerOutput::new(shared.witness);
+    let mut output = WorkerOutput::default();
 
-    let updater = RangeUpdater::<H>::new(root, shared.clone(), write_pass, &page_cache, &page_pool);
+    let updater = RangeUpdater::<H>::new(
+        root,
+        shared.clone(),
+        write_pass,
+        witness_tx,
+        &page_cache,
+        &page_pool,
+    );
 
     // one lucky thread gets the master write pass.
     let mut write_pass = match updater.update(&mut seeker, &mut output, warm_ups)? {
@@ -205,6 +218,9 @@ fn update<H: NodeHasher>(
         page_pool.clone(),
         None,
     );
+    if shared.record_prior_nodes {
+        root_page_updater.record_prior_nodes();
+    }
 
     for (trie_pos, pending_op) in pending_ops {
         match pending_op {
@@ -244,12 +260,13 @@ fn update<H: NodeHasher>(
     // PANIC: output is always root when no parent page is specified.
     loop {
         let page = match root_page_updater.conclude(&mut write_pass) {
-            Ok(Output::Root(new_root, diffs)) => {
+            Ok(Output::Root(new_root, diffs, prior_nodes)) => {
                 output.page_diffs.extend(diffs);
+                output.prior_nodes.extend(prior_nodes);
                 output.root = Some(new_root);
                 break;
             }
-            Ok(Output::ChildPageRoots(_, _)) => unreachable!(),
+            Ok(Output::ChildPageRoots(..)) => unreachable!(),
             Err((NeedsPage(page), page_walker)) => {
                 root_page_updater = page_walker;
                 page
@@ -287,6 +304,8 @@ fn drive_page_fetch(
 // anything that touches the root page is deferred via `shared.pending`.
 struct RangeUpdater<H> {
     shared: Arc<UpdateShared>,
+    // witnessed paths are sent here as soon as they're done. dropped along with the updater.
+    witness_tx: Option<Sender<WitnessedBatch>>,
     write_pass: WritePass<ShardIndex>,
     region: PageRegion,
     page_walker: PageWalker<H>,
@@ -300,6 +319,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
         root: Node,
         shared: Arc<UpdateShared>,
         write_pass: WritePass<ShardIndex>,
+        witness_tx: Option<Sender<WitnessedBatch>>,
         page_cache: &PageCache,
         page_pool: &PagePool,
     ) -> Self {
@@ -325,16 +345,18 @@ impl<H: NodeHasher> RangeUpdater<H> {
             ShardIndex::Shard(i) => PageSource::PageCacheShard(page_cache.get_shard(*i)),
         };
 
+        let mut page_walker =
+            PageWalker::<H>::new(root, page_source, page_pool.clone(), Some(ROOT_PAGE_ID));
+        if shared.record_prior_nodes {
+            page_walker.record_prior_nodes();
+        }
+
         RangeUpdater {
             shared,
+            witness_tx,
             write_pass,
             region,
-            page_walker: PageWalker::<H>::new(
-                root,
-                page_source,
-                page_pool.clone(),
-                Some(ROOT_PAGE_ID),
-            ),
+            page_walker,
             range_start,
             range_end,
             saved_advance: None,
@@ -346,7 +368,6 @@ impl<H: NodeHasher> RangeUpdater<H> {
     fn handle_completion(
         &mut self,
         seeker: &mut Seeker,
-        output: &mut WorkerOutput,
         start_index: usize,
         seek_result: Seek,
     ) -> usize {
@@ -397,7 +418,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
                 seek_result.terminal.clone(),
             );
 
-            if let Some(ref mut witnessed_paths) = output.witnessed_paths {
+            if self.shared.record_siblings() {
                 let path = WitnessedPath {
                     inner: PathProof {
                         // if the terminal lands in the non-exclusive area, then the path to it is
@@ -410,7 +431,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
                     },
                     path: seek_result.position,
                 };
-                witnessed_paths.push((path, seek_result.terminal, batch_size));
+                self.witness_path(start_index, batch_size, path, seek_result.terminal);
             }
 
             return next_index;
@@ -424,7 +445,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
         } else {
             None
         };
-        self.attempt_advance(seeker, output, seek_result, ops, batch_size);
+        self.attempt_advance(seeker, seek_result, ops, start_index, batch_size);
 
         next_index
     }
@@ -434,9 +455,9 @@ impl<H: NodeHasher> RangeUpdater<H> {
     fn attempt_advance(
         &mut self,
         seeker: &mut Seeker,
-        output: &mut WorkerOutput,
         seek_result: Seek,
         ops: Option<Vec<(KeyPath, Option<ValueHash>)>>,
+        start_index: usize,
         batch_size: usize,
     ) {
         let res = match ops {
@@ -458,12 +479,13 @@ impl<H: NodeHasher> RangeUpdater<H> {
             self.saved_advance = Some(SavedAdvance {
                 seek_result,
                 ops,
+                start_index,
                 batch_size,
             });
             return;
         }
 
-        if let Some(ref mut witnessed_paths) = output.witnessed_paths {
+        if self.shared.record_siblings() {
             let siblings = {
                 // nodes may have been altered prior to seeking - the page walker tracks which ones.
                 let mut siblings = seek_result.siblings;
@@ -483,18 +505,41 @@ impl<H: NodeHasher> RangeUpdater<H> {
                 },
                 path: seek_result.position,
             };
-            witnessed_paths.push((path, seek_result.terminal, batch_size));
+            self.witness_path(start_index, batch_size, path, seek_result.terminal);
+        }
+    }
+
+    // pass a witnessed path to the hook, if any, and send it along with the range of operations
+    // it covers, if the witness is collected.
+    fn witness_path(
+        &self,
+        start_index: usize,
+        batch_size: usize,
+        path: WitnessedPath,
+        terminal: Option<LeafData>,
+    ) {
+        if let Some(ref hook) = self.shared.witness_hook {
+            hook.on_path(&path);
+        }
+        if let Some(ref witness_tx) = self.witness_tx {
+            let _ = witness_tx.send(WitnessedBatch {
+                start: start_index,
+                size: batch_size,
+                path,
+                terminal,
+            });
         }
     }
 
-    fn reattempt_advance(&mut self, seeker: &mut Seeker, output: &mut WorkerOutput) {
+    fn reattempt_advance(&mut self, seeker: &mut Seeker) {
         // UNWRAP: guaranteed by behavior of seeker / update / advance.
         let SavedAdvance {
             ops,
             seek_result,
+            start_index,
             batch_size,
         } = self.saved_advance.take().unwrap();
-        self.attempt_advance(seeker, output, seek_result, ops, batch_size);
+        self.attempt_advance(seeker, seek_result, ops, start_index, batch_size);
     }
 
     fn update(
@@ -533,8 +578,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
                     if skips > 0 {
                         skips -= 1;
                     } else {
-                        let end_index =
-                            self.handle_completion(seeker, output, start_index, seek_result);
+                        let end_index = self.handle_completion(seeker, start_index, seek_result);
 
                         // account for stuff we pushed that was already covered by the terminal
                         // we just popped off.
@@ -547,7 +591,7 @@ impl<H: NodeHasher> RangeUpdater<H> {
                     }
                 }
                 Some(Completion::SinglePage) => {
-                    self.reattempt_advance(seeker, output);
+                    self.reattempt_advance(seeker);
                 }
             }
 
@@ -587,18 +631,22 @@ impl<H: NodeHasher> RangeUpdater<H> {
         // 2. conclude, driving additional page fetches as necessary.
         loop {
             // PANIC: walker was configured with a parent page.
-            let (new_nodes, diffs) = match self.page_walker.conclude(&mut self.write_pass) {
-                Ok(Output::Root(_, _)) => unreachable!(),
-                Ok(Output::ChildPageRoots(new_nodes, diffs)) => (new_nodes, diffs),
-                Err((NeedsPage(page), page_walker)) => {
-                    self.page_walker = page_walker;
-                    drive_page_fetch(seeker, self.write_pass.downgrade(), page)?;
-                    continue;
-                }
-            };
+            let (new_nodes, diffs, prior_nodes) =
+                match self.page_walker.conclude(&mut self.write_pass) {
+                    Ok(Output::Root(..)) => unreachable!(),
+                    Ok(Output::ChildPageRoots(new_nodes, diffs, prior_nodes)) => {
+                        (new_nodes, diffs, prior_nodes)
+                    }
+                    Err((NeedsPage(page), page_walker)) => {
+                        self.page_walker = page_walker;
+                        drive_page_fetch(seeker, self.write_pass.downgrade(), page)?;
+                        continue;
+                    }
+                };
 
             assert!(!diffs.iter().any(|item| item.0 == ROOT_PAGE_ID));
             output.page_diffs = diffs;
+            output.prior_nodes = prior_nodes;
 
             self.shared.push_pending_root_nodes(new_nodes);
 
@@ -623,5 +671,6 @@ struct SavedAdvance {
     // none: no writes
     ops: Option<Vec<(KeyPath, Option<ValueHash>)>>,
     seek_result: Seek,
+    start_index: usize,
     batch_size: usize,
 }
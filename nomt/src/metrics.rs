@@ -20,6 +20,10 @@ pub enum Metric {
     PageFetchTime,
     /// Timer used to record average value fetch time during reads
     ValueFetchTime,
+    /// Counter of path proofs served from the proof cache
+    ProofCacheHits,
+    /// Counter of path proofs which had to be generated
+    ProofCacheMisses,
 }
 
 struct ActiveMetrics {
@@ -27,6 +31,8 @@ struct ActiveMetrics {
     page_cache_misses: AtomicU64,
     page_fetch_time: Timer,
     value_fetch_time: Timer,
+    proof_cache_hits: AtomicU64,
+    proof_cache_misses: AtomicU64,
 }
 
 impl Metrics {
@@ -39,6 +45,8 @@ impl Metrics {
                     page_cache_misses: AtomicU64::new(0),
                     page_fetch_time: Timer::new(),
                     value_fetch_time: Timer::new(),
+                    proof_cache_hits: AtomicU64::new(0),
+                    proof_cache_misses: AtomicU64::new(0),
                 }))
             } else {
                 None
@@ -54,6 +62,8 @@ impl Metrics {
             let counter = match metric {
                 Metric::PageRequests => &metrics.page_requests,
                 Metric::PageCacheMisses => &metrics.page_cache_misses,
+                Metric::ProofCacheHits => &metrics.proof_cache_hits,
+                Metric::ProofCacheMisses => &metrics.proof_cache_misses,
                 _ => panic!("Specified metric is not a Counter"),
             };
 
@@ -76,6 +86,16 @@ impl Metrics {
         })
     }
 
+    /// Returns the fraction of path proofs served from the proof cache, if metrics are active and
+    /// any proofs were requested
+    pub fn proof_cache_hit_rate(&self) -> Option<f64> {
+        let metrics = self.metrics.as_ref()?;
+        let hits = metrics.proof_cache_hits.load(Ordering::Relaxed);
+        let misses = metrics.proof_cache_misses.load(Ordering::Relaxed);
+        let requests = hits + misses;
+        (requests != 0).then(|| hits as f64 / requests as f64)
+    }
+
     /// Print collected metrics to stdout
     pub fn print(&self) {
         if let Some(ref metrics) = self.metrics {
@@ -102,6 +122,15 @@ impl Metrics {
             if let Some(mean) = metrics.value_fetch_time.mean() {
                 println!("  value fetch mean      {}", pretty_display_ns(mean));
             }
+
+            if let Some(hit_rate) = self.proof_cache_hit_rate() {
+                let hits = metrics.proof_cache_hits.load(Ordering::Relaxed);
+                println!(
+                    "  proof cache hits      {} - {:.2}% of proof requests",
+                    hits,
+                    hit_rate * 100.0
+                );
+            }
         } else {
             println!("Metrics collection was not activated")
         }
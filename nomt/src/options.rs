@@ -1,6 +1,82 @@
-use std::path::PathBuf;
+use crate::{
+    beatree::Compression,
+    bitbox::{BucketMappingStrategy, RecoveryProgressCallback},
+    io::page_pool::ExhaustedCallback,
+    KeyBinning,
+};
+use std::{path::PathBuf, time::Duration};
+
+#[cfg(feature = "unstable")]
+use {
+    crate::{bitbox::RecoveryProgress, io::page_pool::PagePoolStats},
+    std::sync::Arc,
+};
+
+/// A point during a sync at which a crash can be simulated.
+///
+/// A sync writes the WAL and the b-tree pages, then the meta file, then the hash-table file, and
+/// finally truncates the WAL. Reopening after a crash before the meta file is written must land on
+/// the previous root, and after it on the new root.
+#[doc(hidden)]
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncCrashPoint {
+    /// After the WAL and the b-tree pages have been written, but before the meta file.
+    BeforeMeta,
+    /// After the meta file has been written, but before the hash-table file.
+    AfterMeta,
+    /// After the hash-table file has been written, but before the WAL is truncated.
+    AfterHt,
+}
+
+/// When commits are written to disk.
+///
+/// Writing a commit out waits for its writes to be flushed to disk with `fsync`, in an order which
+/// keeps the database recoverable at any point. This is the dominant cost of a commit on most
+/// disks. Other than with [`SyncMode::Always`], commits are applied in memory when they are made,
+/// so the root moves and the values are readable right away, and are written to disk later,
+/// several at a time. Commits not written yet are lost if the process crashes or the machine loses
+/// power, and the database is found at the root of the last commit written to disk, with all of
+/// the commits before it.
+///
+/// The commits are written out all together with [`crate::Nomt::flush`] and when the database is
+/// closed or dropped. Committing through a [`crate::CommitGroup`] writes them out first. Commit
+/// feeds and watchers are notified of commits when they are written to disk.
+///
+/// Modes other than [`SyncMode::Always`] are not supported with rollback.
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Every commit is written to disk before it returns.
+    #[default]
+    Always,
+    /// A commit writes itself and the commits not written yet to disk if the given interval
+    /// elapsed since commits were last written. Otherwise it returns once applied in memory.
+    ///
+    /// The interval is only checked when committing: commits made before a pause are written out
+    /// by the next commit, or by flushing the database.
+    Periodic(Duration),
+    /// Commits are only written to disk by [`crate::Nomt::flush`], or when the database is closed
+    /// or dropped, leaving it up to the application to bound how many commits may be lost.
+    OsDefault,
+}
+
+/// The outcome of a commit found in doubt when opening the database, see
+/// [`Options::in_doubt_commit`].
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InDoubtCommit {
+    /// Opening the database fails with [`crate::Error::InDoubtCommit`].
+    #[default]
+    Fail,
+    /// The commit takes effect.
+    Commit,
+    /// The commit is discarded.
+    Abort,
+}
 
 /// Options when opening a [`crate::Nomt`] instance.
+#[derive(Clone)]
 pub struct Options {
     /// The path to the directory where the trie is stored.
     pub(crate) path: PathBuf,
@@ -12,15 +88,67 @@ pub struct Options {
     pub(crate) metrics: bool,
     pub(crate) bitbox_num_pages: u32,
     pub(crate) bitbox_seed: [u8; 16],
-    pub(crate) panic_on_sync: bool,
+    pub(crate) bitbox_mapping: BucketMappingStrategy,
+    pub(crate) key_binning: KeyBinning,
+    pub(crate) compression: Compression,
+    pub(crate) compression_dictionary: Option<Vec<u8>>,
+    /// When commits are written to disk.
+    pub(crate) sync_mode: SyncMode,
+    /// The point during a sync at which to simulate a crash, if any.
+    pub(crate) crash_point: Option<SyncCrashPoint>,
     pub(crate) rollback: bool,
     /// The maximum number of commits that can be rolled back.
     pub(crate) max_rollback_log_len: u32,
     pub(crate) warm_up: bool,
+    /// Whether the warm-ups are split between the shards of the page cache.
+    pub(crate) thread_per_core: bool,
     /// The number of threads to use for fetching prior values.
     pub(crate) rollback_tp_size: usize,
     /// Whether to preallocate the hashtable file.
     pub(crate) preallocate_ht: bool,
+    /// The amount of time opening the database is expected to take at most.
+    pub(crate) max_recovery_time_hint: Option<Duration>,
+    /// The number of commit manifests to retain for catching up read replicas.
+    pub(crate) manifest_retention: usize,
+    /// The maximum number of file descriptors NOMT may hold open.
+    pub(crate) max_open_files: Option<usize>,
+    /// The maximum size of the page pool in bytes.
+    pub(crate) page_pool_capacity: Option<usize>,
+    /// Invoked whenever an allocation waits because the page pool is exhausted.
+    pub(crate) on_page_pool_exhausted: Option<ExhaustedCallback>,
+    /// The number of recent commit roots to keep in the meta file.
+    pub(crate) root_history_len: usize,
+    /// The directory sessions are recorded to, if any.
+    pub(crate) record_sessions: Option<PathBuf>,
+    /// The directory incremental backups are written to, if any.
+    pub(crate) incremental_backup: Option<PathBuf>,
+    /// The maximum number of path proofs kept by the proof cache.
+    pub(crate) proof_cache_capacity: usize,
+    pub(crate) adaptive_commit_concurrency: bool,
+    /// Whether the checksums of the pages are verified when they are read.
+    pub(crate) verify_checksums: bool,
+    /// The number of syncs after which leaf pages retained for snapshots are reclaimed.
+    pub(crate) snapshot_horizon: Option<u32>,
+    /// The number of recent commits to keep a snapshot of.
+    pub(crate) snapshot_retention: usize,
+    /// What to do with a commit found in doubt on open.
+    pub(crate) in_doubt_commit: InDoubtCommit,
+    /// Whether the database is opened for reading only.
+    pub(crate) read_only: bool,
+    /// The directory the hash-table file is created in, if not the database directory.
+    pub(crate) ht_dir: Option<PathBuf>,
+    /// The directory the WAL file is created in, if not the database directory.
+    pub(crate) wal_dir: Option<PathBuf>,
+    /// The directory the b-tree files are created in, if not the database directory.
+    pub(crate) beatree_dir: Option<PathBuf>,
+    /// The minimum length of the runs of freed b-tree pages deallocated from the files.
+    pub(crate) hole_punch_run: Option<u32>,
+    /// Whether to compress the WAL of the hash-table.
+    pub(crate) compress_wal: bool,
+    /// Invoked with the progress of replaying the WAL on open.
+    pub(crate) on_recovery_progress: Option<RecoveryProgressCallback>,
+    /// Whether to keep a filter over the pages stored in the hash-table.
+    pub(crate) presence_filter: bool,
 }
 
 impl Options {
@@ -37,12 +165,40 @@ impl Options {
             metrics: false,
             bitbox_num_pages: 64_000,
             bitbox_seed,
-            panic_on_sync: false,
+            bitbox_mapping: BucketMappingStrategy::Hashed,
+            key_binning: KeyBinning::DISABLED,
+            compression: Compression::None,
+            compression_dictionary: None,
+            sync_mode: SyncMode::Always,
+            crash_point: None,
             rollback: false,
             max_rollback_log_len: 100,
             warm_up: false,
+            thread_per_core: false,
             rollback_tp_size: 4,
             preallocate_ht: true,
+            max_recovery_time_hint: None,
+            manifest_retention: 0,
+            max_open_files: None,
+            page_pool_capacity: None,
+            on_page_pool_exhausted: None,
+            root_history_len: 0,
+            record_sessions: None,
+            incremental_backup: None,
+            proof_cache_capacity: 0,
+            adaptive_commit_concurrency: true,
+            verify_checksums: false,
+            snapshot_horizon: None,
+            snapshot_retention: 0,
+            in_doubt_commit: InDoubtCommit::Fail,
+            read_only: false,
+            ht_dir: None,
+            wal_dir: None,
+            beatree_dir: None,
+            hole_punch_run: None,
+            compress_wal: false,
+            on_recovery_progress: None,
+            presence_filter: false,
         }
     }
 
@@ -60,6 +216,19 @@ impl Options {
         self.commit_concurrency = commit_concurrency;
     }
 
+    /// Set whether the number of workers used for updating the b-tree is chosen for every commit
+    /// from the size of the changeset and the number of leaves it touches, up to
+    /// [`Options::commit_concurrency`].
+    ///
+    /// Small commits are dominated by the coordination between workers and are handled faster
+    /// by fewer workers. If disabled, all commits use `commit_concurrency` workers. The workers
+    /// updating the merkle trie are not affected, as the page cache is partitioned between them.
+    ///
+    /// Default: enabled.
+    pub fn adaptive_commit_concurrency(&mut self, adaptive: bool) {
+        self.adaptive_commit_concurrency = adaptive;
+    }
+
     /// Set metrics collection on or off.
     ///
     /// Default: off.
@@ -82,17 +251,160 @@ impl Options {
 
     /// Set the seed for the hash function used by the bitbox store.
     ///
-    /// Useful for reproducibility.
+    /// Only relevant when creating the database. The seed is recorded in the database, and an
+    /// existing database keeps the seed it was created with. By default, a random seed is chosen,
+    /// so that the buckets of pages can't be predicted to make them collide, see
+    /// [`crate::HashTableStats::probe_anomaly`]. Setting a fixed seed is useful for
+    /// reproducibility.
     pub fn bitbox_seed(&mut self, bitbox_seed: [u8; 16]) {
         self.bitbox_seed = bitbox_seed;
     }
 
+    /// Set the strategy for mapping pages to the buckets of the hash-table.
+    ///
+    /// Only relevant when creating the database. An existing database keeps the strategy it was
+    /// created with.
+    ///
+    /// Default: [`BucketMappingStrategy::Hashed`].
+    #[cfg(feature = "unstable")]
+    pub fn bucket_mapping(&mut self, bucket_mapping: BucketMappingStrategy) {
+        self.bitbox_mapping = bucket_mapping;
+    }
+
+    /// Set the binning the application uses to derive key paths, see [`KeyBinning`].
+    ///
+    /// The binning is recorded in the database and attached to witnesses. Key paths are derived
+    /// with [`crate::Nomt::key_path`]. Only relevant when creating the database. An existing
+    /// database keeps the binning it was created with.
+    ///
+    /// Default: [`KeyBinning::DISABLED`].
+    pub fn key_binning(&mut self, key_binning: KeyBinning) {
+        self.key_binning = key_binning;
+    }
+
+    /// Set the compression of the values stored in the leaf node file, see [`Compression`].
+    ///
+    /// Values are compressed when they are written out and decompressed when they are read, which
+    /// cuts the size of the file for values which compress well at the cost of CPU time on both
+    /// paths. Values which don't get smaller are stored as is, with one byte of overhead. Only
+    /// relevant when creating the database. An existing database keeps the compression it was
+    /// created with.
+    ///
+    /// Default: [`Compression::None`].
+    #[cfg(feature = "unstable")]
+    pub fn compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Set the dictionary values are compressed with, see [`crate::train_dictionary`].
+    ///
+    /// A dictionary trained on values similar to the ones stored makes small values, which don't
+    /// compress well on their own, compress much better. It is kept in the meta file and loaded
+    /// when the database is opened. Requires [`Compression::Zstd`], opening the database fails
+    /// with [`crate::Error::InvalidOperation`] otherwise. Only relevant when creating the
+    /// database. An existing database keeps the dictionary it was created with, if any.
+    ///
+    /// Default: no dictionary.
+    #[cfg(feature = "unstable")]
+    pub fn compression_dictionary(&mut self, dictionary: Vec<u8>) {
+        self.compression_dictionary = Some(dictionary);
+    }
+
+    /// Set when commits are written to disk, see [`SyncMode`].
+    ///
+    /// Writing out several commits at a time trades the durability of the most recent commits for
+    /// the latency of commits. Modes other than [`SyncMode::Always`] can't be combined with
+    /// [`Options::rollback`], opening the database fails with
+    /// [`crate::Error::InvalidOperation`] otherwise.
+    ///
+    /// Default: [`SyncMode::Always`].
+    #[cfg(feature = "unstable")]
+    pub fn sync_mode(&mut self, sync_mode: SyncMode) {
+        self.sync_mode = sync_mode;
+    }
+
+    /// Set what to do with a commit prepared by [`crate::Nomt::prepare`] which was neither
+    /// committed nor aborted before the process stopped.
+    ///
+    /// Such a commit is written out but for the meta file, so whether it takes effect is up to the
+    /// next open. By default, opening fails with [`crate::Error::InDoubtCommit`], reporting the root
+    /// and the tag of the commit, so that the outcome can be decided by the external database it
+    /// was coordinated with. Nothing is done if no commit is in doubt.
+    ///
+    /// Default: [`InDoubtCommit::Fail`].
+    #[cfg(feature = "unstable")]
+    pub fn in_doubt_commit(&mut self, outcome: InDoubtCommit) {
+        self.in_doubt_commit = outcome;
+    }
+
+    /// Set whether to open the database for reading only.
+    ///
+    /// Any number of read-only instances can have the database open at the same time, in this
+    /// process or others, but not along with an instance which writes to it: opening fails with
+    /// [`crate::Error::Busy`] while the other kind of instance has it open. The files are opened
+    /// without write access, and committing, rolling back and the other operations which write
+    /// fail with [`crate::Error::InvalidOperation`].
+    ///
+    /// Opening read-only also fails with [`crate::Error::InvalidOperation`] if the database
+    /// doesn't exist or its WAL has to be replayed, and with [`crate::Error::InDoubtCommit`] if a
+    /// commit is in doubt, whatever [`Options::in_doubt_commit`] is set to. It can't be combined
+    /// with [`Options::rollback`] or [`Options::incremental_backup`].
+    ///
+    /// Default: false.
+    #[cfg(feature = "unstable")]
+    pub fn read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Create the hash-table file, which takes the random writes of the trie pages, in the given
+    /// directory rather than the database directory, e.g. to put it on a device of its own.
+    ///
+    /// The file is linked from the database directory, so this only matters when the database is
+    /// created: it is found through the link whatever this is set to when the database is opened
+    /// later. The directory is created if it doesn't exist. Its file has a fixed name, so it can't
+    /// hold the files of another database. Resetting or destroying the database removes the file,
+    /// and resetting creates it anew as set by the options the database was opened with.
+    ///
+    /// Default: the database directory.
+    #[cfg(feature = "unstable")]
+    pub fn ht_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.ht_dir = Some(dir.into());
+    }
+
+    /// Create the WAL file in the given directory rather than the database directory, as
+    /// [`Options::ht_dir`] does for the hash-table file.
+    ///
+    /// Default: the database directory.
+    #[cfg(feature = "unstable")]
+    pub fn wal_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.wal_dir = Some(dir.into());
+    }
+
+    /// Create the files of the b-tree, which stores the values, in the given directory rather
+    /// than the database directory, as [`Options::ht_dir`] does for the hash-table file.
+    ///
+    /// Default: the database directory.
+    #[cfg(feature = "unstable")]
+    pub fn beatree_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.beatree_dir = Some(dir.into());
+    }
+
     /// Set to `true` to panic on sync after writing the WAL file and updating the manifest, but
     /// before the data has been written to the HT file.
     ///
     /// Useful to test WAL recovery.
+    #[cfg(feature = "unstable")]
     pub fn panic_on_sync(&mut self, panic_on_sync: bool) {
-        self.panic_on_sync = panic_on_sync;
+        self.crash_point = panic_on_sync.then_some(SyncCrashPoint::AfterMeta);
+    }
+
+    /// Panic at the given point during every sync, simulating a crash.
+    ///
+    /// Useful to test recovery. A generalization of [`Self::panic_on_sync`].
+    #[doc(hidden)]
+    #[cfg(feature = "unstable")]
+    pub fn crash_on_sync(&mut self, crash_point: SyncCrashPoint) {
+        self.crash_point = Some(crash_point);
     }
 
     /// Set to `true` to enable rolling back committed sessions.
@@ -116,6 +428,25 @@ impl Options {
         self.warm_up = warm_up;
     }
 
+    /// Set whether to shard the warm-ups along with the page cache, in an experimental
+    /// thread-per-core arrangement.
+    ///
+    /// The page cache is partitioned between the commit workers, one shard per worker, see
+    /// [`Options::commit_concurrency`]. In this mode, the warm-ups are also handled by one worker
+    /// per shard, each with its own I/O handle, instead of a single worker. Every key warmed up is
+    /// sent to the worker owning the pages along its path, and each commit worker receives the
+    /// warm-ups of its own shard, instead of all of them sharing a single map. The root is the
+    /// same as without sharding. Only relevant if [`Options::warm_up`] is enabled.
+    ///
+    /// This is meant to be compared against the shared design with the `commit_scaling`
+    /// benchmark, and may be changed or removed.
+    ///
+    /// Default: false.
+    #[cfg(feature = "unstable")]
+    pub fn thread_per_core(&mut self, thread_per_core: bool) {
+        self.thread_per_core = thread_per_core;
+    }
+
     /// Set the number of threads to use for fetching prior values.
     ///
     /// Only relevant if rollback is enabled.
@@ -137,4 +468,229 @@ impl Options {
     pub fn preallocate_ht(&mut self, preallocate_ht: bool) {
         self.preallocate_ht = preallocate_ht;
     }
+
+    /// Set a hint for how long opening the database should take at most.
+    ///
+    /// Opening the database requires replaying the WAL in case of a dirty shutdown and
+    /// reconstructing the in-memory index of the b-tree. With this hint set, NOMT estimates the
+    /// amount of work to be done from the sizes of the files and spreads it across as many threads
+    /// as needed to fit within the hint, bounded by the available parallelism.
+    ///
+    /// This is a best-effort hint and not a hard deadline.
+    ///
+    /// Default: `None`, recovery is performed on a single thread.
+    pub fn max_recovery_time_hint(&mut self, max_recovery_time_hint: Duration) {
+        self.max_recovery_time_hint = Some(max_recovery_time_hint);
+    }
+
+    /// Set the number of commits for which to retain the manifest of changed pages.
+    ///
+    /// Manifests allow a lagging read replica to fetch only the pages changed since its root
+    /// instead of a full snapshot, see [`crate::Nomt::changed_pages_since`]. Manifests are kept in
+    /// memory and are not persisted across restarts.
+    ///
+    /// Default: 0, no manifests are retained.
+    pub fn manifest_retention(&mut self, manifest_retention: usize) {
+        self.manifest_retention = manifest_retention;
+    }
+
+    /// Set the maximum number of file descriptors NOMT may hold open.
+    ///
+    /// NOMT holds a fixed number of file descriptors, determined by the number of I/O workers and
+    /// whether rollback is enabled. Opening the database fails with
+    /// [`crate::Error::InvalidOperation`] if more than this are required, or if the process limit
+    /// (`ulimit -n`) doesn't leave enough room. The soft process limit is raised up to the hard
+    /// limit if needed.
+    ///
+    /// Default: `None`, only the process limit applies.
+    pub fn max_open_files(&mut self, max_open_files: usize) {
+        self.max_open_files = Some(max_open_files);
+    }
+
+    /// Set the maximum size of the page pool in bytes.
+    ///
+    /// All pages NOMT reads or writes, including the ones held by the page cache, are allocated
+    /// from the page pool. Once the pool is exhausted, allocations block until another page is
+    /// freed. Threads cache up to 2048 free pages each for fast reuse, so the capacity should
+    /// leave ample room above the working set. See `Nomt::page_pool_stats` for observing
+    /// the usage of the pool.
+    ///
+    /// Default: `None`, the pool grows without bound.
+    pub fn page_pool_capacity(&mut self, page_pool_capacity: usize) {
+        self.page_pool_capacity = Some(page_pool_capacity);
+    }
+
+    /// Set the number of recent commit roots to keep, see [`crate::Nomt::recent_roots`].
+    ///
+    /// The history is stored in the meta file. Values over [`crate::MAX_ROOT_HISTORY_LEN`] will be
+    /// rounded down to it.
+    ///
+    /// Default: 0, no history is kept.
+    pub fn root_history(&mut self, root_history_len: usize) {
+        self.root_history_len = root_history_len.min(crate::MAX_ROOT_HISTORY_LEN);
+    }
+
+    /// Record every session to a file in the given directory, see [`crate::Recording`].
+    ///
+    /// A recording holds the warm-ups and reads of the session along with the actuals it is
+    /// committed with, and can be re-executed with [`crate::Nomt::replay`]. Recordings are named
+    /// after the root the session began at, so each commit produces a new file. Recording adds a
+    /// file write to every commit and is meant for reproducing issues, not for regular operation.
+    ///
+    /// Default: disabled.
+    #[cfg(feature = "unstable")]
+    pub fn record_sessions(&mut self, dir: impl Into<PathBuf>) {
+        self.record_sessions = Some(dir.into());
+    }
+
+    /// Back the database up to the given directory, one increment per commit.
+    ///
+    /// The first time the database is opened with a directory, the files of the database are
+    /// copied to it as the base of the backup. Every commit then writes the pages it changed along
+    /// with the meta page to a new file in the directory before returning. The database as of the
+    /// last commit can be written to a new directory with [`crate::Nomt::materialize_backup`].
+    ///
+    /// The backup has to continue from the root the database is opened at, so opening fails if
+    /// the database was committed to without the backup, e.g. after [`crate::Nomt::reset`]; a new
+    /// directory must be used in that case. The rollback log and the checkpoints are not backed
+    /// up.
+    ///
+    /// Default: disabled.
+    pub fn incremental_backup(&mut self, dir: impl Into<PathBuf>) {
+        self.incremental_backup = Some(dir.into());
+    }
+
+    /// Set the maximum number of path proofs kept by the cache used by [`crate::Nomt::prove`].
+    ///
+    /// The cache is cleared on every commit, so this is only useful if the same keys are proven
+    /// repeatedly at the same root. The hit rate is reported by the metrics.
+    ///
+    /// Default: 0, the cache is disabled.
+    pub fn proof_cache_capacity(&mut self, proof_cache_capacity: usize) {
+        self.proof_cache_capacity = proof_cache_capacity;
+    }
+
+    /// Set whether the checksums of the pages of the hash-table file and of the leaf and branch
+    /// node files are verified when the pages are read.
+    ///
+    /// Checksums are always written. A page which doesn't match its checksum, e.g. because of bit
+    /// rot, is reported as [`crate::Error::ChecksumMismatch`] by the operation reading it, and by
+    /// [`crate::Nomt::open`] for the pages read on open. Verifying costs hashing every page read.
+    ///
+    /// Default: disabled.
+    pub fn verify_checksums(&mut self, verify_checksums: bool) {
+        self.verify_checksums = verify_checksums;
+    }
+
+    /// Set the number of commits for which the leaf pages freed while a [`crate::Snapshot`] is
+    /// alive are retained for it.
+    ///
+    /// Freed pages are kept from being reused for as long as a snapshot taken before they were
+    /// freed is alive. With a horizon, pages retained for that many commits are reclaimed
+    /// regardless, which bounds the growth of the database caused by long-lived snapshots. The snapshots which needed them expire: reading from them fails with
+    /// [`crate::Error::InvalidOperation`] and their iterators panic. The retained and reclaimed
+    /// pages are reported by [`crate::Nomt::stats`].
+    ///
+    /// Default: `None`, pages are retained until the snapshots are dropped.
+    #[cfg(feature = "unstable")]
+    pub fn snapshot_horizon(&mut self, commits: u32) {
+        self.snapshot_horizon = Some(commits);
+    }
+
+    /// Deallocate the runs of at least the given number of consecutive b-tree pages freed by a
+    /// commit from the files, returning the space to the filesystem.
+    ///
+    /// Freed pages are tracked by the free-lists of the files and reused by later commits, but the
+    /// files never shrink, so the space taken by state which was deleted stays allocated on disk.
+    /// With this set, the runs of pages freed together, as by deleting large amounts of state, are
+    /// punched out of the files once the commit is written to disk. The files keep their length,
+    /// and the space is allocated again when the pages are reused. See the `allocated` size of
+    /// the files reported by [`crate::Nomt::stats`].
+    ///
+    /// Only pages freed by the same commit are considered together. Holes are punched on Linux
+    /// only, and silently skipped by filesystems which don't support them.
+    ///
+    /// Default: `None`, freed pages stay allocated.
+    #[cfg(feature = "unstable")]
+    pub fn punch_holes(&mut self, min_run_pages: u32) {
+        self.hole_punch_run = Some(min_run_pages);
+    }
+
+    /// Compress the write-ahead log of the hash-table with lz4 before it is written out.
+    ///
+    /// Every sync writes and fsyncs the changed trie nodes to the WAL before updating the
+    /// hash-table in place. The nodes are hashes and don't compress much one by one, but the WAL
+    /// also repeats the page IDs and the structure of every update, so compressing it cuts the
+    /// volume written by every sync, at the cost of CPU time on the sync path. A WAL which doesn't
+    /// get smaller is written as is. A WAL to compress is built whole in memory, while otherwise
+    /// it is written out as it is built, so the memory taken by a sync grows with the size of its
+    /// WAL. A commit whose WAL doesn't fit in memory fails, and the database must be reopened.
+    ///
+    /// The WAL is read back only to recover from a sync interrupted after writing the meta, which
+    /// works either way. A compressed WAL can't be recovered by versions of NOMT which don't
+    /// support it, so a database shouldn't be opened with one of those after a crash.
+    ///
+    /// Default: `false`.
+    #[cfg(feature = "unstable")]
+    pub fn compress_wal(&mut self, compress: bool) {
+        self.compress_wal = compress;
+    }
+
+    /// Keep an in-memory filter over the pages stored in the hash-table, to skip looking up pages
+    /// which aren't stored.
+    ///
+    /// Commits look up the trie pages they create before creating them, and every lookup of a page
+    /// which isn't stored walks its probe sequence up to an empty bucket, reading the buckets
+    /// whose hint matches by chance. The filter rules out most of those lookups without probing.
+    /// It takes 2 bytes per bucket of the hash-table, and is built when the database is opened by
+    /// reading every occupied bucket, which makes opening a large database slower.
+    ///
+    /// Default: `false`.
+    #[cfg(feature = "unstable")]
+    pub fn presence_filter(&mut self, enabled: bool) {
+        self.presence_filter = enabled;
+    }
+
+    /// Set the number of recent commits to keep a [`crate::Snapshot`] of, see
+    /// [`crate::Nomt::snapshot_at`].
+    ///
+    /// Every retained snapshot keeps the leaf pages freed after it from being reused, so the
+    /// database holds up to this many versions of the values. The trie pages are updated in place,
+    /// but the nodes overwritten by the commits since the oldest snapshot are kept in memory, so
+    /// [`crate::Nomt::prove_at`] can prove against the roots of the snapshots. Neither is retained
+    /// across restarts. A [snapshot horizon](Self::snapshot_horizon) shorter than the retention
+    /// expires the older snapshots.
+    ///
+    /// Default: 0, no snapshots are retained.
+    #[cfg(feature = "unstable")]
+    pub fn snapshot_retention(&mut self, commits: usize) {
+        self.snapshot_retention = commits;
+    }
+
+    /// Set a callback invoked whenever an allocation has to wait because the page pool is
+    /// exhausted.
+    ///
+    /// This allows to tell buffer starvation apart from slow I/O. The callback is invoked on the
+    /// allocating thread right before it blocks, so it must be cheap and must not allocate pages
+    /// itself, e.g. by reading from the database.
+    ///
+    /// Default: `None`.
+    #[cfg(feature = "unstable")]
+    pub fn on_page_pool_exhausted(&mut self, f: impl Fn(PagePoolStats) + Send + Sync + 'static) {
+        self.on_page_pool_exhausted = Some(Arc::new(f));
+    }
+
+    /// Set a callback receiving the progress of replaying the write-ahead log when the database is
+    /// opened after a crash.
+    ///
+    /// Replaying the WAL of a large sync rewrites many hash-table pages and may take a while. The
+    /// callback is invoked once the WAL is read, periodically while its updates are written, and
+    /// once done. It may be invoked from the threads replaying the WAL, in parallel. The summary
+    /// of the replay is returned by [`crate::Nomt::recovery_report`] after the database is opened.
+    ///
+    /// Default: `None`.
+    #[cfg(feature = "unstable")]
+    pub fn on_recovery_progress(&mut self, f: impl Fn(RecoveryProgress) + Send + Sync + 'static) {
+        self.on_recovery_progress = Some(Arc::new(f));
+    }
 }
@@ -13,7 +13,7 @@ use lru::LruCache;
 use nomt_core::{
     page::DEPTH,
     page_id::{ChildPageIndex, PageId, NUM_CHILDREN, ROOT_PAGE_ID},
-    trie::{LeafData, Node},
+    trie::{KeyPath, LeafData, Node},
     trie_pos::{ChildNodeIndices, TriePosition},
 };
 use parking_lot::{Mutex, RwLock};
@@ -367,6 +367,14 @@ impl PageCache {
         }
     }
 
+    /// Get the index of the shard holding the pages below the root page along the path of the
+    /// given key.
+    pub fn shard_index_for_key(&self, key_path: &KeyPath) -> usize {
+        // The child of the root page is determined by the first 6 bits of the key path.
+        let first_ancestor = (key_path[0] >> 2) as usize;
+        shard_index_for(self.shared.shards.len(), first_ancestor)
+    }
+
     /// Query the cache for the page data at the given [`PageId`].
     ///
     /// Returns `None` if not in the cache.
@@ -633,3 +641,29 @@ unsafe impl RegionContains<ShardIndex> for ShardIndex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PageCache;
+    use crate::Options;
+    use nomt_core::page_id::PageIdsIterator;
+
+    #[test]
+    fn shard_index_for_key_matches_page_ids() {
+        for num_shards in 1..=64 {
+            let mut o = Options::new();
+            o.commit_concurrency(num_shards);
+            let page_cache = PageCache::new(None, &o, None);
+            for first_byte in 0..=255u8 {
+                let mut key_path = [0x55; 32];
+                key_path[0] = first_byte;
+                // UNWRAP: every key path has pages below the root page.
+                let page_id = PageIdsIterator::new(key_path).nth(1).unwrap();
+                assert_eq!(
+                    Some(page_cache.shard_index_for_key(&key_path)),
+                    page_cache.shard_index_for(&page_id),
+                );
+            }
+        }
+    }
+}
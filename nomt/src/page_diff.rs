@@ -53,6 +53,22 @@ impl PageDiff {
         }
     }
 
+    /// Fold a later diff of the same page into this one.
+    ///
+    /// A page cleared and then filled again is treated as changed in its entirety, since none of
+    /// its nodes before the clear are kept.
+    pub fn join(&mut self, later: &PageDiff) {
+        let refilled = self.cleared() && !later.cleared();
+        self.changed_nodes[0] |= later.changed_nodes[0];
+        self.changed_nodes[1] |= later.changed_nodes[1];
+        self.set_cleared(later.cleared());
+        if refilled {
+            for slot_index in 0..NODES_PER_PAGE {
+                self.set_changed(slot_index);
+            }
+        }
+    }
+
     /// Whether the page was completely cleared.
     pub fn cleared(&self) -> bool {
         self.changed_nodes[1] >> 63 == 1
@@ -154,4 +170,24 @@ mod tests {
 
         assert_eq!(iterated_set_bits, set_bits);
     }
+
+    #[test]
+    fn join() {
+        let mut diff = PageDiff::default();
+        diff.set_changed(1);
+        let mut later = PageDiff::default();
+        later.set_changed(70);
+        diff.join(&later);
+        assert_eq!(diff.iter_ones().collect::<Vec<_>>(), vec![1, 70]);
+
+        let mut cleared = PageDiff::default();
+        cleared.set_cleared(true);
+        diff.join(&cleared);
+        assert!(diff.cleared());
+
+        // Refilling a cleared page rewrites all of it.
+        diff.join(&later);
+        assert!(!diff.cleared());
+        assert_eq!(diff.count(), NODES_PER_PAGE);
+    }
 }
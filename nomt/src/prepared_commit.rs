@@ -0,0 +1,71 @@
+//! Committing a session as one participant of a two-phase commit.
+//!
+//! [`Nomt::prepare`] writes out everything but the meta of the store, which is the last write of a
+//! sync and the one which makes the commit take effect, and records the meta in a file of its own.
+//! The commit then takes effect with [`PreparedCommit::commit`], or never does with
+//! [`PreparedCommit::abort`]. If the process stops in between, the commit is in doubt when the
+//! database is opened again, and is resolved as set by [`crate::Options::in_doubt_commit`].
+
+use nomt_core::trie::Node;
+
+use crate::{store, AppliedCommit, Error, HashAlgorithm, Nomt, Result};
+
+/// A commit written to disk but for the step which makes it take effect, returned by
+/// [`Nomt::prepare`].
+///
+/// The commit takes effect in memory right away, as with [`Nomt::commit`], but not on disk until
+/// [`Self::commit`]. [`Self::abort`] discards it instead, as does dropping it. While the commit is
+/// prepared, committing to the database fails with [`Error::InvalidOperation`].
+///
+/// An aborted commit leaves the database as after a failed commit: it has to be reopened, and is
+/// found at the root before the commit.
+pub struct PreparedCommit<'a, T: HashAlgorithm> {
+    pub(crate) nomt: &'a Nomt<T>,
+    pub(crate) commit: Option<(AppliedCommit, store::PreparedCommit)>,
+}
+
+impl<T: HashAlgorithm> PreparedCommit<'_, T> {
+    /// The root of the trie after the commit.
+    pub fn root(&self) -> Node {
+        // UNWRAP: the commit is only taken by `commit`, `abort` and drop.
+        self.commit.as_ref().unwrap().0.root
+    }
+
+    /// Make the commit take effect on disk. Returns the root after the commit.
+    pub fn commit(mut self) -> Result<Node> {
+        // UNWRAP: the commit is only taken by `commit`, `abort` and drop.
+        let (applied, prepared) = self.commit.take().unwrap();
+        let (prev_root, root) = (applied.prev_root, applied.root);
+        let result = match self.nomt.store.commit_prepared(prepared) {
+            Ok(changed_pages) => self.nomt.commits_synced(vec![applied], root, changed_pages),
+            Err(e) => {
+                self.nomt.shared.lock().root = prev_root;
+                Err(Error::internal(e))
+            }
+        };
+        self.nomt.shared.lock().prepared_commit_open = false;
+        result.map(|()| root)
+    }
+
+    /// Discard the commit. The database has to be reopened afterwards.
+    pub fn abort(mut self) -> Result<()> {
+        self.abort_inner()
+    }
+
+    fn abort_inner(&mut self) -> Result<()> {
+        let Some((applied, prepared)) = self.commit.take() else {
+            return Ok(());
+        };
+        let result = self.nomt.store.abort_prepared(prepared);
+        let mut shared = self.nomt.shared.lock();
+        shared.root = applied.prev_root;
+        shared.prepared_commit_open = false;
+        result.map_err(Error::internal)
+    }
+}
+
+impl<T: HashAlgorithm> Drop for PreparedCommit<'_, T> {
+    fn drop(&mut self) {
+        let _ = self.abort_inner();
+    }
+}
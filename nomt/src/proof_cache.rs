@@ -0,0 +1,47 @@
+//! A cache of recently generated path proofs.
+//!
+//! Entries are keyed by the root they were generated against along with the key path, so a cached
+//! proof is never wrong, only useless once the root has moved on. The cache is cleared on every
+//! commit to free the space taken by such entries.
+
+use std::num::NonZeroUsize;
+
+use lru::LruCache;
+use nomt_core::{
+    proof::PathProof,
+    trie::{KeyPath, Node},
+};
+use parking_lot::Mutex;
+
+pub(crate) struct ProofCache {
+    cached: Option<Mutex<LruCache<(Node, KeyPath), PathProof>>>,
+}
+
+impl ProofCache {
+    /// Create a cache holding up to `capacity` proofs. A capacity of zero disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cached: NonZeroUsize::new(capacity).map(|cap| Mutex::new(LruCache::new(cap))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.cached.is_some()
+    }
+
+    pub fn get(&self, root: Node, key_path: KeyPath) -> Option<PathProof> {
+        self.cached.as_ref()?.lock().get(&(root, key_path)).cloned()
+    }
+
+    pub fn insert(&self, root: Node, key_path: KeyPath, proof: PathProof) {
+        if let Some(cached) = &self.cached {
+            cached.lock().put((root, key_path), proof);
+        }
+    }
+
+    pub fn clear(&self) {
+        if let Some(cached) = &self.cached {
+            cached.lock().clear();
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! Recording and replaying sessions.
+//!
+//! When enabled with [`crate::Options::record_sessions`], the warm-ups and reads of every session,
+//! along with the actuals it is committed with, are written to a file named after the root the
+//! session began at. The file is written before the commit is applied, so that a commit which fails
+//! or panics is captured as well. Once the commit succeeds, the resulting root is appended.
+//!
+//! A recording can be loaded with [`Recording::read`] and re-executed with
+//! [`crate::Nomt::replay`] against a copy of the database at the root the session began at.
+//!
+//! # Format
+//!
+//! The file starts with an 8-byte magic followed by the 32-byte prior root. The rest is a sequence
+//! of entries, each starting with a 1-byte tag. Integers are little-endian and values are encoded
+//! as a presence byte followed by a `u32` length and the bytes. Prefixes are encoded as a `u16`
+//! length in bits followed by the bytes holding the bits.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read as _, Write as _},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _};
+use bitvec::prelude::*;
+use parking_lot::Mutex;
+
+use crate::{Error, KeyPath, KeyReadWrite, Node, Result, Value};
+
+const MAGIC: &[u8; 8] = b"NOMTREC1";
+
+const TAG_WARM_UP: u8 = 1;
+const TAG_READ: u8 = 2;
+const TAG_COMMIT_TAG: u8 = 3;
+const TAG_COMMIT: u8 = 4;
+const TAG_COMMITTED: u8 = 5;
+const TAG_DELETE_PREFIX: u8 = 6;
+
+const KIND_READ: u8 = 0;
+const KIND_WRITE: u8 = 1;
+const KIND_READ_THEN_WRITE: u8 = 2;
+
+/// An operation performed within a recorded session, before the commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedOp {
+    /// The key was warmed up with [`crate::Session::warm_up`].
+    WarmUp(KeyPath),
+    /// The key was read with [`crate::Session::read`] or [`crate::Session::read_ref`], returning
+    /// the contained value.
+    Read(KeyPath, Option<Value>),
+    /// The keys starting with the prefix were deleted with [`crate::Session::delete_prefix`].
+    DeletePrefix(BitVec<u8, Msb0>),
+}
+
+/// A session recorded with [`crate::Options::record_sessions`].
+#[derive(Debug, Clone)]
+pub struct Recording {
+    /// The root of the trie when the session began.
+    pub prior_root: Node,
+    /// The warm-ups and reads, in the order they were performed.
+    pub ops: Vec<RecordedOp>,
+    /// The tag attached to the commit, if any.
+    pub commit_tag: Option<Vec<u8>>,
+    /// Whether the session was committed with [`crate::Nomt::commit_and_prove`].
+    pub prove: bool,
+    /// The actuals the session was committed with.
+    pub actuals: Vec<(KeyPath, KeyReadWrite)>,
+    /// The root produced by the commit. `None` if the commit did not complete.
+    pub root: Option<Node>,
+}
+
+impl Recording {
+    /// Load a recording from a file.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let mut buf = Vec::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_end(&mut buf))
+            .map_err(Error::Io)?;
+        Self::decode(&buf).map_err(|e| Error::Other(format!("malformed recording: {e}").into()))
+    }
+
+    fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader(buf);
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("bad magic");
+        }
+        let prior_root = reader.key()?;
+
+        let mut ops = Vec::new();
+        let mut commit_tag = None;
+        let mut commit = None;
+        let mut root = None;
+        while !reader.0.is_empty() {
+            match reader.u8()? {
+                TAG_WARM_UP => ops.push(RecordedOp::WarmUp(reader.key()?)),
+                TAG_READ => ops.push(RecordedOp::Read(reader.key()?, reader.value()?)),
+                TAG_DELETE_PREFIX => ops.push(RecordedOp::DeletePrefix(reader.prefix()?)),
+                TAG_COMMIT_TAG => commit_tag = Some(reader.bytes()?.to_vec()),
+                TAG_COMMIT => {
+                    let prove = reader.u8()? != 0;
+                    let len = reader.u32()?;
+                    let mut actuals = Vec::with_capacity(len as usize);
+                    for _ in 0..len {
+                        let key = reader.key()?;
+                        let read_write = match reader.u8()? {
+                            KIND_READ => KeyReadWrite::Read(reader.value()?),
+                            KIND_WRITE => KeyReadWrite::Write(reader.value()?),
+                            KIND_READ_THEN_WRITE => {
+                                KeyReadWrite::ReadThenWrite(reader.value()?, reader.value()?)
+                            }
+                            kind => bail!("unknown actual kind {kind}"),
+                        };
+                        actuals.push((key, read_write));
+                    }
+                    commit = Some((prove, actuals));
+                }
+                TAG_COMMITTED => root = Some(reader.key()?),
+                tag => bail!("unknown tag {tag}"),
+            }
+        }
+
+        let Some((prove, actuals)) = commit else {
+            bail!("no commit recorded");
+        };
+        Ok(Recording {
+            prior_root,
+            ops,
+            commit_tag,
+            prove,
+            actuals,
+            root,
+        })
+    }
+}
+
+/// The outcome of [`crate::Nomt::replay`].
+#[derive(Debug, Clone)]
+pub struct ReplayOutcome {
+    /// The root produced by the replayed commit.
+    pub root: Node,
+    /// The keys whose reads returned a different value than recorded, in the order they were read.
+    pub mismatched_reads: Vec<KeyPath>,
+}
+
+impl ReplayOutcome {
+    /// Whether the replay reproduced the recorded session exactly: all reads returned the recorded
+    /// values and the commit produced the recorded root.
+    pub fn matches(&self, recording: &Recording) -> bool {
+        self.mismatched_reads.is_empty() && recording.root == Some(self.root)
+    }
+}
+
+/// Records the operations of a single session.
+pub(crate) struct SessionRecorder {
+    dir: PathBuf,
+    prior_root: Node,
+    ops: Mutex<Vec<u8>>,
+}
+
+impl SessionRecorder {
+    pub fn new(dir: PathBuf, prior_root: Node) -> Self {
+        Self {
+            dir,
+            prior_root,
+            ops: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn warm_up(&self, key: KeyPath) {
+        let mut ops = self.ops.lock();
+        ops.push(TAG_WARM_UP);
+        ops.extend_from_slice(&key);
+    }
+
+    pub fn read(&self, key: KeyPath, value: Option<&[u8]>) {
+        let mut ops = self.ops.lock();
+        ops.push(TAG_READ);
+        ops.extend_from_slice(&key);
+        write_value(&mut ops, value);
+    }
+
+    pub fn delete_prefix(&self, prefix: &BitSlice<u8, Msb0>) {
+        let mut ops = self.ops.lock();
+        ops.push(TAG_DELETE_PREFIX);
+        ops.extend_from_slice(&(prefix.len() as u16).to_le_bytes());
+        let mut bits = BitVec::<u8, Msb0>::with_capacity(prefix.len());
+        bits.extend_from_bitslice(prefix);
+        ops.extend_from_slice(bits.as_raw_slice());
+    }
+
+    /// Write the recording, ending with the commit, to the file. Returns the path of the file.
+    pub fn commit(
+        self,
+        commit_tag: Option<&[u8]>,
+        prove: bool,
+        actuals: &[(KeyPath, KeyReadWrite)],
+    ) -> anyhow::Result<PathBuf> {
+        let mut buf = Vec::with_capacity(MAGIC.len() + 32);
+        buf.extend_from_slice(MAGIC);
+        buf.extend_from_slice(&self.prior_root);
+        buf.append(&mut self.ops.into_inner());
+        if let Some(tag) = commit_tag {
+            buf.push(TAG_COMMIT_TAG);
+            write_bytes(&mut buf, tag);
+        }
+
+        buf.push(TAG_COMMIT);
+        buf.push(prove as u8);
+        buf.extend_from_slice(&(actuals.len() as u32).to_le_bytes());
+        for (key, read_write) in actuals {
+            buf.extend_from_slice(key);
+            match read_write {
+                KeyReadWrite::Read(value) => {
+                    buf.push(KIND_READ);
+                    write_value(&mut buf, value.as_deref());
+                }
+                KeyReadWrite::Write(value) => {
+                    buf.push(KIND_WRITE);
+                    write_value(&mut buf, value.as_deref());
+                }
+                KeyReadWrite::ReadThenWrite(prior, value) => {
+                    buf.push(KIND_READ_THEN_WRITE);
+                    write_value(&mut buf, prior.as_deref());
+                    write_value(&mut buf, value.as_deref());
+                }
+            }
+        }
+
+        std::fs::create_dir_all(&self.dir)?;
+        let name = self
+            .prior_root
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+        let path = self.dir.join(format!("{name}.session"));
+        std::fs::write(&path, &buf)
+            .with_context(|| format!("failed to write recording {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Append the root produced by the commit to the recording written by [`Self::commit`].
+    pub fn committed(path: &Path, root: Node) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(path)?;
+        let mut buf = Vec::with_capacity(33);
+        buf.push(TAG_COMMITTED);
+        buf.extend_from_slice(&root);
+        file.write_all(&buf)?;
+        Ok(())
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn write_value(buf: &mut Vec<u8>, value: Option<&[u8]>) {
+    match value {
+        None => buf.push(0),
+        Some(value) => {
+            buf.push(1);
+            write_bytes(buf, value);
+        }
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < n {
+            bail!("unexpected end of recording");
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn key(&mut self) -> anyhow::Result<[u8; 32]> {
+        Ok(self.take(32)?.try_into().unwrap())
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn prefix(&mut self) -> anyhow::Result<BitVec<u8, Msb0>> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as usize;
+        let mut prefix = BitVec::from_slice(self.take(len.div_ceil(8))?);
+        prefix.truncate(len);
+        Ok(prefix)
+    }
+
+    fn value(&mut self) -> anyhow::Result<Option<Value>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(self.bytes()?.into())),
+            presence => bail!("bad value presence byte {presence}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = SessionRecorder::new(dir.path().to_path_buf(), [7; 32]);
+        recorder.warm_up([1; 32]);
+        recorder.read([2; 32], Some(b"two"));
+        recorder.read([3; 32], None);
+        recorder.delete_prefix(&bits![u8, Msb0; 1, 0, 1, 1, 0, 0, 1, 0, 1, 1]);
+        let actuals = vec![
+            ([1; 32], KeyReadWrite::Write(Some(b"one".to_vec().into()))),
+            ([2; 32], KeyReadWrite::Read(Some(b"two".to_vec().into()))),
+            (
+                [3; 32],
+                KeyReadWrite::ReadThenWrite(None, Some(b"three".to_vec().into())),
+            ),
+        ];
+        let path = recorder.commit(Some(b"tag"), true, &actuals).unwrap();
+
+        let recording = Recording::read(&path).unwrap();
+        assert_eq!(recording.prior_root, [7; 32]);
+        assert_eq!(
+            recording.ops,
+            vec![
+                RecordedOp::WarmUp([1; 32]),
+                RecordedOp::Read([2; 32], Some(b"two".to_vec().into())),
+                RecordedOp::Read([3; 32], None),
+                RecordedOp::DeletePrefix(bitvec![u8, Msb0; 1, 0, 1, 1, 0, 0, 1, 0, 1, 1]),
+            ]
+        );
+        assert_eq!(recording.commit_tag.as_deref(), Some(&b"tag"[..]));
+        assert!(recording.prove);
+        assert_eq!(recording.actuals.len(), 3);
+        assert_eq!(recording.actuals[2].1.last_value(), Some(&b"three"[..]));
+        assert_eq!(recording.root, None);
+
+        SessionRecorder::committed(&path, [9; 32]).unwrap();
+        assert_eq!(Recording::read(&path).unwrap().root, Some([9; 32]));
+    }
+
+    #[test]
+    fn truncated_recording_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let recorder = SessionRecorder::new(dir.path().to_path_buf(), [7; 32]);
+        let path = recorder.commit(None, false, &[]).unwrap();
+        let buf = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &buf[..buf.len() - 1]).unwrap();
+        assert!(Recording::read(&path).is_err());
+    }
+}
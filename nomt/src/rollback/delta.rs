@@ -1,4 +1,4 @@
-use nomt_core::trie::KeyPath;
+use nomt_core::trie::{KeyPath, Node};
 use std::{
     collections::HashMap,
     io::{Cursor, Read as _},
@@ -10,6 +10,9 @@ pub struct Delta {
     /// This map contains the prior value for each key that was written by the commit this delta
     /// reverses. `None` indicates that the key did not exist before the commit.
     pub(crate) priors: HashMap<KeyPath, Option<Vec<u8>>>,
+    /// The root of the trie before the commit this delta reverses. `None` for deltas written by
+    /// versions which did not record it.
+    pub(crate) prior_root: Option<Node>,
 }
 
 impl Delta {
@@ -17,6 +20,7 @@ impl Delta {
     fn empty() -> Self {
         Self {
             priors: HashMap::new(),
+            prior_root: None,
         }
     }
 
@@ -38,6 +42,8 @@ impl Delta {
         // This is followed by the keys themselves, written contiguously in little-endian order.
         //
         // The keys are written as 32-byte big-endian values.
+        //
+        // Finally, the prior root is written as 32 bytes, if known.
 
         // Sort the keys into two groups.
         let mut to_erase = Vec::with_capacity(self.priors.len());
@@ -65,6 +71,10 @@ impl Delta {
             buf.extend_from_slice(value);
         }
 
+        if let Some(prior_root) = self.prior_root {
+            buf.extend_from_slice(&prior_root);
+        }
+
         buf
     }
 
@@ -105,7 +115,17 @@ impl Delta {
                 anyhow::bail!("duplicate key path (reinstate): {:?}", key_path);
             }
         }
-        Ok(Delta { priors })
+
+        // The prior root is optional, so that deltas written before it was recorded still decode.
+        let remaining = reader.get_ref().as_ref().len() as u64 - reader.position();
+        let prior_root = if remaining >= 32 {
+            let mut prior_root = [0; 32];
+            reader.read_exact(&mut prior_root)?;
+            Some(prior_root)
+        } else {
+            None
+        };
+        Ok(Delta { priors, prior_root })
     }
 }
 
@@ -126,6 +146,19 @@ mod tests {
         assert_eq!(delta.priors, delta2.priors);
     }
 
+    #[test]
+    fn delta_roundtrip_prior_root() {
+        let mut delta = Delta::empty();
+        delta.priors.insert([1; 32], Some(b"value1".to_vec()));
+        delta.prior_root = Some([7; 32]);
+
+        let mut buf = delta.encode();
+        let mut cursor = Cursor::new(&mut buf);
+        let delta2 = Delta::decode(&mut cursor).unwrap();
+        assert_eq!(delta.priors, delta2.priors);
+        assert_eq!(delta2.prior_root, Some([7; 32]));
+    }
+
     #[test]
     fn delta_roundtrip_empty() {
         let delta = Delta::empty();
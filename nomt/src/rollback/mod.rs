@@ -7,6 +7,10 @@
 //! oldest deltas are discarded to make space for new ones.
 //!
 //! The deltas are also persisted on disk in a [`seglog`].
+//!
+//! Every delta also records the root of the trie before the commit it reverses. This allows
+//! serving reads at the roots of recent commits by applying the deltas to the current values, see
+//! [`Rollback::value_at`].
 
 use std::{
     collections::{BTreeMap, VecDeque},
@@ -17,7 +21,7 @@ use std::{
 };
 
 use dashmap::DashMap;
-use nomt_core::trie::KeyPath;
+use nomt_core::trie::{KeyPath, Node};
 use parking_lot::Mutex;
 use threadpool::ThreadPool;
 
@@ -73,6 +77,10 @@ impl InMemory {
         self.log.pop_back()
     }
 
+    fn pop_front(&mut self) -> Option<(RecordId, Delta)> {
+        self.log.pop_front()
+    }
+
     // Returns the total number of deltas, including the staged one.
     fn total_len(&self) -> usize {
         self.log.len()
@@ -130,14 +138,16 @@ impl Rollback {
     /// Saves the delta into the log.
     ///
     /// This function accepts the final list of operations that should be performed sorted by the
-    /// key paths in ascending order.
+    /// key paths in ascending order. `prior_root` is the root of the trie before the commit.
     pub fn commit(
         &self,
         store: impl LoadValue,
         actuals: &[(KeyPath, KeyReadWrite)],
         delta: ReverseDeltaBuilder,
+        prior_root: Node,
     ) -> anyhow::Result<()> {
-        let delta = delta.finalize(store, actuals);
+        let mut delta = delta.finalize(store, actuals);
+        delta.prior_root = Some(prior_root);
         let delta_bytes = delta.encode();
 
         let mut in_memory = self.shared.in_memory.lock();
@@ -148,6 +158,44 @@ impl Rollback {
         Ok(())
     }
 
+    /// Returns the value of the given key as of the time the trie had the given root, or `None` if
+    /// the root is not covered by the log.
+    ///
+    /// `current_value` must be the value of the key in the store, read before calling this
+    /// function. Every commit logs its delta before modifying the store, so this way the deltas
+    /// of all commits that could have been observed in `current_value` are applied.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn value_at(
+        &self,
+        root: Node,
+        key_path: KeyPath,
+        current_value: Option<Vec<u8>>,
+    ) -> Option<Option<Vec<u8>>> {
+        let in_memory = self.shared.in_memory.lock();
+        let mut value = current_value;
+        for (_, delta) in in_memory.log.iter().rev() {
+            if let Some(prior) = delta.priors.get(&key_path) {
+                value = prior.clone();
+            }
+            if delta.prior_root == Some(root) {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Returns the number of commits made since the trie had the given root, or `None` if the root
+    /// is not covered by the log.
+    pub fn commits_since(&self, root: Node) -> Option<usize> {
+        let in_memory = self.shared.in_memory.lock();
+        in_memory
+            .log
+            .iter()
+            .rev()
+            .position(|(_, delta)| delta.prior_root == Some(root))
+            .map(|i| i + 1)
+    }
+
     /// Truncates the rollback log by removing the last `n` deltas.
     ///
     /// This function returns the keys and values that we should apply to the database to restore
@@ -217,7 +265,8 @@ impl Rollback {
         }
 
         let prune_to_new_start_live = if in_memory.total_len() > self.shared.max_rollback_log_len {
-            Some(in_memory.pop_back().unwrap().0.next().0)
+            // Discard the oldest delta.
+            Some(in_memory.pop_front().unwrap().0.next().0)
         } else {
             None
         };
@@ -282,7 +331,7 @@ pub trait LoadValue: Clone + Send + Sync + 'static {
 
 impl LoadValue for crate::store::Store {
     fn load_value(&self, key_path: KeyPath) -> anyhow::Result<Option<Vec<u8>>> {
-        self.load_value(key_path)
+        Ok(self.load_value(key_path)?.map(|v| v.into_vec()))
     }
 }
 
@@ -340,7 +389,7 @@ impl ReverseDeltaBuilder {
                 }
                 KeyReadWrite::ReadThenWrite(prior, _) => {
                     // The path was read and then written. We could just keep the prior value.
-                    final_priors.insert(*path, prior.clone());
+                    final_priors.insert(*path, prior.as_ref().map(|v| v.to_vec()));
                 }
             }
         }
@@ -351,6 +400,7 @@ impl ReverseDeltaBuilder {
 
         Delta {
             priors: Arc::into_inner(final_priors).unwrap().into_iter().collect(),
+            prior_root: None,
         }
     }
 }
@@ -98,14 +98,15 @@ fn truncate_works() {
             &[
                 (
                     hex!("0101010101010101010101010101010101010101010101010101010101010101"),
-                    KeyReadWrite::Write(Some(b"new_value1".to_vec())),
+                    KeyReadWrite::Write(Some(b"new_value1".to_vec().into())),
                 ),
                 (
                     hex!("0202020202020202020202020202020202020202020202020202020202020202"),
-                    KeyReadWrite::Write(Some(b"new_value2".to_vec())),
+                    KeyReadWrite::Write(Some(b"new_value2".to_vec().into())),
                 ),
             ],
             builder,
+            [0; 32],
         )
         .unwrap();
 
@@ -174,14 +175,15 @@ fn without_tentative_preserve_prior() {
             &[
                 (
                     hex!("0101010101010101010101010101010101010101010101010101010101010101"),
-                    KeyReadWrite::Write(Some(b"new_value1".to_vec())),
+                    KeyReadWrite::Write(Some(b"new_value1".to_vec().into())),
                 ),
                 (
                     hex!("0202020202020202020202020202020202020202020202020202020202020202"),
-                    KeyReadWrite::Write(Some(b"new_value2".to_vec())),
+                    KeyReadWrite::Write(Some(b"new_value2".to_vec().into())),
                 ),
             ],
             builder,
+            [0; 32],
         )
         .unwrap();
 
@@ -242,11 +244,12 @@ fn delta_builder_doesnt_load_read_then_write_priors() {
             &[(
                 key_1,
                 KeyReadWrite::ReadThenWrite(
-                    Some(b"prior_value".to_vec()),
-                    Some(b"new_value1".to_vec()),
+                    Some(b"prior_value".to_vec().into()),
+                    Some(b"new_value1".to_vec().into()),
                 ),
             )],
             builder,
+            [0; 32],
         )
         // This will panic if the delta builder attempts to load from store the prior value for
         // key_1.
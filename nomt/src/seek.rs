@@ -493,6 +493,24 @@ pub enum Completion {
     SinglePage,
 }
 
+/// Seek a single key, blocking the current thread until the terminal node is found.
+pub fn seek_blocking(
+    read_pass: &ReadPass<ShardIndex>,
+    mut seeker: Seeker,
+    key: KeyPath,
+) -> anyhow::Result<Seek> {
+    seeker.push(key);
+    loop {
+        if let Some(Completion::Seek(seek)) = seeker.take_completion() {
+            return Ok(seek);
+        }
+        seeker.submit_all(read_pass)?;
+        if seeker.has_live_requests() {
+            seeker.recv_page(read_pass)?;
+        }
+    }
+}
+
 /// The result of a seek.
 #[derive(Clone)]
 pub struct Seek {
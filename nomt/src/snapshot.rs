@@ -0,0 +1,216 @@
+//! Read handles pinned at a root.
+//!
+//! A snapshot shares the copy-on-write index of the b-tree with the database at the time it was
+//! taken, so taking one doesn't copy any data. Pages that later commits free are kept from being
+//! overwritten for as long as any snapshot is alive.
+
+use std::{
+    iter::Rev,
+    ops::{Bound, RangeBounds},
+};
+
+use crate::{beatree, Error, KeyPath, Node, Result, Value};
+
+/// A read-only view of the values as of the time it was taken, unaffected by later commits.
+///
+/// Only values are served. Proofs against the root of a snapshot retained by the database are
+/// produced by [`crate::Nomt::prove_at`].
+///
+/// Leaf pages freed while a snapshot is alive are only reused after the snapshots taken before
+/// they were freed are dropped, so long-lived snapshots make the database grow. If the process
+/// stops while a snapshot is alive, those pages are leaked. With
+/// [`crate::Options::snapshot_horizon`], the pages are reclaimed after a number of commits
+/// instead, and the snapshot expires: [`Snapshot::read`] fails with
+/// [`Error::InvalidOperation`] and its iterators panic.
+///
+/// A snapshot taken after a commit failed would see the changes of the failed commit, which were
+/// not written out. Such a snapshot behaves like an expired one, and so do the other accessors
+/// of its values.
+///
+/// Created with [`crate::Nomt::snapshot`] or [`crate::Nomt::snapshot_at`]. Cloning is cheap and
+/// shares the pinned pages.
+#[derive(Clone)]
+pub struct Snapshot {
+    root: Node,
+    values: beatree::Snapshot,
+    /// Whether a commit had failed when the snapshot was taken.
+    failed: bool,
+}
+
+impl Snapshot {
+    pub(crate) fn new(root: Node, values: beatree::Snapshot, failed: bool) -> Self {
+        Self {
+            root,
+            values,
+            failed,
+        }
+    }
+
+    fn check_usable(&self) -> Result<()> {
+        if self.failed {
+            return Err(Error::InvalidOperation(
+                "a previous commit failed, the database must be reopened".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn assert_usable(&self) {
+        if let Err(e) = self.check_usable() {
+            panic!("{e}");
+        }
+    }
+
+    /// Returns the root of the trie as of the snapshot.
+    pub fn root(&self) -> Node {
+        self.root
+    }
+
+    /// Returns the value stored under the given key as of the snapshot.
+    pub fn read(&self, path: KeyPath) -> Result<Option<Value>> {
+        self.check_usable()?;
+        Ok(self
+            .values
+            .lookup(path)
+            .map_err(Error::internal)?
+            .map(|v| v.into_value()))
+    }
+
+    /// Returns an iterator over the values within the range of keys as of the snapshot, in
+    /// ascending key order.
+    pub fn iter(&self, range: impl RangeBounds<KeyPath>) -> Iter {
+        self.assert_usable();
+        Iter {
+            inner: inclusive_range(range).map(|(start, end)| self.values.iter(start, end)),
+        }
+    }
+
+    /// Returns an iterator over the values within the range of keys as of the snapshot, in
+    /// descending key order.
+    pub fn iter_rev(&self, range: impl RangeBounds<KeyPath>) -> Rev<Iter> {
+        self.iter(range).rev()
+    }
+
+    /// Returns an estimate of the number of keys stored as of the snapshot.
+    ///
+    /// See [`crate::Nomt::estimate_num_keys`].
+    pub fn estimate_num_keys(&self) -> u64 {
+        self.assert_usable();
+        self.values.estimate_size().keys
+    }
+
+    /// Returns an estimate of the total size of the keys and values stored as of the snapshot, in
+    /// bytes.
+    ///
+    /// See [`crate::Nomt::estimate_num_keys`].
+    pub fn estimate_data_size(&self) -> u64 {
+        self.assert_usable();
+        self.values.estimate_size().bytes
+    }
+
+    /// Returns the lowest key stored as of the snapshot.
+    pub fn first_key(&self) -> Option<KeyPath> {
+        self.assert_usable();
+        self.values.first_key()
+    }
+
+    /// Returns the highest key stored as of the snapshot.
+    pub fn last_key(&self) -> Option<KeyPath> {
+        self.assert_usable();
+        self.values.last_key()
+    }
+
+    /// Returns the first key at or after the given one along with its value, as of the snapshot.
+    pub fn seek_at_or_after(&self, key: KeyPath) -> Option<(KeyPath, Value)> {
+        self.iter(key..).next()
+    }
+
+    /// Returns the last key at or before the given one along with its value, as of the snapshot.
+    pub fn seek_at_or_before(&self, key: KeyPath) -> Option<(KeyPath, Value)> {
+        self.iter_rev(..=key).next()
+    }
+}
+
+/// An iterator over the values within a range of keys, in ascending key order. Values may also
+/// be taken from the end of the range with [`DoubleEndedIterator::next_back`].
+///
+/// The iterator keeps seeing the values as of the time it was created, and keeps the pages it
+/// reads from being reused like a [`Snapshot`] does.
+///
+/// Created with [`crate::Nomt::iter`] or [`Snapshot::iter`].
+pub struct Iter {
+    inner: Option<beatree::Iter>,
+}
+
+impl Iterator for Iter {
+    type Item = (KeyPath, Value);
+
+    fn next(&mut self) -> Option<(KeyPath, Value)> {
+        self.inner.as_mut()?.next()
+    }
+}
+
+impl DoubleEndedIterator for Iter {
+    fn next_back(&mut self) -> Option<(KeyPath, Value)> {
+        self.inner.as_mut()?.next_back()
+    }
+}
+
+/// Converts a range of keys into the first and last key within it. `None` if the range is empty.
+pub(crate) fn inclusive_range(range: impl RangeBounds<KeyPath>) -> Option<(KeyPath, KeyPath)> {
+    let start = match range.start_bound() {
+        Bound::Unbounded => [0; 32],
+        Bound::Included(start) => *start,
+        Bound::Excluded(start) => step(*start, 1)?,
+    };
+    let end = match range.end_bound() {
+        Bound::Unbounded => [0xFF; 32],
+        Bound::Included(end) => *end,
+        Bound::Excluded(end) => step(*end, -1)?,
+    };
+    (start <= end).then_some((start, end))
+}
+
+/// Returns the key following or preceding the given one, or `None` if there is none.
+fn step(mut key: KeyPath, direction: i8) -> Option<KeyPath> {
+    let wraps_at = if direction > 0 { 0xFF } else { 0x00 };
+    for byte in key.iter_mut().rev() {
+        if *byte != wraps_at {
+            *byte = byte.wrapping_add_signed(direction);
+            return Some(key);
+        }
+        *byte = !wraps_at;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inclusive_range;
+
+    #[test]
+    fn inclusive_range_bounds() {
+        let mut key = [0; 32];
+        key[31] = 0xFF;
+        let mut next = [0; 32];
+        next[30] = 1;
+
+        assert_eq!(inclusive_range(..), Some(([0; 32], [0xFF; 32])));
+        assert_eq!(inclusive_range(key..=key), Some((key, key)));
+        assert_eq!(inclusive_range(key..next), Some((key, key)));
+        assert_eq!(
+            inclusive_range((std::ops::Bound::Excluded(key), std::ops::Bound::Unbounded)),
+            Some((next, [0xFF; 32]))
+        );
+        assert_eq!(inclusive_range(key..key), None);
+        assert_eq!(inclusive_range(next..=key), None);
+        assert_eq!(inclusive_range(..[0; 32]), None);
+        assert_eq!(
+            inclusive_range((
+                std::ops::Bound::Excluded([0xFF; 32]),
+                std::ops::Bound::Unbounded
+            )),
+            None
+        );
+    }
+}
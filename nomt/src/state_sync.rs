@@ -0,0 +1,175 @@
+//! Serving the state to peers syncing it, in chunks of consecutive values with range proofs.
+//!
+//! The chunks of a state partition the whole key space: the first chunk starts at the lowest key,
+//! every chunk starts right after the end of the previous one, and the last chunk ends at the
+//! highest key. A peer can thus request the chunks one after another, or in parallel once it
+//! knows where they start, and check each chunk on its own against the root.
+//!
+//! On the receiving side, [`StateSync`] verifies the chunks and commits their values in order, so
+//! the trie pages are built up along with the b-tree as the chunks arrive.
+
+use nomt_core::{
+    range_proof::RangeProof,
+    trie::{KeyPath, Node},
+};
+
+use crate::{Error, HashAlgorithm, KeyReadWrite, Nomt, Result, Value};
+
+/// The values within a range of keys, along with the proof that these are all the values within
+/// it. Returned by [`Nomt::state_chunk`].
+///
+/// The proof can be checked with [`RangeProof::verify`] for the range from `start` to `end`.
+#[derive(Debug, Clone)]
+pub struct StateChunk {
+    /// The root the proof is against.
+    pub root: Node,
+    /// The first key of the range.
+    pub start: KeyPath,
+    /// The last key of the range, inclusive.
+    pub end: KeyPath,
+    /// The values within the range, in ascending key order.
+    pub values: Vec<(KeyPath, Value)>,
+    /// The proof that these are all the values within the range.
+    pub proof: RangeProof,
+    /// The start of the next chunk, or `None` if this is the last chunk.
+    pub next: Option<KeyPath>,
+}
+
+/// An iterator over all the chunks of the state at a root, returned by [`Nomt::state_chunks`].
+pub struct StateChunks<'a, T: HashAlgorithm> {
+    pub(crate) nomt: &'a Nomt<T>,
+    pub(crate) root: Node,
+    pub(crate) max_values: usize,
+    /// The start of the next chunk, `None` once the last chunk was returned or an error occurred.
+    pub(crate) next: Option<KeyPath>,
+}
+
+impl<T: HashAlgorithm> Iterator for StateChunks<'_, T> {
+    type Item = Result<StateChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.next.take()?;
+        let chunk = self.nomt.state_chunk(self.root, start, self.max_values);
+        if let Ok(chunk) = &chunk {
+            self.next = chunk.next;
+        }
+        Some(chunk)
+    }
+}
+
+/// Ingests the chunks of the state at a root into an empty database, returned by
+/// [`Nomt::state_sync`].
+///
+/// The chunks have to be ingested in order, each starting at [`Self::next_start`]. The values of a
+/// chunk are committed once its proof verifies against the root, so the database holds a prefix
+/// of the state until the last chunk lands, at which point its root is the root being synced.
+/// Nothing else may be committed to the database in the meantime.
+///
+/// If the sync is interrupted, the database has to be reset before starting over.
+pub struct StateSync<'a, T: HashAlgorithm> {
+    pub(crate) nomt: &'a Nomt<T>,
+    pub(crate) root: Node,
+    /// The start of the next chunk, `None` once the last chunk was ingested.
+    pub(crate) next: Option<KeyPath>,
+}
+
+impl<T: HashAlgorithm> StateSync<'_, T> {
+    /// The root being synced.
+    pub fn root(&self) -> Node {
+        self.root
+    }
+
+    /// The start of the chunk to ingest next, or `None` once the sync is complete.
+    pub fn next_start(&self) -> Option<KeyPath> {
+        self.next
+    }
+
+    /// Whether the last chunk was ingested.
+    pub fn is_complete(&self) -> bool {
+        self.next.is_none()
+    }
+
+    /// Verify the chunk and commit its values. Returns whether this completed the sync.
+    ///
+    /// Fails with [`Error::InvalidStateChunk`] if the chunk is for another root, doesn't start at
+    /// [`Self::next_start`], or if its proof or values don't verify against the root. Nothing is
+    /// committed in that case, so another chunk may be ingested instead. Fails with
+    /// [`Error::InvalidOperation`] if the sync is complete already.
+    pub fn ingest(&mut self, chunk: &StateChunk) -> Result<bool> {
+        let Some(start) = self.next else {
+            return Err(Error::InvalidOperation(
+                "state sync: already complete".to_string(),
+            ));
+        };
+        let invalid = |msg: &str| Err(Error::InvalidStateChunk(msg.to_string()));
+        if chunk.root != self.root {
+            return invalid("the chunk is for another root");
+        }
+        if chunk.start != start {
+            return invalid("the chunk doesn't start where the previous one ended");
+        }
+        let leaves = match chunk.proof.verify::<T>(self.root, &chunk.start, &chunk.end) {
+            Ok(leaves) => leaves,
+            Err(e) => return Err(Error::InvalidStateChunk(format!("invalid proof: {e:?}"))),
+        };
+        let values_match = leaves.len() == chunk.values.len()
+            && leaves
+                .iter()
+                .zip(&chunk.values)
+                .all(|(leaf, (path, value))| {
+                    leaf.key_path == *path && leaf.value_hash == T::hash_value(value)
+                });
+        if !values_match {
+            return invalid("the values don't match the proof");
+        }
+
+        if !chunk.values.is_empty() {
+            let actuals = chunk
+                .values
+                .iter()
+                .map(|(path, value)| (*path, KeyReadWrite::Write(Some(value.clone()))))
+                .collect();
+            self.nomt.commit_values(actuals)?;
+        }
+        self.next = successor(chunk.end);
+        if self.next.is_none() && self.nomt.root() != self.root {
+            return Err(Error::Corruption(
+                "state sync: the synced values don't match the root".to_string(),
+            ));
+        }
+        Ok(self.next.is_none())
+    }
+}
+
+/// Returns the key following the given one, or `None` for the highest key.
+pub(crate) fn successor(mut key: KeyPath) -> Option<KeyPath> {
+    for byte in key.iter_mut().rev() {
+        if *byte == 0xFF {
+            *byte = 0;
+        } else {
+            *byte += 1;
+            return Some(key);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::successor;
+
+    #[test]
+    fn successor_carries() {
+        let mut one = [0; 32];
+        one[31] = 1;
+        assert_eq!(successor([0; 32]), Some(one));
+
+        let mut key = [0; 32];
+        key[30] = 4;
+        key[31] = 0xFF;
+        let mut expected = [0; 32];
+        expected[30] = 5;
+        assert_eq!(successor(key), Some(expected));
+        assert_eq!(successor([0xFF; 32]), None);
+    }
+}
@@ -11,17 +11,28 @@ pub struct Flock {
 }
 
 impl Flock {
+    /// Take the lock exclusively, for an instance which writes to the directory.
     pub fn lock(db_dir: &Path, lock_filename: &str) -> anyhow::Result<Self> {
-        let lock_path = db_dir.join(lock_filename);
-
         let lock_fd = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(lock_path)?;
+            .open(db_dir.join(lock_filename))?;
+        Self::try_lock(lock_fd, crate::sys::unix::try_lock_exclusive)
+    }
+
+    /// Take the lock shared with other readers, for an instance which only reads the directory.
+    pub fn lock_shared(db_dir: &Path, lock_filename: &str) -> anyhow::Result<Self> {
+        let lock_fd = OpenOptions::new()
+            .read(true)
+            .open(db_dir.join(lock_filename))?;
+        Self::try_lock(lock_fd, crate::sys::unix::try_lock_shared)
+    }
 
-        match crate::sys::unix::try_lock_exclusive(&lock_fd) {
+    fn try_lock(lock_fd: File, try_lock: fn(&File) -> std::io::Result<()>) -> anyhow::Result<Self> {
+        match try_lock(&lock_fd) {
             Ok(_) => Ok(Self { lock_fd }),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Err(crate::Error::Busy.into()),
             Err(e) => {
                 anyhow::bail!("Failed to lock directory: {e}");
             }
@@ -0,0 +1,104 @@
+//! Inspecting a database without opening it.
+
+use super::{
+    meta::{self, Meta},
+    stats::FileSize,
+};
+use crate::{bitbox, io::PAGE_SIZE};
+use nomt_core::{binning::KeyBinning, trie::Node};
+use std::{fs::File, path::Path};
+
+/// The configuration and state of a database as recorded on disk, obtained without opening it.
+///
+/// The on-disk format is not versioned and the hash function is not recorded, so neither can be
+/// reported. Opening a database with a different hash function succeeds, but yields a root which
+/// doesn't match the stored pages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DatabaseInfo {
+    /// The size of the pages of all files, in bytes.
+    pub page_size: usize,
+    /// The sequence number of the last commit. 0 means there were no commits.
+    pub sync_seqn: u32,
+    /// The sequence number of the last commit, which unlike `sync_seqn` never wraps around. See
+    /// [`crate::Nomt::commit_seqn`].
+    pub commit_seqn: u64,
+    /// The number of buckets of the hash-table. See [`crate::Options::hashtable_buckets`].
+    pub hashtable_buckets: u32,
+    /// The mapping of pages to buckets. See [`crate::Options::bucket_mapping`].
+    #[cfg(feature = "unstable")]
+    pub bucket_mapping: crate::BucketMappingStrategy,
+    /// The binning the database was created with. See [`crate::Options::key_binning`].
+    pub key_binning: KeyBinning,
+    /// The compression of the values. See [`crate::Options::compression`].
+    #[cfg(feature = "unstable")]
+    pub compression: crate::Compression,
+    /// The size of the compression dictionary in bytes, or 0 if there is none. See
+    /// [`crate::Options::compression_dictionary`].
+    pub compression_dictionary_len: usize,
+    /// The root of the trie after the last commit. `None` if the root history is disabled, see
+    /// [`crate::Options::root_history`].
+    pub last_root: Option<Node>,
+    /// The root of the trie before the last commit. The terminator if there were no commits, or if
+    /// the last commit was written by a version of NOMT which didn't record it.
+    pub parent_root: Node,
+    /// The tag attached to the last commit, if any.
+    pub last_commit_tag: Option<Vec<u8>>,
+    /// Whether the last commit was interrupted after writing its WAL, which then has to be
+    /// replayed when opening the database.
+    pub needs_recovery: bool,
+    /// The meta file.
+    pub meta: FileSize,
+    /// The hash-table file.
+    pub ht: FileSize,
+    /// The write-ahead log of the hash-table.
+    pub wal: FileSize,
+    /// The leaf node file.
+    pub ln: FileSize,
+    /// The bottom-level branch node file.
+    pub bbn: FileSize,
+}
+
+/// Reads the state of the database at the given path without opening it.
+///
+/// Returns `None` if there is no initialized database at the path.
+pub fn inspect(path: &Path) -> anyhow::Result<Option<DatabaseInfo>> {
+    // See `Store::open`.
+    if !path.join("meta").exists() {
+        return Ok(None);
+    }
+
+    let meta_bytes = std::fs::read(path.join("meta"))?;
+    if meta_bytes.len() < meta::META_SIZE {
+        return Err(crate::Error::Corruption(format!(
+            "meta file is too short: {} bytes",
+            meta_bytes.len()
+        ))
+        .into());
+    }
+    let meta = Meta::decode(&meta_bytes[..meta::META_SIZE])?;
+    meta.validate()?;
+
+    let open = |name: &str| File::open(path.join(name));
+    let wal_fd = open("wal")?;
+    Ok(Some(DatabaseInfo {
+        page_size: PAGE_SIZE,
+        sync_seqn: meta.sync_seqn,
+        commit_seqn: meta.commit_seqn,
+        hashtable_buckets: meta.bitbox_num_pages,
+        #[cfg(feature = "unstable")]
+        bucket_mapping: meta.bitbox_mapping,
+        key_binning: meta.key_binning,
+        #[cfg(feature = "unstable")]
+        compression: meta.compression,
+        compression_dictionary_len: meta.dictionary_len as usize,
+        last_root: meta.root_history.last().map(|record| record.root),
+        parent_root: meta.parent_root,
+        last_commit_tag: Some(meta.commit_tag).filter(|tag| !tag.is_empty()),
+        needs_recovery: bitbox::needs_recovery(&wal_fd, meta.sync_seqn)?,
+        meta: FileSize::of(&open("meta")?)?,
+        ht: FileSize::of(&open("ht")?)?,
+        wal: FileSize::of(&wal_fd)?,
+        ln: FileSize::of(&open("ln")?)?,
+        bbn: FileSize::of(&open("bbn")?)?,
+    }))
+}
@@ -0,0 +1,108 @@
+//! Checks of the integrity of the on-disk storage.
+
+use nomt_core::trie::Node;
+use std::{fmt, ops::Range};
+
+/// How thoroughly [`Nomt::check_integrity`](crate::Nomt::check_integrity) checks the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegrityLevel {
+    /// Check the structure of the files: the occupied buckets of the hash-table, the branch and
+    /// leaf nodes of the b-tree and the free-lists of the node files.
+    Structure,
+    /// In addition to [`IntegrityLevel::Structure`], re-derive the root of the trie from the
+    /// stored values and compare it with the current root. This reads and hashes every value, and
+    /// is skipped if the structural checks found problems.
+    Full,
+}
+
+/// A problem found by [`Nomt::check_integrity`](crate::Nomt::check_integrity).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IntegrityIssue {
+    /// A page of one of the database files is damaged or inconsistent with the rest of the
+    /// database.
+    Page {
+        /// The name of the file: `ht`, `ln` or `bbn`.
+        file: &'static str,
+        /// The number of the page within the file.
+        page_number: u64,
+        /// What is wrong with the page.
+        description: String,
+    },
+    /// The root re-derived from the stored values differs from the root of the trie.
+    RootMismatch {
+        /// The root of the trie.
+        root: Node,
+        /// The root re-derived from the stored values.
+        derived: Node,
+    },
+}
+
+impl fmt::Display for IntegrityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityIssue::Page {
+                file,
+                page_number,
+                description,
+            } => write!(f, "page {page_number} of the {file} file: {description}"),
+            IntegrityIssue::RootMismatch { root, derived } => {
+                write!(f, "root ")?;
+                write_hex(f, root)?;
+                write!(f, " doesn't match the root ")?;
+                write_hex(f, derived)?;
+                write!(f, " derived from the values")
+            }
+        }
+    }
+}
+
+fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|b| write!(f, "{b:02x}"))
+}
+
+/// The outcome of [`Nomt::check_integrity`](crate::Nomt::check_integrity).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    /// The problems found, in the order they were found.
+    pub issues: Vec<IntegrityIssue>,
+    /// The number of occupied buckets of the hash-table checked.
+    pub buckets: usize,
+    /// The number of branch nodes checked.
+    pub branches: usize,
+    /// The number of leaf nodes checked.
+    pub leaves: usize,
+    /// The number of pages of the leaf node file which are neither in use nor free.
+    ///
+    /// Pages freed while a snapshot is alive are leaked if the process stops before they are
+    /// returned to the free-list, so leaked pages waste space but are not a problem.
+    pub leaked_ln_pages: usize,
+    /// The number of pages of the branch node file which are neither in use nor free.
+    pub leaked_bbn_pages: usize,
+    /// The page numbers of the leaked pages of the leaf node file, as ascending ranges of
+    /// consecutive pages.
+    pub leaked_ln_ranges: Vec<Range<u64>>,
+    /// The page numbers of the leaked pages of the branch node file, as ascending ranges of
+    /// consecutive pages.
+    pub leaked_bbn_ranges: Vec<Range<u64>>,
+}
+
+impl IntegrityReport {
+    /// Whether no problems were found. Leaked pages don't count as problems.
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub(crate) fn page_issue(
+        &mut self,
+        file: &'static str,
+        page_number: u64,
+        description: impl Into<String>,
+    ) {
+        self.issues.push(IntegrityIssue::Page {
+            file,
+            page_number,
+            description: description.into(),
+        });
+    }
+}
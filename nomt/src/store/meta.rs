@@ -1,9 +1,59 @@
 /// The utility functions for handling the metadata file.
+///
+/// The first page of the file holds the meta, which is rewritten by every sync. The compression
+/// dictionary, if any, follows on the next pages. It is written once when the database is created.
 use anyhow::Result;
 use std::fs::File;
 use std::os::unix::fs::FileExt as _;
 
-use crate::io::{self, PagePool};
+use crate::{
+    beatree::Compression,
+    bitbox::BucketMappingStrategy,
+    io::{self, PagePool, PAGE_SIZE},
+};
+use nomt_core::{binning::KeyBinning, trie::Node};
+
+/// The maximum length of a commit tag, in bytes.
+pub const MAX_COMMIT_TAG_LEN: usize = 256;
+
+/// The maximum number of roots kept in the root history.
+pub const MAX_ROOT_HISTORY_LEN: usize = 64;
+
+const ROOT_HISTORY_OFFSET: usize = 58 + MAX_COMMIT_TAG_LEN;
+const ROOT_RECORD_SIZE: usize = 36;
+const BUCKET_MAPPING_OFFSET: usize =
+    ROOT_HISTORY_OFFSET + 2 + MAX_ROOT_HISTORY_LEN * ROOT_RECORD_SIZE;
+
+const KEY_BINNING_OFFSET: usize = BUCKET_MAPPING_OFFSET + 1;
+const FORMAT_OFFSET: usize = KEY_BINNING_OFFSET + 1;
+const COMPRESSION_OFFSET: usize = FORMAT_OFFSET + 8;
+const DICTIONARY_OFFSET: usize = COMPRESSION_OFFSET + 2;
+const COMMIT_SEQN_OFFSET: usize = DICTIONARY_OFFSET + 12;
+const PARENT_ROOT_OFFSET: usize = COMMIT_SEQN_OFFSET + 8;
+
+/// The size of the encoded meta, in bytes.
+pub const META_SIZE: usize = PARENT_ROOT_OFFSET + 32;
+
+/// Marks a meta file recording the version of the format of the database files.
+const FORMAT_MAGIC: [u8; 4] = *b"NOMT";
+
+/// The version of the format of the database files. It is bumped by every change to the layout of
+/// the files which makes them unreadable by the previous versions, or the other way around:
+///
+/// 1. The pages of the ht, ln and bbn files end with a checksum.
+/// 2. The entries of the WAL end with their sequence number and a checksum.
+/// 3. The values of a compressed leaf store are framed with a tag.
+pub const FORMAT_VERSION: u32 = 3;
+
+/// A root produced by a commit, along with the sequence number of the commit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CommitRoot {
+    /// The sequence number of the commit. It increases by one with every commit, including the
+    /// commits performed by a rollback.
+    pub seqn: u32,
+    /// The root of the trie after the commit.
+    pub root: Node,
+}
 
 /// This data structure describes the state of the btree.
 #[derive(Clone)]
@@ -30,15 +80,37 @@ pub struct Meta {
     pub bitbox_num_pages: u32,
     /// The random seed used for populating the hash-table in a unique way.
     pub bitbox_seed: [u8; 16],
+    /// The strategy used for mapping pages to the buckets of the bitbox store.
+    pub bitbox_mapping: BucketMappingStrategy,
+    /// The binning applied by the application to derive key paths.
+    pub key_binning: KeyBinning,
+    /// The compression of the values stored in the leaf store.
+    pub compression: Compression,
+    /// The length of the compression dictionary, in bytes. 0 means there is no dictionary.
+    pub dictionary_len: u32,
+    /// The xxh3 checksum of the compression dictionary.
+    pub dictionary_checksum: u64,
     /// The first live record ID in the rollback seglog.
     pub rollback_start_live: u64,
     /// The last live record ID in the rollback seglog.
     pub rollback_end_live: u64,
+    /// The opaque tag attached by the application to the last commit. Empty means no tag.
+    pub commit_tag: Vec<u8>,
+    /// The roots of the most recent commits, from the oldest to the newest.
+    pub root_history: Vec<CommitRoot>,
+    /// The sequence number of the last sync, which unlike `sync_seqn` never wraps around.
+    ///
+    /// 0 means there were no syncs and the DB is empty.
+    pub commit_seqn: u64,
+    /// The root of the trie before the last sync.
+    pub parent_root: Node,
 }
 
 impl Meta {
     pub fn encode_to(&self, buf: &mut [u8]) {
-        assert_eq!(buf.len(), 56);
+        assert_eq!(buf.len(), META_SIZE);
+        assert!(self.commit_tag.len() <= MAX_COMMIT_TAG_LEN);
+        assert!(self.root_history.len() <= MAX_ROOT_HISTORY_LEN);
         buf[0..4].copy_from_slice(&self.ln_freelist_pn.to_le_bytes());
         buf[4..8].copy_from_slice(&self.ln_bump.to_le_bytes());
         buf[8..12].copy_from_slice(&self.bbn_freelist_pn.to_le_bytes());
@@ -48,9 +120,51 @@ impl Meta {
         buf[24..40].copy_from_slice(&self.bitbox_seed);
         buf[40..48].copy_from_slice(&self.rollback_start_live.to_le_bytes());
         buf[48..56].copy_from_slice(&self.rollback_end_live.to_le_bytes());
+        buf[56..58].copy_from_slice(&(self.commit_tag.len() as u16).to_le_bytes());
+        buf[58..58 + self.commit_tag.len()].copy_from_slice(&self.commit_tag);
+        let mut offset = ROOT_HISTORY_OFFSET;
+        buf[offset..offset + 2].copy_from_slice(&(self.root_history.len() as u16).to_le_bytes());
+        offset += 2;
+        for record in &self.root_history {
+            buf[offset..offset + 4].copy_from_slice(&record.seqn.to_le_bytes());
+            buf[offset + 4..offset + ROOT_RECORD_SIZE].copy_from_slice(&record.root);
+            offset += ROOT_RECORD_SIZE;
+        }
+        buf[BUCKET_MAPPING_OFFSET] = self.bitbox_mapping.to_u8();
+        buf[KEY_BINNING_OFFSET] = self.key_binning.bin_bits();
+        buf[FORMAT_OFFSET..FORMAT_OFFSET + 4].copy_from_slice(&FORMAT_MAGIC);
+        buf[FORMAT_OFFSET + 4..FORMAT_OFFSET + 8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buf[COMPRESSION_OFFSET..COMPRESSION_OFFSET + 2]
+            .copy_from_slice(&self.compression.to_bytes());
+        buf[DICTIONARY_OFFSET..DICTIONARY_OFFSET + 4]
+            .copy_from_slice(&self.dictionary_len.to_le_bytes());
+        buf[DICTIONARY_OFFSET + 4..DICTIONARY_OFFSET + 12]
+            .copy_from_slice(&self.dictionary_checksum.to_le_bytes());
+        buf[COMMIT_SEQN_OFFSET..COMMIT_SEQN_OFFSET + 8]
+            .copy_from_slice(&self.commit_seqn.to_le_bytes());
+        buf[PARENT_ROOT_OFFSET..PARENT_ROOT_OFFSET + 32].copy_from_slice(&self.parent_root);
     }
 
-    pub fn decode(buf: &[u8]) -> Self {
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        // Meta files written before the format was versioned lack the magic. They are reported
+        // as version 0.
+        let format_version = if buf[FORMAT_OFFSET..FORMAT_OFFSET + 4] == FORMAT_MAGIC {
+            u32::from_le_bytes(
+                buf[FORMAT_OFFSET + 4..FORMAT_OFFSET + 8]
+                    .try_into()
+                    .unwrap(),
+            )
+        } else {
+            0
+        };
+        if format_version != FORMAT_VERSION {
+            return Err(crate::Error::IncompatibleFormat {
+                found: format_version,
+                supported: FORMAT_VERSION,
+            }
+            .into());
+        }
+
         let ln_freelist_pn = u32::from_le_bytes(buf[0..4].try_into().unwrap());
         let ln_bump = u32::from_le_bytes(buf[4..8].try_into().unwrap());
         let bbn_freelist_pn = u32::from_le_bytes(buf[8..12].try_into().unwrap());
@@ -60,7 +174,60 @@ impl Meta {
         let bitbox_seed = buf[24..40].try_into().unwrap();
         let rollback_start_live = u64::from_le_bytes(buf[40..48].try_into().unwrap());
         let rollback_end_live = u64::from_le_bytes(buf[48..56].try_into().unwrap());
-        Self {
+        // The tag is opaque to NOMT and doesn't affect the integrity of the database, so a corrupt
+        // length is clamped rather than rejected.
+        let commit_tag_len = u16::from_le_bytes(buf[56..58].try_into().unwrap()) as usize;
+        let commit_tag = buf[58..58 + commit_tag_len.min(MAX_COMMIT_TAG_LEN)].to_vec();
+        // Same as with the tag, the history is informational only, so a corrupt length is clamped.
+        let root_history_len = u16::from_le_bytes(
+            buf[ROOT_HISTORY_OFFSET..ROOT_HISTORY_OFFSET + 2]
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        let root_history = buf[ROOT_HISTORY_OFFSET + 2..]
+            .chunks_exact(ROOT_RECORD_SIZE)
+            .take(root_history_len.min(MAX_ROOT_HISTORY_LEN))
+            .map(|record| CommitRoot {
+                seqn: u32::from_le_bytes(record[0..4].try_into().unwrap()),
+                root: record[4..ROOT_RECORD_SIZE].try_into().unwrap(),
+            })
+            .collect();
+        let bitbox_mapping = BucketMappingStrategy::from_u8(buf[BUCKET_MAPPING_OFFSET])
+            .ok_or_else(|| {
+                crate::Error::Corruption(format!(
+                    "unknown bucket mapping strategy: {}",
+                    buf[BUCKET_MAPPING_OFFSET]
+                ))
+            })?;
+        let key_binning = KeyBinning::from_bin_bits(buf[KEY_BINNING_OFFSET]).ok_or_else(|| {
+            crate::Error::Corruption(format!(
+                "invalid key binning: {} bits",
+                buf[KEY_BINNING_OFFSET]
+            ))
+        })?;
+        let compression_bytes = [buf[COMPRESSION_OFFSET], buf[COMPRESSION_OFFSET + 1]];
+        let compression = Compression::from_bytes(compression_bytes).ok_or_else(|| {
+            crate::Error::Corruption(format!("unknown compression: {compression_bytes:?}"))
+        })?;
+        let dictionary_len = u32::from_le_bytes(
+            buf[DICTIONARY_OFFSET..DICTIONARY_OFFSET + 4]
+                .try_into()
+                .unwrap(),
+        );
+        let dictionary_checksum = u64::from_le_bytes(
+            buf[DICTIONARY_OFFSET + 4..DICTIONARY_OFFSET + 12]
+                .try_into()
+                .unwrap(),
+        );
+        let commit_seqn = u64::from_le_bytes(
+            buf[COMMIT_SEQN_OFFSET..COMMIT_SEQN_OFFSET + 8]
+                .try_into()
+                .unwrap(),
+        );
+        let parent_root = buf[PARENT_ROOT_OFFSET..PARENT_ROOT_OFFSET + 32]
+            .try_into()
+            .unwrap();
+        Ok(Self {
             ln_freelist_pn,
             ln_bump,
             bbn_freelist_pn,
@@ -68,9 +235,18 @@ impl Meta {
             sync_seqn,
             bitbox_num_pages,
             bitbox_seed,
+            bitbox_mapping,
+            key_binning,
+            compression,
+            dictionary_len,
+            dictionary_checksum,
             rollback_start_live,
             rollback_end_live,
-        }
+            commit_tag,
+            root_history,
+            commit_seqn,
+            parent_root,
+        })
     }
 
     pub fn validate(&self) -> Result<()> {
@@ -88,22 +264,46 @@ impl Meta {
         if errors.is_empty() {
             Ok(())
         } else {
-            // Collect all the errors and return them in a single anyhow error.
-            Err(anyhow::anyhow!(errors.join("\n")))
+            // Collect all the errors and return them in a single error.
+            Err(crate::Error::Corruption(errors.join("\n")).into())
         }
     }
 
     pub fn read(page_pool: &PagePool, fd: &File) -> Result<Self> {
         let page = io::read_page(page_pool, fd, 0)?;
-        let meta = Meta::decode(&page[..56]);
-        Ok(meta)
+        Meta::decode(&page[..META_SIZE])
+    }
+
+    /// Read the compression dictionary following the meta page, if there is one.
+    pub fn read_dictionary(&self, page_pool: &PagePool, fd: &File) -> Result<Option<Vec<u8>>> {
+        if self.dictionary_len == 0 {
+            return Ok(None);
+        }
+        let len = self.dictionary_len as usize;
+        let mut dictionary = Vec::with_capacity(len.next_multiple_of(PAGE_SIZE));
+        for pn in 1..=len.div_ceil(PAGE_SIZE) {
+            dictionary.extend_from_slice(&io::read_page(page_pool, fd, pn as u64)?[..]);
+        }
+        dictionary.truncate(len);
+        if dictionary_checksum(&dictionary) != self.dictionary_checksum {
+            return Err(crate::Error::Corruption(
+                "compression dictionary checksum mismatch".to_string(),
+            )
+            .into());
+        }
+        Ok(Some(dictionary))
     }
 
     pub fn write(page_pool: &PagePool, fd: &File, meta: &Meta) -> Result<()> {
         let mut page = page_pool.alloc_fat_page();
-        meta.encode_to(&mut page.as_mut()[..56]);
+        meta.encode_to(&mut page.as_mut()[..META_SIZE]);
         fd.write_all_at(&page[..], 0)?;
         fd.sync_all()?;
         Ok(())
     }
 }
+
+/// The checksum of a compression dictionary, stored in the meta.
+pub fn dictionary_checksum(dictionary: &[u8]) -> u64 {
+    xxhash_rust::xxh3::xxh3_64(dictionary)
+}
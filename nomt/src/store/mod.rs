@@ -6,17 +6,26 @@
 use crate::{
     beatree, bitbox,
     io::{self, page_pool::FatPage, IoPool, PagePool},
+    manifest::ChangedPages,
     merkle,
     page_cache::PageCache,
     page_diff::PageDiff,
     rollback::Rollback,
 };
 use meta::Meta;
-use nomt_core::{page_id::PageId, trie::KeyPath};
+use nomt_core::{
+    binning::KeyBinning,
+    page_id::PageId,
+    trie::{KeyPath, Node},
+};
 use parking_lot::Mutex;
 use std::{
     fs::{File, OpenOptions},
-    sync::Arc,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
 #[cfg(target_os = "linux")]
@@ -24,10 +33,21 @@ use std::os::unix::fs::OpenOptionsExt as _;
 
 pub use self::page_loader::{PageLoad, PageLoadCompletion, PageLoader};
 pub use bitbox::BucketIndex;
+pub use inspect::{inspect, DatabaseInfo};
+pub use integrity::{IntegrityIssue, IntegrityLevel, IntegrityReport};
+pub use meta::{CommitRoot, MAX_COMMIT_TAG_LEN, MAX_ROOT_HISTORY_LEN};
+pub use repair::{repair, RepairReport};
+pub use stats::{FileSize, HashTableStats, NodeFileStats, StorageStats};
 
 mod flock;
+mod inspect;
+mod integrity;
 mod meta;
 mod page_loader;
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+mod prepared;
+mod repair;
+mod stats;
 mod sync;
 
 /// This is a lightweight handle and can be cloned cheaply.
@@ -35,31 +55,130 @@ mod sync;
 pub struct Store {
     shared: Arc<Shared>,
     sync: Arc<Mutex<sync::Sync>>,
+    last_commit_tag: Arc<Mutex<Option<Vec<u8>>>>,
 }
 
 struct Shared {
     values: beatree::Tree,
     pages: bitbox::DB,
     rollback: Option<Rollback>,
+    key_binning: KeyBinning,
     page_pool: PagePool,
     io_pool: IoPool,
     meta_fd: File,
-    ln_fd: File,
-    bbn_fd: File,
+    ln_fd: Arc<File>,
+    bbn_fd: Arc<File>,
     ht_fd: File,
-    // keep alive.
-    #[allow(unused)]
-    wal_fd: File,
+    wal_fd: Arc<File>,
+    /// Whether a commit has failed. The in-memory state is then ahead of the files, so the store
+    /// can't be used until it is reopened.
+    failed: AtomicBool,
+    /// Whether the files were opened for reading only.
+    read_only: bool,
+    /// The path to the database directory.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    path: PathBuf,
     #[allow(unused)]
     flock: flock::Flock,
     #[allow(unused)]
     db_dir_fd: File,
 }
 
+impl Shared {
+    fn check_usable(&self) -> anyhow::Result<()> {
+        if self.failed.load(Ordering::Relaxed) {
+            return Err(crate::Error::InvalidOperation(
+                "a previous commit failed, the database must be reopened".to_string(),
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    fn check_writable(&self) -> anyhow::Result<()> {
+        if self.read_only {
+            return Err(crate::Error::InvalidOperation(
+                "the database is open read-only".to_string(),
+            )
+            .into());
+        }
+        self.check_usable()
+    }
+}
+
+/// The file descriptors held for the lifetime of the store: the directory, the lock file, and the
+/// meta, ln, bbn, ht and WAL files.
+const STORE_FDS: usize = 7;
+
+/// The file descriptors additionally held by the rollback log: a handle to the directory and the
+/// segment being appended to.
+const ROLLBACK_FDS: usize = 2;
+
+/// Headroom for file descriptors which are open only briefly, e.g. while creating the database or
+/// reading the rollback log on open.
+const TRANSIENT_FDS: usize = 2;
+
+/// The number of file descriptors required with the given options.
+fn required_fds(o: &crate::Options) -> usize {
+    let mut fds = STORE_FDS + TRANSIENT_FDS;
+    if o.rollback {
+        fds += ROLLBACK_FDS;
+    }
+    // Every I/O worker owns an io_uring instance.
+    if cfg!(target_os = "linux") {
+        fds += o.io_workers;
+    }
+    fds
+}
+
+/// Makes sure that all the required file descriptors can be opened, raising the soft limit of the
+/// process if necessary.
+///
+/// Fails with a descriptive error rather than letting an I/O worker or a file open fail midway.
+fn ensure_fd_limit(o: &crate::Options) -> anyhow::Result<()> {
+    let required = required_fds(o);
+    if let Some(max_open_files) = o.max_open_files {
+        if required > max_open_files {
+            return Err(crate::Error::InvalidOperation(format!(
+                "{required} file descriptors are required, but at most {max_open_files} are \
+                 allowed; consider reducing the number of I/O workers"
+            ))
+            .into());
+        }
+    }
+
+    let (soft, hard) = crate::sys::unix::open_files_limit()?;
+    let in_use = crate::sys::unix::open_files_count().unwrap_or(0);
+    let needed = in_use.saturating_add(required as u64);
+    if needed <= soft {
+        return Ok(());
+    }
+    if needed <= hard {
+        crate::sys::unix::set_open_files_soft_limit(needed, hard)?;
+        return Ok(());
+    }
+    Err(crate::Error::InvalidOperation(format!(
+        "{required} file descriptors are required, but only {} are available under the process \
+         limit of {hard}; raise the limit with `ulimit -n`",
+        hard.saturating_sub(in_use),
+    ))
+    .into())
+}
+
 impl Store {
     /// Open the store with the provided `Options`.
     pub fn open(o: &crate::Options, page_pool: PagePool) -> anyhow::Result<Self> {
-        if !o.path.exists() {
+        ensure_fd_limit(o)?;
+
+        // The meta file is written last on creation and removed first on a wipe, so its absence
+        // means the database is uninitialized.
+        if !o.path.join("meta").exists() {
+            if o.read_only {
+                return Err(crate::Error::InvalidOperation(
+                    "no database to open read-only".to_string(),
+                )
+                .into());
+            }
             create(o)?;
         }
 
@@ -68,13 +187,17 @@ impl Store {
             options.read(true);
             options.open(&o.path)?
         };
-        let flock = flock::Flock::lock(&o.path, ".lock")?;
+        let flock = if o.read_only {
+            flock::Flock::lock_shared(&o.path, ".lock")?
+        } else {
+            flock::Flock::lock(&o.path, ".lock")?
+        };
 
         let io_pool = io::start_io_pool(o.io_workers, page_pool.clone());
 
         let meta_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!o.read_only);
             #[cfg(target_os = "linux")]
             options.custom_flags(libc::O_DIRECT);
             options.open(&o.path.join("meta"))?
@@ -82,31 +205,31 @@ impl Store {
 
         let ln_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!o.read_only);
             #[cfg(target_os = "linux")]
             options.custom_flags(libc::O_DIRECT);
-            options.open(&o.path.join("ln"))?
+            Arc::new(options.open(&o.path.join("ln"))?)
         };
         let bbn_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!o.read_only);
             #[cfg(target_os = "linux")]
             options.custom_flags(libc::O_DIRECT);
-            options.open(&o.path.join("bbn"))?
+            Arc::new(options.open(&o.path.join("bbn"))?)
         };
         let ht_fd = {
             let mut options = OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!o.read_only);
             #[cfg(target_os = "linux")]
             options.custom_flags(libc::O_DIRECT);
             options.open(&o.path.join("ht"))?
         };
         let wal_fd = {
             let options = &mut OpenOptions::new();
-            options.read(true).write(true);
+            options.read(true).write(!o.read_only);
             #[cfg(target_os = "linux")]
             options.custom_flags(libc::O_DIRECT);
-            options.open(&o.path.join("wal"))?
+            Arc::new(options.open(&o.path.join("wal"))?)
         };
 
         #[cfg(target_os = "macos")]
@@ -121,8 +244,51 @@ impl Store {
             }
         }
 
-        let meta = meta::Meta::read(&page_pool, &meta_fd)?;
+        let mut meta = meta::Meta::read(&page_pool, &meta_fd)?;
         meta.validate()?;
+        match prepared::read(&o.path)? {
+            // A commit prepared after the last sync is in doubt. The WAL is only replayed and the
+            // b-tree pages are only referred to if the meta of the commit is written.
+            Some(prepared) if prepared.meta.sync_seqn == meta.sync_seqn.wrapping_add(1) => {
+                let outcome = if o.read_only {
+                    crate::options::InDoubtCommit::Fail
+                } else {
+                    o.in_doubt_commit
+                };
+                match outcome {
+                    crate::options::InDoubtCommit::Fail => {
+                        return Err(crate::Error::InDoubtCommit {
+                            root: prepared.root,
+                            commit_tag: prepared.meta.commit_tag,
+                        }
+                        .into())
+                    }
+                    crate::options::InDoubtCommit::Commit => {
+                        meta::Meta::write(&page_pool, &meta_fd, &prepared.meta)?;
+                        meta = prepared.meta;
+                    }
+                    crate::options::InDoubtCommit::Abort => {}
+                }
+                prepared::remove(&o.path, &db_dir_fd)?;
+            }
+            // The commit was completed or aborted.
+            Some(_) if !o.read_only => prepared::remove(&o.path, &db_dir_fd)?,
+            Some(_) => {}
+            None => {}
+        }
+        let dictionary = meta.read_dictionary(&page_pool, &meta_fd)?;
+        if o.read_only && wal_fd.metadata()?.len() > 0 {
+            return Err(crate::Error::InvalidOperation(
+                "the WAL has to be replayed, the database can't be opened read-only".to_string(),
+            )
+            .into());
+        }
+
+        // The bulk of the work on open is replaying the WAL and reading the BBN file.
+        let recovery_concurrency = recovery_concurrency(
+            o.max_recovery_time_hint,
+            wal_fd.metadata()?.len() + bbn_fd.metadata()?.len(),
+        );
         let values = beatree::Tree::open(
             page_pool.clone(),
             &io_pool,
@@ -132,15 +298,35 @@ impl Store {
             meta.bbn_bump,
             &bbn_fd,
             &ln_fd,
-            o.commit_concurrency,
+            beatree::TreeOptions {
+                commit_workers: beatree::CommitWorkers {
+                    max: o.commit_concurrency,
+                    adaptive: o.adaptive_commit_concurrency,
+                },
+                recovery_concurrency,
+                verify_checksums: o.verify_checksums,
+                snapshot_horizon: o.snapshot_horizon,
+                hole_punch_run: o.hole_punch_run,
+                codec: beatree::compression::Codec::new(meta.compression, dictionary.as_deref()),
+            },
         )?;
         let pages = bitbox::DB::open(
-            meta.bitbox_num_pages,
-            meta.bitbox_seed,
+            bitbox::DbOptions {
+                num_pages: meta.bitbox_num_pages,
+                seed: meta.bitbox_seed,
+                mapping: meta.bitbox_mapping,
+                sync_seqn: meta.sync_seqn,
+                recovery_concurrency,
+                verify_checksums: o.verify_checksums,
+                compress_wal: o.compress_wal,
+                on_recovery_progress: o.on_recovery_progress.clone(),
+                presence_filter: o.presence_filter,
+            },
             &page_pool,
             &ht_fd,
             &wal_fd,
         )?;
+        let last_commit_tag = Some(meta.commit_tag.clone()).filter(|tag| !tag.is_empty());
         let rollback = o
             .rollback
             .then(|| {
@@ -158,6 +344,7 @@ impl Store {
         Ok(Self {
             shared: Arc::new(Shared {
                 rollback,
+                key_binning: meta.key_binning,
                 page_pool,
                 values,
                 pages,
@@ -168,25 +355,216 @@ impl Store {
                 bbn_fd,
                 ht_fd,
                 wal_fd,
+                failed: AtomicBool::new(false),
+                read_only: o.read_only,
+                path: o.path.clone(),
                 flock,
             }),
             sync: Arc::new(Mutex::new(sync::Sync::new(
-                meta.sync_seqn,
-                meta.bitbox_num_pages,
-                meta.bitbox_seed,
-                o.panic_on_sync,
+                &meta,
+                o.crash_point,
+                o.root_history_len,
             ))),
+            last_commit_tag: Arc::new(Mutex::new(last_commit_tag)),
         })
     }
 
+    /// Waits for an in-flight sync, if any, to finish and then flushes all files to disk.
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let _sync = self.sync.lock();
+        self.shared.meta_fd.sync_all()?;
+        self.shared.ln_fd.sync_all()?;
+        self.shared.bbn_fd.sync_all()?;
+        self.shared.ht_fd.sync_all()?;
+        self.shared.wal_fd.sync_all()?;
+        self.shared.db_dir_fd.sync_all()?;
+        Ok(())
+    }
+
+    /// Wipes the database by removing the meta file, which is the point of no return.
+    ///
+    /// The remaining files are removed when the store is opened the next time.
+    pub fn wipe(&self, path: &Path) -> anyhow::Result<()> {
+        let _sync = self.sync.lock();
+        if self.shared.read_only {
+            return Err(crate::Error::InvalidOperation(
+                "the database is open read-only".to_string(),
+            )
+            .into());
+        }
+        std::fs::remove_file(path.join("meta"))?;
+        self.shared.db_dir_fd.sync_all()?;
+        Ok(())
+    }
+
     /// Returns a handle to the rollback object. `None` if the rollback feature is not enabled.
     pub fn rollback(&self) -> Option<&Rollback> {
         self.shared.rollback.as_ref()
     }
 
+    /// Waits for an in-flight sync, if any, to finish and then collects statistics about the files.
+    pub fn stats(&self) -> anyhow::Result<StorageStats> {
+        let _sync = self.sync.lock();
+        let (occupied_buckets, buckets) = self.shared.pages.bucket_counts();
+        let (placements, placement_probes, longest_placement_probe) =
+            self.shared.pages.placement_probes();
+        let (ln_stats, bbn_stats) = self.shared.values.store_stats();
+        Ok(StorageStats {
+            meta: FileSize::of(&self.shared.meta_fd)?,
+            ht: HashTableStats {
+                file: FileSize::of(&self.shared.ht_fd)?,
+                buckets,
+                occupied_buckets,
+                placements,
+                placement_probes,
+                longest_placement_probe,
+            },
+            wal: FileSize::of(&self.shared.wal_fd)?,
+            ln: NodeFileStats::new(FileSize::of(&self.shared.ln_fd)?, ln_stats),
+            bbn: NodeFileStats::new(FileSize::of(&self.shared.bbn_fd)?, bbn_stats),
+        })
+    }
+
+    /// Waits for an in-flight sync, if any, to finish and then checks the structure of the
+    /// hash-table and the b-tree.
+    ///
+    /// Commits wait for the check to finish.
+    pub fn check_integrity(&self) -> anyhow::Result<IntegrityReport> {
+        let _sync = self.sync.lock();
+        let mut report = IntegrityReport::default();
+        self.shared.pages.check_integrity(
+            &self.shared.page_pool,
+            &self.shared.ht_fd,
+            &mut report,
+        )?;
+        self.shared.values.check_integrity(&mut report)?;
+        Ok(report)
+    }
+
+    /// Waits for an in-flight sync, if any, to finish and then reads the given pages as they are
+    /// on disk, followed by the meta page. Each page is passed to `f` along with the name of its
+    /// file and its page number.
+    pub fn read_pages(
+        &self,
+        pages: &ChangedPages,
+        mut f: impl FnMut(&'static str, u64, &[u8]),
+    ) -> std::io::Result<()> {
+        let _sync = self.sync.lock();
+        let page_pool = &self.shared.page_pool;
+        for &pn in &pages.ht_pages {
+            f("ht", pn, &io::read_page(page_pool, &self.shared.ht_fd, pn)?);
+        }
+        for &pn in &pages.ln_pages {
+            let pn = pn as u64;
+            f("ln", pn, &io::read_page(page_pool, &self.shared.ln_fd, pn)?);
+        }
+        for &pn in &pages.bbn_pages {
+            let pn = pn as u64;
+            f(
+                "bbn",
+                pn,
+                &io::read_page(page_pool, &self.shared.bbn_fd, pn)?,
+            );
+        }
+        f(
+            "meta",
+            0,
+            &io::read_page(page_pool, &self.shared.meta_fd, 0)?,
+        );
+        Ok(())
+    }
+
+    /// Records the root of the trie as of opening the store. The roots of commits are recorded
+    /// by [`Self::commit`].
+    pub fn set_root(&self, root: Node) {
+        let mut sync = self.sync.lock();
+        sync.root = root;
+        sync.synced_root = root;
+    }
+
+    /// Takes a snapshot of the values, along with the root of the trie they correspond to and
+    /// whether a commit has failed.
+    ///
+    /// Waits for an in-flight commit to finish.
+    pub fn snapshot(&self) -> (Node, beatree::Snapshot, bool) {
+        let sync = self.sync.lock();
+        let failed = self.shared.failed.load(Ordering::Relaxed);
+        (sync.root, self.shared.values.snapshot(), failed)
+    }
+
+    /// Fails with [`crate::Error::InvalidOperation`] if a commit has failed. The state in memory
+    /// then holds the changes of the failed commit, which were not written out, so nothing may
+    /// be read until the database is reopened.
+    pub fn check_usable(&self) -> anyhow::Result<()> {
+        self.shared.check_usable()
+    }
+
+    /// Like [`Self::check_usable`], but also fails if the store was opened read-only.
+    pub fn check_writable(&self) -> anyhow::Result<()> {
+        self.shared.check_writable()
+    }
+
+    /// Fail to grow the WAL blob past `size` bytes, see [`bitbox::DB::limit_wal_blob`].
+    #[cfg(test)]
+    pub fn limit_wal_blob(&self, size: usize) {
+        self.shared.pages.limit_wal_blob(size);
+    }
+
+    /// Returns the roots of the most recent commits, from the oldest to the newest.
+    pub fn recent_roots(&self) -> Vec<CommitRoot> {
+        self.sync.lock().root_history.iter().copied().collect()
+    }
+
+    /// Returns the binning the database was created with.
+    pub fn key_binning(&self) -> KeyBinning {
+        self.shared.key_binning
+    }
+
+    /// Returns the summary of replaying the WAL on open, if it was replayed.
+    #[cfg(feature = "unstable")]
+    pub fn recovery_report(&self) -> Option<bitbox::RecoveryReport> {
+        self.shared.pages.recovery_report()
+    }
+
+    /// Returns the tag attached to the last commit, if any.
+    pub fn last_commit_tag(&self) -> Option<Vec<u8>> {
+        self.last_commit_tag.lock().clone()
+    }
+
+    /// Returns the sequence number of the last commit, see [`Meta::commit_seqn`].
+    pub fn commit_seqn(&self) -> u64 {
+        self.sync.lock().commit_seqn
+    }
+
     /// Loads the flat value stored under the given key.
-    pub fn load_value(&self, key: KeyPath) -> anyhow::Result<Option<Vec<u8>>> {
-        Ok(self.shared.values.lookup(key))
+    pub fn load_value(&self, key: KeyPath) -> anyhow::Result<Option<beatree::ValueRef>> {
+        self.check_usable()?;
+        self.shared.values.lookup(key)
+    }
+
+    /// Checks whether a value is stored under the given key, without loading the value.
+    pub fn contains_value(&self, key: KeyPath) -> anyhow::Result<bool> {
+        self.check_usable()?;
+        self.shared.values.contains(key)
+    }
+
+    /// Returns the size of the value stored under the given key, without loading the value.
+    pub fn value_size(&self, key: KeyPath) -> anyhow::Result<Option<usize>> {
+        self.check_usable()?;
+        self.shared.values.value_size(key)
+    }
+
+    /// Extends the leaf node file up front to fit `pages` overflow pages written by the next
+    /// commit, so that the commit doesn't have to.
+    pub fn reserve_overflow_pages(&self, pages: usize) -> anyhow::Result<()> {
+        self.check_writable()?;
+        self.shared.values.reserve_leaf_pages(pages)
+    }
+
+    /// Returns the keys with a value stored within the inclusive range `start..=end`, in order.
+    pub fn keys_in_range(&self, start: KeyPath, end: KeyPath) -> anyhow::Result<Vec<KeyPath>> {
+        self.check_usable()?;
+        Ok(self.shared.values.keys_in_range(start, end))
     }
 
     /// Loads the given page, blocking the current thread.
@@ -225,41 +603,127 @@ impl Store {
         ValueTransaction { batch: Vec::new() }
     }
 
+    /// Make the values of the transaction visible ahead of the commit which writes them out, as
+    /// of the given root.
+    pub fn stage_values(&self, tx: ValueTransaction, root: Node) {
+        let mut sync = self.sync.lock();
+        self.shared.values.commit(tx.batch);
+        sync.root = root;
+    }
+
     /// Atomically apply the given transaction.
     ///
     /// After this function returns, accessor methods such as [`Self::load_page`] will return the
     /// updated values.
+    ///
+    /// The commit tag is persisted atomically along with the changes, and so is the new root in
+    /// the root history.
+    ///
+    /// Returns the pages written by the commit. If the commit fails, the files are left at the
+    /// previous commit while the in-memory state is not, so all later commits and reads fail, see
+    /// [`Self::check_usable`].
     pub fn commit(
         &self,
         value_tx: ValueTransaction,
         page_cache: PageCache,
         page_diffs: merkle::PageDiffs,
-    ) -> anyhow::Result<()> {
+        commit_tag: Option<Vec<u8>>,
+        root: Node,
+    ) -> anyhow::Result<ChangedPages> {
         let mut sync = self.sync.lock();
+        self.check_writable()?;
+
+        let changed_pages = sync
+            .sync(
+                &self.shared,
+                value_tx,
+                page_cache,
+                page_diffs,
+                commit_tag.clone().unwrap_or_default(),
+                root,
+            )
+            .inspect_err(|_| self.shared.failed.store(true, Ordering::Relaxed))?;
+        *self.last_commit_tag.lock() = commit_tag;
+        Ok(changed_pages)
+    }
 
-        sync.sync(
-            &self.shared,
-            value_tx,
-            self.shared.pages.clone(),
-            self.shared.values.clone(),
-            self.shared.rollback.clone(),
-            page_cache,
-            page_diffs,
-        )
-        .unwrap();
-        Ok(())
+    /// Write out the given transaction up to the meta and record it as prepared, see
+    /// [`prepared`]. It takes effect on disk once completed with [`Self::commit_prepared`].
+    ///
+    /// The in-memory state reflects the transaction from now on, so if it fails or is aborted, all
+    /// later commits and reads fail as after a failed [`Self::commit`].
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn prepare_commit(
+        &self,
+        value_tx: ValueTransaction,
+        page_cache: PageCache,
+        page_diffs: merkle::PageDiffs,
+        commit_tag: Option<Vec<u8>>,
+        root: Node,
+    ) -> anyhow::Result<PreparedCommit> {
+        let mut sync = self.sync.lock();
+        self.check_writable()?;
+
+        let prepare = || {
+            let sync = sync.prepare(
+                &self.shared,
+                value_tx,
+                page_cache,
+                page_diffs,
+                commit_tag.clone().unwrap_or_default(),
+                root,
+            )?;
+            let record = prepared::Prepared {
+                meta: sync.meta.clone(),
+                root,
+            };
+            prepared::write(&self.shared.path, &self.shared.db_dir_fd, &record)?;
+            Ok(PreparedCommit { sync, commit_tag })
+        };
+        prepare().inspect_err(|_| self.shared.failed.store(true, Ordering::Relaxed))
+    }
+
+    /// Complete a prepared transaction, see [`Self::prepare_commit`].
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn commit_prepared(&self, prepared: PreparedCommit) -> anyhow::Result<ChangedPages> {
+        let mut sync = self.sync.lock();
+        let changed_pages = sync
+            .finish(&self.shared, prepared.sync)
+            .inspect_err(|_| self.shared.failed.store(true, Ordering::Relaxed))?;
+        // The commit took effect with the meta. A record left behind is recognized as stale on
+        // open, so failing to remove it is harmless.
+        let _ = prepared::remove(&self.shared.path, &self.shared.db_dir_fd);
+        *self.last_commit_tag.lock() = prepared.commit_tag;
+        Ok(changed_pages)
     }
+
+    /// Abort a prepared transaction, see [`Self::prepare_commit`]. The files are left at the
+    /// previous commit and the store can't be used until it is reopened.
+    #[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+    pub fn abort_prepared(&self, prepared: PreparedCommit) -> anyhow::Result<()> {
+        let _sync = self.sync.lock();
+        self.shared.failed.store(true, Ordering::Relaxed);
+        drop(prepared);
+        prepared::remove(&self.shared.path, &self.shared.db_dir_fd)
+    }
+}
+
+/// A transaction written out up to the meta, returned by [`Store::prepare_commit`].
+#[cfg_attr(not(feature = "unstable"), allow(dead_code))]
+pub struct PreparedCommit {
+    sync: sync::PreparedSync,
+    commit_tag: Option<Vec<u8>>,
 }
 
 /// An atomic transaction on raw key/value pairs to be applied against the store
 /// with [`Store::commit`].
 pub struct ValueTransaction {
-    batch: Vec<(KeyPath, Option<Vec<u8>>)>,
+    batch: Vec<(KeyPath, Option<crate::Value>)>,
 }
 
 impl ValueTransaction {
     /// Write a value to flat storage.
-    pub fn write_value(&mut self, path: KeyPath, value: Option<Vec<u8>>) {
+    pub fn write_value(&mut self, path: KeyPath, value: Option<crate::Value>) {
         self.batch.push((path, value))
     }
 }
@@ -302,14 +766,119 @@ impl MerkleTransaction {
     }
 }
 
+/// The number of bytes of on-disk state a single thread is assumed to process per second during
+/// recovery. This is a conservative estimate which is meant to be good enough for SSDs.
+const RECOVERY_BYTES_PER_SEC_PER_THREAD: u64 = 256 * 1024 * 1024;
+
+/// Pick the number of threads to use for recovery so that processing `work_bytes` of on-disk
+/// state fits within the given time hint, if any.
+fn recovery_concurrency(hint: Option<std::time::Duration>, work_bytes: u64) -> usize {
+    let Some(hint) = hint else {
+        return 1;
+    };
+    let max_concurrency = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let single_thread_secs = work_bytes as f64 / RECOVERY_BYTES_PER_SEC_PER_THREAD as f64;
+    let needed = (single_thread_secs / hint.as_secs_f64().max(f64::EPSILON)).ceil() as usize;
+    needed.clamp(1, max_concurrency)
+}
+
+/// Removes the files of the hash-table, the WAL and the b-tree from the database directory, along
+/// with the files they link to if they were placed in other directories.
+pub fn remove_store_files(db_dir: &Path) -> std::io::Result<()> {
+    for name in ["ht", "wal", "ln", "bbn"] {
+        let path = db_dir.join(name);
+        let is_link = match path.symlink_metadata() {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if is_link {
+            match std::fs::remove_file(std::fs::read_link(&path)?) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
 fn create(o: &crate::Options) -> anyhow::Result<()> {
     use std::io::Write as _;
 
+    if o.compression_dictionary.is_some()
+        && !matches!(o.compression, beatree::Compression::Zstd { .. })
+    {
+        return Err(crate::Error::InvalidOperation(
+            "a compression dictionary requires zstd compression".to_string(),
+        )
+        .into());
+    }
+
     // Create the directory and its parent directories.
     std::fs::create_dir_all(&o.path)?;
 
-    let mut meta_fd = std::fs::File::create(o.path.join("meta"))?;
-    let mut buf = [0u8; 4096];
+    // Remove whatever is left over from a wiped database.
+    remove_store_files(&o.path)?;
+    for entry in std::fs::read_dir(&o.path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("rollback") || name.starts_with(crate::checkpoint::CHECKPOINTS_FILE) {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+
+    // Files placed in other directories are linked from the database directory, so that they are
+    // found there whatever the options the database is opened with. The links are made before the
+    // files, so that files left over by a crash are removed along with them on the next attempt.
+    let db_dir = std::fs::canonicalize(&o.path)?;
+    let place = |dir: &Option<PathBuf>| -> std::io::Result<PathBuf> {
+        match dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)?;
+                std::fs::canonicalize(dir)
+            }
+            None => Ok(db_dir.clone()),
+        }
+    };
+    let ht_dir = place(&o.ht_dir)?;
+    let wal_dir = place(&o.wal_dir)?;
+    let beatree_dir = place(&o.beatree_dir)?;
+    for (name, dir) in [
+        ("ht", &ht_dir),
+        ("wal", &wal_dir),
+        ("ln", &beatree_dir),
+        ("bbn", &beatree_dir),
+    ] {
+        if *dir == db_dir {
+            continue;
+        }
+        let target = dir.join(name);
+        if target.symlink_metadata().is_ok() {
+            return Err(crate::Error::InvalidOperation(format!(
+                "{} exists already, it may belong to another database",
+                target.display()
+            ))
+            .into());
+        }
+        std::os::unix::fs::symlink(target, o.path.join(name))?;
+    }
+    bitbox::create(&ht_dir, &wal_dir, o.bitbox_num_pages, o.preallocate_ht)?;
+    beatree::create(&beatree_dir)?;
+    for dir in [&ht_dir, &wal_dir, &beatree_dir] {
+        if *dir != db_dir {
+            std::fs::File::open(dir)?.sync_all()?;
+        }
+    }
+
+    // The meta file is written last and moved into place atomically, so that a crash while
+    // creating the database leaves it uninitialized rather than half-initialized.
+    let mut meta_fd = std::fs::File::create(o.path.join("meta.tmp"))?;
+    let dictionary = o.compression_dictionary.as_deref().unwrap_or_default();
+    let mut buf = vec![0u8; io::PAGE_SIZE + dictionary.len().next_multiple_of(io::PAGE_SIZE)];
     Meta {
         ln_freelist_pn: 0,
         ln_bump: 1,
@@ -318,16 +887,24 @@ fn create(o: &crate::Options) -> anyhow::Result<()> {
         sync_seqn: 0,
         bitbox_num_pages: o.bitbox_num_pages,
         bitbox_seed: o.bitbox_seed,
+        bitbox_mapping: o.bitbox_mapping,
+        key_binning: o.key_binning,
+        compression: o.compression,
+        dictionary_len: dictionary.len() as u32,
+        dictionary_checksum: meta::dictionary_checksum(dictionary),
         rollback_start_live: 0,
         rollback_end_live: 0,
+        commit_tag: Vec::new(),
+        root_history: Vec::new(),
+        commit_seqn: 0,
+        parent_root: nomt_core::trie::TERMINATOR,
     }
-    .encode_to(&mut buf[0..56]);
+    .encode_to(&mut buf[0..meta::META_SIZE]);
+    buf[io::PAGE_SIZE..io::PAGE_SIZE + dictionary.len()].copy_from_slice(dictionary);
     meta_fd.write_all(&buf)?;
     meta_fd.sync_all()?;
     drop(meta_fd);
-
-    bitbox::create(o.path.clone(), o.bitbox_num_pages, o.preallocate_ht)?;
-    beatree::create(&o.path)?;
+    std::fs::rename(o.path.join("meta.tmp"), o.path.join("meta"))?;
 
     // As the last step, sync the directory.
     std::fs::File::open(&o.path)?.sync_all()?;
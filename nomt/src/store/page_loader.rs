@@ -19,13 +19,14 @@ impl PageLoader {
     }
 
     /// Advance the state of the given page load, blocking the current thread.
-    /// Fails if the I/O pool is down.
+    /// Fails if the I/O pool is down or a commit has failed.
     ///
     /// Panics if the page load needs a completion.
     ///
     /// This returns `Ok(true)` if the page request has been submitted and a completion will be
     /// coming. `Ok(false)` means that the page is guaranteed to be fresh.
     pub fn advance(&self, load: &mut PageLoad, user_data: u64) -> anyhow::Result<bool> {
+        self.shared.check_usable()?;
         self.inner
             .advance(self.shared.ht_fd.as_raw_fd(), load, user_data)
     }
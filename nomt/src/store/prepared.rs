@@ -0,0 +1,69 @@
+//! The record of a commit prepared for a two-phase commit, see [`crate::Nomt::prepare`].
+//!
+//! Preparing a commit writes out everything but the meta, and then records the meta which
+//! completes it, along with the root after the commit, in the `prepared` file. Completing the
+//! commit writes the meta and removes the file. If the process stops in between, the file is
+//! found on open: the commit is in doubt, and can still be completed from the recorded meta.
+//!
+//! The file is written under a temporary name and renamed into place, so it is either whole or
+//! absent.
+
+use anyhow::Result;
+use std::{fs::File, io::ErrorKind, path::Path};
+
+use super::meta::{Meta, META_SIZE};
+use nomt_core::trie::Node;
+
+const FILE_NAME: &str = "prepared";
+const TMP_FILE_NAME: &str = "prepared.tmp";
+
+/// A commit written out up to the meta.
+pub struct Prepared {
+    /// The meta which completes the commit.
+    pub meta: Meta,
+    /// The root of the trie after the commit.
+    pub root: Node,
+}
+
+/// Record the prepared commit.
+pub fn write(db_dir: &Path, db_dir_fd: &File, prepared: &Prepared) -> Result<()> {
+    let mut buf = vec![0; META_SIZE + 32];
+    prepared.meta.encode_to(&mut buf[..META_SIZE]);
+    buf[META_SIZE..].copy_from_slice(&prepared.root);
+
+    let tmp_path = db_dir.join(TMP_FILE_NAME);
+    let file = File::create(&tmp_path)?;
+    std::io::Write::write_all(&mut &file, &buf)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, db_dir.join(FILE_NAME))?;
+    db_dir_fd.sync_all()?;
+    Ok(())
+}
+
+/// Read the prepared commit, if any.
+pub fn read(db_dir: &Path) -> Result<Option<Prepared>> {
+    let buf = match std::fs::read(db_dir.join(FILE_NAME)) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if buf.len() != META_SIZE + 32 {
+        return Err(crate::Error::Corruption("the prepared commit is damaged".to_string()).into());
+    }
+    let meta = Meta::decode(&buf[..META_SIZE])?;
+    meta.validate()?;
+    // UNWRAP: the length was checked above.
+    let root = buf[META_SIZE..].try_into().unwrap();
+    Ok(Some(Prepared { meta, root }))
+}
+
+/// Remove the record of the prepared commit, if any.
+pub fn remove(db_dir: &Path, db_dir_fd: &File) -> Result<()> {
+    match std::fs::remove_file(db_dir.join(FILE_NAME)) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    db_dir_fd.sync_all()?;
+    Ok(())
+}
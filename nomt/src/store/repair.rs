@@ -0,0 +1,87 @@
+//! Repairing the b-tree metadata of a database which is not open.
+
+use super::{flock::Flock, integrity::IntegrityIssue, meta::Meta};
+use crate::{beatree, io::PagePool};
+use std::{fs::OpenOptions, path::Path};
+
+/// The outcome of [`Nomt::repair`](crate::Nomt::repair).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// The pages which were dropped because they are damaged or inconsistent with the rest of the
+    /// database, in the order they were found. The values stored in the leaves of a dropped branch
+    /// node are lost.
+    pub dropped: Vec<IntegrityIssue>,
+    /// The number of branch nodes kept.
+    pub branches: usize,
+    /// The number of leaf nodes kept.
+    pub leaves: usize,
+    /// The number of pages of the leaf node file tracked by the rebuilt free-list.
+    pub free_ln_pages: usize,
+    /// The number of pages of the branch node file tracked by the rebuilt free-list.
+    pub free_bbn_pages: usize,
+}
+
+impl RepairReport {
+    pub(crate) fn drop_page(
+        &mut self,
+        file: &'static str,
+        page_number: u64,
+        description: impl Into<String>,
+    ) {
+        self.dropped.push(IntegrityIssue::Page {
+            file,
+            page_number,
+            description: description.into(),
+        });
+    }
+}
+
+/// Rebuilds the free-lists and the bumps of the node files of the database at the given path and
+/// records them in the meta file. See [`beatree::ops::repair`].
+///
+/// Returns `None` if there is no initialized database at the path.
+pub fn repair(path: &Path) -> anyhow::Result<Option<RepairReport>> {
+    // See `Store::open`.
+    if !path.join("meta").exists() {
+        return Ok(None);
+    }
+    let _flock = Flock::lock(path, ".lock")?;
+
+    let open = |name: &str| {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path.join(name))
+    };
+    let meta_fd = open("meta")?;
+    let ln_fd = open("ln")?;
+    let bbn_fd = open("bbn")?;
+
+    let page_pool = PagePool::with_capacity(None, None);
+    let meta = Meta::read(&page_pool, &meta_fd)?;
+    meta.validate()?;
+
+    let mut report = RepairReport::default();
+    let repaired = beatree::ops::repair(
+        &page_pool,
+        &ln_fd,
+        &bbn_fd,
+        beatree::ops::TreeMeta {
+            ln_freelist_pn: meta.ln_freelist_pn,
+            ln_bump: meta.ln_bump,
+            bbn_freelist_pn: meta.bbn_freelist_pn,
+            bbn_bump: meta.bbn_bump,
+        },
+        &mut report,
+    )?;
+
+    let meta = Meta {
+        ln_freelist_pn: repaired.ln_freelist_pn,
+        ln_bump: repaired.ln_bump,
+        bbn_freelist_pn: repaired.bbn_freelist_pn,
+        bbn_bump: repaired.bbn_bump,
+        ..meta
+    };
+    Meta::write(&page_pool, &meta_fd, &meta)?;
+    Ok(Some(report))
+}
@@ -0,0 +1,119 @@
+//! Statistics about the on-disk storage.
+
+use crate::{beatree::allocator::StoreStats, io::PAGE_SIZE};
+use std::{fs::File, os::unix::fs::MetadataExt as _};
+
+/// The size of a database file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileSize {
+    /// The length of the file in bytes.
+    pub len: u64,
+    /// The number of bytes actually allocated on disk. This is less than `len` for sparse files.
+    pub allocated: u64,
+}
+
+impl FileSize {
+    pub(super) fn of(file: &File) -> std::io::Result<Self> {
+        let metadata = file.metadata()?;
+        Ok(Self {
+            len: metadata.len(),
+            // `st_blocks` is always counted in 512-byte units.
+            allocated: metadata.blocks() * 512,
+        })
+    }
+}
+
+/// The number of placements below which [`HashTableStats::probe_anomaly`] doesn't tell.
+const PROBE_ANOMALY_MIN_PLACEMENTS: u64 = 1000;
+
+/// How many times longer than expected the average probe sequence has to be to be an anomaly.
+const PROBE_ANOMALY_FACTOR: f64 = 4.0;
+
+/// Statistics about the hash-table file storing the trie pages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashTableStats {
+    /// The size of the file.
+    pub file: FileSize,
+    /// The total number of buckets.
+    pub buckets: usize,
+    /// The number of buckets holding a page.
+    pub occupied_buckets: usize,
+    /// The number of pages placed into buckets since the database was opened.
+    pub placements: u64,
+    /// The total number of buckets probed to place those pages.
+    pub placement_probes: u64,
+    /// The most buckets probed to place a single page since the database was opened.
+    pub longest_placement_probe: u64,
+}
+
+impl HashTableStats {
+    /// Whether placing pages has probed far more buckets than the load of the table explains.
+    ///
+    /// With pages spread evenly, placing a page probes about `1 / (1 - load)` buckets on average.
+    /// An average several times that, over enough placements, means that the pages pile up on
+    /// some buckets, e.g. because the key paths were chosen to collide under the hash seed. Export
+    /// the database and import it into one created with a new [`crate::Options::bitbox_seed`] to
+    /// spread the pages again.
+    pub fn probe_anomaly(&self) -> bool {
+        if self.placements < PROBE_ANOMALY_MIN_PLACEMENTS || self.buckets == 0 {
+            return false;
+        }
+        let load = (self.occupied_buckets as f64 / self.buckets as f64).min(0.99);
+        let expected = 1.0 / (1.0 - load);
+        let mean = self.placement_probes as f64 / self.placements as f64;
+        mean > PROBE_ANOMALY_FACTOR * expected
+    }
+}
+
+/// Statistics about a file storing b-tree nodes, i.e. leaf nodes or bottom-level branch nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeFileStats {
+    /// The size of the file.
+    pub file: FileSize,
+    /// The page number at which the file grows once the free-list is exhausted.
+    pub bump: u32,
+    /// The number of pages holding live data.
+    pub live_pages: usize,
+    /// The number of free pages which are tracked by the free-list and will be reused.
+    pub free_pages: usize,
+    /// The number of pages storing the free-list itself.
+    pub free_list_pages: usize,
+    /// The number of freed pages retained for snapshots, which will be reused once these are
+    /// dropped or expire.
+    pub retained_pages: usize,
+    /// The number of bytes of retained pages which were returned to the free-list since the
+    /// database was opened.
+    pub reclaimed_bytes: u64,
+}
+
+impl NodeFileStats {
+    pub(super) fn new(file: FileSize, stats: StoreStats) -> Self {
+        // Page 0 is reserved and never allocated.
+        let allocated_pages = stats.bump.0.saturating_sub(1) as usize;
+        Self {
+            file,
+            bump: stats.bump.0,
+            live_pages: allocated_pages
+                .saturating_sub(stats.free_pages + stats.free_list_pages + stats.retained_pages),
+            free_pages: stats.free_pages,
+            free_list_pages: stats.free_list_pages,
+            retained_pages: stats.retained_pages,
+            reclaimed_bytes: stats.reclaimed_pages * PAGE_SIZE as u64,
+        }
+    }
+}
+
+/// Statistics about the on-disk storage of the database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StorageStats {
+    /// The meta file.
+    pub meta: FileSize,
+    /// The hash-table file.
+    pub ht: HashTableStats,
+    /// The write-ahead log of the hash-table. Empty between commits after a clean shutdown.
+    pub wal: FileSize,
+    /// The leaf node file.
+    pub ln: NodeFileStats,
+    /// The bottom-level branch node file.
+    pub bbn: NodeFileStats,
+}
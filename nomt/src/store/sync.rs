@@ -1,14 +1,21 @@
-use super::{meta::Meta, MerkleTransaction, Shared, ValueTransaction};
+use super::{
+    meta::{CommitRoot, Meta},
+    MerkleTransaction, Shared, ValueTransaction,
+};
 use crate::{
-    beatree, bitbox,
-    io::{FatPage, PagePool},
+    beatree::{self, Compression},
+    bitbox,
+    io::{self, FatPage, IoHandle, PagePool},
+    manifest::ChangedPages,
     merkle,
+    options::SyncCrashPoint,
     page_cache::PageCache,
     rollback,
 };
 
 use crossbeam::channel::{self, Receiver};
-use std::{fs::File, mem};
+use nomt_core::{binning::KeyBinning, trie::Node};
+use std::{collections::VecDeque, fs::File, mem, os::fd::AsRawFd as _, sync::Arc};
 use threadpool::ThreadPool;
 
 pub struct Sync {
@@ -16,37 +23,92 @@ pub struct Sync {
     pub(crate) sync_seqn: u32,
     pub(crate) bitbox_num_pages: u32,
     pub(crate) bitbox_seed: [u8; 16],
-    pub(crate) panic_on_sync: bool,
+    pub(crate) bitbox_mapping: bitbox::BucketMappingStrategy,
+    pub(crate) key_binning: KeyBinning,
+    pub(crate) compression: Compression,
+    pub(crate) dictionary_len: u32,
+    pub(crate) dictionary_checksum: u64,
+    pub(crate) crash_point: Option<SyncCrashPoint>,
+    /// The roots of the most recent syncs, from the oldest to the newest.
+    pub(crate) root_history: VecDeque<CommitRoot>,
+    pub(crate) root_history_len: usize,
+    /// The root of the trie as of the last sync.
+    pub(crate) root: Node,
+    /// The sequence number of the last sync, see [`Meta::commit_seqn`].
+    pub(crate) commit_seqn: u64,
+    /// The root written out by the last sync, which unlike `root` doesn't include the staged
+    /// values.
+    pub(crate) synced_root: Node,
 }
 
 impl Sync {
-    pub fn new(
-        sync_seqn: u32,
-        bitbox_num_pages: u32,
-        bitbox_seed: [u8; 16],
-        panic_on_sync: bool,
-    ) -> Self {
+    /// Creates the sync state of a database whose last sync wrote out the given meta.
+    pub fn new(meta: &Meta, crash_point: Option<SyncCrashPoint>, root_history_len: usize) -> Self {
+        let mut root_history = VecDeque::from(meta.root_history.clone());
+        while root_history.len() > root_history_len {
+            root_history.pop_front();
+        }
         Self {
             tp: ThreadPool::with_name("store-sync".into(), 6),
-            sync_seqn,
-            bitbox_num_pages,
-            bitbox_seed,
-            panic_on_sync,
+            sync_seqn: meta.sync_seqn,
+            bitbox_num_pages: meta.bitbox_num_pages,
+            bitbox_seed: meta.bitbox_seed,
+            bitbox_mapping: meta.bitbox_mapping,
+            key_binning: meta.key_binning,
+            compression: meta.compression,
+            dictionary_len: meta.dictionary_len,
+            dictionary_checksum: meta.dictionary_checksum,
+            crash_point,
+            root_history,
+            root_history_len,
+            root: nomt_core::trie::TERMINATOR,
+            commit_seqn: meta.commit_seqn,
+            synced_root: nomt_core::trie::TERMINATOR,
         }
     }
 
     pub fn sync(
+        &mut self,
+        shared: &Shared,
+        value_tx: ValueTransaction,
+        page_cache: PageCache,
+        page_diffs: merkle::PageDiffs,
+        commit_tag: Vec<u8>,
+        root: Node,
+    ) -> anyhow::Result<ChangedPages> {
+        let prepared = self.prepare(shared, value_tx, page_cache, page_diffs, commit_tag, root)?;
+        self.finish(shared, prepared)
+    }
+
+    /// Writes out the sync up to the meta: the WAL, the b-tree and the rollback log are written
+    /// and synced, but nothing refers to them until [`Self::finish`] writes the meta.
+    pub fn prepare(
         &mut self,
         shared: &Shared,
         mut value_tx: ValueTransaction,
-        bitbox: bitbox::DB,
-        beatree: beatree::Tree,
-        rollback: Option<rollback::Rollback>,
         page_cache: PageCache,
         page_diffs: merkle::PageDiffs,
-    ) -> anyhow::Result<()> {
+        commit_tag: Vec<u8>,
+        root: Node,
+    ) -> anyhow::Result<PreparedSync> {
+        let bitbox = shared.pages.clone();
+        let beatree = shared.values.clone();
+        let rollback = shared.rollback.clone();
         self.sync_seqn += 1;
         let sync_seqn = self.sync_seqn;
+        self.commit_seqn += 1;
+        let parent_root = mem::replace(&mut self.synced_root, root);
+        self.root = root;
+
+        if self.root_history_len > 0 {
+            if self.root_history.len() == self.root_history_len {
+                self.root_history.pop_front();
+            }
+            self.root_history.push_back(CommitRoot {
+                seqn: sync_seqn,
+                root,
+            });
+        }
 
         let rollback_writeout_wd_rx = spawn_rollback_writeout_start(&self.tp, &rollback);
 
@@ -54,6 +116,7 @@ impl Sync {
             &self.tp,
             shared.page_pool.clone(),
             bitbox,
+            sync_seqn,
             page_cache,
             page_diffs,
         );
@@ -61,17 +124,22 @@ impl Sync {
         let (beatree_trigger_fsync_rx, meta_wd) =
             spawn_prepare_sync_beatree(&self.tp, &mut value_tx, beatree.clone());
 
-        let (bbn_writeout_done, ln_writeout_done) = spawn_fsync_beatree(
+        let beatree_writeout_done = spawn_fsync_beatree(
             &self.tp,
+            shared.io_pool.make_handle(),
             &shared.bbn_fd,
             &shared.ln_fd,
             beatree_trigger_fsync_rx,
         );
-        let bitbox_writeout_done = spawn_wal_writeout(&self.tp, &shared.wal_fd, bitbox_wal_wd);
+        let bitbox_writeout_done = spawn_wal_writeout(
+            &self.tp,
+            shared.io_pool.make_handle(),
+            &shared.wal_fd,
+            bitbox_wal_wd,
+        );
 
-        bbn_writeout_done.recv().unwrap();
-        ln_writeout_done.recv().unwrap();
-        bitbox_writeout_done.recv().unwrap();
+        let beatree_writeout_result = beatree_writeout_done.recv().unwrap();
+        let wal_writeout_result = bitbox_writeout_done.recv().unwrap();
 
         let rollback_writeout_wd = rollback_writeout_wd_rx
             .map(|rollback_writeout_wd| rollback_writeout_wd.recv().unwrap());
@@ -93,7 +161,14 @@ impl Sync {
         }
 
         let beatree_meta_wd = meta_wd.recv().unwrap();
-        let new_meta = Meta {
+
+        // Nothing refers to the pages written so far until the meta is written, so failing here
+        // leaves the last committed state intact on disk.
+        wal_writeout_result?;
+        let beatree_meta_wd = beatree_meta_wd?;
+        beatree_writeout_result.map_err(crate::Error::writeout)?;
+
+        let meta = Meta {
             ln_freelist_pn: beatree_meta_wd.ln_freelist_pn,
             ln_bump: beatree_meta_wd.ln_bump,
             bbn_freelist_pn: beatree_meta_wd.bbn_freelist_pn,
@@ -101,20 +176,54 @@ impl Sync {
             sync_seqn,
             bitbox_num_pages: self.bitbox_num_pages,
             bitbox_seed: self.bitbox_seed,
+            bitbox_mapping: self.bitbox_mapping,
+            key_binning: self.key_binning,
+            compression: self.compression,
+            dictionary_len: self.dictionary_len,
+            dictionary_checksum: self.dictionary_checksum,
             rollback_start_live,
             rollback_end_live,
+            commit_tag,
+            root_history: self.root_history.iter().copied().collect(),
+            commit_seqn: self.commit_seqn,
+            parent_root,
         };
-        Meta::write(&shared.io_pool.page_pool(), &shared.meta_fd, &new_meta)?;
+        Ok(PreparedSync {
+            meta,
+            bitbox_ht_wd,
+            beatree_meta_wd,
+            rollback_prune_to_new_start_live,
+            rollback_prune_to_new_end_live,
+        })
+    }
 
-        if self.panic_on_sync {
-            panic!("panic_on_sync is true");
-        }
+    /// Completes a sync prepared by [`Self::prepare`] by writing the meta, and then applies it to
+    /// the hash-table and the b-tree.
+    pub fn finish(
+        &mut self,
+        shared: &Shared,
+        prepared: PreparedSync,
+    ) -> anyhow::Result<ChangedPages> {
+        let PreparedSync {
+            meta,
+            bitbox_ht_wd,
+            beatree_meta_wd,
+            rollback_prune_to_new_start_live,
+            rollback_prune_to_new_end_live,
+        } = prepared;
+        let beatree = shared.values.clone();
+
+        self.maybe_crash(SyncCrashPoint::BeforeMeta);
+
+        Meta::write(&shared.io_pool.page_pool(), &shared.meta_fd, &meta)?;
+
+        self.maybe_crash(SyncCrashPoint::AfterMeta);
 
         // Spawn a task to finish off the rollback writeout, if required.
-        let rollback_writeout_end_rx = if let Some(rollback) = rollback {
+        let rollback_writeout_end_rx = if let Some(ref rollback) = shared.rollback {
             spawn_rollback_writeout_end(
                 &self.tp,
-                &rollback,
+                rollback,
                 rollback_prune_to_new_start_live,
                 rollback_prune_to_new_end_live,
             )
@@ -124,20 +233,52 @@ impl Sync {
             rx
         };
 
+        // The pages are sent before the WAL, which was written out.
         let HtWriteoutData { ht_pages } = bitbox_ht_wd.recv().unwrap();
+        let changed_pages = ChangedPages::new(
+            ht_pages.iter().map(|(pn, _)| *pn).collect(),
+            beatree_meta_wd
+                .ln_written_pages
+                .iter()
+                .map(|pn| pn.0)
+                .collect(),
+            beatree_meta_wd
+                .bbn_written_pages
+                .iter()
+                .map(|pn| pn.0)
+                .collect(),
+        );
         bitbox::writeout::write_ht(shared.io_pool.make_handle(), &shared.ht_fd, ht_pages)?;
+        self.maybe_crash(SyncCrashPoint::AfterHt);
         bitbox::writeout::truncate_wal(&shared.wal_fd)?;
 
         beatree.finish_sync(beatree_meta_wd.bbn_index);
 
         rollback_writeout_end_rx.recv().unwrap();
 
-        Ok(())
+        Ok(changed_pages)
+    }
+
+    fn maybe_crash(&self, crash_point: SyncCrashPoint) {
+        if self.crash_point == Some(crash_point) {
+            panic!("simulated crash: {crash_point:?}");
+        }
     }
 }
 
+/// A sync written out up to the meta, returned by [`Sync::prepare`].
+pub struct PreparedSync {
+    /// The meta which completes the sync.
+    pub meta: Meta,
+    bitbox_ht_wd: Receiver<HtWriteoutData>,
+    beatree_meta_wd: beatree::SyncData,
+    rollback_prune_to_new_start_live: Option<u64>,
+    rollback_prune_to_new_end_live: Option<u64>,
+}
+
 struct WalWriteoutData {
     wal_blob: (*mut u8, usize),
+    wal_offset: u64,
 }
 unsafe impl Send for WalWriteoutData {}
 
@@ -145,30 +286,49 @@ struct HtWriteoutData {
     ht_pages: Vec<(u64, FatPage)>,
 }
 
+// If the WAL blob can't be built, the error is sent in place of the WAL and the hash-table pages
+// are never sent.
 fn spawn_prepare_sync_bitbox(
     tp: &ThreadPool,
     page_pool: PagePool,
     bitbox: bitbox::DB,
+    sync_seqn: u32,
     page_cache: PageCache,
     page_diffs: merkle::PageDiffs,
-) -> (Receiver<HtWriteoutData>, Receiver<WalWriteoutData>) {
+) -> (
+    Receiver<HtWriteoutData>,
+    Receiver<anyhow::Result<WalWriteoutData>>,
+) {
     let (ht_result_tx, ht_result_rx) = channel::bounded(1);
     let (wal_result_tx, wal_result_rx) = channel::bounded(1);
     tp.execute(move || {
+        // Size everything for the changed pages up front, so that the bucket allocation doesn't
+        // stall on growing it.
+        let changed_pages = page_diffs.len();
         let mut merkle_tx = MerkleTransaction {
             page_pool: page_pool.clone(),
-            bucket_allocator: bitbox.bucket_allocator(),
-            new_pages: Vec::new(),
+            bucket_allocator: bitbox.bucket_allocator(changed_pages),
+            new_pages: Vec::with_capacity(changed_pages),
         };
 
         page_cache.prepare_transaction(page_diffs.into_iter(), &mut merkle_tx);
 
-        let bitbox::WriteoutData { ht_pages, wal_blob } = bitbox
-            .prepare_sync(&page_pool, merkle_tx.new_pages)
-            // TODO: handle error.
-            .unwrap();
-        let _ = ht_result_tx.send(HtWriteoutData { ht_pages });
-        let _ = wal_result_tx.send(WalWriteoutData { wal_blob });
+        match bitbox.prepare_sync(&page_pool, sync_seqn, merkle_tx.new_pages) {
+            Ok(bitbox::WriteoutData {
+                ht_pages,
+                wal_blob,
+                wal_offset,
+            }) => {
+                let _ = ht_result_tx.send(HtWriteoutData { ht_pages });
+                let _ = wal_result_tx.send(Ok(WalWriteoutData {
+                    wal_blob,
+                    wal_offset,
+                }));
+            }
+            Err(e) => {
+                let _ = wal_result_tx.send(Err(e));
+            }
+        }
 
         // evict outside of the critical path.
         page_cache.evict();
@@ -180,7 +340,7 @@ fn spawn_prepare_sync_beatree(
     tp: &ThreadPool,
     tx: &mut ValueTransaction,
     beatree: beatree::Tree,
-) -> (Receiver<()>, Receiver<beatree::SyncData>) {
+) -> (Receiver<()>, Receiver<anyhow::Result<beatree::SyncData>>) {
     let batch = mem::take(&mut tx.batch);
     let (trigger_fsync_tx, trigger_fsync_rx) = channel::bounded(1);
     let (meta_result_tx, meta_result_rx) = channel::bounded(1);
@@ -188,54 +348,59 @@ fn spawn_prepare_sync_beatree(
     tp.execute(move || {
         beatree.commit(batch);
         let meta = beatree.prepare_sync();
+        // The fsync is triggered even if the sync failed, so that it doesn't wait forever.
         let _ = trigger_fsync_tx.send(());
         let _ = meta_result_tx.send(meta);
     });
     (trigger_fsync_rx, meta_result_rx)
 }
 
+// The BBN and LN files are flushed through the I/O pool, concurrently with the other writes of the
+// sync.
 fn spawn_fsync_beatree(
     tp: &ThreadPool,
-    bbn_fd: &File,
-    ln_fd: &File,
+    io_handle: IoHandle,
+    bbn_fd: &Arc<File>,
+    ln_fd: &Arc<File>,
     beatree_trigger_fsync_rx: Receiver<()>,
-) -> (Receiver<()>, Receiver<()>) {
-    let (bbn_result_tx, bbn_result_rx) = channel::bounded(1);
-    let (ln_result_tx, ln_result_rx) = channel::bounded(1);
+) -> Receiver<std::io::Result<()>> {
+    let (result_tx, result_rx) = channel::bounded(1);
     tp.execute({
-        let bbn_fd = bbn_fd.try_clone().unwrap();
-        let ln_fd = ln_fd.try_clone().unwrap();
-        let tp = tp.clone();
+        let bbn_fd = bbn_fd.clone();
+        let ln_fd = ln_fd.clone();
         move || {
             let () = beatree_trigger_fsync_rx.recv().unwrap();
-            tp.execute(move || {
-                bbn_fd.sync_all().unwrap();
-                let _ = bbn_result_tx.send(());
-            });
-            tp.execute(move || {
-                ln_fd.sync_all().unwrap();
-                let _ = ln_result_tx.send(());
-            });
+            let fds = [bbn_fd.as_raw_fd(), ln_fd.as_raw_fd()];
+            let _ = result_tx.send(io::fsync(&io_handle, &fds));
         }
     });
-    (bbn_result_rx, ln_result_rx)
+    result_rx
 }
 
 fn spawn_wal_writeout(
     tp: &ThreadPool,
-    wal_fd: &File,
-    wal_wd: Receiver<WalWriteoutData>,
-) -> Receiver<()> {
+    io_handle: IoHandle,
+    wal_fd: &Arc<File>,
+    wal_wd: Receiver<anyhow::Result<WalWriteoutData>>,
+) -> Receiver<anyhow::Result<()>> {
     let (result_tx, result_rx) = channel::bounded(1);
-    let mut wal_fd = wal_fd.try_clone().unwrap();
-    tp.execute({
-        let WalWriteoutData { wal_blob } = wal_wd.recv().unwrap();
-        let (data, len) = wal_blob;
-        let wal_blob = unsafe { std::slice::from_raw_parts(data, len) };
-        move || {
-            bitbox::writeout::write_wal(&mut wal_fd, wal_blob).unwrap();
-            let _ = result_tx.send(());
+    let wal_fd = wal_fd.clone();
+    let WalWriteoutData {
+        wal_blob,
+        wal_offset,
+    } = match wal_wd.recv().unwrap() {
+        Ok(wal_wd) => wal_wd,
+        Err(e) => {
+            let _ = result_tx.send(Err(e));
+            return result_rx;
         }
+    };
+    let (data, len) = wal_blob;
+    let wal_blob = unsafe { std::slice::from_raw_parts(data, len) };
+    tp.execute(move || {
+        let _ = result_tx.send(bitbox::writeout::write_wal(
+            &io_handle, &wal_fd, wal_blob, wal_offset,
+        ));
     });
     result_rx
 }
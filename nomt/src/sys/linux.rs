@@ -18,6 +18,22 @@ pub fn tmpfs_check(file: &File) -> bool {
     }
 }
 
+/// Deallocates the given range of the file, keeping its size. Reading the range returns zeroes
+/// afterwards.
+pub fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<()> {
+    cvt_r(|| unsafe {
+        // SAFETY: unsafe because ffi call. This should be IO-safe because the file is passed
+        //         by reference.
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as _,
+            len as _,
+        )
+    })
+    .map(drop)
+}
+
 /// fallocate changes the size of the file to the given length if it's less than the current size.
 /// If the file is larger than the given length, the file is not truncated.
 ///
@@ -6,10 +6,44 @@ pub fn try_lock_exclusive(file: &File) -> std::io::Result<()> {
     cvt_r(|| unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }).map(drop)
 }
 
+pub fn try_lock_shared(file: &File) -> std::io::Result<()> {
+    cvt_r(|| unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_SH | libc::LOCK_NB) }).map(drop)
+}
+
 pub fn unlock(file: &File) -> std::io::Result<()> {
     unsafe { cvt_r(|| libc::flock(file.as_raw_fd(), libc::LOCK_UN)).map(drop) }
 }
 
+/// Returns the soft and hard limits on the number of open file descriptors of the process.
+pub fn open_files_limit() -> std::io::Result<(u64, u64)> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: unsafe because ffi call. This is memory-safe because `rlim` is a valid pointer.
+    cvt_r(|| unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) })?;
+    Ok((rlim.rlim_cur, rlim.rlim_max))
+}
+
+/// Sets the soft limit on the number of open file descriptors of the process. The soft limit
+/// cannot exceed the hard limit.
+pub fn set_open_files_soft_limit(soft: u64, hard: u64) -> std::io::Result<()> {
+    let rlim = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    // SAFETY: unsafe because ffi call. This is memory-safe because `rlim` is a valid pointer.
+    cvt_r(|| unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) }).map(drop)
+}
+
+/// Returns the number of file descriptors currently open in the process, if it can be determined.
+pub fn open_files_count() -> Option<u64> {
+    // Both Linux and macOS expose the open file descriptors of the process in `/dev/fd`. Reading
+    // the directory itself takes one descriptor, which is not counted.
+    let count = std::fs::read_dir("/dev/fd").ok()?.count() as u64;
+    Some(count.saturating_sub(1))
+}
+
 pub(super) fn cvt_r<F>(mut f: F) -> std::io::Result<i32>
 where
     F: FnMut() -> i32,
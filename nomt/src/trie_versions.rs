@@ -0,0 +1,178 @@
+//! The trie at recent roots.
+//!
+//! Trie pages are updated in place. To read the trie at a recent root anyway, the values of the
+//! nodes overwritten by every commit are kept for as long as a snapshot from before the commit is
+//! retained. A page is rebuilt at an older root by putting the overwritten values back, from the
+//! newest commit to the oldest.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use bitvec::prelude::*;
+use fxhash::FxBuildHasher;
+use nomt_core::{
+    page::DEPTH,
+    page_id::{PageId, ROOT_PAGE_ID},
+    proof::{PathProof, PathProofTerminal},
+    trie::{KeyPath, LeafData, Node, NodeKind, TERMINATOR},
+    trie_pos::{ChildNodeIndices, TriePosition},
+};
+
+use crate::page_cache::NODES_PER_PAGE;
+
+/// The values of the trie nodes overwritten by a commit, as they were before it.
+#[derive(Default)]
+pub struct PriorNodes(HashMap<PageId, Vec<(usize, Node)>, FxBuildHasher>);
+
+impl PriorNodes {
+    /// Record the value of a node in a page which is about to be overwritten.
+    pub fn record(&mut self, page_id: &PageId, index: usize, node: Node) {
+        self.0
+            .entry(page_id.clone())
+            .or_default()
+            .push((index, node));
+    }
+
+    /// Add the nodes overwritten by another worker of the same commit.
+    pub fn extend(&mut self, other: PriorNodes) {
+        for (page_id, nodes) in other.0 {
+            self.0.entry(page_id).or_default().extend(nodes);
+        }
+    }
+
+    // put the overwritten values back into the nodes of a page, from the last overwrite to the
+    // first.
+    fn restore(&self, page_id: &PageId, nodes: &mut [Node]) {
+        for (index, node) in self.0.get(page_id).into_iter().flatten().rev() {
+            nodes[*index] = *node;
+        }
+    }
+}
+
+/// The nodes overwritten by the commits since the oldest retained snapshot, from the oldest commit
+/// to the newest, along with the root each commit was applied on.
+#[derive(Default)]
+pub struct TrieVersions {
+    commits: VecDeque<(Node, Arc<PriorNodes>)>,
+}
+
+impl TrieVersions {
+    /// Record the nodes overwritten by a commit applied on the given root.
+    pub fn push(&mut self, prev_root: Node, prior_nodes: PriorNodes) {
+        self.commits.push_back((prev_root, Arc::new(prior_nodes)));
+    }
+
+    /// Forget the given number of the oldest commits.
+    pub fn prune(&mut self, commits: usize) {
+        self.commits.drain(..commits.min(self.commits.len()));
+    }
+
+    /// Returns the nodes to put back to read the trie at the given root, from the newest commit to
+    /// the oldest, or `None` if the root isn't the current one or one of the recorded commits was
+    /// applied on it.
+    pub fn undo_to(&self, current_root: Node, root: Node) -> Option<Vec<Arc<PriorNodes>>> {
+        if root == current_root {
+            return Some(Vec::new());
+        }
+        let pos = self
+            .commits
+            .iter()
+            .rposition(|(prev_root, _)| *prev_root == root)?;
+        Some(
+            self.commits
+                .range(pos..)
+                .rev()
+                .map(|(_, prior_nodes)| prior_nodes.clone())
+                .collect(),
+        )
+    }
+}
+
+/// Reads the nodes out of the data of a page.
+pub fn page_nodes(data: &[u8]) -> Vec<Node> {
+    data.chunks_exact(32)
+        .take(NODES_PER_PAGE)
+        .map(|node| {
+            // UNWRAP: chunks are exactly the size of a node.
+            node.try_into().unwrap()
+        })
+        .collect()
+}
+
+/// Look up the path of a key in the trie at a recent root.
+///
+/// `load` returns the current nodes of a page, or `None` if there is no such page, and `undo` the
+/// nodes overwritten since the root, as returned by [`TrieVersions::undo_to`].
+pub fn seek_path_proof(
+    root: Node,
+    key_path: &KeyPath,
+    undo: &[Arc<PriorNodes>],
+    load: impl Fn(&PageId) -> anyhow::Result<Option<Vec<Node>>>,
+) -> anyhow::Result<PathProof> {
+    let load_at_root = |page_id: PageId| -> anyhow::Result<(PageId, Vec<Node>)> {
+        let mut nodes = load(&page_id)?.unwrap_or_else(|| vec![TERMINATOR; NODES_PER_PAGE]);
+        for prior_nodes in undo {
+            prior_nodes.restore(&page_id, &mut nodes);
+        }
+        Ok((page_id, nodes))
+    };
+    let child_page_id = |page_id: &PageId, position: &TriePosition| {
+        page_id
+            .child_page_id(position.child_page_index())
+            .map_err(|_| anyhow::anyhow!("trie deeper than the maximum page depth"))
+    };
+
+    let path = key_path.view_bits::<Msb0>();
+    let mut position = TriePosition::new();
+    let mut node = root;
+    let mut siblings = Vec::new();
+    // the page the current position lies in. `None` at the root.
+    let mut page: Option<(PageId, Vec<Node>)> = None;
+    loop {
+        match NodeKind::of(&node) {
+            NodeKind::Terminator => {
+                return Ok(PathProof {
+                    terminal: PathProofTerminal::Terminator(position),
+                    siblings,
+                })
+            }
+            NodeKind::Leaf => {
+                let ((_, nodes), children) = match page {
+                    None => (load_at_root(ROOT_PAGE_ID)?, ChildNodeIndices::from_left(0)),
+                    Some((page_id, _)) if position.depth_in_page() == DEPTH => (
+                        load_at_root(child_page_id(&page_id, &position)?)?,
+                        ChildNodeIndices::from_left(0),
+                    ),
+                    Some(page) => (page, position.child_node_indices()),
+                };
+                return Ok(PathProof {
+                    terminal: PathProofTerminal::Leaf(LeafData {
+                        key_path: nodes[children.left()],
+                        value_hash: nodes[children.right()],
+                    }),
+                    siblings,
+                });
+            }
+            NodeKind::Internal => {
+                let depth = position.depth() as usize;
+                if depth == path.len() {
+                    anyhow::bail!("internal node at the maximum depth of the trie");
+                }
+                page = match page {
+                    None => Some(load_at_root(ROOT_PAGE_ID)?),
+                    Some((page_id, _)) if position.depth_in_page() == DEPTH => {
+                        Some(load_at_root(child_page_id(&page_id, &position)?)?)
+                    }
+                    page => page,
+                };
+                position.down(path[depth]);
+                // UNWRAP: just set.
+                let (_, nodes) = page.as_ref().unwrap();
+                siblings.push(nodes[position.sibling_index()]);
+                node = nodes[position.node_index()];
+            }
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Notifications about commits.
+//!
+//! Applications serving light clients are often interested in a handful of keys only. Instead of
+//! re-reading them after every commit, they can register a [`Watcher`] for those keys, which
+//! receives an event for every commit changing the value of any of them.
+//!
+//! Indexers and replicas which follow the entire database can register a [`CommitFeed`] instead,
+//! which receives everything written by every commit. Followers in another process which mirror
+//! the files of the database can register a [`ReplicationFeed`], which receives the pages written
+//! by every sync as bytes.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Weak},
+};
+
+use crossbeam::channel::{self, Receiver, Sender};
+use nomt_core::trie::{KeyPath, Node, ValueHash};
+use parking_lot::Mutex;
+
+use crate::{ChangedPages, KeyReadWrite, Value};
+
+/// A change to the value of a watched key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyChange {
+    /// The key path of the key.
+    pub key_path: KeyPath,
+    /// The hash of the value before the commit. `None` if there was no value.
+    pub old: Option<ValueHash>,
+    /// The hash of the value after the commit. `None` if the value was deleted.
+    pub new: Option<ValueHash>,
+}
+
+/// The changes made by a single commit to the keys watched by a [`Watcher`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The root of the trie after the commit.
+    pub root: Node,
+    /// The changed keys, ordered by key path.
+    pub changes: Vec<KeyChange>,
+}
+
+/// Everything written by a single commit, as received by a [`CommitFeed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitDiff {
+    /// The root of the trie before the commit.
+    pub prev_root: Node,
+    /// The root of the trie after the commit.
+    pub root: Node,
+    /// The values written by the commit, ordered by key path. `None` stands for a deletion.
+    ///
+    /// This includes the keys removed by [`crate::Session::delete_prefix`], as well as writes
+    /// which leave the value unchanged.
+    pub key_changes: Vec<(KeyPath, Option<Value>)>,
+    /// The pages written by the commit. See [`crate::Nomt::changed_pages_since`].
+    pub pages: ChangedPages,
+}
+
+/// The pages written by a sync, as received by a [`ReplicationFeed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplicationRecord {
+    /// The sequence number of the sync. It increases by one with every record. See
+    /// [`crate::Nomt::commit_seqn`].
+    pub commit_seqn: u64,
+    /// The root of the trie before the sync.
+    pub prev_root: Node,
+    /// The root of the trie after the sync.
+    pub root: Node,
+    /// The sync encoded as an increment of an [incremental
+    /// backup](crate::Options::incremental_backup): the roots before and after the sync, and the
+    /// pages it wrote to every file, including the meta page, followed by a checksum of it all.
+    ///
+    /// Stored as the next increment of a backup of the database, e.g. `0000000042.increment`, it
+    /// is applied by [`crate::Nomt::materialize_backup`].
+    pub bytes: Arc<[u8]>,
+}
+
+/// Receives a [`WatchEvent`] for every commit changing the value of at least one of the watched
+/// keys. Created with [`crate::Nomt::watch`].
+///
+/// Events are queued until they are received. Dropping the watcher unregisters it.
+pub struct Watcher {
+    _registration: Registration,
+    events: Receiver<WatchEvent>,
+}
+
+impl Watcher {
+    /// Returns the next event, blocking until there is one.
+    ///
+    /// Returns `None` once the database is closed and all queued events have been received.
+    pub fn recv(&self) -> Option<WatchEvent> {
+        self.events.recv().ok()
+    }
+
+    /// Returns the next event if there is one, without blocking.
+    pub fn try_recv(&self) -> Option<WatchEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Returns an iterator over the queued events, which doesn't block.
+    pub fn try_iter(&self) -> impl Iterator<Item = WatchEvent> + '_ {
+        self.events.try_iter()
+    }
+}
+
+/// Receives a [`CommitDiff`] for every successful commit. Created with
+/// [`crate::Nomt::commit_feed`].
+///
+/// Diffs are queued until they are received. Dropping the feed unregisters it.
+pub struct CommitFeed {
+    _registration: Registration,
+    diffs: Receiver<CommitDiff>,
+}
+
+impl CommitFeed {
+    /// Returns the diff of the next commit, blocking until there is one.
+    ///
+    /// Returns `None` once the database is closed and all queued diffs have been received.
+    pub fn recv(&self) -> Option<CommitDiff> {
+        self.diffs.recv().ok()
+    }
+
+    /// Returns the diff of the next commit if there is one, without blocking.
+    pub fn try_recv(&self) -> Option<CommitDiff> {
+        self.diffs.try_recv().ok()
+    }
+
+    /// Returns an iterator over the queued diffs, which doesn't block.
+    pub fn try_iter(&self) -> impl Iterator<Item = CommitDiff> + '_ {
+        self.diffs.try_iter()
+    }
+}
+
+/// Receives a [`ReplicationRecord`] for every sync written to disk. Created with
+/// [`crate::Nomt::replication_feed`].
+///
+/// Records are queued until they are received. Dropping the feed unregisters it.
+pub struct ReplicationFeed {
+    _registration: Registration,
+    records: Receiver<ReplicationRecord>,
+}
+
+impl ReplicationFeed {
+    /// Returns the record of the next sync, blocking until there is one.
+    ///
+    /// Returns `None` once the database is closed and all queued records have been received.
+    pub fn recv(&self) -> Option<ReplicationRecord> {
+        self.records.recv().ok()
+    }
+
+    /// Returns the record of the next sync if there is one, without blocking.
+    pub fn try_recv(&self) -> Option<ReplicationRecord> {
+        self.records.try_recv().ok()
+    }
+
+    /// Returns an iterator over the queued records, which doesn't block.
+    pub fn try_iter(&self) -> impl Iterator<Item = ReplicationRecord> + '_ {
+        self.records.try_iter()
+    }
+}
+
+/// Removes a subscriber from the registry when dropped.
+struct Registration {
+    id: u64,
+    registry: Weak<Mutex<Registry>>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.lock().subscribers.remove(&self.id);
+        }
+    }
+}
+
+enum Subscriber {
+    Keys(BTreeSet<KeyPath>, Sender<WatchEvent>),
+    Commits(Sender<CommitDiff>),
+    Syncs(Sender<ReplicationRecord>),
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    subscribers: BTreeMap<u64, Subscriber>,
+}
+
+/// The watchers and commit feeds registered with a database.
+pub(crate) struct Watchers {
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Watchers {
+    pub fn new() -> Self {
+        Self {
+            registry: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    fn register(&self, subscriber: Subscriber) -> Registration {
+        let mut registry = self.registry.lock();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.subscribers.insert(id, subscriber);
+        Registration {
+            id,
+            registry: Arc::downgrade(&self.registry),
+        }
+    }
+
+    pub fn watch(&self, key_paths: impl IntoIterator<Item = KeyPath>) -> Watcher {
+        let (tx, rx) = channel::unbounded();
+        Watcher {
+            _registration: self.register(Subscriber::Keys(key_paths.into_iter().collect(), tx)),
+            events: rx,
+        }
+    }
+
+    pub fn commit_feed(&self) -> CommitFeed {
+        let (tx, rx) = channel::unbounded();
+        CommitFeed {
+            _registration: self.register(Subscriber::Commits(tx)),
+            diffs: rx,
+        }
+    }
+
+    pub fn replication_feed(&self) -> ReplicationFeed {
+        let (tx, rx) = channel::unbounded();
+        ReplicationFeed {
+            _registration: self.register(Subscriber::Syncs(tx)),
+            records: rx,
+        }
+    }
+
+    /// Whether any commit feed is registered.
+    pub fn has_commit_feeds(&self) -> bool {
+        self.registry
+            .lock()
+            .subscribers
+            .values()
+            .any(|subscriber| matches!(subscriber, Subscriber::Commits(_)))
+    }
+
+    /// Whether any replication feed is registered.
+    pub fn has_replication_feeds(&self) -> bool {
+        self.registry
+            .lock()
+            .subscribers
+            .values()
+            .any(|subscriber| matches!(subscriber, Subscriber::Syncs(_)))
+    }
+
+    /// Computes the changes the actuals make to the watched keys.
+    ///
+    /// `prior_value_hash` looks up the hash of the value stored under a key before the commit.
+    /// It's only called for watched keys written without having been read.
+    pub fn changes<E>(
+        &self,
+        actuals: &[(KeyPath, KeyReadWrite)],
+        hash_value: impl Fn(&[u8]) -> ValueHash,
+        prior_value_hash: impl Fn(KeyPath) -> Result<Option<ValueHash>, E>,
+    ) -> Result<Vec<KeyChange>, E> {
+        let watched = {
+            let registry = self.registry.lock();
+            let watched_sets = registry
+                .subscribers
+                .values()
+                .filter_map(|subscriber| match subscriber {
+                    Subscriber::Keys(key_paths, _) => Some(key_paths),
+                    Subscriber::Commits(_) | Subscriber::Syncs(_) => None,
+                })
+                .collect::<Vec<_>>();
+            if watched_sets.is_empty() {
+                return Ok(Vec::new());
+            }
+            actuals
+                .iter()
+                .filter(|(key_path, read_write)| {
+                    read_write.is_write()
+                        && watched_sets
+                            .iter()
+                            .any(|key_paths| key_paths.contains(key_path))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut changes = Vec::new();
+        for (key_path, read_write) in watched {
+            let (old, new) = match read_write {
+                KeyReadWrite::ReadThenWrite(old, new) => {
+                    (old.as_deref().map(&hash_value), new.as_deref())
+                }
+                KeyReadWrite::Write(new) => (prior_value_hash(*key_path)?, new.as_deref()),
+                KeyReadWrite::Read(_) => unreachable!(),
+            };
+            let new = new.map(&hash_value);
+            if old != new {
+                changes.push(KeyChange {
+                    key_path: *key_path,
+                    old,
+                    new,
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Sends the changes made by a commit to the watchers of the changed keys and its diff, if
+    /// any, to the commit feeds.
+    pub fn notify(&self, root: Node, changes: &[KeyChange], diff: Option<CommitDiff>) {
+        for subscriber in self.registry.lock().subscribers.values() {
+            // A subscriber unregisters itself before its receiver is dropped, so sending can't
+            // fail.
+            match subscriber {
+                Subscriber::Keys(key_paths, events) => {
+                    let changes = changes
+                        .iter()
+                        .filter(|change| key_paths.contains(&change.key_path))
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    if !changes.is_empty() {
+                        let _ = events.send(WatchEvent { root, changes });
+                    }
+                }
+                Subscriber::Commits(diffs) => {
+                    if let Some(diff) = &diff {
+                        let _ = diffs.send(diff.clone());
+                    }
+                }
+                Subscriber::Syncs(_) => {}
+            }
+        }
+    }
+
+    /// Sends the record of a sync to the replication feeds.
+    pub fn notify_sync(&self, record: ReplicationRecord) {
+        for subscriber in self.registry.lock().subscribers.values() {
+            if let Subscriber::Syncs(records) = subscriber {
+                // See `notify`.
+                let _ = records.send(record.clone());
+            }
+        }
+    }
+}
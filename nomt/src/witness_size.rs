@@ -0,0 +1,45 @@
+//! Estimating the size of witnesses before committing.
+
+use std::collections::{BTreeMap, HashSet};
+
+use bitvec::prelude::*;
+
+/// The terminal of a path, as far as its encoded size is concerned.
+#[derive(Clone, Copy)]
+pub(crate) enum TerminalKind {
+    Leaf,
+    Terminator,
+}
+
+/// Computes the size of the encoding of a witness with the given paths and their terminals,
+/// assuming that only siblings at the same position are shared.
+///
+/// This is an upper bound of the actual size: distinct positions may hold the same node, most
+/// commonly the terminator, which is then shared as well.
+pub(crate) fn estimate_encoded_len(paths: &BTreeMap<BitVec<u8, Msb0>, TerminalKind>) -> usize {
+    let mut len = 2 + varint_len(paths.len() as u64);
+    let mut positions = HashSet::new();
+    for (path, terminal) in paths {
+        let path_len = varint_len(path.len() as u64) + path.len().div_ceil(8);
+        len += path_len;
+        len += 1 + match *terminal {
+            TerminalKind::Leaf => 64,
+            TerminalKind::Terminator => path_len,
+        };
+        len += varint_len(path.len() as u64);
+        for depth in 0..path.len() {
+            let mut position = path[..depth].to_bitvec();
+            position.push(!path[depth]);
+            len += if positions.insert(position) {
+                33
+            } else {
+                varint_len(positions.len() as u64)
+            };
+        }
+    }
+    len
+}
+
+fn varint_len(value: u64) -> usize {
+    std::cmp::max(1, (64 - value.leading_zeros() as usize).div_ceil(7))
+}
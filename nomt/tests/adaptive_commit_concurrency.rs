@@ -0,0 +1,59 @@
+//! Tests commits of various sizes with the number of b-tree workers chosen per commit.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+
+#[test]
+fn commits_of_varying_size() {
+    let mut path = PathBuf::from("test");
+    path.push("adaptive_commit_concurrency");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(&path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(50_000);
+    o.commit_concurrency(8);
+    o.adaptive_commit_concurrency(true);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+
+    let key = |i: u64| -> KeyPath { *blake3::hash(&i.to_le_bytes()).as_bytes() };
+    let mut values = BTreeMap::new();
+
+    // Alternate between large commits, spread over all workers, and tiny ones.
+    let mut next = 0;
+    for size in [10_000, 1, 5_000, 3, 20_000, 1] {
+        let session = nomt.begin_session();
+        let mut actuals = (next..next + size)
+            .map(|i| {
+                (
+                    key(i),
+                    KeyReadWrite::Write(Some(i.to_le_bytes().to_vec().into())),
+                )
+            })
+            .collect::<Vec<_>>();
+        // Also delete some of the earlier keys.
+        for i in (0..next).step_by(7).take(size as usize) {
+            actuals.push((key(i), KeyReadWrite::Write(None)));
+        }
+        actuals.sort_by_key(|(k, _)| *k);
+        for (k, v) in &actuals {
+            values.insert(*k, v.last_value().map(|v| v.to_vec()));
+        }
+        nomt.commit(session, actuals).unwrap();
+        next += size;
+
+        let ops = values
+            .iter()
+            .filter_map(|(k, v)| v.as_ref().map(|v| (*k, *blake3::hash(v).as_bytes())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nomt.root(),
+            nomt_core::update::build_trie::<Blake3Hasher>(0, ops, |_| {})
+        );
+    }
+
+    for (k, v) in &values {
+        assert_eq!(nomt.read(*k).unwrap().map(|v| v.to_vec()), *v);
+    }
+}
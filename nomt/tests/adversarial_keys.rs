@@ -0,0 +1,240 @@
+//! Tests with adversarial key patterns.
+//!
+//! Uniformly distributed keys keep the trie shallow and spread the leaves of the beatree evenly.
+//! The patterns here do the opposite, to hit the structural edge cases: deep chains of pages,
+//! leaves packed to the limit, and splits and merges of neighboring leaves happening within the
+//! same commit across several update workers.
+
+mod common;
+
+use common::Test;
+use nomt::{KeyPath, Node};
+use std::collections::BTreeMap;
+
+/// Keys sharing all but the last byte, forcing the trie to its maximum depth.
+fn shared_prefix_key(i: u8) -> KeyPath {
+    let mut key = [0xAA; 32];
+    key[31] = i;
+    key
+}
+
+/// Keys forming a contiguous range of big-endian integers, packing the leaves of the beatree
+/// as densely as possible.
+fn dense_key(i: u64) -> KeyPath {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+/// Pairs of keys which are neighbors in key order and share a 63-bit prefix. They don't overlap
+/// with the other patterns.
+fn neighbor_keys(i: u64) -> (KeyPath, KeyPath) {
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&(i << 8).to_be_bytes());
+    key[0] = 0x55;
+    let mut neighbor = key;
+    neighbor[7] = 1;
+    (key, neighbor)
+}
+
+/// A value whose size varies with the key, so that leaves are filled unevenly.
+fn value(i: u64, round: u64) -> Vec<u8> {
+    let size = 1 + ((i * 7919 + round * 104_729) % 1500) as usize;
+    vec![(i ^ round) as u8; size]
+}
+
+/// Tracks the expected contents of the database alongside a [`Test`].
+struct Model {
+    t: Test,
+    values: BTreeMap<KeyPath, Vec<u8>>,
+}
+
+impl Model {
+    fn new(name: &str, commit_concurrency: usize) -> Self {
+        Self {
+            t: Test::new_with_params(name, commit_concurrency, 64_000, false, true),
+            values: BTreeMap::new(),
+        }
+    }
+
+    fn write(&mut self, key: KeyPath, value: Option<Vec<u8>>) {
+        match &value {
+            Some(v) => self.values.insert(key, v.clone()),
+            None => self.values.remove(&key),
+        };
+        self.t.write(key, value);
+    }
+
+    /// Commit and check that the root matches the expected contents and that every value reads
+    /// back.
+    fn commit_and_check(&mut self) -> Node {
+        let (root, _, _) = self.t.commit();
+        assert_eq!(root, self.expected_root());
+        for (key, value) in &self.values {
+            assert_eq!(self.t.read(*key).as_ref(), Some(value));
+        }
+        root
+    }
+
+    fn expected_root(&self) -> Node {
+        let ops = self
+            .values
+            .iter()
+            .map(|(k, v)| (*k, *blake3::hash(v).as_bytes()))
+            .collect::<Vec<_>>();
+        nomt_core::update::build_trie::<nomt::Blake3Hasher>(0, ops, |_| {})
+    }
+}
+
+fn long_shared_prefixes(commit_concurrency: usize) {
+    let mut m = Model::new(
+        &format!("long_shared_prefixes_{commit_concurrency}"),
+        commit_concurrency,
+    );
+
+    // Grow the deep chain key by key and then shrink it back, including the removal of the key
+    // with the longest shared prefix.
+    for i in 0..=255u8 {
+        m.write(shared_prefix_key(i), Some(value(i as u64, 0)));
+        if i % 64 == 0 {
+            m.commit_and_check();
+        }
+    }
+    m.commit_and_check();
+    for i in (0..=255u8).step_by(2) {
+        m.write(shared_prefix_key(i), None);
+    }
+    m.commit_and_check();
+    for i in (1..=255u8).step_by(2).skip(1) {
+        m.write(shared_prefix_key(i), None);
+    }
+    m.commit_and_check();
+    m.write(shared_prefix_key(1), None);
+    assert_eq!(m.commit_and_check(), [0; 32]);
+}
+
+#[test]
+fn long_shared_prefixes_single_worker() {
+    long_shared_prefixes(1);
+}
+
+#[test]
+fn long_shared_prefixes_multiple_workers() {
+    long_shared_prefixes(4);
+}
+
+fn dense_ranges(commit_concurrency: usize) {
+    let mut m = Model::new(
+        &format!("dense_ranges_{commit_concurrency}"),
+        commit_concurrency,
+    );
+
+    // Tiny values pack the maximum number of keys into every leaf.
+    for i in 0..2048 {
+        m.write(dense_key(i), Some(vec![i as u8]));
+    }
+    m.commit_and_check();
+
+    // Grow every value, splitting the packed leaves.
+    for i in 0..2048 {
+        m.write(dense_key(i), Some(value(i, 1)));
+    }
+    m.commit_and_check();
+
+    // Delete contiguous runs, merging the leaves around them, while growing the survivors at the
+    // edges of the runs.
+    for i in 0..2048 {
+        match i % 256 {
+            32..=223 => m.write(dense_key(i), None),
+            31 | 224 => m.write(dense_key(i), Some(value(i, 2))),
+            _ => {}
+        }
+    }
+    m.commit_and_check();
+
+    // Refill the holes with tiny values.
+    for i in 0..2048 {
+        if (32..224).contains(&(i % 256)) {
+            m.write(dense_key(i), Some(vec![0]));
+        }
+    }
+    m.commit_and_check();
+}
+
+#[test]
+fn dense_ranges_single_worker() {
+    dense_ranges(1);
+}
+
+#[test]
+fn dense_ranges_multiple_workers() {
+    dense_ranges(4);
+}
+
+fn alternating_neighbors(commit_concurrency: usize) {
+    let mut m = Model::new(
+        &format!("alternating_neighbors_{commit_concurrency}"),
+        commit_concurrency,
+    );
+
+    // Every round deletes one key of each pair and inserts its neighbor, so that every leaf is
+    // rewritten and the trie collapses and expands at the same position.
+    for round in 0..6 {
+        for i in 0..512 {
+            let (key, neighbor) = neighbor_keys(i);
+            let (insert, delete) = if (i + round) % 2 == 0 {
+                (key, neighbor)
+            } else {
+                (neighbor, key)
+            };
+            m.write(insert, Some(value(i, round)));
+            m.write(delete, None);
+        }
+        m.commit_and_check();
+
+        // Every few rounds, insert both neighbors to put the leaves under maximum pressure.
+        if round % 3 == 2 {
+            for i in 0..512 {
+                let (key, neighbor) = neighbor_keys(i);
+                m.write(key, Some(value(i, round)));
+                m.write(neighbor, Some(value(i + 1, round)));
+            }
+            m.commit_and_check();
+        }
+    }
+}
+
+#[test]
+fn alternating_neighbors_single_worker() {
+    alternating_neighbors(1);
+}
+
+#[test]
+fn alternating_neighbors_multiple_workers() {
+    alternating_neighbors(4);
+}
+
+#[test]
+fn adversarial_keys_survive_reopen() {
+    let name = "adversarial_keys_survive_reopen";
+    let mut m = Model::new(name, 4);
+    for i in 0..=255u8 {
+        m.write(shared_prefix_key(i), Some(value(i as u64, 0)));
+    }
+    for i in 0..1024 {
+        m.write(dense_key(i), Some(vec![i as u8]));
+        let (key, neighbor) = neighbor_keys(i);
+        m.write(key, Some(value(i, 0)));
+        m.write(neighbor, Some(value(i, 1)));
+    }
+    let root = m.commit_and_check();
+
+    let Model { t, values } = m;
+    drop(t);
+    let mut t = Test::new_with_params(name, 4, 64_000, false, false);
+    for (key, value) in &values {
+        assert_eq!(t.read(*key).as_ref(), Some(value));
+    }
+    let (reopened_root, _, _) = t.commit();
+    assert_eq!(reopened_root, root);
+}
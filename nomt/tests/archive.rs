@@ -0,0 +1,81 @@
+//! Tests reading values at the roots of recent commits.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, KeyReadWrite, Node, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path, rollback: bool) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.rollback(rollback);
+    o.max_rollback_log_len(3);
+    Nomt::open(o).unwrap()
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, key: u8, value: Option<u8>) -> Node {
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![(
+            [key; 32],
+            KeyReadWrite::Write(value.map(|v| vec![v].into())),
+        )],
+    )
+    .unwrap()
+}
+
+fn read_at(nomt: &Nomt<Blake3Hasher>, root: Node, key: u8) -> Option<u8> {
+    nomt.read_at(root, [key; 32]).unwrap().map(|v| v[0])
+}
+
+#[test]
+fn read_at_recent_roots() {
+    let path = test_path("read_at_recent_roots");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path, true);
+
+    let empty = nomt.root();
+    let r1 = write(&nomt, 1, Some(1));
+    let r2 = write(&nomt, 1, Some(2));
+    let r3 = write(&nomt, 2, Some(3));
+    let r4 = write(&nomt, 1, None);
+
+    assert_eq!(read_at(&nomt, r4, 1), None);
+    assert_eq!(read_at(&nomt, r3, 1), Some(2));
+    assert_eq!(read_at(&nomt, r3, 2), Some(3));
+    assert_eq!(read_at(&nomt, r2, 1), Some(2));
+    assert_eq!(read_at(&nomt, r2, 2), None);
+    assert_eq!(read_at(&nomt, r1, 1), Some(1));
+
+    // Only the last three commits are retained.
+    assert!(matches!(
+        nomt.read_at(empty, [1; 32]),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    drop(nomt);
+
+    // The history survives a restart.
+    let nomt = open(&path, true);
+    assert_eq!(read_at(&nomt, r1, 1), Some(1));
+    assert_eq!(read_at(&nomt, r3, 2), Some(3));
+}
+
+#[test]
+fn read_at_requires_rollback() {
+    let path = test_path("read_at_requires_rollback");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path, false);
+
+    let r1 = write(&nomt, 1, Some(1));
+    write(&nomt, 1, Some(2));
+    assert!(nomt.read_at(r1, [1; 32]).is_err());
+    assert_eq!(read_at(&nomt, nomt.root(), 1), Some(2));
+}
@@ -0,0 +1,173 @@
+use nomt::{Blake3Hasher, IntegrityLevel, KeyReadWrite, Nomt, Options};
+use std::path::{Path, PathBuf};
+
+fn opts(path: &Path, backup: Option<&Path>) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(1024);
+    if let Some(backup) = backup {
+        o.incremental_backup(backup);
+    }
+    o
+}
+
+fn key(i: u16) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..2].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn value(i: u16) -> Vec<u8> {
+    // Every 50th value is stored in overflow pages.
+    let len = if i % 50 == 0 { 10_000 } else { 100 };
+    vec![i as u8; len]
+}
+
+// Returns the paths of the database, the backup and the materialized database.
+fn paths(name: &str) -> (PathBuf, PathBuf, PathBuf) {
+    let dir = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    (dir.join("db"), dir.join("backup"), dir.join("restored"))
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, keys: impl Iterator<Item = u16>, delete: bool) {
+    let session = nomt.begin_session();
+    let actuals = keys
+        .map(|i| {
+            let value = (!delete).then(|| value(i).into());
+            (key(i), KeyReadWrite::Write(value))
+        })
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+}
+
+#[test]
+fn materialize_latest_commit() {
+    let (path, backup, restored) = paths("materialize_latest_commit");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 0..500, false);
+    commit(&nomt, 500..1000, false);
+    commit(&nomt, (0..100).map(|i| i * 7), true);
+    let root = nomt.root();
+    drop(nomt);
+
+    assert_eq!(
+        Nomt::<Blake3Hasher>::materialize_backup(&backup, &restored).unwrap(),
+        Some(root)
+    );
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&restored, None)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(1)).unwrap(), Some(value(1).into()));
+    assert_eq!(nomt.read(key(7)).unwrap(), None);
+    assert_eq!(nomt.read(key(950)).unwrap(), Some(value(950).into()));
+    let integrity = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+}
+
+#[test]
+fn backup_continues_after_reopen() {
+    let (path, backup, restored) = paths("backup_continues_after_reopen");
+
+    // The base is taken of a database which already holds values.
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, None)).unwrap();
+    commit(&nomt, 0..300, false);
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 300..600, false);
+    drop(nomt);
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 600..900, false);
+    let root = nomt.root();
+    drop(nomt);
+
+    assert_eq!(
+        Nomt::<Blake3Hasher>::materialize_backup(&backup, &restored).unwrap(),
+        Some(root)
+    );
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&restored, None)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(100)).unwrap(), Some(value(100).into()));
+    assert_eq!(nomt.read(key(800)).unwrap(), Some(value(800).into()));
+}
+
+#[test]
+fn open_fails_if_backup_is_behind() {
+    let (path, backup, _) = paths("open_fails_if_backup_is_behind");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 0..100, false);
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, None)).unwrap();
+    commit(&nomt, 100..200, false);
+    drop(nomt);
+
+    assert!(Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).is_err());
+}
+
+#[test]
+fn materialize_rejects_damaged_increment() {
+    let (path, backup, restored) = paths("materialize_rejects_damaged_increment");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 0..100, false);
+    drop(nomt);
+
+    let increment = backup.join("0000000000.increment");
+    let mut buf = std::fs::read(&increment).unwrap();
+    buf[1000] ^= 1;
+    std::fs::write(&increment, buf).unwrap();
+    assert!(Nomt::<Blake3Hasher>::materialize_backup(&backup, &restored).is_err());
+}
+
+#[test]
+fn materialize_without_backup() {
+    let (_, backup, restored) = paths("materialize_without_backup");
+    assert_eq!(
+        Nomt::<Blake3Hasher>::materialize_backup(&backup, &restored).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn restore_verifies_backup() {
+    let (path, backup, restored) = paths("restore_verifies_backup");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap();
+    commit(&nomt, 0..500, false);
+    commit(&nomt, (0..100).map(|i| i * 3), true);
+    let root = nomt.root();
+    drop(nomt);
+
+    assert_eq!(
+        Nomt::<Blake3Hasher>::restore(&backup, &restored).unwrap(),
+        Some(root)
+    );
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&restored, None)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(1)).unwrap(), Some(value(1).into()));
+    assert_eq!(nomt.read(key(3)).unwrap(), None);
+}
+
+#[test]
+fn restore_refuses_damaged_base() {
+    let (path, backup, restored) = paths("restore_refuses_damaged_base");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, None)).unwrap();
+    commit(&nomt, 0..500, false);
+    drop(nomt);
+    // Take the base of the populated database.
+    drop(Nomt::<Blake3Hasher>::open(opts(&path, Some(&backup))).unwrap());
+
+    // Corrupt every page of the base holding a value.
+    let needle = value(2);
+    let ln = backup.join("base").join("ln");
+    let mut buf = std::fs::read(&ln).unwrap();
+    for page in buf.chunks_mut(4096) {
+        if let Some(offset) = page.windows(needle.len()).position(|w| w == &needle[..]) {
+            page[offset] ^= 1;
+        }
+    }
+    std::fs::write(&ln, buf).unwrap();
+
+    assert!(Nomt::<Blake3Hasher>::restore(&backup, &restored).is_err());
+    assert!(!restored.join("meta").exists());
+}
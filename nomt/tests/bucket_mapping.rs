@@ -0,0 +1,65 @@
+//! Tests the bucket mapping strategies of the hash-table.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, BucketMappingStrategy, KeyPath, KeyReadWrite, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path, bucket_mapping: BucketMappingStrategy) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.bucket_mapping(bucket_mapping);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+#[test]
+fn clustered_mapping_roundtrip() {
+    let path = test_path("clustered_mapping_roundtrip");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = open(&path, BucketMappingStrategy::Clustered);
+    let session = nomt.begin_session();
+    let mut actuals = (0..5000)
+        .map(|id| {
+            (
+                key(id),
+                KeyReadWrite::Write(Some(id.to_le_bytes().to_vec().into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let root = nomt.commit(session, actuals).unwrap();
+    drop(nomt);
+
+    // The strategy is fixed at creation, so the one passed on reopen is ignored. If it weren't,
+    // the pages couldn't be found and the root would differ.
+    let nomt = open(&path, BucketMappingStrategy::Hashed);
+    assert_eq!(nomt.root(), root);
+    for id in [0, 1234, 4999] {
+        assert_eq!(
+            nomt.read(key(id)).unwrap().as_deref(),
+            Some(&id.to_le_bytes()[..])
+        );
+    }
+
+    // Further commits go through the pages stored in the hash-table.
+    let session = nomt.begin_session();
+    let new_root = nomt
+        .commit(session, vec![(key(0), KeyReadWrite::Write(None))])
+        .unwrap();
+    assert_ne!(new_root, root);
+    drop(nomt);
+    let nomt = open(&path, BucketMappingStrategy::Clustered);
+    assert_eq!(nomt.root(), new_root);
+}
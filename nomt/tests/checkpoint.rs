@@ -0,0 +1,126 @@
+//! Tests named checkpoints.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, KeyReadWrite, Node, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.rollback(true);
+    o.max_rollback_log_len(3);
+    Nomt::open(o).unwrap()
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, key: u8, value: Option<u8>) -> Node {
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![(
+            [key; 32],
+            KeyReadWrite::Write(value.map(|v| vec![v].into())),
+        )],
+    )
+    .unwrap()
+}
+
+fn read(nomt: &Nomt<Blake3Hasher>, key: u8) -> Option<u8> {
+    nomt.read([key; 32]).unwrap().map(|v| v[0])
+}
+
+#[test]
+fn rollback_to_checkpoint() {
+    let path = test_path("rollback_to_checkpoint");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path);
+
+    write(&nomt, 1, Some(1));
+    let checkpoint = nomt.checkpoint("block 1").unwrap();
+    write(&nomt, 1, Some(2));
+    write(&nomt, 2, Some(2));
+    assert_eq!(nomt.checkpoint("block 3").unwrap(), nomt.root());
+
+    nomt.rollback_to("block 1").unwrap();
+    assert_eq!(nomt.root(), checkpoint);
+    assert_eq!(read(&nomt, 1), Some(1));
+    assert_eq!(read(&nomt, 2), None);
+
+    // Rolling back to the current root is a no-op.
+    nomt.rollback_to("block 1").unwrap();
+    assert_eq!(nomt.root(), checkpoint);
+
+    // The later checkpoint is no longer reachable.
+    assert!(matches!(
+        nomt.rollback_to("block 3"),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        nomt.rollback_to("unknown"),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+
+    assert!(nomt.remove_checkpoint("block 3").unwrap());
+    assert_eq!(
+        nomt.checkpoints(),
+        vec![("block 1".to_string(), checkpoint)]
+    );
+}
+
+#[test]
+fn checkpoints_survive_reopen() {
+    let path = test_path("checkpoints_survive_reopen");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path);
+
+    let checkpoint = write(&nomt, 1, Some(1));
+    nomt.checkpoint("a").unwrap();
+    write(&nomt, 1, None);
+    drop(nomt);
+
+    let nomt = open(&path);
+    assert_eq!(nomt.checkpoints(), vec![("a".to_string(), checkpoint)]);
+    nomt.rollback_to("a").unwrap();
+    assert_eq!(nomt.root(), checkpoint);
+    assert_eq!(read(&nomt, 1), Some(1));
+}
+
+#[test]
+fn checkpoint_beyond_rollback_log() {
+    let path = test_path("checkpoint_beyond_rollback_log");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path);
+
+    nomt.checkpoint("genesis").unwrap();
+    for i in 0..4 {
+        write(&nomt, i, Some(i));
+    }
+
+    // Only the last three commits are retained.
+    assert!(matches!(
+        nomt.rollback_to("genesis"),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    assert_eq!(read(&nomt, 0), Some(0));
+}
+
+#[test]
+fn checkpoint_requires_rollback() {
+    let path = test_path("checkpoint_requires_rollback");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(&path);
+    o.hashtable_buckets(10_000);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    assert!(matches!(
+        nomt.checkpoint("a"),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
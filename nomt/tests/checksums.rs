@@ -0,0 +1,115 @@
+use nomt::{Blake3Hasher, Error, KeyReadWrite, Nomt, Options};
+use std::{
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+const PAGE_SIZE: usize = 4096;
+
+fn opts(path: &Path, verify_checksums: bool) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(64);
+    o.verify_checksums(verify_checksums);
+    o
+}
+
+fn key(i: u8) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[0] = i;
+    key
+}
+
+// Populate a fresh database and close it.
+fn populate(name: &str) -> PathBuf {
+    let path = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, true)).unwrap();
+    let session = nomt.begin_session();
+    let actuals = (0..100)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(vec![i; 100].into()))))
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+    path
+}
+
+// Flip a byte in every non-empty page of a file, skipping the first `skip` pages.
+fn corrupt(path: &Path, file: &str, skip: u64) {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join(file))
+        .unwrap();
+    let pages = file.metadata().unwrap().len() / PAGE_SIZE as u64;
+    let mut page = vec![0; PAGE_SIZE];
+    for pn in skip..pages {
+        file.read_exact_at(&mut page, pn * PAGE_SIZE as u64)
+            .unwrap();
+        if page.iter().all(|b| *b == 0) {
+            continue;
+        }
+        page[100] ^= 0x10;
+        file.write_all_at(&page, pn * PAGE_SIZE as u64).unwrap();
+    }
+}
+
+#[test]
+fn clean_database_verifies() {
+    let path = populate("checksums_clean_database_verifies");
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, true)).unwrap();
+    for i in 0..100 {
+        assert_eq!(nomt.read(key(i)).unwrap().as_deref(), Some(&[i; 100][..]));
+    }
+}
+
+#[test]
+fn corrupted_leaf_detected() {
+    let path = populate("checksums_corrupted_leaf_detected");
+    corrupt(&path, "ln", 1);
+
+    // Without verification, the corruption goes unnoticed.
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, false)).unwrap();
+    nomt.read(key(0)).unwrap();
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path, true)).unwrap();
+    let result = nomt.read(key(0));
+    assert!(matches!(
+        result,
+        Err(Error::ChecksumMismatch { file: "ln", .. })
+    ));
+}
+
+#[test]
+fn corrupted_bucket_detected() {
+    let path = populate("checksums_corrupted_bucket_detected");
+    // The first page of the hash-table file holds the meta bytes.
+    corrupt(&path, "ht", 1);
+
+    // The root page is loaded on open, other pages when committing.
+    let result = Nomt::<Blake3Hasher>::open(opts(&path, true)).and_then(|nomt| {
+        let session = nomt.begin_session();
+        nomt.commit(
+            session,
+            vec![(key(0), KeyReadWrite::Write(Some(vec![1].into())))],
+        )
+    });
+    assert!(matches!(
+        result,
+        Err(Error::ChecksumMismatch { file: "ht", .. })
+    ));
+}
+
+#[test]
+fn corrupted_branch_detected() {
+    let path = populate("checksums_corrupted_branch_detected");
+    corrupt(&path, "bbn", 1);
+
+    let result = Nomt::<Blake3Hasher>::open(opts(&path, true));
+    assert!(matches!(
+        result,
+        Err(Error::ChecksumMismatch { file: "bbn", .. })
+    ));
+}
@@ -0,0 +1,164 @@
+//! Tests committing several sessions together with a single sync.
+
+use nomt::{
+    Blake3Hasher, Error, IntegrityLevel, KeyPath, KeyReadWrite, Node, Nomt, Options, Session,
+};
+use std::path::PathBuf;
+
+fn open(name: &str, reset: bool, rollback: bool) -> Nomt<Blake3Hasher> {
+    let path = PathBuf::from("test").join(name);
+    if reset {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.rollback(rollback);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+// Overwrite the keys of the round and delete the keys of the round before it.
+fn actuals(round: u32) -> Vec<(KeyPath, KeyReadWrite)> {
+    let writes = (round * 100..round * 100 + 500).map(|id| {
+        (
+            key(id),
+            KeyReadWrite::Write(Some(vec![round as u8; 40].into())),
+        )
+    });
+    let deletes =
+        (round.saturating_sub(1) * 100..round * 100).map(|id| (key(id), KeyReadWrite::Write(None)));
+    let mut actuals = writes.chain(deletes).collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    actuals
+}
+
+fn session(nomt: &Nomt<Blake3Hasher>, round: u32) -> Session {
+    let mut session = nomt.begin_session();
+    session.set_commit_tag(format!("round {round}"));
+    session
+}
+
+fn sync_seqn(name: &str) -> u32 {
+    Nomt::<Blake3Hasher>::inspect(PathBuf::from("test").join(name))
+        .unwrap()
+        .unwrap()
+        .sync_seqn
+}
+
+#[test]
+fn group_matches_separate_commits() {
+    let separate = open("group_matches_separate_commits_reference", true, false);
+    let roots = (0..4)
+        .map(|round| {
+            separate
+                .commit(session(&separate, round), actuals(round))
+                .unwrap()
+        })
+        .collect::<Vec<Node>>();
+
+    let name = "group_matches_separate_commits";
+    let nomt = open(name, true, false);
+    nomt.commit(session(&nomt, 0), actuals(0)).unwrap();
+    drop(nomt);
+    let seqn = sync_seqn(name);
+
+    let nomt = open(name, false, false);
+    let mut group = nomt.commit_group().unwrap();
+    let mut pending = Vec::new();
+    for round in 1..4 {
+        // Every session sees the commits before it in the group.
+        let session = session(&nomt, round);
+        assert_eq!(
+            session.read(key(round * 100 + 399)).unwrap().as_deref(),
+            Some(&[round as u8 - 1; 40][..])
+        );
+        pending.push(group.commit(session, actuals(round)).unwrap());
+        assert_eq!(nomt.root(), roots[round as usize]);
+    }
+    assert_eq!(group.len(), 3);
+    group.sync().unwrap();
+    for (pending, root) in pending.into_iter().zip(&roots[1..]) {
+        assert_eq!(pending.wait().unwrap(), *root);
+    }
+    assert_eq!(nomt.last_commit_tag().as_deref(), Some(&b"round 3"[..]));
+    drop(nomt);
+    assert_eq!(sync_seqn(name), seqn + 1);
+
+    let nomt = open(name, false, false);
+    assert_eq!(nomt.root(), roots[3]);
+    assert_eq!(
+        nomt.iter(..).collect::<Vec<_>>(),
+        separate.iter(..).collect::<Vec<_>>()
+    );
+    let integrity = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+}
+
+#[test]
+fn dropping_group_syncs() {
+    let name = "dropping_group_syncs";
+    let nomt = open(name, true, false);
+    let pending = {
+        let mut group = nomt.commit_group().unwrap();
+        group.commit(session(&nomt, 0), actuals(0)).unwrap()
+    };
+    let root = pending.wait().unwrap();
+    drop(nomt);
+
+    let nomt = open(name, false, false);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(0)).unwrap().as_deref(), Some(&[0; 40][..]));
+}
+
+#[test]
+fn group_proves_commits() {
+    let nomt = open("group_proves_commits", true, false);
+    let mut group = nomt.commit_group().unwrap();
+    let first = group.commit(session(&nomt, 0), actuals(0)).unwrap();
+    let (second, witness, witnessed) = group
+        .commit_and_prove(session(&nomt, 1), actuals(1))
+        .unwrap();
+    // The witness is against the root of the previous commit of the group.
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(first.root(), &witness, &witnessed).unwrap(),
+        second.root()
+    );
+    group.sync().unwrap();
+    assert_eq!(second.wait().unwrap(), nomt.root());
+}
+
+#[test]
+fn group_is_exclusive() {
+    let nomt = open("group_is_exclusive", true, false);
+    let mut group = nomt.commit_group().unwrap();
+    assert!(matches!(
+        nomt.commit_group(),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        nomt.commit(session(&nomt, 0), actuals(0)),
+        Err(Error::InvalidOperation(_))
+    ));
+    group.commit(session(&nomt, 0), actuals(0)).unwrap();
+    group.sync().unwrap();
+
+    // The database is committed to as usual once the group is synced.
+    nomt.commit(session(&nomt, 1), actuals(1)).unwrap();
+    let empty_group = nomt.commit_group().unwrap();
+    assert!(empty_group.is_empty());
+    empty_group.sync().unwrap();
+}
+
+#[test]
+fn group_requires_no_rollback() {
+    let nomt = open("group_requires_no_rollback", true, true);
+    assert!(matches!(
+        nomt.commit_group(),
+        Err(Error::InvalidOperation(_))
+    ));
+}
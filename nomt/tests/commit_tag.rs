@@ -0,0 +1,38 @@
+mod common;
+
+use common::Test;
+
+#[test]
+fn commit_tag_survives_reopen() {
+    {
+        let mut t = Test::new("commit_tag");
+        assert_eq!(t.last_commit_tag(), None);
+
+        t.write_id(0, Some(vec![1; 32]));
+        t.set_commit_tag(b"block-1");
+        let _ = t.commit();
+        assert_eq!(t.last_commit_tag(), Some(b"block-1".to_vec()));
+
+        t.write_id(1, Some(vec![2; 32]));
+        t.set_commit_tag(&[0xAA; nomt::MAX_COMMIT_TAG_LEN]);
+        let _ = t.commit();
+    }
+
+    let mut t = Test::new_with_params("commit_tag", 1, 64_000, false, false);
+    assert_eq!(
+        t.last_commit_tag(),
+        Some(vec![0xAA; nomt::MAX_COMMIT_TAG_LEN])
+    );
+
+    // A commit without a tag clears it.
+    t.write_id(2, Some(vec![3; 32]));
+    let _ = t.commit();
+    assert_eq!(t.last_commit_tag(), None);
+}
+
+#[test]
+#[should_panic]
+fn commit_tag_too_long() {
+    let mut t = Test::new("commit_tag_too_long");
+    t.set_commit_tag(&[0; nomt::MAX_COMMIT_TAG_LEN + 1]);
+}
@@ -1,8 +1,12 @@
-use nomt::{KeyPath, KeyReadWrite, Node, Nomt, Options, Session, Witness, WitnessedOperations};
+use nomt::{
+    KeyPath, KeyReadWrite, Node, Nomt, Options, Session, ValueRef, Witness, WitnessHook,
+    WitnessedOperations,
+};
 use std::{
     collections::{hash_map::Entry, HashMap},
     mem,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 pub fn account_path(id: u64) -> KeyPath {
@@ -33,6 +37,8 @@ fn opts(path: PathBuf) -> Options {
     let mut opts = Options::new();
     opts.path(path);
     opts.commit_concurrency(1);
+    // Tests choose the concurrency explicitly to exercise the coordination between workers.
+    opts.adaptive_commit_concurrency(false);
     opts
 }
 
@@ -81,6 +87,7 @@ impl Test {
     }
 
     pub fn write(&mut self, key: KeyPath, value: Option<Vec<u8>>) {
+        let value = value.map(Into::into);
         match self.access.entry(key) {
             Entry::Occupied(mut o) => {
                 o.get_mut().write(value);
@@ -104,11 +111,63 @@ impl Test {
                 let value = session.read(key).unwrap();
                 session.warm_up(key);
                 v.insert(KeyReadWrite::Read(value.clone()));
-                value
+                value.map(|v| v.to_vec())
             }
         }
     }
 
+    #[allow(unused)]
+    #[allow(unused)]
+    pub fn set_commit_tag(&mut self, tag: &[u8]) {
+        self.session.as_mut().unwrap().set_commit_tag(tag);
+    }
+
+    #[allow(unused)]
+    pub fn set_witness_hook(&mut self, hook: Arc<dyn WitnessHook>) {
+        self.session.as_mut().unwrap().set_witness_hook(hook);
+    }
+
+    #[allow(unused)]
+    pub fn last_commit_tag(&self) -> Option<Vec<u8>> {
+        self.nomt.last_commit_tag()
+    }
+
+    pub fn contains_id(&self, id: u64) -> bool {
+        self.session
+            .as_ref()
+            .unwrap()
+            .contains(account_path(id))
+            .unwrap()
+    }
+
+    #[allow(unused)]
+    pub fn read_ref_id(&self, id: u64) -> Option<ValueRef> {
+        self.session
+            .as_ref()
+            .unwrap()
+            .read_ref(account_path(id))
+            .unwrap()
+    }
+
+    /// Read within the session, returning the error instead of panicking if the read fails.
+    #[allow(unused)]
+    pub fn try_read_id(&self, id: u64) -> nomt::Result<Option<Vec<u8>>> {
+        self.session
+            .as_ref()
+            .unwrap()
+            .read(account_path(id))
+            .map(|value| value.map(|v| v.to_vec()))
+    }
+
+    #[allow(unused)]
+    pub fn value_size_id(&self, id: u64) -> Option<usize> {
+        self.session
+            .as_ref()
+            .unwrap()
+            .value_size(account_path(id))
+            .unwrap()
+    }
+
     pub fn commit(&mut self) -> (Node, Witness, WitnessedOperations) {
         let session = mem::take(&mut self.session).unwrap();
         let mut actual_access: Vec<_> = mem::take(&mut self.access).into_iter().collect();
@@ -117,6 +176,82 @@ impl Test {
         self.session = Some(self.nomt.begin_session());
         x
     }
+
+    /// Estimate the size of the witness of the next commit.
+    #[allow(unused)]
+    pub fn estimate_witness_size(&self) -> usize {
+        let mut actual_access: Vec<_> = self.access.clone().into_iter().collect();
+        actual_access.sort_by_key(|(k, _)| *k);
+        self.session
+            .as_ref()
+            .unwrap()
+            .estimate_witness_size(&actual_access)
+            .unwrap()
+    }
+
+    /// Commit, witnessing only the operations on the selected keys.
+    #[allow(unused)]
+    pub fn commit_selected(
+        &mut self,
+        select: impl Fn(&KeyPath) -> bool,
+    ) -> (Node, Witness, WitnessedOperations) {
+        let session = mem::take(&mut self.session).unwrap();
+        let mut actual_access: Vec<_> = mem::take(&mut self.access).into_iter().collect();
+        actual_access.sort_by_key(|(k, _)| *k);
+        let x = self
+            .nomt
+            .commit_and_prove_selected(session, actual_access, select)
+            .unwrap();
+        self.session = Some(self.nomt.begin_session());
+        x
+    }
+
+    /// Commit, streaming the witness and collecting the streamed paths and operations.
+    #[allow(unused)]
+    pub fn commit_and_stream_witness(&mut self) -> (Node, Witness, WitnessedOperations) {
+        let session = mem::take(&mut self.session).unwrap();
+        let mut actual_access: Vec<_> = mem::take(&mut self.access).into_iter().collect();
+        actual_access.sort_by_key(|(k, _)| *k);
+        let mut path_proofs = Vec::new();
+        let (mut reads, mut writes) = (Vec::new(), Vec::new());
+        let root = self
+            .nomt
+            .commit_and_stream_witness(session, actual_access, |path, path_reads, path_writes| {
+                path_proofs.push(path);
+                reads.extend(path_reads);
+                writes.extend(path_writes);
+            })
+            .unwrap();
+        self.session = Some(self.nomt.begin_session());
+        let witness = Witness {
+            path_proofs,
+            key_binning: self.nomt.key_binning(),
+        };
+        let witnessed = WitnessedOperations::new(&witness, reads, writes).unwrap();
+        (root, witness, witnessed)
+    }
+
+    /// Prove the reads of the session without committing it.
+    #[allow(unused)]
+    pub fn prove_reads(&mut self) -> nomt::Result<(Node, Witness, WitnessedOperations)> {
+        let session = mem::take(&mut self.session).unwrap();
+        let mut actual_access: Vec<_> = mem::take(&mut self.access).into_iter().collect();
+        actual_access.sort_by_key(|(k, _)| *k);
+        let res = self.nomt.prove_reads(session, actual_access);
+        self.session = Some(self.nomt.begin_session());
+        res
+    }
+
+    /// Commit, returning the error instead of panicking if the commit fails.
+    #[allow(unused)]
+    pub fn try_commit(&mut self) -> nomt::Result<Node> {
+        let session = mem::take(&mut self.session).unwrap();
+        let mut actual_access: Vec<_> = mem::take(&mut self.access).into_iter().collect();
+        actual_access.sort_by_key(|(k, _)| *k);
+        let res = self.nomt.commit(session, actual_access);
+        self.session = Some(self.nomt.begin_session());
+        res
+    }
 }
 
 pub fn read_balance(t: &mut Test, id: u64) -> Option<u64> {
@@ -0,0 +1,189 @@
+//! Tests compressing the values stored in the leaf node file.
+
+use std::path::PathBuf;
+
+use nomt::{
+    train_dictionary, Blake3Hasher, Compression, Error, IntegrityLevel, KeyPath, KeyReadWrite,
+    Nomt, Options, Value,
+};
+
+fn open(name: &str, compression: Compression, reset: bool) -> Nomt<Blake3Hasher> {
+    open_with(name, reset, |o| o.compression(compression)).unwrap()
+}
+
+fn open_with(
+    name: &str,
+    reset: bool,
+    configure: impl FnOnce(&mut Options),
+) -> Result<Nomt<Blake3Hasher>, Error> {
+    let path = PathBuf::from("test").join(name);
+    if reset {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    configure(&mut o);
+    Nomt::open(o)
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+// Random bytes, which don't compress at all.
+fn random(id: u32, len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    blake3::Hasher::new()
+        .update(&id.to_le_bytes())
+        .finalize_xof()
+        .fill(&mut bytes);
+    bytes
+}
+
+// Account-like records which compress well, with every 100th value being large and every 7th
+// value being random bytes. A few more large values still span overflow pages once compressed,
+// because they don't compress at all or only so much.
+fn value(id: u32) -> Value {
+    let value = if id % 1000 == 250 {
+        random(id, 20_000)
+    } else if id % 1000 == 750 {
+        random(id, 8_000).repeat(3)
+    } else if id % 100 == 0 {
+        id.to_le_bytes().repeat(5_000)
+    } else if id % 7 == 0 {
+        blake3::hash(&id.to_le_bytes()).as_bytes().repeat(1)
+    } else {
+        [&id.to_le_bytes()[..], &[0; 200]].concat()
+    };
+    value.into()
+}
+
+fn fill(nomt: &Nomt<Blake3Hasher>) {
+    let session = nomt.begin_session();
+    let mut actuals = (0..3000)
+        .map(|id| (key(id), KeyReadWrite::Write(Some(value(id)))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn check(nomt: &Nomt<Blake3Hasher>) {
+    for id in (0..3000)
+        .step_by(3)
+        .chain([100, 2900, 250, 1250, 750, 1750])
+    {
+        assert_eq!(nomt.read(key(id)).unwrap(), Some(value(id)));
+        let session = nomt.begin_session();
+        assert_eq!(session.value_size(key(id)).unwrap(), Some(value(id).len()));
+    }
+    assert_eq!(nomt.iter(..).count(), 3000);
+    assert!(nomt.iter(..).all(|(_, value)| value.len() >= 32));
+    let report = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(report.is_healthy(), "{:?}", report.issues);
+}
+
+#[test]
+fn compressed_values_roundtrip() {
+    let uncompressed = open("compression_none", Compression::None, true);
+    fill(&uncompressed);
+    let uncompressed_pages = uncompressed.stats().unwrap().ln.live_pages;
+
+    for (name, compression) in [
+        ("compression_lz4", Compression::Lz4),
+        ("compression_zstd", Compression::Zstd { level: 3 }),
+    ] {
+        let nomt = open(name, compression, true);
+        fill(&nomt);
+        assert_eq!(nomt.root(), uncompressed.root());
+        check(&nomt);
+        let pages = nomt.stats().unwrap().ln.live_pages;
+        assert!(
+            pages * 2 < uncompressed_pages,
+            "{name}: {pages} pages, {uncompressed_pages} uncompressed"
+        );
+    }
+}
+
+#[test]
+fn compression_is_persisted() {
+    let compression = Compression::Zstd { level: 5 };
+    let nomt = open("compression_is_persisted", compression, true);
+    fill(&nomt);
+    drop(nomt);
+
+    let info = Nomt::<Blake3Hasher>::inspect("test/compression_is_persisted")
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.compression, compression);
+
+    // The database keeps its compression when opened with other options.
+    let nomt = open("compression_is_persisted", Compression::None, false);
+    check(&nomt);
+}
+
+// Small records made of one of a few templates and an ID. They don't compress on their own, but
+// share most of their bytes with each other.
+fn record(id: u32) -> Value {
+    let template = blake3::hash(&(id % 8).to_le_bytes());
+    let owner = blake3::hash(b"owner");
+    [template.as_bytes(), owner.as_bytes(), &id.to_le_bytes()[..]]
+        .concat()
+        .into()
+}
+
+fn fill_records(nomt: &Nomt<Blake3Hasher>) {
+    let session = nomt.begin_session();
+    let mut actuals = (0..3000)
+        .map(|id| (key(id), KeyReadWrite::Write(Some(record(id)))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+#[test]
+fn dictionary_compression() {
+    let compression = Compression::Zstd { level: 3 };
+    let samples = (10_000..11_000).map(record).collect::<Vec<_>>();
+    let dictionary = train_dictionary(&samples, 4096).unwrap();
+
+    let plain = open("dictionary_compression_plain", compression, true);
+    fill_records(&plain);
+    let plain_pages = plain.stats().unwrap().ln.live_pages;
+
+    let nomt = open_with("dictionary_compression", true, |o| {
+        o.compression(compression);
+        o.compression_dictionary(dictionary.clone());
+    })
+    .unwrap();
+    fill_records(&nomt);
+    assert_eq!(nomt.root(), plain.root());
+    let pages = nomt.stats().unwrap().ln.live_pages;
+    assert!(
+        pages * 3 < plain_pages * 2,
+        "{pages} pages, {plain_pages} without a dictionary"
+    );
+    drop(nomt);
+
+    let info = Nomt::<Blake3Hasher>::inspect("test/dictionary_compression")
+        .unwrap()
+        .unwrap();
+    assert_eq!(info.compression_dictionary_len, dictionary.len());
+
+    // The dictionary is loaded from the database when it is reopened.
+    let nomt = open("dictionary_compression", Compression::None, false);
+    for id in (0..3000).step_by(7) {
+        assert_eq!(nomt.read(key(id)).unwrap(), Some(record(id)));
+    }
+    assert_eq!(nomt.iter(..).count(), 3000);
+}
+
+#[test]
+fn dictionary_requires_zstd() {
+    let result = open_with("dictionary_requires_zstd", true, |o| {
+        o.compression(Compression::Lz4);
+        o.compression_dictionary(vec![1, 2, 3]);
+    });
+    assert!(matches!(result, Err(Error::InvalidOperation(_))));
+}
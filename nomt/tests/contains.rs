@@ -0,0 +1,22 @@
+mod common;
+
+use common::Test;
+
+#[test]
+fn contains_small_and_large_values() {
+    let mut t = Test::new("contains");
+
+    t.write_id(0, Some(vec![1; 32]));
+    t.write_id(1, Some(vec![2; 4096 * 16]));
+    let _ = t.commit();
+
+    assert!(t.contains_id(0));
+    assert!(t.contains_id(1));
+    assert!(!t.contains_id(2));
+
+    t.write_id(0, None);
+    let _ = t.commit();
+
+    assert!(!t.contains_id(0));
+    assert!(t.contains_id(1));
+}
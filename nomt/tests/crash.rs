@@ -0,0 +1,351 @@
+//! Crash tests for every step of a sync.
+//!
+//! A sync writes the WAL and the b-tree pages, then the meta file, then the hash-table file, and
+//! finally truncates the WAL. These tests interrupt the sync at every step, optionally tearing the
+//! writes which were in flight, and check that reopening lands on either the old or the new root
+//! with all reads consistent with that root.
+
+use nomt::{Blake3Hasher, Error, KeyPath, KeyReadWrite, Node, Nomt, Options, SyncCrashPoint};
+use std::{
+    collections::BTreeMap,
+    fs::OpenOptions,
+    os::unix::fs::FileExt as _,
+    path::{Path, PathBuf},
+};
+
+type State = BTreeMap<KeyPath, Vec<u8>>;
+type Changes = BTreeMap<KeyPath, Option<Vec<u8>>>;
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path, crash_point: Option<SyncCrashPoint>) -> Nomt<Blake3Hasher> {
+    Nomt::open(opts(path, crash_point)).unwrap()
+}
+
+fn opts(path: &Path, crash_point: Option<SyncCrashPoint>) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.commit_concurrency(1);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    if let Some(crash_point) = crash_point {
+        o.crash_on_sync(crash_point);
+    }
+    o
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn value(id: u32, version: u8) -> Vec<u8> {
+    // Every 50th value is large enough to be stored in overflow pages.
+    let len = if id % 50 == 0 { 20_000 } else { 32 };
+    vec![version.wrapping_add(id as u8); len]
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, changes: &Changes) -> Node {
+    let session = nomt.begin_session();
+    let actuals = changes
+        .iter()
+        .map(|(k, v)| {
+            session.warm_up(*k);
+            (*k, KeyReadWrite::Write(v.clone().map(Into::into)))
+        })
+        .collect();
+    nomt.commit(session, actuals).unwrap()
+}
+
+fn apply(state: &mut State, changes: &Changes) {
+    for (k, v) in changes {
+        match v {
+            Some(v) => state.insert(*k, v.clone()),
+            None => state.remove(k),
+        };
+    }
+}
+
+fn initial_changes() -> Changes {
+    (0..500).map(|id| (key(id), Some(value(id, 0)))).collect()
+}
+
+fn crash_changes() -> Changes {
+    let updates = (0..100).map(|id| (key(id), Some(value(id, 1))));
+    let deletes = (100..200).map(|id| (key(id), None));
+    let inserts = (500..700).map(|id| (key(id), Some(value(id, 1))));
+    updates.chain(deletes).chain(inserts).collect()
+}
+
+fn after_changes() -> Changes {
+    (150..600).map(|id| (key(id), Some(value(id, 2)))).collect()
+}
+
+fn assert_state(nomt: &Nomt<Blake3Hasher>, root: Node, state: &State) {
+    assert_eq!(nomt.root(), root);
+    for id in 0..700 {
+        let k = key(id);
+        assert_eq!(
+            nomt.read(k).unwrap().map(|v| v.to_vec()),
+            state.get(&k).cloned(),
+            "mismatch for key {id}"
+        );
+    }
+}
+
+/// How to tamper with the files after the crash.
+enum Tear {
+    None,
+    /// Truncate the WAL to its first page.
+    WalPrefix,
+    /// Remove the WAL altogether.
+    WalEmpty,
+    /// Zero the pages appended to the leaf and branch node files by the interrupted sync.
+    BeatreeTail,
+}
+
+fn zero_tail(path: &Path, old_len: u64) {
+    let file = OpenOptions::new().write(true).open(path).unwrap();
+    let len = file.metadata().unwrap().len();
+    file.set_len(old_len).unwrap();
+    file.set_len(len).unwrap();
+}
+
+fn run(name: &str, crash_point: SyncCrashPoint, tear: Tear, expect_new_root: bool) {
+    let path = test_path(name);
+    let _ = std::fs::remove_dir_all(&path);
+
+    let mut old_state = State::new();
+    apply(&mut old_state, &initial_changes());
+    let mut new_state = old_state.clone();
+    apply(&mut new_state, &crash_changes());
+
+    let old_root = commit(&open(&path, None), &initial_changes());
+    let new_root = {
+        let reference_path = test_path(&format!("{name}_reference"));
+        let _ = std::fs::remove_dir_all(&reference_path);
+        let reference = open(&reference_path, None);
+        commit(&reference, &initial_changes());
+        commit(&reference, &crash_changes())
+    };
+
+    let ln_len = std::fs::metadata(path.join("ln")).unwrap().len();
+    let bbn_len = std::fs::metadata(path.join("bbn")).unwrap().len();
+    {
+        let nomt = open(&path, Some(crash_point));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            commit(&nomt, &crash_changes());
+        }));
+        assert!(r.is_err());
+    }
+
+    match tear {
+        Tear::None => {}
+        Tear::WalPrefix => {
+            let wal = OpenOptions::new()
+                .write(true)
+                .open(path.join("wal"))
+                .unwrap();
+            wal.set_len(4096).unwrap();
+        }
+        Tear::WalEmpty => {
+            let wal = OpenOptions::new()
+                .write(true)
+                .open(path.join("wal"))
+                .unwrap();
+            wal.set_len(0).unwrap();
+        }
+        Tear::BeatreeTail => {
+            zero_tail(&path.join("ln"), ln_len);
+            zero_tail(&path.join("bbn"), bbn_len);
+        }
+    }
+
+    let (root, mut state) = if expect_new_root {
+        (new_root, new_state)
+    } else {
+        (old_root, old_state)
+    };
+
+    let nomt = open(&path, None);
+    assert_state(&nomt, root, &state);
+
+    // The database keeps working after recovery.
+    let root = commit(&nomt, &after_changes());
+    apply(&mut state, &after_changes());
+    assert_state(&nomt, root, &state);
+    drop(nomt);
+
+    let nomt = open(&path, None);
+    assert_state(&nomt, root, &state);
+}
+
+#[test]
+fn crash_before_meta() {
+    run(
+        "crash_before_meta",
+        SyncCrashPoint::BeforeMeta,
+        Tear::None,
+        false,
+    );
+}
+
+#[test]
+fn crash_before_meta_torn_wal() {
+    run(
+        "crash_before_meta_torn_wal",
+        SyncCrashPoint::BeforeMeta,
+        Tear::WalPrefix,
+        false,
+    );
+}
+
+#[test]
+fn crash_before_meta_no_wal() {
+    run(
+        "crash_before_meta_no_wal",
+        SyncCrashPoint::BeforeMeta,
+        Tear::WalEmpty,
+        false,
+    );
+}
+
+#[test]
+fn crash_before_meta_torn_beatree() {
+    run(
+        "crash_before_meta_torn_beatree",
+        SyncCrashPoint::BeforeMeta,
+        Tear::BeatreeTail,
+        false,
+    );
+}
+
+#[test]
+fn crash_after_meta() {
+    run(
+        "crash_after_meta",
+        SyncCrashPoint::AfterMeta,
+        Tear::None,
+        true,
+    );
+}
+
+#[test]
+fn crash_after_ht() {
+    run("crash_after_ht", SyncCrashPoint::AfterHt, Tear::None, true);
+}
+
+#[test]
+fn crash_after_meta_damaged_wal() {
+    let path = test_path("crash_after_meta_damaged_wal");
+    let _ = std::fs::remove_dir_all(&path);
+    commit(&open(&path, None), &initial_changes());
+    {
+        let nomt = open(&path, Some(SyncCrashPoint::AfterMeta));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            commit(&nomt, &crash_changes());
+        }));
+        assert!(r.is_err());
+    }
+
+    // The meta records the crashed sync, so its WAL has to be replayed. Flip a bit in one of its
+    // entries.
+    let wal = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("wal"))
+        .unwrap();
+    let mut byte = [0];
+    wal.read_exact_at(&mut byte, 1000).unwrap();
+    wal.write_all_at(&[byte[0] ^ 1], 1000).unwrap();
+
+    let result = Nomt::<Blake3Hasher>::open(opts(&path, None));
+    assert!(matches!(result, Err(Error::Corruption(_))));
+}
+
+#[test]
+fn crash_after_meta_compressed_wal() {
+    let path = test_path("crash_after_meta_compressed_wal");
+    let _ = std::fs::remove_dir_all(&path);
+    let open_compressed = |crash_point| {
+        let mut o = opts(&path, crash_point);
+        o.compress_wal(true);
+        Nomt::<Blake3Hasher>::open(o).unwrap()
+    };
+
+    let mut state = State::new();
+    apply(&mut state, &initial_changes());
+    apply(&mut state, &crash_changes());
+    let new_root = {
+        let reference_path = test_path("crash_after_meta_compressed_wal_reference");
+        let _ = std::fs::remove_dir_all(&reference_path);
+        let reference = open(&reference_path, None);
+        commit(&reference, &initial_changes());
+        commit(&reference, &crash_changes())
+    };
+
+    commit(&open_compressed(None), &initial_changes());
+    {
+        let nomt = open_compressed(Some(SyncCrashPoint::AfterMeta));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            commit(&nomt, &crash_changes());
+        }));
+        assert!(r.is_err());
+    }
+
+    // The entries following the start entry of the WAL are compressed into one.
+    let wal = std::fs::File::open(path.join("wal")).unwrap();
+    let mut tag = [0];
+    wal.read_exact_at(&mut tag, 17).unwrap();
+    assert_eq!(tag, [4]);
+
+    let nomt = open_compressed(None);
+    assert_state(&nomt, new_root, &state);
+}
+
+#[test]
+fn crash_after_meta_recovery_report() {
+    let path = test_path("crash_after_meta_recovery_report");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path, None);
+    commit(&nomt, &initial_changes());
+    assert!(nomt.recovery_report().is_none());
+    drop(nomt);
+    {
+        let nomt = open(&path, Some(SyncCrashPoint::AfterMeta));
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            commit(&nomt, &crash_changes());
+        }));
+        assert!(r.is_err());
+    }
+    let wal_bytes = std::fs::metadata(path.join("wal")).unwrap().len();
+
+    let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut o = opts(&path, None);
+    o.on_recovery_progress({
+        let progress = progress.clone();
+        move |p| progress.lock().unwrap().push(p)
+    });
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    let report = nomt.recovery_report().unwrap();
+    assert_eq!(report.sync_seqn, 2);
+    assert_eq!(report.wal_bytes, wal_bytes);
+    assert!(report.entries > 0);
+    assert!(report.buckets_updated > 0);
+    assert!(report.bytes_written >= report.buckets_updated as u64 * 4096);
+
+    let progress = progress.lock().unwrap();
+    let (first, last) = (progress.first().unwrap(), progress.last().unwrap());
+    assert_eq!(first.bytes_written, 0);
+    assert_eq!(last.entries_applied, report.entries);
+    assert_eq!(last.entries, report.entries);
+    assert_eq!(last.bytes_written, report.buckets_updated as u64 * 4096);
+    drop(progress);
+
+    // The WAL is replayed once.
+    drop(nomt);
+    assert!(open(&path, None).recovery_report().is_none());
+}
@@ -0,0 +1,158 @@
+//! Tests deleting all the keys under a prefix.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn open(name: &str, clean: bool) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    if clean {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.rollback(true);
+    Nomt::open(o).unwrap()
+}
+
+/// Keys of "accounts", each with a number of "storage slots" sharing the first 2 bytes.
+fn key(account: u8, slot: u16) -> KeyPath {
+    let mut key = *blake3::hash(&slot.to_le_bytes()).as_bytes();
+    key[0] = account;
+    key[1] = 0xAB;
+    key
+}
+
+fn expected_root(values: &BTreeMap<KeyPath, Vec<u8>>) -> Node {
+    let ops = values
+        .iter()
+        .map(|(k, v)| (*k, *blake3::hash(v).as_bytes()))
+        .collect::<Vec<_>>();
+    nomt_core::update::build_trie::<Blake3Hasher>(0, ops, |_| {})
+}
+
+fn write_all(nomt: &Nomt<Blake3Hasher>, values: &BTreeMap<KeyPath, Vec<u8>>) -> Node {
+    let session = nomt.begin_session();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone().into()))))
+        .collect();
+    nomt.commit(session, actuals).unwrap()
+}
+
+fn remove_prefix(values: &mut BTreeMap<KeyPath, Vec<u8>>, prefix: &BitSlice<u8, Msb0>) {
+    values.retain(|k, _| !k.view_bits::<Msb0>().starts_with(prefix));
+}
+
+#[test]
+fn delete_prefix_removes_subtrie() {
+    let nomt = open("delete_prefix_removes_subtrie", true);
+
+    let mut values = BTreeMap::new();
+    for account in 1..=3u8 {
+        for slot in 0..300 {
+            values.insert(key(account, slot), vec![account; 1 + slot as usize % 100]);
+        }
+    }
+    write_all(&nomt, &values);
+
+    // Delete account 2 entirely, while another key shares the first few bits of the prefix.
+    let prefix = [2u8, 0xAB];
+    let prefix = &prefix.view_bits::<Msb0>()[..16];
+    let mut session = nomt.begin_session();
+    session.delete_prefix(prefix).unwrap();
+    let root = nomt.commit(session, vec![]).unwrap();
+    remove_prefix(&mut values, prefix);
+    assert_eq!(root, expected_root(&values));
+
+    let session = nomt.begin_session();
+    assert_eq!(session.subtree_root(prefix).unwrap(), [0; 32]);
+    for slot in 0..300 {
+        assert_eq!(session.read(key(2, slot)).unwrap(), None);
+        assert_eq!(
+            session.read(key(3, slot)).unwrap().as_deref(),
+            Some(&vec![3; 1 + slot as usize % 100][..])
+        );
+    }
+    drop(session);
+
+    // A prefix covering nothing is a no-op.
+    let mut session = nomt.begin_session();
+    session.delete_prefix(&prefix.to_bitvec()).unwrap();
+    assert_eq!(nomt.commit(session, vec![]).unwrap(), root);
+
+    // Deletions survive a restart.
+    drop(nomt);
+    let nomt = open("delete_prefix_removes_subtrie", false);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(2, 0)).unwrap(), None);
+}
+
+#[test]
+fn delete_prefix_with_actuals() {
+    let nomt = open("delete_prefix_with_actuals", true);
+
+    let mut values = BTreeMap::new();
+    for account in 1..=2u8 {
+        for slot in 0..100 {
+            values.insert(key(account, slot), vec![account]);
+        }
+    }
+    write_all(&nomt, &values);
+
+    // Writes under the prefix take effect, reads under it are deleted and writes elsewhere are
+    // unaffected.
+    let prefix = [1u8];
+    let prefix = prefix.view_bits::<Msb0>();
+    let mut session = nomt.begin_session();
+    session.delete_prefix(prefix).unwrap();
+    let mut actuals = vec![
+        (key(1, 0), KeyReadWrite::Read(Some(vec![1].into()))),
+        (key(1, 1), KeyReadWrite::Write(Some(vec![9].into()))),
+        (key(1, 500), KeyReadWrite::Write(Some(vec![9].into()))),
+        (key(2, 0), KeyReadWrite::Write(Some(vec![9].into()))),
+    ];
+    actuals.sort_by_key(|(k, _)| *k);
+    let root = nomt.commit(session, actuals).unwrap();
+
+    remove_prefix(&mut values, prefix);
+    values.insert(key(1, 1), vec![9]);
+    values.insert(key(1, 500), vec![9]);
+    values.insert(key(2, 0), vec![9]);
+    assert_eq!(root, expected_root(&values));
+
+    // The deletion can be rolled back.
+    nomt.rollback(1).unwrap();
+    assert_eq!(nomt.read(key(1, 0)).unwrap().as_deref(), Some(&[1][..]));
+    assert_eq!(nomt.read(key(1, 99)).unwrap().as_deref(), Some(&[1][..]));
+    assert_eq!(nomt.read(key(1, 500)).unwrap(), None);
+}
+
+#[test]
+fn delete_everything() {
+    let nomt = open("delete_everything", true);
+
+    let mut values = BTreeMap::new();
+    for slot in 0..200 {
+        values.insert(key(slot as u8, slot), vec![1]);
+    }
+    write_all(&nomt, &values);
+
+    let mut session = nomt.begin_session();
+    session.delete_prefix(BitSlice::empty()).unwrap();
+    assert_eq!(nomt.commit(session, vec![]).unwrap(), [0; 32]);
+    for key in values.keys() {
+        assert_eq!(nomt.read(*key).unwrap(), None);
+    }
+
+    let mut session = nomt.begin_session();
+    let too_long = [0u8; 33];
+    assert!(matches!(
+        session.delete_prefix(too_long.view_bits::<Msb0>()),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
@@ -0,0 +1,81 @@
+//! Tests that the write-ahead log is the same byte for byte regardless of the number of commit
+//! workers, so that replicas and replay tooling can rely on it.
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Node, Nomt, Options, SyncCrashPoint};
+use std::path::{Path, PathBuf};
+
+fn open(path: &Path, commit_concurrency: usize, clean: bool) -> Nomt<Blake3Hasher> {
+    if clean {
+        let _ = std::fs::remove_dir_all(path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.commit_concurrency(commit_concurrency);
+    o.adaptive_commit_concurrency(false);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(20_000);
+    // Keep the WAL around after the commit, instead of truncating it.
+    o.crash_on_sync(SyncCrashPoint::AfterHt);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u64) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn actuals(round: u64) -> Vec<(KeyPath, KeyReadWrite)> {
+    let mut actuals = (0..1500)
+        .map(|id| {
+            let value = ((id + round) % 7 != 0).then(|| vec![round as u8; 1 + id as usize % 64]);
+            (
+                key(id * (round + 1)),
+                KeyReadWrite::Write(value.map(Into::into)),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    actuals.dedup_by_key(|(k, _)| *k);
+    actuals
+}
+
+/// Commits the actuals of the round, returning the new root and the contents of the WAL.
+fn commit_round(path: &Path, commit_concurrency: usize, round: u64) -> (Node, Vec<u8>) {
+    let nomt = open(path, commit_concurrency, round == 0);
+    let session = nomt.begin_session();
+    let actuals = actuals(round);
+    for (k, _) in &actuals {
+        session.warm_up(*k);
+    }
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        nomt.commit(session, actuals).unwrap();
+    }));
+    assert!(result.is_err());
+    drop(nomt);
+    let wal = std::fs::read(path.join("wal")).unwrap();
+
+    // Reopening recovers the commit.
+    let root = open(path, commit_concurrency, false).root();
+    (root, wal)
+}
+
+#[test]
+fn wal_is_independent_of_worker_count() {
+    let paths = [1, 4].map(|workers| {
+        let mut path = PathBuf::from("test");
+        path.push(format!("wal_is_independent_of_worker_count_{workers}"));
+        (path, workers)
+    });
+
+    for round in 0..2 {
+        let outputs = paths
+            .iter()
+            .map(|(path, workers)| commit_round(path, *workers, round))
+            .collect::<Vec<_>>();
+        let (root, wal) = &outputs[0];
+        assert!(!wal.is_empty());
+        for output in &outputs[1..] {
+            assert_eq!(output.0, *root);
+            assert!(output.1 == *wal, "WAL differs in round {round}");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Tests running out of disk space in the middle of a commit.
+//!
+//! The database is placed on a size-limited tmpfs, which requires the privilege to mount, so the
+//! tests are ignored by default. Run them as root with:
+//!
+//! ```text
+//! cargo test -p nomt --test disk_full -- --ignored
+//! ```
+//!
+//! Failing writes are also covered without privileges by `write_failure.rs`.
+
+mod common;
+
+use common::Test;
+use std::{
+    fs::File,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A tmpfs mounted for the duration of the test.
+struct Tmpfs {
+    path: PathBuf,
+}
+
+impl Tmpfs {
+    fn mount(name: &str, size: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("nomt_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&path).unwrap();
+        let mounted = Command::new("mount")
+            .args(["-t", "tmpfs", "-o", &format!("size={size}"), "tmpfs"])
+            .arg(&path)
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !mounted {
+            let _ = std::fs::remove_dir(&path);
+            panic!("cannot mount a tmpfs at {}, run as root", path.display());
+        }
+        Self { path }
+    }
+}
+
+impl Drop for Tmpfs {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.path).status();
+        let _ = std::fs::remove_dir(&self.path);
+    }
+}
+
+/// Fill the filesystem up to the given amount of free space.
+fn fill(dir: &Path, leave_free: u64) {
+    let path = dir.join("filler");
+    let mut filler = File::create(&path).unwrap();
+    let chunk = vec![0xFF; 64 * 1024];
+    while filler.write_all(&chunk).is_ok() {}
+    let len = filler.metadata().unwrap().len();
+    filler.set_len(len.saturating_sub(leave_free)).unwrap();
+}
+
+fn open(dir: &Path, clean: bool) -> Test {
+    Test::new_with_params(dir.join("db"), 1, 4096, false, clean)
+}
+
+#[test]
+#[ignore = "requires root to mount a tmpfs"]
+fn disk_full_during_wal_write() {
+    let tmpfs = Tmpfs::mount("disk_full_during_wal_write", "64m");
+
+    // Rewrite the same keys a few times, so that the beatree reuses the pages it frees instead of
+    // growing its files.
+    let mut t = open(&tmpfs.path, true);
+    for round in 0..3u64 {
+        for id in 0..5000 {
+            common::set_balance(&mut t, id, round);
+        }
+        t.commit();
+    }
+    let root = t.commit().0;
+
+    // Leave enough space for the beatree, but not for the WAL.
+    fill(&tmpfs.path, 512 * 1024);
+    for id in 0..5000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    assert!(matches!(t.try_commit(), Err(nomt::Error::DiskFull)));
+    assert_eq!(
+        std::fs::metadata(tmpfs.path.join("db/wal")).unwrap().len(),
+        0
+    );
+
+    // The database refuses to read or commit until it is reopened.
+    assert!(matches!(
+        t.try_read_id(0),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    common::set_balance(&mut t, 0, 1000);
+    assert!(matches!(
+        t.try_commit(),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    drop(t);
+
+    // The previously committed state is intact.
+    std::fs::remove_file(tmpfs.path.join("filler")).unwrap();
+    let mut t = open(&tmpfs.path, false);
+    assert_eq!(t.commit().0, root);
+    for id in 0..5000 {
+        assert_eq!(common::read_balance(&mut t, id), Some(2));
+    }
+    for id in 0..5000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    t.commit();
+    drop(t);
+
+    let mut t = open(&tmpfs.path, false);
+    for id in 0..5000 {
+        assert_eq!(common::read_balance(&mut t, id), Some(1000));
+    }
+}
+
+#[test]
+#[ignore = "requires root to mount a tmpfs"]
+fn disk_full_during_beatree_growth() {
+    let tmpfs = Tmpfs::mount("disk_full_during_beatree_growth", "64m");
+
+    let mut t = open(&tmpfs.path, true);
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1);
+    }
+    let root = t.commit().0;
+
+    // Leave too little space for the overflow pages holding the new values.
+    fill(&tmpfs.path, 2 * 1024 * 1024);
+    for id in 1000..3000 {
+        t.write_id(id, Some(vec![id as u8; 4096]));
+    }
+    assert!(matches!(t.try_commit(), Err(nomt::Error::DiskFull)));
+    assert!(matches!(
+        t.try_read_id(1000),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    common::set_balance(&mut t, 0, 2);
+    assert!(matches!(
+        t.try_commit(),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    drop(t);
+
+    std::fs::remove_file(tmpfs.path.join("filler")).unwrap();
+    let mut t = open(&tmpfs.path, false);
+    assert_eq!(t.commit().0, root);
+    for id in 0..1000 {
+        assert_eq!(common::read_balance(&mut t, id), Some(1));
+    }
+    assert_eq!(common::read_balance(&mut t, 1000), None);
+}
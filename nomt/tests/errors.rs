@@ -0,0 +1,112 @@
+use nomt::{Blake3Hasher, Error, KeyReadWrite, Nomt, Options};
+use std::path::PathBuf;
+
+fn opts(name: &str) -> Options {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(name);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o
+}
+
+#[test]
+fn zero_commit_concurrency() {
+    let mut o = opts("errors_zero_commit_concurrency");
+    o.commit_concurrency(0);
+    let result = Nomt::<Blake3Hasher>::open(o);
+    assert!(matches!(result, Err(Error::InvalidOperation(_))));
+}
+
+#[test]
+fn unsorted_actuals() {
+    let nomt = Nomt::<Blake3Hasher>::open(opts("errors_unsorted_actuals")).unwrap();
+    let session = nomt.begin_session();
+    let result = nomt.commit(
+        session,
+        vec![
+            ([2; 32], KeyReadWrite::Write(Some(vec![2].into()))),
+            ([1; 32], KeyReadWrite::Write(Some(vec![1].into()))),
+        ],
+    );
+    assert!(matches!(result, Err(Error::InvalidActuals(_))));
+
+    // The database is still usable afterwards.
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([1; 32], KeyReadWrite::Write(Some(vec![1].into())))],
+    )
+    .unwrap();
+}
+
+#[test]
+fn rollback_not_enabled() {
+    let nomt = Nomt::<Blake3Hasher>::open(opts("errors_rollback_not_enabled")).unwrap();
+    assert!(matches!(nomt.rollback(1), Err(Error::InvalidOperation(_))));
+}
+
+#[test]
+fn max_open_files_too_low() {
+    let mut o = opts("errors_max_open_files_too_low");
+    o.max_open_files(4);
+    let result = Nomt::<Blake3Hasher>::open(o);
+    assert!(matches!(result, Err(Error::InvalidOperation(_))));
+
+    let mut o = opts("errors_max_open_files_too_low");
+    o.io_workers(1);
+    o.max_open_files(16);
+    Nomt::<Blake3Hasher>::open(o).unwrap();
+}
+
+#[test]
+fn incompatible_format() {
+    let o = opts("errors_incompatible_format");
+    let path = PathBuf::from("test/errors_incompatible_format");
+    let nomt = Nomt::<Blake3Hasher>::open(o.clone()).unwrap();
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([1; 32], KeyReadWrite::Write(Some(vec![1].into())))],
+    )
+    .unwrap();
+    drop(nomt);
+
+    let meta = std::fs::read(path.join("meta")).unwrap();
+    let format_offset = meta.windows(4).position(|w| w == b"NOMT").unwrap();
+    let version = u32::from_le_bytes(
+        meta[format_offset + 4..format_offset + 8]
+            .try_into()
+            .unwrap(),
+    );
+
+    let open_with_format = |format: [u8; 8]| {
+        let mut meta = meta.clone();
+        meta[format_offset..format_offset + 8].copy_from_slice(&format);
+        std::fs::write(path.join("meta"), meta).unwrap();
+        Nomt::<Blake3Hasher>::open(o.clone())
+    };
+
+    // A meta file written before the format was versioned.
+    let result = open_with_format([0; 8]);
+    assert!(matches!(
+        result,
+        Err(Error::IncompatibleFormat { found: 0, supported }) if supported == version
+    ));
+
+    let mut newer = *b"NOMT\0\0\0\0";
+    newer[4..].copy_from_slice(&(version + 1).to_le_bytes());
+    let result = open_with_format(newer);
+    assert!(matches!(
+        result,
+        Err(Error::IncompatibleFormat { found, .. }) if found == version + 1
+    ));
+
+    let mut current = *b"NOMT\0\0\0\0";
+    current[4..].copy_from_slice(&version.to_le_bytes());
+    open_with_format(current).unwrap();
+}
@@ -0,0 +1,75 @@
+//! Tests estimating the number of keys and the size of the data.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(50_000);
+    Nomt::open(o).unwrap()
+}
+
+fn key(i: u64) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+/// Values of varying size, some of them stored in overflow pages.
+fn value_len(i: u64) -> usize {
+    if i % 100 == 0 {
+        10_000
+    } else {
+        1 + (i % 64) as usize
+    }
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, ids: impl Iterator<Item = u64>, delete: bool) {
+    let mut actuals = ids
+        .map(|i| {
+            let value = (!delete).then(|| vec![1; value_len(i)].into());
+            (key(i), KeyReadWrite::Write(value))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    let session = nomt.begin_session();
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn data_size(ids: impl Iterator<Item = u64>) -> u64 {
+    ids.map(|i| 32 + value_len(i) as u64).sum()
+}
+
+#[test]
+fn estimate_is_exact_for_small_databases() {
+    let nomt = open("estimate_is_exact_for_small_databases");
+    assert_eq!(nomt.estimate_num_keys(), 0);
+    assert_eq!(nomt.estimate_data_size(), 0);
+
+    write(&nomt, 0..300, false);
+    assert_eq!(nomt.estimate_num_keys(), 300);
+    assert_eq!(nomt.estimate_data_size(), data_size(0..300));
+
+    write(&nomt, 0..100, true);
+    assert_eq!(nomt.estimate_num_keys(), 200);
+    assert_eq!(nomt.estimate_data_size(), data_size(100..300));
+}
+
+#[test]
+fn estimate_is_close_for_large_databases() {
+    let nomt = open("estimate_is_close_for_large_databases");
+    write(&nomt, 0..40_000, false);
+
+    let keys = nomt.estimate_num_keys() as f64;
+    assert!((keys / 40_000.0 - 1.0).abs() < 0.15, "{keys}");
+    let bytes = nomt.estimate_data_size() as f64;
+    let expected = data_size(0..40_000) as f64;
+    assert!(
+        (bytes / expected - 1.0).abs() < 0.25,
+        "{bytes} vs {expected}"
+    );
+}
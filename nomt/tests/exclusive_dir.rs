@@ -4,19 +4,28 @@ use std::path::PathBuf;
 
 use nomt::{Blake3Hasher, Nomt, Options};
 
-fn setup_nomt(path: &str, should_clean_up: bool) -> anyhow::Result<Nomt<Blake3Hasher>> {
+fn setup_nomt(path: &str, should_clean_up: bool) -> nomt::Result<Nomt<Blake3Hasher>> {
+    open_nomt(path, should_clean_up, false)
+}
+
+fn open_nomt(
+    path: &str,
+    should_clean_up: bool,
+    read_only: bool,
+) -> nomt::Result<Nomt<Blake3Hasher>> {
     let path = {
         let mut p = PathBuf::from("test");
         p.push(path);
         p
     };
     if should_clean_up && path.exists() {
-        std::fs::remove_dir_all(&path)?;
+        std::fs::remove_dir_all(&path).map_err(nomt::Error::Io)?;
     }
     let mut o = Options::new();
     o.path(path);
     o.panic_on_sync(false);
     o.bitbox_seed([0; 16]);
+    o.read_only(read_only);
     Nomt::open(o)
 }
 
@@ -29,7 +38,7 @@ fn smoke() {
 fn dir_lock() {
     let _nomt_1 = setup_nomt("dir_lock", true).unwrap();
     let nomt_2 = setup_nomt("dir_lock", false);
-    assert!(matches!(nomt_2, Err(e) if e.to_string().contains("Resource temporarily unavailable")));
+    assert!(matches!(nomt_2, Err(nomt::Error::Busy)));
 }
 
 #[test]
@@ -38,3 +47,71 @@ fn dir_unlock() {
     drop(nomt_1);
     let _nomt_2 = setup_nomt("dir_unlock", false).unwrap();
 }
+
+#[test]
+fn close_releases_lock() {
+    let nomt_1 = setup_nomt("close_releases_lock", true).unwrap();
+    let session = nomt_1.begin_session();
+    nomt_1
+        .commit(
+            session,
+            vec![([1; 32], nomt::KeyReadWrite::Write(Some(vec![1].into())))],
+        )
+        .unwrap();
+    nomt_1.close().unwrap();
+
+    let nomt_2 = setup_nomt("close_releases_lock", false).unwrap();
+    assert_eq!(nomt_2.read([1; 32]).unwrap().as_deref(), Some(&[1][..]));
+}
+
+#[test]
+fn read_only_shares_lock() {
+    let nomt = setup_nomt("read_only_shares_lock", true).unwrap();
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([1; 32], nomt::KeyReadWrite::Write(Some(vec![1].into())))],
+    )
+    .unwrap();
+
+    // Readers are kept out by the writer, and the writer by the readers.
+    let reader = open_nomt("read_only_shares_lock", false, true);
+    assert!(matches!(reader, Err(nomt::Error::Busy)));
+    drop(nomt);
+    let reader_1 = open_nomt("read_only_shares_lock", false, true).unwrap();
+    let reader_2 = open_nomt("read_only_shares_lock", false, true).unwrap();
+    let writer = setup_nomt("read_only_shares_lock", false);
+    assert!(matches!(writer, Err(nomt::Error::Busy)));
+
+    assert_eq!(reader_1.read([1; 32]).unwrap().as_deref(), Some(&[1][..]));
+    assert_eq!(reader_2.root(), reader_1.root());
+    drop(reader_1);
+    drop(reader_2);
+    let _writer = setup_nomt("read_only_shares_lock", false).unwrap();
+}
+
+#[test]
+fn read_only_rejects_writes() {
+    drop(setup_nomt("read_only_rejects_writes", true).unwrap());
+    let nomt = open_nomt("read_only_rejects_writes", false, true).unwrap();
+    let session = nomt.begin_session();
+    let result = nomt.commit(
+        session,
+        vec![([1; 32], nomt::KeyReadWrite::Write(Some(vec![1].into())))],
+    );
+    assert!(matches!(result, Err(nomt::Error::InvalidOperation(_))));
+    assert!(matches!(
+        nomt.commit_group(),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        nomt.reset(),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn read_only_needs_database() {
+    let nomt = open_nomt("read_only_needs_database", true, true);
+    assert!(matches!(nomt, Err(nomt::Error::InvalidOperation(_))));
+}
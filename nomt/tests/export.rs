@@ -0,0 +1,108 @@
+//! Tests exporting the values of a database and importing them into another.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, Error, KeyReadWrite, Nomt, Options};
+
+fn open(name: &str, buckets: u32) -> Nomt<Blake3Hasher> {
+    let path = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.hashtable_buckets(buckets);
+    Nomt::open(o).unwrap()
+}
+
+fn key(i: u32) -> [u8; 32] {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn value(i: u32) -> Vec<u8> {
+    // Every 10th value is stored in overflow pages.
+    let len = if i % 10 == 0 {
+        5_000
+    } else {
+        1 + i as usize % 40
+    };
+    vec![i as u8; len]
+}
+
+// Export a database holding 3000 values, larger than a chunk in total.
+fn export(name: &str) -> (Vec<u8>, [u8; 32]) {
+    let nomt = open(name, 4096);
+    let session = nomt.begin_session();
+    let mut actuals = (0..3000)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(value(i).into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(key, _)| *key);
+    nomt.commit(session, actuals).unwrap();
+
+    let mut buf = Vec::new();
+    let root = nomt.export(&mut buf).unwrap();
+    assert_eq!(root, nomt.root());
+    (buf, root)
+}
+
+#[test]
+fn export_import_roundtrip() {
+    let (buf, root) = export("export_import_roundtrip_src");
+
+    // The target uses a different hash-table size.
+    let nomt = open("export_import_roundtrip_dst", 8192);
+    assert_eq!(nomt.import_stream(&buf[..]).unwrap(), root);
+    assert_eq!(nomt.root(), root);
+    for i in [0, 1, 100, 2999] {
+        assert_eq!(nomt.read(key(i)).unwrap(), Some(value(i).into()));
+    }
+
+    // Exporting the imported values reproduces the export.
+    let mut reexported = Vec::new();
+    nomt.export(&mut reexported).unwrap();
+    assert_eq!(reexported, buf);
+}
+
+#[test]
+fn export_empty_database() {
+    let nomt = open("export_empty_database_src", 1024);
+    let mut buf = Vec::new();
+    let root = nomt.export(&mut buf).unwrap();
+
+    let nomt = open("export_empty_database_dst", 1024);
+    assert_eq!(nomt.import_stream(&buf[..]).unwrap(), root);
+}
+
+#[test]
+fn import_rejects_damaged_chunk() {
+    let (mut buf, _) = export("import_rejects_damaged_chunk_src");
+    buf[100] ^= 1;
+
+    let nomt = open("import_rejects_damaged_chunk_dst", 4096);
+    assert!(matches!(
+        nomt.import_stream(&buf[..]),
+        Err(Error::Corruption(_))
+    ));
+}
+
+#[test]
+fn import_rejects_truncated_export() {
+    let (buf, _) = export("import_rejects_truncated_export_src");
+
+    let nomt = open("import_rejects_truncated_export_dst", 4096);
+    assert!(matches!(
+        nomt.import_stream(&buf[..buf.len() - 10]),
+        Err(Error::Corruption(_))
+    ));
+}
+
+#[test]
+fn import_rejects_unknown_version() {
+    let (mut buf, _) = export("import_rejects_unknown_version_src");
+    buf[8..12].copy_from_slice(&(nomt::EXPORT_VERSION + 1).to_le_bytes());
+
+    let nomt = open("import_rejects_unknown_version_dst", 4096);
+    assert!(matches!(
+        nomt.import_stream(&buf[..]),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(nomt.is_empty());
+}
@@ -0,0 +1,88 @@
+//! Tests bulk-loading values into an empty database.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Node, Nomt, Options, Value};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(500_000);
+    o.preallocate_ht(false);
+    o.rollback(true);
+    Nomt::open(o).unwrap()
+}
+
+fn values(count: u64) -> Vec<(KeyPath, Value)> {
+    let mut values = (0..count)
+        .map(|id| {
+            let value = vec![id as u8; 1 + id as usize % 40];
+            (*blake3::hash(&id.to_le_bytes()).as_bytes(), value.into())
+        })
+        .collect::<Vec<(KeyPath, Value)>>();
+    values.sort_by_key(|(path, _)| *path);
+    values
+}
+
+fn expected_root(values: &[(KeyPath, Value)]) -> Node {
+    let ops = values
+        .iter()
+        .map(|(path, value)| (*path, *blake3::hash(value).as_bytes()))
+        .collect::<Vec<_>>();
+    nomt_core::update::build_trie::<Blake3Hasher>(0, ops, |_| {})
+}
+
+#[test]
+fn import_matches_trie() {
+    let nomt = open("import_matches_trie");
+    // More than a single batch.
+    let values = values(300_000);
+    let root = nomt.import(values.clone()).unwrap();
+    assert_eq!(root, expected_root(&values));
+    assert_eq!(nomt.root(), root);
+    for (path, value) in values.iter().step_by(997) {
+        assert_eq!(nomt.read(*path).unwrap().as_ref(), Some(value));
+    }
+
+    // The database can be used as usual afterwards.
+    let (path, _) = values[0];
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![(path, KeyReadWrite::Write(Some(vec![0xFF].into())))],
+    )
+    .unwrap();
+    nomt.rollback(1).unwrap();
+    assert_eq!(nomt.root(), root);
+}
+
+#[test]
+fn import_requires_empty_database() {
+    let nomt = open("import_requires_empty_database");
+    let values = values(100);
+    nomt.import(values[..50].to_vec()).unwrap();
+    assert!(matches!(
+        nomt.import(values[50..].to_vec()),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+
+    // Importing nothing leaves the database empty.
+    let nomt = open("import_requires_empty_database");
+    assert_eq!(nomt.import(vec![]).unwrap(), [0; 32]);
+    assert!(nomt.is_empty());
+}
+
+#[test]
+fn import_requires_sorted_values() {
+    let nomt = open("import_requires_sorted_values");
+    let mut values = values(100);
+    values.swap(10, 20);
+    assert!(matches!(
+        nomt.import(values),
+        Err(nomt::Error::InvalidActuals(_))
+    ));
+}
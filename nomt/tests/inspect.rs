@@ -0,0 +1,139 @@
+//! Tests inspecting a database without opening it.
+
+use nomt::{
+    Blake3Hasher, BucketMappingStrategy, KeyBinning, KeyPath, KeyReadWrite, Nomt, Options,
+    SyncCrashPoint,
+};
+use std::path::{Path, PathBuf};
+
+fn path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    path
+}
+
+fn open(path: &Path, crash_point: Option<SyncCrashPoint>) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.key_binning(KeyBinning::new(8));
+    o.root_history(4);
+    if let Some(crash_point) = crash_point {
+        o.crash_on_sync(crash_point);
+    }
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u64) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, round: u8, tag: &[u8]) {
+    let mut session = nomt.begin_session();
+    session.set_commit_tag(tag);
+    let mut actuals = (0..500)
+        .map(|id| (key(id), KeyReadWrite::Write(Some(vec![round; 32].into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn crashing_commit(path: &Path, crash_point: SyncCrashPoint) {
+    let nomt = open(path, Some(crash_point));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| commit(&nomt, 2, b"")));
+    assert!(result.is_err());
+}
+
+#[test]
+fn inspect_uninitialized() {
+    let path = path("inspect_uninitialized");
+    let _ = std::fs::remove_dir_all(&path);
+    assert_eq!(Nomt::<Blake3Hasher>::inspect(&path).unwrap(), None);
+
+    // Wiping the database makes it uninitialized again.
+    open(&path, None).destroy().unwrap();
+    assert_eq!(Nomt::<Blake3Hasher>::inspect(&path).unwrap(), None);
+}
+
+#[test]
+fn inspect_clean_database() {
+    let path = path("inspect_clean_database");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path, None);
+    commit(&nomt, 1, b"block 1");
+    let root = nomt.root();
+    let stats = nomt.stats().unwrap();
+    drop(nomt);
+
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert_eq!(info.page_size, 4096);
+    assert_eq!(info.sync_seqn, 1);
+    assert_eq!(info.hashtable_buckets, 10_000);
+    assert_eq!(info.bucket_mapping, BucketMappingStrategy::default());
+    assert_eq!(info.key_binning, KeyBinning::new(8));
+    assert_eq!(info.last_root, Some(root));
+    assert_eq!(info.last_commit_tag.as_deref(), Some(&b"block 1"[..]));
+    assert!(!info.needs_recovery);
+    assert_eq!(info.wal.len, 0);
+    assert_eq!(info.ht.len, stats.ht.file.len);
+    assert_eq!(info.ln.len, stats.ln.file.len);
+    assert_eq!(info.bbn.len, stats.bbn.file.len);
+}
+
+#[test]
+fn inspect_interrupted_commit() {
+    let path = path("inspect_interrupted_commit");
+    let _ = std::fs::remove_dir_all(&path);
+    commit(&open(&path, None), 1, b"");
+
+    // A commit interrupted before the meta file is written is discarded, so its WAL doesn't need
+    // to be replayed.
+    crashing_commit(&path, SyncCrashPoint::BeforeMeta);
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert_eq!(info.sync_seqn, 1);
+    assert!(info.wal.len > 0);
+    assert!(!info.needs_recovery);
+
+    // One interrupted after the meta file is written is completed by replaying the WAL.
+    drop(open(&path, None));
+    crashing_commit(&path, SyncCrashPoint::AfterMeta);
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert_eq!(info.sync_seqn, 2);
+    assert!(info.needs_recovery);
+
+    let nomt = open(&path, None);
+    assert_eq!(info.last_root, Some(nomt.root()));
+    drop(nomt);
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert!(!info.needs_recovery);
+    assert_eq!(info.wal.len, 0);
+}
+
+#[test]
+fn inspect_commit_seqn() {
+    let path = path("inspect_commit_seqn");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = open(&path, None);
+    assert_eq!(nomt.commit_seqn(), 0);
+    commit(&nomt, 1, b"");
+    let parent_root = nomt.root();
+    commit(&nomt, 2, b"");
+    assert_eq!(nomt.commit_seqn(), 2);
+    drop(nomt);
+
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert_eq!(info.commit_seqn, 2);
+    assert_eq!(info.parent_root, parent_root);
+
+    // The sequence number carries on after reopening.
+    let nomt = open(&path, None);
+    assert_eq!(nomt.commit_seqn(), 2);
+    let parent_root = nomt.root();
+    commit(&nomt, 3, b"");
+    assert_eq!(nomt.commit_seqn(), 3);
+    drop(nomt);
+    let info = Nomt::<Blake3Hasher>::inspect(&path).unwrap().unwrap();
+    assert_eq!(info.commit_seqn, 3);
+    assert_eq!(info.parent_root, parent_root);
+}
@@ -0,0 +1,204 @@
+use nomt::{
+    Blake3Hasher, IntegrityIssue, IntegrityLevel, IntegrityReport, KeyReadWrite, Nomt, Options,
+};
+use std::{
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+const PAGE_SIZE: usize = 4096;
+
+fn opts(path: &Path) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(1024);
+    o
+}
+
+fn key(i: u16) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..2].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn value(i: u16) -> Vec<u8> {
+    // Every 50th value is stored in overflow pages.
+    let len = if i % 50 == 0 { 10_000 } else { 100 };
+    vec![i as u8; len]
+}
+
+// Populate a fresh database over a few commits, including deletions, and close it.
+fn populate(name: &str) -> PathBuf {
+    let path = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    for round in 0..3u16 {
+        let session = nomt.begin_session();
+        let actuals = (0..1000u16)
+            .filter(|i| i % 3 == round)
+            .map(|i| (key(i), KeyReadWrite::Write(Some(value(i).into()))))
+            .chain(
+                (0..100u16)
+                    .filter(|_| round == 2)
+                    .map(|i| (key(i * 7), KeyReadWrite::Write(None))),
+            )
+            .collect::<std::collections::BTreeMap<_, _>>()
+            .into_iter()
+            .collect();
+        nomt.commit(session, actuals).unwrap();
+    }
+    path
+}
+
+fn check(path: &Path, level: IntegrityLevel) -> IntegrityReport {
+    let nomt = Nomt::<Blake3Hasher>::open(opts(path)).unwrap();
+    nomt.check_integrity(level).unwrap()
+}
+
+// Rewrite every non-empty page of a file, from the given page on, with `f`.
+fn rewrite_pages(path: &Path, file: &str, skip: u64, mut f: impl FnMut(&mut [u8]) -> bool) {
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join(file))
+        .unwrap();
+    let pages = file.metadata().unwrap().len() / PAGE_SIZE as u64;
+    let mut page = vec![0; PAGE_SIZE];
+    for pn in skip..pages {
+        file.read_exact_at(&mut page, pn * PAGE_SIZE as u64)
+            .unwrap();
+        if page.iter().all(|b| *b == 0) {
+            continue;
+        }
+        if f(&mut page) {
+            file.write_all_at(&page, pn * PAGE_SIZE as u64).unwrap();
+        }
+    }
+}
+
+fn issue_files(report: &IntegrityReport) -> Vec<&'static str> {
+    report
+        .issues
+        .iter()
+        .map(|issue| match issue {
+            IntegrityIssue::Page { file, .. } => *file,
+            _ => "",
+        })
+        .collect()
+}
+
+#[test]
+fn healthy_database() {
+    let path = populate("integrity_healthy_database");
+    let report = check(&path, IntegrityLevel::Full);
+    assert!(report.is_healthy(), "{:?}", report.issues);
+    assert!(report.buckets > 0);
+    assert!(report.branches > 0);
+    assert!(report.leaves > 1);
+    assert_eq!(report.leaked_ln_pages, 0);
+    assert_eq!(report.leaked_bbn_pages, 0);
+    assert!(report.leaked_ln_ranges.is_empty());
+}
+
+#[test]
+fn leaked_pages() {
+    let path = populate("integrity_leaked_pages");
+
+    // Pages freed while a snapshot is alive are returned to the free-list by the first sync after
+    // it is dropped. Stopping before that sync leaks them.
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    let snapshot = nomt.snapshot();
+    let session = nomt.begin_session();
+    let actuals = (0..1000u16)
+        .filter(|i| i % 50 == 0)
+        .map(|i| (key(i), KeyReadWrite::Write(None)))
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+    drop(snapshot);
+    drop(nomt);
+
+    let report = check(&path, IntegrityLevel::Structure);
+    assert!(report.is_healthy(), "{:?}", report.issues);
+    assert!(report.leaked_ln_pages > 0);
+    let ranged = report
+        .leaked_ln_ranges
+        .iter()
+        .map(|r| r.end - r.start)
+        .sum::<u64>();
+    assert_eq!(ranged, report.leaked_ln_pages as u64);
+    assert!(report
+        .leaked_ln_ranges
+        .windows(2)
+        .all(|w| w[0].end < w[1].start));
+}
+
+#[test]
+fn corrupted_leaf() {
+    let path = populate("integrity_corrupted_leaf");
+    // Stale copies of the leaf may be left in free pages, so corrupt every page holding the value.
+    let needle = value(2);
+    let mut corrupted = false;
+    rewrite_pages(&path, "ln", 1, |page| {
+        let Some(offset) = page.windows(needle.len()).position(|w| w == &needle[..]) else {
+            return false;
+        };
+        corrupted = true;
+        page[offset] ^= 1;
+        true
+    });
+    assert!(corrupted);
+
+    let report = check(&path, IntegrityLevel::Structure);
+    assert!(!report.is_healthy());
+    assert!(issue_files(&report).iter().all(|file| *file == "ln"));
+}
+
+#[test]
+fn corrupted_bucket() {
+    let path = populate("integrity_corrupted_bucket");
+    // The first page of the hash-table file holds the meta bytes, and the root page is loaded on
+    // open, so corrupt a page which is neither.
+    let mut seen = 0;
+    rewrite_pages(&path, "ht", 1, |page| {
+        seen += 1;
+        if seen != 5 {
+            return false;
+        }
+        page[0] ^= 1;
+        true
+    });
+
+    let report = check(&path, IntegrityLevel::Structure);
+    assert_eq!(issue_files(&report), vec!["ht"]);
+}
+
+#[test]
+fn wrong_value_changes_root() {
+    let path = populate("integrity_wrong_value_changes_root");
+    // Change a byte of a value stored inline and fix up the checksum of the leaf, so that only
+    // re-deriving the root reveals the change.
+    let needle = value(2);
+    let mut changed = false;
+    rewrite_pages(&path, "ln", 1, |page| {
+        let Some(offset) = page.windows(needle.len()).position(|w| w == &needle[..]) else {
+            return false;
+        };
+        changed = true;
+        page[offset] ^= 0xff;
+        let checksum = xxhash_rust::xxh3::xxh3_64(&page[..PAGE_SIZE - 8]);
+        page[PAGE_SIZE - 8..].copy_from_slice(&checksum.to_le_bytes());
+        true
+    });
+    assert!(changed);
+
+    let report = check(&path, IntegrityLevel::Structure);
+    assert!(report.is_healthy(), "{:?}", report.issues);
+
+    let report = check(&path, IntegrityLevel::Full);
+    assert!(matches!(
+        &report.issues[..],
+        [IntegrityIssue::RootMismatch { .. }]
+    ));
+}
@@ -0,0 +1,322 @@
+//! Tests iterating over the values in key order.
+
+use std::{
+    collections::BTreeMap,
+    ops::{Bound, RangeBounds as _},
+    path::PathBuf,
+};
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    Nomt::open(o).unwrap()
+}
+
+fn key(i: u64) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+/// Values of varying size, some of them too large to be stored within a leaf.
+fn value(i: u64, round: u64) -> Vec<u8> {
+    let size = if i % 50 == 0 {
+        5000
+    } else {
+        1 + (i % 100) as usize
+    };
+    vec![(i + round) as u8; size]
+}
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    model: &mut BTreeMap<KeyPath, Vec<u8>>,
+    writes: Vec<(KeyPath, Option<Vec<u8>>)>,
+) {
+    let mut actuals = Vec::new();
+    for (key, value) in writes {
+        match &value {
+            Some(v) => model.insert(key, v.clone()),
+            None => model.remove(&key),
+        };
+        actuals.push((key, KeyReadWrite::Write(value.map(Into::into))));
+    }
+    actuals.sort_by_key(|(k, _)| *k);
+    let session = nomt.begin_session();
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn collect(iter: nomt::Iter) -> Vec<(KeyPath, Vec<u8>)> {
+    iter.map(|(k, v)| (k, v.to_vec())).collect()
+}
+
+fn collect_rev(iter: std::iter::Rev<nomt::Iter>) -> Vec<(KeyPath, Vec<u8>)> {
+    iter.map(|(k, v)| (k, v.to_vec())).collect()
+}
+
+fn expected(
+    model: &BTreeMap<KeyPath, Vec<u8>>,
+    range: (Bound<KeyPath>, Bound<KeyPath>),
+) -> Vec<(KeyPath, Vec<u8>)> {
+    // Unlike `BTreeMap::range`, this doesn't panic on ranges whose start is past the end.
+    model
+        .iter()
+        .filter(|(k, _)| range.contains(*k))
+        .map(|(k, v)| (*k, v.clone()))
+        .collect()
+}
+
+#[test]
+fn iter_matches_model() {
+    let nomt = open("iter_matches_model");
+    let mut model = BTreeMap::new();
+    assert_eq!(nomt.iter(..).count(), 0);
+
+    commit(
+        &nomt,
+        &mut model,
+        (0..2000).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+    assert_eq!(
+        collect(nomt.iter(..)),
+        expected(&model, (Bound::Unbounded, Bound::Unbounded))
+    );
+
+    // Delete some keys, update others and insert new ones.
+    commit(
+        &nomt,
+        &mut model,
+        (0..3000)
+            .step_by(3)
+            .map(|i| (key(i), (i % 2 == 0).then(|| value(i, 1))))
+            .collect(),
+    );
+
+    let keys = model.keys().copied().collect::<Vec<_>>();
+    let (a, b) = (keys[100], keys[1500]);
+    let ranges = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(a), Bound::Excluded(b)),
+        (Bound::Excluded(a), Bound::Included(b)),
+        (Bound::Included(a), Bound::Included(a)),
+        (Bound::Unbounded, Bound::Excluded(a)),
+        (Bound::Excluded(b), Bound::Unbounded),
+        // Bounds which are not stored keys.
+        (Bound::Included([0x40; 32]), Bound::Excluded([0x80; 32])),
+        (Bound::Included(b), Bound::Excluded(a)),
+    ];
+    for range in ranges {
+        assert_eq!(
+            collect(nomt.iter(range)),
+            expected(&model, range),
+            "{range:?}"
+        );
+    }
+}
+
+#[test]
+fn iter_rev_matches_model() {
+    let nomt = open("iter_rev_matches_model");
+    let mut model = BTreeMap::new();
+    assert_eq!(nomt.iter_rev(..).count(), 0);
+
+    commit(
+        &nomt,
+        &mut model,
+        (0..2000).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+    commit(
+        &nomt,
+        &mut model,
+        (0..2000)
+            .step_by(7)
+            .map(|i| (key(i), (i % 2 == 0).then(|| value(i, 1))))
+            .collect(),
+    );
+
+    let keys = model.keys().copied().collect::<Vec<_>>();
+    let (a, b) = (keys[200], keys[1200]);
+    let ranges = [
+        (Bound::Unbounded, Bound::Unbounded),
+        (Bound::Included(a), Bound::Excluded(b)),
+        (Bound::Excluded(a), Bound::Included(b)),
+        (Bound::Included(b), Bound::Included(b)),
+        (Bound::Included([0x40; 32]), Bound::Excluded([0x80; 32])),
+        (Bound::Included(b), Bound::Excluded(a)),
+    ];
+    for range in ranges {
+        let mut expected = expected(&model, range);
+        expected.reverse();
+        assert_eq!(collect_rev(nomt.iter_rev(range)), expected, "{range:?}");
+    }
+
+    // The last value under a prefix.
+    let prefix = keys[500][0];
+    let (mut lo, mut hi) = ([0; 32], [0xFF; 32]);
+    lo[0] = prefix;
+    hi[0] = prefix;
+    let last = nomt.iter_rev(lo..=hi).next().map(|(k, _)| k);
+    assert_eq!(
+        last,
+        model.keys().filter(|k| k[0] == prefix).last().copied()
+    );
+}
+
+#[test]
+fn iter_from_both_ends() {
+    let nomt = open("iter_from_both_ends");
+    let mut model = BTreeMap::new();
+    commit(
+        &nomt,
+        &mut model,
+        (0..1500).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+
+    // Alternate between both ends in varying patterns: every value is returned exactly once.
+    for pattern in [0b1u32, 0b10, 0b110, 0b1011] {
+        let expected = expected(&model, (Bound::Unbounded, Bound::Unbounded));
+        let mut iter = nomt.iter(..);
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        for step in 0.. {
+            let entry = if pattern & (1 << (step % 5)) != 0 {
+                iter.next_back().map(|e| back.push(e))
+            } else {
+                iter.next().map(|e| front.push(e))
+            };
+            if entry.is_none() {
+                break;
+            }
+        }
+        assert!(iter.next().is_none() && iter.next_back().is_none());
+        front.extend(back.into_iter().rev());
+        assert_eq!(
+            front
+                .into_iter()
+                .map(|(k, v)| (k, v.to_vec()))
+                .collect::<Vec<_>>(),
+            expected,
+            "{pattern:b}"
+        );
+    }
+}
+
+#[test]
+fn iter_is_unaffected_by_later_commits() {
+    let nomt = open("iter_is_unaffected_by_later_commits");
+    let mut model = BTreeMap::new();
+    commit(
+        &nomt,
+        &mut model,
+        (0..500).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+    let before = model.clone();
+
+    let mut iter = nomt.iter(..);
+    let first = iter.next().unwrap();
+
+    commit(
+        &nomt,
+        &mut model,
+        (0..500)
+            .map(|i| (key(i), (i % 2 == 0).then(|| value(i, 1))))
+            .collect(),
+    );
+
+    let mut seen = vec![(first.0, first.1.to_vec())];
+    seen.extend(collect(iter));
+    assert_eq!(
+        seen,
+        expected(&before, (Bound::Unbounded, Bound::Unbounded))
+    );
+    assert_eq!(
+        collect(nomt.iter(..)),
+        expected(&model, (Bound::Unbounded, Bound::Unbounded))
+    );
+}
+
+#[test]
+fn seek_nearest_key() {
+    let nomt = open("seek_nearest_key");
+    let mut model = BTreeMap::new();
+    assert_eq!(nomt.seek_at_or_after([0; 32]), None);
+    assert_eq!(nomt.seek_at_or_before([0xFF; 32]), None);
+
+    commit(
+        &nomt,
+        &mut model,
+        (0..1000).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+    commit(
+        &nomt,
+        &mut model,
+        (0..1000).step_by(4).map(|i| (key(i), None)).collect(),
+    );
+
+    let to_vec = |entry: Option<(KeyPath, nomt::Value)>| entry.map(|(k, v)| (k, v.to_vec()));
+    for i in 0..1000 {
+        // Both stored and deleted keys, as well as keys next to them.
+        let mut probe = key(i);
+        for _ in 0..2 {
+            assert_eq!(
+                to_vec(nomt.seek_at_or_after(probe)),
+                model.range(probe..).next().map(|(k, v)| (*k, v.clone()))
+            );
+            assert_eq!(
+                to_vec(nomt.seek_at_or_before(probe)),
+                model
+                    .range(..=probe)
+                    .next_back()
+                    .map(|(k, v)| (*k, v.clone()))
+            );
+            probe[31] ^= 1;
+        }
+    }
+
+    let (first, last) = (model.keys().next(), model.keys().next_back());
+    assert_eq!(
+        nomt.seek_at_or_after([0; 32]).map(|(k, _)| k).as_ref(),
+        first
+    );
+    assert_eq!(
+        nomt.seek_at_or_before([0xFF; 32]).map(|(k, _)| k).as_ref(),
+        last
+    );
+    assert_eq!(nomt.seek_at_or_before([0; 32]), None);
+}
+
+#[test]
+fn first_and_last_key() {
+    let nomt = open("first_and_last_key");
+    let mut model = BTreeMap::new();
+    assert_eq!(nomt.first_key(), None);
+    assert_eq!(nomt.last_key(), None);
+
+    commit(
+        &nomt,
+        &mut model,
+        (0..1000).map(|i| (key(i), Some(value(i, 0)))).collect(),
+    );
+    assert_eq!(nomt.first_key(), model.keys().next().copied());
+    assert_eq!(nomt.last_key(), model.keys().next_back().copied());
+
+    // Deleting the extremes moves the bounds inwards.
+    let extremes = model
+        .keys()
+        .take(3)
+        .chain(model.keys().rev().take(3))
+        .map(|k| (*k, None))
+        .collect();
+    commit(&nomt, &mut model, extremes);
+    assert_eq!(nomt.first_key(), model.keys().next().copied());
+    assert_eq!(nomt.last_key(), model.keys().next_back().copied());
+
+    let all = model.keys().map(|k| (*k, None)).collect();
+    commit(&nomt, &mut model, all);
+    assert_eq!(nomt.first_key(), None);
+    assert_eq!(nomt.last_key(), None);
+}
@@ -0,0 +1,103 @@
+//! Tests deriving key paths with binning.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, KeyBinning, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn open(name: &str, key_binning: KeyBinning, clean: bool) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    if clean {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.key_binning(key_binning);
+    Nomt::open(o).unwrap()
+}
+
+fn bin_hash(bin: u64) -> [u8; 32] {
+    *blake3::hash(&bin.to_le_bytes()).as_bytes()
+}
+
+fn key_hash(slot: u64) -> [u8; 32] {
+    *blake3::hash(&slot.to_be_bytes()).as_bytes()
+}
+
+fn build_trie(values: &BTreeMap<KeyPath, Vec<u8>>, prefix: &BitSlice<u8, Msb0>) -> Node {
+    let ops = values
+        .iter()
+        .filter(|(k, _)| k.view_bits::<Msb0>().starts_with(prefix))
+        .map(|(k, v)| (*k, *blake3::hash(v).as_bytes()))
+        .collect::<Vec<_>>();
+    nomt_core::update::build_trie::<Blake3Hasher>(prefix.len(), ops, |_| {})
+}
+
+#[test]
+fn binned_keys_share_subtries() {
+    let name = "binned_keys_share_subtries";
+    let binning = KeyBinning::new(16);
+    let nomt = open(name, binning, true);
+    assert_eq!(nomt.key_binning(), binning);
+
+    let mut values = BTreeMap::new();
+    for bin in 0..4 {
+        for slot in 0..200 {
+            let key_path = nomt.key_path(&bin_hash(bin), &key_hash(slot));
+            assert!(key_path
+                .view_bits::<Msb0>()
+                .starts_with(binning.bin_prefix(&bin_hash(bin))));
+            values.insert(key_path, vec![bin as u8; 1 + slot as usize % 50]);
+        }
+    }
+
+    let session = nomt.begin_session();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone().into()))))
+        .collect();
+    let (root, witness, _) = nomt.commit_and_prove(session, actuals).unwrap();
+    assert_eq!(root, build_trie(&values, BitSlice::empty()));
+    assert_eq!(witness.key_binning, binning);
+
+    // Every bin is a subtrie of its own.
+    let session = nomt.begin_session();
+    for bin in 0..4 {
+        let bin_hash = bin_hash(bin);
+        let prefix = binning.bin_prefix(&bin_hash);
+        assert_eq!(
+            session.subtree_root(prefix).unwrap(),
+            build_trie(&values, prefix)
+        );
+    }
+    drop(session);
+
+    // The binning is recorded in the database and takes precedence over the options.
+    drop(nomt);
+    let nomt = open(name, KeyBinning::DISABLED, false);
+    assert_eq!(nomt.key_binning(), binning);
+    let key_path = nomt.key_path(&bin_hash(1), &key_hash(7));
+    assert_eq!(
+        nomt.read(key_path).unwrap().as_deref(),
+        values.get(&key_path).map(|v| &v[..])
+    );
+}
+
+#[test]
+fn disabled_binning_uses_key_hash() {
+    let nomt = open("disabled_binning_uses_key_hash", KeyBinning::DISABLED, true);
+    assert!(!nomt.key_binning().is_enabled());
+    assert_eq!(nomt.key_path(&bin_hash(1), &key_hash(7)), key_hash(7));
+
+    let session = nomt.begin_session();
+    let (_, witness, _) = nomt
+        .commit_and_prove(
+            session,
+            vec![(key_hash(7), KeyReadWrite::Write(Some(vec![1].into())))],
+        )
+        .unwrap();
+    assert_eq!(witness.key_binning, KeyBinning::DISABLED);
+}
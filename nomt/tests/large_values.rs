@@ -1,6 +1,7 @@
 mod common;
 
 use common::Test;
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options};
 
 #[test]
 fn large_values() {
@@ -19,3 +20,77 @@ fn large_values() {
     assert_eq!(&*t.read_id(0).unwrap(), &large1);
     assert!(t.read_id(1).is_none());
 }
+
+#[test]
+fn large_value_size() {
+    let mut t = Test::new("large_value_size");
+
+    let small = vec![1; 100];
+    let large = vec![2; 4096 * 64 + 17];
+
+    t.write_id(0, Some(small.clone()));
+    t.write_id(1, Some(large.clone()));
+    let _ = t.commit();
+    assert_eq!(t.value_size_id(0), Some(small.len()));
+    assert_eq!(t.value_size_id(1), Some(large.len()));
+    assert_eq!(t.value_size_id(2), None);
+}
+
+#[test]
+fn read_ref_matches_read() {
+    let mut t = Test::new("read_ref_matches_read");
+
+    let small = vec![3; 200];
+    let large = vec![4; 4096 * 3 + 5];
+
+    t.write_id(0, Some(small.clone()));
+    t.write_id(1, Some(large.clone()));
+    let _ = t.commit();
+    assert_eq!(&*t.read_ref_id(0).unwrap(), &small[..]);
+    assert_eq!(t.read_ref_id(1).unwrap().into_vec(), large);
+    assert!(t.read_ref_id(2).is_none());
+}
+
+#[test]
+fn write_size_hints_reserve_space() {
+    let path = std::path::PathBuf::from("test/write_size_hints_reserve_space");
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(&path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    let ln_len = || nomt.stats().unwrap().ln.file.len;
+    let initial_len = ln_len();
+
+    // Small values don't need any space reserved.
+    let session = nomt.begin_session();
+    session
+        .warm_up_write(common::account_path(0), Some(100))
+        .unwrap();
+    session
+        .warm_up_write(common::account_path(1), None)
+        .unwrap();
+    assert_eq!(ln_len(), initial_len);
+
+    // The space for large values is reserved before the commit, which then fits within it.
+    let values = (2..12u64)
+        .map(|id| (common::account_path(id), vec![id as u8; 1 << 20]))
+        .collect::<Vec<_>>();
+    for (path, value) in &values {
+        session.warm_up_write(*path, Some(value.len())).unwrap();
+    }
+    let reserved_len = ln_len();
+    assert!(reserved_len >= 10 << 20);
+
+    let mut actuals = values
+        .iter()
+        .map(|(path, value)| (*path, KeyReadWrite::Write(Some(value.clone().into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(path, _)| *path);
+    nomt.commit(session, actuals).unwrap();
+    assert_eq!(ln_len(), reserved_len);
+    for (path, value) in &values {
+        assert_eq!(nomt.read(*path).unwrap().as_deref(), Some(&value[..]));
+    }
+}
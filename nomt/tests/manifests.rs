@@ -0,0 +1,130 @@
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options};
+use std::{
+    fs::{File, OpenOptions},
+    os::unix::fs::FileExt as _,
+    path::{Path, PathBuf},
+};
+
+const PAGE_SIZE: u64 = 4096;
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn setup_nomt(path: &Path, manifest_retention: usize) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.commit_concurrency(1);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.manifest_retention(manifest_retention);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u8) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[0] = id;
+    key
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, ids: impl IntoIterator<Item = u8>, value_len: usize) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .into_iter()
+        .map(|id| {
+            (
+                key(id),
+                KeyReadWrite::Write(Some(vec![id; value_len].into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by(|a, b| a.0.cmp(&b.0));
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn copy_pages(from: &Path, to: &Path, file: &str, pages: impl IntoIterator<Item = u64>) {
+    let src = File::open(from.join(file)).unwrap();
+    let dst = OpenOptions::new().write(true).open(to.join(file)).unwrap();
+    dst.set_len(src.metadata().unwrap().len()).unwrap();
+    let mut buf = vec![0; PAGE_SIZE as usize];
+    for pn in pages {
+        src.read_exact_at(&mut buf, pn * PAGE_SIZE).unwrap();
+        dst.write_all_at(&buf, pn * PAGE_SIZE).unwrap();
+    }
+}
+
+#[test]
+fn changed_pages_since_respects_retention() {
+    let path = test_path("manifests_retention");
+    let _ = std::fs::remove_dir_all(&path);
+    let nomt = setup_nomt(&path, 2);
+
+    let root_0 = nomt.root();
+    assert_eq!(nomt.changed_pages_since(root_0), Some(Default::default()));
+
+    commit(&nomt, [1, 2], 32);
+    let root_1 = nomt.root();
+    let pages = nomt.changed_pages_since(root_0).unwrap();
+    assert!(!pages.ht_pages.is_empty());
+    assert!(!pages.ln_pages.is_empty());
+
+    commit(&nomt, [3], 32);
+    commit(&nomt, [4], 32);
+
+    assert!(nomt.changed_pages_since(root_0).is_none());
+    assert!(nomt.changed_pages_since(root_1).is_some());
+    assert!(nomt.changed_pages_since(nomt.root()).unwrap().is_empty());
+}
+
+#[test]
+fn replica_catches_up_with_changed_pages() {
+    let leader_path = test_path("manifests_leader");
+    let replica_path = test_path("manifests_replica");
+    let _ = std::fs::remove_dir_all(&leader_path);
+    let _ = std::fs::remove_dir_all(&replica_path);
+    std::fs::create_dir_all(&replica_path).unwrap();
+
+    let leader = setup_nomt(&leader_path, 10);
+    commit(&leader, 0..50, 64);
+
+    // Take a snapshot of the leader.
+    let replica_root = leader.root();
+    for file in ["meta", "ln", "bbn", "ht", "wal"] {
+        std::fs::copy(leader_path.join(file), replica_path.join(file)).unwrap();
+    }
+
+    commit(&leader, 25..100, 128);
+    commit(&leader, [7], 10_000);
+
+    let pages = leader.changed_pages_since(replica_root).unwrap();
+    copy_pages(
+        &leader_path,
+        &replica_path,
+        "ht",
+        pages.ht_pages.iter().copied(),
+    );
+    copy_pages(
+        &leader_path,
+        &replica_path,
+        "ln",
+        pages.ln_pages.iter().map(|&pn| pn as u64),
+    );
+    copy_pages(
+        &leader_path,
+        &replica_path,
+        "bbn",
+        pages.bbn_pages.iter().map(|&pn| pn as u64),
+    );
+    std::fs::copy(leader_path.join("meta"), replica_path.join("meta")).unwrap();
+
+    let replica = setup_nomt(&replica_path, 0);
+    assert_eq!(replica.root(), leader.root());
+    for id in 0..100 {
+        assert_eq!(
+            replica.read(key(id)).unwrap(),
+            leader.read(key(id)).unwrap(),
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Tests the page pool capacity and observability.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options};
+
+fn setup_nomt(path: &str, page_pool_capacity: Option<usize>) -> Nomt<Blake3Hasher> {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(path);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    if let Some(page_pool_capacity) = page_pool_capacity {
+        o.page_pool_capacity(page_pool_capacity);
+    }
+    Nomt::open(o).unwrap()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, range: std::ops::Range<u32>) {
+    let session = nomt.begin_session();
+    let actuals = range
+        .map(|i| {
+            let key = *blake3::hash(&i.to_le_bytes()).as_bytes();
+            (key, KeyReadWrite::Write(Some(vec![i as u8; 64].into())))
+        })
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .into_iter()
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+}
+
+#[test]
+fn unbounded_by_default() {
+    let nomt = setup_nomt("page_pool_unbounded", None);
+    commit(&nomt, 0..1000);
+
+    let stats = nomt.page_pool_stats();
+    assert_eq!(stats.capacity, None);
+    assert!(stats.used() > 0);
+    assert_eq!(stats.waits, 0);
+}
+
+#[test]
+fn capacity_is_respected() {
+    let capacity = 64 * 1024 * 1024;
+    let nomt = setup_nomt("page_pool_capacity", Some(capacity));
+    for i in 0..5 {
+        commit(&nomt, i * 1000..(i + 1) * 1000);
+    }
+
+    let stats = nomt.page_pool_stats();
+    assert_eq!(stats.capacity, Some(capacity / 4096));
+    assert!(stats.allocated <= capacity / 4096);
+    assert!(stats.free <= stats.allocated);
+}
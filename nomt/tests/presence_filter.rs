@@ -0,0 +1,75 @@
+//! Tests the filter over the pages stored in the hash-table.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, IntegrityLevel, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    let _ = std::fs::remove_dir_all(&p);
+    p
+}
+
+fn open(path: &Path, presence_filter: bool) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.presence_filter(presence_filter);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u32>, delete: bool) -> Node {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| {
+            let value = (!delete).then(|| id.to_le_bytes().to_vec().into());
+            (key(id), KeyReadWrite::Write(value))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap()
+}
+
+#[test]
+fn filtered_lookups_find_stored_pages() {
+    let path = test_path("presence_filter");
+    let reference_path = test_path("presence_filter_reference");
+    let nomt = open(&path, true);
+    let reference = open(&reference_path, false);
+
+    // Commits creating pages, then deleting and recreating some of them.
+    for (ids, delete) in [(0..5000, false), (1000..3000, true), (2000..8000, false)] {
+        assert_eq!(
+            commit(&nomt, ids.clone(), delete),
+            commit(&reference, ids, delete)
+        );
+    }
+    drop(nomt);
+
+    // The filter is built from the pages found on open.
+    let nomt = open(&path, true);
+    assert_eq!(nomt.root(), reference.root());
+    for id in [0u32, 999, 1000, 2999, 7999] {
+        let expected = (!(1000..2000).contains(&id)).then(|| id.to_le_bytes().to_vec());
+        assert_eq!(nomt.read(key(id)).unwrap().map(|v| v.to_vec()), expected);
+    }
+    assert_eq!(
+        commit(&nomt, 4000..10000, true),
+        commit(&reference, 4000..10000, true)
+    );
+    drop(nomt);
+
+    // Opening without the filter finds the same pages.
+    let nomt = open(&path, false);
+    assert_eq!(nomt.root(), reference.root());
+    assert!(nomt
+        .check_integrity(IntegrityLevel::Full)
+        .unwrap()
+        .is_healthy());
+}
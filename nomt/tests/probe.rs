@@ -0,0 +1,123 @@
+//! Tests probing the terminal nodes of key paths.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Nomt, Options, Probe};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    Nomt::open(o).unwrap()
+}
+
+fn key(i: u64) -> KeyPath {
+    *blake3::hash(&i.to_le_bytes()).as_bytes()
+}
+
+fn shared_bits(a: &KeyPath, b: &KeyPath) -> usize {
+    a.view_bits::<Msb0>()
+        .iter()
+        .zip(b.view_bits::<Msb0>())
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// The number of stored keys starting with the first `depth` bits of the key.
+fn keys_under(values: &BTreeMap<KeyPath, Vec<u8>>, key: &KeyPath, depth: u16) -> usize {
+    values
+        .keys()
+        .filter(|k| shared_bits(k, key) >= depth as usize)
+        .count()
+}
+
+#[test]
+fn probe_reports_terminal_and_depth() {
+    let nomt = open("probe_reports_terminal_and_depth");
+    let session = nomt.begin_session();
+    assert_eq!(
+        session.probe(key(0)).unwrap(),
+        Probe::Terminator { depth: 0 }
+    );
+    drop(session);
+
+    // A single leaf sits at the root.
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![(key(0), KeyReadWrite::Write(Some(vec![1].into())))],
+    )
+    .unwrap();
+    let session = nomt.begin_session();
+    assert_eq!(
+        session.probe(key(0)).unwrap(),
+        Probe::Leaf {
+            depth: 0,
+            value_hash: *blake3::hash(&[1]).as_bytes()
+        }
+    );
+    assert_eq!(
+        session.probe(key(1)).unwrap(),
+        Probe::OtherLeaf {
+            depth: 0,
+            key_path: key(0)
+        }
+    );
+    drop(session);
+
+    // Empty values are present.
+    let values = (0..500)
+        .map(|i| (key(i), vec![1; i as usize % 3]))
+        .collect::<BTreeMap<_, _>>();
+    let session = nomt.begin_session();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone().into()))))
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+
+    let session = nomt.begin_session();
+    for (k, v) in &values {
+        let depth = values
+            .keys()
+            .filter(|other| *other != k)
+            .map(|other| shared_bits(k, other) + 1)
+            .max()
+            .unwrap();
+        assert_eq!(
+            session.probe(*k).unwrap(),
+            Probe::Leaf {
+                depth: depth as u16,
+                value_hash: *blake3::hash(v).as_bytes()
+            }
+        );
+    }
+
+    // The path of an absent key ends where no other key, or only a single one, shares its prefix.
+    let (mut other_leaves, mut terminators) = (0, 0);
+    for i in 500..1000 {
+        let probe = session.probe(key(i)).unwrap();
+        assert!(!probe.is_present());
+        let depth = probe.depth();
+        assert!(keys_under(&values, &key(i), depth - 1) > 1);
+        match probe {
+            Probe::OtherLeaf { key_path, .. } => {
+                other_leaves += 1;
+                assert!(values.contains_key(&key_path));
+                assert!(shared_bits(&key_path, &key(i)) >= depth as usize);
+                assert_eq!(keys_under(&values, &key(i), depth), 1);
+            }
+            Probe::Terminator { .. } => {
+                terminators += 1;
+                assert_eq!(keys_under(&values, &key(i), depth), 0);
+            }
+            Probe::Leaf { .. } => unreachable!(),
+        }
+    }
+    assert!(other_leaves > 0 && terminators > 0);
+}
@@ -0,0 +1,199 @@
+//! Tests generating path proofs outside of commits.
+
+use std::path::PathBuf;
+
+use bitvec::prelude::*;
+use nomt::{
+    proof::{self, PathProofTerminal},
+    range_proof::{RangeProof, RangeProofVerificationError},
+    Blake3Hasher, KeyReadWrite, LeafData, Nomt, Options, ProvenRange,
+};
+
+fn open(name: &str, proof_cache_capacity: usize) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.metrics(true);
+    o.proof_cache_capacity(proof_cache_capacity);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u8) -> [u8; 32] {
+    let mut key = [id; 32];
+    key[0] = id.wrapping_mul(37);
+    key
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, ids: &[u8]) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .iter()
+        .map(|&id| (key(id), KeyReadWrite::Write(Some(vec![id].into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn check_proof(nomt: &Nomt<Blake3Hasher>, id: u8, present: bool) {
+    let (root, proof) = nomt.prove(key(id)).unwrap();
+    assert_eq!(root, nomt.root());
+    let verified = proof
+        .verify::<Blake3Hasher>(key(id).view_bits::<Msb0>(), root)
+        .unwrap();
+    match proof.terminal {
+        PathProofTerminal::Leaf(ref leaf) if leaf.key_path == key(id) => {
+            assert!(present);
+            assert_eq!(leaf.value_hash, *blake3::hash(&[id]).as_bytes());
+        }
+        _ => assert!(!present),
+    }
+    assert_eq!(verified.confirm_nonexistence(&key(id)).unwrap(), !present);
+}
+
+#[test]
+fn prove_present_and_absent_keys() {
+    let nomt = open("prove_present_and_absent_keys", 0);
+    check_proof(&nomt, 1, false);
+
+    write(&nomt, &(0..100).collect::<Vec<_>>());
+    for id in 0..100 {
+        check_proof(&nomt, id, true);
+    }
+    for id in 100..200 {
+        check_proof(&nomt, id, false);
+    }
+    assert_eq!(nomt.metrics().proof_cache_hit_rate(), None);
+}
+
+#[test]
+fn proof_cache_hits_and_invalidation() {
+    let nomt = open("proof_cache_hits_and_invalidation", 16);
+    write(&nomt, &[1, 2, 3]);
+
+    for _ in 0..4 {
+        check_proof(&nomt, 1, true);
+    }
+    assert_eq!(nomt.metrics().proof_cache_hit_rate(), Some(0.75));
+
+    // The cached proof is stale after the commit.
+    write(&nomt, &[4]);
+    check_proof(&nomt, 1, true);
+    check_proof(&nomt, 4, true);
+    assert_eq!(nomt.metrics().proof_cache_hit_rate(), Some(0.5));
+}
+
+#[test]
+fn prove_absence() {
+    let nomt = open("prove_absence", 0);
+    let (root, proof) = nomt.prove_absence(key(1)).unwrap().unwrap();
+    assert_eq!(root, nomt.root());
+    assert!(proof::verify_absence::<Blake3Hasher>(root, &key(1), &proof).unwrap());
+
+    write(&nomt, &(0..100).collect::<Vec<_>>());
+    let root = nomt.root();
+    for id in 0..100 {
+        assert!(nomt.prove_absence(key(id)).unwrap().is_none());
+    }
+    for id in 100..200 {
+        let (proof_root, proof) = nomt.prove_absence(key(id)).unwrap().unwrap();
+        assert_eq!(proof_root, root);
+        assert!(proof::verify_absence::<Blake3Hasher>(root, &key(id), &proof).unwrap());
+        // The proof doesn't hold for another root.
+        assert!(proof::verify_absence::<Blake3Hasher>([1; 32], &key(id), &proof).is_err());
+    }
+
+    // A proof of a present key doesn't prove its absence.
+    let (_, proof) = nomt.prove(key(5)).unwrap();
+    assert!(!proof::verify_absence::<Blake3Hasher>(root, &key(5), &proof).unwrap());
+}
+
+fn check_range(nomt: &Nomt<Blake3Hasher>, start: [u8; 32], end: [u8; 32]) -> RangeProof {
+    let ProvenRange {
+        root,
+        values,
+        proof,
+    } = nomt.prove_range(start..=end).unwrap();
+    assert_eq!(root, nomt.root());
+    let leaves = proof.verify::<Blake3Hasher>(root, &start, &end).unwrap();
+    let expected = values
+        .iter()
+        .map(|(key_path, value)| LeafData {
+            key_path: *key_path,
+            value_hash: *blake3::hash(value).as_bytes(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(leaves, expected);
+    proof
+}
+
+#[test]
+fn prove_range() {
+    let nomt = open("prove_range", 0);
+    check_range(&nomt, [0; 32], [0xFF; 32]);
+
+    write(&nomt, &(0..200).collect::<Vec<_>>());
+    let all = nomt.prove_range(..).unwrap().values;
+    assert_eq!(all.len(), 200);
+    check_range(&nomt, [0; 32], [0xFF; 32]);
+    check_range(&nomt, all[10].0, all[50].0);
+    check_range(&nomt, [0x40; 32], [0x80; 32]);
+    check_range(&nomt, all[7].0, all[7].0);
+
+    // A range without any key.
+    let mut start = all[20].0;
+    start[31] = start[31].wrapping_add(1);
+    let mut end = all[21].0;
+    end[31] = end[31].wrapping_sub(1);
+    if start <= end {
+        let proof = check_range(&nomt, start, end);
+        assert!(proof
+            .verify::<Blake3Hasher>(nomt.root(), &start, &end)
+            .unwrap()
+            .is_empty());
+    }
+
+    assert!(matches!(
+        nomt.prove_range([1; 32]..[0; 32]),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn incomplete_range_proof_is_rejected() {
+    let nomt = open("incomplete_range_proof_is_rejected", 0);
+    write(&nomt, &(0..200).collect::<Vec<_>>());
+    let root = nomt.root();
+    let ProvenRange { values, proof, .. } = nomt.prove_range([0x40; 32]..=[0xC0; 32]).unwrap();
+    assert!(values.len() > 2);
+
+    // Leaving out any proof of a key within the range hides a subtrie.
+    for i in 1..proof.path_proofs.len() - 1 {
+        let mut incomplete = proof.clone();
+        incomplete.path_proofs.remove(i);
+        assert!(matches!(
+            incomplete.verify::<Blake3Hasher>(root, &[0x40; 32], &[0xC0; 32]),
+            Err(RangeProofVerificationError::Incomplete)
+        ));
+    }
+
+    // The proof doesn't cover a wider range.
+    assert!(matches!(
+        proof.verify::<Blake3Hasher>(root, &[0; 32], &[0xFF; 32]),
+        Err(RangeProofVerificationError::Incomplete)
+    ));
+
+    let mut unordered = proof.clone();
+    unordered.path_proofs.swap(0, 1);
+    assert!(matches!(
+        unordered.verify::<Blake3Hasher>(root, &[0x40; 32], &[0xC0; 32]),
+        Err(RangeProofVerificationError::PathsOutOfOrder)
+    ));
+    assert!(matches!(
+        proof.verify::<Blake3Hasher>([1; 32], &[0x40; 32], &[0xC0; 32]),
+        Err(RangeProofVerificationError::InvalidPath(0, _))
+    ));
+}
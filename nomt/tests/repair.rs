@@ -0,0 +1,145 @@
+use nomt::{Blake3Hasher, IntegrityLevel, KeyReadWrite, Nomt, Options};
+use std::{
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+};
+
+const PAGE_SIZE: usize = 4096;
+
+fn opts(path: &Path) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(1024);
+    o
+}
+
+fn key(i: u16) -> [u8; 32] {
+    let mut key = [0; 32];
+    key[..2].copy_from_slice(&i.to_be_bytes());
+    key
+}
+
+fn value(i: u16) -> Vec<u8> {
+    // Every 50th value is stored in overflow pages.
+    let len = if i % 50 == 0 { 10_000 } else { 100 };
+    vec![i as u8; len]
+}
+
+// Populate a fresh database over a few commits, including deletions, and close it. Returns the
+// path along with the root.
+fn populate(name: &str) -> (PathBuf, [u8; 32]) {
+    let path = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    for round in 0..3u16 {
+        let session = nomt.begin_session();
+        let actuals = (0..1000u16)
+            .filter(|i| i % 3 == round)
+            .map(|i| (key(i), KeyReadWrite::Write(Some(value(i).into()))))
+            .chain(
+                (0..100u16)
+                    .filter(|_| round == 2)
+                    .map(|i| (key(i * 7), KeyReadWrite::Write(None))),
+            )
+            .collect::<std::collections::BTreeMap<_, _>>()
+            .into_iter()
+            .collect();
+        nomt.commit(session, actuals).unwrap();
+    }
+    let root = nomt.root();
+    (path, root)
+}
+
+// Overwrite the given bytes of the meta file.
+fn write_meta(path: &Path, offset: u64, bytes: &[u8]) {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(path.join("meta"))
+        .unwrap();
+    file.write_all_at(bytes, offset).unwrap();
+}
+
+#[test]
+fn repair_healthy_database() {
+    let (path, root) = populate("repair_healthy_database");
+
+    let report = Nomt::<Blake3Hasher>::repair(&path).unwrap().unwrap();
+    assert!(report.dropped.is_empty(), "{:?}", report.dropped);
+    assert!(report.branches > 0);
+    assert!(report.leaves > 1);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    assert_eq!(nomt.root(), root);
+    let integrity = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+    assert_eq!(integrity.leaked_ln_pages, 0);
+    assert_eq!(integrity.leaked_bbn_pages, 0);
+    assert_eq!(nomt.read(key(1)).unwrap(), Some(value(1).into()));
+}
+
+#[test]
+fn repair_lost_leaf_free_list() {
+    let (path, root) = populate("repair_lost_leaf_free_list");
+    // Point the leaf free-list past the end of the file.
+    write_meta(&path, 0, &u32::MAX.to_le_bytes());
+    assert!(Nomt::<Blake3Hasher>::open(opts(&path)).is_err());
+
+    let report = Nomt::<Blake3Hasher>::repair(&path).unwrap().unwrap();
+    assert_eq!(report.dropped.len(), 1, "{:?}", report.dropped);
+    assert!(report.free_ln_pages > 0);
+
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    assert_eq!(nomt.root(), root);
+    let integrity = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+    assert_eq!(integrity.leaked_ln_pages, 0);
+
+    // The rebuilt free-list is usable.
+    let session = nomt.begin_session();
+    let actuals = (1000..1100u16)
+        .map(|i| (key(i), KeyReadWrite::Write(Some(value(i).into()))))
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+    assert_eq!(nomt.read(key(1050)).unwrap(), Some(value(1050).into()));
+    let integrity = nomt.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+}
+
+#[test]
+fn repair_drops_damaged_leaf() {
+    let (path, _) = populate("repair_drops_damaged_leaf");
+    // Corrupt every page holding a live value. Stale copies of the leaf may be corrupted too.
+    let needle = value(2);
+    let ln = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("ln"))
+        .unwrap();
+    let pages = ln.metadata().unwrap().len() / PAGE_SIZE as u64;
+    let mut page = vec![0; PAGE_SIZE];
+    for pn in 1..pages {
+        ln.read_exact_at(&mut page, pn * PAGE_SIZE as u64).unwrap();
+        if let Some(offset) = page.windows(needle.len()).position(|w| w == &needle[..]) {
+            page[offset] ^= 1;
+            ln.write_all_at(&page, pn * PAGE_SIZE as u64).unwrap();
+        }
+    }
+
+    let report = Nomt::<Blake3Hasher>::repair(&path).unwrap().unwrap();
+    assert!(!report.dropped.is_empty());
+
+    // The structure is sound again, but the values of the dropped leaves are gone.
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    let integrity = nomt.check_integrity(IntegrityLevel::Structure).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+    assert_eq!(nomt.read(key(2)).unwrap(), None);
+}
+
+#[test]
+fn repair_missing_database() {
+    let path = PathBuf::from("test").join("repair_missing_database");
+    let _ = std::fs::remove_dir_all(&path);
+    assert!(Nomt::<Blake3Hasher>::repair(&path).unwrap().is_none());
+}
@@ -0,0 +1,118 @@
+//! Tests recording sessions and replaying them.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options, RecordedOp, Recording};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path, record: Option<&Path>) -> Nomt<Blake3Hasher> {
+    let _ = std::fs::remove_dir_all(path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    if let Some(dir) = record {
+        o.record_sessions(dir);
+    }
+    Nomt::open(o).unwrap()
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, key: u8, value: u8) {
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([key; 32], KeyReadWrite::Write(Some(vec![value].into())))],
+    )
+    .unwrap();
+}
+
+fn recording_path(dir: &Path, nomt: &Nomt<Blake3Hasher>) -> PathBuf {
+    dir.join(format!("{}.session", hex::encode(nomt.root())))
+}
+
+#[test]
+fn replay_reproduces_commit() {
+    let recordings = test_path("replay_reproduces_commit_recordings");
+    let _ = std::fs::remove_dir_all(&recordings);
+    let recorded = open(&test_path("replay_reproduces_commit"), Some(&recordings));
+    let replica = open(&test_path("replay_reproduces_commit_replica"), None);
+
+    write(&recorded, 1, 1);
+    write(&replica, 1, 1);
+    assert_eq!(recorded.root(), replica.root());
+
+    let path = recording_path(&recordings, &recorded);
+    let mut session = recorded.begin_session();
+    session.warm_up([2; 32]);
+    assert_eq!(session.read([1; 32]).unwrap().as_deref(), Some(&[1][..]));
+    session.set_commit_tag(b"block 2".to_vec());
+    let (root, _, _) = recorded
+        .commit_and_prove(
+            session,
+            vec![
+                (
+                    [1; 32],
+                    KeyReadWrite::ReadThenWrite(Some(vec![1].into()), None),
+                ),
+                ([2; 32], KeyReadWrite::Write(Some(vec![2].into()))),
+            ],
+        )
+        .unwrap();
+
+    let recording = Recording::read(&path).unwrap();
+    assert_eq!(
+        recording.ops,
+        vec![
+            RecordedOp::WarmUp([2; 32]),
+            RecordedOp::Read([1; 32], Some(vec![1].into())),
+        ]
+    );
+    assert_eq!(recording.commit_tag.as_deref(), Some(&b"block 2"[..]));
+    assert!(recording.prove);
+    assert_eq!(recording.root, Some(root));
+
+    let outcome = replica.replay(&recording).unwrap();
+    assert!(outcome.matches(&recording));
+    assert_eq!(replica.root(), root);
+}
+
+#[test]
+fn replay_reports_mismatched_reads() {
+    let recordings = test_path("replay_reports_mismatched_reads_recordings");
+    let _ = std::fs::remove_dir_all(&recordings);
+    let recorded = open(
+        &test_path("replay_reports_mismatched_reads"),
+        Some(&recordings),
+    );
+
+    let path = recording_path(&recordings, &recorded);
+    let session = recorded.begin_session();
+    assert_eq!(session.read([1; 32]).unwrap(), None);
+    recorded
+        .commit(
+            session,
+            vec![([1; 32], KeyReadWrite::Write(Some(vec![1].into())))],
+        )
+        .unwrap();
+    let recording = Recording::read(&path).unwrap();
+
+    // The recorded database is no longer at the prior root.
+    assert!(matches!(
+        recorded.replay(&recording),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+
+    // A fresh database is at the prior root, but a tampered recording doesn't match its contents.
+    let replica = open(&test_path("replay_reports_mismatched_reads_replica"), None);
+    let mut tampered = recording.clone();
+    tampered.ops = vec![RecordedOp::Read([1; 32], Some(vec![9].into()))];
+    let outcome = replica.replay(&tampered).unwrap();
+    assert_eq!(outcome.mismatched_reads, vec![[1; 32]]);
+    assert!(!outcome.matches(&tampered));
+    assert_eq!(Some(outcome.root), recording.root);
+}
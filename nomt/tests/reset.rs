@@ -0,0 +1,99 @@
+//! Tests wiping the database with `Nomt::reset` and `Nomt::destroy`.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn setup_nomt(path: &PathBuf, should_clean_up: bool) -> Nomt<Blake3Hasher> {
+    if should_clean_up {
+        let _ = std::fs::remove_dir_all(path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.rollback(true);
+    Nomt::open(o).unwrap()
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, key: u8, value: u8) {
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([key; 32], KeyReadWrite::Write(Some(vec![value].into())))],
+    )
+    .unwrap();
+}
+
+#[test]
+fn reset_clears_everything() {
+    let path = test_path("reset_clears_everything");
+    let nomt = setup_nomt(&path, true);
+    for key in 0..10 {
+        write(&nomt, key, key);
+    }
+    assert!(!nomt.is_empty());
+
+    let nomt = nomt.reset().unwrap();
+    assert!(nomt.is_empty());
+    assert_eq!(nomt.read([1; 32]).unwrap(), None);
+    assert_eq!(nomt.last_commit_tag(), None);
+    assert!(nomt.rollback(1).is_err());
+
+    // The database is usable after the reset and survives a reopen.
+    write(&nomt, 42, 1);
+    let root = nomt.root();
+    drop(nomt);
+
+    let nomt = setup_nomt(&path, false);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read([42; 32]).unwrap().as_deref(), Some(&[1][..]));
+    assert_eq!(nomt.read([1; 32]).unwrap(), None);
+}
+
+#[test]
+fn interrupted_reset_yields_empty_database() {
+    let path = test_path("interrupted_reset_yields_empty_database");
+    let nomt = setup_nomt(&path, true);
+    write(&nomt, 1, 1);
+    drop(nomt);
+
+    // A crash right after the meta file has been removed leaves all the other files behind.
+    std::fs::remove_file(path.join("meta")).unwrap();
+
+    let nomt = setup_nomt(&path, false);
+    assert!(nomt.is_empty());
+    assert_eq!(nomt.read([1; 32]).unwrap(), None);
+}
+
+#[test]
+fn reset_fails_with_active_session() {
+    let path = test_path("reset_fails_with_active_session");
+    let nomt = setup_nomt(&path, true);
+    write(&nomt, 1, 1);
+
+    let _session = nomt.begin_session();
+    assert!(matches!(
+        nomt.reset(),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn destroy_removes_directory() {
+    let path = test_path("destroy_removes_directory");
+    let nomt = setup_nomt(&path, true);
+    write(&nomt, 1, 1);
+
+    nomt.destroy().unwrap();
+    assert!(!path.exists());
+
+    let nomt = setup_nomt(&path, false);
+    assert!(nomt.is_empty());
+}
@@ -45,7 +45,7 @@ fn test_rollback_disabled() {
         session,
         vec![(
             hex!("0000000000000000000000000000000000000000000000000000000000000001"),
-            KeyReadWrite::Write(Some(vec![1])),
+            KeyReadWrite::Write(Some(vec![1].into())),
         )],
     )
     .unwrap();
@@ -69,7 +69,7 @@ fn test_rollback_to_initial() {
         session,
         vec![(
             hex!("0000000000000000000000000000000000000000000000000000000000000001"),
-            KeyReadWrite::Write(Some(vec![1])),
+            KeyReadWrite::Write(Some(vec![1].into())),
         )],
     )
     .unwrap();
@@ -127,7 +127,7 @@ impl TestPlan {
                 key[30] = commit_ix as u8;
                 key[31] = j as u8;
                 let key = *blake3::hash(&key).as_bytes();
-                let value: Vec<u8> = blake3::hash(&key).as_bytes().to_vec();
+                let value: Value = blake3::hash(&key).as_bytes()[..].into();
                 // vec![commit_ix as u8, j as u8];
 
                 per_commit_insert.insert(key, value.clone());
@@ -400,7 +400,7 @@ fn test_rollback_change_history() {
     // 3. Create new commits
     let session = nomt.begin_session();
     let new_key = KeyPath::from([0xAA; 32]);
-    let new_value = vec![0xBB; 32];
+    let new_value: Value = vec![0xBB; 32].into();
     nomt.commit(
         session,
         vec![(new_key, KeyReadWrite::Write(Some(new_value.clone())))],
@@ -433,7 +433,7 @@ fn test_rollback_read_then_write() {
     // Create a new key and write a value to it
     let session = nomt.begin_session();
     let key = KeyPath::from([0xAA; 32]);
-    let original_value = vec![0xBB; 32];
+    let original_value: Value = vec![0xBB; 32].into();
     nomt.commit(
         session,
         vec![(key, KeyReadWrite::Write(Some(original_value.clone())))],
@@ -446,7 +446,7 @@ fn test_rollback_read_then_write() {
     // over the original value.
     let session = nomt.begin_session();
     assert_eq!(session.read(key).unwrap(), Some(original_value.clone()));
-    let new_value = vec![0xCC; 32];
+    let new_value: Value = vec![0xCC; 32].into();
     nomt.commit(
         session,
         vec![(
@@ -0,0 +1,75 @@
+//! Tests the history of recent commit roots.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, KeyReadWrite, Node, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let mut p = PathBuf::from("test");
+    p.push(name);
+    p
+}
+
+fn open(path: &Path, root_history: usize) -> Nomt<Blake3Hasher> {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.root_history(root_history);
+    Nomt::open(o).unwrap()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, key: u8) -> Node {
+    let session = nomt.begin_session();
+    nomt.commit(
+        session,
+        vec![([key; 32], KeyReadWrite::Write(Some(vec![key].into())))],
+    )
+    .unwrap()
+}
+
+fn roots(nomt: &Nomt<Blake3Hasher>) -> Vec<Node> {
+    nomt.recent_roots().into_iter().map(|r| r.root).collect()
+}
+
+#[test]
+fn recent_roots_are_kept_and_persisted() {
+    let path = test_path("recent_roots_are_kept_and_persisted");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = open(&path, 3);
+    assert!(nomt.recent_roots().is_empty());
+
+    let committed = (0..5).map(|key| commit(&nomt, key)).collect::<Vec<_>>();
+    assert_eq!(roots(&nomt), committed[2..]);
+    assert_eq!(nomt.recent_roots().last().unwrap().root, nomt.root());
+
+    let seqns = nomt
+        .recent_roots()
+        .into_iter()
+        .map(|r| r.seqn)
+        .collect::<Vec<_>>();
+    assert_eq!(seqns, vec![3, 4, 5]);
+    drop(nomt);
+
+    // The history survives a restart and is trimmed if fewer roots are requested.
+    let nomt = open(&path, 3);
+    assert_eq!(roots(&nomt), committed[2..]);
+    drop(nomt);
+
+    let nomt = open(&path, 2);
+    assert_eq!(roots(&nomt), committed[3..]);
+    let root = commit(&nomt, 5);
+    assert_eq!(roots(&nomt), vec![committed[4], root]);
+    assert_eq!(nomt.recent_roots().last().unwrap().seqn, 6);
+}
+
+#[test]
+fn no_history_by_default() {
+    let path = test_path("no_history_by_default");
+    let _ = std::fs::remove_dir_all(&path);
+
+    let nomt = open(&path, 0);
+    commit(&nomt, 1);
+    assert!(nomt.recent_roots().is_empty());
+}
@@ -0,0 +1,221 @@
+//! Tests snapshots pinned at a root.
+
+use std::path::PathBuf;
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, Error, KeyPath, KeyReadWrite, LeafData, Nomt, Options};
+
+fn setup_nomt(path: &str, snapshot_horizon: Option<u32>) -> Nomt<Blake3Hasher> {
+    setup_nomt_with(path, |o| {
+        if let Some(commits) = snapshot_horizon {
+            o.snapshot_horizon(commits);
+        }
+    })
+}
+
+fn setup_nomt_with(path: &str, configure: impl FnOnce(&mut Options)) -> Nomt<Blake3Hasher> {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(path);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    configure(&mut o);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u32>, value: Option<u8>) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| {
+            (
+                key(id),
+                KeyReadWrite::Write(value.map(|v| vec![v; 100].into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn read(value: Option<nomt::Value>) -> Option<u8> {
+    value.map(|v| v[0])
+}
+
+#[test]
+fn snapshot_is_unaffected_by_commits() {
+    let nomt = setup_nomt("snapshot_is_unaffected_by_commits", None);
+    commit(&nomt, 0..2000, Some(1));
+
+    let snapshot = nomt.snapshot();
+    assert_eq!(snapshot.root(), nomt.root());
+
+    // Rewrite everything a few times, so that the freed leaf pages would be reused if they weren't
+    // pinned.
+    commit(&nomt, 0..1000, None);
+    for value in 2..5 {
+        commit(&nomt, 1000..3000, Some(value));
+    }
+    assert_ne!(snapshot.root(), nomt.root());
+
+    for id in (0..3000).step_by(7) {
+        let expected = (id < 2000).then_some(1);
+        assert_eq!(read(snapshot.read(key(id)).unwrap()), expected);
+        let current = (id >= 1000).then_some(4);
+        assert_eq!(read(nomt.read(key(id)).unwrap()), current);
+    }
+}
+
+#[test]
+fn pages_are_released_after_drop() {
+    let nomt = setup_nomt("snapshot_pages_are_released_after_drop", None);
+    commit(&nomt, 0..2000, Some(1));
+
+    let snapshot = nomt.snapshot();
+    commit(&nomt, 0..2000, None);
+    assert_eq!(nomt.stats().unwrap().ln.free_pages, 0);
+    drop(snapshot);
+
+    commit(&nomt, 0..1, Some(1));
+    assert!(nomt.stats().unwrap().ln.free_pages > 0);
+}
+
+#[test]
+fn pages_are_released_once_older_snapshots_are_dropped() {
+    let nomt = setup_nomt("snapshot_pages_released_once_older_dropped", None);
+    commit(&nomt, 0..2000, Some(1));
+
+    let older = nomt.snapshot();
+    commit(&nomt, 0..1000, Some(2));
+    drop(older);
+
+    // The newer snapshot doesn't need the pages freed before it was taken.
+    let newer = nomt.snapshot();
+    commit(&nomt, 1000..2000, Some(3));
+    let stats = nomt.stats().unwrap().ln;
+    assert!(stats.free_pages > 0);
+    assert!(stats.retained_pages > 0);
+    assert!(stats.reclaimed_bytes > 0);
+
+    for id in (0..2000).step_by(7) {
+        let expected = if id < 1000 { 2 } else { 1 };
+        assert_eq!(read(newer.read(key(id)).unwrap()), Some(expected));
+    }
+}
+
+#[test]
+fn snapshot_expires_past_horizon() {
+    let nomt = setup_nomt("snapshot_expires_past_horizon", Some(2));
+    commit(&nomt, 0..2000, Some(1));
+
+    let snapshot = nomt.snapshot();
+    commit(&nomt, 0..2000, Some(2));
+    commit(&nomt, 5000..5001, Some(2));
+    assert_eq!(read(snapshot.read(key(0)).unwrap()), Some(1));
+    let reclaimed_before = nomt.stats().unwrap().ln.reclaimed_bytes;
+
+    // The pages freed two commits ago are reclaimed despite the snapshot.
+    commit(&nomt, 5001..5002, Some(2));
+    assert!(nomt.stats().unwrap().ln.reclaimed_bytes > reclaimed_before);
+    assert!(matches!(
+        snapshot.read(key(0)),
+        Err(Error::InvalidOperation(_))
+    ));
+    let iterated = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        snapshot.iter(..).next();
+    }));
+    assert!(iterated.is_err());
+
+    // Snapshots taken later are unaffected.
+    let snapshot = nomt.snapshot();
+    assert_eq!(read(snapshot.read(key(0)).unwrap()), Some(2));
+    assert_eq!(snapshot.iter(..).count(), 2002);
+}
+
+#[test]
+fn snapshots_of_recent_roots_are_retained() {
+    let nomt = setup_nomt_with("snapshots_of_recent_roots_are_retained", |o| {
+        o.snapshot_retention(2);
+    });
+    assert!(nomt.snapshot_at(nomt.root()).is_none());
+
+    let mut roots = Vec::new();
+    for value in 1..=3 {
+        commit(&nomt, 0..2000, Some(value));
+        roots.push(nomt.root());
+    }
+
+    // Only the last two commits are retained.
+    assert!(nomt.snapshot_at(roots[0]).is_none());
+    for (root, value) in roots[1..].iter().zip(2..) {
+        let snapshot = nomt.snapshot_at(*root).unwrap();
+        assert_eq!(snapshot.root(), *root);
+        for id in (0..2000).step_by(7) {
+            assert_eq!(read(snapshot.read(key(id)).unwrap()), Some(value));
+        }
+    }
+
+    // The pages of the dropped snapshots are reused.
+    let retained_pages = nomt.stats().unwrap().ln.retained_pages;
+    commit(&nomt, 0..2000, Some(4));
+    assert!(nomt.stats().unwrap().ln.free_pages > 0);
+    assert!(nomt.stats().unwrap().ln.retained_pages <= retained_pages);
+    assert!(nomt.snapshot_at(roots[1]).is_none());
+}
+
+#[test]
+fn proofs_at_retained_roots() {
+    let nomt = setup_nomt_with("proofs_at_retained_roots", |o| {
+        o.snapshot_retention(2);
+    });
+
+    let mut values = vec![None; 3000];
+    let mut roots = Vec::new();
+    let mut states = Vec::new();
+    for (ids, value) in [
+        (0..2000, Some(1)),
+        (1000..3000, Some(2)),
+        (0..1500, None),
+        (500..700, Some(3)),
+    ] {
+        commit(&nomt, ids.clone(), value);
+        for id in ids {
+            values[id as usize] = value;
+        }
+        roots.push(nomt.root());
+        states.push(values.clone());
+    }
+
+    // Proofs are served at the roots of the last two commits, whose snapshots are retained.
+    for (root, values) in roots.iter().zip(&states).skip(2) {
+        for id in (0..3000).step_by(7) {
+            let proof = nomt.prove_at(*root, key(id)).unwrap();
+            let verified = proof
+                .verify::<Blake3Hasher>(key(id).view_bits::<Msb0>(), *root)
+                .unwrap();
+            match values[id as usize] {
+                Some(value) => assert!(verified
+                    .confirm_value(&LeafData {
+                        key_path: key(id),
+                        value_hash: *blake3::hash(&[value; 100]).as_bytes(),
+                    })
+                    .unwrap()),
+                None => assert!(verified.confirm_nonexistence(&key(id)).unwrap()),
+            }
+        }
+    }
+    for root in &roots[..2] {
+        assert!(matches!(
+            nomt.prove_at(*root, key(0)),
+            Err(Error::InvalidOperation(_))
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Tests serving the state in chunks for state sync.
+
+use std::path::PathBuf;
+
+use nomt::{
+    Blake3Hasher, Error, IntegrityLevel, KeyReadWrite, LeafData, Nomt, Options, StateChunk,
+};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let path = PathBuf::from("test").join(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u16) -> [u8; 32] {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn write(nomt: &Nomt<Blake3Hasher>, ids: impl Iterator<Item = u16>) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| {
+            (
+                key(id),
+                KeyReadWrite::Write(Some(id.to_le_bytes().to_vec().into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+fn verify(chunk: &StateChunk) {
+    let leaves = chunk
+        .proof
+        .verify::<Blake3Hasher>(chunk.root, &chunk.start, &chunk.end)
+        .unwrap();
+    let expected = chunk
+        .values
+        .iter()
+        .map(|(key_path, value)| LeafData {
+            key_path: *key_path,
+            value_hash: *blake3::hash(value).as_bytes(),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(leaves, expected);
+}
+
+#[test]
+fn chunks_cover_the_state() {
+    let nomt = open("chunks_cover_the_state");
+    write(&nomt, 0..1000);
+    let root = nomt.root();
+
+    let chunks = nomt
+        .state_chunks(root, 64)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(chunks.len(), 16);
+    assert_eq!(chunks[0].start, [0; 32]);
+    assert_eq!(chunks.last().unwrap().end, [0xFF; 32]);
+    assert!(chunks.last().unwrap().next.is_none());
+
+    let mut values = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        verify(chunk);
+        assert!(chunk.values.len() <= 64);
+        if let Some(next) = chunks.get(i + 1) {
+            assert_eq!(chunk.next, Some(next.start));
+            assert_eq!(chunk.end, chunk.values.last().unwrap().0);
+        }
+        values.extend(chunk.values.iter().cloned());
+    }
+    assert_eq!(values, nomt.iter(..).collect::<Vec<_>>());
+}
+
+#[test]
+fn chunk_of_empty_state() {
+    let nomt = open("chunk_of_empty_state");
+    let chunk = nomt.state_chunk(nomt.root(), [0; 32], 10).unwrap();
+    verify(&chunk);
+    assert!(chunk.values.is_empty());
+    assert_eq!(chunk.end, [0xFF; 32]);
+    assert!(chunk.next.is_none());
+}
+
+#[test]
+fn chunk_requires_current_root() {
+    let nomt = open("chunk_requires_current_root");
+    write(&nomt, 0..10);
+    let root = nomt.root();
+    write(&nomt, 10..20);
+
+    assert!(matches!(
+        nomt.state_chunk(root, [0; 32], 10),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        nomt.state_chunk(nomt.root(), [0; 32], 0),
+        Err(Error::InvalidOperation(_))
+    ));
+}
+
+#[test]
+fn sync_from_chunks() {
+    let src = open("sync_from_chunks_src");
+    write(&src, 0..1000);
+    let root = src.root();
+
+    let dst = open("sync_from_chunks_dst");
+    let mut sync = dst.state_sync(root).unwrap();
+    let mut chunks = 0;
+    while let Some(start) = sync.next_start() {
+        let chunk = src.state_chunk(root, start, 100).unwrap();
+        let complete = sync.ingest(&chunk).unwrap();
+        assert_eq!(complete, chunk.next.is_none());
+        chunks += 1;
+    }
+    assert_eq!(chunks, 10);
+    assert!(sync.is_complete());
+
+    assert_eq!(dst.root(), root);
+    assert_eq!(
+        dst.iter(..).collect::<Vec<_>>(),
+        src.iter(..).collect::<Vec<_>>()
+    );
+    let integrity = dst.check_integrity(IntegrityLevel::Full).unwrap();
+    assert!(integrity.is_healthy(), "{:?}", integrity.issues);
+}
+
+#[test]
+fn sync_rejects_invalid_chunks() {
+    let src = open("sync_rejects_invalid_chunks_src");
+    write(&src, 0..100);
+    let root = src.root();
+    let chunk = src.state_chunk(root, [0; 32], 10).unwrap();
+
+    let dst = open("sync_rejects_invalid_chunks_dst");
+    let mut sync = dst.state_sync(root).unwrap();
+    let is_invalid = |result| matches!(result, Err(Error::InvalidStateChunk(_)));
+
+    let mut tampered = chunk.clone();
+    tampered.values[3].1 = vec![0xAB].into();
+    assert!(is_invalid(sync.ingest(&tampered)));
+
+    let mut missing_value = chunk.clone();
+    missing_value.values.remove(3);
+    assert!(is_invalid(sync.ingest(&missing_value)));
+
+    let mut wrong_root = chunk.clone();
+    wrong_root.root = [1; 32];
+    assert!(is_invalid(sync.ingest(&wrong_root)));
+
+    let later = src.state_chunk(root, chunk.next.unwrap(), 10).unwrap();
+    assert!(is_invalid(sync.ingest(&later)));
+
+    // Nothing was committed, and the valid chunks are still accepted.
+    assert!(dst.is_empty());
+    assert_eq!(sync.next_start(), Some([0; 32]));
+    assert!(!sync.ingest(&chunk).unwrap());
+    assert!(!sync.ingest(&later).unwrap());
+    assert_eq!(dst.iter(..).count(), 20);
+}
+
+#[test]
+fn sync_requires_empty_database() {
+    let nomt = open("sync_requires_empty_database");
+    write(&nomt, 0..10);
+    assert!(matches!(
+        nomt.state_sync(nomt.root()),
+        Err(Error::InvalidOperation(_))
+    ));
+}
@@ -0,0 +1,119 @@
+//! Tests the storage statistics.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, BucketMappingStrategy, KeyPath, KeyReadWrite, Nomt, Options};
+
+fn setup_nomt(path: &str) -> Nomt<Blake3Hasher> {
+    setup_nomt_with(path, |_| {})
+}
+
+fn setup_nomt_with(path: &str, configure: impl FnOnce(&mut Options)) -> Nomt<Blake3Hasher> {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(path);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    configure(&mut o);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, ids: std::ops::Range<u32>, value: Option<Vec<u8>>) {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| (key(id), KeyReadWrite::Write(value.clone().map(Into::into))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+}
+
+#[test]
+fn stats_track_usage() {
+    let nomt = setup_nomt("stats_track_usage");
+
+    let stats = nomt.stats().unwrap();
+    assert_eq!(stats.ht.buckets, 10_000);
+    assert_eq!(stats.ht.occupied_buckets, 0);
+    assert_eq!(stats.ln.bump, 1);
+    assert_eq!(stats.ln.live_pages, 0);
+    assert_eq!(stats.bbn.live_pages, 0);
+    assert_eq!(stats.wal.len, 0);
+
+    commit(&nomt, 0..5000, Some(vec![1; 100]));
+    let stats = nomt.stats().unwrap();
+    assert!(stats.ht.occupied_buckets > 0);
+    assert_eq!(stats.ht.placements, stats.ht.occupied_buckets as u64);
+    assert!(stats.ht.placement_probes >= stats.ht.placements);
+    assert!(!stats.ht.probe_anomaly());
+    assert!(stats.ln.live_pages > 0);
+    assert!(stats.bbn.live_pages > 0);
+    assert!(stats.ln.file.len >= stats.ln.bump as u64 * 4096);
+    assert_eq!(
+        stats.ln.live_pages + stats.ln.free_pages + stats.ln.free_list_pages,
+        stats.ln.bump as usize - 1
+    );
+    let live_leaves = stats.ln.live_pages;
+
+    // Deleting most of the values frees leaf pages for reuse.
+    commit(&nomt, 0..4500, None);
+    let stats = nomt.stats().unwrap();
+    assert!(stats.ln.live_pages < live_leaves);
+    assert!(stats.ln.free_pages > 0);
+    assert_eq!(
+        stats.ln.live_pages + stats.ln.free_pages + stats.ln.free_list_pages,
+        stats.ln.bump as usize - 1
+    );
+}
+
+#[test]
+fn punched_holes_release_space() {
+    let nomt = setup_nomt_with("punched_holes_release_space", |o| o.punch_holes(16));
+
+    // Large values are stored in runs of overflow pages.
+    commit(&nomt, 0..200, Some(vec![1; 20_000]));
+    let allocated = nomt.stats().unwrap().ln.file.allocated;
+
+    commit(&nomt, 0..200, None);
+    let stats = nomt.stats().unwrap();
+    assert!(stats.ln.free_pages >= 1000);
+    assert!(stats.ln.file.allocated + 1000 * 4096 <= allocated);
+
+    // The pages are reused as usual.
+    commit(&nomt, 0..200, Some(vec![2; 20_000]));
+    assert_eq!(
+        nomt.read(key(7)).unwrap().as_deref(),
+        Some(&[2; 20_000][..])
+    );
+}
+
+#[test]
+fn colliding_pages_are_an_anomaly() {
+    let nomt = setup_nomt_with("colliding_pages_are_an_anomaly", |o| {
+        o.bucket_mapping(BucketMappingStrategy::Clustered)
+    });
+
+    // Clustered mapping places the pages by the key ranges they cover, so keys sharing a long
+    // prefix pile their pages up on the same buckets.
+    let session = nomt.begin_session();
+    let mut actuals = (0..4_000u32)
+        .map(|id| {
+            let mut k = [0xAA; 32];
+            k[8..].copy_from_slice(&key(id)[..24]);
+            (k, KeyReadWrite::Write(Some(vec![1].into())))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap();
+
+    let stats = nomt.stats().unwrap();
+    assert!(stats.ht.probe_anomaly());
+}
@@ -0,0 +1,99 @@
+//! Tests placing the files of the stores outside of the database directory.
+
+use std::path::{Path, PathBuf};
+
+use nomt::{Blake3Hasher, KeyReadWrite, Nomt, Options};
+
+fn test_path(name: &str) -> PathBuf {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(name);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+fn opts(path: &Path) -> Options {
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o
+}
+
+fn is_link(path: &Path) -> bool {
+    path.symlink_metadata().unwrap().file_type().is_symlink()
+}
+
+#[test]
+fn files_in_other_dirs() {
+    let path = test_path("store_dirs");
+    let other = test_path("store_dirs_other");
+    let mut o = opts(&path);
+    o.ht_dir(other.join("ht_dir"));
+    o.wal_dir(other.join("wal_dir"));
+    o.beatree_dir(other.join("beatree_dir"));
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    let root = nomt
+        .commit(
+            nomt.begin_session(),
+            vec![([1; 32], KeyReadWrite::Write(Some(vec![1; 100].into())))],
+        )
+        .unwrap();
+    drop(nomt);
+
+    for (dir, name) in [
+        ("ht_dir", "ht"),
+        ("wal_dir", "wal"),
+        ("beatree_dir", "ln"),
+        ("beatree_dir", "bbn"),
+    ] {
+        assert!(is_link(&path.join(name)));
+        assert!(other.join(dir).join(name).is_file());
+    }
+
+    // The files are found through the links without the options.
+    let nomt = Nomt::<Blake3Hasher>::open(opts(&path)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read([1; 32]).unwrap().as_deref(), Some(&[1; 100][..]));
+    assert!(Nomt::<Blake3Hasher>::inspect(&path).unwrap().is_some());
+
+    // Resetting removes the files placed elsewhere and creates them as set by the options.
+    let nomt = nomt.reset().unwrap();
+    assert!(nomt.read([1; 32]).unwrap().is_none());
+    assert!(!is_link(&path.join("ht")));
+    assert!(!other.join("ht_dir").join("ht").exists());
+    nomt.destroy().unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn destroy_removes_files_in_other_dirs() {
+    let path = test_path("store_dirs_destroy");
+    let other = test_path("store_dirs_destroy_other");
+    let mut o = opts(&path);
+    o.ht_dir(&other);
+    o.beatree_dir(&other);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    nomt.destroy().unwrap();
+    assert!(!path.exists());
+    for name in ["ht", "ln", "bbn"] {
+        assert!(!other.join(name).exists());
+    }
+}
+
+#[test]
+fn refuses_existing_files() {
+    let path = test_path("store_dirs_existing");
+    let other = test_path("store_dirs_existing_other");
+    std::fs::create_dir_all(&other).unwrap();
+    std::fs::write(other.join("wal"), b"").unwrap();
+
+    let mut o = opts(&path);
+    o.wal_dir(&other);
+    assert!(matches!(
+        Nomt::<Blake3Hasher>::open(o),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
@@ -0,0 +1,83 @@
+//! Tests querying the roots of subtries.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    Nomt::open(o).unwrap()
+}
+
+/// Keys of "accounts", each with a number of "storage slots" sharing the first 2 bytes.
+fn key(account: u8, slot: u16) -> KeyPath {
+    let mut key = *blake3::hash(&slot.to_le_bytes()).as_bytes();
+    key[0] = account;
+    key[1] = 0xAB;
+    key
+}
+
+fn expected_subtree_root(values: &BTreeMap<KeyPath, Vec<u8>>, prefix: &BitSlice<u8, Msb0>) -> Node {
+    let ops = values
+        .iter()
+        .filter(|(k, _)| k.view_bits::<Msb0>().starts_with(prefix))
+        .map(|(k, v)| (*k, *blake3::hash(v).as_bytes()))
+        .collect::<Vec<_>>();
+    nomt_core::update::build_trie::<Blake3Hasher>(prefix.len(), ops, |_| {})
+}
+
+#[test]
+fn subtree_roots_match_rebuilt_subtries() {
+    let nomt = open("subtree_roots_match_rebuilt_subtries");
+
+    // Account 1 has many slots, account 2 a single one and account 3 none.
+    let mut values = BTreeMap::new();
+    for slot in 0..500 {
+        values.insert(key(1, slot), slot.to_le_bytes().to_vec());
+    }
+    values.insert(key(2, 0), vec![2]);
+    let session = nomt.begin_session();
+    let actuals = values
+        .iter()
+        .map(|(k, v)| (*k, KeyReadWrite::Write(Some(v.clone().into()))))
+        .collect();
+    nomt.commit(session, actuals).unwrap();
+
+    let session = nomt.begin_session();
+    for account in [1u8, 2, 3] {
+        let prefix_bytes = [account, 0xAB];
+        for len in [0, 5, 8, 16] {
+            let prefix = &prefix_bytes.view_bits::<Msb0>()[..len];
+            assert_eq!(
+                session.subtree_root(prefix).unwrap(),
+                expected_subtree_root(&values, prefix),
+                "account {account}, prefix length {len}",
+            );
+        }
+    }
+
+    assert_eq!(
+        session.subtree_root(BitSlice::empty()).unwrap(),
+        nomt.root()
+    );
+
+    // A prefix which is a full key is the leaf itself.
+    let full = key(1, 7);
+    assert_eq!(
+        session.subtree_root(full.view_bits::<Msb0>()).unwrap(),
+        expected_subtree_root(&values, full.view_bits::<Msb0>()),
+    );
+
+    let too_long = [0u8; 33];
+    assert!(matches!(
+        session.subtree_root(too_long.view_bits::<Msb0>()),
+        Err(nomt::Error::InvalidOperation(_))
+    ));
+}
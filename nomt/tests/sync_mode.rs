@@ -0,0 +1,187 @@
+//! Tests deferring the commits to be written to disk several at a time.
+
+use nomt::{
+    Blake3Hasher, Error, KeyPath, KeyReadWrite, Node, Nomt, Options, SyncCrashPoint, SyncMode,
+    SyncState,
+};
+use std::{path::PathBuf, time::Duration};
+
+fn opts(name: &str, reset: bool, sync_mode: SyncMode) -> Options {
+    let path = PathBuf::from("test").join(name);
+    if reset {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.sync_mode(sync_mode);
+    o
+}
+
+fn open(name: &str, reset: bool, sync_mode: SyncMode) -> Nomt<Blake3Hasher> {
+    Nomt::open(opts(name, reset, sync_mode)).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, round: u8) -> Node {
+    let session = nomt.begin_session();
+    let mut actuals = (0..1000)
+        .map(|id| (key(id), KeyReadWrite::Write(Some(vec![round; 64].into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals).unwrap()
+}
+
+fn assert_round(nomt: &Nomt<Blake3Hasher>, root: Node, round: u8) {
+    assert_eq!(nomt.root(), root);
+    for id in 0..1000 {
+        assert_eq!(
+            nomt.read(key(id)).unwrap().unwrap().to_vec(),
+            vec![round; 64]
+        );
+    }
+}
+
+fn sync_seqn(name: &str) -> u32 {
+    Nomt::<Blake3Hasher>::inspect(PathBuf::from("test").join(name))
+        .unwrap()
+        .unwrap()
+        .sync_seqn
+}
+
+#[test]
+fn deferred_commits_are_written_together() {
+    for (name, sync_mode, syncs) in [
+        (
+            "deferred_commits_periodic",
+            SyncMode::Periodic(Duration::from_secs(3600)),
+            1,
+        ),
+        (
+            "deferred_commits_periodic_zero",
+            SyncMode::Periodic(Duration::ZERO),
+            4,
+        ),
+        ("deferred_commits_os_default", SyncMode::OsDefault, 1),
+    ] {
+        let nomt = open(name, true, sync_mode);
+        let mut root = commit(&nomt, 0);
+        for round in 1..4 {
+            root = commit(&nomt, round);
+            // Deferred commits are readable right away.
+            assert_round(&nomt, root, round);
+        }
+        drop(nomt);
+        assert_eq!(sync_seqn(name), syncs, "{name}");
+
+        let nomt = open(name, false, SyncMode::Always);
+        assert_round(&nomt, root, 3);
+    }
+}
+
+#[test]
+fn flush_writes_deferred_commits() {
+    let name = "flush_writes_deferred_commits";
+    let nomt = open(name, true, SyncMode::OsDefault);
+    commit(&nomt, 0);
+    commit(&nomt, 1);
+    assert_eq!(sync_seqn(name), 0);
+    nomt.flush().unwrap();
+    assert_eq!(sync_seqn(name), 1);
+
+    // Opening a commit group writes out the deferred commits too.
+    let root = commit(&nomt, 2);
+    nomt.commit_group().unwrap().sync().unwrap();
+    assert_eq!(sync_seqn(name), 2);
+    nomt.flush().unwrap();
+    assert_eq!(sync_seqn(name), 2);
+    drop(nomt);
+
+    let nomt = open(name, false, SyncMode::Always);
+    assert_round(&nomt, root, 2);
+}
+
+#[test]
+fn sync_state_tells_deferred_commits() {
+    let name = "sync_state_tells_deferred_commits";
+    let nomt = open(name, true, SyncMode::OsDefault);
+    let empty_root = nomt.root();
+    let expected = |root, commit_seqn, synced| SyncState {
+        root,
+        commit_seqn,
+        synced,
+    };
+    assert_eq!(nomt.sync_state(), expected(empty_root, 0, true));
+
+    let root = commit(&nomt, 0);
+    assert_eq!(nomt.sync_state(), expected(root, 0, false));
+    nomt.flush().unwrap();
+    assert_eq!(nomt.sync_state(), expected(root, 1, true));
+
+    let root = commit(&nomt, 1);
+    assert_eq!(nomt.sync_state(), expected(root, 1, false));
+    drop(nomt);
+
+    let nomt = open(name, false, SyncMode::Always);
+    assert_eq!(nomt.sync_state(), expected(root, 2, true));
+    let root = commit(&nomt, 2);
+    assert_eq!(nomt.sync_state(), expected(root, 3, true));
+}
+
+#[test]
+fn deferred_commits_are_lost_on_crash() {
+    let name = "deferred_commits_are_lost_on_crash";
+    let nomt = open(name, true, SyncMode::OsDefault);
+    let old_root = commit(&nomt, 0);
+    nomt.flush().unwrap();
+    drop(nomt);
+
+    // The deferred commits are written out together: a crash before the meta is written lands on
+    // the commit before them, and after it on the last of them.
+    for (crash_point, expected_round) in [
+        (SyncCrashPoint::BeforeMeta, 0),
+        (SyncCrashPoint::AfterMeta, 2),
+    ] {
+        let mut o = opts(name, false, SyncMode::OsDefault);
+        o.crash_on_sync(crash_point);
+        let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+        commit(&nomt, 1);
+        let new_root = commit(&nomt, 2);
+        let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = nomt.flush();
+        }));
+        assert!(r.is_err());
+        drop(nomt);
+
+        let nomt = open(name, false, SyncMode::OsDefault);
+        let expected_root = if expected_round == 0 {
+            old_root
+        } else {
+            new_root
+        };
+        assert_round(&nomt, expected_root, expected_round);
+        drop(nomt);
+
+        // Start over from the first round for the next crash point.
+        let nomt = open(name, true, SyncMode::Always);
+        assert_eq!(commit(&nomt, 0), old_root);
+    }
+}
+
+#[test]
+fn deferred_commits_require_no_rollback() {
+    let mut o = opts(
+        "deferred_commits_require_no_rollback",
+        true,
+        SyncMode::OsDefault,
+    );
+    o.rollback(true);
+    assert!(matches!(
+        Nomt::<Blake3Hasher>::open(o),
+        Err(Error::InvalidOperation(_))
+    ));
+}
@@ -0,0 +1,75 @@
+//! Tests the experimental thread-per-core mode, which shards the warm-ups along with the page
+//! cache, against the shared design.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn open(name: &str, commit_concurrency: usize, thread_per_core: bool) -> Nomt<Blake3Hasher> {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(name);
+        p
+    };
+    let _ = std::fs::remove_dir_all(&path);
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o.commit_concurrency(commit_concurrency);
+    o.warm_up(true);
+    o.thread_per_core(thread_per_core);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u64) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+/// The witnessed paths of a commit along with the siblings proving each.
+type WitnessedPaths = Vec<(Vec<bool>, Vec<Node>)>;
+
+fn commit(nomt: &Nomt<Blake3Hasher>, round: u64) -> (Node, WitnessedPaths) {
+    let session = nomt.begin_session();
+    let mut actuals = (0..2000)
+        .map(|i| {
+            let path = key((i * 7 + round * 1000) % 5000);
+            session.warm_up(path);
+            let value = (i % 5 != 0).then(|| vec![round as u8; 1 + i as usize % 100].into());
+            (path, KeyReadWrite::Write(value))
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(path, _)| *path);
+    actuals.dedup_by_key(|(path, _)| *path);
+    let (root, witness, _) = nomt.commit_and_prove(session, actuals).unwrap();
+    let paths = witness
+        .path_proofs
+        .into_iter()
+        .map(|p| (p.path.path().iter().by_vals().collect(), p.inner.siblings))
+        .collect();
+    (root, paths)
+}
+
+#[test]
+fn sharded_warm_ups_match_shared() {
+    // 3 workers don't divide the children of the root page evenly.
+    for commit_concurrency in [1, 3, 4] {
+        let shared = open(
+            &format!("thread_per_core_shared_{commit_concurrency}"),
+            commit_concurrency,
+            false,
+        );
+        let sharded = open(
+            &format!("thread_per_core_sharded_{commit_concurrency}"),
+            commit_concurrency,
+            true,
+        );
+        for round in 0..4 {
+            let (root, paths) = commit(&shared, round);
+            let (sharded_root, sharded_paths) = commit(&sharded, round);
+            assert_eq!(sharded_root, root);
+            assert_eq!(sharded_paths, paths);
+        }
+        assert_eq!(sharded.read(key(0)).unwrap(), shared.read(key(0)).unwrap());
+    }
+}
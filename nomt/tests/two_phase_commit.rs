@@ -0,0 +1,148 @@
+//! Tests preparing commits and completing or aborting them.
+
+use std::path::PathBuf;
+
+use nomt::{Blake3Hasher, Error, InDoubtCommit, KeyPath, KeyReadWrite, Node, Nomt, Options};
+
+fn options(name: &str, reset: bool) -> Options {
+    let path = {
+        let mut p = PathBuf::from("test");
+        p.push(name);
+        p
+    };
+    if reset {
+        let _ = std::fs::remove_dir_all(&path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn actuals(round: u8) -> Vec<(KeyPath, KeyReadWrite)> {
+    let mut actuals = (0..200)
+        .map(|id| (key(id), KeyReadWrite::Write(Some(vec![round; 40].into()))))
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    actuals
+}
+
+fn read(nomt: &Nomt<Blake3Hasher>, id: u32) -> Option<Vec<u8>> {
+    nomt.begin_session()
+        .read(key(id))
+        .unwrap()
+        .map(|v| v.to_vec())
+}
+
+// Commit round 0, then prepare round 1 and stop as if the process had crashed. Returns the roots
+// before and after round 1.
+fn leave_in_doubt(name: &str) -> (Node, Node) {
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, true)).unwrap();
+    let prev_root = nomt.commit(nomt.begin_session(), actuals(0)).unwrap();
+    let mut session = nomt.begin_session();
+    session.set_commit_tag("round 1");
+    let prepared = nomt.prepare(session, actuals(1)).unwrap();
+    let root = prepared.root();
+    std::mem::forget(prepared);
+    drop(nomt);
+    (prev_root, root)
+}
+
+#[test]
+fn prepare_and_commit() {
+    let name = "two_phase_prepare_and_commit";
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, true)).unwrap();
+    let prepared = nomt.prepare(nomt.begin_session(), actuals(0)).unwrap();
+    let root = prepared.root();
+    assert_eq!(nomt.root(), root);
+
+    // Nothing else commits while the commit is prepared.
+    assert!(matches!(
+        nomt.commit(nomt.begin_session(), Vec::new()),
+        Err(Error::InvalidOperation(_))
+    ));
+    assert!(matches!(
+        nomt.prepare(nomt.begin_session(), Vec::new()),
+        Err(Error::InvalidOperation(_))
+    ));
+
+    assert_eq!(prepared.commit().unwrap(), root);
+    let root = nomt.commit(nomt.begin_session(), actuals(1)).unwrap();
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, false)).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(read(&nomt, 7), Some(vec![1; 40]));
+}
+
+#[test]
+fn prepare_and_abort() {
+    let name = "two_phase_prepare_and_abort";
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, true)).unwrap();
+    let prev_root = nomt.commit(nomt.begin_session(), actuals(0)).unwrap();
+    let prepared = nomt.prepare(nomt.begin_session(), actuals(1)).unwrap();
+    prepared.abort().unwrap();
+    assert_eq!(nomt.root(), prev_root);
+
+    // The database has to be reopened after an abort.
+    assert!(nomt.commit(nomt.begin_session(), actuals(2)).is_err());
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, false)).unwrap();
+    assert_eq!(nomt.root(), prev_root);
+    assert_eq!(read(&nomt, 7), Some(vec![0; 40]));
+}
+
+#[test]
+fn in_doubt_commit_fails_open() {
+    let name = "two_phase_in_doubt_fail";
+    let (_, root) = leave_in_doubt(name);
+    match Nomt::<Blake3Hasher>::open(options(name, false)) {
+        Err(Error::InDoubtCommit {
+            root: in_doubt,
+            commit_tag,
+        }) => {
+            assert_eq!(in_doubt, root);
+            assert_eq!(commit_tag, b"round 1");
+        }
+        Err(e) => panic!("unexpected error: {e}"),
+        Ok(_) => panic!("opened with a commit in doubt"),
+    }
+}
+
+#[test]
+fn in_doubt_commit_resolved_by_commit() {
+    let name = "two_phase_in_doubt_commit";
+    let (_, root) = leave_in_doubt(name);
+    let mut o = options(name, false);
+    o.in_doubt_commit(InDoubtCommit::Commit);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    assert_eq!(nomt.root(), root);
+    assert_eq!(read(&nomt, 7), Some(vec![1; 40]));
+    assert_eq!(nomt.last_commit_tag().as_deref(), Some(&b"round 1"[..]));
+    drop(nomt);
+
+    // The commit is resolved for good.
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, false)).unwrap();
+    assert_eq!(nomt.root(), root);
+}
+
+#[test]
+fn in_doubt_commit_resolved_by_abort() {
+    let name = "two_phase_in_doubt_abort";
+    let (prev_root, _) = leave_in_doubt(name);
+    let mut o = options(name, false);
+    o.in_doubt_commit(InDoubtCommit::Abort);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    assert_eq!(nomt.root(), prev_root);
+    assert_eq!(read(&nomt, 7), Some(vec![0; 40]));
+    drop(nomt);
+
+    let nomt = Nomt::<Blake3Hasher>::open(options(name, false)).unwrap();
+    assert_eq!(nomt.root(), prev_root);
+}
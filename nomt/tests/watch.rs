@@ -0,0 +1,245 @@
+//! Tests watching keys for changes and following all commits.
+
+use std::path::PathBuf;
+
+use bitvec::prelude::*;
+use nomt::{Blake3Hasher, KeyChange, KeyPath, KeyReadWrite, Nomt, Options};
+
+fn open(name: &str) -> Nomt<Blake3Hasher> {
+    let mut o = opts(name);
+    o.rollback(true);
+    Nomt::open(o).unwrap()
+}
+
+fn test_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from("test");
+    path.push(name);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+fn opts(name: &str) -> Options {
+    let mut o = Options::new();
+    o.path(test_path(name));
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(10_000);
+    o
+}
+
+fn key(id: u8) -> KeyPath {
+    [id; 32]
+}
+
+fn hash(value: &[u8]) -> [u8; 32] {
+    *blake3::hash(value).as_bytes()
+}
+
+fn commit(nomt: &Nomt<Blake3Hasher>, mut actuals: Vec<(KeyPath, KeyReadWrite)>) -> [u8; 32] {
+    actuals.sort_by_key(|(k, _)| *k);
+    let session = nomt.begin_session();
+    nomt.commit_and_prove(session, actuals).unwrap().0
+}
+
+fn write(id: u8, value: Option<&[u8]>) -> (KeyPath, KeyReadWrite) {
+    (
+        key(id),
+        KeyReadWrite::Write(value.map(|v| v.to_vec().into())),
+    )
+}
+
+#[test]
+fn watch_reports_changes() {
+    let nomt = open("watch_reports_changes");
+    let watcher = nomt.watch([key(1), key(2)]);
+
+    // Inserting a watched key along with an unwatched one.
+    let root = commit(&nomt, vec![write(1, Some(b"a")), write(3, Some(b"c"))]);
+    let event = watcher.try_recv().unwrap();
+    assert_eq!(event.root, root);
+    assert_eq!(
+        event.changes,
+        vec![KeyChange {
+            key_path: key(1),
+            old: None,
+            new: Some(hash(b"a")),
+        }]
+    );
+    assert!(watcher.try_recv().is_none());
+
+    // Commits not changing any watched key, including writes of the same value, are not reported.
+    commit(&nomt, vec![write(1, Some(b"a")), write(3, Some(b"d"))]);
+    assert!(watcher.try_recv().is_none());
+
+    // Updates and deletions, with and without the prior value in the actuals.
+    let root = commit(
+        &nomt,
+        vec![
+            (
+                key(1),
+                KeyReadWrite::ReadThenWrite(Some(b"a".to_vec().into()), None),
+            ),
+            write(2, Some(b"b")),
+        ],
+    );
+    let event = watcher.try_recv().unwrap();
+    assert_eq!(event.root, root);
+    assert_eq!(
+        event.changes,
+        vec![
+            KeyChange {
+                key_path: key(1),
+                old: Some(hash(b"a")),
+                new: None,
+            },
+            KeyChange {
+                key_path: key(2),
+                old: None,
+                new: Some(hash(b"b")),
+            },
+        ]
+    );
+
+    // Rollbacks are reported as well.
+    nomt.rollback(1).unwrap();
+    let event = watcher.try_recv().unwrap();
+    assert_eq!(event.root, nomt.root());
+    assert_eq!(
+        event.changes,
+        vec![
+            KeyChange {
+                key_path: key(1),
+                old: None,
+                new: Some(hash(b"a")),
+            },
+            KeyChange {
+                key_path: key(2),
+                old: Some(hash(b"b")),
+                new: None,
+            },
+        ]
+    );
+}
+
+#[test]
+fn watch_sees_deleted_prefixes() {
+    let nomt = open("watch_sees_deleted_prefixes");
+    commit(&nomt, vec![write(1, Some(b"a")), write(2, Some(b"b"))]);
+    let watcher = nomt.watch([key(1)]);
+
+    let mut session = nomt.begin_session();
+    session
+        .delete_prefix(&[1u8].view_bits::<Msb0>()[..8])
+        .unwrap();
+    let root = nomt.commit(session, vec![]).unwrap();
+    let event = watcher.try_recv().unwrap();
+    assert_eq!(event.root, root);
+    assert_eq!(
+        event.changes,
+        vec![KeyChange {
+            key_path: key(1),
+            old: Some(hash(b"a")),
+            new: None,
+        }]
+    );
+}
+
+#[test]
+fn watchers_are_independent() {
+    let nomt = open("watchers_are_independent");
+    let a = nomt.watch([key(1)]);
+    let b = nomt.watch([key(1), key(2)]);
+    let c = nomt.watch([key(2)]);
+    drop(c);
+
+    commit(&nomt, vec![write(2, Some(b"b"))]);
+    assert!(a.try_recv().is_none());
+    assert_eq!(b.try_recv().unwrap().changes[0].key_path, key(2));
+
+    commit(&nomt, vec![write(1, Some(b"a"))]);
+    commit(&nomt, vec![write(1, Some(b"b"))]);
+    assert_eq!(a.try_iter().count(), 2);
+    assert_eq!(b.try_iter().count(), 2);
+
+    // Watchers are disconnected once the database is closed.
+    drop(nomt);
+    assert!(a.recv().is_none());
+}
+
+#[test]
+fn commit_feed_reports_all_writes() {
+    let nomt = open("commit_feed_reports_all_writes");
+    let prev_root = commit(&nomt, vec![write(1, Some(b"a")), write(2, Some(b"b"))]);
+    let feed = nomt.commit_feed();
+
+    let mut session = nomt.begin_session();
+    session
+        .delete_prefix(&[1u8].view_bits::<Msb0>()[..8])
+        .unwrap();
+    let actuals = vec![
+        write(2, Some(b"b")),
+        (key(3), KeyReadWrite::Read(None)),
+        write(4, Some(b"d")),
+    ];
+    let root = nomt.commit(session, actuals).unwrap();
+
+    let diff = feed.try_recv().unwrap();
+    assert_eq!(diff.prev_root, prev_root);
+    assert_eq!(diff.root, root);
+    let key_changes = diff
+        .key_changes
+        .iter()
+        .map(|(k, v)| (*k, v.as_deref().map(<[u8]>::to_vec)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        key_changes,
+        vec![
+            (key(1), None),
+            (key(2), Some(b"b".to_vec())),
+            (key(4), Some(b"d".to_vec())),
+        ]
+    );
+    assert!(!diff.pages.is_empty());
+    assert!(feed.try_recv().is_none());
+
+    // Every commit is reported in order, including rollbacks.
+    let next_root = commit(&nomt, vec![write(5, Some(b"e"))]);
+    nomt.rollback(1).unwrap();
+    let diffs = feed.try_iter().collect::<Vec<_>>();
+    assert_eq!(diffs.len(), 2);
+    assert_eq!((diffs[0].prev_root, diffs[0].root), (root, next_root));
+    assert_eq!((diffs[1].prev_root, diffs[1].root), (next_root, root));
+    assert_eq!(diffs[1].key_changes, vec![(key(5), None)]);
+
+    drop(nomt);
+    assert!(feed.recv().is_none());
+}
+
+#[test]
+fn replication_feed_receives_syncs() {
+    let backup = test_path("replication_feed_receives_syncs_backup");
+    let mut o = opts("replication_feed_receives_syncs");
+    o.incremental_backup(&backup);
+    let nomt = Nomt::<Blake3Hasher>::open(o).unwrap();
+    let feed = nomt.replication_feed();
+
+    let root_0 = nomt.root();
+    let root_1 = commit(&nomt, vec![write(1, Some(b"a"))]);
+    let root_2 = commit(&nomt, vec![write(1, None), write(2, Some(b"b"))]);
+    let records = feed.try_iter().collect::<Vec<_>>();
+    assert_eq!(records.len(), 2);
+    for (i, (record, roots)) in records
+        .iter()
+        .zip([(root_0, root_1), (root_1, root_2)])
+        .enumerate()
+    {
+        assert_eq!(record.commit_seqn, i as u64 + 1);
+        assert_eq!((record.prev_root, record.root), roots);
+        // The records are laid out as the increments of the backup.
+        let increment = std::fs::read(backup.join(format!("{i:010}.increment"))).unwrap();
+        assert_eq!(&record.bytes[..], &increment[..]);
+    }
+
+    // Dropping the feed unregisters it.
+    drop(feed);
+    commit(&nomt, vec![write(3, Some(b"c"))]);
+}
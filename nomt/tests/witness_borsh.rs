@@ -0,0 +1,39 @@
+mod common;
+
+use common::Test;
+use nomt::{proof::PathProof, Blake3Hasher, Witness, WitnessedOperations};
+
+#[test]
+fn witness_borsh_round_trip() {
+    let mut t = Test::new("witness_borsh_round_trip");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..5 {
+        t.read_id(id);
+    }
+    t.read_id(100);
+    common::kill(&mut t, 5);
+    common::set_balance(&mut t, 10, 1000);
+    let (_, witness, witnessed) = t.commit();
+    assert!(!witness.path_proofs.is_empty());
+
+    let encoded = borsh::to_vec(&witness).unwrap();
+    let decoded: Witness = borsh::from_slice(&encoded).unwrap();
+    assert_eq!(decoded, witness);
+
+    let encoded = borsh::to_vec(&witnessed).unwrap();
+    let decoded: WitnessedOperations = borsh::from_slice(&encoded).unwrap();
+    assert_eq!(decoded, witnessed);
+
+    // Decoded proofs still verify.
+    for witnessed_path in &witness.path_proofs {
+        let encoded = borsh::to_vec(&witnessed_path.inner).unwrap();
+        let proof: PathProof = borsh::from_slice(&encoded).unwrap();
+        proof
+            .verify::<Blake3Hasher>(witnessed_path.path.path(), prev_root)
+            .unwrap();
+    }
+}
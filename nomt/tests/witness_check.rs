@@ -1,7 +1,12 @@
 mod common;
 
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
 use common::Test;
-use nomt::{proof, Blake3Hasher, LeafData};
+use nomt::{proof, Blake3Hasher, LeafData, WitnessHook, WitnessedPath};
 
 #[test]
 fn produced_witness_validity() {
@@ -40,8 +45,8 @@ fn produced_witness_validity() {
         t.commit()
     };
 
-    assert_eq!(witnessed.reads.len(), 15); // 10 existing + 5 nonexisting
-    assert_eq!(witnessed.writes.len(), 10); // 5 deletes + 5 inserts
+    assert_eq!(witnessed.reads().len(), 15); // 10 existing + 5 nonexisting
+    assert_eq!(witnessed.writes().len(), 10); // 5 deletes + 5 inserts
 
     let mut updates = Vec::new();
     for (i, witnessed_path) in witness.path_proofs.iter().enumerate() {
@@ -50,7 +55,7 @@ fn produced_witness_validity() {
             .verify::<Blake3Hasher>(&witnessed_path.path.path(), prev_root)
             .unwrap();
         for read in witnessed
-            .reads
+            .reads()
             .iter()
             .skip_while(|r| r.path_index != i)
             .take_while(|r| r.path_index == i)
@@ -69,7 +74,7 @@ fn produced_witness_validity() {
 
         let mut write_ops = Vec::new();
         for write in witnessed
-            .writes
+            .writes()
             .iter()
             .skip_while(|r| r.path_index != i)
             .take_while(|r| r.path_index == i)
@@ -90,3 +95,392 @@ fn produced_witness_validity() {
         new_root,
     );
 }
+
+#[test]
+fn verify_witness_recomputes_root() {
+    let mut t = Test::new("verify_witness_recomputes_root");
+    for id in 0..100 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..10 {
+        t.read_id(id);
+    }
+    t.read_id(500);
+    for id in 10..15 {
+        common::kill(&mut t, id);
+    }
+    for id in 200..205 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (new_root, witness, witnessed) = t.commit();
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &witness, &witnessed).unwrap(),
+        new_root,
+    );
+
+    // A witness against another root doesn't verify.
+    assert!(matches!(
+        nomt::verify_witness::<Blake3Hasher>(new_root, &witness, &witnessed),
+        Err(nomt::WitnessVerificationError::InvalidPath(0, _))
+    ));
+
+    // Operations contradicting their paths can't be created.
+    let (mut reads, mut writes) = witnessed.clone().into_parts();
+    let read = reads.iter_mut().find(|r| r.value.is_some()).unwrap();
+    read.value = Some([0xFF; 32]);
+    assert!(matches!(
+        nomt::WitnessedOperations::new(&witness, reads, writes.clone()),
+        Err(nomt::WitnessVerificationError::ReadMismatch(_))
+    ));
+
+    let (reads, _) = witnessed.clone().into_parts();
+    let mut out_of_bounds = writes.clone();
+    out_of_bounds[0].path_index = witness.path_proofs.len();
+    assert!(matches!(
+        nomt::WitnessedOperations::new(&witness, reads.clone(), out_of_bounds),
+        Err(nomt::WitnessVerificationError::PathIndexOutOfBounds(_))
+    ));
+
+    let mut out_of_scope = writes.clone();
+    out_of_scope[0].key = out_of_scope[0].key.map(|b| !b);
+    assert!(matches!(
+        nomt::WitnessedOperations::new(&witness, reads.clone(), out_of_scope),
+        Err(nomt::WitnessVerificationError::KeyOutOfScope(_))
+    ));
+
+    // A different write leads to a different root.
+    writes[0].value = Some([0xFF; 32]);
+    let tampered = nomt::WitnessedOperations::new(&witness, reads, writes).unwrap();
+    assert_ne!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &witness, &tampered).unwrap(),
+        new_root,
+    );
+}
+
+#[test]
+fn verify_witness_without_writes() {
+    let mut t = Test::new("verify_witness_without_writes");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    t.read_id(3);
+    t.read_id(100);
+    let (new_root, witness, witnessed) = t.commit();
+    assert_eq!(new_root, prev_root);
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &witness, &witnessed).unwrap(),
+        prev_root,
+    );
+}
+
+#[test]
+fn multi_proof_from_witness() {
+    let mut t = Test::new("multi_proof_from_witness");
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..100 {
+        t.read_id(id);
+    }
+    for id in 5000..5010 {
+        t.read_id(id);
+    }
+    for id in 100..110 {
+        common::set_balance(&mut t, id, 2000);
+    }
+    let (_, witness, witnessed) = t.commit();
+
+    let multi_proof = witness.to_multi_proof();
+    assert_eq!(multi_proof.paths.len(), witness.path_proofs.len());
+    let path_siblings = witness
+        .path_proofs
+        .iter()
+        .map(|p| p.inner.siblings.len())
+        .sum::<usize>();
+    assert!(multi_proof.siblings.len() * 2 < path_siblings);
+
+    let verified =
+        nomt::multi_proof_verification::verify::<Blake3Hasher>(&multi_proof, prev_root).unwrap();
+    for read in witnessed.reads() {
+        match read.value {
+            None => assert!(verified.confirm_nonexistence(&read.key).unwrap()),
+            Some(value_hash) => assert!(verified
+                .confirm_value(&LeafData {
+                    key_path: read.key,
+                    value_hash,
+                })
+                .unwrap()),
+        }
+    }
+    assert!(nomt::multi_proof_verification::verify::<Blake3Hasher>(&multi_proof, [1; 32]).is_err());
+}
+
+#[test]
+fn witness_selected_keys() {
+    let mut t = Test::new("witness_selected_keys");
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    let selected = (0..10)
+        .chain(900..905)
+        .map(common::account_path)
+        .collect::<HashSet<_>>();
+    for id in 0..200 {
+        t.read_id(id);
+    }
+    for id in 900..905 {
+        common::set_balance(&mut t, id, 2000);
+    }
+    let (new_root, witness, witnessed) = t.commit_selected(|key| selected.contains(key));
+
+    // Only the paths of the selected keys are witnessed, with all the operations along them.
+    assert!(witness.path_proofs.len() < 50);
+    let witnessed_keys = witnessed
+        .reads()
+        .iter()
+        .map(|r| r.key)
+        .chain(witnessed.writes().iter().map(|w| w.key))
+        .collect::<HashSet<_>>();
+    assert!(selected.is_subset(&witnessed_keys));
+    for read in witnessed.reads() {
+        assert!(read.path_index < witness.path_proofs.len());
+    }
+
+    // All writes are selected, so the new root can be recomputed.
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &witness, &witnessed).unwrap(),
+        new_root,
+    );
+
+    // Selecting nothing witnesses nothing.
+    t.read_id(1);
+    let (_, witness, witnessed) = t.commit_selected(|_| false);
+    assert!(witness.path_proofs.is_empty());
+    assert!(witnessed.reads().is_empty() && witnessed.writes().is_empty());
+}
+
+#[test]
+fn update_from_multi_proof() {
+    let mut t = Test::new("update_from_multi_proof");
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..50 {
+        t.read_id(id);
+    }
+    for id in 100..110 {
+        common::set_balance(&mut t, id, 2000);
+    }
+    common::kill(&mut t, 200);
+    common::set_balance(&mut t, 5000, 1000);
+    let (root, witness, witnessed) = t.commit();
+
+    let multi_proof = witness.to_multi_proof();
+    let path_proofs =
+        nomt::multi_proof_verification::verify_path_proofs::<Blake3Hasher>(&multi_proof, prev_root)
+            .unwrap();
+    let mut expected = witness
+        .path_proofs
+        .iter()
+        .map(|p| p.inner.clone())
+        .collect::<Vec<_>>();
+    expected.sort_by(|a, b| a.terminal.path().cmp(b.terminal.path()));
+    assert_eq!(path_proofs, expected);
+
+    let mut ops = witnessed
+        .writes()
+        .iter()
+        .map(|w| (w.key, w.value))
+        .collect::<Vec<_>>();
+    ops.sort_by_key(|(key, _)| *key);
+    assert_eq!(
+        proof::verify_multi_proof_update::<Blake3Hasher>(prev_root, &multi_proof, &ops).unwrap(),
+        root,
+    );
+    assert_eq!(
+        proof::verify_multi_proof_update::<Blake3Hasher>(prev_root, &multi_proof, &[]).unwrap(),
+        prev_root,
+    );
+
+    let mut out_of_scope = ops.clone();
+    out_of_scope.push(([0xFF; 32], None));
+    assert!(matches!(
+        proof::verify_multi_proof_update::<Blake3Hasher>(prev_root, &multi_proof, &out_of_scope),
+        Err(proof::MultiProofUpdateError::OpOutOfScope)
+    ));
+}
+
+#[test]
+fn prove_reads_without_commit() {
+    let mut t = Test::new("prove_reads_without_commit");
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (root, _, _) = t.commit();
+
+    for id in 0..20 {
+        t.read_id(id);
+    }
+    t.read_id(5000);
+    let (proven_root, witness, witnessed) = t.prove_reads().unwrap();
+    assert_eq!(proven_root, root);
+    assert_eq!(witnessed.reads().len(), 21);
+    assert!(witnessed.writes().is_empty());
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(root, &witness, &witnessed).unwrap(),
+        root,
+    );
+    let read = witnessed
+        .reads()
+        .iter()
+        .find(|r| r.key == common::account_path(5000))
+        .unwrap();
+    assert_eq!(read.value, None);
+
+    // Nothing was committed.
+    assert_eq!(t.commit().0, root);
+
+    common::set_balance(&mut t, 1, 2000);
+    assert!(matches!(
+        t.prove_reads(),
+        Err(nomt::Error::InvalidActuals(_))
+    ));
+}
+
+#[test]
+fn stream_witness() {
+    let mut collected = Test::new_with_params("stream_witness_collected", 4, 64_000, false, true);
+    let mut streamed = Test::new_with_params("stream_witness_streamed", 4, 64_000, false, true);
+    let mut prev_root = None;
+    for t in [&mut collected, &mut streamed] {
+        for id in 0..2000 {
+            common::set_balance(t, id, 1000);
+        }
+        prev_root = Some(t.commit().0);
+    }
+    let prev_root = prev_root.unwrap();
+
+    for t in [&mut collected, &mut streamed] {
+        for id in (0..2000).step_by(7) {
+            t.read_id(id);
+        }
+        for id in (0..2000).step_by(13) {
+            common::kill(t, id);
+        }
+        for id in 3000..3100 {
+            common::set_balance(t, id, 1000);
+        }
+    }
+
+    let (root, witness, witnessed) = collected.commit();
+    let (streamed_root, streamed_witness, streamed_witnessed) =
+        streamed.commit_and_stream_witness();
+    assert_eq!(streamed_root, root);
+    assert_eq!(streamed_witness, witness);
+    assert_eq!(streamed_witnessed, witnessed);
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &streamed_witness, &streamed_witnessed)
+            .unwrap(),
+        root,
+    );
+}
+
+#[derive(Default)]
+struct CollectPaths(Mutex<Vec<WitnessedPath>>);
+
+impl WitnessHook for CollectPaths {
+    fn on_path(&self, path: &WitnessedPath) {
+        self.0.lock().unwrap().push(path.clone());
+    }
+}
+
+impl CollectPaths {
+    fn take_sorted(&self) -> Vec<WitnessedPath> {
+        let mut paths = std::mem::take(&mut *self.0.lock().unwrap());
+        paths.sort_by(|a, b| a.path.path().cmp(b.path.path()));
+        paths
+    }
+}
+
+#[test]
+fn witness_hook() {
+    let mut t = Test::new_with_params("witness_hook", 4, 64_000, false, true);
+    for id in 0..2000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    t.commit();
+
+    let hook = Arc::new(CollectPaths::default());
+    for id in (0..2000).step_by(7) {
+        t.read_id(id);
+    }
+    for id in 3000..3100 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    t.set_witness_hook(hook.clone());
+    let (root, witness, _) = t.commit();
+    assert_eq!(hook.take_sorted(), witness.path_proofs);
+
+    // The hook gets the paths of commits which don't collect the witness too.
+    for id in (0..2000).step_by(11) {
+        common::kill(&mut t, id);
+    }
+    t.set_witness_hook(hook.clone());
+    assert!(t.try_commit().is_ok());
+    let paths = hook.take_sorted();
+    assert_eq!(paths.len(), 2000 / 11 + 1);
+    for path in &paths {
+        assert!(path
+            .inner
+            .verify::<Blake3Hasher>(path.path.path(), root)
+            .is_ok());
+    }
+}
+
+#[test]
+fn canonical_witness_order() {
+    let mut witnesses = Vec::new();
+    for commit_concurrency in [1, 2, 4, 16, 64] {
+        let mut t = Test::new_with_params(
+            format!("canonical_witness_order_{commit_concurrency}"),
+            commit_concurrency,
+            64_000,
+            false,
+            true,
+        );
+        for id in 0..2000 {
+            common::set_balance(&mut t, id, 1000);
+        }
+        t.commit();
+
+        for id in (0..2000).step_by(3) {
+            t.read_id(id);
+        }
+        for id in (0..2000).step_by(17) {
+            common::kill(&mut t, id);
+        }
+        for id in 10_000..10_500 {
+            common::set_balance(&mut t, id, 1000);
+        }
+        let (_, witness, witnessed) = t.commit();
+        assert!(witness.is_canonical());
+        assert!(witnessed.is_canonical());
+        witnesses.push((witness, witnessed));
+    }
+
+    let (witness, witnessed) = &witnesses[0];
+    for (other_witness, other_witnessed) in &witnesses[1..] {
+        assert_eq!(other_witness.encode(), witness.encode());
+        assert_eq!(other_witnessed, witnessed);
+    }
+}
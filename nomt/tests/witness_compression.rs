@@ -0,0 +1,66 @@
+mod common;
+
+use common::Test;
+use nomt::{witness_format::WitnessDecodeError, Blake3Hasher};
+
+#[test]
+fn compress_round_trip() {
+    let mut t = Test::new("witness_compression_round_trip");
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..50 {
+        t.read_id(id);
+    }
+    t.read_id(5000);
+    common::kill(&mut t, 50);
+    common::set_balance(&mut t, 5001, 1000);
+    let (root, witness, witnessed) = t.commit();
+
+    let compressed = witness.compress();
+    let siblings = witness
+        .path_proofs
+        .iter()
+        .map(|p| p.inner.siblings.len())
+        .sum::<usize>();
+    assert!(compressed.nodes.len() < siblings);
+
+    let decompressed = compressed.decompress().unwrap();
+    assert_eq!(decompressed, witness);
+    assert_eq!(
+        nomt::verify_witness::<Blake3Hasher>(prev_root, &decompressed, &witnessed).unwrap(),
+        root,
+    );
+}
+
+#[test]
+fn decompress_rejects_malformed() {
+    let mut t = Test::new("witness_compression_malformed");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    t.commit();
+    t.read_id(3);
+    let (_, witness, _) = t.commit();
+
+    let mut compressed = witness.compress();
+    compressed.path_proofs[0].siblings[0] = compressed.nodes.len() as u32;
+    assert_eq!(
+        compressed.decompress(),
+        Err(WitnessDecodeError::Invalid("reference to unknown node")),
+    );
+
+    let mut compressed = witness.compress();
+    compressed.path_proofs[0].path.bits.push(0);
+    assert!(compressed.decompress().is_err());
+
+    let mut compressed = witness.compress();
+    let node = compressed.nodes[0];
+    compressed.nodes.push(node);
+    assert_eq!(
+        compressed.decompress(),
+        Err(WitnessDecodeError::Invalid("repeated node not shared")),
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use common::Test;
+use nomt::{
+    witness_format::{WitnessDecodeError, WITNESS_FORMAT_VERSION},
+    Blake3Hasher, Witness,
+};
+
+fn witness(name: &str) -> Witness {
+    let mut t = Test::new(name);
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    t.commit();
+
+    for id in 0..50 {
+        t.read_id(id);
+    }
+    t.read_id(5000);
+    common::kill(&mut t, 50);
+    common::set_balance(&mut t, 5001, 1000);
+    t.commit().1
+}
+
+#[test]
+fn round_trip() {
+    let witness = witness("witness_format_round_trip");
+    let encoded = witness.encode();
+    assert_eq!(encoded[0], WITNESS_FORMAT_VERSION);
+    let decoded = Witness::decode(&encoded).unwrap();
+    assert_eq!(decoded, witness);
+    assert_eq!(decoded.encode(), encoded);
+
+    // Siblings near the root are shared between the paths.
+    let siblings = witness
+        .path_proofs
+        .iter()
+        .map(|p| p.inner.siblings.len())
+        .sum::<usize>();
+    assert!(encoded.len() < siblings * 32);
+
+    let empty = Witness {
+        path_proofs: Vec::new(),
+        key_binning: Default::default(),
+    };
+    assert_eq!(empty.encode(), vec![WITNESS_FORMAT_VERSION, 0, 0]);
+    assert_eq!(Witness::decode(&empty.encode()).unwrap(), empty);
+}
+
+#[test]
+fn estimated_size() {
+    let mut t = Test::new("witness_format_estimated_size");
+    assert_eq!(t.estimate_witness_size(), 3);
+    for id in 0..1000 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let estimate = t.estimate_witness_size();
+    let actual = t.commit().1.encode().len();
+    assert!(estimate >= actual);
+
+    for id in 0..50 {
+        t.read_id(id);
+    }
+    t.read_id(5000);
+    common::kill(&mut t, 50);
+    common::set_balance(&mut t, 5001, 1000);
+    let estimate = t.estimate_witness_size();
+    let actual = t.commit().1.encode().len();
+    assert!(estimate >= actual);
+    assert!(estimate <= actual + actual / 2, "{estimate} vs {actual}");
+}
+
+#[test]
+fn decoded_proofs_verify() {
+    let mut t = Test::new("witness_format_decoded_proofs_verify");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+    t.read_id(3);
+    common::set_balance(&mut t, 11, 1000);
+    let (_, witness, _) = t.commit();
+
+    let decoded = Witness::decode(&witness.encode()).unwrap();
+    for witnessed_path in &decoded.path_proofs {
+        witnessed_path
+            .inner
+            .verify::<Blake3Hasher>(witnessed_path.path.path(), prev_root)
+            .unwrap();
+    }
+}
+
+#[test]
+fn rejects_non_canonical_input() {
+    let encoded = witness("witness_format_rejects_non_canonical_input").encode();
+
+    let mut bytes = encoded.clone();
+    bytes[0] = 2;
+    assert_eq!(
+        Witness::decode(&bytes),
+        Err(WitnessDecodeError::UnsupportedVersion(2))
+    );
+
+    assert_eq!(
+        Witness::decode(&encoded[..encoded.len() - 1]),
+        Err(WitnessDecodeError::UnexpectedEnd)
+    );
+
+    let mut bytes = encoded.clone();
+    bytes.push(0);
+    assert_eq!(
+        Witness::decode(&bytes),
+        Err(WitnessDecodeError::TrailingBytes)
+    );
+
+    // An overlong varint for the number of paths.
+    let bytes = [WITNESS_FORMAT_VERSION, 0, 0x80, 0x00];
+    assert!(matches!(
+        Witness::decode(&bytes),
+        Err(WitnessDecodeError::Invalid(_))
+    ));
+
+    // A path of depth 1 with a non-zero padding bit.
+    let mut bytes = vec![WITNESS_FORMAT_VERSION, 0, 1, 1, 0b0100_0000, 1, 0, 0];
+    assert!(matches!(
+        Witness::decode(&bytes),
+        Err(WitnessDecodeError::Invalid(_))
+    ));
+    bytes[4] = 0b1000_0000;
+    assert!(Witness::decode(&bytes).is_ok());
+
+    // The same sibling encoded twice instead of being shared.
+    let mut bytes = vec![WITNESS_FORMAT_VERSION, 0, 1, 0, 1, 0, 2];
+    for _ in 0..2 {
+        bytes.push(0);
+        bytes.extend_from_slice(&[7; 32]);
+    }
+    assert!(matches!(
+        Witness::decode(&bytes),
+        Err(WitnessDecodeError::Invalid(_))
+    ));
+    bytes.truncate(7 + 33);
+    bytes.push(1);
+    assert_eq!(Witness::decode(&bytes).unwrap().encode(), bytes);
+}
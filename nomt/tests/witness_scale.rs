@@ -0,0 +1,41 @@
+mod common;
+
+use codec::{Decode, Encode};
+use common::Test;
+use nomt::{proof::PathProof, Blake3Hasher, Witness, WitnessedOperations};
+
+#[test]
+fn witness_scale_round_trip() {
+    let mut t = Test::new("witness_scale_round_trip");
+    for id in 0..10 {
+        common::set_balance(&mut t, id, 1000);
+    }
+    let (prev_root, _, _) = t.commit();
+
+    for id in 0..5 {
+        t.read_id(id);
+    }
+    t.read_id(100);
+    common::kill(&mut t, 5);
+    common::set_balance(&mut t, 10, 1000);
+    let (_, witness, witnessed) = t.commit();
+    assert!(!witness.path_proofs.is_empty());
+
+    // `Witness` has inherent `encode` and `decode` methods for the canonical format.
+    let encoded = Encode::encode(&witness);
+    let decoded = <Witness as Decode>::decode(&mut &encoded[..]).unwrap();
+    assert_eq!(decoded, witness);
+
+    let encoded = witnessed.encode();
+    let decoded = WitnessedOperations::decode(&mut &encoded[..]).unwrap();
+    assert_eq!(decoded, witnessed);
+
+    // Decoded proofs still verify.
+    for witnessed_path in &witness.path_proofs {
+        let encoded = witnessed_path.inner.encode();
+        let proof = PathProof::decode(&mut &encoded[..]).unwrap();
+        proof
+            .verify::<Blake3Hasher>(witnessed_path.path.path(), prev_root)
+            .unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Tests a commit failing to write out its files, without the privileges needed to run out of
+//! disk space for real, see `disk_full.rs`. The writes past a size are failed by limiting the
+//! size of the files the process may write.
+//!
+//! The limit applies to the whole process, so this file holds a single test.
+
+use nomt::{Blake3Hasher, Error, KeyPath, KeyReadWrite, Node, Nomt, Options};
+use std::path::{Path, PathBuf};
+
+fn open(path: &Path, reset: bool) -> Nomt<Blake3Hasher> {
+    if reset {
+        let _ = std::fs::remove_dir_all(path);
+    }
+    let mut o = Options::new();
+    o.path(path);
+    o.bitbox_seed([0; 16]);
+    o.hashtable_buckets(4096);
+    Nomt::open(o).unwrap()
+}
+
+fn key(id: u32) -> KeyPath {
+    *blake3::hash(&id.to_le_bytes()).as_bytes()
+}
+
+fn commit(
+    nomt: &Nomt<Blake3Hasher>,
+    ids: std::ops::Range<u32>,
+    value_len: usize,
+) -> nomt::Result<Node> {
+    let session = nomt.begin_session();
+    let mut actuals = ids
+        .map(|id| {
+            (
+                key(id),
+                KeyReadWrite::Write(Some(vec![1; value_len].into())),
+            )
+        })
+        .collect::<Vec<_>>();
+    actuals.sort_by_key(|(k, _)| *k);
+    nomt.commit(session, actuals)
+}
+
+/// Fail the writes past `bytes` into any file with `EFBIG`, instead of killing the process.
+fn limit_file_size(bytes: libc::rlim_t) {
+    let limit = libc::rlimit {
+        rlim_cur: bytes,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    unsafe {
+        libc::signal(libc::SIGXFSZ, libc::SIG_IGN);
+        assert_eq!(libc::setrlimit(libc::RLIMIT_FSIZE, &limit), 0);
+    }
+}
+
+fn is_invalid_operation<T>(result: nomt::Result<T>) -> bool {
+    matches!(result, Err(Error::InvalidOperation(_)))
+}
+
+#[test]
+fn failed_commit_blocks_reads_and_commits() {
+    let path = PathBuf::from("test").join("failed_commit_blocks_reads_and_commits");
+    let nomt = open(&path, true);
+    let root = commit(&nomt, 0..1000, 32).unwrap();
+
+    // Leave room for far less than the overflow pages of the large values.
+    let largest = std::fs::read_dir(&path)
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .max()
+        .unwrap();
+    limit_file_size(largest + 1024 * 1024);
+    let result = commit(&nomt, 1000..4000, 8192);
+    limit_file_size(libc::RLIM_INFINITY);
+    assert!(result.is_err());
+
+    // The values of the failed commit are in memory but not on disk, so nothing may be read.
+    assert!(is_invalid_operation(nomt.read(key(1000))));
+    assert!(is_invalid_operation(nomt.prove(key(0))));
+    assert!(is_invalid_operation(nomt.snapshot().read(key(0))));
+    let session = nomt.begin_session();
+    assert!(is_invalid_operation(session.read(key(0))));
+    assert!(is_invalid_operation(session.contains(key(0))));
+    drop(session);
+    assert!(is_invalid_operation(commit(&nomt, 0..1, 32)));
+    drop(nomt);
+
+    // The previously committed state is intact.
+    let nomt = open(&path, false);
+    assert_eq!(nomt.root(), root);
+    assert_eq!(nomt.read(key(0)).unwrap().unwrap().to_vec(), vec![1; 32]);
+    assert_eq!(nomt.read(key(1000)).unwrap(), None);
+    assert!(commit(&nomt, 1000..1100, 8192).is_ok());
+}